@@ -1,10 +1,16 @@
 import { invoke } from '@tauri-apps/api/core'
 import type {
   RequestLogListResponse,
+  CostLogListResponse,
   RequestLogDetail,
+  BatchRequestItem,
+  LogContext,
+  ReplayResult,
   SystemLogListResponse,
   GatewaySettings,
-  GatewaySettingsUpdate
+  GatewaySettingsUpdate,
+  ActiveRequest,
+  CliType
 } from '@/types/models'
 
 export interface RequestLogQuery {
@@ -24,8 +30,23 @@ export interface SystemLogQuery {
 
 export const logsApi = {
   getSettings: async () => {
-    const data = await invoke<{ debug_log: number }>('get_gateway_settings')
-    return { data: { debug_log: !!data.debug_log } as GatewaySettings }
+    const data = await invoke<{ debug_log: number; max_request_body_mb: number; log_body_max_kb: number; use_keychain: number; request_id_header: string | null; ws_proxy_enabled: number; gateway_token: string | null; gateway_token_enforced: number; listen_external: number; listen_address: string | null; allow_simulation_commands: number; default_cli_type: CliType }>('get_gateway_settings')
+    return {
+      data: {
+        debug_log: !!data.debug_log,
+        max_request_body_mb: data.max_request_body_mb,
+        log_body_max_kb: data.log_body_max_kb,
+        use_keychain: !!data.use_keychain,
+        request_id_header: data.request_id_header,
+        ws_proxy_enabled: !!data.ws_proxy_enabled,
+        gateway_token: data.gateway_token,
+        gateway_token_enforced: !!data.gateway_token_enforced,
+        listen_external: !!data.listen_external,
+        listen_address: data.listen_address,
+        allow_simulation_commands: !!data.allow_simulation_commands,
+        default_cli_type: data.default_cli_type
+      } as GatewaySettings
+    }
   },
   updateSettings: async (data: GatewaySettingsUpdate) => {
     await invoke('update_gateway_settings', { debugLog: data.debug_log })
@@ -40,10 +61,41 @@ export const logsApi = {
     })
     return { data }
   },
+  listRequestCosts: async (params: RequestLogQuery) => {
+    const data = await invoke<CostLogListResponse>('get_request_cost_breakdown', {
+      page: params.page,
+      pageSize: params.page_size,
+      cliType: params.cli_type
+    })
+    return { data }
+  },
   getRequestLog: async (id: number) => {
     const data = await invoke<RequestLogDetail>('get_request_log_detail', { id })
     return { data }
   },
+  getBatchItems: async (logId: number): Promise<{ data: BatchRequestItem[] }> => {
+    const data = await invoke<BatchRequestItem[]>('get_batch_items', { logId })
+    return { data }
+  },
+  getRequestLogContext: async (logId: number, contextSize?: number) => {
+    const data = await invoke<LogContext>('get_request_log_context', { logId, contextSize })
+    return { data }
+  },
+  getRequestLogByRequestId: async (requestId: string) => {
+    const data = await invoke<RequestLogDetail>('get_request_log_by_id', { requestId })
+    return { data }
+  },
+  getActiveRequests: async () => {
+    const data = await invoke<ActiveRequest[]>('get_active_requests')
+    return { data }
+  },
+  cancelActiveRequest: async (requestId: string) => {
+    await invoke('cancel_active_request', { requestId })
+  },
+  replayRequest: async (logId: number, providerId?: number): Promise<{ data: ReplayResult }> => {
+    const data = await invoke<ReplayResult>('replay_request', { logId, providerId })
+    return { data }
+  },
   clearRequestLogs: async (before_timestamp?: number) => {
     await invoke('clear_request_logs')
     return { data: null }
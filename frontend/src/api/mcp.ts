@@ -1,5 +1,5 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { Mcp, McpCreate, McpUpdate } from '@/types/models'
+import type { Mcp, McpCreate, McpUpdate, McpValidationResult, McpDiff } from '@/types/models'
 
 // 后端返回的 cli_flags 格式
 type McpCliFlagBackend = { cli_type: string; enabled: boolean }
@@ -41,5 +41,17 @@ export const mcpApi = {
   delete: async (id: number) => {
     await invoke('delete_mcp', { id })
     return { data: null }
+  },
+  validate: async (configJson: string, cliType: string): Promise<{ data: McpValidationResult }> => {
+    const data = await invoke<McpValidationResult>('validate_mcp_config', { configJson, cliType })
+    return { data }
+  },
+  diffWithFilesystem: async (cliType: string): Promise<{ data: McpDiff[] }> => {
+    const data = await invoke<McpDiff[]>('diff_mcp_with_filesystem', { cliType })
+    return { data }
+  },
+  reconcileFromFilesystem: async (cliType: string): Promise<{ data: number }> => {
+    const data = await invoke<number>('reconcile_mcp_from_filesystem', { cliType })
+    return { data }
   }
 }
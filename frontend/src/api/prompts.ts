@@ -26,6 +26,11 @@ export const promptsApi = {
     const data = await invoke<PromptBackend[]>('get_prompts')
     return { data: data.map(transformPrompt) }
   },
+  // 当前写入该 CLI 提示词文件的提示词（追加模式下可能有多个）
+  getActive: async (cliType: string): Promise<{ data: Prompt[] }> => {
+    const data = await invoke<PromptBackend[]>('get_active_prompts', { cliType })
+    return { data: data.map(transformPrompt) }
+  },
   get: async (id: number): Promise<{ data: Prompt }> => {
     const data = await invoke<PromptBackend>('get_prompt', { id })
     return { data: transformPrompt(data) }
@@ -1,5 +1,6 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { Provider, ProviderCreate, ProviderUpdate } from '@/types/models'
+import type { Provider, ProviderCreate, ProviderUpdate, ProviderBulkPatch, ProviderApiKey, PurgeResult, ImportProviderResult,
+  ProviderSchedule, ProviderScheduleCreate, ProviderScheduleUpdate, ProviderGeoInfo, BenchmarkResult } from '@/types/models'
 
 export const providersApi = {
   list: async (cliType?: string): Promise<{ data: Provider[] }> => {
@@ -18,14 +19,58 @@ export const providersApi = {
     const result = await invoke<Provider>('update_provider', { id, input: data })
     return { data: result }
   },
-  delete: async (id: number) => {
-    await invoke('delete_provider', { id })
+  delete: async (id: number, keepLogs?: boolean) => {
+    await invoke('delete_provider', { id, keepLogs })
     return { data: null }
   },
+  purgeLogs: async (providerName: string): Promise<{ data: PurgeResult }> => {
+    const data = await invoke<PurgeResult>('purge_provider_from_logs', { providerName })
+    return { data }
+  },
+  exportProviders: async (cliType?: string, maskApiKeys?: boolean): Promise<{ data: string }> => {
+    const data = await invoke<string>('export_providers', { cliType, maskApiKeys })
+    return { data }
+  },
+  importProviders: async (json: string, overwriteExisting: boolean): Promise<{ data: ImportProviderResult }> => {
+    const data = await invoke<ImportProviderResult>('import_providers', { json, overwriteExisting })
+    return { data }
+  },
+  importFromOpenaiConfig: async (filePath: string, overwrite: boolean, cliType?: string): Promise<{ data: ImportProviderResult }> => {
+    const data = await invoke<ImportProviderResult>('import_providers_from_openai_config', { filePath, overwrite, cliType })
+    return { data }
+  },
+  bulkUpdate: async (ids: number[], patch: ProviderBulkPatch): Promise<{ data: Provider[] }> => {
+    const data = await invoke<Provider[]>('bulk_update_providers', { ids, patch })
+    return { data }
+  },
   reorder: async (ids: number[]) => {
     await invoke('reorder_providers', { ids })
     return { data: null }
   },
+  reorderModelMaps: async (providerId: number, ids: number[]) => {
+    await invoke('reorder_model_maps', { providerId, ids })
+    return { data: null }
+  },
+  getModelSuggestions: async (providerId: number): Promise<{ data: string[] }> => {
+    const data = await invoke<string[]>('get_provider_model_suggestions', { providerId })
+    return { data }
+  },
+  getGeoInfo: async (providerId: number): Promise<{ data: ProviderGeoInfo }> => {
+    const data = await invoke<ProviderGeoInfo>('get_provider_geographic_info', { providerId })
+    return { data }
+  },
+  refreshGeoInfo: async (providerId: number): Promise<{ data: ProviderGeoInfo }> => {
+    const data = await invoke<ProviderGeoInfo>('refresh_provider_geo_info', { providerId })
+    return { data }
+  },
+  benchmark: async (providerId: number, requestCount: number, modelId: string): Promise<{ data: BenchmarkResult }> => {
+    const data = await invoke<BenchmarkResult>('benchmark_provider', { providerId, requestCount, modelId })
+    return { data }
+  },
+  benchmarkConcurrent: async (providerId: number, concurrency: number, total: number, modelId: string): Promise<{ data: BenchmarkResult }> => {
+    const data = await invoke<BenchmarkResult>('benchmark_concurrent', { providerId, concurrency, total, modelId })
+    return { data }
+  },
   resetFailures: async (id: number) => {
     await invoke('reset_provider_failures', { id })
     return { data: null }
@@ -33,5 +78,41 @@ export const providersApi = {
   unblacklist: async (id: number) => {
     await invoke('reset_provider_failures', { id })
     return { data: null }
+  },
+  simulateFailure: async (providerId: number, failureCount: number) => {
+    await invoke('simulate_provider_failure', { providerId, failureCount })
+    return { data: null }
+  },
+  simulateRecovery: async (providerId: number) => {
+    await invoke('simulate_provider_recovery', { providerId })
+    return { data: null }
+  },
+  listApiKeys: async (providerId: number): Promise<{ data: ProviderApiKey[] }> => {
+    const data = await invoke<ProviderApiKey[]>('list_provider_api_keys', { providerId })
+    return { data }
+  },
+  addApiKey: async (providerId: number, apiKey: string): Promise<{ data: ProviderApiKey }> => {
+    const data = await invoke<ProviderApiKey>('add_provider_api_key', { providerId, apiKey })
+    return { data }
+  },
+  removeApiKey: async (id: number) => {
+    await invoke('remove_provider_api_key', { id })
+    return { data: null }
+  },
+  listSchedules: async (providerId?: number): Promise<{ data: ProviderSchedule[] }> => {
+    const data = await invoke<ProviderSchedule[]>('get_provider_schedules', { providerId })
+    return { data }
+  },
+  createSchedule: async (data: ProviderScheduleCreate): Promise<{ data: ProviderSchedule }> => {
+    const result = await invoke<ProviderSchedule>('create_provider_schedule', { input: data })
+    return { data: result }
+  },
+  updateSchedule: async (id: number, data: ProviderScheduleUpdate): Promise<{ data: ProviderSchedule }> => {
+    const result = await invoke<ProviderSchedule>('update_provider_schedule', { id, input: data })
+    return { data: result }
+  },
+  deleteSchedule: async (id: number) => {
+    await invoke('delete_provider_schedule', { id })
+    return { data: null }
   }
 }
@@ -15,7 +15,8 @@ export interface SessionInfo {
   mtime: number
   first_message: string
   git_branch: string
-  summary: string
+  summary: string | null
+  estimated_cost_usd: number | null
 }
 
 export interface SessionMessage {
@@ -24,6 +25,25 @@ export interface SessionMessage {
   timestamp?: number
 }
 
+// Payload of the session_message_added event
+export interface SessionMessagesAdded {
+  session_id: string
+  messages: SessionMessage[]
+}
+
+export interface SessionSearchHit {
+  cli_type: string
+  project_name: string
+  session_id: string
+  matching_excerpt: string
+  mtime: number
+}
+
+export interface CompressionStats {
+  files_compressed: number
+  bytes_saved: number
+}
+
 export interface PaginatedResponse<T> {
   items: T[]
   total: number
@@ -60,6 +80,36 @@ export const sessionsApi = {
     return { data }
   },
 
+  summarize: async (cliType: string, projectName: string, sessionId: string, providerId?: number): Promise<{ data: string }> => {
+    const data = await invoke<string>('get_session_summary', { cliType, projectName, sessionId, providerId })
+    return { data }
+  },
+
+  watchSession: async (cliType: string, projectName: string, sessionId: string) => {
+    await invoke('watch_session_file', { cliType, projectName, sessionId })
+    return { data: null }
+  },
+
+  stopWatching: async (sessionId: string) => {
+    await invoke('stop_watching_session', { sessionId })
+    return { data: null }
+  },
+
+  search: async (query: string, cliType?: string, page = 1, pageSize = 20): Promise<{ data: PaginatedResponse<SessionSearchHit> }> => {
+    const data = await invoke<PaginatedResponse<SessionSearchHit>>('get_sessions_search', {
+      query,
+      cliType,
+      page,
+      pageSize
+    })
+    return { data }
+  },
+
+  compress: async (cliType: string, olderThanDays: number): Promise<{ data: CompressionStats }> => {
+    const data = await invoke<CompressionStats>('compress_sessions', { cliType, olderThanDays })
+    return { data }
+  },
+
   deleteSession: async (cliType: string, projectName: string, sessionId: string) => {
     await invoke('delete_session', { cliType, projectName, sessionId })
     return { data: null }
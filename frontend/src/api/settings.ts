@@ -1,19 +1,19 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { AllSettings, GatewaySettingsUpdate, TimeoutSettingsUpdate, CliSettingsUpdate, SystemStatus } from '@/types/models'
+import type { AllSettings, CliType, GatewaySettingsUpdate, UaRule, UaRuleCreate, ModelAlias, ModelAliasCreate, ModelAliasUpdate, LogExcludeRules, TimeoutSettingsUpdate, CliSettingsUpdate, CliSyncStatus, BudgetSettings, BudgetSettingsUpdate, SystemStatus, GatewayDiagnostics, DatabaseIntegrityResult, ApplyEnvResult } from '@/types/models'
 
 export const settingsApi = {
   getAll: async () => {
     const [gateway, timeouts, claudeCode, codex, gemini, status] = await Promise.all([
-      invoke<{ debug_log: number }>('get_gateway_settings'),
-      invoke<{ stream_first_byte_timeout: number; stream_idle_timeout: number; non_stream_timeout: number }>('get_timeout_settings'),
-      invoke<{ cli_type: string; enabled: boolean; default_json_config: string }>('get_cli_settings', { cliType: 'claude_code' }),
-      invoke<{ cli_type: string; enabled: boolean; default_json_config: string }>('get_cli_settings', { cliType: 'codex' }),
-      invoke<{ cli_type: string; enabled: boolean; default_json_config: string }>('get_cli_settings', { cliType: 'gemini' }),
+      invoke<{ debug_log: number; max_request_body_mb: number; log_body_max_kb: number; use_keychain: number; request_id_header: string | null; ws_proxy_enabled: number; gateway_token: string | null; gateway_token_enforced: number; listen_external: number; listen_address: string | null; allow_simulation_commands: number; default_cli_type: CliType; server_host: string | null; server_port: number | null; cli_ports: string; cli_path_prefix: number; default_proxy_user_agent: string | null; sticky_session_enabled: number }>('get_gateway_settings'),
+      invoke<{ stream_first_byte_timeout: number; stream_idle_timeout: number; non_stream_timeout: number; stream_per_token_timeout_ms: number; stream_keepalive_interval: number }>('get_timeout_settings'),
+      invoke<{ cli_type: string; enabled: boolean; default_json_config: string; prompt_append_mode: boolean }>('get_cli_settings', { cliType: 'claude_code' }),
+      invoke<{ cli_type: string; enabled: boolean; default_json_config: string; prompt_append_mode: boolean }>('get_cli_settings', { cliType: 'codex' }),
+      invoke<{ cli_type: string; enabled: boolean; default_json_config: string; prompt_append_mode: boolean }>('get_cli_settings', { cliType: 'gemini' }),
       invoke<SystemStatus>('get_system_status')
     ])
     return {
       data: {
-        gateway: { debug_log: !!gateway.debug_log },
+        gateway: { debug_log: !!gateway.debug_log, max_request_body_mb: gateway.max_request_body_mb, log_body_max_kb: gateway.log_body_max_kb, use_keychain: !!gateway.use_keychain, request_id_header: gateway.request_id_header, ws_proxy_enabled: !!gateway.ws_proxy_enabled, gateway_token: gateway.gateway_token, gateway_token_enforced: !!gateway.gateway_token_enforced, listen_external: !!gateway.listen_external, listen_address: gateway.listen_address, allow_simulation_commands: !!gateway.allow_simulation_commands, default_cli_type: gateway.default_cli_type, server_host: gateway.server_host, server_port: gateway.server_port, cli_ports: JSON.parse(gateway.cli_ports || '{}'), cli_path_prefix: !!gateway.cli_path_prefix, default_proxy_user_agent: gateway.default_proxy_user_agent, sticky_session_enabled: !!gateway.sticky_session_enabled },
         timeouts,
         cli_settings: {
           claude_code: claudeCode,
@@ -25,7 +25,42 @@ export const settingsApi = {
     }
   },
   updateGateway: async (data: GatewaySettingsUpdate) => {
-    await invoke('update_gateway_settings', { debugLog: data.debug_log })
+    await invoke('update_gateway_settings', {
+      debugLog: data.debug_log,
+      maxRequestBodyMb: data.max_request_body_mb,
+      logBodyMaxKb: data.log_body_max_kb,
+      useKeychain: data.use_keychain,
+      requestIdHeader: data.request_id_header,
+      wsProxyEnabled: data.ws_proxy_enabled,
+      gatewayTokenEnforced: data.gateway_token_enforced,
+      listenExternal: data.listen_external,
+      listenAddress: data.listen_address,
+      allowSimulationCommands: data.allow_simulation_commands,
+      defaultCliType: data.default_cli_type,
+      cliPathPrefix: data.cli_path_prefix,
+      defaultProxyUserAgent: data.default_proxy_user_agent,
+      stickySessionEnabled: data.sticky_session_enabled
+    })
+    return { data: null }
+  },
+  migrateKeysToKeychain: async () => {
+    const data = await invoke<number>('migrate_keys_to_keychain')
+    return { data }
+  },
+  regenerateGatewayToken: async () => {
+    const data = await invoke<string>('regenerate_gateway_token')
+    return { data }
+  },
+  updateGatewayPort: async (newPort: number) => {
+    await invoke('update_gateway_port', { newPort })
+    return { data: null }
+  },
+  updateServerBinding: async (host: string, port: number) => {
+    await invoke('update_server_binding', { host, port })
+    return { data: null }
+  },
+  updateCliPorts: async (ports: Partial<Record<CliType, number>>) => {
+    await invoke('update_cli_ports', { ports })
     return { data: null }
   },
   updateTimeouts: async (data: TimeoutSettingsUpdate) => {
@@ -36,8 +71,108 @@ export const settingsApi = {
     await invoke('update_cli_settings', { cliType, input: data })
     return { data: null }
   },
+  getCliSyncStatus: async () => {
+    const data = await invoke<CliSyncStatus[]>('get_cli_sync_status')
+    return { data }
+  },
+  listUaRules: async () => {
+    const data = await invoke<UaRule[]>('list_ua_rules')
+    return { data }
+  },
+  createUaRule: async (input: UaRuleCreate) => {
+    const data = await invoke<UaRule>('create_ua_rule', { input })
+    return { data }
+  },
+  deleteUaRule: async (id: number) => {
+    await invoke('delete_ua_rule', { id })
+    return { data: null }
+  },
+  listModelAliases: async (cliType?: CliType) => {
+    const data = await invoke<ModelAlias[]>('get_model_aliases', { cliType })
+    return { data }
+  },
+  createModelAlias: async (input: ModelAliasCreate) => {
+    const data = await invoke<ModelAlias>('create_model_alias', { input })
+    return { data }
+  },
+  updateModelAlias: async (id: number, input: ModelAliasUpdate) => {
+    const data = await invoke<ModelAlias>('update_model_alias', { id, input })
+    return { data }
+  },
+  deleteModelAlias: async (id: number) => {
+    await invoke('delete_model_alias', { id })
+    return { data: null }
+  },
+  getMaskPatterns: async () => {
+    const data = await invoke<string[]>('get_mask_patterns')
+    return { data }
+  },
+  updateMaskPatterns: async (patterns: string[]) => {
+    await invoke('update_mask_patterns', { patterns })
+    return { data: null }
+  },
+  getCorsOrigins: async () => {
+    const data = await invoke<string[]>('get_cors_origins')
+    return { data }
+  },
+  updateCorsOrigins: async (origins: string[]) => {
+    const data = await invoke<string[]>('update_cors_origins', { origins })
+    return { data }
+  },
+  getAllowedClientIps: async () => {
+    const data = await invoke<string[]>('get_allowed_client_ips')
+    return { data }
+  },
+  updateAllowedClientIps: async (entries: string[]) => {
+    const data = await invoke<string[]>('update_allowed_client_ips', { entries })
+    return { data }
+  },
+  getMaxConcurrentRequests: async () => {
+    const data = await invoke<number>('get_max_concurrent_requests')
+    return { data }
+  },
+  updateMaxConcurrentRequests: async (limit: number) => {
+    const data = await invoke<number>('update_max_concurrent_requests', { limit })
+    return { data }
+  },
+  getBudgetSettings: async () => {
+    const data = await invoke<BudgetSettings[]>('get_budget_settings')
+    return { data }
+  },
+  updateBudgetSettings: async (cliType: CliType, data: BudgetSettingsUpdate) => {
+    await invoke('update_budget_settings', { cliType, input: data })
+    return { data: null }
+  },
+  getLogExcludePaths: async () => {
+    const data = await invoke<LogExcludeRules>('get_log_exclude_paths')
+    return { data }
+  },
+  updateLogExcludePaths: async (data: LogExcludeRules) => {
+    await invoke('update_log_exclude_paths', { patterns: data.patterns, countUsage: data.count_usage })
+    return { data: null }
+  },
+  reloadConfig: async () => {
+    await invoke('reload_config')
+    return { data: null }
+  },
+  checkDatabaseIntegrity: async () => {
+    const data = await invoke<DatabaseIntegrityResult>('check_database_integrity')
+    return { data }
+  },
+  getDiagnostics: async () => {
+    const data = await invoke<GatewayDiagnostics>('get_gateway_diagnostics')
+    return { data }
+  },
   getStatus: async () => {
     const data = await invoke<SystemStatus>('get_system_status')
     return { data }
+  },
+  exportEnvFile: async () => {
+    const data = await invoke<string>('get_gateway_config_as_env_file')
+    return { data }
+  },
+  applyEnvFile: async (content: string) => {
+    const data = await invoke<ApplyEnvResult>('apply_env_file', { content })
+    return { data }
   }
 }
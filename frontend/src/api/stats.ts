@@ -1,5 +1,5 @@
 import { invoke } from '@tauri-apps/api/core'
-import type { DailyStats, ProviderStats } from '@/types/models'
+import type { DailyStats, ProviderStats, ModelUsageStats, UncoveredModel, ProviderErrorBreakdown, HourlyStats, ModelPricing } from '@/types/models'
 
 export const statsApi = {
   getDaily: async (params?: { start_date?: string; end_date?: string; cli_type?: string; provider_name?: string }): Promise<{ data: DailyStats[] }> => {
@@ -16,5 +16,65 @@ export const statsApi = {
       endDate: params?.end_date
     })
     return { data }
+  },
+  getModels: async (params?: { start_date?: string; end_date?: string }): Promise<{ data: ModelUsageStats[] }> => {
+    const data = await invoke<ModelUsageStats[]>('get_model_usage_breakdown', {
+      startDate: params?.start_date,
+      endDate: params?.end_date
+    })
+    return { data }
+  },
+  getUncoveredModels: async (cliType?: string): Promise<{ data: UncoveredModel[] }> => {
+    const data = await invoke<UncoveredModel[]>('get_uncovered_models', { cliType })
+    return { data }
+  },
+  suggestModelMap: async (sourceModel: string): Promise<{ data: string[] }> => {
+    const data = await invoke<string[]>('suggest_model_map', { sourceModel })
+    return { data }
+  },
+  getErrorBreakdown: async (params?: { provider_name?: string; start_date?: string; end_date?: string }): Promise<{ data: ProviderErrorBreakdown[] }> => {
+    const data = await invoke<ProviderErrorBreakdown[]>('get_provider_error_breakdown', {
+      providerName: params?.provider_name,
+      startDate: params?.start_date,
+      endDate: params?.end_date
+    })
+    return { data }
+  },
+  getHourly: async (params: { date: string; cli_type?: string }): Promise<{ data: HourlyStats[] }> => {
+    const data = await invoke<HourlyStats[]>('get_hourly_stats', {
+      date: params.date,
+      cliType: params.cli_type
+    })
+    return { data }
+  },
+  getAvailableModels: async (): Promise<{ data: string[] }> => {
+    const data = await invoke<string[]>('get_available_models')
+    return { data }
+  },
+  getModelPricing: async (): Promise<{ data: ModelPricing[] }> => {
+    const data = await invoke<ModelPricing[]>('get_model_pricing')
+    return { data }
+  },
+  setModelPricing: async (params: {
+    provider_name: string
+    model_id: string
+    input_price_per_million_tokens: number
+    output_price_per_million_tokens: number
+    cache_read_price_per_million_tokens?: number | null
+    cache_write_price_per_million_tokens?: number | null
+  }): Promise<{ data: ModelPricing }> => {
+    const data = await invoke<ModelPricing>('set_model_pricing', {
+      providerName: params.provider_name,
+      modelId: params.model_id,
+      inputPricePerMillionTokens: params.input_price_per_million_tokens,
+      outputPricePerMillionTokens: params.output_price_per_million_tokens,
+      cacheReadPricePerMillionTokens: params.cache_read_price_per_million_tokens ?? null,
+      cacheWritePricePerMillionTokens: params.cache_write_price_per_million_tokens ?? null
+    })
+    return { data }
+  },
+  deleteModelPricing: async (id: number) => {
+    await invoke('delete_model_pricing', { id })
+    return { data: null }
   }
 }
@@ -1,24 +1,35 @@
 import { defineStore } from 'pinia'
 import { ref } from 'vue'
 import { settingsApi } from '@/api/settings'
+import type { BudgetStatus } from '@/types/models'
 
 export const useDashboardStore = defineStore('dashboard', () => {
-  const status = ref<'running' | 'stopped'>('stopped')
+  const status = ref<'starting' | 'running' | 'failed' | 'stopped'>('stopped')
+  const error = ref<string | null>(null)
   const port = ref(7788)
   const uptime = ref(0)
   const version = ref('')
+  const lanUrl = ref<string | null>(null)
+  const listenAddress = ref<string | null>(null)
+  const totalCostUsd = ref(0)
+  const budgets = ref<BudgetStatus[]>([])
 
   async function fetchStatus() {
     try {
       const { data } = await settingsApi.getStatus()
       status.value = data.status
+      error.value = data.error
       port.value = data.port
       uptime.value = data.uptime
       version.value = data.version
+      lanUrl.value = data.lan_url
+      listenAddress.value = data.listen_address
+      totalCostUsd.value = data.total_cost_usd
+      budgets.value = data.budgets
     } catch {
       status.value = 'stopped'
     }
   }
 
-  return { status, port, uptime, version, fetchStatus }
+  return { status, error, port, uptime, version, lanUrl, listenAddress, totalCostUsd, budgets, fetchStatus }
 })
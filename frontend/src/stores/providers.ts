@@ -35,8 +35,8 @@ export const useProviderStore = defineStore('providers', () => {
     return provider
   }
 
-  async function deleteProvider(id: number) {
-    await providersApi.delete(id)
+  async function deleteProvider(id: number, keepLogs?: boolean) {
+    await providersApi.delete(id, keepLogs)
     providers.value = providers.value.filter(p => p.id !== id)
   }
 
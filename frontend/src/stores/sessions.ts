@@ -1,6 +1,7 @@
 import { defineStore } from 'pinia'
 import { ref } from 'vue'
-import { sessionsApi, type ProjectInfo, type SessionInfo, type SessionMessage } from '@/api/sessions'
+import { listen, type UnlistenFn } from '@tauri-apps/api/event'
+import { sessionsApi, type ProjectInfo, type SessionInfo, type SessionMessage, type SessionMessagesAdded } from '@/api/sessions'
 import type { CliType } from '@/types/models'
 import { useUiStore } from './ui'
 
@@ -20,6 +21,10 @@ export const useSessionStore = defineStore('sessions', () => {
   const sessionTotal = ref(0)
   const pageSize = ref(20)
 
+  // 正在查看的会话文件有新消息时追加
+  const watchedSession = ref<string>('')
+  let unlistenMessages: UnlistenFn | null = null
+
   async function fetchProjects(page?: number, cliType?: CliType) {
     loading.value = true
     if (page !== undefined) {
@@ -72,6 +77,7 @@ export const useSessionStore = defineStore('sessions', () => {
       const type = cliType || uiStore.sessionsActiveCliType
       const { data } = await sessionsApi.getSessionMessages(type, projectName, sessionId)
       messages.value = data
+      await watchSession(type, projectName, sessionId)
     } catch (error: any) {
       console.error('Failed to fetch messages:', error)
       messages.value = []
@@ -80,6 +86,42 @@ export const useSessionStore = defineStore('sessions', () => {
     }
   }
 
+  async function watchSession(cliType: CliType, projectName: string, sessionId: string) {
+    if (!unlistenMessages) {
+      unlistenMessages = await listen<SessionMessagesAdded>('session_message_added', event => {
+        if (event.payload.session_id === currentSession.value) {
+          messages.value.push(...event.payload.messages)
+        }
+      })
+    }
+    await stopWatching()
+    try {
+      await sessionsApi.watchSession(cliType, projectName, sessionId)
+      watchedSession.value = sessionId
+    } catch (error: any) {
+      // 已压缩的历史会话无法监听，不影响查看
+      console.warn('Failed to watch session:', error)
+    }
+  }
+
+  async function stopWatching() {
+    if (!watchedSession.value) return
+    const sessionId = watchedSession.value
+    watchedSession.value = ''
+    await sessionsApi.stopWatching(sessionId).catch(() => {})
+  }
+
+  async function summarizeSession(projectName: string, sessionId: string, cliType?: CliType) {
+    const uiStore = useUiStore()
+    const type = cliType || uiStore.sessionsActiveCliType
+    const { data } = await sessionsApi.summarize(type, projectName, sessionId)
+    const session = sessions.value.find(s => s.session_id === sessionId)
+    if (session) {
+      session.summary = data
+    }
+    return data
+  }
+
   async function deleteSession(projectName: string, sessionId: string, cliType?: CliType) {
     const uiStore = useUiStore()
     const type = cliType || uiStore.sessionsActiveCliType
@@ -99,6 +141,7 @@ export const useSessionStore = defineStore('sessions', () => {
   function clearMessages() {
     messages.value = []
     currentSession.value = ''
+    stopWatching()
   }
 
   function clearSessions() {
@@ -125,6 +168,7 @@ export const useSessionStore = defineStore('sessions', () => {
     fetchProjects,
     fetchSessions,
     fetchMessages,
+    summarizeSession,
     deleteSession,
     deleteProject,
     clearMessages,
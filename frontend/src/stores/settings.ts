@@ -1,11 +1,16 @@
 import { defineStore } from 'pinia'
 import { ref } from 'vue'
+import { ElMessage } from 'element-plus'
+import { listen, type UnlistenFn } from '@tauri-apps/api/event'
 import { settingsApi } from '@/api/settings'
-import type { AllSettings, GatewaySettingsUpdate, TimeoutSettingsUpdate, CliSettingsUpdate } from '@/types/models'
+import type { CliSyncStatus, AllSettings, GatewaySettingsUpdate, TimeoutSettingsUpdate, CliSettingsUpdate } from '@/types/models'
 
 export const useSettingsStore = defineStore('settings', () => {
   const settings = ref<AllSettings | null>(null)
   const loading = ref(false)
+  // CLI 配置文件被外部程序改动后与网关状态不一致
+  const cliSyncStatus = ref<Record<string, CliSyncStatus>>({})
+  let unlistenCliSync: UnlistenFn | null = null
 
   async function fetchSettings() {
     loading.value = true
@@ -29,8 +34,26 @@ export const useSettingsStore = defineStore('settings', () => {
 
   async function updateCli(cliType: string, data: CliSettingsUpdate) {
     await settingsApi.updateCli(cliType, data)
-    await fetchSettings()
+    await Promise.all([fetchSettings(), fetchCliSyncStatus()])
+  }
+
+  async function fetchCliSyncStatus() {
+    const { data } = await settingsApi.getCliSyncStatus()
+    cliSyncStatus.value = Object.fromEntries(data.map(s => [s.cli_type, s]))
+  }
+
+  async function listenCliSync() {
+    if (unlistenCliSync) return
+    unlistenCliSync = await listen<CliSyncStatus>('cli_config_externally_modified', async (event) => {
+      const status = event.payload
+      cliSyncStatus.value = { ...cliSyncStatus.value, [status.cli_type]: status }
+      if (status.out_of_sync) {
+        ElMessage.warning(`${status.cli_type} 配置文件已被外部修改，当前${status.is_gateway_enabled ? '已' : '未'}接入网关`)
+      }
+      await fetchSettings()
+    })
+    await fetchCliSyncStatus()
   }
 
-  return { settings, loading, fetchSettings, updateGateway, updateTimeouts, updateCli }
+  return { settings, loading, cliSyncStatus, fetchSettings, updateGateway, updateTimeouts, updateCli, fetchCliSyncStatus, listenCliSync }
 })
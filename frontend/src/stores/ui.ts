@@ -14,7 +14,7 @@ export const useUiStore = defineStore('ui', () => {
 
   // 全局配置页面的 tab 状态
   const configActiveCliTab = ref<'claude_code' | 'codex' | 'gemini'>('claude_code')
-  const configActiveBackupTab = ref<'local' | 'webdav'>('local')
+  const configActiveBackupTab = ref<'local' | 'webdav' | 'env'>('local')
 
   function setProvidersActiveCliType(cliType: CliType) {
     providersActiveCliType.value = cliType
@@ -32,7 +32,7 @@ export const useUiStore = defineStore('ui', () => {
     configActiveCliTab.value = tab
   }
 
-  function setConfigActiveBackupTab(tab: 'local' | 'webdav') {
+  function setConfigActiveBackupTab(tab: 'local' | 'webdav' | 'env') {
     configActiveBackupTab.value = tab
   }
 
@@ -2,11 +2,22 @@
 export type CliType = 'claude_code' | 'codex' | 'gemini'
 
 // Provider types
+export type AuthScheme = 'bearer' | 'x-api-key' | 'query_param' | 'passthrough'
+export type ProviderFlavor = 'openai' | 'azure_openai'
+export type WireApi = 'responses' | 'chat'
+export type ProviderProtocol = 'native' | 'openai'
+
 export interface ModelMap {
   id?: number
   source_model: string
   target_model: string
   enabled: boolean
+  sort_order?: number
+}
+
+export interface PathRewriteRule {
+  from_prefix: string
+  to_prefix: string
 }
 
 export interface Provider {
@@ -21,10 +32,112 @@ export interface Provider {
   consecutive_failures: number
   blacklisted_until: number | null
   sort_order: number
+  proxy_url: string | null
+  user_agent_pattern: string | null
+  cli_type_override: CliType | null
+  insecure_skip_tls_verify: boolean
+  custom_headers: Record<string, string>
+  extra_query_params: Record<string, string>
+  path_rewrite_rules: PathRewriteRule[]
+  auth_scheme: AuthScheme | null
+  flavor: ProviderFlavor | null
+  wire_api: WireApi | null
+  protocol: ProviderProtocol | null
+  max_tokens_limit: number | null
+  strip_params: string[]
+  inject_system_prompt: string | null
+  inject_system_prompt_enabled: boolean
+  body_rewrite_rules: BodyRewriteRule[]
+  drop_response_headers: string[]
+  local_count_tokens: boolean
+  stream_include_usage: boolean
+  proxy_user_agent: string | null
+  max_connections: number | null
+  connection_timeout_secs: number | null
+  budget_exceeded_until: number | null
+  country_code: string | null
   model_maps: ModelMap[]
   is_blacklisted: boolean
 }
 
+export interface ProviderGeoInfo {
+  provider_id: number
+  host: string
+  ip: string
+  country_code: string | null
+  city: string | null
+  asn_name: string | null
+  resolved_at: number
+}
+
+export interface BenchmarkResult {
+  min_ms: number
+  max_ms: number
+  mean_ms: number
+  p95_ms: number
+  success_rate: number
+  requests_sent: number
+}
+
+export interface ProviderApiKey {
+  id: number
+  provider_id: number
+  api_key: string
+  enabled: boolean
+  last_used_at: number | null
+  failure_count: number
+}
+
+export interface ProviderSchedule {
+  id: number
+  provider_id: number
+  day_of_week: number
+  hour_start: number
+  hour_end: number
+  priority_override: number
+  created_at: number
+  updated_at: number
+}
+
+export interface ProviderScheduleCreate {
+  provider_id: number
+  day_of_week?: number
+  hour_start: number
+  hour_end: number
+  priority_override: number
+}
+
+export interface ProviderScheduleUpdate {
+  day_of_week?: number
+  hour_start?: number
+  hour_end?: number
+  priority_override?: number
+}
+
+export type BodyRewriteRule =
+  | { op: 'set'; path: string; value: unknown }
+  | { op: 'remove'; path: string }
+  | { op: 'rename'; path: string; to: string }
+
+export interface PurgeResult {
+  request_log_count: number
+  system_log_count: number
+  usage_daily_count: number
+}
+
+export interface ImportProviderResult {
+  imported: number
+  skipped: number
+  errors: string[]
+}
+
+// apply_env_file: applied / unrecognized keys and `KEY: error` failures
+export interface ApplyEnvResult {
+  applied: string[]
+  skipped: string[]
+  failed: string[]
+}
+
 export interface ProviderCreate {
   cli_type?: CliType
   name: string
@@ -33,6 +146,28 @@ export interface ProviderCreate {
   enabled?: boolean
   failure_threshold?: number
   blacklist_minutes?: number
+  proxy_url?: string
+  user_agent_pattern?: string
+  cli_type_override?: CliType | ''
+  insecure_skip_tls_verify?: boolean
+  custom_headers?: Record<string, string>
+  extra_query_params?: Record<string, string>
+  path_rewrite_rules?: PathRewriteRule[]
+  auth_scheme?: AuthScheme | ''
+  flavor?: ProviderFlavor | ''
+  wire_api?: WireApi | ''
+  protocol?: ProviderProtocol | ''
+  max_tokens_limit?: number | null
+  strip_params?: string[]
+  inject_system_prompt?: string
+  inject_system_prompt_enabled?: boolean
+  body_rewrite_rules?: BodyRewriteRule[]
+  drop_response_headers?: string[]
+  local_count_tokens?: boolean
+  stream_include_usage?: boolean
+  proxy_user_agent?: string
+  max_connections?: number | null
+  connection_timeout_secs?: number | null
   model_maps?: ModelMap[]
 }
 
@@ -43,24 +178,85 @@ export interface ProviderUpdate {
   enabled?: boolean
   failure_threshold?: number
   blacklist_minutes?: number
+  proxy_url?: string
+  user_agent_pattern?: string
+  cli_type_override?: CliType | ''
+  insecure_skip_tls_verify?: boolean
+  custom_headers?: Record<string, string>
+  extra_query_params?: Record<string, string>
+  path_rewrite_rules?: PathRewriteRule[]
+  auth_scheme?: AuthScheme | ''
+  flavor?: ProviderFlavor | ''
+  wire_api?: WireApi | ''
+  protocol?: ProviderProtocol | ''
+  max_tokens_limit?: number | null
+  strip_params?: string[]
+  inject_system_prompt?: string
+  inject_system_prompt_enabled?: boolean
+  body_rewrite_rules?: BodyRewriteRule[]
+  drop_response_headers?: string[]
+  local_count_tokens?: boolean
+  stream_include_usage?: boolean
+  proxy_user_agent?: string
+  max_connections?: number | null
+  connection_timeout_secs?: number | null
   model_maps?: ModelMap[]
 }
 
+export interface ProviderBulkPatch {
+  enabled?: boolean
+  failure_threshold?: number
+  blacklist_minutes?: number
+}
+
 // Settings types
 export interface GatewaySettings {
   debug_log: boolean
+  max_request_body_mb: number
+  log_body_max_kb: number
+  use_keychain: boolean
+  request_id_header: string | null
+  ws_proxy_enabled: boolean
+  gateway_token: string | null
+  gateway_token_enforced: boolean
+  listen_external: boolean
+  listen_address: string | null
+  allow_simulation_commands: boolean
+  default_cli_type: CliType
+  server_host: string | null
+  server_port: number | null
+  // 各 CLI 的独立监听端口，未设置的 CLI 使用共享端口（按 User-Agent 识别）
+  cli_ports: Partial<Record<CliType, number>>
+  cli_path_prefix: boolean
+  default_proxy_user_agent: string | null
+  sticky_session_enabled: boolean
+}
+
+export interface LogExcludeRules {
+  patterns: string[]
+  count_usage: boolean
 }
 
 export interface TimeoutSettings {
   stream_first_byte_timeout: number
   stream_idle_timeout: number
   non_stream_timeout: number
+  stream_per_token_timeout_ms: number
+  stream_keepalive_interval: number
 }
 
 export interface CliSettings {
   cli_type: string
   enabled: boolean
   default_json_config: string
+  prompt_append_mode: boolean
+}
+
+// CLI 配置文件是否仍与网关写入的状态一致
+export interface CliSyncStatus {
+  cli_type: string
+  is_gateway_enabled: boolean
+  out_of_sync: boolean
 }
 
 export interface AllSettings {
@@ -71,24 +267,152 @@ export interface AllSettings {
 
 export interface GatewaySettingsUpdate {
   debug_log?: boolean
+  max_request_body_mb?: number
+  log_body_max_kb?: number
+  use_keychain?: boolean
+  request_id_header?: string
+  ws_proxy_enabled?: boolean
+  gateway_token_enforced?: boolean
+  listen_external?: boolean
+  listen_address?: string
+  allow_simulation_commands?: boolean
+  default_cli_type?: CliType
+  cli_path_prefix?: boolean
+  default_proxy_user_agent?: string
+  sticky_session_enabled?: boolean
+}
+
+// 全局 User-Agent 规则（priority 小的先匹配）
+export interface UaRule {
+  id: number
+  pattern: string
+  cli_type: CliType
+  priority: number
+  created_at: number
+}
+
+export interface UaRuleCreate {
+  pattern: string
+  cli_type: CliType
+  priority?: number
+}
+
+export interface ModelAlias {
+  id: number
+  cli_type: CliType
+  source_model: string
+  target_model: string
+  enabled: number
+  created_at: number
+  updated_at: number
+}
+
+export interface ModelAliasCreate {
+  cli_type: CliType
+  source_model: string
+  target_model: string
+  enabled?: boolean
+}
+
+export interface ModelAliasUpdate {
+  source_model?: string
+  target_model?: string
+  enabled?: boolean
 }
 
 export interface TimeoutSettingsUpdate {
   stream_first_byte_timeout?: number
   stream_idle_timeout?: number
   non_stream_timeout?: number
+  stream_per_token_timeout_ms?: number
+  stream_keepalive_interval?: number
 }
 
 export interface CliSettingsUpdate {
   enabled?: boolean
   default_json_config?: string
+  prompt_append_mode?: boolean
+}
+
+export interface DatabaseIntegrityResult {
+  ok: boolean
+  issues: string[]
 }
 
 export interface SystemStatus {
-  status: 'running' | 'stopped'
+  status: 'starting' | 'running' | 'failed'
+  error: string | null
   port: number
   uptime: number
   version: string
+  listen_address: string | null
+  lan_url: string | null
+  total_cost_usd: number
+  budgets: BudgetStatus[]
+  // 占用并发名额的代理请求（含未结束的流式响应）
+  requests_in_flight: number
+  // 0 表示不限制
+  max_concurrent_requests: number
+}
+
+// 每日 token 预算，作用于该 CLI 类型下的每个服务商
+export interface BudgetSettings {
+  cli_type: CliType
+  daily_input_token_limit: number | null
+  daily_output_token_limit: number | null
+  warning_threshold_pct: number
+  block_when_exceeded: number
+}
+
+export interface BudgetSettingsUpdate {
+  daily_input_token_limit?: number | null
+  daily_output_token_limit?: number | null
+  warning_threshold_pct?: number
+  block_when_exceeded?: boolean
+}
+
+export interface BudgetStatus {
+  cli_type: CliType
+  provider_name: string
+  input_tokens: number
+  output_tokens: number
+  daily_input_token_limit: number | null
+  daily_output_token_limit: number | null
+  state: 'ok' | 'warning' | 'exceeded'
+}
+
+export interface ProviderHealthSummary {
+  id: number
+  cli_type: string
+  name: string
+  enabled: boolean
+  consecutive_failures: number
+  is_blacklisted: boolean
+  blacklisted_until: number | null
+}
+
+export interface GatewayDiagnostics {
+  version: string
+  port: number
+  db_size_bytes: number
+  log_db_size_bytes: number
+  active_connections: number
+  provider_statuses: ProviderHealthSummary[]
+  memory_rss_kb: number | null
+  uptime_secs: number
+  total_requests_handled: number
+  requests_in_flight: number
+}
+
+// 进行中的代理请求（流式请求持续到流结束）
+export interface ActiveRequest {
+  request_id: string
+  provider_name: string | null
+  cli_type: string | null
+  model_id: string | null
+  started_at: number
+  elapsed_ms: number
+  is_streaming: boolean
 }
 
 // MCP types
@@ -104,6 +428,8 @@ export interface Mcp {
   config_json: string
   enabled: boolean
   cli_flags: Record<string, boolean>
+  // 启用于 Gemini 时同时注入请求的 systemInstruction
+  inject_system_prompt: boolean
 }
 
 export interface McpCreate {
@@ -111,6 +437,7 @@ export interface McpCreate {
   config_json: string
   enabled?: boolean
   cli_flags?: CliFlags
+  inject_system_prompt?: boolean
 }
 
 export interface McpUpdate {
@@ -118,6 +445,21 @@ export interface McpUpdate {
   config_json?: string
   enabled?: boolean
   cli_flags?: CliFlags
+  inject_system_prompt?: boolean
+}
+
+export interface McpValidationResult {
+  valid: boolean
+  errors: string[]
+}
+
+export type McpDiffStatus = 'in_sync' | 'db_only' | 'filesystem_only' | 'diverged'
+
+export interface McpDiff {
+  mcp_name: string
+  db_config: string | null
+  fs_config: string | null
+  status: McpDiffStatus
 }
 
 // Prompt types
@@ -153,6 +495,21 @@ export interface DailyStats {
   failure_count: number
   prompt_tokens: number
   completion_tokens: number
+  cache_creation_tokens: number
+  cache_read_tokens: number
+  cost_usd: number
+}
+
+export interface ModelPricing {
+  id: number
+  provider_name: string
+  model_id: string
+  input_price_per_million_tokens: number
+  output_price_per_million_tokens: number
+  // null = input price
+  cache_read_price_per_million_tokens: number | null
+  cache_write_price_per_million_tokens: number | null
+  updated_at: number
 }
 
 export interface ProviderStats {
@@ -163,6 +520,53 @@ export interface ProviderStats {
   total_failure: number
   success_rate: number
   total_tokens: number
+  avg_ttfb_ms: number | null
+}
+
+// 请求日志中没有命中该服务商任何模型映射的模型
+export interface UncoveredModel {
+  model_id: string
+  provider_name: string
+  cli_type: CliType
+  request_count: number
+  first_seen: number
+  last_seen: number
+}
+
+export interface ModelUsageStats {
+  model_id: string
+  total_requests: number
+  total_input_tokens: number
+  total_output_tokens: number
+  provider_count: number
+  avg_latency_ms: number
+}
+
+export type ProviderErrorType = 'timeout' | 'rate_limit' | 'server_error' | 'auth_error' | 'client_error' | 'unknown'
+
+export interface ProviderErrorBreakdown {
+  provider_name: string
+  error_type: ProviderErrorType
+  count: number
+  last_occurrence: number
+}
+
+export interface HourlyStats {
+  hour: number
+  request_count: number
+  success_count: number
+  input_tokens: number
+  output_tokens: number
+}
+
+export interface ReplayResult {
+  log_id: number
+  provider_name: string
+  status_code: number
+  elapsed_ms: number
+  response_body: string
+  input_tokens: number
+  output_tokens: number
 }
 
 // Log types
@@ -176,14 +580,20 @@ export interface RequestLogListItem {
   elapsed_ms: number
   input_tokens: number
   output_tokens: number
+  cache_creation_tokens: number
+  cache_read_tokens: number
   client_method: string
   client_path: string
+  request_id: string | null
+  client_ip: string | null
+  ttfb_ms: number | null
 }
 
 export interface RequestLogDetail extends RequestLogListItem {
   client_headers: string
   client_body: string
   forward_url: string
+  forward_proxy: string | null
   forward_headers: string
   forward_body: string
   provider_headers: string | null
@@ -191,6 +601,34 @@ export interface RequestLogDetail extends RequestLogListItem {
   response_headers: string | null
   response_body: string | null
   error_message: string | null
+  body_transforms: string | null
+  model_chain: string | null
+  attempts: string | null
+  batch_id: string | null
+}
+
+export interface BatchRequestItem {
+  id: number
+  batch_id: string
+  custom_id: string
+  result_type: string
+  status_code: number | null
+  input_tokens: number
+  output_tokens: number
+  created_at: number
+}
+
+export interface RequestAttempt {
+  provider_name: string
+  status_code: number | null
+  error: string | null
+  elapsed_ms: number
+}
+
+export interface LogContext {
+  before: RequestLogListItem[]
+  target: RequestLogDetail
+  after: RequestLogListItem[]
 }
 
 export interface RequestLogListResponse {
@@ -200,6 +638,18 @@ export interface RequestLogListResponse {
   page_size: number
 }
 
+export interface CostLogItem extends RequestLogListItem {
+  cost_usd: number | null
+}
+
+export interface CostLogListResponse {
+  items: CostLogItem[]
+  total: number
+  page: number
+  page_size: number
+  total_cost_usd: number
+}
+
 export interface SystemLogItem {
   id: number
   created_at: number
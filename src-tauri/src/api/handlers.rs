@@ -1,35 +1,55 @@
 use axum::{
     body::Body,
     extract::{Path, Query, State},
-    http::{Response, StatusCode},
+    http::{header, HeaderName, HeaderValue, Method, Response, StatusCode},
+    response::IntoResponse,
     Json,
 };
 use bytes::Bytes;
 use futures_util::StreamExt;
+use tracing::Instrument;
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{mpsc, Mutex};
-use flate2::read::GzDecoder;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
 use std::io::Read;
 
 use super::AppState;
+use crate::commands;
 use crate::db::models::{
-    Provider, ProviderCreate, ProviderResponse, ProviderUpdate,
-    GatewaySettings, TimeoutSettings, TimeoutSettingsUpdate,
-    RequestLogItem, RequestLogDetail, PaginatedLogs,
-    SystemLogItem, SystemLogListResponse,
-    DailyStats,
+    ProviderCreate, ProviderResponse, ProviderUpdate,
+    GatewaySettings, GatewaySettingsUpdate, TimeoutSettings, TimeoutSettingsUpdate, CliSettingsResponse, CliSettingsUpdate,
+    WebdavSettings, WebdavSettingsUpdate, WebdavBackup,
+    RequestLogDetail, RequestLogKey, PaginatedLogs,
+    SystemLogListResponse,
+    DailyStats, ProviderStatsResponse,
     SystemStatus,
+    McpCreate, McpResponse, McpUpdate, PromptCreate, PromptResponse, PromptUpdate,
+    PaginatedProjects, PaginatedSessions, SessionMessage,
+    DEFAULT_MAX_REQUEST_BODY_MB, MAX_REQUEST_BODY_MB_LIMIT,
 };
 use crate::services::proxy::{
-    apply_body_model_mapping, apply_url_model_mapping, detect_cli_type,
-    filter_headers, is_streaming, parse_token_usage, set_auth_header,
-    CliType, TimeoutConfig, TokenUsage,
+    append_azure_api_version, append_query_params, apply_auth_query, apply_body_model_mapping, apply_body_rewrite_rules, apply_model_override, apply_custom_headers, apply_path_rewrites, apply_url_model_mapping, apply_model_aliases, clamp_max_tokens, load_model_aliases, detect_cli_type_with_patterns, inject_system_prompt, append_gemini_system_instruction,
+    apply_proxy_user_agent, azure_openai_path, client_presents_token, ends_sse_event, extract_session_id, sticky_session_id, is_event_stream, filter_headers, strip_gateway_token, take_model_override, is_streaming, strip_cli_path_prefix, inject_stream_include_usage, parse_token_usage, set_auth_header, set_azure_api_key, strip_body_params,
+    AuthScheme, CliType, ProviderFlavor, ProviderProtocol, ResponseHeaderPolicy, WireApi, SseUsageParser, TimeoutConfig, TokenUsage,
+    REQUEST_ID_RESPONSE_HEADER, SSE_KEEPALIVE,
 };
-use crate::services::routing::select_provider;
+use crate::services::diagnostics::GaugeGuard;
+use crate::services::http_client::ClientOptions;
+use crate::services::masking::{is_secret_header, mask_secret_header_value};
+use crate::services::responses_chat;
+use crate::services::translate::{self, ResponseTranslation};
+use crate::services::websocket;
+use crate::services::routing::{select_provider, select_sticky_provider, ProviderWithMaps};
+use crate::services::secrets;
+use crate::services::batch;
+use crate::services::budget;
 use crate::services::{provider as provider_service, stats as stats_service};
-use crate::services::stats::RequestLogInfo;
+use crate::services::stats::{truncation_marker, RequestAttempt, RequestLogInfo};
+use crate::services::token_count;
 
 // Common query params
 #[derive(Debug, Deserialize)]
@@ -45,6 +65,11 @@ pub struct ProviderQuery {
     pub cli_type: Option<String>,
 }
 
+/// Default of gateway_settings.log_body_max_kb
+const DEFAULT_LOG_BODY_MAX_KB: i64 = 100;
+/// Share of a truncated log body kept from its start; the rest keeps its tail
+const LOG_BODY_HEAD_PERCENT: usize = 80;
+
 fn default_page() -> i64 {
     1
 }
@@ -77,10 +102,90 @@ pub async fn proxy_handler_catchall(
     State(state): State<Arc<AppState>>,
     req: axum::http::Request<Body>,
 ) -> Result<Response<Body>, StatusCode> {
+    // Every proxied request gets an ID: logged in request_logs, attached to its tracing
+    // lines and returned to the client so errors can be matched to the log entry
+    let active_id = uuid::Uuid::new_v4();
+    let request_id = active_id.to_string();
+    let span = tracing::info_span!("request", request_id = %request_id);
+
+    // Saturated: ask the client to retry instead of piling up more upstream requests
+    let Some(permit) = state.concurrency.try_acquire() else {
+        tracing::warn!(limit = state.concurrency.limit(), "Concurrent request limit reached");
+        let mut response = saturated_response();
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response.headers_mut().insert(REQUEST_ID_RESPONSE_HEADER, value);
+        }
+        return Ok(response);
+    };
+
+    // Listed in get_active_requests until the response (or its stream) is done
+    let active = state.active_requests.register(active_id);
+    let cancelled = active.cancelled();
+    let mut response = tokio::select! {
+        response = proxy_request(state.clone(), req, active_id).instrument(span) => {
+            response.unwrap_or_else(IntoResponse::into_response)
+        }
+        _ = cancelled => {
+            tracing::info!(request_id = %request_id, "Request cancelled");
+            return Ok(cancelled_response(&request_id));
+        }
+    };
+
+    if active.is_streaming() {
+        // Keep the entry and the concurrency slot until the stream ends; cancelling stops forwarding
+        let cancelled = active.cancelled();
+        let body = std::mem::take(response.body_mut()).into_data_stream().take_until(cancelled);
+        *response.body_mut() = Body::from_stream(async_stream::stream! {
+            let _active = active;
+            let _permit = permit;
+            for await chunk in body {
+                yield chunk;
+            }
+        });
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_RESPONSE_HEADER, value);
+    }
+    Ok(response)
+}
+
+fn saturated_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("content-type", "application/json")
+        .header(header::RETRY_AFTER, crate::services::concurrency::RETRY_AFTER_SECS)
+        .body(Body::from(r#"{"error": "Gateway is busy, retry later"}"#))
+        .unwrap()
+}
+
+fn cancelled_response(request_id: &str) -> Response<Body> {
+    let mut response = Response::builder()
+        .status(StatusCode::from_u16(499).unwrap_or(StatusCode::SERVICE_UNAVAILABLE))
+        .header("content-type", "application/json")
+        .body(Body::from(r#"{"error": "Request cancelled by the gateway"}"#))
+        .unwrap();
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(REQUEST_ID_RESPONSE_HEADER, value);
+    }
+    response
+}
+
+async fn proxy_request(
+    state: Arc<AppState>,
+    mut req: axum::http::Request<Body>,
+    active_id: uuid::Uuid,
+) -> Result<Response<Body>, StatusCode> {
+    let request_id = active_id.to_string();
     let start_time = Instant::now();
+    let _in_flight = GaugeGuard::new(&state.counters.requests_in_flight);
+    state.counters.total_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     let method = req.method().clone();
-    let headers = req.headers().clone();
+    let mut headers = req.headers().clone();
     let uri = req.uri().clone();
+    let client_ip = req
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .map(|info| info.0.ip().to_string());
 
     // Get the full path including query string
     let full_path = if let Some(query) = uri.query() {
@@ -89,26 +194,93 @@ pub async fn proxy_handler_catchall(
         uri.path().to_string()
     };
 
-    // Detect CLI type from User-Agent
-    let cli_type = detect_cli_type(&headers);
+    // A /claude, /codex or /gemini prefix names the CLI type and is not forwarded
+    let (prefix_cli_type, full_path) = match strip_cli_path_prefix(&full_path) {
+        Some((cli_type, path)) => (Some(cli_type), path),
+        None => (None, full_path),
+    };
+
+    // Dedicated per-CLI listeners know the CLI type, then the path prefix;
+    // otherwise detect it from the User-Agent (provider patterns take precedence)
+    let cli_type = match req.extensions().get::<super::ForcedCliType>() {
+        Some(forced) => forced.0,
+        None => prefix_cli_type
+            .unwrap_or_else(|| detect_cli_type_with_patterns(&headers, &state.ua_patterns, &state.ua_rules)),
+    };
+    state.active_requests.update(&active_id, |info| info.cli_type = Some(cli_type.to_string()));
 
-    // Serialize client headers for logging
-    let client_headers_json = serialize_headers(&headers);
+    // OPTIONS is answered locally and never reaches the upstream (the proxy routes have no CORS)
+    if method == Method::OPTIONS {
+        return Ok(options_response(&state, cli_type, &full_path, &headers, client_ip, &request_id, start_time).await);
+    }
+    // GET / HEAD carry no body: nothing to read, map or rewrite
+    let bodyless = method == Method::GET || method == Method::HEAD;
+
+    // Clients must present the gateway token unless enforcement is turned off
+    // (LAN mode always enforces it)
+    if let Some(token) = enforced_gateway_token(&state.db, state.listen_addr.is_external()).await {
+        if !client_presents_token(&headers, &token) {
+            return Ok(gateway_unauthorized_response(&state, cli_type, &method, &full_path, client_ip.as_deref()).await);
+        }
+        strip_gateway_token(&mut headers, &token);
+    }
 
-    // Read request body
-    let body_bytes = match axum::body::to_bytes(req.into_body(), 10 * 1024 * 1024).await {
-        Ok(bytes) => bytes.to_vec(),
-        Err(e) => {
-            tracing::error!(error = %e, "Failed to read request body");
-            return Err(StatusCode::BAD_REQUEST);
+    // Client details shared by every request log entry
+    let client_log = RequestLogInfo {
+        client_headers: Some(serialize_headers(&headers)),
+        client_ip,
+        request_id: Some(request_id.clone()),
+        ..Default::default()
+    };
+    let log_body_limit = log_body_limit(&state.db).await;
+
+    // X-CCG-Model forces the target model for this request only
+    let forced_model = take_model_override(&mut headers);
+
+    // WebSocket handshakes are relayed as a raw connection instead of a request/response
+    if websocket::is_websocket_upgrade(&headers) && ws_proxy_enabled(&state.db).await {
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        return ws_proxy_handler(state, on_upgrade, &headers, cli_type, &full_path, client_log, log_body_limit, start_time).await;
+    }
+
+    // Read request body (limit is read per request so setting changes apply immediately)
+    let body_bytes = if bodyless {
+        Vec::new()
+    } else {
+        let max_body_bytes = max_request_body_bytes(&state.db).await;
+        let content_length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if let Some(len) = content_length.filter(|len| *len > max_body_bytes) {
+            return Ok(body_too_large_response(&state, cli_type, &method, &full_path, client_log, start_time, &len.to_string()).await);
+        }
+
+        match axum::body::to_bytes(req.into_body(), max_body_bytes).await {
+            Ok(bytes) => bytes.to_vec(),
+            Err(e) => {
+                let exceeded = e
+                    .into_inner()
+                    .downcast_ref::<http_body_util::LengthLimitError>()
+                    .is_some();
+                if exceeded {
+                    let size = format!("> {}", max_body_bytes);
+                    return Ok(body_too_large_response(&state, cli_type, &method, &full_path, client_log, start_time, &size).await);
+                }
+                tracing::error!("Failed to read request body");
+                return Err(StatusCode::BAD_REQUEST);
+            }
         }
     };
 
-    // Store client body for logging (truncate if too large)
-    let client_body_str = truncate_body(&body_bytes);
+    // Store client body for logging (truncate if too large; none for bodyless requests)
+    let client_body_str = (!body_bytes.is_empty()).then(|| truncate_body(&body_bytes, log_body_limit));
+
+    // The request ID is also sent upstream when request_id_header is configured
+    let (debug_log, request_id_header) = request_tracing_settings(&state.db).await;
 
     // Select provider based on CLI type
-    let provider_with_maps = match select_provider(&state.db, cli_type.as_str()).await {
+    let provider_with_maps = match select_request_provider(&state, &headers, &body_bytes, cli_type).await {
         Ok(Some(p)) => p,
         Ok(None) => {
             tracing::warn!(cli_type = %cli_type, "No available provider");
@@ -137,28 +309,55 @@ pub async fn proxy_handler_catchall(
     let provider_id = provider.id;
     let provider_name = provider.name.clone();
 
+    // Relays without count_tokens: answer locally instead of collecting 404s and failures
+    if provider.local_count_tokens != 0
+        && cli_type == CliType::ClaudeCode
+        && method == Method::POST
+        && token_count::is_count_tokens_path(&full_path)
+    {
+        return Ok(local_count_tokens_response(&state, cli_type, &full_path, &body_bytes, client_log, log_body_limit, start_time).await);
+    }
+
     // Get timeout settings
-    let timeouts = match sqlx::query_as::<_, (i64, i64, i64)>(
-        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout FROM timeout_settings WHERE id = 1",
+    let timeouts = match sqlx::query_as::<_, (i64, i64, i64, i64, i64)>(
+        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout, stream_per_token_timeout_ms, stream_keepalive_interval FROM timeout_settings WHERE id = 1",
     )
     .fetch_one(&state.db)
     .await
     {
-        Ok((first, idle, non_stream)) => TimeoutConfig::from_db(first, idle, non_stream),
+        Ok((first, idle, non_stream, per_token_ms, keepalive)) => TimeoutConfig::from_db(first, idle, non_stream, per_token_ms, keepalive),
         Err(_) => TimeoutConfig::default(),
     };
 
     // Check if streaming
     let streaming = is_streaming(&body_bytes, &full_path, cli_type);
 
+    // Global model aliases rewrite the client model first; provider model maps apply on top
+    let alias = if bodyless {
+        None
+    } else {
+        match load_model_aliases(&state.db, cli_type).await {
+            Ok(aliases) => apply_model_aliases(cli_type, &body_bytes, &full_path, &aliases),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to load model aliases");
+                None
+            }
+        }
+    };
+    let (mapping_body, mapping_path) = match &alias {
+        Some(alias) => (alias.body.as_slice(), alias.path.as_str()),
+        None => (body_bytes.as_slice(), full_path.as_str()),
+    };
+
     // Apply model mapping and extract model info
     let (final_body, final_path, source_model, target_model) = match cli_type {
+        _ if bodyless => (body_bytes.clone(), full_path.clone(), None, None),
         CliType::Gemini => {
-            let mapping = apply_url_model_mapping(&provider_with_maps, &full_path, &provider_with_maps.model_maps);
-            (body_bytes.clone(), mapping.path, mapping.source_model, mapping.target_model)
+            let mapping = apply_url_model_mapping(&provider_with_maps, mapping_path, mapping_body, &provider_with_maps.model_maps);
+            (mapping.body, mapping.path, mapping.source_model, mapping.target_model)
         }
         _ => {
-            let mapping = apply_body_model_mapping(&provider_with_maps, &body_bytes, &full_path);
+            let mapping = apply_body_model_mapping(&provider_with_maps, mapping_body, mapping_path);
             (mapping.body, mapping.path, mapping.source_model, mapping.target_model)
         }
     };
@@ -166,14 +365,232 @@ pub async fn proxy_handler_catchall(
     // Use target model if mapped, otherwise use source model
     let model_id = target_model.clone().or(source_model.clone());
 
+    // Client model -> alias -> provider map (-> forced model), logged when anything was rewritten
+    let mut model_chain: Vec<String> = match &alias {
+        Some(alias) => alias.source_model.iter().chain(&alias.target_model).cloned().collect(),
+        None => source_model.iter().cloned().collect(),
+    };
+    model_chain.extend(target_model.clone());
+
+    let mut body_transforms = Vec::new();
+
+    // Forced model replaces the mapped one; the log keeps both
+    let (final_body, final_path, model_id) = match forced_model.as_deref() {
+        Some(forced) => match apply_model_override(cli_type, &final_body, &final_path, forced) {
+            Some((body, path)) => {
+                body_transforms.push(format!(
+                    "model forced by header: {} -> {}",
+                    model_id.as_deref().unwrap_or("-"),
+                    forced
+                ));
+                model_chain.push(forced.to_string());
+                (body, path, Some(forced.to_string()))
+            }
+            None => (final_body, final_path, model_id),
+        },
+        None => (final_body, final_path, model_id),
+    };
+
+    // Provider body rewrite rules run right after model mapping, before the built-in transforms
+    let final_body = match apply_body_rewrite_rules(&final_body, provider.body_rewrite_rules.as_deref()) {
+        Some((body, applied)) => {
+            body_transforms.push(format!("{} rewrite rule(s) applied", applied));
+            body
+        }
+        None => final_body,
+    };
+
+    // Clamp the requested output tokens to the provider limit (before any protocol translation)
+    let final_body = match clamp_max_tokens(&final_body, cli_type, provider.max_tokens_limit) {
+        Some((body, note)) => {
+            body_transforms.push(note);
+            body
+        }
+        None => final_body,
+    };
+
+    // Provider system prompt goes in before translation so every protocol carries it
+    // (generation endpoints only: countTokens and batches reject the field)
+    let final_body = match provider.inject_system_prompt.as_deref() {
+        Some(prompt) if provider.inject_system_prompt_enabled != 0 => {
+            match inject_system_prompt(&final_body, &final_path, cli_type, prompt) {
+                Some(body) => {
+                    body_transforms.push("system prompt injected".to_string());
+                    body
+                }
+                None => final_body,
+            }
+        }
+        _ => final_body,
+    };
+
+    // Gemini generate requests: prompt presets marked for injection that are enabled for Gemini
+    // go into systemInstruction (countTokens and other endpoints don't accept the field)
+    let final_body = if cli_type == CliType::Gemini && translate::gemini_generate_target(&final_path).is_some() {
+        let prompts = commands::load_injected_prompts(&state.db, cli_type.as_str())
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!(error = %e, "Failed to load prompts to inject");
+                Vec::new()
+            });
+        match append_gemini_system_instruction(&final_body, &prompts) {
+            Some(body) => {
+                body_transforms.push("prompt preset added to systemInstruction".to_string());
+                body
+            }
+            None => final_body,
+        }
+    } else {
+        final_body
+    };
+
+    // Codex provider speaking chat/completions: translate the Responses API request
+    let translate_to_chat = cli_type == CliType::Codex
+        && method == Method::POST
+        && WireApi::from_provider(provider.wire_api.as_deref()) == WireApi::Chat
+        && responses_chat::is_responses_path(&final_path);
+    // Claude Code / Gemini provider speaking OpenAI chat/completions: translate the request
+    let openai_protocol = method == Method::POST
+        && ProviderProtocol::from_provider(provider.protocol.as_deref()) == ProviderProtocol::OpenAi;
+    let translate_anthropic =
+        openai_protocol && cli_type == CliType::ClaudeCode && translate::is_messages_path(&final_path);
+    let translate_gemini = openai_protocol
+        && cli_type == CliType::Gemini
+        && translate::gemini_generate_target(&final_path).is_some();
+    let (final_body, final_path, translation) = if translate_to_chat {
+        match responses_chat::responses_to_chat_request(&final_body) {
+            Ok((body, ctx)) => (
+                body,
+                responses_chat::chat_completions_path(&final_path),
+                Some(ResponseTranslation::Responses(ctx)),
+            ),
+            Err(e) => {
+                tracing::warn!(provider = %provider_name, error = %e, "Failed to translate Responses request to chat");
+                (final_body, final_path, None)
+            }
+        }
+    } else if translate_anthropic {
+        match translate::anthropic_to_chat_request(&final_body) {
+            Ok((body, ctx)) => (
+                body,
+                translate::chat_completions_path(&final_path),
+                Some(ResponseTranslation::Anthropic(ctx)),
+            ),
+            Err(e) => {
+                tracing::warn!(provider = %provider_name, error = %e, "Failed to translate Messages request to chat");
+                (final_body, final_path, None)
+            }
+        }
+    } else if translate_gemini {
+        match translate::gemini_to_chat_request(&final_body, &final_path) {
+            Ok((body, ctx)) => (
+                body,
+                translate::GEMINI_CHAT_PATH.to_string(),
+                Some(ResponseTranslation::Gemini(ctx)),
+            ),
+            Err(e) => {
+                tracing::warn!(provider = %provider_name, error = %e, "Failed to translate Gemini request to chat");
+                (final_body, final_path, None)
+            }
+        }
+    } else {
+        (final_body, final_path, None)
+    };
+
+    // Codex chat/completions streams: request the final usage chunk so tokens are not logged as 0
+    let final_body = if cli_type == CliType::Codex && provider.stream_include_usage != 0 {
+        match inject_stream_include_usage(&final_body, &final_path) {
+            Some(body) => {
+                body_transforms.push("stream_options.include_usage added".to_string());
+                body
+            }
+            None => final_body,
+        }
+    } else {
+        final_body
+    };
+
+    // Drop fields the upstream rejects; runs last so it applies to the body actually forwarded
+    let final_body = match strip_body_params(&final_body, provider.strip_params.as_deref()) {
+        Some((body, removed)) => {
+            body_transforms.push(format!("stripped {}", removed.join(", ")));
+            body
+        }
+        None => final_body,
+    };
+
     // Build upstream URL: base_url + original_path
     // e.g., base_url="https://api.example.com/v1", path="/responses" -> "https://api.example.com/v1/responses"
     let base_url = provider.base_url.trim_end_matches('/');
-    let upstream_url = format!("{}{}", base_url, final_path);
+    let final_path = apply_path_rewrites(&final_path, provider.path_rewrite_rules.as_deref());
+    // Azure OpenAI (Codex only): the mapped model is the deployment name
+    let azure = cli_type == CliType::Codex
+        && ProviderFlavor::from_provider(provider.flavor.as_deref()) == ProviderFlavor::AzureOpenAi;
+    let final_path = match model_id.as_deref() {
+        Some(deployment) if azure => azure_openai_path(&final_path, deployment),
+        _ => final_path,
+    };
+    let upstream_url = append_query_params(
+        &format!("{}{}", base_url, final_path),
+        provider.extra_query_params.as_deref(),
+    );
+    let upstream_url = if azure { append_azure_api_version(&upstream_url) } else { upstream_url };
+
+    // Use the provider key, or rotate through provider_api_keys when it is empty
+    let rotated_key = if provider.api_key.trim().is_empty() {
+        match provider_service::select_api_key(&state.db, provider, &state.key_cursors).await {
+            Ok(key) => key,
+            Err(e) => {
+                tracing::error!(provider = %provider_name, error = %e, "Failed to select API key");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let api_key = rotated_key
+        .as_ref()
+        .map(|k| k.api_key.as_str())
+        .unwrap_or(&provider.api_key);
+    let api_key = match resolve_provider_key(&state, &provider_name, api_key).await {
+        Ok(key) => key,
+        Err(response) => return Ok(response),
+    };
+
+    // query_param auth carries the key in the URL; the logged URL keeps it masked
+    let auth_scheme = AuthScheme::from_provider(provider.auth_scheme.as_deref());
+    // 翻译成 chat 的 Gemini 请求默认走 Bearer，不把 x-goog-api-key / ?key= 发给 OpenAI 兼容服务
+    let auth_scheme = match translation {
+        Some(ResponseTranslation::Gemini(_)) => auth_scheme.or(Some(AuthScheme::Bearer)),
+        _ => auth_scheme,
+    };
+    let (upstream_url, logged_upstream_url) = apply_auth_query(&upstream_url, &api_key, auth_scheme);
 
     // Prepare headers - filter hop-by-hop headers and set auth
-    let mut req_headers = filter_headers(&headers);
-    set_auth_header(&mut req_headers, &provider.api_key, cli_type);
+    let mut req_headers = filter_headers(&headers, cli_type);
+    set_auth_header(&mut req_headers, &api_key, cli_type, auth_scheme);
+    if azure && auth_scheme.is_none() {
+        set_azure_api_key(&mut req_headers, &api_key);
+    }
+    if translation.is_some() {
+        // 需要逐行改写响应，要求上游不压缩
+        req_headers.insert(
+            reqwest::header::ACCEPT_ENCODING,
+            reqwest::header::HeaderValue::from_static("identity"),
+        );
+    }
+
+    if let Some(name) = &request_id_header {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&request_id) {
+            req_headers.insert(name.clone(), value);
+        }
+    }
+
+    let default_user_agent = default_proxy_user_agent(&state.db).await;
+    apply_proxy_user_agent(&mut req_headers, provider.proxy_user_agent.as_deref(), default_user_agent.as_deref());
+
+    // Provider static headers win over client headers (and the auth header only when named)
+    let custom_header_names = apply_custom_headers(&mut req_headers, provider.custom_headers.as_deref());
 
     // Set content-type if not present
     if !req_headers.contains_key(reqwest::header::CONTENT_TYPE) {
@@ -184,11 +601,31 @@ pub async fn proxy_handler_catchall(
     }
 
     // Serialize forward headers for logging (mask sensitive headers)
-    let forward_headers_json = serialize_reqwest_headers(&req_headers);
-    let forward_body_str = truncate_body(&final_body);
+    let forward_headers_json = serialize_reqwest_headers(&req_headers, &custom_header_names);
+    let forward_body_str = (!final_body.is_empty()).then(|| truncate_body(&final_body, log_body_limit));
+
+    // Reuse the pooled HTTP client (per-provider client when a proxy is configured)
+    let client_options = ClientOptions::from_provider(provider);
+    let client = match state.http_clients.client_for(provider_id, &client_options) {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(provider = %provider_name, error = %e, "Failed to build HTTP client");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    if client_options.insecure_skip_tls_verify && state.http_clients.first_insecure_use(provider_id) {
+        tracing::warn!(provider = %provider_name, "TLS certificate verification disabled");
+        let _ = stats_service::record_system_log(
+            &state.log_db,
+            "warn",
+            "insecure_tls",
+            &format!("Provider {} is using a connection without TLS certificate verification", provider_name),
+            Some(&provider_name),
+            None,
+        ).await;
+    }
 
-    // Create HTTP client request
-    let client = reqwest::Client::new();
     let request_builder = match method.as_str() {
         "GET" => client.get(&upstream_url),
         "POST" => client.post(&upstream_url),
@@ -208,18 +645,40 @@ pub async fn proxy_handler_catchall(
         request_builder
     };
 
+    if debug_log {
+        tracing::info!(
+            provider = %provider_name,
+            url = %logged_upstream_url,
+            "[{}] Forwarding {} {}",
+            cli_type, method, full_path
+        );
+    }
+
     // Build log info
     let log_info = RequestLogInfo {
-        client_headers: Some(client_headers_json),
-        client_body: Some(client_body_str),
-        forward_url: Some(upstream_url.clone()),
+        client_body: client_body_str,
+        forward_url: Some(logged_upstream_url),
+        forward_proxy: client_options.proxy_url.as_deref().map(crate::services::masking::mask_url_credentials),
         forward_headers: Some(forward_headers_json),
-        forward_body: Some(forward_body_str),
-        ..Default::default()
+        forward_body: forward_body_str,
+        api_key_id: rotated_key.as_ref().map(|k| k.id),
+        session_id: extract_session_id(&headers, &body_bytes, cli_type),
+        body_transforms: (!body_transforms.is_empty()).then(|| body_transforms.join("; ")),
+        model_chain: (model_chain.len() > 1).then(|| model_chain.join(" -> ")),
+        ..client_log
     };
 
+    // Upstream headers are filtered on the way back; the request log keeps them all
+    let header_policy = ResponseHeaderPolicy::from_provider(provider.drop_response_headers.as_deref());
+
+    state.active_requests.update(&active_id, |info| {
+        info.provider_name = Some(provider_name.clone());
+        info.model_id = model_id.clone();
+        info.is_streaming = streaming;
+    });
+
     // Execute request
-    if streaming {
+    let response = if streaming {
         handle_streaming_request(
             request_builder,
             &state,
@@ -232,6 +691,9 @@ pub async fn proxy_handler_catchall(
             start_time,
             timeouts,
             log_info,
+            log_body_limit,
+            translation,
+            header_policy,
         )
         .await
     } else {
@@ -247,131 +709,772 @@ pub async fn proxy_handler_catchall(
             start_time,
             timeouts,
             log_info,
+            log_body_limit,
+            translation,
+            header_policy,
         )
         .await
+    };
+
+    // HEAD responses never carry a body, whatever the upstream or an error path produced
+    let response = if method == Method::HEAD {
+        response.map(|mut response| {
+            *response.body_mut() = Body::empty();
+            response
+        })
+    } else {
+        response
+    };
+
+    // Also echo the request ID under the configured upstream header name
+    match (response, request_id_header) {
+        (Ok(mut response), Some(name)) => {
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                response.headers_mut().insert(name, value);
+            }
+            Ok(response)
+        }
+        (response, _) => response,
     }
 }
 
+/// Serialize client headers for the request log; credential values are masked
 fn serialize_headers(headers: &axum::http::HeaderMap) -> String {
     let map: std::collections::HashMap<String, String> = headers
         .iter()
         .filter_map(|(k, v)| {
             let key = k.as_str().to_lowercase();
-            v.to_str().ok().map(|v| (key, v.to_string()))
+            v.to_str().ok().map(|v| {
+                let value = if is_secret_header(&key) { mask_secret_header_value(v) } else { v.to_string() };
+                (key, value)
+            })
         })
         .collect();
     serde_json::to_string(&map).unwrap_or_default()
 }
 
-fn serialize_reqwest_headers(headers: &reqwest::header::HeaderMap) -> String {
+/// Serialize outgoing headers; credentials and provider custom headers whose name looks secret are masked
+fn serialize_reqwest_headers(
+    headers: &reqwest::header::HeaderMap,
+    custom_header_names: &[reqwest::header::HeaderName],
+) -> String {
     let map: std::collections::HashMap<String, String> = headers
         .iter()
         .filter_map(|(k, v)| {
             let key = k.as_str().to_lowercase();
-            v.to_str().ok().map(|v| (key, v.to_string()))
+            let secret = is_secret_header(&key) || (custom_header_names.contains(k) && key.contains("key"));
+            v.to_str().ok().map(|v| {
+                let value = if secret { mask_secret_header_value(v) } else { v.to_string() };
+                (key, value)
+            })
         })
         .collect();
     serde_json::to_string(&map).unwrap_or_default()
 }
 
-fn truncate_body(body: &[u8]) -> String {
-    const MAX_SIZE: usize = 100 * 1024; // 100KB
-    let s = String::from_utf8_lossy(body);
-    if s.len() > MAX_SIZE {
-        format!("{}...[truncated]", &s[..MAX_SIZE])
-    } else {
-        s.to_string()
-    }
+/// Read max_request_body_mb from gateway_settings, falling back to the default
+async fn max_request_body_bytes(db: &SqlitePool) -> usize {
+    let mb = sqlx::query_scalar::<_, i64>("SELECT max_request_body_mb FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .unwrap_or(DEFAULT_MAX_REQUEST_BODY_MB)
+        .clamp(1, MAX_REQUEST_BODY_MB_LIMIT);
+    mb as usize * 1024 * 1024
 }
 
-/// Decompress gzip data if needed
-fn maybe_decompress(body: &[u8], content_encoding: Option<&str>) -> Vec<u8> {
-    if let Some(encoding) = content_encoding {
-        if encoding.to_lowercase().contains("gzip") {
-            let mut decoder = GzDecoder::new(body);
-            let mut decompressed = Vec::new();
-            if decoder.read_to_end(&mut decompressed).is_ok() {
-                return decompressed;
-            }
+/// The gateway token when gateway_token_enforced is on (or `required` by LAN mode)
+pub(crate) async fn enforced_gateway_token(db: &SqlitePool, required: bool) -> Option<String> {
+    let (token, enforced) = sqlx::query_as::<_, (Option<String>, i64)>(
+        "SELECT gateway_token, gateway_token_enforced FROM gateway_settings WHERE id = 1",
+    )
+    .fetch_one(db)
+    .await
+    .ok()?;
+    token.filter(|t| (enforced != 0 || required) && !t.is_empty())
+}
+
+/// Plaintext API key for the upstream request (`keychain:{id}` references are read from the keychain).
+/// A keychain failure is answered with 500 and left in the system log.
+async fn resolve_provider_key(state: &AppState, provider_name: &str, api_key: &str) -> Result<String, Response<Body>> {
+    let e = match secrets::resolve_api_key(api_key) {
+        Ok(key) => return Ok(key),
+        Err(e) => e,
+    };
+    tracing::error!(provider = %provider_name, error = %e, "Failed to read API key");
+    let _ = stats_service::record_system_log(
+        &state.log_db,
+        "error",
+        "keychain_read_failed",
+        &e,
+        Some(provider_name),
+        None,
+    ).await;
+    Err(Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::json!({ "error": e }).to_string()))
+        .unwrap())
+}
+
+/// Reject a proxy request without a valid gateway token and leave a system log entry
+async fn gateway_unauthorized_response(
+    state: &AppState,
+    cli_type: CliType,
+    method: &Method,
+    full_path: &str,
+    client_ip: Option<&str>,
+) -> Response<Body> {
+    let client_ip = client_ip.unwrap_or("unknown");
+    tracing::warn!(cli_type = %cli_type, path = %full_path, client_ip, "Rejected request without a valid gateway token");
+    let _ = stats_service::record_system_log(
+        &state.log_db,
+        "warn",
+        "gateway_unauthorized",
+        &format!("Rejected {} {} ({}) from {}: missing or invalid gateway token", method, full_path, cli_type, client_ip),
+        None,
+        None,
+    ).await;
+
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("content-type", "application/json")
+        .body(Body::from(r#"{"error": "Invalid or missing gateway token"}"#))
+        .unwrap()
+}
+
+/// User-Agent sent upstream for providers without their own proxy_user_agent
+async fn default_proxy_user_agent(db: &SqlitePool) -> Option<String> {
+    sqlx::query_scalar::<_, Option<String>>("SELECT default_proxy_user_agent FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Read ws_proxy_enabled from gateway_settings
+async fn ws_proxy_enabled(db: &SqlitePool) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT ws_proxy_enabled FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .map(|v| v != 0)
+        .unwrap_or(false)
+}
+
+async fn sticky_session_enabled(db: &SqlitePool) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT sticky_session_enabled FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .map(|v| v != 0)
+        .unwrap_or(false)
+}
+
+/// Provider for a request; with sticky_session_enabled, requests carrying a session id
+/// go to the provider their session is pinned to
+async fn select_request_provider(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+    body: &[u8],
+    cli_type: CliType,
+) -> Result<Option<ProviderWithMaps>, sqlx::Error> {
+    let session_id = if sticky_session_enabled(&state.db).await {
+        sticky_session_id(headers, body, cli_type)
+    } else {
+        None
+    };
+    match session_id {
+        Some(session_id) => {
+            select_sticky_provider(&state.db, &state.schedules, &state.session_provider_map, cli_type.as_str(), &session_id).await
         }
+        None => select_provider(&state.db, &state.schedules, cli_type.as_str()).await,
     }
-    body.to_vec()
 }
 
-async fn handle_streaming_request(
-    request_builder: reqwest::RequestBuilder,
-    state: &Arc<AppState>,
-    provider_id: i64,
-    provider_name: &str,
+/// Proxy a WebSocket handshake to the selected provider and relay frames both ways.
+///
+/// The whole session is logged as one request, with elapsed_ms covering open to close
+/// and token usage summed over the upstream text messages.
+#[allow(clippy::too_many_arguments)]
+async fn ws_proxy_handler(
+    state: Arc<AppState>,
+    on_upgrade: hyper::upgrade::OnUpgrade,
+    headers: &axum::http::HeaderMap,
     cli_type: CliType,
-    model_id: Option<&str>,
-    client_method: &str,
-    client_path: &str,
+    full_path: &str,
+    client_log: RequestLogInfo,
+    log_body_limit: Option<usize>,
     start_time: Instant,
-    timeouts: TimeoutConfig,
-    mut log_info: RequestLogInfo,
 ) -> Result<Response<Body>, StatusCode> {
-    // Send request with timeout for first byte
+    let provider_with_maps = match select_request_provider(&state, headers, &[], cli_type).await {
+        Ok(Some(p)) => p,
+        Ok(None) => {
+            tracing::warn!(cli_type = %cli_type, "No available provider for WebSocket");
+            return Ok(Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"error": "No available provider configured"}"#))
+                .unwrap());
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to select provider");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let provider = &provider_with_maps.provider;
+    let provider_id = provider.id;
+    let provider_name = provider.name.clone();
+
+    let timeouts = match sqlx::query_as::<_, (i64, i64, i64, i64, i64)>(
+        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout, stream_per_token_timeout_ms, stream_keepalive_interval FROM timeout_settings WHERE id = 1",
+    )
+    .fetch_one(&state.db)
+    .await
+    {
+        Ok((first, idle, non_stream, per_token_ms, keepalive)) => TimeoutConfig::from_db(first, idle, non_stream, per_token_ms, keepalive),
+        Err(_) => TimeoutConfig::default(),
+    };
+
+    let base_url = provider.base_url.trim_end_matches('/');
+    let final_path = apply_path_rewrites(full_path, provider.path_rewrite_rules.as_deref());
+    let upstream_url = append_query_params(
+        &websocket::handshake_url(&format!("{}{}", base_url, final_path)),
+        provider.extra_query_params.as_deref(),
+    );
+
+    let rotated_key = if provider.api_key.trim().is_empty() {
+        match provider_service::select_api_key(&state.db, provider, &state.key_cursors).await {
+            Ok(key) => key,
+            Err(e) => {
+                tracing::error!(provider = %provider_name, error = %e, "Failed to select API key");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let api_key = rotated_key
+        .as_ref()
+        .map(|k| k.api_key.as_str())
+        .unwrap_or(&provider.api_key);
+    let api_key = match resolve_provider_key(&state, &provider_name, api_key).await {
+        Ok(key) => key,
+        Err(response) => return Ok(response),
+    };
+    let auth_scheme = AuthScheme::from_provider(provider.auth_scheme.as_deref());
+    let (upstream_url, logged_upstream_url) = apply_auth_query(&upstream_url, &api_key, auth_scheme);
+
+    let mut req_headers = filter_headers(headers, cli_type);
+    set_auth_header(&mut req_headers, &api_key, cli_type, auth_scheme);
+    let default_user_agent = default_proxy_user_agent(&state.db).await;
+    apply_proxy_user_agent(&mut req_headers, provider.proxy_user_agent.as_deref(), default_user_agent.as_deref());
+    let custom_header_names = apply_custom_headers(&mut req_headers, provider.custom_headers.as_deref());
+    websocket::prepare_handshake_headers(&mut req_headers);
+
+    let client_options = ClientOptions::from_provider(provider);
+    let client = match state.http_clients.upgrade_client_for(provider_id, &client_options) {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(provider = %provider_name, error = %e, "Failed to build HTTP client");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    let mut log_info = RequestLogInfo {
+        forward_url: Some(logged_upstream_url),
+        forward_proxy: client_options.proxy_url.as_deref().map(crate::services::masking::mask_url_credentials),
+        forward_headers: Some(serialize_reqwest_headers(&req_headers, &custom_header_names)),
+        api_key_id: rotated_key.as_ref().map(|k| k.id),
+        ..client_log
+    };
+
+    let sent_at = Instant::now();
     let response = match tokio::time::timeout(
         timeouts.first_byte_timeout,
-        request_builder.send(),
+        client.get(&upstream_url).headers(req_headers).send(),
     )
     .await
     {
         Ok(Ok(resp)) => resp,
         Ok(Err(e)) => {
-            tracing::error!(error = %e, "Upstream request failed");
-            if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
-                if was_blacklisted {
-                    let _ = stats_service::record_system_log(
-                        &state.log_db,
-                        "warn",
-                        "provider_blacklisted",
-                        &format!("Provider {} blacklisted due to consecutive failures", prov_name),
-                        Some(&prov_name),
-                        Some(&format!("{{\"error\": \"{}\"}}", e)),
-                    ).await;
-                }
-            }
-            log_info.error_message = Some(format!("Upstream error: {}", e));
-            record_request_stats(
-                state,
-                cli_type,
-                provider_name,
-                model_id,
-                None,
-                start_time.elapsed().as_millis() as i64,
-                0,
-                0,
-                client_method,
-                client_path,
-                Some(log_info),
-            )
-            .await;
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
-                .header("content-type", "application/json")
-                .body(Body::from(format!(r#"{{"error": "Upstream error: {}"}}"#, e)))
-                .unwrap());
+            let error = format!("Upstream error: {}", e);
+            return Ok(ws_handshake_failed(&state, provider_id, &provider_name, cli_type, full_path, start_time, log_info, error).await);
         }
         Err(_) => {
-            tracing::error!("First byte timeout");
-            if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
-                if was_blacklisted {
-                    let _ = stats_service::record_system_log(
-                        &state.log_db,
-                        "warn",
-                        "provider_blacklisted",
-                        &format!("Provider {} blacklisted due to consecutive failures", prov_name),
-                        Some(&prov_name),
-                        Some("{\"error\": \"First byte timeout\"}"),
-                    ).await;
-                }
+            let error = "First byte timeout".to_string();
+            return Ok(ws_handshake_failed(&state, provider_id, &provider_name, cli_type, full_path, start_time, log_info, error).await);
+        }
+    };
+
+    let status = response.status();
+    let resp_headers = response.headers().clone();
+    log_info.ttfb_ms = Some(sent_at.elapsed().as_millis() as i64);
+    log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers, &[]));
+    log_info.response_headers = log_info.provider_headers.clone();
+
+    if status != reqwest::StatusCode::SWITCHING_PROTOCOLS {
+        // 上游拒绝升级：原样返回其响应
+        let body = response.bytes().await.unwrap_or_default();
+        log_info.provider_body = Some(truncate_body(&body, log_body_limit));
+        log_info.response_body = log_info.provider_body.clone();
+        let error = format!("WebSocket upgrade rejected with status {}", status.as_u16());
+        let mut failed = ws_handshake_failed(&state, provider_id, &provider_name, cli_type, full_path, start_time, log_info, error).await;
+        *failed.status_mut() = StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        *failed.body_mut() = Body::from(body);
+        for (name, value) in resp_headers.iter() {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_str().as_bytes()), HeaderValue::from_bytes(value.as_bytes())) {
+                failed.headers_mut().insert(name, value);
             }
-            log_info.error_message = Some("First byte timeout".to_string());
-            record_request_stats(
-                state,
-                cli_type,
+        }
+        return Ok(failed);
+    }
+
+    let mut builder = Response::builder().status(StatusCode::SWITCHING_PROTOCOLS);
+    for (name, value) in resp_headers.iter() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    builder = builder.header("X-CCG-Provider", provider_name.as_str());
+
+    let full_path = full_path.to_string();
+    let open_connection = GaugeGuard::new(&state.counters.active_connections);
+    tokio::spawn(async move {
+        let _open_connection = open_connection;
+        let upstream_io = match response.upgrade().await {
+            Ok(io) => io,
+            Err(e) => {
+                log_info.error_message = Some(format!("Upstream upgrade failed: {}", e));
+                record_request_stats(&state, cli_type, &provider_name, None, None, start_time.elapsed().as_millis() as i64, 0, 0, "GET", &full_path, Some(log_info)).await;
+                return;
+            }
+        };
+        let client_io = match on_upgrade.await {
+            Ok(io) => hyper_util::rt::TokioIo::new(io),
+            Err(e) => {
+                log_info.error_message = Some(format!("Client upgrade failed: {}", e));
+                record_request_stats(&state, cli_type, &provider_name, None, None, start_time.elapsed().as_millis() as i64, 0, 0, "GET", &full_path, Some(log_info)).await;
+                return;
+            }
+        };
+
+        // 每条上游文本消息单独解析，用量累加
+        let mut usage = TokenUsage::default();
+        let end = websocket::relay(client_io, upstream_io, timeouts.idle_timeout, |text| {
+            let mut message_usage = TokenUsage::default();
+            parse_token_usage(text, cli_type, &mut message_usage);
+            usage.input_tokens += message_usage.input_tokens;
+            usage.output_tokens += message_usage.output_tokens;
+            usage.cache_creation_tokens += message_usage.cache_creation_tokens;
+            usage.cache_read_tokens += message_usage.cache_read_tokens;
+        })
+        .await;
+        tracing::debug!(provider = %provider_name, end = ?end, "WebSocket session closed");
+
+        if let Ok(had_failures) = provider_service::record_success(&state.db, provider_id).await {
+            if had_failures {
+                let _ = stats_service::record_system_log(
+                    &state.log_db,
+                    "info",
+                    "provider_recovered",
+                    &format!("Provider {} recovered successfully", provider_name),
+                    Some(&provider_name),
+                    None,
+                ).await;
+            }
+        }
+
+        log_info.error_message = end.error_message();
+        log_info.cache_creation_tokens = usage.cache_creation_tokens;
+        log_info.cache_read_tokens = usage.cache_read_tokens;
+        record_request_stats(
+            &state,
+            cli_type,
+            &provider_name,
+            None,
+            Some(StatusCode::SWITCHING_PROTOCOLS.as_u16()),
+            start_time.elapsed().as_millis() as i64,
+            usage.input_tokens,
+            usage.output_tokens,
+            "GET",
+            &full_path,
+            Some(log_info),
+        )
+        .await;
+    }.instrument(tracing::Span::current()));
+
+    Ok(builder.body(Body::empty()).unwrap())
+}
+
+/// Record a failed WebSocket handshake and build the 502 returned to the client
+#[allow(clippy::too_many_arguments)]
+async fn ws_handshake_failed(
+    state: &Arc<AppState>,
+    provider_id: i64,
+    provider_name: &str,
+    cli_type: CliType,
+    full_path: &str,
+    start_time: Instant,
+    mut log_info: RequestLogInfo,
+    error: String,
+) -> Response<Body> {
+    tracing::error!(provider = %provider_name, error = %error, "WebSocket handshake failed");
+    if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
+        if was_blacklisted {
+            let _ = stats_service::record_system_log(
+                &state.log_db,
+                "warn",
+                "provider_blacklisted",
+                &format!("Provider {} blacklisted due to consecutive failures", prov_name),
+                Some(&prov_name),
+                Some(&serde_json::json!({ "error": error }).to_string()),
+            ).await;
+        }
+    }
+    log_info.error_message = Some(error.clone());
+    record_request_stats(state, cli_type, provider_name, None, None, start_time.elapsed().as_millis() as i64, 0, 0, "GET", full_path, Some(log_info)).await;
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::json!({ "error": error }).to_string()))
+        .unwrap()
+}
+
+/// Read debug_log and the request ID header name from gateway_settings
+async fn request_tracing_settings(db: &SqlitePool) -> (bool, Option<HeaderName>) {
+    let (debug_log, request_id_header) = sqlx::query_as::<_, (i64, Option<String>)>(
+        "SELECT debug_log, request_id_header FROM gateway_settings WHERE id = 1",
+    )
+    .fetch_one(db)
+    .await
+    .unwrap_or((0, None));
+    let request_id_header = request_id_header
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .and_then(|name| HeaderName::from_bytes(name.as_bytes()).ok());
+    (debug_log != 0, request_id_header)
+}
+
+/// Whether `client_path` matches gateway_settings.log_exclude_paths (such requests skip the request log)
+fn log_excluded(state: &AppState, client_path: &str) -> bool {
+    state.log_exclude.read().unwrap_or_else(|e| e.into_inner()).excludes(client_path)
+}
+
+/// Answer an OPTIONS request locally with the methods the proxy accepts
+async fn options_response(
+    state: &AppState,
+    cli_type: CliType,
+    full_path: &str,
+    headers: &axum::http::HeaderMap,
+    client_ip: Option<String>,
+    request_id: &str,
+    start_time: Instant,
+) -> Response<Body> {
+    if !log_excluded(state, full_path) {
+        let _ = stats_service::record_request_log(
+            &state.log_db,
+            &state.mask_patterns,
+            cli_type.as_str(),
+            "local",
+            None,
+            Some(StatusCode::NO_CONTENT.as_u16()),
+            start_time.elapsed().as_millis() as i64,
+            0,
+            0,
+            Method::OPTIONS.as_str(),
+            full_path,
+            Some(RequestLogInfo {
+                client_headers: Some(serialize_headers(headers)),
+                client_ip,
+                request_id: Some(request_id.to_string()),
+                ..Default::default()
+            }),
+        )
+        .await;
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(header::ALLOW, "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS")
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Build a 413 response and record the rejected request so it shows up in the logs page
+async fn body_too_large_response(
+    state: &AppState,
+    cli_type: CliType,
+    method: &Method,
+    full_path: &str,
+    client_log: RequestLogInfo,
+    start_time: Instant,
+    size: &str,
+) -> Response<Body> {
+    let error_message = format!("request body too large ({} bytes)", size);
+    tracing::warn!(cli_type = %cli_type, path = %full_path, "{}", error_message);
+
+    if !log_excluded(state, full_path) {
+        let _ = stats_service::record_request_log(
+            &state.log_db,
+            &state.mask_patterns,
+            cli_type.as_str(),
+            "",
+            None,
+            Some(StatusCode::PAYLOAD_TOO_LARGE.as_u16()),
+            start_time.elapsed().as_millis() as i64,
+            0,
+            0,
+            method.as_str(),
+            full_path,
+            Some(RequestLogInfo {
+                error_message: Some(error_message.clone()),
+                ..client_log
+            }),
+        )
+        .await;
+    }
+
+    let body = serde_json::json!({ "error": error_message }).to_string();
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Answer count_tokens with a local estimate; logged as provider `local`, provider counters untouched
+async fn local_count_tokens_response(
+    state: &AppState,
+    cli_type: CliType,
+    full_path: &str,
+    body: &[u8],
+    client_log: RequestLogInfo,
+    log_body_limit: Option<usize>,
+    start_time: Instant,
+) -> Response<Body> {
+    let model_id = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("model").and_then(|m| m.as_str()).map(str::to_string));
+    let (status, response_body, error_message) = match token_count::estimate_input_tokens(body) {
+        Ok(tokens) => (StatusCode::OK, serde_json::json!({ "input_tokens": tokens }).to_string(), None),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "type": "error", "error": { "type": "invalid_request_error", "message": e } }).to_string(),
+            Some(e),
+        ),
+    };
+
+    if !log_excluded(state, full_path) {
+        let _ = stats_service::record_request_log(
+            &state.log_db,
+            &state.mask_patterns,
+            cli_type.as_str(),
+            "local",
+            model_id.as_deref(),
+            Some(status.as_u16()),
+            start_time.elapsed().as_millis() as i64,
+            0,
+            0,
+            Method::POST.as_str(),
+            full_path,
+            Some(RequestLogInfo {
+                client_body: Some(truncate_body(body, log_body_limit)),
+                response_body: Some(response_body.clone()),
+                error_message,
+                ..client_log
+            }),
+        )
+        .await;
+    }
+
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .header("X-CCG-Provider", "local")
+        .body(Body::from(response_body))
+        .unwrap()
+}
+
+/// Read log_body_max_kb from gateway_settings (None = unlimited)
+async fn log_body_limit(db: &SqlitePool) -> Option<usize> {
+    let kb = sqlx::query_scalar::<_, i64>("SELECT log_body_max_kb FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .unwrap_or(DEFAULT_LOG_BODY_MAX_KB);
+    (kb > 0).then(|| kb as usize * 1024)
+}
+
+/// Split of the log body limit into (head, tail) bytes kept around a truncation
+fn log_body_split(limit: usize) -> (usize, usize) {
+    let head = limit * LOG_BODY_HEAD_PERCENT / 100;
+    (head, limit - head)
+}
+
+/// Body text for the request log. Over the limit, the head and the tail are kept
+/// so usage blocks at the end of a stream survive.
+fn truncate_body(body: &[u8], limit: Option<usize>) -> String {
+    let s = String::from_utf8_lossy(body);
+    let Some(limit) = limit.filter(|limit| s.len() > *limit) else {
+        return s.into_owned();
+    };
+
+    let (head, tail) = log_body_split(limit);
+    let mut head_end = head;
+    while !s.is_char_boundary(head_end) {
+        head_end -= 1;
+    }
+    let mut tail_start = s.len() - tail;
+    while !s.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+    format!("{}{}{}", &s[..head_end], truncation_marker(tail_start - head_end), &s[tail_start..])
+}
+
+/// Streamed bytes kept for the request log: the head plus a rolling tail within the log body limit
+struct StreamCapture {
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    head_limit: usize,
+    tail_limit: usize,
+    skipped: usize,
+}
+
+impl StreamCapture {
+    fn new(limit: Option<usize>) -> Self {
+        let (head_limit, tail_limit) = limit.map(log_body_split).unwrap_or((usize::MAX, 0));
+        Self {
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            head_limit,
+            tail_limit,
+            skipped: 0,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        let to_head = self.head_limit.saturating_sub(self.head.len()).min(chunk.len());
+        self.head.extend_from_slice(&chunk[..to_head]);
+        self.tail.extend(&chunk[to_head..]);
+        let excess = self.tail.len().saturating_sub(self.tail_limit);
+        self.tail.drain(..excess);
+        self.skipped += excess;
+    }
+
+    fn len(&self) -> usize {
+        self.head.len() + self.tail.len()
+    }
+}
+
+/// Decompress the response body for logging / token parsing.
+///
+/// Supports gzip, deflate, br and zstd, including chained encodings such as
+/// "gzip, br" (decoded in reverse order). Falls back to the raw bytes if any
+/// step fails; the client always receives the original bytes.
+fn maybe_decompress(body: &[u8], content_encoding: Option<&str>) -> Vec<u8> {
+    let Some(encoding) = content_encoding else {
+        return body.to_vec();
+    };
+
+    let mut data = body.to_vec();
+    for coding in encoding.rsplit(',').map(|c| c.trim().to_lowercase()) {
+        let decoded = match coding.as_str() {
+            "" | "identity" => continue,
+            "gzip" | "x-gzip" => read_all(GzDecoder::new(data.as_slice())),
+            // deflate 按规范是 zlib 格式，但部分服务端直接返回裸 deflate
+            "deflate" => read_all(ZlibDecoder::new(data.as_slice()))
+                .or_else(|| read_all(DeflateDecoder::new(data.as_slice()))),
+            "br" => read_all(brotli_decompressor::Decompressor::new(data.as_slice(), 4096)),
+            "zstd" => zstd::stream::decode_all(data.as_slice()).ok(),
+            _ => None,
+        };
+        match decoded {
+            Some(d) => data = d,
+            None => return body.to_vec(),
+        }
+    }
+    data
+}
+
+fn read_all(mut reader: impl Read) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).ok().map(|_| out)
+}
+
+async fn handle_streaming_request(
+    request_builder: reqwest::RequestBuilder,
+    state: &Arc<AppState>,
+    provider_id: i64,
+    provider_name: &str,
+    cli_type: CliType,
+    model_id: Option<&str>,
+    client_method: &str,
+    client_path: &str,
+    start_time: Instant,
+    timeouts: TimeoutConfig,
+    mut log_info: RequestLogInfo,
+    log_body_limit: Option<usize>,
+    translation: Option<ResponseTranslation>,
+    header_policy: ResponseHeaderPolicy,
+) -> Result<Response<Body>, StatusCode> {
+    // Send request with timeout for first byte
+    let sent_at = Instant::now();
+    let response = match tokio::time::timeout(
+        timeouts.first_byte_timeout,
+        request_builder.send(),
+    )
+    .await
+    {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(e)) => {
+            tracing::error!(error = %e, "Upstream request failed");
+            if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
+                if was_blacklisted {
+                    let _ = stats_service::record_system_log(
+                        &state.log_db,
+                        "warn",
+                        "provider_blacklisted",
+                        &format!("Provider {} blacklisted due to consecutive failures", prov_name),
+                        Some(&prov_name),
+                        Some(&format!("{{\"error\": \"{}\"}}", e)),
+                    ).await;
+                }
+            }
+            log_info.error_message = Some(format!("Upstream error: {}", e));
+            record_request_stats(
+                state,
+                cli_type,
+                provider_name,
+                model_id,
+                None,
+                start_time.elapsed().as_millis() as i64,
+                0,
+                0,
+                client_method,
+                client_path,
+                Some(log_info),
+            )
+            .await;
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .header("content-type", "application/json")
+                .body(Body::from(format!(r#"{{"error": "Upstream error: {}"}}"#, e)))
+                .unwrap());
+        }
+        Err(_) => {
+            tracing::error!("First byte timeout");
+            if let Ok((was_blacklisted, prov_name)) = provider_service::record_failure(&state.db, provider_id).await {
+                if was_blacklisted {
+                    let _ = stats_service::record_system_log(
+                        &state.log_db,
+                        "warn",
+                        "provider_blacklisted",
+                        &format!("Provider {} blacklisted due to consecutive failures", prov_name),
+                        Some(&prov_name),
+                        Some("{\"error\": \"First byte timeout\"}"),
+                    ).await;
+                }
+            }
+            log_info.error_message = Some("First byte timeout".to_string());
+            record_request_stats(
+                state,
+                cli_type,
                 provider_name,
                 model_id,
                 None,
@@ -393,16 +1496,29 @@ async fn handle_streaming_request(
 
     let status = response.status();
     let resp_headers = response.headers().clone();
+    log_info.ttfb_ms = Some(sent_at.elapsed().as_millis() as i64);
 
     // Store provider response info
-    log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers));
-    log_info.response_headers = Some(serialize_reqwest_headers(&resp_headers));
+    log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers, &[]));
+    log_info.response_headers = Some(serialize_reqwest_headers(&resp_headers, &[]));
 
     // Build response headers
     let mut builder = Response::builder()
         .status(StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::OK));
 
+    // Create streaming body
+    let is_success = status.is_success();
+
+    // chat/completions SSE 改写为客户端协议的事件（仅成功响应）
+    let translation = translation.filter(|_| is_success);
+
     for (name, value) in resp_headers.iter() {
+        if !header_policy.allows(name.as_str()) {
+            continue;
+        }
+        if translation.is_some() && name == reqwest::header::CONTENT_LENGTH {
+            continue;
+        }
         if let Ok(header_name) = axum::http::HeaderName::from_bytes(name.as_str().as_bytes()) {
             if let Ok(header_value) = axum::http::HeaderValue::from_bytes(value.as_bytes()) {
                 builder = builder.header(header_name, header_value);
@@ -411,36 +1527,70 @@ async fn handle_streaming_request(
     }
     builder = builder.header("X-CCG-Provider", provider_name);
 
-    // Create streaming body
-    let is_success = status.is_success();
-
     // 使用共享状态收集chunks，确保即使stream被提前终止也能记录日志
-    // 优化：只存储原始chunks，后台任务再解析（避免重复解析）
-    let collected_chunks = Arc::new(Mutex::new(Vec::<Bytes>::new()));
+    // 优化：只存储原始字节，后台任务再解析（避免重复解析）
+    let collected_chunks = Arc::new(Mutex::new(StreamCapture::new(log_body_limit)));
     let collected_chunks_for_stream = collected_chunks.clone();
+
+    // 逐 chunk 增量解析 usage，不受上面日志收集上限影响
+    // 压缩过的流无法按行解析，交给后台任务对解压后的 body 兜底
+    let stream_compressed = resp_headers
+        .get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !v.trim().eq_ignore_ascii_case("identity"));
+    let sse_parser = Arc::new(Mutex::new(SseUsageParser::new(cli_type)));
+    let sse_parser_for_stream = sse_parser.clone();
     
     // 创建channel用于通知stream结束
     let (stream_end_tx, mut stream_end_rx) = mpsc::channel::<()>(1);
 
+    let mut translator = translation.clone().map(ResponseTranslation::stream);
+
+    // 压缩流或非 SSE 响应中插入注释会破坏内容，此时不发 keep-alive
+    let keepalive_interval = timeouts
+        .keepalive_interval
+        .filter(|_| !stream_compressed && is_event_stream(&resp_headers));
+
+    let open_connection = GaugeGuard::new(&state.counters.active_connections);
     let stream = async_stream::stream! {
+        let _open_connection = open_connection;
         let mut byte_stream = response.bytes_stream();
-        let idle_timeout = timeouts.idle_timeout;
         let mut chunk_count = 0usize;
         let mut total_bytes = 0usize;
+        // 只在完整事件之后插入 keep-alive，避免切断半个事件
+        let mut at_event_boundary = true;
 
         loop {
-            match tokio::time::timeout(idle_timeout, byte_stream.next()).await {
+            // 已输出的 token 越多，允许的空闲时间越长
+            let output_tokens = sse_parser_for_stream.lock().await.output_tokens();
+            let idle_deadline = tokio::time::Instant::now() + timeouts.stream_idle_timeout(output_tokens);
+            // keep-alive 只发给客户端，不重置上游空闲计时
+            let next = loop {
+                let wake_at = match keepalive_interval {
+                    Some(interval) if at_event_boundary => idle_deadline.min(tokio::time::Instant::now() + interval),
+                    _ => idle_deadline,
+                };
+                match tokio::time::timeout_at(wake_at, byte_stream.next()).await {
+                    Ok(item) => break Ok(item),
+                    Err(_) if wake_at < idle_deadline => {
+                        yield Ok::<Bytes, std::io::Error>(Bytes::from_static(SSE_KEEPALIVE));
+                    }
+                    Err(e) => break Err(e),
+                }
+            };
+            match next {
                 Ok(Some(Ok(chunk))) => {
                     chunk_count += 1;
                     let chunk_size = chunk.len();
                     total_bytes += chunk_size;
                     
                     // 只收集chunk到共享状态（快速操作，减少锁持有时间）
-                    // 限制总大小避免内存占用过大
-                    if total_bytes <= 100 * 1024 {
-                        let mut chunks = collected_chunks_for_stream.lock().await;
-                        chunks.push(chunk.clone());
-                        drop(chunks);  // 立即释放锁
+                    // 超出日志上限时只保留开头和滚动的结尾，避免内存占用过大
+                    collected_chunks_for_stream.lock().await.push(&chunk);
+
+                    // 转换时按客户端协议解析 usage
+                    if !stream_compressed && translator.is_none() {
+                        sse_parser_for_stream.lock().await.feed(&chunk);
                     }
                     
                     tracing::debug!(
@@ -448,6 +1598,17 @@ async fn handle_streaming_request(
                         cli_type, chunk_count, chunk_size, total_bytes
                     );
                     
+                    if let Some(translator) = translator.as_mut() {
+                        let translated = translator.feed(&chunk);
+                        sse_parser_for_stream.lock().await.feed(&translated);
+                        if !translated.is_empty() {
+                            at_event_boundary = ends_sse_event(&translated);
+                            yield Ok::<Bytes, std::io::Error>(Bytes::from(translated));
+                        }
+                        continue;
+                    }
+
+                    at_event_boundary = ends_sse_event(&chunk);
                     yield Ok::<Bytes, std::io::Error>(chunk);
                 }
                 Ok(Some(Err(e))) => {
@@ -479,6 +1640,15 @@ async fn handle_streaming_request(
             }
         }
 
+        // 补发结束事件（response.completed / message_stop 或失败事件）
+        if let Some(translator) = translator.as_mut() {
+            let translated = translator.finish();
+            sse_parser_for_stream.lock().await.feed(&translated);
+            if !translated.is_empty() {
+                yield Ok::<Bytes, std::io::Error>(Bytes::from(translated));
+            }
+        }
+
         // Stream loop正常结束（无论是completed、error还是timeout）
         tracing::debug!("[{}] Stream loop ended naturally", cli_type);
         
@@ -496,57 +1666,78 @@ async fn handle_streaming_request(
     let log_status = status;
     let log_resp_headers = resp_headers.clone();
     let log_is_success = is_success;
+    let pending_log_write = GaugeGuard::new(&state.counters.pending_log_writes);
     
     tokio::spawn(async move {
+        let _pending_log_write = pending_log_write;
         // 等待stream结束通知（已验证可靠，无需超时兜底）
         let _ = stream_end_rx.recv().await;
         tracing::debug!("[{}] Received stream end notification", cli_type);
         
-        // 读取收集的chunks
-        let chunks = collected_chunks.lock().await.clone();
+        // 读取收集的字节
+        let capture = std::mem::replace(&mut *collected_chunks.lock().await, StreamCapture::new(None));
         drop(collected_chunks);  // 立即释放Arc引用
         
-        // 一次性解析（避免重复解析，提升性能）
-        let full_body: Vec<u8> = chunks.iter().flat_map(|c| c.iter()).copied().collect();
-        let chunk_count = chunks.len();
-        
         tracing::info!(
-            "[{}] Processing stream log: {} chunks, {} bytes",
-            cli_type, chunk_count, full_body.len()
+            "[{}] Processing stream log: {} bytes captured, {} bytes skipped",
+            cli_type, capture.len(), capture.skipped
         );
-        
-        // 解析token usage
-        let mut usage = TokenUsage::default();
-        if !full_body.is_empty() {
-            // SSE 格式需要逐行解析，不能直接解析整个body
-            // 注意：流式响应可能有多个usage更新，应该使用最后一个值
-            let body_str = String::from_utf8_lossy(&full_body);
-            for line in body_str.lines() {
-                if line.starts_with("data:") {
-                    // 提取 data: 后面的 JSON
-                    let data = line.strip_prefix("data:").unwrap_or("").trim();
-                    if data == "[DONE]" || data.is_empty() {
-                        continue;
-                    }
-                    // 解析这一行的 JSON（如果有usage，会覆盖旧值）
-                    parse_token_usage(data.as_bytes(), cli_type, &mut usage);
-                    // 继续遍历所有行，使用最后一个值
-                }
+        let StreamCapture { head, tail, skipped, .. } = capture;
+        let tail = Vec::from(tail);
+
+        // 解析token usage（增量解析的结果）
+        let mut usage = sse_parser.lock().await.finish();
+
+        let (provider_body, response_body) = if skipped == 0 {
+            // 一次性解析（避免重复解析，提升性能）
+            let mut full_body = head;
+            full_body.extend_from_slice(&tail);
+            let content_encoding = log_resp_headers.get("content-encoding")
+                .and_then(|v| v.to_str().ok());
+            let decompressed_body = maybe_decompress(&full_body, content_encoding);
+
+            if stream_compressed && !decompressed_body.is_empty() {
+                // 压缩流兜底：对收集到的（已解压）body 逐行解析
+                let mut parser = SseUsageParser::new(cli_type);
+                parser.feed(&decompressed_body);
+                usage = parser.finish();
             }
-        }
-        
+
+            let provider_body = truncate_body(&decompressed_body, log_body_limit);
+            let response_body = match translation {
+                Some(translation) => truncate_body(&translation.translate_stream_body(&decompressed_body), log_body_limit),
+                None => provider_body.clone(),
+            };
+            (provider_body, response_body)
+        } else {
+            // 中间部分已丢弃：首尾分别记录（压缩流无法解压，按原样记录）
+            let marker = truncation_marker(skipped);
+            let join = |head: &[u8], tail: &[u8]| {
+                format!("{}{}{}", String::from_utf8_lossy(head), marker, String::from_utf8_lossy(tail))
+            };
+            let provider_body = join(&head, &tail);
+            let response_body = match translation {
+                Some(translation) => {
+                    let mut translator = translation.stream();
+                    let translated_head = translator.feed(&head);
+                    let mut translated_tail = translator.feed(&tail);
+                    translated_tail.extend(translator.finish());
+                    join(&translated_head, &translated_tail)
+                }
+                None => provider_body.clone(),
+            };
+            (provider_body, response_body)
+        };
+
         tracing::debug!(
             "[{}] Parsed tokens: input={}, output={}",
             cli_type, usage.input_tokens, usage.output_tokens
         );
-        
-        // Update log info with response body
-        let content_encoding = log_resp_headers.get("content-encoding")
-            .and_then(|v| v.to_str().ok());
-        let decompressed_body = maybe_decompress(&full_body, content_encoding);
         let mut final_log_info = log_info;
-        final_log_info.provider_body = Some(truncate_body(&decompressed_body));
-        final_log_info.response_body = final_log_info.provider_body.clone();
+        final_log_info.cache_creation_tokens = usage.cache_creation_tokens;
+        final_log_info.cache_read_tokens = usage.cache_read_tokens;
+        final_log_info.provider_body = Some(provider_body);
+        final_log_info.response_body = Some(response_body);
         
         // Record stats
         let elapsed = start_time.elapsed().as_millis() as i64;
@@ -591,7 +1782,7 @@ async fn handle_streaming_request(
         ).await;
         
         tracing::info!("[{}] Delayed log recording completed", cli_type);
-    });
+    }.instrument(tracing::Span::current()));
 
     Ok(builder
         .body(Body::from_stream(stream))
@@ -610,8 +1801,12 @@ async fn handle_non_streaming_request(
     start_time: Instant,
     timeouts: TimeoutConfig,
     mut log_info: RequestLogInfo,
+    log_body_limit: Option<usize>,
+    translation: Option<ResponseTranslation>,
+    header_policy: ResponseHeaderPolicy,
 ) -> Result<Response<Body>, StatusCode> {
     // Send request with timeout
+    let sent_at = Instant::now();
     let response = match tokio::time::timeout(
         timeouts.non_stream_timeout,
         request_builder.send(),
@@ -693,11 +1888,12 @@ async fn handle_non_streaming_request(
 
     let status = response.status();
     let resp_headers = response.headers().clone();
+    log_info.ttfb_ms = Some(sent_at.elapsed().as_millis() as i64);
     let is_success = status.is_success();
 
     // Store provider response info
-    log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers));
-    log_info.response_headers = Some(serialize_reqwest_headers(&resp_headers));
+    log_info.provider_headers = Some(serialize_reqwest_headers(&resp_headers, &[]));
+    log_info.response_headers = Some(serialize_reqwest_headers(&resp_headers, &[]));
 
     // Read response body
     let body_bytes = match response.bytes().await {
@@ -740,13 +1936,33 @@ async fn handle_non_streaming_request(
         .and_then(|v| v.to_str().ok());
     let decompressed_body = maybe_decompress(&body_bytes, content_encoding);
 
-    // Store response body for logging (use decompressed version)
-    log_info.provider_body = Some(truncate_body(&decompressed_body));
-    log_info.response_body = log_info.provider_body.clone();
+    // Translate a chat completion back into the client's protocol
+    let translated_body = translation
+        .filter(|_| is_success)
+        .and_then(|translation| translation.translate_body(&decompressed_body));
+
+    // Store response body for logging (use decompressed version; none for HEAD / empty bodies)
+    log_info.provider_body = (!decompressed_body.is_empty()).then(|| truncate_body(&decompressed_body, log_body_limit));
+    log_info.response_body = match &translated_body {
+        Some(body) => Some(truncate_body(body, log_body_limit)),
+        None => log_info.provider_body.clone(),
+    };
 
     // Parse token usage (use decompressed body)
     let mut usage = TokenUsage::default();
-    parse_token_usage(&decompressed_body, cli_type, &mut usage);
+    parse_token_usage(translated_body.as_deref().unwrap_or(&decompressed_body), cli_type, &mut usage);
+
+    // Message Batches：记录 batch_id，结果文件中的每个请求单独写入 batch_request_items
+    if is_success && cli_type == CliType::ClaudeCode && client_path.contains("/messages/batches") {
+        if let Some((batch_id, items)) = batch::parse_batch_response(&decompressed_body, client_path) {
+            if !items.is_empty() {
+                if let Err(e) = batch::record_batch_items(&state.log_db, &batch_id, &items).await {
+                    tracing::warn!("Failed to record batch {} results: {}", batch_id, e);
+                }
+            }
+            log_info.batch_id = Some(batch_id);
+        }
+    }
 
     // Record success/failure
     if is_success {
@@ -777,6 +1993,8 @@ async fn handle_non_streaming_request(
 
     // Record stats
     let elapsed = start_time.elapsed().as_millis() as i64;
+    log_info.cache_creation_tokens = usage.cache_creation_tokens;
+    log_info.cache_read_tokens = usage.cache_read_tokens;
     record_request_stats(
         state,
         cli_type,
@@ -797,6 +2015,15 @@ async fn handle_non_streaming_request(
         .status(StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::OK));
 
     for (name, value) in resp_headers.iter() {
+        if !header_policy.allows(name.as_str()) {
+            continue;
+        }
+        // 改写后的 body 已解压且长度变化
+        if translated_body.is_some()
+            && (name == reqwest::header::CONTENT_LENGTH || name == reqwest::header::CONTENT_ENCODING)
+        {
+            continue;
+        }
         if let Ok(header_name) = axum::http::HeaderName::from_bytes(name.as_str().as_bytes()) {
             if let Ok(header_value) = axum::http::HeaderValue::from_bytes(value.as_bytes()) {
                 builder = builder.header(header_name, header_value);
@@ -805,7 +2032,11 @@ async fn handle_non_streaming_request(
     }
     builder = builder.header("X-CCG-Provider", provider_name);
 
-    Ok(builder.body(Body::from(body_bytes)).unwrap())
+    let body = match translated_body {
+        Some(body) => Body::from(body),
+        None => Body::from(body_bytes),
+    };
+    Ok(builder.body(body).unwrap())
 }
 
 async fn record_request_stats(
@@ -819,104 +2050,127 @@ async fn record_request_stats(
     output_tokens: i64,
     client_method: &str,
     client_path: &str,
-    log_info: Option<RequestLogInfo>,
+    mut log_info: Option<RequestLogInfo>,
 ) {
-    // Derive success from status_code (200-299 = success)
-    let success = status_code.map(|code| (200..300).contains(&code)).unwrap_or(false);
+    // Derive success from status_code (200-299 = success, 101 = WebSocket session)
+    let success = status_code.map(|code| (200..300).contains(&code) || code == 101).unwrap_or(false);
+
+    // The attempt that produced this response closes the attempt chain
+    if let Some(info) = log_info.as_mut() {
+        let previous_ms: i64 = info.attempts.iter().map(|a| a.elapsed_ms).sum();
+        info.attempts.push(RequestAttempt {
+            provider_name: provider_name.to_string(),
+            status_code,
+            error: info.error_message.clone(),
+            elapsed_ms: (elapsed_ms - previous_ms).max(0),
+        });
+    }
+
+    // Track health of the rotation key used for this request
+    if let Some(key_id) = log_info.as_ref().and_then(|info| info.api_key_id) {
+        if success {
+            let _ = provider_service::record_key_success(&state.db, key_id).await;
+        } else if let Ok(true) = provider_service::record_key_failure(&state.db, key_id).await {
+            let _ = stats_service::record_system_log(
+                &state.log_db,
+                "warn",
+                "key_exhausted",
+                &format!("API key #{} of provider {} reached the failure threshold and will be skipped", key_id, provider_name),
+                Some(provider_name),
+                None,
+            ).await;
+        }
+    }
+
+    let usage = TokenUsage {
+        input_tokens,
+        output_tokens,
+        cache_creation_tokens: log_info.as_ref().map(|info| info.cache_creation_tokens).unwrap_or(0),
+        cache_read_tokens: log_info.as_ref().map(|info| info.cache_read_tokens).unwrap_or(0),
+    };
+
+    // 命中 log_exclude_paths 时跳过请求日志，按 count_usage 决定是否仍计入 usage_daily
+    let (excluded, count_usage) = {
+        let rules = state.log_exclude.read().unwrap_or_else(|e| e.into_inner());
+        (rules.excludes(client_path), rules.count_usage)
+    };
+    if excluded && !count_usage {
+        return;
+    }
 
     // Record to request_logs
-    let _ = stats_service::record_request_log(
+    if !excluded {
+        let _ = stats_service::record_request_log(
+            &state.log_db,
+            &state.mask_patterns,
+            cli_type.as_str(),
+            provider_name,
+            model_id,
+            status_code,
+            elapsed_ms,
+            input_tokens,
+            output_tokens,
+            client_method,
+            client_path,
+            log_info,
+        )
+        .await;
+    }
+
+    // Record to usage_daily
+    let recorded = stats_service::record_request(
+        &state.db,
         &state.log_db,
-        cli_type.as_str(),
         provider_name,
+        cli_type.as_str(),
         model_id,
-        status_code,
-        elapsed_ms,
-        input_tokens,
-        output_tokens,
-        client_method,
-        client_path,
-        log_info,
-    )
-    .await;
-
-    // Record to usage_daily
-    let _ = stats_service::record_request(
-        &state.log_db,
-        provider_name,
-        cli_type.as_str(),
         success,
-        input_tokens,
-        output_tokens,
+        &usage,
     )
     .await;
+
+    // Daily token budget warnings run on the updated totals
+    if recorded.is_ok() {
+        if let Err(e) = budget::check_token_budget(&state.db, &state.log_db, provider_name, cli_type.as_str(), &usage).await {
+            tracing::warn!(provider = %provider_name, error = %e, "Failed to check token budget");
+        }
+    }
+}
+
+// Providers (/api/providers): same logic and JSON as the Tauri commands
+#[derive(Debug, Deserialize)]
+pub struct DeleteProviderQuery {
+    pub keep_logs: Option<bool>,
 }
 
-// Providers
 pub async fn list_providers(
     State(state): State<Arc<AppState>>,
     Query(query): Query<ProviderQuery>,
 ) -> Result<Json<Vec<ProviderResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    let providers = if let Some(ct) = query.cli_type {
-        sqlx::query_as::<_, Provider>(
-            "SELECT * FROM providers WHERE cli_type = ? ORDER BY sort_order, id",
-        )
-        .bind(&ct)
-        .fetch_all(&state.db)
+    commands::load_providers(&state.db, query.cli_type)
         .await
-    } else {
-        sqlx::query_as::<_, Provider>("SELECT * FROM providers ORDER BY sort_order, id")
-            .fetch_all(&state.db)
-            .await
-    };
-
-    providers
-        .map(|ps| Json(ps.into_iter().map(ProviderResponse::from).collect()))
-        .map_err(db_error)
+        .map(Json)
+        .map_err(error_response)
 }
 
 pub async fn get_provider_handler(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
 ) -> Result<Json<ProviderResponse>, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
-        .bind(id)
-        .fetch_optional(&state.db)
+    commands::load_provider(&state.db, id)
         .await
-        .map_err(db_error)?
-        .map(ProviderResponse::from)
         .map(Json)
-        .ok_or_else(|| error_response("Provider not found"))
+        .map_err(error_response)
 }
 
 pub async fn create_provider_handler(
     State(state): State<Arc<AppState>>,
     Json(input): Json<ProviderCreate>,
 ) -> Result<Json<ProviderResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let now = chrono::Utc::now().timestamp();
-    let cli_type = input.cli_type.unwrap_or_else(|| "claude_code".to_string());
-
-    let result = sqlx::query(
-        r#"
-        INSERT INTO providers (cli_type, name, base_url, api_key, enabled, failure_threshold, blacklist_minutes, consecutive_failures, sort_order, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?, 0, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM providers), ?, ?)
-        "#,
-    )
-    .bind(&cli_type)
-    .bind(&input.name)
-    .bind(&input.base_url)
-    .bind(&input.api_key)
-    .bind(input.enabled.unwrap_or(true) as i64)
-    .bind(input.failure_threshold.unwrap_or(3))
-    .bind(input.blacklist_minutes.unwrap_or(10))
-    .bind(now)
-    .bind(now)
-    .execute(&state.db)
-    .await
-    .map_err(db_error)?;
-
-    let id = result.last_insert_rowid();
-    get_provider_handler(State(state), Path(id)).await
+    commands::insert_provider(&state.db, &state.log_db, &state.ua_patterns, input)
+        .await
+        .map(Json)
+        .map_err(error_response)
 }
 
 pub async fn update_provider_handler(
@@ -924,78 +2178,20 @@ pub async fn update_provider_handler(
     Path(id): Path<i64>,
     Json(input): Json<ProviderUpdate>,
 ) -> Result<Json<ProviderResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let now = chrono::Utc::now().timestamp();
-    let mut updates = vec!["updated_at = ?".to_string()];
-    let mut has_updates = false;
-
-    if input.name.is_some() {
-        updates.push("name = ?".to_string());
-        has_updates = true;
-    }
-    if input.base_url.is_some() {
-        updates.push("base_url = ?".to_string());
-        has_updates = true;
-    }
-    if input.api_key.is_some() {
-        updates.push("api_key = ?".to_string());
-        has_updates = true;
-    }
-    if input.enabled.is_some() {
-        updates.push("enabled = ?".to_string());
-        has_updates = true;
-    }
-    if input.failure_threshold.is_some() {
-        updates.push("failure_threshold = ?".to_string());
-        has_updates = true;
-    }
-    if input.blacklist_minutes.is_some() {
-        updates.push("blacklist_minutes = ?".to_string());
-        has_updates = true;
-    }
-
-    if !has_updates {
-        return get_provider_handler(State(state), Path(id)).await;
-    }
-
-    let query = format!("UPDATE providers SET {} WHERE id = ?", updates.join(", "));
-    let mut q = sqlx::query(&query).bind(now);
-
-    if let Some(ref name) = input.name {
-        q = q.bind(name);
-    }
-    if let Some(ref base_url) = input.base_url {
-        q = q.bind(base_url);
-    }
-    if let Some(ref api_key) = input.api_key {
-        q = q.bind(api_key);
-    }
-    if let Some(enabled) = input.enabled {
-        q = q.bind(enabled as i64);
-    }
-    if let Some(failure_threshold) = input.failure_threshold {
-        q = q.bind(failure_threshold);
-    }
-    if let Some(blacklist_minutes) = input.blacklist_minutes {
-        q = q.bind(blacklist_minutes);
-    }
-
-    q.bind(id)
-        .execute(&state.db)
+    commands::apply_provider_update(&state.db, &state.log_db, &state.ua_patterns, id, input)
         .await
-        .map_err(db_error)?;
-
-    get_provider_handler(State(state), Path(id)).await
+        .map(Json)
+        .map_err(error_response)
 }
 
 pub async fn delete_provider_handler(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
+    Query(query): Query<DeleteProviderQuery>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query("DELETE FROM providers WHERE id = ?")
-        .bind(id)
-        .execute(&state.db)
+    commands::remove_provider(&state.db, &state.log_db, &state.ua_patterns, &state.schedules, id, query.keep_logs)
         .await
-        .map_err(db_error)?;
+        .map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -1003,14 +2199,9 @@ pub async fn reorder_providers_handler(
     State(state): State<Arc<AppState>>,
     Json(ids): Json<Vec<i64>>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    for (idx, id) in ids.iter().enumerate() {
-        sqlx::query("UPDATE providers SET sort_order = ? WHERE id = ?")
-            .bind(idx as i64)
-            .bind(id)
-            .execute(&state.db)
-            .await
-            .map_err(db_error)?;
-    }
+    commands::save_provider_order(&state.db, &ids)
+        .await
+        .map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -1018,81 +2209,77 @@ pub async fn reset_provider_failures_handler(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query("UPDATE providers SET consecutive_failures = 0, blacklisted_until = NULL WHERE id = ?")
-        .bind(id)
-        .execute(&state.db)
+    commands::reset_failures(&state.db, &state.log_db, id)
         .await
-        .map_err(db_error)?;
+        .map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
-// Settings
-#[derive(Debug, Deserialize)]
-pub struct GatewaySettingsUpdate {
-    pub debug_log: bool,
-}
-
-#[derive(Debug, Serialize)]
-pub struct GatewaySettingsResponse {
-    pub debug_log: bool,
+// Settings (/api/settings): same logic and JSON as the Tauri commands
+/// The gateway token itself is not served over HTTP (callers already hold it)
+fn without_gateway_token(mut settings: GatewaySettings) -> GatewaySettings {
+    settings.gateway_token = None;
+    settings
 }
 
 pub async fn get_gateway_settings(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<GatewaySettingsResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let settings = sqlx::query_as::<_, GatewaySettings>("SELECT debug_log FROM gateway_settings WHERE id = 1")
-        .fetch_one(&state.db)
+) -> Result<Json<GatewaySettings>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_gateway_settings(&state.db)
         .await
-        .map_err(db_error)?;
-
-    Ok(Json(GatewaySettingsResponse {
-        debug_log: settings.debug_log != 0,
-    }))
+        .map(|settings| Json(without_gateway_token(settings)))
+        .map_err(error_response)
 }
 
 pub async fn update_gateway_settings_handler(
     State(state): State<Arc<AppState>>,
     Json(input): Json<GatewaySettingsUpdate>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    let now = chrono::Utc::now().timestamp();
-    sqlx::query("UPDATE gateway_settings SET debug_log = ?, updated_at = ? WHERE id = 1")
-        .bind(input.debug_log as i64)
-        .bind(now)
-        .execute(&state.db)
+    commands::save_gateway_settings(&state.db, &state.ua_rules, &state.port, input)
         .await
-        .map_err(db_error)?;
+        .map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn get_timeout_settings(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<TimeoutSettings>, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query_as::<_, TimeoutSettings>(
-        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout FROM timeout_settings WHERE id = 1",
-    )
-    .fetch_one(&state.db)
-    .await
-    .map(Json)
-    .map_err(db_error)
+    commands::load_timeout_settings(&state.db)
+        .await
+        .map(Json)
+        .map_err(error_response)
 }
 
 pub async fn update_timeout_settings_handler(
     State(state): State<Arc<AppState>>,
     Json(input): Json<TimeoutSettingsUpdate>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    let now = chrono::Utc::now().timestamp();
-    let current = get_timeout_settings(State(state.clone())).await?;
+    commands::save_timeout_settings(&state.db, input)
+        .await
+        .map_err(error_response)?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    sqlx::query(
-        "UPDATE timeout_settings SET stream_first_byte_timeout = ?, stream_idle_timeout = ?, non_stream_timeout = ?, updated_at = ? WHERE id = 1",
-    )
-    .bind(input.stream_first_byte_timeout.unwrap_or(current.stream_first_byte_timeout))
-    .bind(input.stream_idle_timeout.unwrap_or(current.stream_idle_timeout))
-    .bind(input.non_stream_timeout.unwrap_or(current.non_stream_timeout))
-    .bind(now)
-    .execute(&state.db)
-    .await
-    .map_err(db_error)?;
+pub async fn get_cli_settings_handler(
+    State(state): State<Arc<AppState>>,
+    Path(cli_type): Path<String>,
+) -> Result<Json<CliSettingsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    cli_type.parse::<CliType>().map_err(error_response)?;
+    commands::load_cli_settings(&state.db, &state.port, cli_type)
+        .await
+        .map(Json)
+        .map_err(error_response)
+}
+
+pub async fn update_cli_settings_handler(
+    State(state): State<Arc<AppState>>,
+    Path(cli_type): Path<String>,
+    Json(input): Json<CliSettingsUpdate>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    cli_type.parse::<CliType>().map_err(error_response)?;
+    commands::save_cli_settings(&state.db, &state.port, &state.cli_sync, cli_type, input)
+        .await
+        .map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -1110,78 +2297,29 @@ pub async fn get_request_logs(
     State(state): State<Arc<AppState>>,
     Query(query): Query<LogQuery>,
 ) -> Result<Json<PaginatedLogs>, (StatusCode, Json<ErrorResponse>)> {
-    let page = query.page.max(1);
-    let page_size = query.page_size.clamp(1, 100);
-    let offset = (page - 1) * page_size;
-    let pool = &state.log_db;
-
-    let (items, total) = if let Some(ct) = query.cli_type {
-        let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs WHERE cli_type = ? ORDER BY id DESC LIMIT ? OFFSET ?",
-        )
-        .bind(&ct)
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(pool)
+    commands::load_request_logs(&state.log_db, Some(query.page), Some(query.page_size), query.cli_type)
         .await
-        .map_err(db_error)?;
-
-        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs WHERE cli_type = ?")
-            .bind(&ct)
-            .fetch_one(pool)
-            .await
-            .map_err(db_error)?;
-
-        (items, total.0)
-    } else {
-        let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs ORDER BY id DESC LIMIT ? OFFSET ?",
-        )
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(pool)
-        .await
-        .map_err(db_error)?;
-
-        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs")
-            .fetch_one(pool)
-            .await
-            .map_err(db_error)?;
-
-        (items, total.0)
-    };
-
-    Ok(Json(PaginatedLogs {
-        items,
-        total,
-        page,
-        page_size,
-    }))
+        .map(Json)
+        .map_err(error_response)
 }
 
 pub async fn clear_request_logs(
     State(state): State<Arc<AppState>>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query("DELETE FROM request_logs")
-        .execute(&state.log_db)
+    commands::delete_request_logs(&state.log_db)
         .await
-        .map_err(db_error)?;
+        .map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn get_request_log_detail(
     State(state): State<Arc<AppState>>,
-    Path(id): Path<i64>,
+    Path(id): Path<String>,
 ) -> Result<Json<RequestLogDetail>, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query_as::<_, RequestLogDetail>(
-        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, response_headers, response_body, error_message FROM request_logs WHERE id = ?",
-    )
-    .bind(id)
-    .fetch_optional(&state.log_db)
-    .await
-    .map_err(db_error)?
-    .map(Json)
-    .ok_or_else(|| error_response("Log not found"))
+    commands::load_request_log_detail(&state.log_db, RequestLogKey::from(id))
+        .await
+        .map(Json)
+        .map_err(error_response)
 }
 
 // System logs
@@ -1200,73 +2338,25 @@ pub async fn get_system_logs_handler(
     State(state): State<Arc<AppState>>,
     Query(query): Query<SystemLogQuery>,
 ) -> Result<Json<SystemLogListResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let page = query.page.max(1);
-    let page_size = query.page_size.clamp(1, 100);
-    let offset = (page - 1) * page_size;
-    let pool = &state.log_db;
-
-    // Build query
-    let mut sql = "SELECT * FROM system_logs WHERE 1=1".to_string();
-    let mut count_sql = "SELECT COUNT(*) FROM system_logs WHERE 1=1".to_string();
-
-    if query.level.is_some() {
-        sql.push_str(" AND level = ?");
-        count_sql.push_str(" AND level = ?");
-    }
-    if query.event_type.is_some() {
-        sql.push_str(" AND event_type = ?");
-        count_sql.push_str(" AND event_type = ?");
-    }
-    if query.provider_name.is_some() {
-        sql.push_str(" AND provider_name = ?");
-        count_sql.push_str(" AND provider_name = ?");
-    }
-
-    sql.push_str(" ORDER BY id DESC LIMIT ? OFFSET ?");
-    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql)
-        .bind(page_size)
-        .bind(offset);
-
-    if let Some(ref lvl) = query.level {
-        q = q.bind(lvl);
-    }
-    if let Some(ref et) = query.event_type {
-        q = q.bind(et);
-    }
-    if let Some(ref pn) = query.provider_name {
-        q = q.bind(pn);
-    }
-
-    let items = q.fetch_all(pool).await.map_err(db_error)?;
-
-    // Get total count
-    let mut count_q = sqlx::query_as::<_, (i64,)>(&count_sql);
-    if let Some(ref lvl) = query.level {
-        count_q = count_q.bind(lvl);
-    }
-    if let Some(ref et) = query.event_type {
-        count_q = count_q.bind(et);
-    }
-    if let Some(ref pn) = query.provider_name {
-        count_q = count_q.bind(pn);
-    }
-    let (total,) = count_q.fetch_one(pool).await.map_err(db_error)?;
-
-    Ok(Json(SystemLogListResponse {
-        items,
-        total,
-        page,
-        page_size,
-    }))
+    commands::load_system_logs(
+        &state.log_db,
+        Some(query.page),
+        Some(query.page_size),
+        query.level,
+        query.event_type,
+        query.provider_name,
+    )
+    .await
+    .map(Json)
+    .map_err(error_response)
 }
 
 pub async fn clear_system_logs_handler(
     State(state): State<Arc<AppState>>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    sqlx::query("DELETE FROM system_logs")
-        .execute(&state.log_db)
+    commands::delete_system_logs(&state.log_db)
         .await
-        .map_err(db_error)?;
+        .map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -1276,333 +2366,918 @@ pub struct StatsQuery {
     pub start_date: Option<String>,
     pub end_date: Option<String>,
     pub cli_type: Option<String>,
+    pub provider_name: Option<String>,
 }
 
 pub async fn get_daily_stats(
     State(state): State<Arc<AppState>>,
     Query(query): Query<StatsQuery>,
 ) -> Result<Json<Vec<DailyStats>>, (StatusCode, Json<ErrorResponse>)> {
-    let pool = &state.log_db;
+    commands::load_daily_stats(&state.log_db, query.start_date, query.end_date, query.cli_type)
+        .await
+        .map(Json)
+        .map_err(error_response)
+}
 
-    let mut sql = "SELECT * FROM usage_daily WHERE 1=1".to_string();
-    if query.start_date.is_some() {
-        sql.push_str(" AND usage_date >= ?");
-    }
-    if query.end_date.is_some() {
-        sql.push_str(" AND usage_date <= ?");
-    }
-    if query.cli_type.is_some() {
-        sql.push_str(" AND cli_type = ?");
-    }
-    sql.push_str(" ORDER BY usage_date DESC");
+pub async fn get_provider_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Vec<ProviderStatsResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_provider_stats(&state.log_db, query.start_date, query.end_date, query.cli_type, query.provider_name)
+        .await
+        .map(Json)
+        .map_err(error_response)
+}
 
-    let mut q = sqlx::query_as::<_, DailyStats>(&sql);
-    if let Some(ref sd) = query.start_date {
-        q = q.bind(sd);
-    }
-    if let Some(ref ed) = query.end_date {
-        q = q.bind(ed);
-    }
-    if let Some(ref ct) = query.cli_type {
-        q = q.bind(ct);
+#[derive(Debug, Deserialize)]
+pub struct HealthQuery {
+    pub verbose: Option<String>,
+}
+
+/// Provider readiness of one CLI type
+#[derive(Debug, Default, Serialize)]
+struct CliHealth {
+    enabled: i64,
+    blacklisted: i64,
+    available: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    providers: Option<Vec<serde_json::Value>>,
+}
+
+/// Gateway and provider readiness for external monitors.
+///
+/// status: `ok`，`degraded`（有服务商被拉黑、某个已配置的 CLI 无可用服务商或数据库不可用），
+/// `no_providers`（没有任何可用服务商，返回 503）。`?verbose=1` 附带服务商名称列表（不含密钥）。
+pub async fn health_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HealthQuery>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let verbose = matches!(query.verbose.as_deref(), Some("1") | Some("true"));
+    let server = state.listen_addr.status();
+    let db_ok = sqlx::query("SELECT 1").execute(&state.db).await.is_ok();
+    let log_db_ok = sqlx::query("SELECT 1").execute(&state.log_db).await.is_ok();
+
+    let now = chrono::Utc::now().timestamp();
+    let providers = sqlx::query_as::<_, (String, String, i64, Option<i64>)>(
+        "SELECT cli_type, name, enabled, blacklisted_until FROM providers ORDER BY cli_type, sort_order, id",
+    )
+    .fetch_all(&state.db)
+    .await
+    .unwrap_or_default();
+
+    let mut clis: std::collections::BTreeMap<String, CliHealth> = [CliType::ClaudeCode, CliType::Codex, CliType::Gemini]
+        .iter()
+        .map(|cli_type| (cli_type.as_str().to_string(), CliHealth::default()))
+        .collect();
+    for (cli_type, name, enabled, blacklisted_until) in providers {
+        let enabled = enabled != 0;
+        let blacklisted = blacklisted_until.is_some_and(|t| t > now);
+        let cli = clis.entry(cli_type).or_default();
+        if enabled {
+            cli.enabled += 1;
+            if blacklisted {
+                cli.blacklisted += 1;
+            } else {
+                cli.available += 1;
+            }
+        }
+        if verbose {
+            cli.providers.get_or_insert_with(Vec::new).push(serde_json::json!({
+                "name": name,
+                "enabled": enabled,
+                "blacklisted": blacklisted,
+            }));
+        }
     }
 
-    q.fetch_all(pool)
-        .await
-        .map(Json)
-        .map_err(db_error)
+    // 已配置 = 至少有一个启用的服务商
+    let configured: Vec<&CliHealth> = clis.values().filter(|c| c.enabled > 0).collect();
+    let status = if configured.iter().all(|c| c.available == 0) {
+        "no_providers"
+    } else if !db_ok || !log_db_ok || configured.iter().any(|c| c.blacklisted > 0 || c.available == 0) {
+        "degraded"
+    } else {
+        "ok"
+    };
+    let code = if status == "no_providers" { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK };
+
+    (
+        code,
+        Json(serde_json::json!({
+            "status": status,
+            "server": server.state(),
+            "listen_address": state.listen_addr.get().map(|a| a.to_string()),
+            "uptime": server.uptime(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "database": { "main": db_ok, "logs": log_db_ok },
+            "cli_types": clis,
+        })),
+    )
 }
 
 pub async fn get_system_status_handler(
-    State(_state): State<Arc<AppState>>,
+    State(state): State<Arc<AppState>>,
 ) -> Result<Json<SystemStatus>, (StatusCode, Json<ErrorResponse>)> {
+    let total_cost_usd = crate::services::pricing::logged_cost_total(&state.db, &state.log_db, None)
+        .await
+        .map_err(db_error)?;
+    let budgets = budget::budget_statuses(&state.db, &state.log_db)
+        .await
+        .map_err(db_error)?;
+    let server = state.listen_addr.status();
     Ok(Json(SystemStatus {
-        status: "running".to_string(),
-        port: 7788,
-        uptime: 0,
+        status: server.state().to_string(),
+        error: server.error(),
+        port: state.port.get(),
+        uptime: server.uptime(),
         version: env!("CARGO_PKG_VERSION").to_string(),
+        listen_address: state.listen_addr.get().map(|a| a.to_string()),
+        lan_url: state.listen_addr.lan_url(),
+        total_cost_usd,
+        budgets,
+        requests_in_flight: state.concurrency.in_flight(),
+        max_concurrent_requests: state.concurrency.limit(),
     }))
 }
 
 // Get all settings (for dashboard)
 #[derive(Debug, Serialize)]
 pub struct AllSettingsResponse {
-    pub gateway: GatewaySettingsResponse,
+    pub gateway: GatewaySettings,
     pub timeouts: TimeoutSettings,
-    pub cli_settings: std::collections::HashMap<String, crate::db::models::CliSettingsResponse>,
+    pub cli_settings: std::collections::HashMap<String, CliSettingsResponse>,
 }
 
 pub async fn get_all_settings(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<AllSettingsResponse>, (StatusCode, Json<ErrorResponse>)> {
-    // Get gateway settings
-    let gateway_settings = sqlx::query_as::<_, GatewaySettings>("SELECT debug_log FROM gateway_settings WHERE id = 1")
-        .fetch_one(&state.db)
-        .await
-        .map_err(db_error)?;
-
-    // Get timeout settings
-    let timeout_settings = sqlx::query_as::<_, TimeoutSettings>("SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout FROM timeout_settings WHERE id = 1")
-        .fetch_one(&state.db)
-        .await
-        .map_err(db_error)?;
-
-    // Get CLI settings
+    let gateway = commands::load_gateway_settings(&state.db).await.map_err(error_response)?;
+    let timeouts = commands::load_timeout_settings(&state.db).await.map_err(error_response)?;
     let mut cli_settings = std::collections::HashMap::new();
-    for cli_type in &["claude_code", "codex", "gemini"] {
-        cli_settings.insert(
-            cli_type.to_string(),
-            crate::db::models::CliSettingsResponse {
-                cli_type: cli_type.to_string(),
-                enabled: false, // TODO: Check if config file exists
-                default_json_config: String::new(),
-            },
-        );
+    for cli_type in [CliType::ClaudeCode, CliType::Codex, CliType::Gemini] {
+        let settings = commands::load_cli_settings(&state.db, &state.port, cli_type.as_str().to_string())
+            .await
+            .map_err(error_response)?;
+        cli_settings.insert(cli_type.as_str().to_string(), settings);
     }
 
     Ok(Json(AllSettingsResponse {
-        gateway: GatewaySettingsResponse {
-            debug_log: gateway_settings.debug_log != 0,
-        },
-        timeouts: timeout_settings,
+        gateway: without_gateway_token(gateway),
+        timeouts,
         cli_settings,
     }))
 }
 
-// Get provider stats
-#[derive(Debug, Serialize)]
-pub struct ProviderStatsResponse {
-    pub provider_name: String,
-    pub cli_type: String,
-    pub total_requests: i64,
-    pub total_success: i64,
-    pub total_failure: i64,
-    pub success_rate: f64,
-    pub total_tokens: i64,
+// WebDAV backups (/api/webdav). Restoring a backup replaces the database and exits the app,
+// so it stays in the desktop app
+/// Like the gateway token, the WebDAV password is only shown masked over HTTP
+fn with_masked_password(mut settings: WebdavSettings) -> WebdavSettings {
+    if !settings.password.is_empty() {
+        settings.password = crate::services::masking::mask_secret(&settings.password);
+    }
+    settings
 }
 
-pub async fn get_provider_stats(
+pub async fn get_webdav_settings_handler(
     State(state): State<Arc<AppState>>,
-    Query(query): Query<StatsQuery>,
-) -> Result<Json<Vec<ProviderStatsResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    let pool = &state.log_db;
-
-    let mut sql = r#"
-        SELECT
-            provider_name,
-            cli_type,
-            COUNT(*) as total_requests,
-            SUM(CASE WHEN status_code >= 200 AND status_code < 300 THEN 1 ELSE 0 END) as total_success,
-            SUM(CASE WHEN status_code IS NULL OR status_code < 200 OR status_code >= 300 THEN 1 ELSE 0 END) as total_failure,
-            SUM(input_tokens + output_tokens) as total_tokens
-        FROM request_logs
-        WHERE 1=1
-    "#.to_string();
-
-    if query.start_date.is_some() {
-        sql.push_str(" AND DATE(created_at, 'unixepoch') >= ?");
-    }
-    if query.end_date.is_some() {
-        sql.push_str(" AND DATE(created_at, 'unixepoch') <= ?");
-    }
-    if query.cli_type.is_some() {
-        sql.push_str(" AND cli_type = ?");
-    }
-
-    sql.push_str(" GROUP BY provider_name, cli_type ORDER BY total_requests DESC");
+) -> Result<Json<WebdavSettings>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_webdav_settings(&state.db)
+        .await
+        .map(|settings| Json(with_masked_password(settings)))
+        .map_err(error_response)
+}
 
-    let mut q = sqlx::query_as::<_, (String, String, i64, i64, i64, i64)>(&sql);
-    if let Some(ref sd) = query.start_date {
-        q = q.bind(sd);
-    }
-    if let Some(ref ed) = query.end_date {
-        q = q.bind(ed);
-    }
-    if let Some(ref ct) = query.cli_type {
-        q = q.bind(ct);
-    }
+pub async fn update_webdav_settings_handler(
+    State(state): State<Arc<AppState>>,
+    Json(input): Json<WebdavSettingsUpdate>,
+) -> Result<Json<WebdavSettings>, (StatusCode, Json<ErrorResponse>)> {
+    commands::save_webdav_settings(&state.db, input)
+        .await
+        .map(|settings| Json(with_masked_password(settings)))
+        .map_err(error_response)
+}
 
-    let results = q.fetch_all(pool).await.map_err(db_error)?;
+#[derive(Debug, Deserialize)]
+pub struct WebdavConnection {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
 
-    let stats = results
-        .into_iter()
-        .map(|(provider_name, cli_type, total_requests, total_success, total_failure, total_tokens)| {
-            let success_rate = if total_requests > 0 {
-                (total_success as f64 / total_requests as f64) * 100.0
-            } else {
-                0.0
-            };
+pub async fn test_webdav_connection_handler(
+    State(state): State<Arc<AppState>>,
+    Json(input): Json<WebdavConnection>,
+) -> Result<Json<bool>, (StatusCode, Json<ErrorResponse>)> {
+    commands::check_webdav_connection(state.http_clients.default_client(), &input.url, &input.username, &input.password)
+        .await
+        .map(Json)
+        .map_err(error_response)
+}
 
-            ProviderStatsResponse {
-                provider_name,
-                cli_type,
-                total_requests,
-                total_success,
-                total_failure,
-                success_rate,
-                total_tokens,
-            }
-        })
-        .collect();
+pub async fn list_webdav_backups_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<WebdavBackup>>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_webdav_backups(&state.db, state.http_clients.default_client())
+        .await
+        .map(Json)
+        .map_err(error_response)
+}
 
-    Ok(Json(stats))
+pub async fn export_to_webdav_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<String>, (StatusCode, Json<ErrorResponse>)> {
+    commands::upload_webdav_backup(&state.db, state.http_clients.default_client())
+        .await
+        .map(Json)
+        .map_err(error_response)
 }
 
-// MCP, Prompts, Sessions, Backup - placeholder implementations
-// For brevity, returning empty responses for now
+pub async fn delete_webdav_backup_handler(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    commands::remove_webdav_backup(&state.db, state.http_clients.default_client(), &filename)
+        .await
+        .map_err(error_response)?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
+// MCP
 pub async fn list_mcps(
-    State(_state): State<Arc<AppState>>,
-) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(vec![]))
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<McpResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_mcps(&state.db).await.map(Json).map_err(error_response)
 }
 
 pub async fn get_mcp_handler(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<i64>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({})))
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<McpResponse>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_mcp(&state.db, id).await.map(Json).map_err(error_response)
 }
 
 pub async fn create_mcp_handler(
-    State(_state): State<Arc<AppState>>,
-    Json(_input): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({})))
+    State(state): State<Arc<AppState>>,
+    Json(input): Json<McpCreate>,
+) -> Result<Json<McpResponse>, (StatusCode, Json<ErrorResponse>)> {
+    commands::insert_mcp(&state.db, input).await.map(Json).map_err(error_response)
 }
 
 pub async fn update_mcp_handler(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<i64>,
-    Json(_input): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({})))
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(input): Json<McpUpdate>,
+) -> Result<Json<McpResponse>, (StatusCode, Json<ErrorResponse>)> {
+    commands::apply_mcp_update(&state.db, id, input).await.map(Json).map_err(error_response)
 }
 
 pub async fn delete_mcp_handler(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<i64>,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    commands::remove_mcp(&state.db, id).await.map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
+// Prompts
 pub async fn list_prompts(
-    State(_state): State<Arc<AppState>>,
-) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(vec![]))
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<PromptResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_prompts(&state.db).await.map(Json).map_err(error_response)
 }
 
 pub async fn get_prompt_handler(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<i64>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({})))
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<PromptResponse>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_prompt(&state.db, id).await.map(Json).map_err(error_response)
 }
 
 pub async fn create_prompt_handler(
-    State(_state): State<Arc<AppState>>,
-    Json(_input): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({})))
+    State(state): State<Arc<AppState>>,
+    Json(input): Json<PromptCreate>,
+) -> Result<Json<PromptResponse>, (StatusCode, Json<ErrorResponse>)> {
+    commands::insert_prompt(&state.db, input).await.map(Json).map_err(error_response)
 }
 
 pub async fn update_prompt_handler(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<i64>,
-    Json(_input): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({})))
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(input): Json<PromptUpdate>,
+) -> Result<Json<PromptResponse>, (StatusCode, Json<ErrorResponse>)> {
+    commands::apply_prompt_update(&state.db, id, input).await.map(Json).map_err(error_response)
 }
 
 pub async fn delete_prompt_handler(
-    State(_state): State<Arc<AppState>>,
-    Path(_id): Path<i64>,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    commands::remove_prompt(&state.db, id).await.map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
+// Sessions
+#[derive(Debug, Deserialize)]
+pub struct SessionQuery {
+    pub cli_type: String,
+    pub page: Option<i64>,
+    pub page_size: Option<i64>,
+}
+
 pub async fn list_projects(
-    Query(_query): Query<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "items": [], "total": 0, "page": 1, "page_size": 20 })))
+    Query(query): Query<SessionQuery>,
+) -> Result<Json<PaginatedProjects>, (StatusCode, Json<ErrorResponse>)> {
+    commands::get_session_projects(query.cli_type, query.page, query.page_size)
+        .await
+        .map(Json)
+        .map_err(error_response)
 }
 
 pub async fn delete_project_handler(
-    Query(_query): Query<serde_json::Value>,
-    Path(_name): Path<String>,
+    Query(query): Query<SessionQuery>,
+    Path(name): Path<String>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    commands::delete_project(query.cli_type, name).await.map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn list_sessions(
-    Query(_query): Query<serde_json::Value>,
-    Path(_name): Path<String>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "items": [], "total": 0, "page": 1, "page_size": 20 })))
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SessionQuery>,
+    Path(name): Path<String>,
+) -> Result<Json<PaginatedSessions>, (StatusCode, Json<ErrorResponse>)> {
+    commands::load_project_sessions(&state.db, &state.log_db, query.cli_type, name, query.page, query.page_size)
+        .await
+        .map(Json)
+        .map_err(error_response)
 }
 
 pub async fn delete_session_handler(
-    Query(_query): Query<serde_json::Value>,
-    Path((_name, _id)): Path<(String, String)>,
+    Query(query): Query<SessionQuery>,
+    Path((name, id)): Path<(String, String)>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    commands::delete_session(query.cli_type, name, id).await.map_err(error_response)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
 pub async fn get_session_messages_handler(
-    Query(_query): Query<serde_json::Value>,
-    Path((_name, _id)): Path<(String, String)>,
-) -> Result<Json<Vec<serde_json::Value>>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(vec![]))
+    Query(query): Query<SessionQuery>,
+    Path((name, id)): Path<(String, String)>,
+) -> Result<Json<Vec<SessionMessage>>, (StatusCode, Json<ErrorResponse>)> {
+    commands::get_session_messages(query.cli_type, name, id)
+        .await
+        .map(Json)
+        .map_err(error_response)
 }
 
-pub async fn get_webdav_settings_handler(
-    State(_state): State<Arc<AppState>>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "url": "", "username": "", "password": "" })))
-}
+/// Proxy access: clients outside gateway_settings.allowed_client_ips get 403
+pub async fn require_allowed_client_ip(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response<Body> {
+    let client_ip = req
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .map(|info| info.0.ip());
+    let allowed = {
+        let allowlist = state.allowed_client_ips.read().unwrap_or_else(|e| e.into_inner());
+        match client_ip {
+            Some(ip) => crate::services::ip_allowlist::is_allowed(&allowlist, ip),
+            None => allowlist.is_empty(),
+        }
+    };
+    if allowed {
+        return next.run(req).await;
+    }
 
-pub async fn update_webdav_settings_handler(
-    State(_state): State<Arc<AppState>>,
-    Json(_input): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "url": "", "username": "", "password": "" })))
+    let client_ip = client_ip.map(|ip| ip.to_canonical().to_string()).unwrap_or_else(|| "unknown".to_string());
+    tracing::warn!(client_ip = %client_ip, path = %req.uri().path(), "Rejected request from a client outside the allowlist");
+    let _ = stats_service::record_system_log(
+        &state.log_db,
+        "warn",
+        "client_ip_denied",
+        &format!("Rejected {} {} from {}: client address not in the allowlist", req.method(), req.uri().path(), client_ip),
+        None,
+        None,
+    ).await;
+
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"error": "Client address not allowed"}"#))
+        .unwrap()
+}
+
+/// `/api` access: the gateway token is always required, whether or not the proxy enforces it
+pub async fn require_gateway_token(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response<Body> {
+    let token = sqlx::query_scalar::<_, Option<String>>("SELECT gateway_token FROM gateway_settings WHERE id = 1")
+        .fetch_one(&state.db)
+        .await
+        .ok()
+        .flatten()
+        .filter(|t| !t.is_empty());
+    match token {
+        Some(token) if client_presents_token(req.headers(), &token) => next.run(req).await,
+        _ => {
+            let body = serde_json::json!({ "error": "Missing or invalid gateway token" });
+            Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        }
+    }
 }
 
-pub async fn test_webdav_connection_handler(
-    Json(_input): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "success": false })))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
 
-pub async fn export_to_local_handler() -> Result<Response<Body>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "application/octet-stream")
-        .body(Body::empty())
-        .unwrap())
-}
+    const USAGE_JSON: &[u8] = br#"{"usage":{"input_tokens":12,"output_tokens":34}}"#;
+    /// USAGE_JSON compressed with brotli (quality 5, window 22)
+    const USAGE_JSON_BR: &[u8] = &[
+        27, 47, 0, 0, 4, 114, 113, 228, 207, 73, 21, 205, 36, 129, 112, 23, 108, 192, 129, 99, 16, 14, 246, 160, 195,
+        198, 216, 57, 130, 96, 197, 116, 13, 25, 195, 253, 246, 15, 101, 5, 43, 193, 237, 249, 7,
+    ];
 
-pub async fn import_from_local_handler(
-    _bytes: axum::body::Bytes,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "success": true, "message": "Not implemented" })))
-}
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
 
-pub async fn export_to_webdav_handler(
-    State(_state): State<Arc<AppState>>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "success": false, "message": "Not implemented" })))
-}
+    #[test]
+    fn decompresses_single_encodings() {
+        assert_eq!(maybe_decompress(&gzip(USAGE_JSON), Some("gzip")), USAGE_JSON);
+        assert_eq!(maybe_decompress(USAGE_JSON_BR, Some("br")), USAGE_JSON);
+        assert_eq!(maybe_decompress(&zstd::encode_all(USAGE_JSON, 3).unwrap(), Some("zstd")), USAGE_JSON);
+
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(USAGE_JSON).unwrap();
+        assert_eq!(maybe_decompress(&zlib.finish().unwrap(), Some("deflate")), USAGE_JSON);
+        // 裸 deflate（不符合规范但常见）
+        let mut raw = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw.write_all(USAGE_JSON).unwrap();
+        assert_eq!(maybe_decompress(&raw.finish().unwrap(), Some("deflate")), USAGE_JSON);
+    }
 
-pub async fn list_webdav_backups_handler(
-    State(_state): State<Arc<AppState>>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "backups": [] })))
-}
+    #[test]
+    fn decompresses_chained_encodings_in_reverse_order() {
+        // `br, gzip`：先 br 后 gzip，解码时反过来
+        let body = gzip(USAGE_JSON_BR);
+        assert_eq!(maybe_decompress(&body, Some("br, gzip")), USAGE_JSON);
+        let body = zstd::encode_all(gzip(USAGE_JSON).as_slice(), 3).unwrap();
+        assert_eq!(maybe_decompress(&body, Some("gzip,zstd")), USAGE_JSON);
+        assert_eq!(maybe_decompress(&gzip(USAGE_JSON), Some("identity, GZIP")), USAGE_JSON);
+    }
+
+    #[test]
+    fn unknown_or_corrupt_encodings_keep_the_original_bytes() {
+        assert_eq!(maybe_decompress(USAGE_JSON, None), USAGE_JSON);
+        assert_eq!(maybe_decompress(USAGE_JSON, Some("compress")), USAGE_JSON);
+        assert_eq!(maybe_decompress(b"not gzip", Some("gzip")), b"not gzip");
+        // 链中任一步失败都返回原始字节，而不是半解码的结果
+        let body = gzip(b"not brotli");
+        assert_eq!(maybe_decompress(&body, Some("br, gzip")), body);
+    }
+
+    #[test]
+    fn decompressed_bodies_yield_token_usage() {
+        let mut usage = TokenUsage::default();
+        parse_token_usage(&maybe_decompress(USAGE_JSON_BR, Some("br")), CliType::ClaudeCode, &mut usage);
+        assert_eq!((usage.input_tokens, usage.output_tokens), (12, 34));
+    }
+
+    /// Request received by the mock provider
+    #[derive(Debug)]
+    struct SeenRequest {
+        method: String,
+        /// Path with query
+        path: String,
+        body_len: usize,
+        user_agent: Option<String>,
+    }
+
+    type SeenRequests = Arc<std::sync::Mutex<Vec<SeenRequest>>>;
+
+    /// Mock provider answering every request with a small JSON body
+    async fn spawn_upstream() -> (String, SeenRequests) {
+        let seen = SeenRequests::default();
+        let recorder = seen.clone();
+        let app = axum::Router::new().fallback(move |req: axum::http::Request<Body>| {
+            let recorder = recorder.clone();
+            async move {
+                let method = req.method().to_string();
+                let path = req.uri().path_and_query().map(|p| p.to_string()).unwrap_or_default();
+                let user_agent = req.headers().get(header::USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_string);
+                let body = axum::body::to_bytes(req.into_body(), usize::MAX).await.unwrap();
+                recorder.lock().unwrap().push(SeenRequest {
+                    method,
+                    path,
+                    body_len: body.len(),
+                    user_agent,
+                });
+                ([(header::CONTENT_TYPE, "application/json")], r#"{"data":[{"id":"claude-sonnet"}]}"#)
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}", addr), seen)
+    }
+
+    /// Temporary data directory, removed when the test ends
+    struct TestDir(std::path::PathBuf);
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Gateway on a fresh database with one Claude Code provider pointing at `upstream`
+    async fn spawn_gateway(upstream: &str) -> (String, Arc<AppState>, TestDir) {
+        let dir = TestDir(std::env::temp_dir().join(format!("ccg-test-{}", uuid::Uuid::new_v4().simple())));
+        let (db, _) = crate::db::init_db(&dir.0.join("ccg_gateway.db")).await.unwrap();
+        let (log_db, _) = crate::db::init_db(&dir.0.join("ccg_logs.db")).await.unwrap();
+        let ua_patterns = crate::services::proxy::UaPatternCache::default();
+        let input: ProviderCreate = serde_json::from_value(serde_json::json!({
+            "cli_type": "claude_code",
+            "name": "mock",
+            "base_url": upstream,
+            "api_key": "sk-test",
+        }))
+        .unwrap();
+        commands::insert_provider(&db, &log_db, &ua_patterns, input).await.unwrap();
+
+        let state = Arc::new(AppState {
+            db,
+            log_db,
+            http_clients: crate::services::http_client::HttpClientPool::new(reqwest::Client::new()),
+            ua_patterns,
+            ua_rules: Default::default(),
+            mask_patterns: Default::default(),
+            log_exclude: Default::default(),
+            cors_origins: Default::default(),
+            allowed_client_ips: Default::default(),
+            key_cursors: Default::default(),
+            schedules: Default::default(),
+            session_provider_map: Default::default(),
+            port: Default::default(),
+            listen_addr: Default::default(),
+            config: Default::default(),
+            counters: Default::default(),
+            active_requests: Default::default(),
+            concurrency: Default::default(),
+            cli_sync: Default::default(),
+        });
+        let router = super::super::create_router(state.clone(), Some(CliType::ClaudeCode));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router.into_make_service_with_connect_info::<std::net::SocketAddr>()).await
+        });
+        (format!("http://{}", addr), state, dir)
+    }
+
+    #[tokio::test]
+    async fn options_head_and_get_reach_the_upstream_as_expected() {
+        let (upstream, seen) = spawn_upstream().await;
+        let (gateway, state, _dir) = spawn_gateway(&upstream).await;
+        let client = reqwest::Client::new();
+
+        // OPTIONS 在本地应答，不转发
+        let response = client.request(reqwest::Method::OPTIONS, format!("{}/v1/models", gateway)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+        assert!(response.headers()[header::ALLOW].to_str().unwrap().contains("HEAD"));
+        let request_id = response.headers()[REQUEST_ID_RESPONSE_HEADER].to_str().unwrap().to_string();
+        assert!(seen.lock().unwrap().is_empty());
+        let logged: (String, String) =
+            sqlx::query_as("SELECT client_method, provider_name FROM request_logs WHERE request_id = ?")
+                .bind(&request_id)
+                .fetch_one(&state.log_db)
+                .await
+                .unwrap();
+        assert_eq!(logged, ("OPTIONS".to_string(), "local".to_string()));
+
+        let response = client.head(format!("{}/v1/models", gateway)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(response.bytes().await.unwrap().is_empty());
+
+        let response = client.get(format!("{}/v1/models?limit=5", gateway)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), r#"{"data":[{"id":"claude-sonnet"}]}"#);
+
+        let seen: Vec<_> = seen
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| (r.method.clone(), r.path.clone(), r.body_len))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                ("HEAD".to_string(), "/v1/models".to_string(), 0),
+                ("GET".to_string(), "/v1/models?limit=5".to_string(), 0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn logged_headers_and_urls_never_contain_full_keys() {
+        const PROVIDER_KEY: &str = "sk-provider-0123456789abcdef";
+        const CLIENT_KEY: &str = "sk-ant-REDACTED";
+        let (upstream, _) = spawn_upstream().await;
+        let (gateway, state, _dir) = spawn_gateway(&upstream).await;
+        sqlx::query("UPDATE providers SET api_key = ?, extra_query_params = ?")
+            .bind(PROVIDER_KEY)
+            .bind(r#"{"api_key":"sk-query-0123456789abcdef"}"#)
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        let response = reqwest::Client::new()
+            .get(format!("{}/v1/models", gateway))
+            .header("x-api-key", CLIENT_KEY)
+            .header("authorization", format!("Bearer {}", CLIENT_KEY))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        let (client_headers, forward_headers, forward_url): (String, String, String) = sqlx::query_as(
+            "SELECT client_headers, forward_headers, forward_url FROM request_logs WHERE client_method = 'GET'",
+        )
+        .fetch_one(&state.log_db)
+        .await
+        .unwrap();
+        for logged in [&client_headers, &forward_headers, &forward_url] {
+            for secret in [PROVIDER_KEY, CLIENT_KEY, "sk-query-0123456789abcdef"] {
+                assert!(!logged.contains(secret), "{} leaked into {}", secret, logged);
+            }
+        }
+        assert!(client_headers.contains("Bearer sk-a...cdef"));
+        assert!(forward_headers.contains("sk-p...cdef"));
+        assert!(forward_url.contains("api_key=sk-q...cdef"));
+    }
+
+    #[test]
+    fn serialized_headers_mask_credentials() {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("x-goog-api-key", HeaderValue::from_static("AIzaSyA1234567890abcdef"));
+        headers.insert("user-agent", HeaderValue::from_static("claude-cli/1.0"));
+        let logged: std::collections::HashMap<String, String> = serde_json::from_str(&serialize_headers(&headers)).unwrap();
+        assert_eq!(logged["x-goog-api-key"], "AIza...cdef");
+        assert_eq!(logged["user-agent"], "claude-cli/1.0");
+
+        let mut forwarded = reqwest::header::HeaderMap::new();
+        forwarded.insert("x-custom-key", reqwest::header::HeaderValue::from_static("custom-secret-value-123"));
+        forwarded.insert("x-custom-flag", reqwest::header::HeaderValue::from_static("on"));
+        let custom = [
+            reqwest::header::HeaderName::from_static("x-custom-key"),
+            reqwest::header::HeaderName::from_static("x-custom-flag"),
+        ];
+        let logged: std::collections::HashMap<String, String> =
+            serde_json::from_str(&serialize_reqwest_headers(&forwarded, &custom)).unwrap();
+        assert_eq!(logged["x-custom-key"], "cust...-123");
+        assert_eq!(logged["x-custom-flag"], "on");
+    }
+
+    #[test]
+    fn query_param_auth_is_masked_in_the_logged_url() {
+        let key = "AIzaSyA1234567890abcdef";
+        let (url, logged) = crate::services::proxy::apply_auth_query(
+            "https://generativelanguage.googleapis.com/v1beta/models/m:generateContent?alt=sse",
+            key,
+            Some(AuthScheme::QueryParam),
+        );
+        assert!(url.contains(key));
+        assert!(!logged.contains(key));
+        assert!(logged.ends_with("key=AIza...cdef"));
+    }
+
+    #[tokio::test]
+    async fn provider_user_agent_is_forwarded_verbatim() {
+        let (upstream, seen) = spawn_upstream().await;
+        let (gateway, state, _dir) = spawn_gateway(&upstream).await;
+        sqlx::query("UPDATE providers SET proxy_user_agent = 'curl/7.88'")
+            .execute(&state.db)
+            .await
+            .unwrap();
+
+        reqwest::Client::new()
+            .post(format!("{}/v1/messages", gateway))
+            .header(header::USER_AGENT, "claude-cli/2.0.1 (external, cli)")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(r#"{"model":"claude-sonnet","messages":[]}"#)
+            .send()
+            .await
+            .unwrap();
+        let user_agent = seen.lock().unwrap()[0].user_agent.clone();
+        assert_eq!(user_agent.as_deref(), Some("curl/7.88"));
+    }
+
+    /// Mock provider whose SSE streams stay open until `release` is set
+    async fn spawn_streaming_upstream(release: tokio::sync::watch::Receiver<bool>) -> String {
+        let app = axum::Router::new().fallback(move || {
+            let mut release = release.clone();
+            async move {
+                let body = Body::from_stream(async_stream::stream! {
+                    yield Ok::<_, std::io::Error>(bytes::Bytes::from_static(b"event: message_start\ndata: {\"type\":\"message_start\"}\n\n"));
+                    let _ = release.wait_for(|released| *released).await;
+                    yield Ok(bytes::Bytes::from_static(b"event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+                });
+                ([(header::CONTENT_TYPE, "text/event-stream")], body)
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit_holds_under_load_with_open_streams() {
+        const LIMIT: u32 = 4;
+        const REQUESTS: usize = 20;
+        let (release_tx, release_rx) = tokio::sync::watch::channel(false);
+        let upstream = spawn_streaming_upstream(release_rx).await;
+        let (gateway, state, _dir) = spawn_gateway(&upstream).await;
+        state.concurrency.set_limit(LIMIT);
+        let client = reqwest::Client::new();
+        let send = |client: reqwest::Client, gateway: String| async move {
+            client
+                .post(format!("{}/v1/messages", gateway))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(r#"{"model":"claude-sonnet","stream":true,"messages":[]}"#)
+                .send()
+                .await
+                .unwrap()
+        };
+
+        let test = async {
+            let mut tasks = tokio::task::JoinSet::new();
+            for _ in 0..REQUESTS {
+                tasks.spawn(send(client.clone(), gateway.clone()));
+            }
+            let (mut open, mut rejected) = (Vec::new(), 0);
+            while let Some(response) = tasks.join_next().await {
+                let response = response.unwrap();
+                match response.status() {
+                    reqwest::StatusCode::OK => open.push(response),
+                    reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+                        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+                        rejected += 1;
+                    }
+                    status => panic!("unexpected status {}", status),
+                }
+            }
+            // 流未结束时名额一直被占用，新请求（包括非流式）仍被拒绝
+            assert_eq!(open.len(), LIMIT as usize);
+            assert_eq!(rejected, REQUESTS - LIMIT as usize);
+            assert_eq!(state.concurrency.in_flight(), LIMIT);
+            let response = client.get(format!("{}/v1/models", gateway)).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+            release_tx.send_replace(true);
+            for response in open {
+                assert!(response.text().await.unwrap().contains("message_stop"));
+            }
+            while state.concurrency.in_flight() > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            assert_eq!(send(client.clone(), gateway.clone()).await.status(), reqwest::StatusCode::OK);
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(20), test)
+            .await
+            .expect("requests deadlocked behind the concurrency limit");
+    }
 
-pub async fn import_from_webdav_handler(
-    State(_state): State<Arc<AppState>>,
-    Query(_query): Query<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    Ok(Json(serde_json::json!({ "success": true, "message": "Not implemented" })))
+    /// Shared listener (with /api) on the gateway's state, authenticated with `gw-test-token`
+    async fn spawn_api(state: Arc<AppState>) -> (String, reqwest::Client) {
+        sqlx::query("UPDATE gateway_settings SET gateway_token = 'gw-test-token'")
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let router = super::super::create_router(state, None);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, router.into_make_service_with_connect_info::<std::net::SocketAddr>()).await
+        });
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::AUTHORIZATION, "Bearer gw-test-token".parse().unwrap());
+        let client = reqwest::Client::builder().default_headers(headers).build().unwrap();
+        (format!("http://{}/api", addr), client)
+    }
+
+    #[tokio::test]
+    async fn settings_api_matches_the_tauri_commands() {
+        let (upstream, _) = spawn_upstream().await;
+        let (_, state, _dir) = spawn_gateway(&upstream).await;
+        let (api, client) = spawn_api(state.clone()).await;
+
+        let settings: serde_json::Value = client.get(format!("{}/settings/gateway", api)).send().await.unwrap().json().await.unwrap();
+        let expected = serde_json::to_value(without_gateway_token(commands::load_gateway_settings(&state.db).await.unwrap())).unwrap();
+        assert_eq!(settings, expected);
+        assert!(settings["gateway_token"].is_null());
+        assert!(settings.get("cli_ports").is_some() && settings.get("cli_path_prefix").is_some());
+
+        // 部分更新；校验与命令一致（超出上限报错而不是截断）
+        let response = client
+            .put(format!("{}/settings/gateway", api))
+            .json(&serde_json::json!({ "sticky_session_enabled": true }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+        let response = client
+            .put(format!("{}/settings/gateway", api))
+            .json(&serde_json::json!({ "max_request_body_mb": MAX_REQUEST_BODY_MB_LIMIT + 1 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        let saved = commands::load_gateway_settings(&state.db).await.unwrap();
+        assert_eq!(saved.sticky_session_enabled, 1);
+        assert_eq!(saved.max_request_body_mb, settings["max_request_body_mb"].as_i64().unwrap());
+
+        let all: serde_json::Value = client.get(format!("{}/settings", api)).send().await.unwrap().json().await.unwrap();
+        assert!(all["gateway"]["gateway_token"].is_null());
+        let claude = commands::load_cli_settings(&state.db, &state.port, "claude_code".to_string()).await.unwrap();
+        assert_eq!(all["cli_settings"]["claude_code"], serde_json::to_value(&claude).unwrap());
+        let response = client.get(format!("{}/settings/cli/unknown", api)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn stats_api_matches_the_tauri_commands() {
+        let (upstream, _) = spawn_upstream().await;
+        let (_, state, _dir) = spawn_gateway(&upstream).await;
+        let (api, client) = spawn_api(state.clone()).await;
+        for (provider, model) in [("mock", "claude-sonnet"), ("mock", "claude-haiku"), ("other", "gpt-4o")] {
+            stats_service::record_request_log(
+                &state.log_db, &state.mask_patterns, "claude_code", provider, Some(model), Some(200), 10, 1, 2, "POST", "/v1/messages", None,
+            )
+            .await
+            .unwrap();
+        }
+
+        let stats: serde_json::Value = client
+            .get(format!("{}/stats/providers?provider_name=mock", api))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let expected = commands::load_provider_stats(&state.log_db, None, None, None, Some("mock".to_string())).await.unwrap();
+        assert_eq!(stats, serde_json::to_value(&expected).unwrap());
+        // 与命令相同：按 (cli_type, provider, model) 分组，并可按服务商筛选
+        assert_eq!(stats.as_array().unwrap().len(), 2);
+        assert!(stats.as_array().unwrap().iter().all(|row| row["provider_name"] == "mock" && row["model_id"].is_string()));
+        let none: serde_json::Value = client
+            .get(format!("{}/stats/providers?provider_name=absent", api))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(none, serde_json::json!([]));
+
+        let daily: serde_json::Value = client.get(format!("{}/stats/daily", api)).send().await.unwrap().json().await.unwrap();
+        let expected = commands::load_daily_stats(&state.log_db, None, None, None).await.unwrap();
+        assert_eq!(daily, serde_json::to_value(&expected).unwrap());
+    }
+
+    #[tokio::test]
+    async fn webdav_api_masks_the_password_and_checks_backup_names() {
+        let (upstream, _) = spawn_upstream().await;
+        let (_, state, _dir) = spawn_gateway(&upstream).await;
+        let (api, client) = spawn_api(state.clone()).await;
+
+        let settings: serde_json::Value = client
+            .put(format!("{}/webdav", api))
+            .json(&serde_json::json!({ "url": "http://127.0.0.1:1/dav", "username": "me", "password": "dav-password-123456" }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(settings["username"], "me");
+        assert_eq!(settings["password"], "dav-...3456");
+        assert_eq!(commands::load_webdav_settings(&state.db).await.unwrap().password, "dav-password-123456");
+
+        let response = client.delete(format!("{}/webdav/backups/..%2Fccg_gateway.db", api)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.text().await.unwrap().contains("Invalid backup file name"));
+    }
 }
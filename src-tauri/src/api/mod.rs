@@ -1,32 +1,139 @@
 pub mod handlers;
+pub mod status_page;
 
 use axum::{
-    routing::get,
-    Router,
+    middleware,
+    routing::{get, post},
+    Extension, Router,
 };
 use sqlx::SqlitePool;
+use crate::config::SharedConfig;
+use crate::services::active_requests::ActiveRequests;
+use crate::services::cli_sync::CliSyncState;
+use crate::services::concurrency::ConcurrencyLimiter;
+use crate::services::cors::{api_cors_layer, CorsOriginCache};
+use crate::services::diagnostics::ProxyCounters;
+use crate::services::http_client::HttpClientPool;
+use crate::services::ip_allowlist::IpAllowlistCache;
+use crate::services::log_exclude::LogExcludeCache;
+use crate::services::masking::MaskPatternCache;
+use crate::services::provider::KeyCursors;
+use crate::services::proxy::{CliType, UaPatternCache, UaRuleCache};
+use crate::services::routing::{ScheduleCache, SessionProviderMap};
 use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: SqlitePool,
     pub log_db: SqlitePool,
+    pub http_clients: HttpClientPool,
+    pub ua_patterns: UaPatternCache,
+    /// Global User-Agent rules and the default CLI type for unrecognized clients
+    pub ua_rules: UaRuleCache,
+    /// Compiled regexes used to mask secrets in logged bodies
+    pub mask_patterns: MaskPatternCache,
+    /// Client paths that skip the request log
+    pub log_exclude: LogExcludeCache,
+    /// Browser origins allowed to call /api (no CORS on the proxy routes)
+    pub cors_origins: CorsOriginCache,
+    /// Client addresses allowed to use the proxy (empty = everyone)
+    pub allowed_client_ips: IpAllowlistCache,
+    /// Round-robin cursors for provider API key rotation
+    pub key_cursors: KeyCursors,
+    /// Time-of-day provider priority overrides
+    pub schedules: ScheduleCache,
+    /// Providers pinned to client sessions (sticky_session_enabled)
+    pub session_provider_map: SessionProviderMap,
+    /// Actual listening port (may differ from the configured one after fallback) and the
+    /// ports of the dedicated per-CLI listeners
+    pub port: crate::GatewayPort,
+    /// Bound address; a non-loopback address always requires the gateway token
+    pub listen_addr: crate::ListenAddr,
+    /// Live config, replaced when the config file is reloaded
+    pub config: SharedConfig,
+    /// In-flight / open-connection / total request counters for diagnostics
+    pub counters: ProxyCounters,
+    /// Proxy requests in flight, listed and cancellable from the app
+    pub active_requests: ActiveRequests,
+    /// Ceiling on concurrent proxy requests (max_concurrent_requests)
+    pub concurrency: ConcurrencyLimiter,
+    /// Whether each CLI config file still matches what the gateway wrote
+    pub cli_sync: CliSyncState,
 }
 
-pub fn create_router(state: AppState) -> Router {
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+/// CLI type of a dedicated listener (gateway_settings.cli_ports); replaces User-Agent detection
+#[derive(Debug, Clone, Copy)]
+pub struct ForcedCliType(pub CliType);
 
-    // Desktop-only mode: No /api routes needed
-    // Frontend uses Tauri IPC instead of HTTP
-    // Only CLI proxy is required
-    Router::new()
-        .route("/health", get(|| async { "ok" }))
-        // Catch-all proxy route for CLI tools (Claude Code, Codex, Gemini)
+/// Router serving the gateway; `cli_type` is set for the dedicated per-CLI listeners
+pub fn create_router(state: Arc<AppState>, cli_type: Option<CliType>) -> Router {
+    // The desktop frontend uses Tauri IPC; /api is for headless management (scripts, servers)
+    // and, like the /status page, is only served by the shared listener
+    let router = Router::new().route("/health", get(handlers::health_handler));
+    let router = match cli_type {
+        Some(_) => router,
+        None => router
+            .route("/status", get(status_page::status_page_handler))
+            .nest("/api", api_router(state.clone())),
+    };
+    // Catch-all proxy route for CLI tools (Claude Code, Codex, Gemini)
+    let proxy = Router::new()
         .fallback(handlers::proxy_handler_catchall)
-        .layer(cors)
-        .with_state(Arc::new(state))
+        .layer(middleware::from_fn_with_state(state.clone(), handlers::require_allowed_client_ip));
+    let router = router.merge(proxy);
+    match cli_type {
+        Some(cli_type) => router.layer(Extension(ForcedCliType(cli_type))),
+        None => router,
+    }
+    .with_state(state)
+}
+
+/// Management API, same JSON as the Tauri commands; every route requires the gateway token
+fn api_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/providers", get(handlers::list_providers).post(handlers::create_provider_handler))
+        .route("/providers/reorder", post(handlers::reorder_providers_handler))
+        .route(
+            "/providers/:id",
+            get(handlers::get_provider_handler)
+                .put(handlers::update_provider_handler)
+                .delete(handlers::delete_provider_handler),
+        )
+        .route("/providers/:id/reset", post(handlers::reset_provider_failures_handler))
+        .route("/logs", get(handlers::get_request_logs).delete(handlers::clear_request_logs))
+        .route("/logs/:id", get(handlers::get_request_log_detail))
+        .route("/system-logs", get(handlers::get_system_logs_handler).delete(handlers::clear_system_logs_handler))
+        .route("/stats/daily", get(handlers::get_daily_stats))
+        .route("/stats/providers", get(handlers::get_provider_stats))
+        .route("/status", get(handlers::get_system_status_handler))
+        .route("/settings", get(handlers::get_all_settings))
+        .route("/settings/gateway", get(handlers::get_gateway_settings).put(handlers::update_gateway_settings_handler))
+        .route("/settings/timeouts", get(handlers::get_timeout_settings).put(handlers::update_timeout_settings_handler))
+        .route("/settings/cli/:cli_type", get(handlers::get_cli_settings_handler).put(handlers::update_cli_settings_handler))
+        .route("/webdav", get(handlers::get_webdav_settings_handler).put(handlers::update_webdav_settings_handler))
+        .route("/webdav/test", post(handlers::test_webdav_connection_handler))
+        .route("/webdav/backups", get(handlers::list_webdav_backups_handler).post(handlers::export_to_webdav_handler))
+        .route("/webdav/backups/:filename", axum::routing::delete(handlers::delete_webdav_backup_handler))
+        .route("/mcps", get(handlers::list_mcps).post(handlers::create_mcp_handler))
+        .route(
+            "/mcps/:id",
+            get(handlers::get_mcp_handler)
+                .put(handlers::update_mcp_handler)
+                .delete(handlers::delete_mcp_handler),
+        )
+        .route("/prompts", get(handlers::list_prompts).post(handlers::create_prompt_handler))
+        .route(
+            "/prompts/:id",
+            get(handlers::get_prompt_handler)
+                .put(handlers::update_prompt_handler)
+                .delete(handlers::delete_prompt_handler),
+        )
+        .route("/projects", get(handlers::list_projects))
+        .route("/projects/:name", axum::routing::delete(handlers::delete_project_handler))
+        .route("/projects/:name/sessions", get(handlers::list_sessions))
+        .route("/projects/:name/sessions/:id", axum::routing::delete(handlers::delete_session_handler))
+        .route("/projects/:name/sessions/:id/messages", get(handlers::get_session_messages_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), handlers::require_gateway_token))
+        // Outside the token check: preflight requests carry no credentials
+        .layer(api_cors_layer(state.cors_origins.clone()))
 }
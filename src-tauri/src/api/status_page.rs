@@ -0,0 +1,206 @@
+//! Read-only HTML status page at `/status` for checking the gateway from another machine.
+//!
+//! 服务端直接渲染，不依赖前端构建；每 10 秒自动刷新。启用网关令牌时需要携带令牌，
+//! 浏览器无法设置请求头，因此也接受 `?token=`（刷新时保留在地址中）。
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, Response, StatusCode},
+};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use super::AppState;
+use crate::commands;
+use crate::db::models::{ProviderResponse, RequestLogItem};
+use crate::services::proxy::{client_presents_token, token_matches};
+
+/// Seconds between automatic refreshes
+const REFRESH_SECS: u32 = 10;
+/// Request log lines shown at the bottom of the page
+const RECENT_LOG_LIMIT: i64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct StatusPageQuery {
+    pub token: Option<String>,
+}
+
+/// Requests and tokens of the current (UTC) day, as counted in usage_daily
+#[derive(Debug, Default, sqlx::FromRow)]
+struct TodayUsage {
+    requests: i64,
+    successes: i64,
+    input_tokens: i64,
+    output_tokens: i64,
+}
+
+pub async fn status_page_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatusPageQuery>,
+    req: axum::extract::Request,
+) -> Response<Body> {
+    if let Some(token) = super::handlers::enforced_gateway_token(&state.db, state.listen_addr.is_external()).await {
+        let by_query = query.token.as_deref().is_some_and(|t| token_matches(t, &token));
+        if !by_query && !client_presents_token(req.headers(), &token) {
+            return html_response(
+                StatusCode::UNAUTHORIZED,
+                page("Unauthorized", "<p>Missing or invalid gateway token. Open <code>/status?token=&lt;gateway token&gt;</code>.</p>", false),
+            );
+        }
+    }
+
+    match render_status(&state).await {
+        Ok(body) => html_response(StatusCode::OK, page("CCG Gateway", &body, true)),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to render status page");
+            html_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                page("CCG Gateway", &format!("<p>Failed to load status: {}</p>", escape(&e)), true),
+            )
+        }
+    }
+}
+
+async fn render_status(state: &AppState) -> Result<String, String> {
+    let server = state.listen_addr.status();
+    let providers = commands::load_providers(&state.db, None).await?;
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    let usage = sqlx::query_as::<_, TodayUsage>(
+        "SELECT COALESCE(SUM(request_count), 0) AS requests, COALESCE(SUM(success_count), 0) AS successes, COALESCE(SUM(input_tokens), 0) AS input_tokens, COALESCE(SUM(output_tokens), 0) AS output_tokens FROM usage_daily WHERE usage_date = ?",
+    )
+    .bind(&today)
+    .fetch_one(&state.log_db)
+    .await
+    .map_err(|e| e.to_string())?;
+    let logs = commands::load_request_logs(&state.log_db, Some(1), Some(RECENT_LOG_LIMIT), None).await?;
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<h2>Gateway</h2><table><tr><th>Status</th><td>{}</td></tr><tr><th>Address</th><td>{}</td></tr><tr><th>Uptime</th><td>{}</td></tr><tr><th>Version</th><td>{}</td></tr><tr><th>In flight</th><td>{}</td></tr></table>",
+        escape(server.state()),
+        escape(&state.listen_addr.get().map(|a| a.to_string()).unwrap_or_else(|| "-".to_string())),
+        format_duration(server.uptime()),
+        env!("CARGO_PKG_VERSION"),
+        state.concurrency.in_flight(),
+    );
+    let _ = write!(
+        html,
+        "<h2>Today ({} UTC)</h2><table><tr><th>Requests</th><td>{}</td></tr><tr><th>Succeeded</th><td>{}</td></tr><tr><th>Input tokens</th><td>{}</td></tr><tr><th>Output tokens</th><td>{}</td></tr></table>",
+        today, usage.requests, usage.successes, usage.input_tokens, usage.output_tokens,
+    );
+    render_providers(&mut html, &providers);
+    render_logs(&mut html, &logs.items);
+    Ok(html)
+}
+
+fn render_providers(html: &mut String, providers: &[ProviderResponse]) {
+    html.push_str("<h2>Providers</h2><table><tr><th>CLI</th><th>Name</th><th>State</th><th>Failures</th></tr>");
+    let now = chrono::Utc::now().timestamp();
+    for provider in providers {
+        let (class, label) = match provider.blacklisted_until {
+            _ if !provider.enabled => ("muted", "disabled".to_string()),
+            Some(until) if until > now => ("bad", format!("blacklisted for {}", format_duration(until - now))),
+            _ => ("ok", "available".to_string()),
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td>{}/{}</td></tr>",
+            escape(&provider.cli_type),
+            escape(&provider.name),
+            class,
+            label,
+            provider.consecutive_failures,
+            provider.failure_threshold,
+        );
+    }
+    if providers.is_empty() {
+        html.push_str("<tr><td colspan=\"4\" class=\"muted\">No providers configured</td></tr>");
+    }
+    html.push_str("</table>");
+}
+
+fn render_logs(html: &mut String, logs: &[RequestLogItem]) {
+    let _ = write!(
+        html,
+        "<h2>Last {} requests</h2><table><tr><th>Time</th><th>CLI</th><th>Provider</th><th>Model</th><th>Request</th><th>Status</th><th>Time (ms)</th><th>Tokens in/out</th></tr>",
+        RECENT_LOG_LIMIT
+    );
+    for log in logs {
+        let time = chrono::DateTime::from_timestamp(log.created_at, 0)
+            .map(|t| t.with_timezone(&chrono::Local).format("%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let (class, status) = match log.status_code {
+            Some(code) if (200..400).contains(&code) => ("ok", code.to_string()),
+            Some(code) => ("bad", code.to_string()),
+            None => ("bad", "-".to_string()),
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} {}</td><td class=\"{}\">{}</td><td>{}</td><td>{}/{}</td></tr>",
+            time,
+            escape(&log.cli_type),
+            escape(&log.provider_name),
+            escape(log.model_id.as_deref().unwrap_or("-")),
+            escape(&log.client_method),
+            escape(&log.client_path),
+            class,
+            status,
+            log.elapsed_ms,
+            log.input_tokens,
+            log.output_tokens,
+        );
+    }
+    if logs.is_empty() {
+        html.push_str("<tr><td colspan=\"8\" class=\"muted\">No requests logged</td></tr>");
+    }
+    html.push_str("</table>");
+}
+
+fn page(title: &str, body: &str, refresh: bool) -> String {
+    let refresh = if refresh {
+        format!("<meta http-equiv=\"refresh\" content=\"{}\">", REFRESH_SECS)
+    } else {
+        String::new()
+    };
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">{}<title>{}</title><style>\
+         body{{font-family:system-ui,sans-serif;margin:24px;color:#303133}}\
+         table{{border-collapse:collapse;margin-bottom:16px}}\
+         th,td{{border:1px solid #dcdfe6;padding:4px 10px;text-align:left;font-size:13px}}\
+         th{{background:#f5f7fa}}.ok{{color:#67c23a}}.bad{{color:#f56c6c}}.muted{{color:#909399}}\
+         </style></head><body><h1>{}</h1>{}</body></html>",
+        refresh, title, title, body
+    )
+}
+
+fn html_response(status: StatusCode, html: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `3d 4h 5m`, `12m 3s`
+fn format_duration(secs: i64) -> String {
+    let secs = secs.max(0);
+    let (days, hours, minutes, seconds) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m {}s", minutes, seconds)
+    }
+}
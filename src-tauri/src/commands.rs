@@ -1,17 +1,22 @@
 use crate::config::get_data_dir;
 use crate::db::models::{
-    Provider, ProviderCreate, ProviderResponse, ProviderUpdate,
-    GatewaySettings, TimeoutSettings, TimeoutSettingsUpdate,
-    CliSettingsRow, CliSettingsResponse, CliSettingsUpdate,
-    RequestLogItem, RequestLogDetail, PaginatedLogs,
+    Provider, ProviderCreate, ProviderResponse, ProviderUpdate, ProviderBulkPatch,
+    ProviderApiKey, ProviderApiKeyResponse,
+    ProviderSchedule, ProviderScheduleCreate, ProviderScheduleUpdate, UaRule, UaRuleCreate, ModelAlias, ModelAliasCreate, ModelAliasUpdate, ModelPricing,
+    GatewaySettings, GatewaySettingsUpdate, MAX_REQUEST_BODY_MB_LIMIT, TimeoutSettings, TimeoutSettingsUpdate,
+    CliSettingsRow, CliSettingsResponse, CliSettingsUpdate, BudgetSettings, BudgetSettingsUpdate,
+    RequestLogItem, RequestLogDetail, RequestLogKey, ActiveRequest, BatchRequestItem, PaginatedLogs, LogContext, CostLogItem, PaginatedCostLogs,
     SystemLogItem, SystemLogListResponse,
-    DailyStats, ProviderStatsRow, ProviderStatsResponse,
-    McpConfig, McpCliFlag, McpResponse, McpCreate, McpUpdate,
+    DailyStats, ProviderStatsRow, ProviderStatsResponse, ModelUsageStats, UncoveredModel, ProviderErrorBreakdown, HourlyStats, PurgeResult,
+    ProviderExport, ImportProviderResult, ApplyEnvResult,
+    McpConfig, McpCliFlag, McpResponse, McpCreate, McpUpdate, McpValidationResult, McpDiff, McpDiffStatus,
     PromptPreset, PromptCliFlag, PromptResponse, PromptCreate, PromptUpdate,
     WebdavSettings, WebdavSettingsUpdate, WebdavBackup,
-    ProjectInfo, SessionInfo, PaginatedProjects, PaginatedSessions, SessionMessage,
-    SystemStatus,
+    ProjectInfo, SessionInfo, PaginatedProjects, PaginatedSessions, SessionMessage, SessionMessagesAdded,
+    SessionSearchHit, PaginatedSearchResults, CompressionStats,
+    SystemStatus, ReplayResult, BenchmarkResult, GatewayDiagnostics, DatabaseIntegrityResult, ProviderHealthSummary, ProviderGeoInfo,
 };
+use crate::services::cli_sync::{CliSyncState, CliSyncStatus, CLI_TYPES};
 use crate::LogDb;
 use sqlx::SqlitePool;
 use tauri::State;
@@ -23,41 +28,56 @@ pub async fn get_providers(
     db: State<'_, SqlitePool>,
     cli_type: Option<String>,
 ) -> Result<Vec<ProviderResponse>> {
+    load_providers(db.inner(), cli_type).await
+}
+
+/// Providers with their model maps; shared by get_providers and `GET /api/providers`
+pub async fn load_providers(db: &SqlitePool, cli_type: Option<String>) -> Result<Vec<ProviderResponse>> {
     let providers = if let Some(ct) = cli_type {
         sqlx::query_as::<_, Provider>(
             "SELECT * FROM providers WHERE cli_type = ? ORDER BY sort_order, id",
         )
         .bind(&ct)
-        .fetch_all(db.inner())
+        .fetch_all(db)
         .await
     } else {
         sqlx::query_as::<_, Provider>("SELECT * FROM providers ORDER BY sort_order, id")
-            .fetch_all(db.inner())
+            .fetch_all(db)
             .await
     };
 
     let providers = providers.map_err(|e| e.to_string())?;
     let mut results = Vec::new();
 
+    let countries: std::collections::HashMap<i64, String> =
+        sqlx::query_as::<_, (i64, String)>("SELECT provider_id, country_code FROM provider_geo_cache WHERE country_code IS NOT NULL")
+            .fetch_all(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+
     for provider in providers {
         let mut response = ProviderResponse::from(provider.clone());
+        response.country_code = countries.get(&provider.id).cloned();
 
         // Load model maps
-        let maps: Vec<(i64, String, String, i64)> = sqlx::query_as(
-            "SELECT id, source_model, target_model, enabled FROM provider_model_map WHERE provider_id = ? ORDER BY id",
+        let maps: Vec<(i64, String, String, i64, i64)> = sqlx::query_as(
+            "SELECT id, source_model, target_model, enabled, sort_order FROM provider_model_map WHERE provider_id = ? ORDER BY sort_order, id",
         )
         .bind(provider.id)
-        .fetch_all(db.inner())
+        .fetch_all(db)
         .await
         .map_err(|e| e.to_string())?;
 
         response.model_maps = maps
             .into_iter()
-            .map(|(id, source_model, target_model, enabled)| crate::db::models::ModelMapResponse {
+            .map(|(id, source_model, target_model, enabled, sort_order)| crate::db::models::ModelMapResponse {
                 id,
                 source_model,
                 target_model,
                 enabled: enabled != 0,
+                sort_order,
             })
             .collect();
 
@@ -69,31 +89,42 @@ pub async fn get_providers(
 
 #[tauri::command]
 pub async fn get_provider(db: State<'_, SqlitePool>, id: i64) -> Result<ProviderResponse> {
+    load_provider(db.inner(), id).await
+}
+
+pub async fn load_provider(db: &SqlitePool, id: i64) -> Result<ProviderResponse> {
     let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
         .bind(id)
-        .fetch_optional(db.inner())
+        .fetch_optional(db)
         .await
         .map_err(|e| e.to_string())?
         .ok_or_else(|| "Provider not found".to_string())?;
 
     let mut response = ProviderResponse::from(provider);
+    response.country_code = sqlx::query_scalar("SELECT country_code FROM provider_geo_cache WHERE provider_id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .flatten();
 
     // Load model maps
-    let maps: Vec<(i64, String, String, i64)> = sqlx::query_as(
-        "SELECT id, source_model, target_model, enabled FROM provider_model_map WHERE provider_id = ? ORDER BY id",
+    let maps: Vec<(i64, String, String, i64, i64)> = sqlx::query_as(
+        "SELECT id, source_model, target_model, enabled, sort_order FROM provider_model_map WHERE provider_id = ? ORDER BY sort_order, id",
     )
     .bind(id)
-    .fetch_all(db.inner())
+    .fetch_all(db)
     .await
     .map_err(|e| e.to_string())?;
 
     response.model_maps = maps
         .into_iter()
-        .map(|(id, source_model, target_model, enabled)| crate::db::models::ModelMapResponse {
+        .map(|(id, source_model, target_model, enabled, sort_order)| crate::db::models::ModelMapResponse {
             id,
             source_model,
             target_model,
             enabled: enabled != 0,
+            sort_order,
         })
         .collect();
 
@@ -104,16 +135,46 @@ pub async fn get_provider(db: State<'_, SqlitePool>, id: i64) -> Result<Provider
 pub async fn create_provider(
     db: State<'_, SqlitePool>,
     log_db: State<'_, LogDb>,
+    ua_patterns: State<'_, crate::UaPatterns>,
+    input: ProviderCreate,
+) -> Result<ProviderResponse> {
+    insert_provider(db.inner(), &log_db.0, &ua_patterns.0, input).await
+}
+
+/// Validate and insert a provider; shared by create_provider and `POST /api/providers`
+pub async fn insert_provider(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    ua_patterns: &crate::services::proxy::UaPatternCache,
     input: ProviderCreate,
 ) -> Result<ProviderResponse> {
     let now = chrono::Utc::now().timestamp();
     let cli_type = input.cli_type.unwrap_or_else(|| "claude_code".to_string());
     let provider_name = input.name.clone();
+    let proxy_url = check_proxy_url(input.proxy_url.as_deref())?;
+    let user_agent_pattern = check_user_agent_pattern(input.user_agent_pattern.as_deref())?;
+    let cli_type_override = check_cli_type_override(input.cli_type_override.as_deref())?;
+    let custom_headers = check_custom_headers(input.custom_headers.as_ref())?;
+    let extra_query_params = check_extra_query_params(input.extra_query_params.as_ref())?;
+    let path_rewrite_rules = check_path_rewrite_rules(input.path_rewrite_rules.as_deref())?;
+    let auth_scheme = check_auth_scheme(input.auth_scheme.as_deref())?;
+    let flavor = check_flavor(input.flavor.as_deref())?;
+    let wire_api = check_wire_api(input.wire_api.as_deref())?;
+    let protocol = check_protocol(input.protocol.as_deref())?;
+    let max_tokens_limit = input.max_tokens_limit.filter(|v| *v > 0);
+    let strip_params = check_strip_params(input.strip_params.as_deref())?;
+    let inject_system_prompt = normalize_system_prompt(input.inject_system_prompt.as_deref());
+    let body_rewrite_rules = check_body_rewrite_rules(input.body_rewrite_rules.as_deref())?;
+    let drop_response_headers = check_drop_response_headers(input.drop_response_headers.as_deref())?;
+    let proxy_user_agent = check_proxy_user_agent(input.proxy_user_agent.as_deref())?;
+    let max_connections = input.max_connections.filter(|v| *v > 0);
+    let connection_timeout_secs = input.connection_timeout_secs.filter(|v| *v > 0);
+    check_model_maps(input.model_maps.as_deref())?;
 
     let result = sqlx::query(
         r#"
-        INSERT INTO providers (cli_type, name, base_url, api_key, enabled, failure_threshold, blacklist_minutes, consecutive_failures, sort_order, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?, 0, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM providers), ?, ?)
+        INSERT INTO providers (cli_type, name, base_url, api_key, enabled, failure_threshold, blacklist_minutes, consecutive_failures, sort_order, proxy_url, user_agent_pattern, cli_type_override, insecure_skip_tls_verify, custom_headers, extra_query_params, path_rewrite_rules, auth_scheme, flavor, wire_api, protocol, max_tokens_limit, strip_params, inject_system_prompt, inject_system_prompt_enabled, body_rewrite_rules, drop_response_headers, local_count_tokens, stream_include_usage, proxy_user_agent, max_connections, connection_timeout_secs, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, 0, (SELECT COALESCE(MAX(sort_order), 0) + 1 FROM providers), ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         "#,
     )
     .bind(&cli_type)
@@ -123,33 +184,84 @@ pub async fn create_provider(
     .bind(input.enabled.unwrap_or(true) as i64)
     .bind(input.failure_threshold.unwrap_or(3))
     .bind(input.blacklist_minutes.unwrap_or(10))
+    .bind(&proxy_url)
+    .bind(&user_agent_pattern)
+    .bind(&cli_type_override)
+    .bind(input.insecure_skip_tls_verify.unwrap_or(false) as i64)
+    .bind(&custom_headers)
+    .bind(&extra_query_params)
+    .bind(&path_rewrite_rules)
+    .bind(&auth_scheme)
+    .bind(&flavor)
+    .bind(&wire_api)
+    .bind(&protocol)
+    .bind(max_tokens_limit)
+    .bind(&strip_params)
+    .bind(&inject_system_prompt)
+    .bind(input.inject_system_prompt_enabled.unwrap_or(true) as i64)
+    .bind(&body_rewrite_rules)
+    .bind(&drop_response_headers)
+    .bind(input.local_count_tokens.unwrap_or(false) as i64)
+    .bind(input.stream_include_usage.unwrap_or(true) as i64)
+    .bind(&proxy_user_agent)
+    .bind(max_connections)
+    .bind(connection_timeout_secs)
     .bind(now)
     .bind(now)
-    .execute(db.inner())
+    .execute(db)
     .await
     .map_err(|e| e.to_string())?;
 
     let id = result.last_insert_rowid();
 
+    // 需要 provider id 作为钥匙串条目名，插入后再替换为引用
+    let use_keychain = keychain_enabled(db).await?;
+    match crate::services::secrets::protect_provider_key(id, &input.api_key, use_keychain) {
+        Ok(stored) if stored != input.api_key => {
+            sqlx::query("UPDATE providers SET api_key = ? WHERE id = ?")
+                .bind(&stored)
+                .bind(id)
+                .execute(db)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::warn!(provider = %provider_name, "API key kept in the database: {}", e);
+            let _ = crate::services::stats::record_system_log(
+                log_db,
+                "warn",
+                "keychain_store_failed",
+                &format!("API key of provider {} kept in the database: {}", provider_name, e),
+                Some(&provider_name),
+                None,
+            ).await;
+        }
+    }
+
     // Insert model maps if provided
     if let Some(model_maps) = input.model_maps {
-        for map in model_maps {
+        // 列表顺序即匹配顺序
+        for (sort_order, map) in model_maps.iter().enumerate() {
             sqlx::query(
-                "INSERT INTO provider_model_map (provider_id, source_model, target_model, enabled) VALUES (?, ?, ?, ?)",
+                "INSERT INTO provider_model_map (provider_id, source_model, target_model, enabled, sort_order) VALUES (?, ?, ?, ?, ?)",
             )
             .bind(id)
             .bind(&map.source_model)
             .bind(&map.target_model)
             .bind(map.enabled as i64)
-            .execute(db.inner())
+            .bind(sort_order as i64)
+            .execute(db)
             .await
             .map_err(|e| e.to_string())?;
         }
     }
 
+    refresh_ua_patterns(db, ua_patterns).await;
+
     // Log system event
     let _ = crate::services::stats::record_system_log(
-        &log_db.0,
+        log_db,
         "info",
         "provider_created",
         &format!("Provider {} created", provider_name),
@@ -157,13 +269,25 @@ pub async fn create_provider(
         None,
     ).await;
 
-    get_provider(db, id).await
+    load_provider(db, id).await
 }
 
 #[tauri::command]
 pub async fn update_provider(
     db: State<'_, SqlitePool>,
     log_db: State<'_, LogDb>,
+    ua_patterns: State<'_, crate::UaPatterns>,
+    id: i64,
+    input: ProviderUpdate,
+) -> Result<ProviderResponse> {
+    apply_provider_update(db.inner(), &log_db.0, &ua_patterns.0, id, input).await
+}
+
+/// Validate and apply a partial provider update; shared by update_provider and `PUT /api/providers/:id`
+pub async fn apply_provider_update(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    ua_patterns: &crate::services::proxy::UaPatternCache,
     id: i64,
     input: ProviderUpdate,
 ) -> Result<ProviderResponse> {
@@ -174,7 +298,7 @@ pub async fn update_provider(
         "SELECT name FROM providers WHERE id = ?",
     )
     .bind(id)
-    .fetch_optional(db.inner())
+    .fetch_optional(db)
     .await
     .map_err(|e| e.to_string())?;
 
@@ -182,6 +306,83 @@ pub async fn update_provider(
 
     // Check if model maps will be updated (before moving)
     let has_model_maps_update = input.model_maps.is_some();
+    check_model_maps(input.model_maps.as_deref())?;
+
+    // Validate proxy URL before touching the database (empty string clears it)
+    let proxy_url = match input.proxy_url {
+        Some(ref url) => Some(check_proxy_url(Some(url))?),
+        None => None,
+    };
+    let user_agent_pattern = match input.user_agent_pattern {
+        Some(ref pattern) => Some(check_user_agent_pattern(Some(pattern))?),
+        None => None,
+    };
+    let cli_type_override = match input.cli_type_override {
+        Some(ref cli_type) => Some(check_cli_type_override(Some(cli_type))?),
+        None => None,
+    };
+    let custom_headers = match input.custom_headers {
+        Some(ref headers) => Some(check_custom_headers(Some(headers))?),
+        None => None,
+    };
+    let extra_query_params = match input.extra_query_params {
+        Some(ref params) => Some(check_extra_query_params(Some(params))?),
+        None => None,
+    };
+    let path_rewrite_rules = match input.path_rewrite_rules {
+        Some(ref rules) => Some(check_path_rewrite_rules(Some(rules))?),
+        None => None,
+    };
+    let auth_scheme = match input.auth_scheme {
+        Some(ref scheme) => Some(check_auth_scheme(Some(scheme))?),
+        None => None,
+    };
+    let flavor = match input.flavor {
+        Some(ref flavor) => Some(check_flavor(Some(flavor))?),
+        None => None,
+    };
+    let wire_api = match input.wire_api {
+        Some(ref wire_api) => Some(check_wire_api(Some(wire_api))?),
+        None => None,
+    };
+    let protocol = match input.protocol {
+        Some(ref protocol) => Some(check_protocol(Some(protocol))?),
+        None => None,
+    };
+    // 0 或负数表示取消限制
+    let max_tokens_limit = input.max_tokens_limit.map(|v| (v > 0).then_some(v));
+    let strip_params = match input.strip_params {
+        Some(ref params) => Some(check_strip_params(Some(params))?),
+        None => None,
+    };
+    let inject_system_prompt = input
+        .inject_system_prompt
+        .as_deref()
+        .map(|prompt| normalize_system_prompt(Some(prompt)));
+    let body_rewrite_rules = match input.body_rewrite_rules {
+        Some(ref rules) => Some(check_body_rewrite_rules(Some(rules))?),
+        None => None,
+    };
+    let drop_response_headers = match input.drop_response_headers {
+        Some(ref headers) => Some(check_drop_response_headers(Some(headers))?),
+        None => None,
+    };
+    let proxy_user_agent = match input.proxy_user_agent {
+        Some(ref user_agent) => Some(check_proxy_user_agent(Some(user_agent))?),
+        None => None,
+    };
+    // 0 或负数恢复默认值
+    let max_connections = input.max_connections.map(|v| (v > 0).then_some(v));
+    let connection_timeout_secs = input.connection_timeout_secs.map(|v| (v > 0).then_some(v));
+
+    // 新 Key 先写入钥匙串，数据库只保存引用
+    let api_key = match input.api_key {
+        Some(ref key) => {
+            let use_keychain = keychain_enabled(db).await?;
+            Some(crate::services::secrets::protect_provider_key(id, key, use_keychain)?)
+        }
+        None => None,
+    };
 
     // Build dynamic update query
     let mut updates = vec!["updated_at = ?".to_string()];
@@ -211,6 +412,94 @@ pub async fn update_provider(
         updates.push("blacklist_minutes = ?".to_string());
         has_updates = true;
     }
+    if proxy_url.is_some() {
+        updates.push("proxy_url = ?".to_string());
+        has_updates = true;
+    }
+    if user_agent_pattern.is_some() {
+        updates.push("user_agent_pattern = ?".to_string());
+        has_updates = true;
+    }
+    if cli_type_override.is_some() {
+        updates.push("cli_type_override = ?".to_string());
+        has_updates = true;
+    }
+    if input.insecure_skip_tls_verify.is_some() {
+        updates.push("insecure_skip_tls_verify = ?".to_string());
+        has_updates = true;
+    }
+    if custom_headers.is_some() {
+        updates.push("custom_headers = ?".to_string());
+        has_updates = true;
+    }
+    if extra_query_params.is_some() {
+        updates.push("extra_query_params = ?".to_string());
+        has_updates = true;
+    }
+    if path_rewrite_rules.is_some() {
+        updates.push("path_rewrite_rules = ?".to_string());
+        has_updates = true;
+    }
+    if auth_scheme.is_some() {
+        updates.push("auth_scheme = ?".to_string());
+        has_updates = true;
+    }
+    if flavor.is_some() {
+        updates.push("flavor = ?".to_string());
+        has_updates = true;
+    }
+    if wire_api.is_some() {
+        updates.push("wire_api = ?".to_string());
+        has_updates = true;
+    }
+    if protocol.is_some() {
+        updates.push("protocol = ?".to_string());
+        has_updates = true;
+    }
+    if max_tokens_limit.is_some() {
+        updates.push("max_tokens_limit = ?".to_string());
+        has_updates = true;
+    }
+    if strip_params.is_some() {
+        updates.push("strip_params = ?".to_string());
+        has_updates = true;
+    }
+    if inject_system_prompt.is_some() {
+        updates.push("inject_system_prompt = ?".to_string());
+        has_updates = true;
+    }
+    if input.inject_system_prompt_enabled.is_some() {
+        updates.push("inject_system_prompt_enabled = ?".to_string());
+        has_updates = true;
+    }
+    if body_rewrite_rules.is_some() {
+        updates.push("body_rewrite_rules = ?".to_string());
+        has_updates = true;
+    }
+    if drop_response_headers.is_some() {
+        updates.push("drop_response_headers = ?".to_string());
+        has_updates = true;
+    }
+    if input.local_count_tokens.is_some() {
+        updates.push("local_count_tokens = ?".to_string());
+        has_updates = true;
+    }
+    if input.stream_include_usage.is_some() {
+        updates.push("stream_include_usage = ?".to_string());
+        has_updates = true;
+    }
+    if proxy_user_agent.is_some() {
+        updates.push("proxy_user_agent = ?".to_string());
+        has_updates = true;
+    }
+    if max_connections.is_some() {
+        updates.push("max_connections = ?".to_string());
+        has_updates = true;
+    }
+    if connection_timeout_secs.is_some() {
+        updates.push("connection_timeout_secs = ?".to_string());
+        has_updates = true;
+    }
 
     if has_updates {
         let query = format!("UPDATE providers SET {} WHERE id = ?", updates.join(", "));
@@ -222,7 +511,7 @@ pub async fn update_provider(
         if let Some(ref base_url) = input.base_url {
             q = q.bind(base_url);
         }
-        if let Some(ref api_key) = input.api_key {
+        if let Some(ref api_key) = api_key {
             q = q.bind(api_key);
         }
         if let Some(enabled) = input.enabled {
@@ -234,9 +523,75 @@ pub async fn update_provider(
         if let Some(blacklist_minutes) = input.blacklist_minutes {
             q = q.bind(blacklist_minutes);
         }
+        if let Some(ref proxy_url) = proxy_url {
+            q = q.bind(proxy_url);
+        }
+        if let Some(ref user_agent_pattern) = user_agent_pattern {
+            q = q.bind(user_agent_pattern);
+        }
+        if let Some(ref cli_type_override) = cli_type_override {
+            q = q.bind(cli_type_override);
+        }
+        if let Some(insecure_skip_tls_verify) = input.insecure_skip_tls_verify {
+            q = q.bind(insecure_skip_tls_verify as i64);
+        }
+        if let Some(ref custom_headers) = custom_headers {
+            q = q.bind(custom_headers);
+        }
+        if let Some(ref extra_query_params) = extra_query_params {
+            q = q.bind(extra_query_params);
+        }
+        if let Some(ref path_rewrite_rules) = path_rewrite_rules {
+            q = q.bind(path_rewrite_rules);
+        }
+        if let Some(ref auth_scheme) = auth_scheme {
+            q = q.bind(auth_scheme);
+        }
+        if let Some(ref flavor) = flavor {
+            q = q.bind(flavor);
+        }
+        if let Some(ref wire_api) = wire_api {
+            q = q.bind(wire_api);
+        }
+        if let Some(ref protocol) = protocol {
+            q = q.bind(protocol);
+        }
+        if let Some(max_tokens_limit) = max_tokens_limit {
+            q = q.bind(max_tokens_limit);
+        }
+        if let Some(ref strip_params) = strip_params {
+            q = q.bind(strip_params);
+        }
+        if let Some(ref inject_system_prompt) = inject_system_prompt {
+            q = q.bind(inject_system_prompt);
+        }
+        if let Some(inject_system_prompt_enabled) = input.inject_system_prompt_enabled {
+            q = q.bind(inject_system_prompt_enabled as i64);
+        }
+        if let Some(ref body_rewrite_rules) = body_rewrite_rules {
+            q = q.bind(body_rewrite_rules);
+        }
+        if let Some(ref drop_response_headers) = drop_response_headers {
+            q = q.bind(drop_response_headers);
+        }
+        if let Some(local_count_tokens) = input.local_count_tokens {
+            q = q.bind(local_count_tokens as i64);
+        }
+        if let Some(stream_include_usage) = input.stream_include_usage {
+            q = q.bind(stream_include_usage as i64);
+        }
+        if let Some(ref proxy_user_agent) = proxy_user_agent {
+            q = q.bind(proxy_user_agent);
+        }
+        if let Some(max_connections) = max_connections {
+            q = q.bind(max_connections);
+        }
+        if let Some(connection_timeout_secs) = connection_timeout_secs {
+            q = q.bind(connection_timeout_secs);
+        }
 
         q.bind(id)
-            .execute(db.inner())
+            .execute(db)
             .await
             .map_err(|e| e.to_string())?;
     }
@@ -246,29 +601,35 @@ pub async fn update_provider(
         // Delete existing maps
         sqlx::query("DELETE FROM provider_model_map WHERE provider_id = ?")
             .bind(id)
-            .execute(db.inner())
+            .execute(db)
             .await
             .map_err(|e| e.to_string())?;
 
         // Insert new maps
-        for map in model_maps {
+        // 列表顺序即匹配顺序
+        for (sort_order, map) in model_maps.iter().enumerate() {
             sqlx::query(
-                "INSERT INTO provider_model_map (provider_id, source_model, target_model, enabled) VALUES (?, ?, ?, ?)",
+                "INSERT INTO provider_model_map (provider_id, source_model, target_model, enabled, sort_order) VALUES (?, ?, ?, ?, ?)",
             )
             .bind(id)
             .bind(&map.source_model)
             .bind(&map.target_model)
             .bind(map.enabled as i64)
-            .execute(db.inner())
+            .bind(sort_order as i64)
+            .execute(db)
             .await
             .map_err(|e| e.to_string())?;
         }
     }
 
+    if has_updates {
+        refresh_ua_patterns(db, ua_patterns).await;
+    }
+
     // Log system event (only if there were actual updates)
     if has_updates || has_model_maps_update {
         let _ = crate::services::stats::record_system_log(
-            &log_db.0,
+            log_db,
             "info",
             "provider_updated",
             &format!("Provider {} updated", provider_name),
@@ -277,1642 +638,5878 @@ pub async fn update_provider(
         ).await;
     }
 
-    get_provider(db, id).await
+    load_provider(db, id).await
 }
 
+/// Export providers (with model maps) as portable JSON; masked exports leave `api_key` empty
 #[tauri::command]
-pub async fn delete_provider(
+pub async fn export_providers(
+    db: State<'_, SqlitePool>,
+    cli_type: Option<String>,
+    mask_api_keys: Option<bool>,
+) -> Result<String> {
+    let mask_api_keys = mask_api_keys.unwrap_or(true);
+    let providers = get_providers(db, cli_type)
+        .await?
+        .into_iter()
+        .map(|p| {
+            let mut input = ProviderCreate::from(p);
+            if mask_api_keys {
+                input.api_key.clear();
+            } else {
+                input.api_key = crate::services::secrets::resolve_api_key(&input.api_key)?;
+            }
+            Ok(input)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    serde_json::to_string(&ProviderExport { version: 1, providers }).map_err(|e| e.to_string())
+}
+
+/// Import providers exported by `export_providers` (or a bare JSON array of them).
+/// Every entry is validated before anything is written; an invalid entry aborts the whole import.
+/// Existing providers with the same cli_type + name are overwritten or skipped per `overwrite_existing`.
+#[tauri::command]
+pub async fn import_providers(
     db: State<'_, SqlitePool>,
     log_db: State<'_, LogDb>,
-    id: i64,
-) -> Result<()> {
-    // Get provider name before deletion
-    let provider_name: Option<(String,)> = sqlx::query_as(
-        "SELECT name FROM providers WHERE id = ?",
-    )
-    .bind(id)
-    .fetch_optional(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
+    ua_patterns: State<'_, crate::UaPatterns>,
+    json: String,
+    overwrite_existing: bool,
+) -> Result<ImportProviderResult> {
+    let value: serde_json::Value =
+        serde_json::from_str(&json).map_err(|e| format!("Invalid provider JSON: {}", e))?;
+    let entries = match value {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Object(mut obj) => match obj.remove("providers") {
+            Some(serde_json::Value::Array(items)) => items,
+            _ => return Err("Missing 'providers' array".to_string()),
+        },
+        _ => return Err("Provider JSON must be an object or an array".to_string()),
+    };
 
-    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
+    let mut inputs = Vec::new();
+    let mut errors = Vec::new();
+    for (i, entry) in entries.into_iter().enumerate() {
+        let label = entry
+            .get("name")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("#{}", i + 1));
+        let checked = serde_json::from_value::<ProviderCreate>(entry)
+            .map_err(|e| e.to_string())
+            .and_then(|input| check_provider_import(&input).map(|_| input));
+        match checked {
+            Ok(input) => inputs.push(input),
+            Err(e) => errors.push(format!("Provider {}: {}", label, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Ok(ImportProviderResult {
+            imported: 0,
+            skipped: (inputs.len() + errors.len()) as i64,
+            errors,
+        });
+    }
 
-    // Delete associated model maps first (cascade delete)
-    sqlx::query("DELETE FROM provider_model_map WHERE provider_id = ?")
-        .bind(id)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+    save_imported_providers(db, log_db, ua_patterns, inputs, overwrite_existing, errors).await
+}
 
-    // Then delete the provider
-    sqlx::query("DELETE FROM providers WHERE id = ?")
-        .bind(id)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+/// Create or overwrite validated import entries, appending failures to `errors`
+async fn save_imported_providers(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    ua_patterns: State<'_, crate::UaPatterns>,
+    inputs: Vec<ProviderCreate>,
+    overwrite_existing: bool,
+    mut errors: Vec<String>,
+) -> Result<ImportProviderResult> {
+    let mut imported = 0;
+    let mut skipped = errors.len() as i64;
+    for mut input in inputs {
+        let cli_type = input.cli_type.get_or_insert_with(|| "claude_code".to_string()).clone();
+        let name = input.name.clone();
+        let existing: Option<(i64,)> = sqlx::query_as("SELECT id FROM providers WHERE cli_type = ? AND name = ?")
+            .bind(&cli_type)
+            .bind(&name)
+            .fetch_optional(db.inner())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let result = match existing {
+            Some(_) if !overwrite_existing => {
+                skipped += 1;
+                continue;
+            }
+            Some((id,)) => {
+                update_provider(db.clone(), log_db.clone(), ua_patterns.clone(), id, provider_update_from_import(input)).await
+            }
+            None => create_provider(db.clone(), log_db.clone(), ua_patterns.clone(), input).await,
+        };
+        match result {
+            Ok(_) => imported += 1,
+            Err(e) => {
+                skipped += 1;
+                errors.push(format!("Provider {}: {}", name, e));
+            }
+        }
+    }
 
-    // Log system event
     let _ = crate::services::stats::record_system_log(
         &log_db.0,
         "info",
-        "provider_deleted",
-        &format!("Provider {} deleted", provider_name),
-        Some(&provider_name),
+        "providers_imported",
+        &format!("Imported {} providers ({} skipped)", imported, skipped),
+        None,
         None,
     ).await;
 
-    Ok(())
-}
-
-#[tauri::command]
-pub async fn reorder_providers(db: State<'_, SqlitePool>, ids: Vec<i64>) -> Result<()> {
-    for (idx, id) in ids.iter().enumerate() {
-        sqlx::query("UPDATE providers SET sort_order = ? WHERE id = ?")
-            .bind(idx as i64)
-            .bind(id)
-            .execute(db.inner())
-            .await
-            .map_err(|e| e.to_string())?;
-    }
-    Ok(())
+    Ok(ImportProviderResult { imported, skipped, errors })
 }
 
+/// Import providers from a LiteLLM config (`model_list`) or a simple
+/// `{"providers": [{"name", "base_url", "api_key"}]}` JSON/YAML file.
+/// Invalid entries are reported in `errors` while the rest are still imported.
 #[tauri::command]
-pub async fn reset_provider_failures(
+pub async fn import_providers_from_openai_config(
     db: State<'_, SqlitePool>,
     log_db: State<'_, LogDb>,
-    id: i64,
-) -> Result<()> {
-    // Get provider name for logging
-    let provider_name: Option<(String,)> = sqlx::query_as(
-        "SELECT name FROM providers WHERE id = ?",
-    )
-    .bind(id)
-    .fetch_optional(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
+    ua_patterns: State<'_, crate::UaPatterns>,
+    file_path: String,
+    overwrite: bool,
+    cli_type: Option<String>,
+) -> Result<ImportProviderResult> {
+    // LiteLLM 的服务商都是 OpenAI 兼容接口，默认导入到 Codex
+    let cli_type = cli_type.unwrap_or_else(|| "codex".to_string());
+    cli_type.parse::<crate::services::proxy::CliType>()?;
+
+    let path = match file_path.trim().strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().ok_or("Cannot find home directory")?.join(rest),
+        None => std::path::PathBuf::from(file_path.trim()),
+    };
+    let content = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    // YAML 是 JSON 的超集，两种格式都用 serde_yaml 解析
+    let value: serde_json::Value =
+        serde_yaml::from_str(&content).map_err(|e| format!("Invalid config file: {}", e))?;
+
+    let mut inputs: Vec<ProviderCreate> = Vec::new();
+    let mut errors = Vec::new();
+    for (label, entry) in openai_config_entries(&value)? {
+        let checked = entry
+            .and_then(|(name, base_url, api_key)| {
+                serde_json::from_value::<ProviderCreate>(serde_json::json!({
+                    "name": name,
+                    "base_url": base_url,
+                    "api_key": api_key,
+                    "cli_type": cli_type,
+                }))
+                .map_err(|e| e.to_string())
+            })
+            .and_then(|input| check_provider_import(&input).map(|_| input));
+        match checked {
+            // LiteLLM 同一 model_name 可配置多个部署做负载均衡，只导入第一个
+            Ok(input) if inputs.iter().any(|i| i.name == input.name) => {
+                errors.push(format!("Provider {}: duplicate name in file", label));
+            }
+            Ok(input) => inputs.push(input),
+            Err(e) => errors.push(format!("Provider {}: {}", label, e)),
+        }
+    }
 
-    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
+    save_imported_providers(db, log_db, ua_patterns, inputs, overwrite, errors).await
+}
 
-    sqlx::query("UPDATE providers SET consecutive_failures = 0, blacklisted_until = NULL WHERE id = ?")
-        .bind(id)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+/// (label, Ok((name, base_url, api_key))) for every provider entry of an OpenAI/LiteLLM config
+#[allow(clippy::type_complexity)]
+fn openai_config_entries(value: &serde_json::Value) -> Result<Vec<(String, Result<(String, String, String)>)>> {
+    let str_field = |v: &serde_json::Value, key: &str| {
+        v.get(key).and_then(|s| s.as_str()).map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+    };
 
-    // Log system event
-    let _ = crate::services::stats::record_system_log(
-        &log_db.0,
-        "info",
-        "provider_reset",
-        &format!("Provider {} status manually reset", provider_name),
-        Some(&provider_name),
-        None,
-    ).await;
+    if let Some(models) = value.get("model_list") {
+        let models = models.as_array().ok_or("'model_list' must be a list")?;
+        return Ok(models
+            .iter()
+            .enumerate()
+            .map(|(i, model)| {
+                let name = str_field(model, "model_name");
+                let label = name.clone().unwrap_or_else(|| format!("#{}", i + 1));
+                let params = model.get("litellm_params").cloned().unwrap_or_default();
+                let entry = match (name, str_field(&params, "api_base")) {
+                    (None, _) => Err("model_name is required".to_string()),
+                    (_, None) => Err("litellm_params.api_base is required".to_string()),
+                    (Some(name), Some(base_url)) => str_field(&params, "api_key")
+                        .map(|key| litellm_api_key(&key))
+                        .transpose()
+                        .map(|key| (name, base_url, key.unwrap_or_default())),
+                };
+                (label, entry)
+            })
+            .collect());
+    }
 
-    Ok(())
+    let providers = value
+        .get("providers")
+        .and_then(|p| p.as_array())
+        .ok_or("Expected a LiteLLM 'model_list' or a 'providers' list")?;
+    Ok(providers
+        .iter()
+        .enumerate()
+        .map(|(i, provider)| {
+            let name = str_field(provider, "name");
+            let label = name.clone().unwrap_or_else(|| format!("#{}", i + 1));
+            let entry = match (name, str_field(provider, "base_url")) {
+                (None, _) => Err("name is required".to_string()),
+                (_, None) => Err("base_url is required".to_string()),
+                (Some(name), Some(base_url)) => Ok((name, base_url, str_field(provider, "api_key").unwrap_or_default())),
+            };
+            (label, entry)
+        })
+        .collect())
 }
 
-// Settings commands
-#[tauri::command]
-pub async fn get_gateway_settings(db: State<'_, SqlitePool>) -> Result<GatewaySettings> {
-    sqlx::query_as::<_, GatewaySettings>("SELECT debug_log FROM gateway_settings WHERE id = 1")
-        .fetch_one(db.inner())
-        .await
-        .map_err(|e| e.to_string())
+/// Resolve LiteLLM's `os.environ/NAME` key references from the environment
+fn litellm_api_key(key: &str) -> Result<String> {
+    match key.strip_prefix("os.environ/") {
+        Some(var) => std::env::var(var).map_err(|_| format!("environment variable {} is not set", var)),
+        None => Ok(key.to_string()),
+    }
 }
 
-#[tauri::command]
-pub async fn update_gateway_settings(db: State<'_, SqlitePool>, debug_log: bool) -> Result<()> {
-    let now = chrono::Utc::now().timestamp();
-    sqlx::query("UPDATE gateway_settings SET debug_log = ?, updated_at = ? WHERE id = 1")
-        .bind(debug_log as i64)
-        .bind(now)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+/// Validate an imported provider with the same rules as create_provider
+fn check_provider_import(input: &ProviderCreate) -> Result<()> {
+    if input.name.trim().is_empty() {
+        return Err("name is required".to_string());
+    }
+    if input.base_url.trim().is_empty() {
+        return Err("base_url is required".to_string());
+    }
+    if let Some(ref cli_type) = input.cli_type {
+        cli_type.parse::<crate::services::proxy::CliType>()?;
+    }
+    check_proxy_url(input.proxy_url.as_deref())?;
+    check_user_agent_pattern(input.user_agent_pattern.as_deref())?;
+    check_cli_type_override(input.cli_type_override.as_deref())?;
+    check_custom_headers(input.custom_headers.as_ref())?;
+    check_extra_query_params(input.extra_query_params.as_ref())?;
+    check_path_rewrite_rules(input.path_rewrite_rules.as_deref())?;
+    check_auth_scheme(input.auth_scheme.as_deref())?;
+    check_flavor(input.flavor.as_deref())?;
+    check_wire_api(input.wire_api.as_deref())?;
+    check_protocol(input.protocol.as_deref())?;
+    check_strip_params(input.strip_params.as_deref())?;
+    check_body_rewrite_rules(input.body_rewrite_rules.as_deref())?;
+    check_drop_response_headers(input.drop_response_headers.as_deref())?;
+    check_proxy_user_agent(input.proxy_user_agent.as_deref())?;
     Ok(())
 }
 
-#[tauri::command]
-pub async fn get_timeout_settings(db: State<'_, SqlitePool>) -> Result<TimeoutSettings> {
-    sqlx::query_as::<_, TimeoutSettings>(
-        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout FROM timeout_settings WHERE id = 1",
-    )
-    .fetch_one(db.inner())
-    .await
-    .map_err(|e| e.to_string())
+/// Overwrite an existing provider from an import entry; an empty (masked) api_key keeps the current key
+fn provider_update_from_import(input: ProviderCreate) -> ProviderUpdate {
+    ProviderUpdate {
+        name: Some(input.name),
+        base_url: Some(input.base_url),
+        api_key: Some(input.api_key).filter(|k| !k.is_empty()),
+        enabled: input.enabled,
+        failure_threshold: input.failure_threshold,
+        blacklist_minutes: input.blacklist_minutes,
+        proxy_url: Some(input.proxy_url.unwrap_or_default()),
+        user_agent_pattern: Some(input.user_agent_pattern.unwrap_or_default()),
+        cli_type_override: Some(input.cli_type_override.unwrap_or_default()),
+        insecure_skip_tls_verify: input.insecure_skip_tls_verify,
+        custom_headers: Some(input.custom_headers.unwrap_or_default()),
+        extra_query_params: Some(input.extra_query_params.unwrap_or_default()),
+        path_rewrite_rules: Some(input.path_rewrite_rules.unwrap_or_default()),
+        auth_scheme: Some(input.auth_scheme.unwrap_or_default()),
+        flavor: Some(input.flavor.unwrap_or_default()),
+        wire_api: Some(input.wire_api.unwrap_or_default()),
+        protocol: Some(input.protocol.unwrap_or_default()),
+        max_tokens_limit: Some(input.max_tokens_limit.unwrap_or(0)),
+        strip_params: Some(input.strip_params.unwrap_or_default()),
+        inject_system_prompt: Some(input.inject_system_prompt.unwrap_or_default()),
+        inject_system_prompt_enabled: input.inject_system_prompt_enabled,
+        body_rewrite_rules: Some(input.body_rewrite_rules.unwrap_or_default()),
+        drop_response_headers: Some(input.drop_response_headers.unwrap_or_default()),
+        local_count_tokens: input.local_count_tokens,
+        stream_include_usage: input.stream_include_usage,
+        proxy_user_agent: Some(input.proxy_user_agent.unwrap_or_default()),
+        max_connections: Some(input.max_connections.unwrap_or(0)),
+        connection_timeout_secs: Some(input.connection_timeout_secs.unwrap_or(0)),
+        model_maps: Some(input.model_maps.unwrap_or_default()),
+    }
 }
 
-#[tauri::command]
-pub async fn update_timeout_settings(
-    db: State<'_, SqlitePool>,
-    input: TimeoutSettingsUpdate,
-) -> Result<()> {
-    let now = chrono::Utc::now().timestamp();
-    let current = get_timeout_settings(db.clone()).await?;
-
-    sqlx::query(
-        "UPDATE timeout_settings SET stream_first_byte_timeout = ?, stream_idle_timeout = ?, non_stream_timeout = ?, updated_at = ? WHERE id = 1",
-    )
-    .bind(input.stream_first_byte_timeout.unwrap_or(current.stream_first_byte_timeout))
-    .bind(input.stream_idle_timeout.unwrap_or(current.stream_idle_timeout))
-    .bind(input.non_stream_timeout.unwrap_or(current.non_stream_timeout))
-    .bind(now)
-    .execute(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
-    Ok(())
+/// Validate a User-Agent sent upstream; empty means keep the client's
+fn check_proxy_user_agent(user_agent: Option<&str>) -> Result<Option<String>> {
+    let user_agent = user_agent.map(str::trim).filter(|ua| !ua.is_empty());
+    if let Some(ua) = user_agent {
+        reqwest::header::HeaderValue::from_str(ua).map_err(|_| format!("Invalid User-Agent: '{}'", ua))?;
+    }
+    Ok(user_agent.map(|ua| ua.to_string()))
 }
 
-#[tauri::command]
-pub async fn get_cli_settings(db: State<'_, SqlitePool>, cli_type: String) -> Result<CliSettingsResponse> {
-    let row = sqlx::query_as::<_, CliSettingsRow>(
-        "SELECT cli_type, default_json_config, updated_at FROM cli_settings WHERE cli_type = ?",
-    )
-    .bind(&cli_type)
-    .fetch_optional(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
-
-    if let Some(row) = row {
-        // Check if CLI is enabled by reading config file
-        let enabled = check_cli_enabled(&cli_type);
-        Ok(CliSettingsResponse {
-            cli_type: row.cli_type,
-            enabled,
-            default_json_config: row.default_json_config.unwrap_or_default(),
-        })
-    } else {
-        Ok(CliSettingsResponse {
-            cli_type,
-            enabled: false,
-            default_json_config: String::new(),
-        })
+/// Normalize and validate a provider User-Agent regex; empty means no pattern
+fn check_user_agent_pattern(pattern: Option<&str>) -> Result<Option<String>> {
+    let pattern = pattern.map(|p| p.trim()).filter(|p| !p.is_empty());
+    if let Some(p) = pattern {
+        regex::Regex::new(p).map_err(|e| format!("Invalid User-Agent pattern '{}': {}", p, e))?;
     }
+    Ok(pattern.map(|p| p.to_string()))
 }
 
-#[tauri::command]
-pub async fn update_cli_settings(
-    db: State<'_, SqlitePool>,
-    cli_type: String,
-    input: CliSettingsUpdate,
-) -> Result<()> {
-    let now = chrono::Utc::now().timestamp();
-
-    // Validate and update database
-    if let Some(ref config) = input.default_json_config {
-        let config_trimmed = config.trim();
-
-        // Validate format if config is not empty
-        if !config_trimmed.is_empty() {
-            match cli_type.as_str() {
-                "claude_code" | "gemini" => {
-                    // Validate JSON format
-                    serde_json::from_str::<serde_json::Value>(config_trimmed)
-                        .map_err(|e| format!("JSON 格式错误: {}", e))?;
-                }
-                "codex" => {
-                    // Validate TOML format
-                    config_trimmed.parse::<toml_edit::DocumentMut>()
-                        .map_err(|e| format!("TOML 格式错误: {}", e))?;
-                }
-                _ => {}
-            }
-        }
-
-        sqlx::query(
-            "UPDATE cli_settings SET default_json_config = ?, updated_at = ? WHERE cli_type = ?",
-        )
-        .bind(config_trimmed)
-        .bind(now)
-        .bind(&cli_type)
-        .execute(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
+/// Reject model maps whose "re:" source is not a valid regex
+fn check_model_maps(maps: Option<&[crate::db::models::ModelMapInput]>) -> Result<()> {
+    for map in maps.unwrap_or_default() {
+        check_model_source(&map.source_model)?;
     }
+    Ok(())
+}
 
-    // Update CLI config file if enabled flag is provided
-    if let Some(enabled) = input.enabled {
-        // Get default_json_config from database
-        let row = sqlx::query_as::<_, CliSettingsRow>(
-            "SELECT cli_type, default_json_config, updated_at FROM cli_settings WHERE cli_type = ?",
-        )
-        .bind(&cli_type)
-        .fetch_optional(db.inner())
-        .await
-        .map_err(|e| e.to_string())?;
-
-        let default_config = row.and_then(|r| r.default_json_config).unwrap_or_default();
-        sync_cli_config(&cli_type, enabled, &default_config, db).await?;
+/// Validate one model map / alias source pattern (`re:` sources must compile)
+fn check_model_source(source_model: &str) -> Result<()> {
+    use crate::services::proxy::{compile_model_regex, MODEL_REGEX_PREFIX};
+    if let Some(pattern) = source_model.trim().strip_prefix(MODEL_REGEX_PREFIX) {
+        compile_model_regex(pattern)
+            .map_err(|e| format!("Invalid model map regex '{}': {}", source_model, e))?;
     }
-
     Ok(())
 }
 
-// Normalize text for comparison: trim, normalize whitespace, remove extra blank lines
-fn normalize_text(text: &str) -> String {
-    text.lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<&str>>()
-        .join("\n")
+/// Validate the CLI type forced by a User-Agent pattern; empty means the provider's own type
+fn check_cli_type_override(cli_type: Option<&str>) -> Result<Option<String>> {
+    let cli_type = cli_type.map(|c| c.trim()).filter(|c| !c.is_empty());
+    if let Some(c) = cli_type {
+        c.parse::<crate::services::proxy::CliType>()?;
+    }
+    Ok(cli_type.map(|c| c.to_string()))
 }
 
-// Check if MCP config exists in the CLI config file
-fn mcp_enabled_in_file(cli_type: &str, mcp_name: &str) -> bool {
-    let home = match dirs::home_dir() {
-        Some(h) => h,
-        None => return false,
-    };
-
-    match cli_type {
-        "claude_code" => {
-            let path = home.join(".claude.json");
-            if !path.exists() {
-                return false;
-            }
-            let content = match std::fs::read_to_string(&path) {
-                Ok(c) => c,
-                Err(_) => return false,
-            };
-            match serde_json::from_str::<serde_json::Value>(&content) {
-                Ok(config) => {
-                    config.get("mcpServers")
-                        .and_then(|v| v.as_object())
-                        .map(|servers| servers.contains_key(mcp_name))
-                        .unwrap_or(false)
-                }
-                Err(_) => false,
-            }
-        }
-        "gemini" => {
-            let path = home.join(".gemini").join("settings.json");
-            if !path.exists() {
-                return false;
-            }
-            let content = match std::fs::read_to_string(&path) {
-                Ok(c) => c,
-                Err(_) => return false,
-            };
-            match serde_json::from_str::<serde_json::Value>(&content) {
-                Ok(config) => {
-                    config.get("mcpServers")
-                        .and_then(|v| v.as_object())
-                        .map(|servers| servers.contains_key(mcp_name))
-                        .unwrap_or(false)
-                }
-                Err(_) => false,
-            }
-        }
-        "codex" => {
-            let path = home.join(".codex").join("config.toml");
-            if !path.exists() {
-                return false;
-            }
-            let content = match std::fs::read_to_string(&path) {
-                Ok(c) => c,
-                Err(_) => return false,
-            };
-            match content.parse::<toml_edit::DocumentMut>() {
-                Ok(doc) => {
-                    doc.get("mcp_servers")
-                        .and_then(|v| v.as_table())
-                        .map(|servers| servers.contains_key(mcp_name))
-                        .unwrap_or(false)
-                }
-                Err(_) => false,
-            }
-        }
-        _ => false,
+/// Validate the provider auth scheme; empty means the CLI default
+fn check_auth_scheme(auth_scheme: Option<&str>) -> Result<Option<String>> {
+    let auth_scheme = auth_scheme.map(|s| s.trim()).filter(|s| !s.is_empty());
+    if let Some(s) = auth_scheme {
+        s.parse::<crate::services::proxy::AuthScheme>()?;
     }
+    Ok(auth_scheme.map(|s| s.to_string()))
 }
 
-// Check if prompt content matches the file content
-fn prompt_enabled_in_file(cli_type: &str, prompt_content: &str) -> bool {
-    let home = match dirs::home_dir() {
-        Some(h) => h,
-        None => return false,
-    };
-
-    let prompt_path = match cli_type {
-        "claude_code" => home.join(".claude").join("CLAUDE.md"),
-        "codex" => home.join(".codex").join("AGENTS.md"),
-        "gemini" => home.join(".gemini").join("GEMINI.md"),
-        _ => return false,
-    };
-
-    if !prompt_path.exists() {
-        return false;
+/// Validate the provider API flavor; empty means plain OpenAI-compatible
+fn check_flavor(flavor: Option<&str>) -> Result<Option<String>> {
+    let flavor = flavor.map(|s| s.trim()).filter(|s| !s.is_empty());
+    if let Some(s) = flavor {
+        s.parse::<crate::services::proxy::ProviderFlavor>()?;
     }
+    Ok(flavor.map(|s| s.to_string()))
+}
 
-    let file_content = match std::fs::read_to_string(&prompt_path) {
-        Ok(c) => c,
-        Err(_) => return false,
-    };
-
-    // Normalize and compare
-    normalize_text(prompt_content) == normalize_text(&file_content)
+/// Validate the Codex wire API; empty means the Responses API
+fn check_wire_api(wire_api: Option<&str>) -> Result<Option<String>> {
+    let wire_api = wire_api.map(|s| s.trim()).filter(|s| !s.is_empty());
+    if let Some(s) = wire_api {
+        s.parse::<crate::services::proxy::WireApi>()?;
+    }
+    Ok(wire_api.map(|s| s.to_string()))
 }
 
-fn check_cli_enabled(cli_type: &str) -> bool {
-    match cli_type {
-        "claude_code" => check_claude_uses_gateway(),
-        "codex" => check_codex_uses_gateway(),
-        "gemini" => check_gemini_uses_gateway(),
-        _ => false,
+/// Validate the Claude Code / Gemini upstream protocol; empty means the native API
+fn check_protocol(protocol: Option<&str>) -> Result<Option<String>> {
+    let protocol = protocol.map(|s| s.trim()).filter(|s| !s.is_empty());
+    if let Some(s) = protocol {
+        s.parse::<crate::services::proxy::ProviderProtocol>()?;
     }
+    Ok(protocol.map(|s| s.to_string()))
 }
 
-fn check_claude_uses_gateway() -> bool {
-    let Some(home) = dirs::home_dir() else {
-        return false;
+/// Validate custom header names/values and serialize them; empty map clears the column
+fn check_custom_headers(
+    headers: Option<&std::collections::BTreeMap<String, String>>,
+) -> Result<Option<String>> {
+    let Some(headers) = headers.filter(|h| !h.is_empty()) else {
+        return Ok(None);
     };
-    let config_path = home.join(".claude").join("settings.json");
-
-    if !config_path.exists() {
-        return false;
+    for (name, value) in headers {
+        reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name: '{}'", name))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value for header '{}'", name))?;
     }
+    serde_json::to_string(headers).map(Some).map_err(|e| e.to_string())
+}
 
-    let content = match std::fs::read_to_string(&config_path) {
-        Ok(c) => c,
-        Err(_) => return false,
+/// Validate extra query parameters and serialize them; empty map clears the column
+fn check_extra_query_params(
+    params: Option<&std::collections::BTreeMap<String, String>>,
+) -> Result<Option<String>> {
+    let Some(params) = params.filter(|p| !p.is_empty()) else {
+        return Ok(None);
     };
-
-    let content_trimmed = content.trim();
-    if content_trimmed.is_empty() || content_trimmed == "{}" {
-        return false;
+    if params.keys().any(|k| k.trim().is_empty()) {
+        return Err("Query parameter name cannot be empty".to_string());
     }
+    serde_json::to_string(params).map(Some).map_err(|e| e.to_string())
+}
 
-    match serde_json::from_str::<serde_json::Value>(content_trimmed) {
-        Ok(data) => {
-            if let Some(env) = data.get("env") {
-                if let Some(base_url) = env.get("ANTHROPIC_BASE_URL").and_then(|v| v.as_str()) {
-                    return base_url.contains("127.0.0.1:7788") || base_url.contains("localhost:7788");
-                }
-            }
-            false
+/// Validate path rewrite rules and serialize them; empty list clears the column
+fn check_path_rewrite_rules(
+    rules: Option<&[crate::db::models::PathRewriteRule]>,
+) -> Result<Option<String>> {
+    let Some(rules) = rules.filter(|r| !r.is_empty()) else {
+        return Ok(None);
+    };
+    for rule in rules {
+        if !rule.from_prefix.starts_with('/') || !rule.to_prefix.starts_with('/') {
+            return Err(format!(
+                "Invalid path rewrite rule '{}' -> '{}': prefixes must start with '/'",
+                rule.from_prefix, rule.to_prefix
+            ));
         }
-        Err(_) => false,
     }
+    serde_json::to_string(rules).map(Some).map_err(|e| e.to_string())
 }
 
-fn check_codex_uses_gateway() -> bool {
-    let Some(home) = dirs::home_dir() else {
-        return false;
+/// Validate body rewrite rules and serialize them; empty list clears the column
+fn check_body_rewrite_rules(
+    rules: Option<&[crate::db::models::BodyRewriteRule]>,
+) -> Result<Option<String>> {
+    let Some(rules) = rules.filter(|r| !r.is_empty()) else {
+        return Ok(None);
     };
-    let config_path = home.join(".codex").join("config.toml");
-
-    if !config_path.exists() {
-        return false;
-    }
+    crate::services::proxy::validate_body_rewrite_rules(rules)?;
+    serde_json::to_string(rules).map(Some).map_err(|e| e.to_string())
+}
 
-    let content = match std::fs::read_to_string(&config_path) {
-        Ok(c) => c,
-        Err(_) => return false,
-    };
+/// Trim the injected system prompt; empty means no injection
+fn normalize_system_prompt(prompt: Option<&str>) -> Option<String> {
+    prompt.map(str::trim).filter(|p| !p.is_empty()).map(|p| p.to_string())
+}
 
-    if content.trim().is_empty() {
-        return false;
+/// Validate body params to strip (same paths as body_rewrite_rules); empty list clears the column
+fn check_strip_params(params: Option<&[String]>) -> Result<Option<String>> {
+    let params: Vec<String> = params
+        .unwrap_or_default()
+        .iter()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect();
+    if params.is_empty() {
+        return Ok(None);
+    }
+    for p in &params {
+        crate::services::proxy::validate_strip_param(p).map_err(|e| format!("Invalid strip param: {}", e))?;
     }
+    serde_json::to_string(&params).map(Some).map_err(|e| e.to_string())
+}
 
-    match content.parse::<toml_edit::DocumentMut>() {
-        Ok(doc) => {
-            // Check if model_provider is "ccg-gateway"
-            if let Some(provider) = doc.get("model_provider").and_then(|v| v.as_str()) {
-                if provider == "ccg-gateway" {
-                    return true;
-                }
-            }
-            false
+/// Validate response header names to drop (`name` or `prefix-*`); empty list clears the column
+fn check_drop_response_headers(headers: Option<&[String]>) -> Result<Option<String>> {
+    let headers: Vec<String> = headers
+        .unwrap_or_default()
+        .iter()
+        .map(|h| h.trim().to_ascii_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect();
+    if headers.is_empty() {
+        return Ok(None);
+    }
+    for h in &headers {
+        let name = h.strip_suffix('*').unwrap_or(h);
+        if name.is_empty() || reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(format!("Invalid response header name: '{}'", h));
         }
-        Err(_) => false,
     }
+    serde_json::to_string(&headers).map(Some).map_err(|e| e.to_string())
 }
 
-fn check_gemini_uses_gateway() -> bool {
-    let Some(home) = dirs::home_dir() else {
-        return false;
-    };
-    let env_path = home.join(".gemini").join(".env");
+/// Rebuild the proxy's User-Agent pattern cache after providers change
+async fn refresh_ua_patterns(db: &SqlitePool, ua_patterns: &crate::services::proxy::UaPatternCache) {
+    if let Err(e) = crate::services::proxy::reload_ua_patterns(db, ua_patterns).await {
+        tracing::warn!("Failed to reload User-Agent patterns: {}", e);
+    }
+}
 
-    if !env_path.exists() {
-        return false;
+/// Normalize and validate a provider proxy URL; empty means direct connection
+fn check_proxy_url(proxy_url: Option<&str>) -> Result<Option<String>> {
+    let proxy_url = crate::services::http_client::normalize_proxy_url(proxy_url);
+    if let Some(ref url) = proxy_url {
+        crate::services::http_client::validate_proxy_url(url)?;
     }
+    Ok(proxy_url)
+}
 
-    let content = match std::fs::read_to_string(&env_path) {
-        Ok(c) => c,
-        Err(_) => return false,
-    };
+#[tauri::command]
+pub async fn delete_provider(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    ua_patterns: State<'_, crate::UaPatterns>,
+    schedules: State<'_, crate::ProviderSchedules>,
+    id: i64,
+    keep_logs: Option<bool>,
+) -> Result<()> {
+    remove_provider(db.inner(), &log_db.0, &ua_patterns.0, &schedules.0, id, keep_logs).await
+}
 
-    // Check if .env contains GOOGLE_GEMINI_BASE_URL pointing to gateway
-    for line in content.lines() {
-        if line.starts_with("GOOGLE_GEMINI_BASE_URL=") {
-            let url = line.split('=').nth(1).unwrap_or("");
-            return url.contains("127.0.0.1:7788") || url.contains("localhost:7788");
+pub async fn remove_provider(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    ua_patterns: &crate::services::proxy::UaPatternCache,
+    schedules: &crate::services::routing::ScheduleCache,
+    id: i64,
+    keep_logs: Option<bool>,
+) -> Result<()> {
+    // Get provider name before deletion
+    let provider: Option<(String, String, String)> = sqlx::query_as(
+        "SELECT name, cli_type, api_key FROM providers WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let (provider_name, cli_type, api_key) =
+        provider.unwrap_or_else(|| (format!("Provider#{}", id), String::new(), String::new()));
+    let rotation_keys: Vec<i64> = sqlx::query_scalar(
+        "SELECT id FROM provider_api_keys WHERE provider_id = ? AND api_key LIKE 'keychain:%'",
+    )
+    .bind(id)
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    // Model maps, API keys and schedules go with it (ON DELETE CASCADE)
+    sqlx::query("DELETE FROM providers WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if api_key == crate::services::secrets::provider_key_ref(id) {
+        if let Err(e) = crate::services::secrets::delete_secret(&crate::services::secrets::provider_secret_id(id)) {
+            tracing::warn!(provider = %provider_name, "Failed to remove API key from keychain: {}", e);
         }
     }
-    false
-}
+    delete_rotation_secrets(&rotation_keys);
 
-// Get the config file path for MCP/prompts sync (different for Codex)
-fn get_mcp_config_path(cli_type: &str) -> Option<std::path::PathBuf> {
-    let home = dirs::home_dir()?;
-    match cli_type {
-        "claude_code" => Some(home.join(".claude.json")),  // Claude Code MCP goes to ~/.claude.json
-        "codex" => Some(home.join(".codex").join("config.toml")),  // Codex MCP goes to config.toml
-        "gemini" => Some(home.join(".gemini").join("settings.json")),
-        _ => None,
+    refresh_ua_patterns(db, ua_patterns).await;
+    refresh_schedules(db, schedules).await;
+
+    // Log system event
+    let _ = crate::services::stats::record_system_log(
+        log_db,
+        "info",
+        "provider_deleted",
+        &format!("Provider {} deleted", provider_name),
+        Some(&provider_name),
+        None,
+    ).await;
+
+    // 默认匿名化该服务商的日志，除非显式保留。其它 CLI 下可能有同名服务商：
+    // 请求日志和用量按 cli_type 限定；系统日志无法区分，仍有同名服务商时保留
+    if !keep_logs.unwrap_or(false) {
+        let name_in_use: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM providers WHERE name = ?)")
+            .bind(&provider_name)
+            .fetch_one(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        purge_provider_logs(log_db, &provider_name, Some(&cli_type), !name_in_use).await?;
     }
+
+    Ok(())
 }
 
-async fn sync_cli_config(cli_type: &str, enabled: bool, default_config: &str, db: State<'_, SqlitePool>) -> Result<()> {
-    match cli_type {
-        "claude_code" => sync_claude_code_config(enabled, default_config, db).await,
-        "codex" => sync_codex_config(enabled, default_config, db).await,
-        "gemini" => sync_gemini_config(enabled, default_config, db).await,
-        _ => Err("Invalid CLI type".to_string()),
+#[tauri::command]
+pub async fn purge_provider_from_logs(
+    log_db: State<'_, LogDb>,
+    provider_name: String,
+) -> Result<PurgeResult> {
+    purge_provider_logs(&log_db.0, &provider_name, None, true).await
+}
+
+async fn purge_provider_logs(
+    log_db: &SqlitePool,
+    provider_name: &str,
+    cli_type: Option<&str>,
+    include_system_logs: bool,
+) -> Result<PurgeResult> {
+    if provider_name == crate::services::stats::DELETED_PROVIDER_NAME {
+        return Err("Provider logs are already anonymized".to_string());
     }
+    crate::services::stats::purge_provider_logs(log_db, provider_name, cli_type, include_system_logs)
+        .await
+        .map_err(|e| e.to_string())
 }
 
-fn get_backup_path(original_path: &std::path::Path) -> std::path::PathBuf {
-    let file_name = original_path.file_name().unwrap().to_str().unwrap();
-    original_path.parent().unwrap().join(format!("{}.ccg-backup", file_name))
+#[tauri::command]
+pub async fn reorder_providers(db: State<'_, SqlitePool>, ids: Vec<i64>) -> Result<()> {
+    save_provider_order(db.inner(), &ids).await
 }
 
-fn backup_file(path: &std::path::Path) -> Result<()> {
-    if !path.exists() {
-        return Ok(());
+pub async fn save_provider_order(db: &SqlitePool, ids: &[i64]) -> Result<()> {
+    for (idx, id) in ids.iter().enumerate() {
+        sqlx::query("UPDATE providers SET sort_order = ? WHERE id = ?")
+            .bind(idx as i64)
+            .bind(id)
+            .execute(db)
+            .await
+            .map_err(|e| e.to_string())?;
     }
-    let backup_path = get_backup_path(path);
-    std::fs::copy(path, &backup_path).map_err(|e| {
-        tracing::error!("Failed to backup {}: {}", path.display(), e);
-        e.to_string()
-    })?;
     Ok(())
 }
 
-fn restore_backup(path: &std::path::Path) -> Result<bool> {
-    let backup_path = get_backup_path(path);
-    if !backup_path.exists() {
-        return Ok(false);
+/// Set the match order of a provider's model maps (ids in the new order)
+#[tauri::command]
+pub async fn reorder_model_maps(db: State<'_, SqlitePool>, provider_id: i64, ids: Vec<i64>) -> Result<()> {
+    for (idx, id) in ids.iter().enumerate() {
+        sqlx::query("UPDATE provider_model_map SET sort_order = ? WHERE id = ? AND provider_id = ?")
+            .bind(idx as i64)
+            .bind(id)
+            .bind(provider_id)
+            .execute(db.inner())
+            .await
+            .map_err(|e| e.to_string())?;
     }
-    std::fs::copy(&backup_path, path).map_err(|e| {
-        tracing::error!("Failed to restore backup from {}: {}", backup_path.display(), e);
-        e.to_string()
-    })?;
-    std::fs::remove_file(&backup_path).map_err(|e| {
-        tracing::warn!("Failed to remove backup file {}: {}", backup_path.display(), e);
-        e.to_string()
-    })?;
-    Ok(true)
+    Ok(())
 }
 
-fn has_backup(path: &std::path::Path) -> bool {
-    get_backup_path(path).exists()
+// Model lists fetched from a provider are reused for a day
+const MODEL_SUGGESTIONS_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// Model IDs of a models list response: `data[].id` (OpenAI / Anthropic)
+/// or `models[].name` without the `models/` prefix (Gemini)
+fn parse_model_list(body: &[u8]) -> Option<Vec<String>> {
+    let json: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let ids: Vec<String> = if let Some(data) = json.get("data").and_then(|d| d.as_array()) {
+        data.iter()
+            .filter_map(|m| m.get("id").and_then(|id| id.as_str()))
+            .map(|id| id.to_string())
+            .collect()
+    } else {
+        json.get("models")?
+            .as_array()?
+            .iter()
+            .filter_map(|m| m.get("name").and_then(|n| n.as_str()))
+            .map(|name| name.strip_prefix("models/").unwrap_or(name).to_string())
+            .collect()
+    };
+    let mut ids: Vec<String> = ids.into_iter().filter(|id| !id.is_empty()).collect();
+    ids.sort();
+    ids.dedup();
+    Some(ids)
 }
 
-fn deep_merge(base: &mut serde_json::Value, override_val: &serde_json::Value) {
-    if let (Some(base_obj), Some(override_obj)) = (base.as_object_mut(), override_val.as_object()) {
-        for (key, value) in override_obj {
-            if let Some(base_value) = base_obj.get_mut(key) {
-                if base_value.is_object() && value.is_object() {
-                    deep_merge(base_value, value);
-                } else {
-                    *base_value = value.clone();
-                }
-            } else {
-                base_obj.insert(key.clone(), value.clone());
-            }
-        }
-    }
-}
+/// Models the provider reports through its models list endpoint, for filling in model maps.
+/// Failures are written to the system log and yield an empty list.
+#[tauri::command]
+pub async fn get_provider_model_suggestions(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    http_clients: State<'_, crate::services::http_client::HttpClientPool>,
+    provider_id: i64,
+) -> Result<Vec<String>> {
+    use crate::services::proxy::{CliType, ProviderProtocol};
 
-// Sync Claude Code configuration (settings.json)
-async fn sync_claude_code_config(enabled: bool, default_config: &str, _db: State<'_, SqlitePool>) -> Result<()> {
-    let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
-    let config_path = home.join(".claude").join("settings.json");
+    let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+        .bind(provider_id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Provider not found".to_string())?;
 
-    if enabled {
-        // Backup existing config if not already backed up
-        if config_path.exists() && !has_backup(&config_path) {
-            backup_file(&config_path)?;
+    let now = chrono::Utc::now().timestamp();
+    let cached: Option<(String, String, i64)> = sqlx::query_as(
+        "SELECT base_url, models, fetched_at FROM provider_model_cache WHERE provider_id = ?",
+    )
+    .bind(provider_id)
+    .fetch_optional(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+    if let Some((base_url, models, fetched_at)) = cached {
+        if base_url == provider.base_url && now - fetched_at < MODEL_SUGGESTIONS_TTL_SECS {
+            return Ok(serde_json::from_str(&models).unwrap_or_default());
         }
+    }
 
-        // Create config directory if it doesn't exist
-        if let Some(parent) = config_path.parent() {
-            std::fs::create_dir_all(parent).map_err(|e| {
-                tracing::error!("Failed to create directory: {}", e);
-                e.to_string()
-            })?;
-        }
+    let cli_type: CliType = provider.cli_type.parse()?;
+    let openai_protocol = ProviderProtocol::from_provider(provider.protocol.as_deref()) == ProviderProtocol::OpenAi;
+    // Codex 的 base_url 自带 /v1
+    let path = match cli_type {
+        CliType::Codex => "/models",
+        CliType::Gemini if !openai_protocol => "/v1beta/models",
+        _ => "/v1/models",
+    };
+    let (request, logged_url) =
+        provider_request(db.inner(), &http_clients, &provider, cli_type, reqwest::Method::GET, path, None).await?;
+    let request = if cli_type == CliType::ClaudeCode && !openai_protocol {
+        request.header("anthropic-version", "2023-06-01")
+    } else {
+        request
+    };
 
-        // Build base config with gateway address
-        let mut config = serde_json::json!({
-            "env": {
-                "ANTHROPIC_BASE_URL": "http://127.0.0.1:7788",
-                "ANTHROPIC_AUTH_TOKEN": "ccg-gateway"
-            }
-        });
+    let result = match request.timeout(std::time::Duration::from_secs(30)).send().await {
+        Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+            Ok(body) => parse_model_list(&body).ok_or_else(|| "Unrecognized models list response".to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+        Ok(resp) => Err(format!("HTTP {}", resp.status())),
+        Err(e) => Err(e.to_string()),
+    };
 
-        // Merge user's custom config if provided
-        if !default_config.is_empty() {
-            match serde_json::from_str::<serde_json::Value>(default_config) {
-                Ok(custom_config) => {
-                    deep_merge(&mut config, &custom_config);
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to parse custom config (invalid JSON): {}", e);
-                }
-            }
+    match result {
+        Ok(models) => {
+            let json = serde_json::to_string(&models).map_err(|e| e.to_string())?;
+            sqlx::query(
+                r#"
+                INSERT INTO provider_model_cache (provider_id, base_url, models, fetched_at)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT(provider_id) DO UPDATE SET
+                    base_url = excluded.base_url,
+                    models = excluded.models,
+                    fetched_at = excluded.fetched_at
+                "#,
+            )
+            .bind(provider_id)
+            .bind(&provider.base_url)
+            .bind(&json)
+            .bind(now)
+            .execute(db.inner())
+            .await
+            .map_err(|e| e.to_string())?;
+            Ok(models)
         }
-
-        // Write config file
-        let config_str = serde_json::to_string_pretty(&config).map_err(|e| {
-            tracing::error!("Failed to serialize config: {}", e);
-            e.to_string()
-        })?;
-        std::fs::write(&config_path, config_str).map_err(|e| {
-            tracing::error!("Failed to write config file: {}", e);
-            e.to_string()
-        })?;
-    } else {
-        // When disabling, restore backup or remove config file
-        if restore_backup(&config_path)? {
-        } else if config_path.exists() {
-            // No backup, remove the config file
-            std::fs::remove_file(&config_path).map_err(|e| {
-                tracing::error!("Failed to remove config file: {}", e);
-                e.to_string()
-            })?;
+        Err(e) => {
+            tracing::warn!(provider = %provider.name, "Failed to list models: {}", e);
+            let _ = crate::services::stats::record_system_log(
+                &log_db,
+                "warn",
+                "model_list_failed",
+                &format!("Failed to list models of provider {} ({}): {}", provider.name, logged_url, e),
+                Some(&provider.name),
+                None,
+            ).await;
+            Ok(Vec::new())
         }
     }
-
-    Ok(())
 }
 
-// Sync Codex configuration (auth.json + config.toml)
-async fn sync_codex_config(enabled: bool, default_config: &str, _db: State<'_, SqlitePool>) -> Result<()> {
-    let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
-    let codex_dir = home.join(".codex");
-    let auth_path = codex_dir.join("auth.json");
-    let config_path = codex_dir.join("config.toml");
+// GeoIP lookups of a provider host are reused for a week
+const PROVIDER_GEO_TTL_SECS: i64 = 7 * 24 * 60 * 60;
 
-    if enabled {
-        // Backup existing configs if not already backed up
-        if auth_path.exists() && !has_backup(&auth_path) {
-            backup_file(&auth_path)?;
-        }
-        if config_path.exists() && !has_backup(&config_path) {
-            backup_file(&config_path)?;
-        }
+// host, ip, country_code, city, asn_name, resolved_at
+type ProviderGeoCacheRow = (String, String, Option<String>, Option<String>, Option<String>, i64);
 
-        // Create config directory if it doesn't exist
-        std::fs::create_dir_all(&codex_dir).map_err(|e| {
-            tracing::error!("Failed to create Codex directory: {}", e);
-            e.to_string()
-        })?;
+/// Directories searched for the GeoLite2 databases: data dir first, then the bundled resources
+fn geoip_dirs(app: &tauri::AppHandle) -> Vec<std::path::PathBuf> {
+    use tauri::Manager;
 
-        // Write auth.json with gateway API key
-        let auth = serde_json::json!({
-            "OPENAI_API_KEY": "ccg-gateway"
-        });
-        let auth_str = serde_json::to_string_pretty(&auth).map_err(|e| {
-            tracing::error!("Failed to serialize auth.json: {}", e);
-            e.to_string()
-        })?;
-        std::fs::write(&auth_path, auth_str).map_err(|e| {
-            tracing::error!("Failed to write auth.json: {}", e);
-            e.to_string()
-        })?;
+    let mut dirs = vec![get_data_dir().join("geoip")];
+    if let Ok(resource_dir) = app.path().resource_dir() {
+        dirs.push(resource_dir.join("resources").join("geoip"));
+    }
+    dirs
+}
 
-        // Build base config.toml pointing to gateway
-        let mut doc = toml_edit::DocumentMut::new();
-        doc["model_provider"] = toml_edit::value("ccg-gateway");
+/// Cached location of a provider's base_url host, looked up again after a week, when the host
+/// changed or when `force` is set
+async fn provider_geo_info(app: &tauri::AppHandle, db: &SqlitePool, provider_id: i64, force: bool) -> Result<ProviderGeoInfo> {
+    let base_url: String = sqlx::query_scalar("SELECT base_url FROM providers WHERE id = ?")
+        .bind(provider_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Provider not found".to_string())?;
+    let host = reqwest::Url::parse(&base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .ok_or_else(|| format!("Invalid base URL: {}", base_url))?;
 
-        if !doc.contains_table("model_providers") {
-            doc["model_providers"] = toml_edit::table();
+    let now = chrono::Utc::now().timestamp();
+    if !force {
+        let cached: Option<ProviderGeoCacheRow> = sqlx::query_as(
+            "SELECT host, ip, country_code, city, asn_name, resolved_at FROM provider_geo_cache WHERE provider_id = ?",
+        )
+        .bind(provider_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?;
+        if let Some((cached_host, ip, country_code, city, asn_name, resolved_at)) = cached {
+            if cached_host == host && now - resolved_at < PROVIDER_GEO_TTL_SECS {
+                return Ok(ProviderGeoInfo { provider_id, host, ip, country_code, city, asn_name, resolved_at });
+            }
         }
+    }
 
-        let mut gateway_table = toml_edit::Table::new();
-        gateway_table.insert("name", toml_edit::value("ccg-gateway"));
-        gateway_table.insert("base_url", toml_edit::value("http://127.0.0.1:7788"));
-        gateway_table.insert("wire_api", toml_edit::value("responses"));
-        gateway_table.insert("requires_openai_auth", toml_edit::value(false));
+    let ip = crate::services::geoip::resolve_host(&host).await?;
+    let dirs = geoip_dirs(app);
+    let location = tokio::task::spawn_blocking(move || {
+        crate::services::geoip::databases(&dirs).map(|databases| databases.lookup(ip))
+    })
+    .await
+    .map_err(|e| e.to_string())??;
 
-        doc["model_providers"]["ccg-gateway"] = toml_edit::Item::Table(gateway_table);
+    sqlx::query(
+        r#"
+        INSERT INTO provider_geo_cache (provider_id, host, ip, country_code, city, asn_name, resolved_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(provider_id) DO UPDATE SET
+            host = excluded.host,
+            ip = excluded.ip,
+            country_code = excluded.country_code,
+            city = excluded.city,
+            asn_name = excluded.asn_name,
+            resolved_at = excluded.resolved_at
+        "#,
+    )
+    .bind(provider_id)
+    .bind(&host)
+    .bind(ip.to_string())
+    .bind(&location.country_code)
+    .bind(&location.city)
+    .bind(&location.asn_name)
+    .bind(now)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
 
-        // Merge user's custom config if provided (TOML format)
-        if !default_config.is_empty() {
-            match default_config.parse::<toml_edit::DocumentMut>() {
-                Ok(custom_doc) => {
-                    // Merge custom config into base config
-                    for (key, value) in custom_doc.iter() {
-                        if key != "model_provider" && key != "model_providers" {
-                            doc[key] = value.clone();
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to parse custom config (invalid TOML): {}", e);
-                }
-            }
-        }
+    Ok(ProviderGeoInfo {
+        provider_id,
+        host,
+        ip: ip.to_string(),
+        country_code: location.country_code,
+        city: location.city,
+        asn_name: location.asn_name,
+        resolved_at: now,
+    })
+}
 
-        std::fs::write(&config_path, doc.to_string()).map_err(|e| {
-            tracing::error!("Failed to write config.toml: {}", e);
-            e.to_string()
-        })?;
-    } else {
-        // When disabling, restore backups or remove config files
-        let auth_restored = restore_backup(&auth_path)?;
-        let config_restored = restore_backup(&config_path)?;
+/// Country, city and network (ASN) of the provider's data center, from its resolved base_url host
+#[tauri::command]
+pub async fn get_provider_geographic_info(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    provider_id: i64,
+) -> Result<ProviderGeoInfo> {
+    provider_geo_info(&app, db.inner(), provider_id, false).await
+}
 
-        if auth_restored {
-        } else if auth_path.exists() {
-            std::fs::remove_file(&auth_path).map_err(|e| {
-                tracing::error!("Failed to remove auth.json: {}", e);
-                e.to_string()
-            })?;
-        }
+/// Resolve the provider host again, ignoring the cached location
+#[tauri::command]
+pub async fn refresh_provider_geo_info(
+    app: tauri::AppHandle,
+    db: State<'_, SqlitePool>,
+    provider_id: i64,
+) -> Result<ProviderGeoInfo> {
+    provider_geo_info(&app, db.inner(), provider_id, true).await
+}
 
-        if config_restored {
-        } else if config_path.exists() {
-            std::fs::remove_file(&config_path).map_err(|e| {
-                tracing::error!("Failed to remove config.toml: {}", e);
-                e.to_string()
-            })?;
-        }
+/// Apply one patch to several providers in a single transaction.
+/// Unknown IDs roll back the whole update and are listed in the error.
+#[tauri::command]
+pub async fn bulk_update_providers(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    ua_patterns: State<'_, crate::UaPatterns>,
+    ids: Vec<i64>,
+    patch: ProviderBulkPatch,
+) -> Result<Vec<ProviderResponse>> {
+    let mut seen = std::collections::HashSet::new();
+    let ids: Vec<i64> = ids.into_iter().filter(|id| seen.insert(*id)).collect();
+    if ids.is_empty() {
+        return Err("No providers selected".to_string());
     }
 
-    Ok(())
-}
-
-// Sync Gemini configuration (settings.json + .env)
-async fn sync_gemini_config(enabled: bool, default_config: &str, _db: State<'_, SqlitePool>) -> Result<()> {
-    let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
-    let gemini_dir = home.join(".gemini");
-    let config_path = gemini_dir.join("settings.json");
-    let env_path = gemini_dir.join(".env");
+    let mut changes = serde_json::Map::new();
+    if let Some(enabled) = patch.enabled {
+        changes.insert("enabled".to_string(), enabled.into());
+    }
+    if let Some(failure_threshold) = patch.failure_threshold {
+        changes.insert("failure_threshold".to_string(), failure_threshold.into());
+    }
+    if let Some(blacklist_minutes) = patch.blacklist_minutes {
+        changes.insert("blacklist_minutes".to_string(), blacklist_minutes.into());
+    }
+    if changes.is_empty() {
+        return Err("Nothing to update".to_string());
+    }
 
-    if enabled {
-        // Backup existing configs if not already backed up
-        if config_path.exists() && !has_backup(&config_path) {
-            backup_file(&config_path)?;
-        }
-        if env_path.exists() && !has_backup(&env_path) {
-            backup_file(&env_path)?;
+    let now = chrono::Utc::now().timestamp();
+    let mut tx = db.inner().begin().await.map_err(|e| e.to_string())?;
+    let mut missing = Vec::new();
+    for id in &ids {
+        let result = sqlx::query(
+            "UPDATE providers SET enabled = COALESCE(?, enabled), failure_threshold = COALESCE(?, failure_threshold), blacklist_minutes = COALESCE(?, blacklist_minutes), updated_at = ? WHERE id = ?",
+        )
+        .bind(patch.enabled.map(|v| v as i64))
+        .bind(patch.failure_threshold)
+        .bind(patch.blacklist_minutes)
+        .bind(now)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+        if result.rows_affected() == 0 {
+            missing.push(id.to_string());
         }
+    }
+    if !missing.is_empty() {
+        tx.rollback().await.map_err(|e| e.to_string())?;
+        return Err(format!("Providers not found: {}", missing.join(", ")));
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
 
-        // Create config directory if it doesn't exist
-        std::fs::create_dir_all(&gemini_dir).map_err(|e| {
-            tracing::error!("Failed to create Gemini directory: {}", e);
-            e.to_string()
-        })?;
+    refresh_ua_patterns(&db, &ua_patterns.0).await;
 
-        // Write .env file with gateway address
-        let env_content = "GEMINI_API_KEY=ccg-gateway\nGOOGLE_GEMINI_BASE_URL=http://127.0.0.1:7788\n".to_string();
-        std::fs::write(&env_path, env_content).map_err(|e| {
-            tracing::error!("Failed to write .env file: {}", e);
-            e.to_string()
-        })?;
+    let details = serde_json::json!({ "ids": ids, "changes": changes }).to_string();
+    let changed_fields: Vec<&str> = changes.keys().map(String::as_str).collect();
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "info",
+        "providers_bulk_updated",
+        &format!("{} providers updated ({})", ids.len(), changed_fields.join(", ")),
+        None,
+        Some(&details),
+    ).await;
 
-        // Build base config with security.auth.selectedType
-        let mut config = serde_json::json!({
-            "security": {
-                "auth": {
-                    "selectedType": "gemini-api-key"
-                }
-            }
-        });
+    let mut results = Vec::with_capacity(ids.len());
+    for id in ids {
+        results.push(get_provider(db.clone(), id).await?);
+    }
+    Ok(results)
+}
 
-        // Merge user's custom config if provided
-        if !default_config.is_empty() {
-            match serde_json::from_str::<serde_json::Value>(default_config) {
-                Ok(custom_config) => {
-                    deep_merge(&mut config, &custom_config);
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to parse custom config (invalid JSON): {}", e);
-                }
-            }
-        }
+#[tauri::command]
+pub async fn reset_provider_failures(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    id: i64,
+) -> Result<()> {
+    reset_failures(db.inner(), &log_db.0, id).await
+}
 
-        // Write config file
-        let config_str = serde_json::to_string_pretty(&config).map_err(|e| {
-            tracing::error!("Failed to serialize config.json: {}", e);
-            e.to_string()
-        })?;
-        std::fs::write(&config_path, config_str).map_err(|e| {
-            tracing::error!("Failed to write config.json: {}", e);
-            e.to_string()
-        })?;
-    } else {
-        // When disabling, restore backups or remove config files
-        let env_restored = restore_backup(&env_path)?;
-        let config_restored = restore_backup(&config_path)?;
+pub async fn reset_failures(db: &SqlitePool, log_db: &SqlitePool, id: i64) -> Result<()> {
+    // Get provider name for logging
+    let provider_name: Option<(String,)> = sqlx::query_as(
+        "SELECT name FROM providers WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
 
-        if env_restored {
-        } else if env_path.exists() {
-            std::fs::remove_file(&env_path).map_err(|e| {
-                tracing::error!("Failed to remove .env file: {}", e);
-                e.to_string()
-            })?;
-        }
+    let provider_name = provider_name.map(|(n,)| n).unwrap_or_else(|| format!("Provider#{}", id));
 
-        if config_restored {
-        } else if config_path.exists() {
-            std::fs::remove_file(&config_path).map_err(|e| {
-                tracing::error!("Failed to remove config.json: {}", e);
-                e.to_string()
-            })?;
-        }
-    }
+    sqlx::query("UPDATE providers SET consecutive_failures = 0, blacklisted_until = NULL, budget_exceeded_until = NULL WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Log system event
+    let _ = crate::services::stats::record_system_log(
+        log_db,
+        "info",
+        "provider_reset",
+        &format!("Provider {} status manually reset", provider_name),
+        Some(&provider_name),
+        None,
+    ).await;
 
     Ok(())
 }
 
-// Log commands
-#[tauri::command]
-pub async fn get_request_logs(
-    log_db: State<'_, crate::LogDb>,
-    page: Option<i64>,
-    page_size: Option<i64>,
-    cli_type: Option<String>,
-) -> Result<PaginatedLogs> {
-    let page = page.unwrap_or(1).max(1);
-    let page_size = page_size.unwrap_or(20).clamp(1, 100);
-    let offset = (page - 1) * page_size;
-    let pool = &log_db.0;
-
-    let (items, total) = if let Some(ct) = cli_type {
-        let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs WHERE cli_type = ? ORDER BY id DESC LIMIT ? OFFSET ?",
-        )
-        .bind(&ct)
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(pool)
+/// Simulation commands change real provider state, so they stay off unless enabled in gateway_settings
+async fn check_simulation_allowed(db: &SqlitePool) -> Result<()> {
+    let allowed = sqlx::query_scalar::<_, i64>("SELECT allow_simulation_commands FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
         .await
         .map_err(|e| e.to_string())?;
+    if allowed == 0 {
+        return Err("Simulation commands are disabled (enable allow_simulation_commands first)".to_string());
+    }
+    Ok(())
+}
 
-        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs WHERE cli_type = ?")
-            .bind(&ct)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| e.to_string())?;
+/// Put a provider into a failure state without sending requests, for testing failover.
+/// Blacklists it when failure_count reaches failure_threshold, like real failures do.
+#[tauri::command]
+pub async fn simulate_provider_failure(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    provider_id: i64,
+    failure_count: i64,
+) -> Result<()> {
+    check_simulation_allowed(db.inner()).await?;
+    if failure_count < 0 {
+        return Err("failure_count must not be negative".to_string());
+    }
 
-        (items, total.0)
-    } else {
-        let items = sqlx::query_as::<_, RequestLogItem>(
-            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path FROM request_logs ORDER BY id DESC LIMIT ? OFFSET ?",
-        )
-        .bind(page_size)
-        .bind(offset)
-        .fetch_all(pool)
+    let provider: Option<(String, i64, i64)> = sqlx::query_as(
+        "SELECT name, failure_threshold, blacklist_minutes FROM providers WHERE id = ?",
+    )
+    .bind(provider_id)
+    .fetch_optional(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+    let Some((provider_name, failure_threshold, blacklist_minutes)) = provider else {
+        return Err(format!("Provider {} not found", provider_id));
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let blacklisted_until = (failure_count >= failure_threshold).then(|| now + blacklist_minutes * 60);
+    sqlx::query("UPDATE providers SET consecutive_failures = ?, blacklisted_until = COALESCE(?, blacklisted_until), updated_at = ? WHERE id = ?")
+        .bind(failure_count)
+        .bind(blacklisted_until)
+        .bind(now)
+        .bind(provider_id)
+        .execute(db.inner())
         .await
         .map_err(|e| e.to_string())?;
 
-        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs")
-            .fetch_one(pool)
-            .await
-            .map_err(|e| e.to_string())?;
-
-        (items, total.0)
+    let message = match blacklisted_until {
+        Some(until) => format!(
+            "Simulated {} failures for provider {}, blacklisted until {}",
+            failure_count, provider_name, until
+        ),
+        None => format!("Simulated {} failures for provider {}", failure_count, provider_name),
     };
+    tracing::warn!(provider = %provider_name, failures = failure_count, "{}", message);
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "warn",
+        "provider_failure_simulated",
+        &message,
+        Some(&provider_name),
+        None,
+    ).await;
 
-    Ok(PaginatedLogs {
-        items,
-        total,
-        page,
-        page_size,
-    })
+    Ok(())
 }
 
+/// Clear a simulated failure the same way a successful request would
 #[tauri::command]
-pub async fn clear_request_logs(log_db: State<'_, crate::LogDb>) -> Result<()> {
-    sqlx::query("DELETE FROM request_logs")
-        .execute(&log_db.0)
+pub async fn simulate_provider_recovery(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    provider_id: i64,
+) -> Result<()> {
+    check_simulation_allowed(db.inner()).await?;
+
+    let provider_name: Option<String> = sqlx::query_scalar("SELECT name FROM providers WHERE id = ?")
+        .bind(provider_id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(provider_name) = provider_name else {
+        return Err(format!("Provider {} not found", provider_id));
+    };
+
+    crate::services::provider::record_success(db.inner(), provider_id)
         .await
         .map_err(|e| e.to_string())?;
+
+    let message = format!("Simulated recovery for provider {}", provider_name);
+    tracing::warn!(provider = %provider_name, "{}", message);
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "warn",
+        "provider_recovery_simulated",
+        &message,
+        Some(&provider_name),
+        None,
+    ).await;
+
     Ok(())
 }
 
+// Provider API key rotation commands
 #[tauri::command]
-pub async fn get_request_log_detail(
-    log_db: State<'_, crate::LogDb>,
-    id: i64,
-) -> Result<RequestLogDetail> {
-    sqlx::query_as::<_, RequestLogDetail>(
-        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, response_headers, response_body, error_message FROM request_logs WHERE id = ?",
+pub async fn list_provider_api_keys(
+    db: State<'_, SqlitePool>,
+    provider_id: i64,
+) -> Result<Vec<ProviderApiKeyResponse>> {
+    let keys = sqlx::query_as::<_, ProviderApiKey>(
+        "SELECT * FROM provider_api_keys WHERE provider_id = ? ORDER BY id",
     )
-    .bind(id)
-    .fetch_optional(&log_db.0)
+    .bind(provider_id)
+    .fetch_all(db.inner())
     .await
-    .map_err(|e| e.to_string())?
-    .ok_or_else(|| "Log not found".to_string())
-}
-
-// System logs commands
-#[tauri::command]
-pub async fn get_system_logs(
-    log_db: State<'_, crate::LogDb>,
-    page: Option<i64>,
-    page_size: Option<i64>,
-    level: Option<String>,
-    event_type: Option<String>,
-    provider_name: Option<String>,
-) -> Result<SystemLogListResponse> {
-    let page = page.unwrap_or(1).max(1);
-    let page_size = page_size.unwrap_or(20).clamp(1, 100);
-    let offset = (page - 1) * page_size;
+    .map_err(|e| e.to_string())?;
 
-    // Build query
-    let mut sql = "SELECT * FROM system_logs WHERE 1=1".to_string();
-    let mut count_sql = "SELECT COUNT(*) FROM system_logs WHERE 1=1".to_string();
+    Ok(keys.into_iter().map(rotation_key_response).collect())
+}
 
-    if level.is_some() {
-        sql.push_str(" AND level = ?");
-        count_sql.push_str(" AND level = ?");
-    }
-    if event_type.is_some() {
-        sql.push_str(" AND event_type = ?");
-        count_sql.push_str(" AND event_type = ?");
-    }
-    if provider_name.is_some() {
-        sql.push_str(" AND provider_name = ?");
-        count_sql.push_str(" AND provider_name = ?");
+/// Response with the masked tail of the real key, not of its keychain reference
+fn rotation_key_response(mut key: ProviderApiKey) -> ProviderApiKeyResponse {
+    if let Ok(plaintext) = crate::services::secrets::resolve_api_key(&key.api_key) {
+        key.api_key = plaintext;
     }
+    ProviderApiKeyResponse::from(key)
+}
 
-    sql.push_str(" ORDER BY id DESC LIMIT ? OFFSET ?");
-    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql)
-        .bind(page_size)
-        .bind(offset);
-
-    if let Some(ref lvl) = level {
-        q = q.bind(lvl);
-    }
-    if let Some(ref et) = event_type {
-        q = q.bind(et);
-    }
-    if let Some(ref pn) = provider_name {
-        q = q.bind(pn);
+#[tauri::command]
+pub async fn add_provider_api_key(
+    db: State<'_, SqlitePool>,
+    provider_id: i64,
+    api_key: String,
+) -> Result<ProviderApiKeyResponse> {
+    let api_key = api_key.trim().to_string();
+    if api_key.is_empty() {
+        return Err("API key cannot be empty".to_string());
     }
 
-    let items = q.fetch_all(&log_db.0)
+    let exists: Option<(i64,)> = sqlx::query_as("SELECT id FROM providers WHERE id = ?")
+        .bind(provider_id)
+        .fetch_optional(db.inner())
         .await
         .map_err(|e| e.to_string())?;
-
-    // Get total count
-    let mut count_q = sqlx::query_as::<_, (i64,)>(&count_sql);
-    if let Some(ref lvl) = level {
-        count_q = count_q.bind(lvl);
-    }
-    if let Some(ref et) = event_type {
-        count_q = count_q.bind(et);
+    if exists.is_none() {
+        return Err("Provider not found".to_string());
     }
-    if let Some(ref pn) = provider_name {
-        count_q = count_q.bind(pn);
+
+    // 开启钥匙串时明文不落库：先插入空值拿到 id，再写入钥匙串引用
+    let use_keychain = keychain_enabled(db.inner()).await?;
+    let now = chrono::Utc::now().timestamp();
+    let result = sqlx::query(
+        "INSERT INTO provider_api_keys (provider_id, api_key, enabled, failure_count, created_at) VALUES (?, ?, 1, 0, ?)",
+    )
+    .bind(provider_id)
+    .bind(if use_keychain { "" } else { api_key.as_str() })
+    .bind(now)
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+    let id = result.last_insert_rowid();
+
+    if use_keychain {
+        let stored = match crate::services::secrets::protect_rotation_key(id, &api_key, true) {
+            Ok(stored) => stored,
+            Err(e) => {
+                let _ = sqlx::query("DELETE FROM provider_api_keys WHERE id = ?").bind(id).execute(db.inner()).await;
+                return Err(e);
+            }
+        };
+        sqlx::query("UPDATE provider_api_keys SET api_key = ? WHERE id = ?")
+            .bind(&stored)
+            .bind(id)
+            .execute(db.inner())
+            .await
+            .map_err(|e| e.to_string())?;
     }
-    let (total,) = count_q.fetch_one(&log_db.0)
+
+    let key = sqlx::query_as::<_, ProviderApiKey>("SELECT * FROM provider_api_keys WHERE id = ?")
+        .bind(id)
+        .fetch_one(db.inner())
         .await
         .map_err(|e| e.to_string())?;
 
-    Ok(SystemLogListResponse {
-        items,
-        total,
-        page,
-        page_size,
-    })
+    Ok(rotation_key_response(key))
 }
 
 #[tauri::command]
-pub async fn clear_system_logs(log_db: State<'_, crate::LogDb>) -> Result<()> {
-    sqlx::query("DELETE FROM system_logs")
-        .execute(&log_db.0)
+pub async fn remove_provider_api_key(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
+    sqlx::query("DELETE FROM provider_api_keys WHERE id = ?")
+        .bind(id)
+        .execute(db.inner())
         .await
         .map_err(|e| e.to_string())?;
+    delete_rotation_secrets(&[id]);
     Ok(())
 }
 
-// System status
-#[tauri::command]
-pub async fn get_system_status(start_time: State<'_, crate::StartTime>) -> Result<SystemStatus> {
-    let uptime = chrono::Utc::now().timestamp() - start_time.0;
-    Ok(SystemStatus {
-        status: "running".to_string(),
-        port: 7788,
-        uptime,
-        version: env!("CARGO_PKG_VERSION").to_string(),
-    })
+/// Remove the keychain entries of deleted rotation keys (entries that never existed are fine)
+fn delete_rotation_secrets(key_ids: &[i64]) {
+    for id in key_ids {
+        if let Err(e) = crate::services::secrets::delete_secret(&crate::services::secrets::rotation_secret_id(*id)) {
+            tracing::warn!(key_id = id, "Failed to remove rotation API key from keychain: {}", e);
+        }
+    }
+}
+
+// Provider schedule commands
+/// Validate the weekday mask and hour range of a schedule
+fn check_provider_schedule(day_of_week: i64, hour_start: i64, hour_end: i64) -> Result<()> {
+    use crate::services::routing::ALL_DAYS_MASK;
+    if day_of_week <= 0 || day_of_week & !ALL_DAYS_MASK != 0 {
+        return Err(format!("day_of_week must be a bitmask between 1 and {}", ALL_DAYS_MASK));
+    }
+    if !(0..=23).contains(&hour_start) {
+        return Err("hour_start must be between 0 and 23".to_string());
+    }
+    if !(0..=24).contains(&hour_end) {
+        return Err("hour_end must be between 0 and 24".to_string());
+    }
+    if hour_start == hour_end {
+        return Err("hour_start and hour_end cannot be equal".to_string());
+    }
+    Ok(())
+}
+
+/// Rebuild the proxy's schedule cache after schedules change
+async fn refresh_schedules(db: &SqlitePool, schedules: &crate::services::routing::ScheduleCache) {
+    if let Err(e) = crate::services::routing::reload_provider_schedules(db, schedules).await {
+        tracing::warn!("Failed to reload provider schedules: {}", e);
+    }
 }
 
-// MCP commands
 #[tauri::command]
-pub async fn get_mcps(db: State<'_, SqlitePool>) -> Result<Vec<McpResponse>> {
-    let mcps = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs ORDER BY id")
+pub async fn get_provider_schedules(
+    db: State<'_, SqlitePool>,
+    provider_id: Option<i64>,
+) -> Result<Vec<ProviderSchedule>> {
+    let schedules = if let Some(provider_id) = provider_id {
+        sqlx::query_as::<_, ProviderSchedule>(
+            "SELECT * FROM provider_schedules WHERE provider_id = ? ORDER BY id",
+        )
+        .bind(provider_id)
         .fetch_all(db.inner())
         .await
+    } else {
+        sqlx::query_as::<_, ProviderSchedule>("SELECT * FROM provider_schedules ORDER BY provider_id, id")
+            .fetch_all(db.inner())
+            .await
+    };
+    schedules.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn create_provider_schedule(
+    db: State<'_, SqlitePool>,
+    schedules: State<'_, crate::ProviderSchedules>,
+    input: ProviderScheduleCreate,
+) -> Result<ProviderSchedule> {
+    let day_of_week = input.day_of_week.unwrap_or(crate::services::routing::ALL_DAYS_MASK);
+    check_provider_schedule(day_of_week, input.hour_start, input.hour_end)?;
+
+    let exists: Option<(i64,)> = sqlx::query_as("SELECT id FROM providers WHERE id = ?")
+        .bind(input.provider_id)
+        .fetch_optional(db.inner())
+        .await
         .map_err(|e| e.to_string())?;
+    if exists.is_none() {
+        return Err("Provider not found".to_string());
+    }
 
-    let cli_types = vec!["claude_code", "codex", "gemini"];
+    let now = chrono::Utc::now().timestamp();
+    let result = sqlx::query(
+        r#"
+        INSERT INTO provider_schedules (provider_id, day_of_week, hour_start, hour_end, priority_override, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(input.provider_id)
+    .bind(day_of_week)
+    .bind(input.hour_start)
+    .bind(input.hour_end)
+    .bind(input.priority_override)
+    .bind(now)
+    .bind(now)
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
 
-    let mut results = Vec::new();
-    for mcp in mcps {
-        // Read real status from config files
-        let mut cli_flags = Vec::new();
-        for cli_type in &cli_types {
-            let enabled = mcp_enabled_in_file(cli_type, &mcp.name);
-            cli_flags.push(McpCliFlag {
-                cli_type: cli_type.to_string(),
-                enabled,
-            });
-        }
+    refresh_schedules(&db, &schedules.0).await;
 
-        results.push(McpResponse {
-            id: mcp.id,
-            name: mcp.name,
-            config_json: mcp.config_json,
-            cli_flags,
-        });
-    }
-    Ok(results)
+    sqlx::query_as::<_, ProviderSchedule>("SELECT * FROM provider_schedules WHERE id = ?")
+        .bind(result.last_insert_rowid())
+        .fetch_one(db.inner())
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub async fn get_mcp(db: State<'_, SqlitePool>, id: i64) -> Result<McpResponse> {
-    let mcp = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs WHERE id = ?")
+pub async fn update_provider_schedule(
+    db: State<'_, SqlitePool>,
+    schedules: State<'_, crate::ProviderSchedules>,
+    id: i64,
+    input: ProviderScheduleUpdate,
+) -> Result<ProviderSchedule> {
+    let current = sqlx::query_as::<_, ProviderSchedule>("SELECT * FROM provider_schedules WHERE id = ?")
         .bind(id)
         .fetch_optional(db.inner())
         .await
         .map_err(|e| e.to_string())?
-        .ok_or_else(|| "MCP not found".to_string())?;
-
-    // Read real status from config files
-    let cli_types = vec!["claude_code", "codex", "gemini"];
-    let mut cli_flags = Vec::new();
-    for cli_type in &cli_types {
-        let enabled = mcp_enabled_in_file(cli_type, &mcp.name);
-        cli_flags.push(McpCliFlag {
-            cli_type: cli_type.to_string(),
-            enabled,
-        });
-    }
+        .ok_or_else(|| "Schedule not found".to_string())?;
 
-    Ok(McpResponse {
-        id: mcp.id,
-        name: mcp.name,
-        config_json: mcp.config_json,
-        cli_flags,
-    })
-}
+    let day_of_week = input.day_of_week.unwrap_or(current.day_of_week);
+    let hour_start = input.hour_start.unwrap_or(current.hour_start);
+    let hour_end = input.hour_end.unwrap_or(current.hour_end);
+    let priority_override = input.priority_override.unwrap_or(current.priority_override);
+    check_provider_schedule(day_of_week, hour_start, hour_end)?;
 
-#[tauri::command]
-pub async fn create_mcp(db: State<'_, SqlitePool>, input: McpCreate) -> Result<McpResponse> {
     let now = chrono::Utc::now().timestamp();
-
-    let result = sqlx::query(
-        "INSERT INTO mcp_configs (name, config_json, updated_at) VALUES (?, ?, ?)",
+    sqlx::query(
+        r#"
+        UPDATE provider_schedules
+        SET day_of_week = ?, hour_start = ?, hour_end = ?, priority_override = ?, updated_at = ?
+        WHERE id = ?
+        "#,
     )
-    .bind(&input.name)
-    .bind(&input.config_json)
+    .bind(day_of_week)
+    .bind(hour_start)
+    .bind(hour_end)
+    .bind(priority_override)
     .bind(now)
+    .bind(id)
     .execute(db.inner())
     .await
     .map_err(|e| e.to_string())?;
 
-    let id = result.last_insert_rowid();
-
-    // Sync to CLI files if cli_flags provided
-    let cli_flags = input.cli_flags.unwrap_or_default();
-    if !cli_flags.is_empty() {
-        sync_single_mcp_to_cli(id, &input.name, &input.config_json, &cli_flags).await?;
-    }
+    refresh_schedules(&db, &schedules.0).await;
 
-    get_mcp(db, id).await
+    sqlx::query_as::<_, ProviderSchedule>("SELECT * FROM provider_schedules WHERE id = ?")
+        .bind(id)
+        .fetch_one(db.inner())
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub async fn update_mcp(db: State<'_, SqlitePool>, id: i64, input: McpUpdate) -> Result<McpResponse> {
-    let now = chrono::Utc::now().timestamp();
-
-    let (name, config_json) = if input.name.is_some() || input.config_json.is_some() {
-        let current = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs WHERE id = ?")
-            .bind(id)
-            .fetch_optional(db.inner())
-            .await
-            .map_err(|e| e.to_string())?
-            .ok_or_else(|| "MCP not found".to_string())?;
-
-        let new_name = input.name.unwrap_or(current.name.clone());
-        let new_config = input.config_json.unwrap_or(current.config_json.clone());
-
-        sqlx::query(
-            "UPDATE mcp_configs SET name = ?, config_json = ?, updated_at = ? WHERE id = ?",
-        )
-        .bind(&new_name)
-        .bind(&new_config)
-        .bind(now)
+pub async fn delete_provider_schedule(
+    db: State<'_, SqlitePool>,
+    schedules: State<'_, crate::ProviderSchedules>,
+    id: i64,
+) -> Result<()> {
+    sqlx::query("DELETE FROM provider_schedules WHERE id = ?")
         .bind(id)
         .execute(db.inner())
         .await
         .map_err(|e| e.to_string())?;
 
-        (new_name, new_config)
-    } else {
-        // Get current values if not updating
-        let current = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs WHERE id = ?")
-            .bind(id)
-            .fetch_optional(db.inner())
-            .await
-            .map_err(|e| e.to_string())?
-            .ok_or_else(|| "MCP not found".to_string())?;
-        (current.name, current.config_json)
-    };
+    refresh_schedules(&db, &schedules.0).await;
+    Ok(())
+}
 
-    // Sync to CLI files if cli_flags provided
-    if let Some(cli_flags) = input.cli_flags {
-        sync_single_mcp_to_cli(id, &name, &config_json, &cli_flags).await?;
+// User-Agent rule commands
+/// Rebuild the proxy's User-Agent rule cache after rules or the default CLI type change
+async fn refresh_ua_rules(db: &SqlitePool, ua_rules: &crate::services::proxy::UaRuleCache) {
+    if let Err(e) = crate::services::proxy::reload_ua_rules(db, ua_rules).await {
+        tracing::warn!("Failed to reload User-Agent rules: {}", e);
     }
+}
 
-    get_mcp(db, id).await
+fn check_cli_type(cli_type: &str) -> Result<()> {
+    cli_type.parse::<crate::services::proxy::CliType>().map(|_| ())
 }
 
 #[tauri::command]
-pub async fn delete_mcp(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
-    // Get MCP name before deletion
-    let mcp = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs WHERE id = ?")
-        .bind(id)
-        .fetch_optional(db.inner())
+pub async fn list_ua_rules(db: State<'_, SqlitePool>) -> Result<Vec<UaRule>> {
+    sqlx::query_as::<_, UaRule>("SELECT * FROM user_agent_rules ORDER BY priority, id")
+        .fetch_all(db.inner())
         .await
-        .map_err(|e| e.to_string())?
-        .ok_or_else(|| "MCP not found".to_string())?;
+        .map_err(|e| e.to_string())
+}
 
-    let mcp_name = mcp.name.clone();
+#[tauri::command]
+pub async fn create_ua_rule(
+    db: State<'_, SqlitePool>,
+    ua_rules: State<'_, crate::UaRules>,
+    input: UaRuleCreate,
+) -> Result<UaRule> {
+    let pattern = input.pattern.trim();
+    if pattern.is_empty() {
+        return Err("User-Agent pattern cannot be empty".to_string());
+    }
+    regex::Regex::new(pattern).map_err(|e| format!("Invalid User-Agent pattern: {}", e))?;
+    check_cli_type(&input.cli_type)?;
 
-    // Delete from database
-    sqlx::query("DELETE FROM mcp_configs WHERE id = ?")
-        .bind(id)
+    let now = chrono::Utc::now().timestamp();
+    let result = sqlx::query(
+        "INSERT INTO user_agent_rules (pattern, cli_type, priority, created_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(pattern)
+    .bind(&input.cli_type)
+    .bind(input.priority.unwrap_or(0))
+    .bind(now)
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    refresh_ua_rules(&db, &ua_rules.0).await;
+
+    sqlx::query_as::<_, UaRule>("SELECT * FROM user_agent_rules WHERE id = ?")
+        .bind(result.last_insert_rowid())
+        .fetch_one(db.inner())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn delete_ua_rule(
+    db: State<'_, SqlitePool>,
+    ua_rules: State<'_, crate::UaRules>,
+    id: i64,
+) -> Result<()> {
+    sqlx::query("DELETE FROM user_agent_rules WHERE id = ?")
+        .bind(id)
         .execute(db.inner())
         .await
         .map_err(|e| e.to_string())?;
 
-    // Remove from all CLI configs
-    delete_mcp_from_cli(&mcp_name)?;
-
+    refresh_ua_rules(&db, &ua_rules.0).await;
     Ok(())
 }
 
-// Sync a single MCP to CLI files based on enabled flags
-async fn sync_single_mcp_to_cli(
-    _mcp_id: i64,
-    mcp_name: &str,
-    mcp_config_json: &str,
-    cli_flags: &[McpCliFlag],
-) -> Result<()> {
-    let cli_types = vec!["claude_code", "codex", "gemini"];
+// Global model alias commands
+#[tauri::command]
+pub async fn get_model_aliases(db: State<'_, SqlitePool>, cli_type: Option<String>) -> Result<Vec<ModelAlias>> {
+    match cli_type {
+        Some(cli_type) => {
+            sqlx::query_as::<_, ModelAlias>("SELECT * FROM global_model_aliases WHERE cli_type = ? ORDER BY id")
+                .bind(cli_type)
+                .fetch_all(db.inner())
+                .await
+        }
+        None => {
+            sqlx::query_as::<_, ModelAlias>("SELECT * FROM global_model_aliases ORDER BY cli_type, id")
+                .fetch_all(db.inner())
+                .await
+        }
+    }
+    .map_err(|e| e.to_string())
+}
 
-    for cli_type in cli_types {
-        // Check if this MCP is enabled for this CLI
-        let is_enabled = cli_flags.iter()
-            .any(|f| f.cli_type == cli_type && f.enabled);
+/// Trimmed alias source / target, both required
+fn check_model_alias(source_model: &str, target_model: &str) -> Result<(String, String)> {
+    let (source_model, target_model) = (source_model.trim(), target_model.trim());
+    if source_model.is_empty() || target_model.is_empty() {
+        return Err("Alias source and target model cannot be empty".to_string());
+    }
+    check_model_source(source_model)?;
+    Ok((source_model.to_string(), target_model.to_string()))
+}
 
-        let config_path = get_mcp_config_path(cli_type);
-        if let Some(path) = config_path {
-            // Handle Codex separately (TOML format)
-            if cli_type == "codex" {
-                sync_single_codex_mcp(path, mcp_name, mcp_config_json, is_enabled)?;
-                continue;
-            }
+#[tauri::command]
+pub async fn create_model_alias(db: State<'_, SqlitePool>, input: ModelAliasCreate) -> Result<ModelAlias> {
+    check_cli_type(&input.cli_type)?;
+    let (source_model, target_model) = check_model_alias(&input.source_model, &input.target_model)?;
 
-            // For ClaudeCode and Gemini (JSON format)
-            // Read existing config or create new one
-            let mut config = if path.exists() {
-                let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
-                serde_json::from_str::<serde_json::Value>(&content).unwrap_or_else(|_| serde_json::json!({}))
-            } else {
-                serde_json::json!({})
-            };
+    let now = chrono::Utc::now().timestamp();
+    let result = sqlx::query(
+        "INSERT INTO global_model_aliases (cli_type, source_model, target_model, enabled, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&input.cli_type)
+    .bind(&source_model)
+    .bind(&target_model)
+    .bind(input.enabled.unwrap_or(true) as i64)
+    .bind(now)
+    .bind(now)
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
 
-            // Update MCP section
-            if is_enabled {
-                // Add or update this MCP
-                if let Ok(mcp_json) = serde_json::from_str::<serde_json::Value>(mcp_config_json) {
-                    if let Some(obj) = config.as_object_mut() {
-                        if !obj.contains_key("mcpServers") {
-                            obj.insert("mcpServers".to_string(), serde_json::json!({}));
-                        }
-                        if let Some(servers) = obj.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
-                            servers.insert(mcp_name.to_string(), mcp_json);
-                        }
-                    }
-                }
-            } else {
-                // Remove this MCP by name
-                if let Some(obj) = config.as_object_mut() {
-                    if let Some(servers) = obj.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
-                        servers.remove(mcp_name);
-                    }
-                }
-            }
+    sqlx::query_as::<_, ModelAlias>("SELECT * FROM global_model_aliases WHERE id = ?")
+        .bind(result.last_insert_rowid())
+        .fetch_one(db.inner())
+        .await
+        .map_err(|e| e.to_string())
+}
 
-            // Write config file
-            if let Some(parent) = path.parent() {
-                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
-            }
-            let config_str = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
-            std::fs::write(&path, config_str).map_err(|e| e.to_string())?;
-        }
-    }
+#[tauri::command]
+pub async fn update_model_alias(db: State<'_, SqlitePool>, id: i64, input: ModelAliasUpdate) -> Result<ModelAlias> {
+    let current = sqlx::query_as::<_, ModelAlias>("SELECT * FROM global_model_aliases WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Model alias not found".to_string())?;
+
+    let (source_model, target_model) = check_model_alias(
+        input.source_model.as_deref().unwrap_or(&current.source_model),
+        input.target_model.as_deref().unwrap_or(&current.target_model),
+    )?;
+    let enabled = input.enabled.map(|e| e as i64).unwrap_or(current.enabled);
+
+    sqlx::query(
+        "UPDATE global_model_aliases SET source_model = ?, target_model = ?, enabled = ?, updated_at = ? WHERE id = ?",
+    )
+    .bind(&source_model)
+    .bind(&target_model)
+    .bind(enabled)
+    .bind(chrono::Utc::now().timestamp())
+    .bind(id)
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    sqlx::query_as::<_, ModelAlias>("SELECT * FROM global_model_aliases WHERE id = ?")
+        .bind(id)
+        .fetch_one(db.inner())
+        .await
+        .map_err(|e| e.to_string())
+}
 
+#[tauri::command]
+pub async fn delete_model_alias(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
+    sqlx::query("DELETE FROM global_model_aliases WHERE id = ?")
+        .bind(id)
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
     Ok(())
 }
 
-// Helper function to sync a single MCP to Codex config.toml
-fn sync_single_codex_mcp(
-    config_path: std::path::PathBuf,
-    mcp_name: &str,
-    mcp_config_json: &str,
-    is_enabled: bool,
+// Settings commands
+#[tauri::command]
+pub async fn get_gateway_settings(db: State<'_, SqlitePool>) -> Result<GatewaySettings> {
+    load_gateway_settings(db.inner()).await
+}
+
+pub async fn load_gateway_settings(db: &SqlitePool) -> Result<GatewaySettings> {
+    sqlx::query_as::<_, GatewaySettings>("SELECT debug_log, max_request_body_mb, log_body_max_kb, use_keychain, request_id_header, ws_proxy_enabled, gateway_token, gateway_token_enforced, listen_external, listen_address, allow_simulation_commands, default_cli_type, server_host, server_port, cli_ports, cli_path_prefix, default_proxy_user_agent, sticky_session_enabled FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_gateway_settings(
+    db: State<'_, SqlitePool>,
+    ua_rules: State<'_, crate::UaRules>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    debug_log: Option<bool>,
+    max_request_body_mb: Option<i64>,
+    log_body_max_kb: Option<i64>,
+    use_keychain: Option<bool>,
+    request_id_header: Option<String>,
+    ws_proxy_enabled: Option<bool>,
+    gateway_token_enforced: Option<bool>,
+    listen_external: Option<bool>,
+    listen_address: Option<String>,
+    allow_simulation_commands: Option<bool>,
+    default_cli_type: Option<String>,
+    cli_path_prefix: Option<bool>,
+    default_proxy_user_agent: Option<String>,
+    sticky_session_enabled: Option<bool>,
 ) -> Result<()> {
-    // Read existing TOML or create new one
-    let mut doc = if config_path.exists() {
-        let content = std::fs::read_to_string(&config_path).map_err(|e| {
-            tracing::error!("Failed to read config.toml: {}", e);
-            e.to_string()
-        })?;
-        content.parse::<toml_edit::DocumentMut>().unwrap_or_else(|e| {
-            tracing::warn!("Failed to parse config.toml, creating new: {}", e);
-            toml_edit::DocumentMut::new()
-        })
-    } else {
-        toml_edit::DocumentMut::new()
+    let input = GatewaySettingsUpdate {
+        debug_log,
+        max_request_body_mb,
+        log_body_max_kb,
+        use_keychain,
+        request_id_header,
+        ws_proxy_enabled,
+        gateway_token_enforced,
+        listen_external,
+        listen_address,
+        allow_simulation_commands,
+        default_cli_type,
+        cli_path_prefix,
+        default_proxy_user_agent,
+        sticky_session_enabled,
     };
+    save_gateway_settings(db.inner(), &ua_rules.0, &gateway_port, input).await
+}
 
-    // Ensure mcp_servers table exists
-    if !doc.contains_table("mcp_servers") {
-        doc["mcp_servers"] = toml_edit::table();
+pub async fn save_gateway_settings(
+    db: &SqlitePool,
+    ua_rules: &crate::services::proxy::UaRuleCache,
+    gateway_port: &crate::GatewayPort,
+    input: GatewaySettingsUpdate,
+) -> Result<()> {
+    if let Some(mb) = input.max_request_body_mb {
+        check_max_request_body_mb(mb)?;
     }
+    if input.log_body_max_kb.is_some_and(|kb| kb < 0) {
+        return Err("log_body_max_kb must not be negative".to_string());
+    }
+    if let Some(ref cli_type) = input.default_cli_type {
+        check_cli_type(cli_type)?;
+    }
+    // 空字符串表示关闭请求 ID
+    let request_id_header = match input.request_id_header {
+        Some(ref name) => Some(check_request_id_header(name)?),
+        None => None,
+    };
+    // 空字符串表示监听所有网卡
+    let listen_address = match input.listen_address {
+        Some(ref addr) => Some(check_listen_address(addr)?),
+        None => None,
+    };
+    // 空字符串表示保留客户端的 User-Agent
+    let default_proxy_user_agent = match input.default_proxy_user_agent {
+        Some(ref ua) => Some(check_proxy_user_agent(Some(ua))?),
+        None => None,
+    };
 
-    if is_enabled {
-        // Add or update this MCP
-        if let Ok(mcp_config) = serde_json::from_str::<serde_json::Value>(mcp_config_json) {
-            let mcp_type = mcp_config.get("type").and_then(|v| v.as_str()).unwrap_or("stdio");
-
-            // Create MCP server table
-            let mut server_table = toml_edit::Table::new();
+    let now = chrono::Utc::now().timestamp();
+    let current = load_gateway_settings(db).await?;
+
+    sqlx::query("UPDATE gateway_settings SET debug_log = ?, max_request_body_mb = ?, log_body_max_kb = ?, use_keychain = ?, request_id_header = ?, ws_proxy_enabled = ?, gateway_token_enforced = ?, listen_external = ?, listen_address = ?, allow_simulation_commands = ?, default_cli_type = ?, cli_path_prefix = ?, default_proxy_user_agent = ?, sticky_session_enabled = ?, updated_at = ? WHERE id = 1")
+        .bind(input.debug_log.map(|v| v as i64).unwrap_or(current.debug_log))
+        .bind(input.max_request_body_mb.unwrap_or(current.max_request_body_mb))
+        .bind(input.log_body_max_kb.unwrap_or(current.log_body_max_kb))
+        .bind(input.use_keychain.map(|v| v as i64).unwrap_or(current.use_keychain))
+        .bind(request_id_header.unwrap_or(current.request_id_header))
+        .bind(input.ws_proxy_enabled.map(|v| v as i64).unwrap_or(current.ws_proxy_enabled))
+        .bind(input.gateway_token_enforced.map(|v| v as i64).unwrap_or(current.gateway_token_enforced))
+        .bind(input.listen_external.map(|v| v as i64).unwrap_or(current.listen_external))
+        .bind(listen_address.unwrap_or(current.listen_address))
+        .bind(input.allow_simulation_commands.map(|v| v as i64).unwrap_or(current.allow_simulation_commands))
+        .bind(input.default_cli_type.unwrap_or(current.default_cli_type))
+        .bind(input.cli_path_prefix.map(|v| v as i64).unwrap_or(current.cli_path_prefix))
+        .bind(default_proxy_user_agent.unwrap_or(current.default_proxy_user_agent))
+        .bind(input.sticky_session_enabled.map(|v| v as i64).unwrap_or(current.sticky_session_enabled))
+        .bind(now)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
 
-            // Handle STDIO type servers
-            if let Some(command) = mcp_config.get("command").and_then(|v| v.as_str()) {
-                server_table.insert("command", toml_edit::value(command));
-            }
-            if let Some(args) = mcp_config.get("args").and_then(|v| v.as_array()) {
-                let args_array: toml_edit::Array = args.iter()
-                    .filter_map(|v| v.as_str())
-                    .map(toml_edit::Value::from)
-                    .collect();
-                server_table.insert("args", toml_edit::Item::Value(args_array.into()));
-            }
-            if let Some(env) = mcp_config.get("env").and_then(|v| v.as_object()) {
-                let mut env_table = toml_edit::Table::new();
-                for (k, v) in env.iter() {
-                    if let Some(v_str) = v.as_str() {
-                        env_table.insert(k, toml_edit::value(v_str));
-                    }
-                }
-                server_table.insert("env", toml_edit::Item::Table(env_table));
-            }
-            if let Some(cwd) = mcp_config.get("cwd").and_then(|v| v.as_str()) {
-                server_table.insert("cwd", toml_edit::value(cwd));
-            }
+    // 已接入网关的 CLI 配置改写为（不）带前缀的地址
+    if let Some(prefix) = input.cli_path_prefix.filter(|v| *v != (current.cli_path_prefix != 0)) {
+        let updated = retarget_cli_configs(db, gateway_port, prefix).await;
+        tracing::info!("Rewrote CLI gateway URLs (path prefix: {}): {:?}", prefix, updated);
+    }
 
-            // Handle HTTP/SSE type servers
-            if mcp_type == "sse" || mcp_type == "http" {
-                if let Some(url) = mcp_config.get("url").and_then(|v| v.as_str()) {
-                    server_table.insert("url", toml_edit::value(url));
-                }
-            }
+    refresh_ua_rules(db, ua_rules).await;
+    Ok(())
+}
 
-            // Optional fields
-            if let Some(timeout) = mcp_config.get("startup_timeout_sec").and_then(|v| v.as_i64()) {
-                server_table.insert("startup_timeout_sec", toml_edit::value(timeout));
-            }
-            if let Some(timeout) = mcp_config.get("tool_timeout_sec").and_then(|v| v.as_i64()) {
-                server_table.insert("tool_timeout_sec", toml_edit::value(timeout));
-            }
+/// Whether gateway_settings.use_keychain is on
+async fn keychain_enabled(db: &SqlitePool) -> Result<bool> {
+    sqlx::query_scalar::<_, i64>("SELECT use_keychain FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .map(|v| v != 0)
+        .map_err(|e| e.to_string())
+}
 
-            doc["mcp_servers"][mcp_name] = toml_edit::Item::Table(server_table);
-        }
-    } else {
-        // Remove this MCP by name
-        if let Some(table) = doc.get_mut("mcp_servers").and_then(|v| v.as_table_mut()) {
-            table.remove(mcp_name);
-        }
+/// Move plaintext provider API keys into the OS keychain; returns how many were moved
+#[tauri::command]
+pub async fn migrate_keys_to_keychain(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+) -> Result<i64> {
+    if !keychain_enabled(db.inner()).await? {
+        return Err("Enable use_keychain before migrating API keys".to_string());
     }
 
-    // Write config file
-    if let Some(parent) = config_path.parent() {
-        std::fs::create_dir_all(parent).map_err(|e| {
-            tracing::error!("Failed to create directory: {}", e);
-            e.to_string()
-        })?;
+    let providers: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT id, name, api_key FROM providers WHERE api_key != '' AND api_key NOT LIKE 'keychain:%'",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut migrated = 0;
+    for (id, name, api_key) in providers {
+        let stored = crate::services::secrets::protect_provider_key(id, &api_key, true)
+            .map_err(|e| format!("Provider {}: {}", name, e))?;
+        sqlx::query("UPDATE providers SET api_key = ? WHERE id = ?")
+            .bind(&stored)
+            .bind(id)
+            .execute(db.inner())
+            .await
+            .map_err(|e| e.to_string())?;
+        migrated += 1;
     }
-    std::fs::write(&config_path, doc.to_string()).map_err(|e| {
-        tracing::error!("Failed to write config.toml: {}", e);
-        e.to_string()
-    })?;
 
-    Ok(())
-}
+    let rotation_keys: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT k.id, p.name, k.api_key FROM provider_api_keys k JOIN providers p ON p.id = k.provider_id WHERE k.api_key != '' AND k.api_key NOT LIKE 'keychain:%'",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
 
-// Delete a single MCP from all CLI configs
-fn delete_mcp_from_cli(mcp_name: &str) -> Result<()> {
-    let cli_types = vec!["claude_code", "codex", "gemini"];
-
-    for cli_type in cli_types {
-        let config_path = get_mcp_config_path(cli_type);
-        if let Some(path) = config_path {
-            if !path.exists() {
-                continue;
-            }
+    for (id, name, api_key) in rotation_keys {
+        let stored = crate::services::secrets::protect_rotation_key(id, &api_key, true)
+            .map_err(|e| format!("Provider {} (rotation key #{}): {}", name, id, e))?;
+        sqlx::query("UPDATE provider_api_keys SET api_key = ? WHERE id = ?")
+            .bind(&stored)
+            .bind(id)
+            .execute(db.inner())
+            .await
+            .map_err(|e| e.to_string())?;
+        migrated += 1;
+    }
 
-            if cli_type == "codex" {
-                // Handle Codex TOML format
-                let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
-                let mut doc = content.parse::<toml_edit::DocumentMut>().unwrap_or_else(|_| toml_edit::DocumentMut::new());
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "info",
+        "keys_migrated",
+        &format!("Moved {} provider API keys to the keychain", migrated),
+        None,
+        None,
+    ).await;
 
-                if let Some(table) = doc["mcp_servers"].as_table_mut() {
-                    table.remove(mcp_name);
-                }
+    Ok(migrated)
+}
 
-                std::fs::write(&path, doc.to_string()).map_err(|e| e.to_string())?;
-            } else {
-                // Handle Claude/Gemini JSON format
-                let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
-                let mut config: serde_json::Value = serde_json::from_str(&content).unwrap_or_else(|_| serde_json::json!({}));
+#[tauri::command]
+pub async fn get_mask_patterns(db: State<'_, SqlitePool>) -> Result<Vec<String>> {
+    crate::services::masking::load_mask_patterns(db.inner())
+        .await
+        .map_err(|e| e.to_string())
+}
 
-                if let Some(mcp_servers) = config.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
-                    mcp_servers.remove(mcp_name);
-                }
+#[tauri::command]
+pub async fn update_mask_patterns(
+    db: State<'_, SqlitePool>,
+    mask_patterns: State<'_, crate::MaskPatterns>,
+    patterns: Vec<String>,
+) -> Result<()> {
+    crate::services::masking::validate_mask_patterns(&patterns)?;
 
-                let config_str = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
-                std::fs::write(&path, config_str).map_err(|e| e.to_string())?;
-            }
-        }
-    }
+    let json = serde_json::to_string(&patterns).map_err(|e| e.to_string())?;
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query("UPDATE gateway_settings SET log_mask_patterns = ?, updated_at = ? WHERE id = 1")
+        .bind(&json)
+        .bind(now)
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
 
-    Ok(())
+    crate::services::masking::reload_mask_patterns(db.inner(), &mask_patterns.0)
+        .await
+        .map_err(|e| e.to_string())
 }
 
-// Prompt commands
 #[tauri::command]
-pub async fn get_prompts(db: State<'_, SqlitePool>) -> Result<Vec<PromptResponse>> {
-    let prompts = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets ORDER BY id")
-        .fetch_all(db.inner())
+pub async fn get_log_exclude_paths(db: State<'_, SqlitePool>) -> Result<crate::services::log_exclude::LogExcludeRules> {
+    crate::services::log_exclude::load_log_exclude_rules(db.inner())
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| e.to_string())
+}
 
-    let cli_types = vec!["claude_code", "codex", "gemini"];
+/// Replace the client path patterns kept out of the request log
+#[tauri::command]
+pub async fn update_log_exclude_paths(
+    db: State<'_, SqlitePool>,
+    log_exclude: State<'_, crate::LogExcludePaths>,
+    patterns: Vec<String>,
+    count_usage: Option<bool>,
+) -> Result<()> {
+    let patterns = crate::services::log_exclude::normalize_log_exclude_paths(&patterns)?;
 
-    let mut results = Vec::new();
-    for prompt in prompts {
-        // Read real status from prompt files
-        let mut cli_flags = Vec::new();
-        for cli_type in &cli_types {
-            let enabled = prompt_enabled_in_file(cli_type, &prompt.content);
-            cli_flags.push(PromptCliFlag {
-                cli_type: cli_type.to_string(),
-                enabled,
-            });
-        }
+    let json = serde_json::to_string(&patterns).map_err(|e| e.to_string())?;
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query("UPDATE gateway_settings SET log_exclude_paths = ?, log_exclude_count_usage = COALESCE(?, log_exclude_count_usage), updated_at = ? WHERE id = 1")
+        .bind(&json)
+        .bind(count_usage.map(|v| v as i64))
+        .bind(now)
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
 
-        results.push(PromptResponse {
-            id: prompt.id,
-            name: prompt.name,
-            content: prompt.content,
-            cli_flags,
-        });
-    }
-    Ok(results)
+    crate::services::log_exclude::reload_log_exclude_rules(db.inner(), &log_exclude.0)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub async fn get_prompt(db: State<'_, SqlitePool>, id: i64) -> Result<PromptResponse> {
-    let prompt = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
-        .bind(id)
-        .fetch_optional(db.inner())
+pub async fn get_cors_origins(db: State<'_, SqlitePool>) -> Result<Vec<String>> {
+    crate::services::cors::load_cors_origins(db.inner())
         .await
-        .map_err(|e| e.to_string())?
-        .ok_or_else(|| "Prompt not found".to_string())?;
-
-    // Read real status from prompt files
-    let cli_types = vec!["claude_code", "codex", "gemini"];
-    let mut cli_flags = Vec::new();
-    for cli_type in &cli_types {
-        let enabled = prompt_enabled_in_file(cli_type, &prompt.content);
-        cli_flags.push(PromptCliFlag {
-            cli_type: cli_type.to_string(),
-            enabled,
-        });
-    }
-
-    Ok(PromptResponse {
-        id: prompt.id,
-        name: prompt.name,
-        content: prompt.content,
-        cli_flags,
-    })
+        .map_err(|e| e.to_string())
 }
 
+/// Save the /api CORS allowlist; takes effect for the next request
 #[tauri::command]
-pub async fn create_prompt(db: State<'_, SqlitePool>, input: PromptCreate) -> Result<PromptResponse> {
-    let now = chrono::Utc::now().timestamp();
-
-    let result = sqlx::query(
-        "INSERT INTO prompt_presets (name, content, updated_at) VALUES (?, ?, ?)",
-    )
-    .bind(&input.name)
-    .bind(&input.content)
-    .bind(now)
-    .execute(db.inner())
-    .await
-    .map_err(|e| e.to_string())?;
-
-    let id = result.last_insert_rowid();
+pub async fn update_cors_origins(
+    db: State<'_, SqlitePool>,
+    cors_origins: State<'_, crate::CorsOrigins>,
+    origins: Vec<String>,
+) -> Result<Vec<String>> {
+    let origins = crate::services::cors::normalize_cors_origins(&origins)?;
 
-    // Sync to CLI files if cli_flags provided
-    let cli_flags = input.cli_flags.unwrap_or_default();
-    if !cli_flags.is_empty() {
-        sync_single_prompt_to_cli(&input.content, &cli_flags).await?;
-    }
+    let json = serde_json::to_string(&origins).map_err(|e| e.to_string())?;
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query("UPDATE gateway_settings SET cors_allowed_origins = ?, updated_at = ? WHERE id = 1")
+        .bind(&json)
+        .bind(now)
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
 
-    get_prompt(db, id).await
+    crate::services::cors::reload_cors_origins(db.inner(), &cors_origins.0)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(origins)
 }
 
 #[tauri::command]
-pub async fn update_prompt(db: State<'_, SqlitePool>, id: i64, input: PromptUpdate) -> Result<PromptResponse> {
-    let now = chrono::Utc::now().timestamp();
-
-    let content = if input.name.is_some() || input.content.is_some() {
-        let current = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
-            .bind(id)
-            .fetch_optional(db.inner())
-            .await
-            .map_err(|e| e.to_string())?
-            .ok_or_else(|| "Prompt not found".to_string())?;
+pub async fn get_allowed_client_ips(db: State<'_, SqlitePool>) -> Result<Vec<String>> {
+    crate::services::ip_allowlist::load_allowed_client_ips(db.inner())
+        .await
+        .map_err(|e| e.to_string())
+}
 
-        let new_name = input.name.unwrap_or(current.name.clone());
-        let new_content = input.content.unwrap_or(current.content.clone());
+/// Save the proxy client allowlist (IPs / CIDRs); takes effect for the next request
+#[tauri::command]
+pub async fn update_allowed_client_ips(
+    db: State<'_, SqlitePool>,
+    allowed_client_ips: State<'_, crate::AllowedClientIps>,
+    entries: Vec<String>,
+) -> Result<Vec<String>> {
+    let entries = crate::services::ip_allowlist::normalize_allowed_client_ips(&entries)?;
 
-        sqlx::query(
-            "UPDATE prompt_presets SET name = ?, content = ?, updated_at = ? WHERE id = ?",
-        )
-        .bind(&new_name)
-        .bind(&new_content)
+    let json = serde_json::to_string(&entries).map_err(|e| e.to_string())?;
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query("UPDATE gateway_settings SET allowed_client_ips = ?, updated_at = ? WHERE id = 1")
+        .bind(&json)
         .bind(now)
-        .bind(id)
         .execute(db.inner())
         .await
         .map_err(|e| e.to_string())?;
 
-        new_content
-    } else {
-        // Get current values if not updating
-        let current = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
-            .bind(id)
-            .fetch_optional(db.inner())
-            .await
-            .map_err(|e| e.to_string())?
-            .ok_or_else(|| "Prompt not found".to_string())?;
-        current.content
-    };
-
-    // Sync to CLI files if cli_flags provided
-    if let Some(cli_flags) = input.cli_flags {
-        sync_single_prompt_to_cli(&content, &cli_flags).await?;
-    }
+    crate::services::ip_allowlist::reload_allowed_client_ips(db.inner(), &allowed_client_ips.0)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(entries)
+}
 
-    get_prompt(db, id).await
+/// Concurrent proxy request ceiling; 0 = unlimited
+#[tauri::command]
+pub async fn get_max_concurrent_requests(db: State<'_, SqlitePool>) -> Result<u32> {
+    crate::services::concurrency::load_max_concurrent_requests(db.inner())
+        .await
+        .map_err(|e| e.to_string())
 }
 
+/// Save the concurrent proxy request ceiling; takes effect for the next request
 #[tauri::command]
-pub async fn delete_prompt(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
-    sqlx::query("DELETE FROM prompt_presets WHERE id = ?")
-        .bind(id)
+pub async fn update_max_concurrent_requests(
+    db: State<'_, SqlitePool>,
+    concurrency: State<'_, crate::services::concurrency::ConcurrencyLimiter>,
+    limit: u32,
+) -> Result<u32> {
+    use crate::services::concurrency::MAX_CONCURRENT_REQUESTS_LIMIT;
+    if limit > MAX_CONCURRENT_REQUESTS_LIMIT {
+        return Err(format!("Concurrent request limit must be between 0 and {}", MAX_CONCURRENT_REQUESTS_LIMIT));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query("UPDATE gateway_settings SET max_concurrent_requests = ?, updated_at = ? WHERE id = 1")
+        .bind(limit as i64)
+        .bind(now)
         .execute(db.inner())
         .await
         .map_err(|e| e.to_string())?;
 
-    // Sync prompt configs to CLI files
-    sync_prompt_configs_to_cli(db).await?;
+    concurrency.set_limit(limit);
+    Ok(limit)
+}
 
-    Ok(())
+/// Proxy requests currently in flight, oldest first
+#[tauri::command]
+pub async fn get_active_requests(
+    active_requests: State<'_, crate::services::active_requests::ActiveRequests>,
+) -> Result<Vec<ActiveRequest>> {
+    Ok(active_requests.list())
 }
 
-// Sync a single prompt to CLI files based on enabled flags
-async fn sync_single_prompt_to_cli(
-    prompt_content: &str,
-    cli_flags: &[PromptCliFlag],
+/// Stop an in-flight request; the client gets an error response or a truncated stream
+#[tauri::command]
+pub async fn cancel_active_request(
+    active_requests: State<'_, crate::services::active_requests::ActiveRequests>,
+    log_db: State<'_, LogDb>,
+    request_id: String,
 ) -> Result<()> {
-    let cli_types = vec!["claude_code", "codex", "gemini"];
+    let id = uuid::Uuid::parse_str(request_id.trim()).map_err(|_| format!("Invalid request ID: '{}'", request_id))?;
+    let request = active_requests
+        .cancel(&id)
+        .ok_or_else(|| "Request not found or already finished".to_string())?;
 
-    for cli_type in cli_types {
-        // Check if this prompt is enabled for this CLI
-        let is_enabled = cli_flags.iter()
-            .any(|f| f.cli_type == cli_type && f.enabled);
-
-        // Get the prompt file path for this CLI
-        let prompt_path = get_prompt_file_path(cli_type);
-        if let Some(path) = prompt_path {
-            // Check if CLI directory exists (skip if CLI not installed)
-            if let Some(parent) = path.parent() {
-                if !parent.exists() {
-                    continue;
-                }
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "info",
+        "request_cancelled",
+        &format!("Request {} cancelled after {} ms", request.request_id, request.elapsed_ms),
+        request.provider_name.as_deref(),
+        None,
+    ).await;
+    Ok(())
+}
 
-                if is_enabled {
-                    // Write prompt content to file
-                    std::fs::write(&path, prompt_content).map_err(|e| {
-                        tracing::error!("Failed to write prompt file: {}", e);
-                        e.to_string()
-                    })?;
-                } else {
-                    // Check if this prompt was previously in the file
-                    if path.exists() {
-                        let file_content = std::fs::read_to_string(&path).unwrap_or_default();
-                        if normalize_text(prompt_content) == normalize_text(&file_content) {
-                            // This prompt was in the file, clear it
-                            std::fs::write(&path, "").map_err(|e| {
-                                tracing::error!("Failed to clear prompt file: {}", e);
-                                e.to_string()
-                            })?;
-                        }
-                    }
-                }
-            }
-        }
+/// Validate the request ID header name; empty disables request IDs
+fn check_request_id_header(name: &str) -> Result<Option<String>> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Ok(None);
     }
+    reqwest::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| format!("Invalid header name: '{}'", name))?;
+    Ok(Some(name.to_string()))
+}
 
-    Ok(())
+fn check_listen_address(addr: &str) -> Result<Option<String>> {
+    let addr = addr.trim();
+    if addr.is_empty() {
+        return Ok(None);
+    }
+    addr.parse::<std::net::IpAddr>()
+        .map_err(|_| format!("Invalid listen address: '{}'", addr))?;
+    Ok(Some(addr.to_string()))
 }
 
-async fn sync_prompt_configs_to_cli(_db: State<'_, SqlitePool>) -> Result<()> {
-    // This function is no longer used, keeping for compatibility
-    Ok(())
+/// Host to bind when LAN access is enabled (listen_address, or all interfaces).
+/// Read once at startup; changes apply after a restart.
+pub async fn external_listen_host(db: &SqlitePool) -> Option<String> {
+    let (listen_external, listen_address) = sqlx::query_as::<_, (i64, Option<String>)>(
+        "SELECT listen_external, listen_address FROM gateway_settings WHERE id = 1",
+    )
+    .fetch_one(db)
+    .await
+    .ok()?;
+    if listen_external == 0 {
+        return None;
+    }
+    Some(
+        listen_address
+            .filter(|addr| addr.parse::<std::net::IpAddr>().is_ok())
+            .unwrap_or_else(|| "0.0.0.0".to_string()),
+    )
 }
 
-fn get_prompt_file_path(cli_type: &str) -> Option<std::path::PathBuf> {
-    let home = dirs::home_dir()?;
-    match cli_type {
-        "claude_code" => Some(home.join(".claude").join("CLAUDE.md")),
-        "codex" => Some(home.join(".codex").join("AGENTS.md")),
-        "gemini" => Some(home.join(".gemini").join("GEMINI.md")),
-        _ => None,
+fn check_max_request_body_mb(mb: i64) -> Result<()> {
+    if !(1..=MAX_REQUEST_BODY_MB_LIMIT).contains(&mb) {
+        return Err(format!(
+            "max_request_body_mb must be between 1 and {}",
+            MAX_REQUEST_BODY_MB_LIMIT
+        ));
     }
+    Ok(())
 }
 
-// Stats commands
 #[tauri::command]
-pub async fn get_daily_stats(
-    log_db: State<'_, crate::LogDb>,
-    start_date: Option<String>,
-    end_date: Option<String>,
-    cli_type: Option<String>,
-) -> Result<Vec<DailyStats>> {
-    let pool = &log_db.0;
+pub async fn get_timeout_settings(db: State<'_, SqlitePool>) -> Result<TimeoutSettings> {
+    load_timeout_settings(db.inner()).await
+}
 
-    let mut query = "SELECT * FROM usage_daily WHERE 1=1".to_string();
-    if start_date.is_some() {
-        query.push_str(" AND usage_date >= ?");
-    }
-    if end_date.is_some() {
-        query.push_str(" AND usage_date <= ?");
-    }
-    if cli_type.is_some() {
-        query.push_str(" AND cli_type = ?");
-    }
-    query.push_str(" ORDER BY usage_date DESC");
+pub async fn load_timeout_settings(db: &SqlitePool) -> Result<TimeoutSettings> {
+    sqlx::query_as::<_, TimeoutSettings>(
+        "SELECT stream_first_byte_timeout, stream_idle_timeout, non_stream_timeout, stream_per_token_timeout_ms, stream_keepalive_interval FROM timeout_settings WHERE id = 1",
+    )
+    .fetch_one(db)
+    .await
+    .map_err(|e| e.to_string())
+}
 
-    let mut q = sqlx::query_as::<_, DailyStats>(&query);
-    if let Some(ref sd) = start_date {
-        q = q.bind(sd);
-    }
-    if let Some(ref ed) = end_date {
-        q = q.bind(ed);
-    }
-    if let Some(ref ct) = cli_type {
-        q = q.bind(ct);
-    }
+#[tauri::command]
+pub async fn update_timeout_settings(
+    db: State<'_, SqlitePool>,
+    input: TimeoutSettingsUpdate,
+) -> Result<()> {
+    save_timeout_settings(db.inner(), input).await
+}
 
-    q.fetch_all(pool).await.map_err(|e| e.to_string())
+pub async fn save_timeout_settings(db: &SqlitePool, input: TimeoutSettingsUpdate) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    let current = load_timeout_settings(db).await?;
+
+    sqlx::query(
+        "UPDATE timeout_settings SET stream_first_byte_timeout = ?, stream_idle_timeout = ?, non_stream_timeout = ?, stream_per_token_timeout_ms = ?, stream_keepalive_interval = ?, updated_at = ? WHERE id = 1",
+    )
+    .bind(input.stream_first_byte_timeout.unwrap_or(current.stream_first_byte_timeout))
+    .bind(input.stream_idle_timeout.unwrap_or(current.stream_idle_timeout))
+    .bind(input.non_stream_timeout.unwrap_or(current.non_stream_timeout))
+    .bind(input.stream_per_token_timeout_ms.unwrap_or(current.stream_per_token_timeout_ms).max(0))
+    .bind(input.stream_keepalive_interval.unwrap_or(current.stream_keepalive_interval).max(0))
+    .bind(now)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
 }
 
 #[tauri::command]
-pub async fn get_provider_stats(
-    log_db: State<'_, crate::LogDb>,
-    start_date: Option<String>,
-    end_date: Option<String>,
-    cli_type: Option<String>,
-    provider_name: Option<String>,
-) -> Result<Vec<ProviderStatsResponse>> {
-    let pool = &log_db.0;
+pub async fn get_cli_settings(
+    db: State<'_, SqlitePool>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    cli_type: String,
+) -> Result<CliSettingsResponse> {
+    load_cli_settings(db.inner(), &gateway_port, cli_type).await
+}
 
-    let mut query = r#"
-        SELECT
-            cli_type,
-            provider_name,
-            model_id,
-            COUNT(*) as total_requests,
-            SUM(CASE WHEN status_code >= 200 AND status_code < 300 THEN 1 ELSE 0 END) as total_success,
-            SUM(input_tokens + output_tokens) as total_tokens,
-            SUM(elapsed_ms) as total_elapsed_ms
-        FROM request_logs
-        WHERE 1=1
-    "#.to_string();
+pub async fn load_cli_settings(
+    db: &SqlitePool,
+    gateway_port: &crate::GatewayPort,
+    cli_type: String,
+) -> Result<CliSettingsResponse> {
+    let row = sqlx::query_as::<_, CliSettingsRow>(
+        "SELECT cli_type, default_json_config, prompt_append_mode, updated_at FROM cli_settings WHERE cli_type = ?",
+    )
+    .bind(&cli_type)
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
 
-    if start_date.is_some() {
-        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') >= ?");
-    }
-    if end_date.is_some() {
-        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') <= ?");
-    }
-    if cli_type.is_some() {
-        query.push_str(" AND cli_type = ?");
-    }
-    if provider_name.is_some() {
-        query.push_str(" AND provider_name = ?");
+    if let Some(row) = row {
+        // Check if CLI is enabled by reading config file
+        let enabled = check_cli_enabled(&cli_type, gateway_port.for_cli(&cli_type));
+        Ok(CliSettingsResponse {
+            cli_type: row.cli_type,
+            enabled,
+            default_json_config: row.default_json_config.unwrap_or_default(),
+            prompt_append_mode: row.prompt_append_mode != 0,
+        })
+    } else {
+        Ok(CliSettingsResponse {
+            cli_type,
+            enabled: false,
+            default_json_config: String::new(),
+            prompt_append_mode: false,
+        })
     }
-    query.push_str(" GROUP BY cli_type, provider_name, model_id ORDER BY total_requests DESC");
+}
 
-    let mut q = sqlx::query_as::<_, ProviderStatsRow>(&query);
-    if let Some(ref sd) = start_date {
-        q = q.bind(sd);
+#[tauri::command]
+pub async fn update_cli_settings(
+    db: State<'_, SqlitePool>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    cli_sync: State<'_, CliSyncState>,
+    cli_type: String,
+    input: CliSettingsUpdate,
+) -> Result<()> {
+    save_cli_settings(db.inner(), &gateway_port, &cli_sync, cli_type, input).await
+}
+
+pub async fn save_cli_settings(
+    db: &SqlitePool,
+    gateway_port: &crate::GatewayPort,
+    cli_sync: &CliSyncState,
+    cli_type: String,
+    input: CliSettingsUpdate,
+) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+
+    // Validate and update database
+    if let Some(ref config) = input.default_json_config {
+        let config_trimmed = config.trim();
+
+        // Validate format if config is not empty
+        if !config_trimmed.is_empty() {
+            match cli_type.as_str() {
+                "claude_code" | "gemini" => {
+                    // Validate JSON format
+                    serde_json::from_str::<serde_json::Value>(config_trimmed)
+                        .map_err(|e| format!("JSON 格式错误: {}", e))?;
+                }
+                "codex" => {
+                    // Validate TOML format
+                    config_trimmed.parse::<toml_edit::DocumentMut>()
+                        .map_err(|e| format!("TOML 格式错误: {}", e))?;
+                }
+                _ => {}
+            }
+        }
+
+        sqlx::query(
+            "UPDATE cli_settings SET default_json_config = ?, updated_at = ? WHERE cli_type = ?",
+        )
+        .bind(config_trimmed)
+        .bind(now)
+        .bind(&cli_type)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
     }
-    if let Some(ref ed) = end_date {
-        q = q.bind(ed);
+
+    if let Some(append) = input.prompt_append_mode {
+        sqlx::query("UPDATE cli_settings SET prompt_append_mode = ?, updated_at = ? WHERE cli_type = ?")
+            .bind(append as i64)
+            .bind(now)
+            .bind(&cli_type)
+            .execute(db)
+            .await
+            .map_err(|e| e.to_string())?;
     }
-    if let Some(ref ct) = cli_type {
-        q = q.bind(ct);
+
+    // Update CLI config file if enabled flag is provided
+    if let Some(enabled) = input.enabled {
+        // Get default_json_config from database
+        let row = sqlx::query_as::<_, CliSettingsRow>(
+            "SELECT cli_type, default_json_config, prompt_append_mode, updated_at FROM cli_settings WHERE cli_type = ?",
+        )
+        .bind(&cli_type)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let default_config = row.and_then(|r| r.default_json_config).unwrap_or_default();
+        sync_cli_config(&cli_type, enabled, &default_config, gateway_port.for_cli(&cli_type), db).await?;
+        cli_sync.mark_synced(&cli_type, enabled);
     }
-    if let Some(ref pn) = provider_name {
-        q = q.bind(pn);
+
+    Ok(())
+}
+
+// Budget settings
+#[tauri::command]
+pub async fn get_budget_settings(db: State<'_, SqlitePool>) -> Result<Vec<BudgetSettings>> {
+    sqlx::query_as::<_, BudgetSettings>(
+        "SELECT cli_type, daily_input_token_limit, daily_output_token_limit, warning_threshold_pct, block_when_exceeded FROM budget_settings ORDER BY cli_type",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn update_budget_settings(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    cli_type: String,
+    input: BudgetSettingsUpdate,
+) -> Result<()> {
+    cli_type.parse::<crate::services::proxy::CliType>()?;
+    let warning_threshold_pct = input.warning_threshold_pct.unwrap_or(80);
+    if !(1..=100).contains(&warning_threshold_pct) {
+        return Err("Warning threshold must be between 1 and 100".to_string());
     }
+    let now = chrono::Utc::now().timestamp();
 
-    let rows = q.fetch_all(pool).await.map_err(|e| e.to_string())?;
+    sqlx::query(
+        r#"
+        INSERT INTO budget_settings (cli_type, daily_input_token_limit, daily_output_token_limit, warning_threshold_pct, block_when_exceeded, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ON CONFLICT(cli_type) DO UPDATE SET
+            daily_input_token_limit = excluded.daily_input_token_limit,
+            daily_output_token_limit = excluded.daily_output_token_limit,
+            warning_threshold_pct = excluded.warning_threshold_pct,
+            block_when_exceeded = excluded.block_when_exceeded,
+            updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(&cli_type)
+    .bind(input.daily_input_token_limit.filter(|v| *v > 0))
+    .bind(input.daily_output_token_limit.filter(|v| *v > 0))
+    .bind(warning_threshold_pct)
+    .bind(input.block_when_exceeded.unwrap_or(false) as i64)
+    .bind(now)
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
 
-    let results = rows.into_iter().map(|row| ProviderStatsResponse {
-        cli_type: row.cli_type,
-        provider_name: row.provider_name,
-        model_id: row.model_id,
-        total_requests: row.total_requests,
-        total_success: row.total_success,
-        total_tokens: row.total_tokens,
-        total_elapsed_ms: row.total_elapsed_ms,
-        success_rate: if row.total_requests > 0 {
-            (row.total_success as f64 / row.total_requests as f64) * 100.0
-        } else {
-            0.0
-        },
-    }).collect();
+    // 按新限额立即重新判断：仍超额的保持（或开始）拉黑，其余恢复
+    crate::services::budget::reconcile_budget_blocks(db.inner(), &log_db.0, &cli_type)
+        .await
+        .map_err(|e| e.to_string())?;
 
-    Ok(results)
+    Ok(())
 }
 
-// Session helpers
-fn get_cli_base_dir(cli_type: &str) -> std::path::PathBuf {
-    let home = dirs::home_dir().unwrap_or_default();
-    match cli_type {
-        "codex" => home.join(".codex"),
-        "gemini" => home.join(".gemini"),
-        _ => home.join(".claude"),
-    }
+/// Whether each CLI config file still matches what the gateway last wrote
+#[tauri::command]
+pub async fn get_cli_sync_status(cli_sync: State<'_, CliSyncState>) -> Result<Vec<CliSyncStatus>> {
+    Ok(CLI_TYPES.into_iter().map(|cli_type| cli_sync.status(cli_type)).collect())
 }
 
-// Extract cwd from Codex session file
-fn extract_codex_cwd(file_path: &std::path::Path) -> Option<String> {
-    use std::io::{BufRead, BufReader};
-    let file = std::fs::File::open(file_path).ok()?;
-    let reader = BufReader::new(file);
-    
-    for line in reader.lines().flatten() {
-        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
-            if data.get("type").and_then(|t| t.as_str()) == Some("session_meta") {
-                if let Some(cwd) = data.get("payload")
-                    .and_then(|p| p.get("cwd"))
-                    .and_then(|c| c.as_str()) {
-                    return Some(cwd.to_string());
-                }
-            }
-        }
-    }
-    None
+// Normalize text for comparison: trim, normalize whitespace, remove extra blank lines
+fn normalize_text(text: &str) -> String {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n")
 }
 
-// Handle Codex projects (group sessions by cwd)
-fn get_codex_projects(sessions_dir: std::path::PathBuf, page: i64, page_size: i64) -> Result<PaginatedProjects> {
-    use std::collections::HashMap;
-    use walkdir::WalkDir;
-    
+// Check if MCP config exists in the CLI config file
+fn mcp_enabled_in_file(cli_type: &str, mcp_name: &str) -> bool {
+    let home = match dirs::home_dir() {
+        Some(h) => h,
+        None => return false,
+    };
+
+    match cli_type {
+        "claude_code" => {
+            let path = home.join(".claude.json");
+            if !path.exists() {
+                return false;
+            }
+            let content = match std::fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(_) => return false,
+            };
+            match serde_json::from_str::<serde_json::Value>(&content) {
+                Ok(config) => {
+                    config.get("mcpServers")
+                        .and_then(|v| v.as_object())
+                        .map(|servers| servers.contains_key(mcp_name))
+                        .unwrap_or(false)
+                }
+                Err(_) => false,
+            }
+        }
+        "gemini" => {
+            let path = home.join(".gemini").join("settings.json");
+            if !path.exists() {
+                return false;
+            }
+            let content = match std::fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(_) => return false,
+            };
+            match serde_json::from_str::<serde_json::Value>(&content) {
+                Ok(config) => {
+                    config.get("mcpServers")
+                        .and_then(|v| v.as_object())
+                        .map(|servers| servers.contains_key(mcp_name))
+                        .unwrap_or(false)
+                }
+                Err(_) => false,
+            }
+        }
+        "codex" => {
+            let path = home.join(".codex").join("config.toml");
+            if !path.exists() {
+                return false;
+            }
+            let content = match std::fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(_) => return false,
+            };
+            match content.parse::<toml_edit::DocumentMut>() {
+                Ok(doc) => {
+                    doc.get("mcp_servers")
+                        .and_then(|v| v.as_table())
+                        .map(|servers| servers.contains_key(mcp_name))
+                        .unwrap_or(false)
+                }
+                Err(_) => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+// Check if prompt content matches the file content
+fn prompt_enabled_in_file(cli_type: &str, prompt_content: &str) -> bool {
+    let home = match dirs::home_dir() {
+        Some(h) => h,
+        None => return false,
+    };
+
+    let prompt_path = match cli_type {
+        "claude_code" => home.join(".claude").join("CLAUDE.md"),
+        "codex" => home.join(".codex").join("AGENTS.md"),
+        "gemini" => home.join(".gemini").join("GEMINI.md"),
+        _ => return false,
+    };
+
+    if !prompt_path.exists() {
+        return false;
+    }
+
+    let file_content = match std::fs::read_to_string(&prompt_path) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+
+    // Normalize and compare; in append mode the prompt is one section of the file
+    normalize_text(prompt_content) == normalize_text(&file_content)
+        || find_prompt_section(&file_content, prompt_content).is_some()
+}
+
+/// Separator line between prompts appended to the same CLI prompt file
+const PROMPT_SECTION_SEPARATOR: &str = "---";
+
+/// Line range (inclusive) of `prompt_content` inside `file_content`, comparing trimmed
+/// non-empty lines so whitespace edits made by hand don't hide the section
+fn find_prompt_section(file_content: &str, prompt_content: &str) -> Option<(usize, usize)> {
+    let prompt_lines: Vec<&str> = prompt_content.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    if prompt_lines.is_empty() {
+        return None;
+    }
+    let file_lines: Vec<(usize, &str)> = file_content
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, l)| !l.is_empty())
+        .collect();
+
+    file_lines
+        .windows(prompt_lines.len())
+        .find(|window| window.iter().map(|(_, l)| *l).eq(prompt_lines.iter().copied()))
+        .map(|window| (window[0].0, window[window.len() - 1].0))
+}
+
+/// Append `prompt_content` below a `---` separator (no-op when already present)
+fn append_prompt_section(file_content: &str, prompt_content: &str) -> String {
+    if find_prompt_section(file_content, prompt_content).is_some() {
+        return file_content.to_string();
+    }
+    let existing = file_content.trim_end();
+    if existing.is_empty() {
+        format!("{}\n", prompt_content.trim())
+    } else {
+        format!("{}\n\n{}\n\n{}\n", existing, PROMPT_SECTION_SEPARATOR, prompt_content.trim())
+    }
+}
+
+/// Remove `prompt_content` and its adjoining separator, leaving the other sections as they are.
+/// Returns None when the prompt is not in the file.
+fn remove_prompt_section(file_content: &str, prompt_content: &str) -> Option<String> {
+    let (start, end) = find_prompt_section(file_content, prompt_content)?;
+    let lines: Vec<&str> = file_content.lines().collect();
+    let is_separator = |i: usize| lines[i].trim() == PROMPT_SECTION_SEPARATOR;
+    let is_blank = |i: usize| lines[i].trim().is_empty();
+
+    // 优先去掉前面的分隔线（第一段则去掉后面的），连同中间的空行
+    let mut before = start;
+    while before > 0 && is_blank(before - 1) {
+        before -= 1;
+    }
+    let mut after = end + 1;
+    while after < lines.len() && is_blank(after) {
+        after += 1;
+    }
+    let (from, to) = if before > 0 && is_separator(before - 1) {
+        let mut from = before - 1;
+        while from > 0 && is_blank(from - 1) {
+            from -= 1;
+        }
+        (from, end + 1)
+    } else if after < lines.len() && is_separator(after) {
+        let mut to = after + 1;
+        while to < lines.len() && is_blank(to) {
+            to += 1;
+        }
+        (start, to)
+    } else {
+        (start, end + 1)
+    };
+
+    let remaining: Vec<&str> = lines[..from].iter().chain(lines[to..].iter()).copied().collect();
+    let remaining = remaining.join("\n");
+    let remaining = remaining.trim();
+    Some(if remaining.is_empty() { String::new() } else { format!("{}\n", remaining) })
+}
+
+pub fn check_cli_enabled(cli_type: &str, port: u16) -> bool {
+    match cli_type {
+        "claude_code" => check_claude_uses_gateway(port),
+        "codex" => check_codex_uses_gateway(),
+        "gemini" => check_gemini_uses_gateway(port),
+        _ => false,
+    }
+}
+
+/// Gateway address written into CLI config files; `path_prefix` is "" or e.g. "/claude"
+fn gateway_url(port: u16, path_prefix: &str) -> String {
+    format!("http://127.0.0.1:{}{}", port, path_prefix)
+}
+
+/// Whether gateway_settings.cli_path_prefix is on
+pub async fn cli_path_prefix_enabled(db: &SqlitePool) -> bool {
+    sqlx::query_scalar::<_, i64>("SELECT cli_path_prefix FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+        .is_some_and(|v| v != 0)
+}
+
+/// Path prefix to append to the gateway URL in the config of `cli_type`
+fn cli_url_prefix(cli_type: &str, enabled: bool) -> &'static str {
+    match cli_type.parse::<crate::services::proxy::CliType>() {
+        Ok(cli_type) if enabled => cli_type.path_prefix(),
+        _ => "",
+    }
+}
+
+/// Whether a base URL points at a local gateway on the given port
+fn is_gateway_url(url: &str, port: u16) -> bool {
+    url.contains(&format!("127.0.0.1:{}", port)) || url.contains(&format!("localhost:{}", port))
+}
+
+/// URLs the gateway last wrote into each CLI config (cli_type -> url)
+async fn load_cli_config_urls(db: &SqlitePool) -> std::collections::HashMap<String, String> {
+    sqlx::query_scalar::<_, String>("SELECT cli_config_urls FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+/// Remember (Some) or forget (None) the URL written into the config of `cli_type`
+async fn record_cli_config_url(db: &SqlitePool, cli_type: &str, url: Option<&str>) -> Result<()> {
+    let mut urls = load_cli_config_urls(db).await;
+    match url {
+        Some(url) => urls.insert(cli_type.to_string(), url.to_string()),
+        None => urls.remove(cli_type),
+    };
+    let raw = serde_json::to_string(&urls).map_err(|e| e.to_string())?;
+    sqlx::query("UPDATE gateway_settings SET cli_config_urls = ? WHERE id = 1")
+        .bind(raw)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Whether `url` is exactly what the gateway wrote for `cli_type` (so not a local proxy the user set up)
+fn is_written_gateway_url(written: &std::collections::HashMap<String, String>, cli_type: &str, url: &str) -> bool {
+    written
+        .get(cli_type)
+        .is_some_and(|w| w.trim_end_matches('/') == url.trim().trim_end_matches('/'))
+}
+
+/// Whether a base URL points at a local gateway on any port (e.g. from a previous run)
+fn is_loopback_gateway_url(url: &str) -> bool {
+    regex::Regex::new(r"^https?://(127\.0\.0\.1|localhost):\d+(/(claude|codex|gemini))?/?$")
+        .map(|re| re.is_match(url.trim()))
+        .unwrap_or(false)
+}
+
+fn check_claude_uses_gateway(port: u16) -> bool {
+    let Some(home) = dirs::home_dir() else {
+        return false;
+    };
+    let config_path = home.join(".claude").join("settings.json");
+
+    if !config_path.exists() {
+        return false;
+    }
+
+    let content = match std::fs::read_to_string(&config_path) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+
+    let content_trimmed = content.trim();
+    if content_trimmed.is_empty() || content_trimmed == "{}" {
+        return false;
+    }
+
+    match serde_json::from_str::<serde_json::Value>(content_trimmed) {
+        Ok(data) => {
+            if let Some(env) = data.get("env") {
+                if let Some(base_url) = env.get("ANTHROPIC_BASE_URL").and_then(|v| v.as_str()) {
+                    return is_gateway_url(base_url, port);
+                }
+            }
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+fn check_codex_uses_gateway() -> bool {
+    let Some(home) = dirs::home_dir() else {
+        return false;
+    };
+    let config_path = home.join(".codex").join("config.toml");
+
+    if !config_path.exists() {
+        return false;
+    }
+
+    let content = match std::fs::read_to_string(&config_path) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+
+    if content.trim().is_empty() {
+        return false;
+    }
+
+    match content.parse::<toml_edit::DocumentMut>() {
+        Ok(doc) => {
+            // Check if model_provider is "ccg-gateway"
+            if let Some(provider) = doc.get("model_provider").and_then(|v| v.as_str()) {
+                if provider == "ccg-gateway" {
+                    return true;
+                }
+            }
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+fn check_gemini_uses_gateway(port: u16) -> bool {
+    let Some(home) = dirs::home_dir() else {
+        return false;
+    };
+    let env_path = home.join(".gemini").join(".env");
+
+    if !env_path.exists() {
+        return false;
+    }
+
+    let content = match std::fs::read_to_string(&env_path) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+
+    // Check if .env contains GOOGLE_GEMINI_BASE_URL pointing to gateway
+    for line in content.lines() {
+        if line.starts_with("GOOGLE_GEMINI_BASE_URL=") {
+            let url = line.split('=').nth(1).unwrap_or("");
+            return is_gateway_url(url, port);
+        }
+    }
+    false
+}
+
+/// Point CLI config files that still carry the URL the gateway wrote at the actual listening port
+/// (the dedicated listener of that CLI, if any). Other loopback URLs, e.g. a local proxy in front
+/// of the gateway, are left alone. Returns the files that were rewritten.
+pub async fn retarget_cli_configs(db: &SqlitePool, gateway_port: &crate::GatewayPort, path_prefix: bool) -> Vec<std::path::PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    let written = load_cli_config_urls(db).await;
+    let mut changed = Vec::new();
+    let mut retargeted = Vec::new();
+
+    // Claude Code: settings.json -> env.ANTHROPIC_BASE_URL
+    let new_url = gateway_url(gateway_port.for_cli("claude_code"), cli_url_prefix("claude_code", path_prefix));
+    let claude_path = home.join(".claude").join("settings.json");
+    if let Some(mut data) = std::fs::read_to_string(&claude_path)
+        .ok()
+        .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+    {
+        let base_url = data
+            .pointer("/env/ANTHROPIC_BASE_URL")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        if let Some(base_url) = base_url {
+            if is_written_gateway_url(&written, "claude_code", &base_url) && base_url.trim_end_matches('/') != new_url {
+                data["env"]["ANTHROPIC_BASE_URL"] = serde_json::Value::String(new_url.clone());
+                if let Ok(content) = serde_json::to_string_pretty(&data) {
+                    if std::fs::write(&claude_path, content).is_ok() {
+                        changed.push(claude_path);
+                        retargeted.push(("claude_code", new_url));
+                    }
+                }
+            }
+        }
+    }
+
+    // Codex: config.toml -> model_providers.ccg-gateway.base_url
+    let new_url = gateway_url(gateway_port.for_cli("codex"), cli_url_prefix("codex", path_prefix));
+    let codex_path = home.join(".codex").join("config.toml");
+    if let Some(mut doc) = std::fs::read_to_string(&codex_path)
+        .ok()
+        .and_then(|c| c.parse::<toml_edit::DocumentMut>().ok())
+    {
+        let base_url = doc
+            .get("model_providers")
+            .and_then(|p| p.get("ccg-gateway"))
+            .and_then(|g| g.get("base_url"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        if let Some(base_url) = base_url {
+            if is_written_gateway_url(&written, "codex", &base_url) && base_url.trim_end_matches('/') != new_url {
+                doc["model_providers"]["ccg-gateway"]["base_url"] = toml_edit::value(new_url.clone());
+                if std::fs::write(&codex_path, doc.to_string()).is_ok() {
+                    changed.push(codex_path);
+                    retargeted.push(("codex", new_url));
+                }
+            }
+        }
+    }
+
+    // Gemini: .env -> GOOGLE_GEMINI_BASE_URL
+    let new_url = gateway_url(gateway_port.for_cli("gemini"), cli_url_prefix("gemini", path_prefix));
+    let gemini_env_path = home.join(".gemini").join(".env");
+    if let Ok(content) = std::fs::read_to_string(&gemini_env_path) {
+        let mut modified = false;
+        let lines: Vec<String> = content
+            .lines()
+            .map(|line| match line.strip_prefix("GOOGLE_GEMINI_BASE_URL=") {
+                Some(url) if is_written_gateway_url(&written, "gemini", url) && url.trim_end_matches('/') != new_url => {
+                    modified = true;
+                    format!("GOOGLE_GEMINI_BASE_URL={}", new_url)
+                }
+                _ => line.to_string(),
+            })
+            .collect();
+        if modified && std::fs::write(&gemini_env_path, lines.join("\n") + "\n").is_ok() {
+            changed.push(gemini_env_path);
+            retargeted.push(("gemini", new_url));
+        }
+    }
+
+    for (cli_type, url) in retargeted {
+        if let Err(e) = record_cli_config_url(db, cli_type, Some(&url)).await {
+            tracing::warn!("Failed to record the {} config URL: {}", cli_type, e);
+        }
+    }
+    changed
+}
+
+/// Placeholder credential written into CLI configs before gateway_token existed
+const LEGACY_GATEWAY_TOKEN: &str = "ccg-gateway";
+
+fn generate_gateway_token() -> String {
+    format!("ccg-{}", uuid::Uuid::new_v4().simple())
+}
+
+async fn gateway_token(db: &SqlitePool) -> Result<String> {
+    sqlx::query_scalar::<_, Option<String>>("SELECT gateway_token FROM gateway_settings WHERE id = 1")
+        .fetch_one(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .filter(|t| !t.is_empty())
+        .ok_or_else(|| "Gateway token has not been generated".to_string())
+}
+
+/// Generate the gateway token on first run and move CLI configs still using the
+/// old placeholder credential over to it. Returns the token.
+pub async fn ensure_gateway_token(db: &SqlitePool) -> Result<String> {
+    if let Ok(token) = gateway_token(db).await {
+        return Ok(token);
+    }
+    let token = generate_gateway_token();
+    sqlx::query("UPDATE gateway_settings SET gateway_token = ?, updated_at = ? WHERE id = 1")
+        .bind(&token)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let updated = retarget_cli_tokens(&[LEGACY_GATEWAY_TOKEN], &token);
+    if !updated.is_empty() {
+        tracing::info!("Updated CLI configs to the gateway token: {:?}", updated);
+    }
+    Ok(token)
+}
+
+/// Replace a new random gateway token; CLI configs using the previous one are updated in place
+#[tauri::command]
+pub async fn regenerate_gateway_token(db: State<'_, SqlitePool>, log_db: State<'_, LogDb>) -> Result<String> {
+    let old_token = gateway_token(db.inner()).await.ok();
+    let token = generate_gateway_token();
+    sqlx::query("UPDATE gateway_settings SET gateway_token = ?, updated_at = ? WHERE id = 1")
+        .bind(&token)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let old_tokens: Vec<&str> = old_token.iter().map(String::as_str).chain([LEGACY_GATEWAY_TOKEN]).collect();
+    let updated = retarget_cli_tokens(&old_tokens, &token);
+
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "info",
+        "gateway_token_regenerated",
+        &format!("Gateway token regenerated, {} CLI config file(s) updated", updated.len()),
+        None,
+        None,
+    ).await;
+    Ok(token)
+}
+
+/// Swap the gateway credential in CLI configs that point at a local gateway and still carry one of `old_tokens`.
+/// Returns the files that were rewritten.
+fn retarget_cli_tokens(old_tokens: &[&str], new_token: &str) -> Vec<std::path::PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    let mut changed = Vec::new();
+
+    // Claude Code: settings.json -> env.ANTHROPIC_AUTH_TOKEN
+    let claude_path = home.join(".claude").join("settings.json");
+    if let Some(mut data) = std::fs::read_to_string(&claude_path)
+        .ok()
+        .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+    {
+        let uses_gateway = data
+            .pointer("/env/ANTHROPIC_BASE_URL")
+            .and_then(|v| v.as_str())
+            .is_some_and(is_loopback_gateway_url);
+        let has_old_token = data
+            .pointer("/env/ANTHROPIC_AUTH_TOKEN")
+            .and_then(|v| v.as_str())
+            .is_some_and(|t| old_tokens.contains(&t));
+        if uses_gateway && has_old_token {
+            data["env"]["ANTHROPIC_AUTH_TOKEN"] = serde_json::Value::String(new_token.to_string());
+            if let Ok(content) = serde_json::to_string_pretty(&data) {
+                if std::fs::write(&claude_path, content).is_ok() {
+                    changed.push(claude_path);
+                }
+            }
+        }
+    }
+
+    // Codex: config.toml -> model_providers.ccg-gateway.experimental_bearer_token, auth.json -> OPENAI_API_KEY
+    let codex_path = home.join(".codex").join("config.toml");
+    let codex_uses_gateway = if let Some(mut doc) = std::fs::read_to_string(&codex_path)
+        .ok()
+        .and_then(|c| c.parse::<toml_edit::DocumentMut>().ok())
+    {
+        let gateway = doc
+            .get("model_providers")
+            .and_then(|p| p.get("ccg-gateway"))
+            .filter(|g| g.get("base_url").and_then(|v| v.as_str()).is_some_and(is_loopback_gateway_url));
+        let uses_gateway = gateway.is_some();
+        let current = gateway.and_then(|g| g.get("experimental_bearer_token")).and_then(|v| v.as_str());
+        if uses_gateway && current.is_none_or(|t| old_tokens.contains(&t)) {
+            doc["model_providers"]["ccg-gateway"]["experimental_bearer_token"] = toml_edit::value(new_token);
+            if std::fs::write(&codex_path, doc.to_string()).is_ok() {
+                changed.push(codex_path);
+            }
+        }
+        uses_gateway
+    } else {
+        false
+    };
+    let codex_auth_path = home.join(".codex").join("auth.json");
+    if codex_uses_gateway {
+        if let Some(mut auth) = std::fs::read_to_string(&codex_auth_path)
+            .ok()
+            .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok())
+        {
+            let has_old_token = auth
+                .get("OPENAI_API_KEY")
+                .and_then(|v| v.as_str())
+                .is_some_and(|t| old_tokens.contains(&t));
+            if has_old_token {
+                auth["OPENAI_API_KEY"] = serde_json::Value::String(new_token.to_string());
+                if let Ok(content) = serde_json::to_string_pretty(&auth) {
+                    if std::fs::write(&codex_auth_path, content).is_ok() {
+                        changed.push(codex_auth_path);
+                    }
+                }
+            }
+        }
+    }
+
+    // Gemini: .env -> GEMINI_API_KEY
+    let gemini_env_path = home.join(".gemini").join(".env");
+    if let Ok(content) = std::fs::read_to_string(&gemini_env_path) {
+        let uses_gateway = content
+            .lines()
+            .filter_map(|line| line.strip_prefix("GOOGLE_GEMINI_BASE_URL="))
+            .any(is_loopback_gateway_url);
+        let mut modified = false;
+        let lines: Vec<String> = content
+            .lines()
+            .map(|line| match line.strip_prefix("GEMINI_API_KEY=") {
+                Some(key) if uses_gateway && old_tokens.contains(&key.trim()) => {
+                    modified = true;
+                    format!("GEMINI_API_KEY={}", new_token)
+                }
+                _ => line.to_string(),
+            })
+            .collect();
+        if modified && std::fs::write(&gemini_env_path, lines.join("\n") + "\n").is_ok() {
+            changed.push(gemini_env_path);
+        }
+    }
+
+    changed
+}
+
+// Get the config file path for MCP/prompts sync (different for Codex)
+fn get_mcp_config_path(cli_type: &str) -> Option<std::path::PathBuf> {
+    let home = dirs::home_dir()?;
+    match cli_type {
+        "claude_code" => Some(home.join(".claude.json")),  // Claude Code MCP goes to ~/.claude.json
+        "codex" => Some(home.join(".codex").join("config.toml")),  // Codex MCP goes to config.toml
+        "gemini" => Some(home.join(".gemini").join("settings.json")),
+        _ => None,
+    }
+}
+
+async fn sync_cli_config(cli_type: &str, enabled: bool, default_config: &str, port: u16, db: &SqlitePool) -> Result<()> {
+    match cli_type {
+        "claude_code" => sync_claude_code_config(enabled, default_config, port, db).await?,
+        "codex" => sync_codex_config(enabled, default_config, port, db).await?,
+        "gemini" => sync_gemini_config(enabled, default_config, port, db).await?,
+        _ => return Err("Invalid CLI type".to_string()),
+    }
+    // 记录写入的地址，retarget_cli_configs 只改写网关自己写入的配置
+    let url = if enabled {
+        Some(gateway_url(port, cli_url_prefix(cli_type, cli_path_prefix_enabled(db).await)))
+    } else {
+        None
+    };
+    record_cli_config_url(db, cli_type, url.as_deref()).await
+}
+
+fn get_backup_path(original_path: &std::path::Path) -> std::path::PathBuf {
+    let file_name = original_path.file_name().unwrap().to_str().unwrap();
+    original_path.parent().unwrap().join(format!("{}.ccg-backup", file_name))
+}
+
+fn backup_file(path: &std::path::Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let backup_path = get_backup_path(path);
+    std::fs::copy(path, &backup_path).map_err(|e| {
+        tracing::error!("Failed to backup {}: {}", path.display(), e);
+        e.to_string()
+    })?;
+    Ok(())
+}
+
+fn restore_backup(path: &std::path::Path) -> Result<bool> {
+    let backup_path = get_backup_path(path);
+    if !backup_path.exists() {
+        return Ok(false);
+    }
+    std::fs::copy(&backup_path, path).map_err(|e| {
+        tracing::error!("Failed to restore backup from {}: {}", backup_path.display(), e);
+        e.to_string()
+    })?;
+    std::fs::remove_file(&backup_path).map_err(|e| {
+        tracing::warn!("Failed to remove backup file {}: {}", backup_path.display(), e);
+        e.to_string()
+    })?;
+    Ok(true)
+}
+
+fn has_backup(path: &std::path::Path) -> bool {
+    get_backup_path(path).exists()
+}
+
+fn deep_merge(base: &mut serde_json::Value, override_val: &serde_json::Value) {
+    if let (Some(base_obj), Some(override_obj)) = (base.as_object_mut(), override_val.as_object()) {
+        for (key, value) in override_obj {
+            if let Some(base_value) = base_obj.get_mut(key) {
+                if base_value.is_object() && value.is_object() {
+                    deep_merge(base_value, value);
+                } else {
+                    *base_value = value.clone();
+                }
+            } else {
+                base_obj.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+// Sync Claude Code configuration (settings.json)
+async fn sync_claude_code_config(enabled: bool, default_config: &str, port: u16, db: &SqlitePool) -> Result<()> {
+    let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
+    let config_path = home.join(".claude").join("settings.json");
+
+    if enabled {
+        let token = gateway_token(db).await?;
+
+        // Backup existing config if not already backed up
+        if config_path.exists() && !has_backup(&config_path) {
+            backup_file(&config_path)?;
+        }
+
+        // Create config directory if it doesn't exist
+        if let Some(parent) = config_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                tracing::error!("Failed to create directory: {}", e);
+                e.to_string()
+            })?;
+        }
+
+        // Build base config with gateway address
+        let mut config = serde_json::json!({
+            "env": {
+                "ANTHROPIC_BASE_URL": gateway_url(port, cli_url_prefix("claude_code", cli_path_prefix_enabled(db).await)),
+                "ANTHROPIC_AUTH_TOKEN": token
+            }
+        });
+
+        // Merge user's custom config if provided
+        if !default_config.is_empty() {
+            match serde_json::from_str::<serde_json::Value>(default_config) {
+                Ok(custom_config) => {
+                    deep_merge(&mut config, &custom_config);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to parse custom config (invalid JSON): {}", e);
+                }
+            }
+        }
+
+        // Write config file
+        let config_str = serde_json::to_string_pretty(&config).map_err(|e| {
+            tracing::error!("Failed to serialize config: {}", e);
+            e.to_string()
+        })?;
+        std::fs::write(&config_path, config_str).map_err(|e| {
+            tracing::error!("Failed to write config file: {}", e);
+            e.to_string()
+        })?;
+    } else {
+        // When disabling, restore backup or remove config file
+        if restore_backup(&config_path)? {
+        } else if config_path.exists() {
+            // No backup, remove the config file
+            std::fs::remove_file(&config_path).map_err(|e| {
+                tracing::error!("Failed to remove config file: {}", e);
+                e.to_string()
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+// Sync Codex configuration (auth.json + config.toml)
+async fn sync_codex_config(enabled: bool, default_config: &str, port: u16, db: &SqlitePool) -> Result<()> {
+    let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
+    let codex_dir = home.join(".codex");
+    let auth_path = codex_dir.join("auth.json");
+    let config_path = codex_dir.join("config.toml");
+
+    if enabled {
+        let token = gateway_token(db).await?;
+
+        // Backup existing configs if not already backed up
+        if auth_path.exists() && !has_backup(&auth_path) {
+            backup_file(&auth_path)?;
+        }
+        if config_path.exists() && !has_backup(&config_path) {
+            backup_file(&config_path)?;
+        }
+
+        // Create config directory if it doesn't exist
+        std::fs::create_dir_all(&codex_dir).map_err(|e| {
+            tracing::error!("Failed to create Codex directory: {}", e);
+            e.to_string()
+        })?;
+
+        // Write auth.json with gateway API key
+        let auth = serde_json::json!({
+            "OPENAI_API_KEY": token
+        });
+        let auth_str = serde_json::to_string_pretty(&auth).map_err(|e| {
+            tracing::error!("Failed to serialize auth.json: {}", e);
+            e.to_string()
+        })?;
+        std::fs::write(&auth_path, auth_str).map_err(|e| {
+            tracing::error!("Failed to write auth.json: {}", e);
+            e.to_string()
+        })?;
+
+        // Build base config.toml pointing to gateway
+        let mut doc = toml_edit::DocumentMut::new();
+        doc["model_provider"] = toml_edit::value("ccg-gateway");
+
+        if !doc.contains_table("model_providers") {
+            doc["model_providers"] = toml_edit::table();
+        }
+
+        let mut gateway_table = toml_edit::Table::new();
+        gateway_table.insert("name", toml_edit::value("ccg-gateway"));
+        let base_url = gateway_url(port, cli_url_prefix("codex", cli_path_prefix_enabled(db).await));
+        gateway_table.insert("base_url", toml_edit::value(base_url));
+        gateway_table.insert("wire_api", toml_edit::value("responses"));
+        gateway_table.insert("requires_openai_auth", toml_edit::value(false));
+        // requires_openai_auth = false 时 Codex 不读 auth.json，令牌通过 bearer token 发送
+        gateway_table.insert("experimental_bearer_token", toml_edit::value(token.as_str()));
+
+        doc["model_providers"]["ccg-gateway"] = toml_edit::Item::Table(gateway_table);
+
+        // Merge user's custom config if provided (TOML format)
+        if !default_config.is_empty() {
+            match default_config.parse::<toml_edit::DocumentMut>() {
+                Ok(custom_doc) => {
+                    // Merge custom config into base config
+                    for (key, value) in custom_doc.iter() {
+                        if key != "model_provider" && key != "model_providers" {
+                            doc[key] = value.clone();
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to parse custom config (invalid TOML): {}", e);
+                }
+            }
+        }
+
+        std::fs::write(&config_path, doc.to_string()).map_err(|e| {
+            tracing::error!("Failed to write config.toml: {}", e);
+            e.to_string()
+        })?;
+    } else {
+        // When disabling, restore backups or remove config files
+        let auth_restored = restore_backup(&auth_path)?;
+        let config_restored = restore_backup(&config_path)?;
+
+        if auth_restored {
+        } else if auth_path.exists() {
+            std::fs::remove_file(&auth_path).map_err(|e| {
+                tracing::error!("Failed to remove auth.json: {}", e);
+                e.to_string()
+            })?;
+        }
+
+        if config_restored {
+        } else if config_path.exists() {
+            std::fs::remove_file(&config_path).map_err(|e| {
+                tracing::error!("Failed to remove config.toml: {}", e);
+                e.to_string()
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+// Sync Gemini configuration (settings.json + .env)
+async fn sync_gemini_config(enabled: bool, default_config: &str, port: u16, db: &SqlitePool) -> Result<()> {
+    let home = dirs::home_dir().ok_or_else(|| "Cannot get home directory".to_string())?;
+    let gemini_dir = home.join(".gemini");
+    let config_path = gemini_dir.join("settings.json");
+    let env_path = gemini_dir.join(".env");
+
+    if enabled {
+        let token = gateway_token(db).await?;
+
+        // Backup existing configs if not already backed up
+        if config_path.exists() && !has_backup(&config_path) {
+            backup_file(&config_path)?;
+        }
+        if env_path.exists() && !has_backup(&env_path) {
+            backup_file(&env_path)?;
+        }
+
+        // Create config directory if it doesn't exist
+        std::fs::create_dir_all(&gemini_dir).map_err(|e| {
+            tracing::error!("Failed to create Gemini directory: {}", e);
+            e.to_string()
+        })?;
+
+        // Write .env file with gateway address
+        let base_url = gateway_url(port, cli_url_prefix("gemini", cli_path_prefix_enabled(db).await));
+        let env_content = format!("GEMINI_API_KEY={}\nGOOGLE_GEMINI_BASE_URL={}\n", token, base_url);
+        std::fs::write(&env_path, env_content).map_err(|e| {
+            tracing::error!("Failed to write .env file: {}", e);
+            e.to_string()
+        })?;
+
+        // Build base config with security.auth.selectedType
+        let mut config = serde_json::json!({
+            "security": {
+                "auth": {
+                    "selectedType": "gemini-api-key"
+                }
+            }
+        });
+
+        // Merge user's custom config if provided
+        if !default_config.is_empty() {
+            match serde_json::from_str::<serde_json::Value>(default_config) {
+                Ok(custom_config) => {
+                    deep_merge(&mut config, &custom_config);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to parse custom config (invalid JSON): {}", e);
+                }
+            }
+        }
+
+        // Write config file
+        let config_str = serde_json::to_string_pretty(&config).map_err(|e| {
+            tracing::error!("Failed to serialize config.json: {}", e);
+            e.to_string()
+        })?;
+        std::fs::write(&config_path, config_str).map_err(|e| {
+            tracing::error!("Failed to write config.json: {}", e);
+            e.to_string()
+        })?;
+    } else {
+        // When disabling, restore backups or remove config files
+        let env_restored = restore_backup(&env_path)?;
+        let config_restored = restore_backup(&config_path)?;
+
+        if env_restored {
+        } else if env_path.exists() {
+            std::fs::remove_file(&env_path).map_err(|e| {
+                tracing::error!("Failed to remove .env file: {}", e);
+                e.to_string()
+            })?;
+        }
+
+        if config_restored {
+        } else if config_path.exists() {
+            std::fs::remove_file(&config_path).map_err(|e| {
+                tracing::error!("Failed to remove config.json: {}", e);
+                e.to_string()
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+// Log commands
+#[tauri::command]
+pub async fn get_request_logs(
+    log_db: State<'_, crate::LogDb>,
+    page: Option<i64>,
+    page_size: Option<i64>,
+    cli_type: Option<String>,
+) -> Result<PaginatedLogs> {
+    load_request_logs(&log_db.0, page, page_size, cli_type).await
+}
+
+/// One page of request logs, newest first; shared by get_request_logs and `GET /api/logs`
+pub async fn load_request_logs(
+    log_db: &SqlitePool,
+    page: Option<i64>,
+    page_size: Option<i64>,
+    cli_type: Option<String>,
+) -> Result<PaginatedLogs> {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+    let offset = (page - 1) * page_size;
+    let pool = log_db;
+
+    let (items, total) = if let Some(ct) = cli_type {
+        let items = sqlx::query_as::<_, RequestLogItem>(
+            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, client_method, client_path, request_id, client_ip, ttfb_ms FROM request_logs WHERE cli_type = ? ORDER BY id DESC LIMIT ? OFFSET ?",
+        )
+        .bind(&ct)
+        .bind(page_size)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs WHERE cli_type = ?")
+            .bind(&ct)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        (items, total.0)
+    } else {
+        let items = sqlx::query_as::<_, RequestLogItem>(
+            "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, client_method, client_path, request_id, client_ip, ttfb_ms FROM request_logs ORDER BY id DESC LIMIT ? OFFSET ?",
+        )
+        .bind(page_size)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM request_logs")
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        (items, total.0)
+    };
+
+    Ok(PaginatedLogs {
+        items,
+        total,
+        page,
+        page_size,
+    })
+}
+
+/// Request logs with the estimated cost of each request (model_pricing lives in the main database)
+#[tauri::command]
+pub async fn get_request_cost_breakdown(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, crate::LogDb>,
+    page: Option<i64>,
+    page_size: Option<i64>,
+    cli_type: Option<String>,
+) -> Result<PaginatedCostLogs> {
+    use crate::services::pricing::{cost_usd, load_pricing, logged_cost_total};
+
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+    let offset = (page - 1) * page_size;
+    let pool = &log_db.0;
+
+    let logs = sqlx::query_as::<_, RequestLogItem>(
+        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, client_method, client_path, request_id, client_ip, ttfb_ms FROM request_logs WHERE ? IS NULL OR cli_type = ? ORDER BY id DESC LIMIT ? OFFSET ?",
+    )
+    .bind(&cli_type)
+    .bind(&cli_type)
+    .bind(page_size)
+    .bind(offset)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM request_logs WHERE ? IS NULL OR cli_type = ?")
+        .bind(&cli_type)
+        .bind(&cli_type)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let pricing = load_pricing(db.inner()).await.map_err(|e| e.to_string())?;
+    let items = logs
+        .into_iter()
+        .map(|log| {
+            let price = pricing.get(&(log.provider_name.clone(), log.model_id.clone().unwrap_or_default()));
+            let usage = crate::services::proxy::TokenUsage {
+                input_tokens: log.input_tokens,
+                output_tokens: log.output_tokens,
+                cache_creation_tokens: log.cache_creation_tokens,
+                cache_read_tokens: log.cache_read_tokens,
+            };
+            CostLogItem {
+                cost_usd: price.map(|p| cost_usd(p, &usage)),
+                log,
+            }
+        })
+        .collect();
+
+    let total_cost_usd = logged_cost_total(db.inner(), pool, cli_type.as_deref())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(PaginatedCostLogs {
+        items,
+        total,
+        page,
+        page_size,
+        total_cost_usd,
+    })
+}
+
+#[tauri::command]
+pub async fn clear_request_logs(log_db: State<'_, crate::LogDb>) -> Result<()> {
+    delete_request_logs(&log_db.0).await
+}
+
+pub async fn delete_request_logs(log_db: &SqlitePool) -> Result<()> {
+    sqlx::query("DELETE FROM request_logs")
+        .execute(log_db)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM batch_request_items")
+        .execute(log_db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Per-request results of the Message Batch a request log belongs to
+#[tauri::command]
+pub async fn get_batch_items(
+    log_db: State<'_, crate::LogDb>,
+    log_id: i64,
+) -> Result<Vec<BatchRequestItem>> {
+    let batch_id: Option<String> = sqlx::query_scalar("SELECT batch_id FROM request_logs WHERE id = ?")
+        .bind(log_id)
+        .fetch_optional(&log_db.0)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Log not found".to_string())?;
+    let Some(batch_id) = batch_id else {
+        return Ok(Vec::new());
+    };
+    sqlx::query_as::<_, BatchRequestItem>(
+        "SELECT id, batch_id, custom_id, result_type, status_code, input_tokens, output_tokens, created_at FROM batch_request_items WHERE batch_id = ? ORDER BY id",
+    )
+    .bind(&batch_id)
+    .fetch_all(&log_db.0)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Request log by numeric id or by the gateway request ID returned as X-CCG-Request-Id
+#[tauri::command]
+pub async fn get_request_log_detail(
+    log_db: State<'_, crate::LogDb>,
+    id: RequestLogKey,
+) -> Result<RequestLogDetail> {
+    load_request_log_detail(&log_db.0, id).await
+}
+
+/// Request log by the gateway request ID (X-CCG-Request-Id); the latest entry when it was retried
+#[tauri::command]
+pub async fn get_request_log_by_id(
+    log_db: State<'_, crate::LogDb>,
+    request_id: String,
+) -> Result<RequestLogDetail> {
+    load_request_log_detail(&log_db.0, RequestLogKey::RequestId(request_id)).await
+}
+
+pub async fn load_request_log_detail(log_db: &SqlitePool, key: RequestLogKey) -> Result<RequestLogDetail> {
+    const COLUMNS: &str = "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_proxy, forward_headers, forward_body, provider_headers, provider_body, response_headers, response_body, error_message, body_transforms, request_id, client_ip, model_chain, ttfb_ms, attempts, batch_id FROM request_logs";
+    let row = match key {
+        RequestLogKey::Id(id) => {
+            sqlx::query_as::<_, RequestLogDetail>(&format!("{} WHERE id = ?", COLUMNS))
+                .bind(id)
+                .fetch_optional(log_db)
+                .await
+        }
+        // A request that has been retried on another provider is logged more than once
+        RequestLogKey::RequestId(request_id) => {
+            sqlx::query_as::<_, RequestLogDetail>(&format!("{} WHERE request_id = ? ORDER BY id DESC LIMIT 1", COLUMNS))
+                .bind(request_id.trim())
+                .fetch_optional(log_db)
+                .await
+        }
+    };
+    row.map_err(|e| e.to_string())?
+        .ok_or_else(|| "Log not found".to_string())
+}
+
+const DEFAULT_LOG_CONTEXT_SIZE: i64 = 5;
+const MAX_LOG_CONTEXT_SIZE: i64 = 100;
+
+/// A request log with up to `context_size` requests logged right before and after it
+#[tauri::command]
+pub async fn get_request_log_context(
+    log_db: State<'_, crate::LogDb>,
+    log_id: i64,
+    context_size: Option<i64>,
+) -> Result<LogContext> {
+    load_request_log_context(&log_db.0, log_id, context_size).await
+}
+
+pub async fn load_request_log_context(log_db: &SqlitePool, log_id: i64, context_size: Option<i64>) -> Result<LogContext> {
+    let context_size = context_size
+        .unwrap_or(DEFAULT_LOG_CONTEXT_SIZE)
+        .clamp(0, MAX_LOG_CONTEXT_SIZE);
+    let target = load_request_log_detail(log_db, RequestLogKey::Id(log_id)).await?;
+
+    let mut before = sqlx::query_as::<_, RequestLogItem>(
+        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, client_method, client_path, request_id, client_ip, ttfb_ms FROM request_logs WHERE id < ? ORDER BY id DESC LIMIT ?",
+    )
+    .bind(log_id)
+    .bind(context_size)
+    .fetch_all(log_db)
+    .await
+    .map_err(|e| e.to_string())?;
+    before.reverse();
+
+    let after = sqlx::query_as::<_, RequestLogItem>(
+        "SELECT id, created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, client_method, client_path, request_id, client_ip, ttfb_ms FROM request_logs WHERE id > ? ORDER BY id ASC LIMIT ?",
+    )
+    .bind(log_id)
+    .bind(context_size)
+    .fetch_all(log_db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(LogContext { before, target, after })
+}
+
+/// Build a request to `provider` the way the proxy does: path rewrites, Azure deployment
+/// paths, extra query params, key rotation, auth and custom headers.
+/// Returns the request (without body) and the URL for the request log.
+async fn provider_request(
+    db: &SqlitePool,
+    http_clients: &crate::services::http_client::HttpClientPool,
+    provider: &Provider,
+    cli_type: crate::services::proxy::CliType,
+    method: reqwest::Method,
+    path: &str,
+    deployment: Option<&str>,
+) -> Result<(reqwest::RequestBuilder, String)> {
+    use crate::services::proxy::{append_query_params, apply_custom_headers, set_auth_header, CliType};
+
+    let path = crate::services::proxy::apply_path_rewrites(path, provider.path_rewrite_rules.as_deref());
+    let azure = cli_type == CliType::Codex
+        && crate::services::proxy::ProviderFlavor::from_provider(provider.flavor.as_deref())
+            == crate::services::proxy::ProviderFlavor::AzureOpenAi;
+    let path = match deployment {
+        Some(deployment) if azure => crate::services::proxy::azure_openai_path(&path, deployment),
+        _ => path,
+    };
+    let url = append_query_params(
+        &format!("{}{}", provider.base_url.trim_end_matches('/'), path),
+        provider.extra_query_params.as_deref(),
+    );
+    let url = if azure { crate::services::proxy::append_azure_api_version(&url) } else { url };
+
+    let api_key = if provider.api_key.trim().is_empty() {
+        crate::services::provider::select_api_key(db, provider, &Default::default())
+            .await
+            .map_err(|e| e.to_string())?
+            .map(|k| k.api_key)
+            .unwrap_or_default()
+    } else {
+        crate::services::secrets::resolve_api_key(&provider.api_key)?
+    };
+
+    let auth_scheme = crate::services::proxy::AuthScheme::from_provider(provider.auth_scheme.as_deref());
+    let (url, logged_url) = crate::services::proxy::apply_auth_query(&url, &api_key, auth_scheme);
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(reqwest::header::CONTENT_TYPE, reqwest::header::HeaderValue::from_static("application/json"));
+    set_auth_header(&mut headers, &api_key, cli_type, auth_scheme);
+    if azure && auth_scheme.is_none() {
+        crate::services::proxy::set_azure_api_key(&mut headers, &api_key);
+    }
+    apply_custom_headers(&mut headers, provider.custom_headers.as_deref());
+
+    let client = http_clients.client_for(provider.id, &crate::services::http_client::ClientOptions::from_provider(provider))?;
+    Ok((client.request(method, &url).headers(headers), logged_url))
+}
+
+/// Replay a logged request against the original (or another) provider.
+/// Streaming requests are replayed as non-streaming.
+#[tauri::command]
+pub async fn replay_request(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, crate::LogDb>,
+    http_clients: State<'_, crate::services::http_client::HttpClientPool>,
+    mask_patterns: State<'_, crate::MaskPatterns>,
+    log_id: i64,
+    provider_id: Option<i64>,
+) -> Result<ReplayResult> {
+    use crate::services::proxy::{parse_token_usage, CliType, TokenUsage};
+
+    const MAX_REPLAY_BODY: usize = 10 * 1024;
+
+    let log: Option<(String, String, String, String, Option<String>)> = sqlx::query_as(
+        "SELECT cli_type, provider_name, client_method, client_path, forward_body FROM request_logs WHERE id = ?",
+    )
+    .bind(log_id)
+    .fetch_optional(&log_db.0)
+    .await
+    .map_err(|e| e.to_string())?;
+    let (cli_type_str, provider_name, method, path, body) =
+        log.ok_or_else(|| "Log not found".to_string())?;
+    let cli_type: CliType = cli_type_str.parse()?;
+
+    let body = body.unwrap_or_default();
+    if crate::services::stats::is_truncated_body(&body) {
+        return Err("Logged request body was truncated and cannot be replayed".to_string());
+    }
+
+    let provider = match provider_id {
+        Some(id) => sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db.inner())
+            .await,
+        None => sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE cli_type = ? AND name = ?")
+            .bind(&cli_type_str)
+            .bind(&provider_name)
+            .fetch_optional(db.inner())
+            .await,
+    }
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "Provider not found".to_string())?;
+
+    // 流式请求改为非流式重放
+    let mut body = body.into_bytes();
+    if let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&body) {
+        if json.get("stream").and_then(|v| v.as_bool()) == Some(true) {
+            json["stream"] = serde_json::Value::Bool(false);
+            body = serde_json::to_vec(&json).map_err(|e| e.to_string())?;
+        }
+    }
+    let path = path
+        .replace(":streamGenerateContent", ":generateContent")
+        .replace("?alt=sse&", "?")
+        .replace("?alt=sse", "")
+        .replace("&alt=sse", "");
+
+    // Azure OpenAI: the logged body already carries the mapped model (deployment)
+    let deployment = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|json| json.get("model").and_then(|m| m.as_str()).map(|m| m.to_string()));
+    let method = reqwest::Method::from_bytes(method.as_bytes()).unwrap_or(reqwest::Method::POST);
+    let (mut request, logged_url) =
+        provider_request(db.inner(), &http_clients, &provider, cli_type, method.clone(), &path, deployment.as_deref()).await?;
+    if !body.is_empty() {
+        request = request.body(body.clone());
+    }
+
+    let start = std::time::Instant::now();
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Replay request failed: {}", e))?;
+    let status = response.status();
+    let response_bytes = response.bytes().await.map_err(|e| e.to_string())?;
+    let elapsed_ms = start.elapsed().as_millis() as i64;
+
+    let mut usage = TokenUsage::default();
+    parse_token_usage(&response_bytes, cli_type, &mut usage);
+
+    let response_text = String::from_utf8_lossy(&response_bytes).to_string();
+    let response_body = if response_text.len() > MAX_REPLAY_BODY {
+        let mut end = MAX_REPLAY_BODY;
+        while !response_text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let marker = crate::services::stats::truncation_marker(response_text.len() - end);
+        format!("{}{}", &response_text[..end], marker)
+    } else {
+        response_text
+    };
+
+    let info = crate::services::stats::RequestLogInfo {
+        forward_url: Some(logged_url),
+        forward_proxy: provider.proxy_url.as_deref().map(crate::services::masking::mask_url_credentials),
+        forward_body: Some(String::from_utf8_lossy(&body).to_string()),
+        provider_body: Some(response_body.clone()),
+        response_body: Some(response_body.clone()),
+        cache_creation_tokens: usage.cache_creation_tokens,
+        cache_read_tokens: usage.cache_read_tokens,
+        ..Default::default()
+    };
+    let _ = crate::services::stats::record_request_log(
+        &log_db.0,
+        &mask_patterns.0,
+        cli_type.as_str(),
+        &provider.name,
+        None,
+        Some(status.as_u16()),
+        elapsed_ms,
+        usage.input_tokens,
+        usage.output_tokens,
+        method.as_str(),
+        &format!("[REPLAY] {}", path),
+        Some(info),
+    )
+    .await;
+    let _ = crate::services::stats::record_request(
+        db.inner(),
+        &log_db.0,
+        &provider.name,
+        cli_type.as_str(),
+        None,
+        status.is_success(),
+        &usage,
+    )
+    .await;
+
+    Ok(ReplayResult {
+        log_id,
+        provider_name: provider.name,
+        status_code: status.as_u16(),
+        elapsed_ms,
+        response_body,
+        input_tokens: usage.input_tokens,
+        output_tokens: usage.output_tokens,
+    })
+}
+
+/// Provider, request path and body of a benchmark, after the provider's model maps
+struct BenchmarkTarget {
+    provider: Provider,
+    cli_type: crate::services::proxy::CliType,
+    api: crate::services::session_summary::SummaryApi,
+    path: String,
+    body: Vec<u8>,
+    model_id: Option<String>,
+}
+
+async fn benchmark_target(db: &SqlitePool, provider_id: i64, model_id: &str) -> Result<BenchmarkTarget> {
+    use crate::services::proxy::{apply_body_model_mapping, apply_url_model_mapping, CliType, ProviderProtocol};
+    use crate::services::routing::ProviderWithMaps;
+    use crate::services::session_summary::SummaryApi;
+
+    let model_id = model_id.trim();
+    if model_id.is_empty() {
+        return Err("Model is required".to_string());
+    }
+    let provider = sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+        .bind(provider_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Provider not found".to_string())?;
+    let model_maps = sqlx::query_as::<_, crate::db::models::ProviderModelMap>(
+        "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY sort_order, id",
+    )
+    .bind(provider.id)
+    .fetch_all(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let cli_type: CliType = provider.cli_type.parse()?;
+    let openai_protocol = ProviderProtocol::from_provider(provider.protocol.as_deref()) == ProviderProtocol::OpenAi;
+    let api = SummaryApi::for_provider(cli_type, openai_protocol);
+    let provider = ProviderWithMaps { provider, model_maps };
+
+    let body = crate::services::benchmark::ping_request_body(api, model_id);
+    let mapping = if api == SummaryApi::Gemini {
+        apply_url_model_mapping(&provider, &api.path(cli_type, model_id), &body, &provider.model_maps)
+    } else {
+        apply_body_model_mapping(&provider, &body, &api.path(cli_type, model_id))
+    };
+    Ok(BenchmarkTarget {
+        provider: provider.provider,
+        cli_type,
+        api,
+        path: mapping.path,
+        body: mapping.body,
+        model_id: mapping.target_model.or(mapping.source_model),
+    })
+}
+
+/// Send one benchmark request and log it; returns (2xx status, elapsed ms)
+async fn benchmark_request(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    http_clients: &crate::services::http_client::HttpClientPool,
+    mask_patterns: &crate::services::masking::MaskPatternCache,
+    target: &BenchmarkTarget,
+) -> (bool, i64) {
+    use crate::services::proxy::{parse_token_usage, TokenUsage};
+
+    let start = std::time::Instant::now();
+    let request = provider_request(
+        db,
+        http_clients,
+        &target.provider,
+        target.cli_type,
+        reqwest::Method::POST,
+        &target.path,
+        target.model_id.as_deref(),
+    )
+    .await;
+    let (request, logged_url) = match request {
+        Ok(request) => request,
+        Err(_) => return (false, start.elapsed().as_millis() as i64),
+    };
+
+    let (status, response_bytes) = match request.body(target.body.clone()).send().await {
+        Ok(response) => {
+            let status = response.status();
+            (Some(status), response.bytes().await.unwrap_or_default())
+        }
+        Err(e) => (None, bytes::Bytes::from(e.to_string())),
+    };
+    let elapsed_ms = start.elapsed().as_millis() as i64;
+    let success = status.is_some_and(|s| s.is_success());
+
+    let mut usage = TokenUsage::default();
+    parse_token_usage(&response_bytes, target.api.usage_format(), &mut usage);
+    let response_text = String::from_utf8_lossy(&response_bytes).to_string();
+    let info = crate::services::stats::RequestLogInfo {
+        forward_url: Some(logged_url),
+        forward_proxy: target.provider.proxy_url.as_deref().map(crate::services::masking::mask_url_credentials),
+        forward_body: Some(String::from_utf8_lossy(&target.body).to_string()),
+        provider_body: Some(response_text.clone()),
+        response_body: Some(response_text),
+        ..Default::default()
+    };
+    let _ = crate::services::stats::record_request_log(
+        log_db,
+        mask_patterns,
+        target.cli_type.as_str(),
+        &target.provider.name,
+        target.model_id.as_deref(),
+        status.map(|s| s.as_u16()),
+        elapsed_ms,
+        usage.input_tokens,
+        usage.output_tokens,
+        "POST",
+        &format!("{} {}", crate::services::benchmark::BENCHMARK_PATH_PREFIX, target.path),
+        Some(info),
+    )
+    .await;
+
+    (success, elapsed_ms)
+}
+
+/// Send `request_count` minimal requests to a provider one after another and report the latency distribution
+#[tauri::command]
+pub async fn benchmark_provider(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    http_clients: State<'_, crate::services::http_client::HttpClientPool>,
+    mask_patterns: State<'_, crate::MaskPatterns>,
+    provider_id: i64,
+    request_count: i64,
+    model_id: String,
+) -> Result<BenchmarkResult> {
+    use crate::services::benchmark::{summarize, BENCHMARK_INTERVAL_MS, MAX_BENCHMARK_REQUESTS};
+
+    if !(1..=MAX_BENCHMARK_REQUESTS).contains(&request_count) {
+        return Err(format!("Request count must be between 1 and {}", MAX_BENCHMARK_REQUESTS));
+    }
+    let target = benchmark_target(db.inner(), provider_id, &model_id).await?;
+
+    let mut samples = Vec::with_capacity(request_count as usize);
+    for i in 0..request_count {
+        if i > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(BENCHMARK_INTERVAL_MS)).await;
+        }
+        samples.push(benchmark_request(db.inner(), &log_db.0, &http_clients, &mask_patterns.0, &target).await);
+    }
+    Ok(summarize(&samples))
+}
+
+/// Load test: `total` minimal requests to a provider, at most `concurrency` at a time
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn benchmark_concurrent(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    http_clients: State<'_, crate::services::http_client::HttpClientPool>,
+    mask_patterns: State<'_, crate::MaskPatterns>,
+    provider_id: i64,
+    concurrency: i64,
+    total: i64,
+    model_id: String,
+) -> Result<BenchmarkResult> {
+    use crate::services::benchmark::{summarize, MAX_BENCHMARK_CONCURRENCY, MAX_BENCHMARK_REQUESTS};
+    use futures_util::StreamExt;
+
+    if !(1..=MAX_BENCHMARK_REQUESTS).contains(&total) {
+        return Err(format!("Request count must be between 1 and {}", MAX_BENCHMARK_REQUESTS));
+    }
+    if !(1..=MAX_BENCHMARK_CONCURRENCY).contains(&concurrency) {
+        return Err(format!("Concurrency must be between 1 and {}", MAX_BENCHMARK_CONCURRENCY));
+    }
+    let target = benchmark_target(db.inner(), provider_id, &model_id).await?;
+
+    let samples: Vec<(bool, i64)> = futures_util::stream::iter(0..total)
+        .map(|_| benchmark_request(db.inner(), &log_db.0, &http_clients, &mask_patterns.0, &target))
+        .buffer_unordered(concurrency as usize)
+        .collect()
+        .await;
+    Ok(summarize(&samples))
+}
+
+// System logs commands
+#[tauri::command]
+pub async fn get_system_logs(
+    log_db: State<'_, crate::LogDb>,
+    page: Option<i64>,
+    page_size: Option<i64>,
+    level: Option<String>,
+    event_type: Option<String>,
+    provider_name: Option<String>,
+) -> Result<SystemLogListResponse> {
+    load_system_logs(&log_db.0, page, page_size, level, event_type, provider_name).await
+}
+
+pub async fn load_system_logs(
+    log_db: &SqlitePool,
+    page: Option<i64>,
+    page_size: Option<i64>,
+    level: Option<String>,
+    event_type: Option<String>,
+    provider_name: Option<String>,
+) -> Result<SystemLogListResponse> {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+    let offset = (page - 1) * page_size;
+
+    // Build query
+    let mut sql = "SELECT * FROM system_logs WHERE 1=1".to_string();
+    let mut count_sql = "SELECT COUNT(*) FROM system_logs WHERE 1=1".to_string();
+
+    if level.is_some() {
+        sql.push_str(" AND level = ?");
+        count_sql.push_str(" AND level = ?");
+    }
+    if event_type.is_some() {
+        sql.push_str(" AND event_type = ?");
+        count_sql.push_str(" AND event_type = ?");
+    }
+    if provider_name.is_some() {
+        sql.push_str(" AND provider_name = ?");
+        count_sql.push_str(" AND provider_name = ?");
+    }
+
+    sql.push_str(" ORDER BY id DESC LIMIT ? OFFSET ?");
+    let mut q = sqlx::query_as::<_, SystemLogItem>(&sql)
+        .bind(page_size)
+        .bind(offset);
+
+    if let Some(ref lvl) = level {
+        q = q.bind(lvl);
+    }
+    if let Some(ref et) = event_type {
+        q = q.bind(et);
+    }
+    if let Some(ref pn) = provider_name {
+        q = q.bind(pn);
+    }
+
+    let items = q.fetch_all(log_db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Get total count
+    let mut count_q = sqlx::query_as::<_, (i64,)>(&count_sql);
+    if let Some(ref lvl) = level {
+        count_q = count_q.bind(lvl);
+    }
+    if let Some(ref et) = event_type {
+        count_q = count_q.bind(et);
+    }
+    if let Some(ref pn) = provider_name {
+        count_q = count_q.bind(pn);
+    }
+    let (total,) = count_q.fetch_one(log_db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(SystemLogListResponse {
+        items,
+        total,
+        page,
+        page_size,
+    })
+}
+
+#[tauri::command]
+pub async fn clear_system_logs(log_db: State<'_, crate::LogDb>) -> Result<()> {
+    delete_system_logs(&log_db.0).await
+}
+
+pub async fn delete_system_logs(log_db: &SqlitePool) -> Result<()> {
+    sqlx::query("DELETE FROM system_logs")
+        .execute(log_db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// System status
+#[tauri::command]
+pub async fn get_system_status(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, crate::LogDb>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    listen_addr: State<'_, crate::ListenAddr>,
+    concurrency: State<'_, crate::services::concurrency::ConcurrencyLimiter>,
+) -> Result<SystemStatus> {
+    let server = listen_addr.status();
+    let total_cost_usd = crate::services::pricing::logged_cost_total(db.inner(), &log_db.0, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let budgets = crate::services::budget::budget_statuses(db.inner(), &log_db.0)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(SystemStatus {
+        status: server.state().to_string(),
+        error: server.error(),
+        port: gateway_port.get(),
+        uptime: server.uptime(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        listen_address: listen_addr.get().map(|a| a.to_string()),
+        lan_url: listen_addr.lan_url(),
+        total_cost_usd,
+        budgets,
+        requests_in_flight: concurrency.in_flight(),
+        max_concurrent_requests: concurrency.limit(),
+    })
+}
+
+/// Run SQLite's integrity and foreign key checks on the main and log databases
+#[tauri::command]
+pub async fn check_database_integrity(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+) -> Result<DatabaseIntegrityResult> {
+    let mut issues = Vec::new();
+    for (name, pool) in [("ccg_gateway.db", db.inner()), ("ccg_logs.db", &log_db.0)] {
+        match crate::db::check_integrity(pool).await {
+            Ok(found) => issues.extend(found.into_iter().map(|issue| format!("{}: {}", name, issue))),
+            Err(e) => issues.push(format!("{}: {}", name, e)),
+        }
+    }
+    Ok(DatabaseIntegrityResult {
+        ok: issues.is_empty(),
+        issues,
+    })
+}
+
+/// Health snapshot for troubleshooting (database sizes, counters, provider states, memory)
+#[tauri::command]
+pub async fn get_gateway_diagnostics(
+    db: State<'_, SqlitePool>,
+    start_time: State<'_, crate::StartTime>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    app_config: State<'_, crate::AppConfig>,
+    counters: State<'_, crate::services::diagnostics::ProxyCounters>,
+) -> Result<GatewayDiagnostics> {
+    use crate::services::diagnostics;
+    use std::sync::atomic::Ordering;
+
+    let (db_path, log_db_path) = {
+        let config = app_config.0.read().map_err(|e| e.to_string())?;
+        (config.database.path.clone(), config.database.log_path.clone())
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let provider_statuses = sqlx::query_as::<_, Provider>("SELECT * FROM providers ORDER BY cli_type, sort_order, id")
+        .fetch_all(db.inner())
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|p| ProviderHealthSummary {
+            id: p.id,
+            cli_type: p.cli_type,
+            name: p.name,
+            enabled: p.enabled != 0,
+            consecutive_failures: p.consecutive_failures,
+            is_blacklisted: p.blacklisted_until.is_some_and(|t| t > now),
+            blacklisted_until: p.blacklisted_until,
+        })
+        .collect();
+
+    Ok(GatewayDiagnostics {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        port: gateway_port.get(),
+        db_size_bytes: diagnostics::db_size_bytes(&db_path),
+        log_db_size_bytes: diagnostics::db_size_bytes(&log_db_path),
+        active_connections: counters.active_connections.load(Ordering::Relaxed),
+        provider_statuses,
+        memory_rss_kb: diagnostics::memory_rss_kb(),
+        uptime_secs: now - start_time.0,
+        total_requests_handled: counters.total_requests.load(Ordering::Relaxed),
+        requests_in_flight: counters.requests_in_flight.load(Ordering::Relaxed),
+    })
+}
+
+#[tauri::command]
+pub async fn reload_config(
+    app_config: State<'_, crate::AppConfig>,
+    log_db: State<'_, LogDb>,
+) -> Result<crate::config::Config> {
+    crate::reload_config_file(&app_config.0, &log_db.0).await
+}
+
+/// Move the gateway to another port without restarting (same host).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_gateway_port(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    app_config: State<'_, crate::AppConfig>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    listen_addr: State<'_, crate::ListenAddr>,
+    gateway_server: State<'_, crate::GatewayServer>,
+    cli_listeners: State<'_, crate::CliListeners>,
+    cli_sync: State<'_, CliSyncState>,
+    new_port: u16,
+) -> Result<()> {
+    let host = match listen_addr.get() {
+        Some(addr) => addr.ip().to_string(),
+        None => app_config.0.read().map_err(|e| e.to_string())?.server.host.clone(),
+    };
+    rebind_gateway(db, log_db, app_config, gateway_port, listen_addr, gateway_server, cli_listeners, cli_sync, host, new_port).await
+}
+
+/// Move the gateway to another host/port without restarting.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_server_binding(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    app_config: State<'_, crate::AppConfig>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    listen_addr: State<'_, crate::ListenAddr>,
+    gateway_server: State<'_, crate::GatewayServer>,
+    cli_listeners: State<'_, crate::CliListeners>,
+    cli_sync: State<'_, CliSyncState>,
+    host: String,
+    port: u16,
+) -> Result<()> {
+    let host = check_server_host(&host)?;
+    rebind_gateway(db, log_db, app_config, gateway_port, listen_addr, gateway_server, cli_listeners, cli_sync, host, port).await
+}
+
+/// Port, enabled providers (API keys masked) and CLI states as a bash env file
+#[tauri::command]
+pub async fn get_gateway_config_as_env_file(
+    db: State<'_, SqlitePool>,
+    gateway_port: State<'_, crate::GatewayPort>,
+) -> Result<String> {
+    use crate::services::env_file::{cli_enabled_key, provider_key, shell_quote, ENABLED_PROVIDERS_KEY, GATEWAY_PORT_KEY};
+
+    let providers = sqlx::query_as::<_, (String, String, String, String)>(
+        "SELECT cli_type, name, base_url, api_key FROM providers WHERE enabled = 1 ORDER BY cli_type, sort_order, id",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let names: Vec<&str> = providers.iter().map(|(_, name, _, _)| name.as_str()).collect();
+    let mut lines = vec![
+        "# CCG Gateway configuration".to_string(),
+        format!("# Exported at {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S")),
+        format!("{}={}", GATEWAY_PORT_KEY, gateway_port.get()),
+        format!("{}={}", ENABLED_PROVIDERS_KEY, shell_quote(&names.join(","))),
+    ];
+    for (index, (cli_type, name, base_url, api_key)) in providers.iter().enumerate() {
+        let index = index + 1;
+        // 密钥可能存于系统钥匙串，解析后再脱敏，保留首尾便于辨认
+        let masked_key = crate::services::secrets::resolve_api_key(api_key)
+            .map(|key| crate::services::masking::mask_secret(&key))
+            .unwrap_or_else(|_| "******".to_string());
+        lines.push(format!("{}={}", provider_key(index, "NAME"), shell_quote(name)));
+        lines.push(format!("{}={}", provider_key(index, "CLI_TYPE"), shell_quote(cli_type)));
+        lines.push(format!("{}={}", provider_key(index, "URL"), shell_quote(base_url)));
+        lines.push(format!("{}={}", provider_key(index, "API_KEY"), shell_quote(&masked_key)));
+    }
+    for cli_type in CLI_TYPES {
+        let enabled = check_cli_enabled(cli_type, gateway_port.for_cli(cli_type));
+        lines.push(format!("{}={}", cli_enabled_key(cli_type), enabled));
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Apply an env file in the get_gateway_config_as_env_file format.
+///
+/// 只应用 GATEWAY_PORT 与 CLI_*_ENABLED；服务商与 API Key 等其他键记为 skipped。
+/// 同一键出现多次时以最后一次为准（与 bash 一致）。
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn apply_env_file(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    app_config: State<'_, crate::AppConfig>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    listen_addr: State<'_, crate::ListenAddr>,
+    gateway_server: State<'_, crate::GatewayServer>,
+    cli_listeners: State<'_, crate::CliListeners>,
+    cli_sync: State<'_, CliSyncState>,
+    content: String,
+) -> Result<ApplyEnvResult> {
+    use crate::services::env_file::{cli_enabled_key, parse_bool, parse_env_file, GATEWAY_PORT_KEY};
+
+    let mut result = ApplyEnvResult::default();
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for entry in parse_env_file(&content) {
+        match entry {
+            Ok((key, value)) => {
+                entries.retain(|(existing, _)| *existing != key);
+                entries.push((key, value));
+            }
+            Err(e) => result.failed.push(e),
+        }
+    }
+
+    for (key, value) in entries {
+        let outcome = if key == GATEWAY_PORT_KEY {
+            Some(match value.trim().parse::<u16>() {
+                Ok(port) => {
+                    update_gateway_port(
+                        db.clone(),
+                        log_db.clone(),
+                        app_config.clone(),
+                        gateway_port.clone(),
+                        listen_addr.clone(),
+                        gateway_server.clone(),
+                        cli_listeners.clone(),
+                        cli_sync.clone(),
+                        port,
+                    )
+                    .await
+                }
+                Err(_) => Err(format!("Invalid port: '{}'", value)),
+            })
+        } else if let Some(cli_type) = CLI_TYPES.into_iter().find(|cli_type| cli_enabled_key(cli_type) == key) {
+            Some(match parse_bool(&value) {
+                Some(enabled) => {
+                    let input = CliSettingsUpdate {
+                        enabled: Some(enabled),
+                        default_json_config: None,
+                        prompt_append_mode: None,
+                    };
+                    update_cli_settings(db.clone(), gateway_port.clone(), cli_sync.clone(), cli_type.to_string(), input).await
+                }
+                None => Err(format!("Expected true or false, got '{}'", value)),
+            })
+        } else {
+            None
+        };
+        match outcome {
+            Some(Ok(())) => result.applied.push(key),
+            Some(Err(e)) => result.failed.push(format!("{}: {}", key, e)),
+            None => result.skipped.push(key),
+        }
+    }
+
+    if !result.applied.is_empty() {
+        let _ = crate::services::stats::record_system_log(
+            &log_db,
+            "info",
+            "env_file_applied",
+            &format!("Applied settings from env file: {}", result.applied.join(", ")),
+            None,
+            None,
+        ).await;
+    }
+    Ok(result)
+}
+
+/// Rewrite the config of a CLI using the gateway to point at `port`
+async fn retarget_cli_config(db: State<'_, SqlitePool>, cli_sync: &CliSyncState, cli_type: &str, port: u16) -> Result<()> {
+    let default_config: Option<String> =
+        sqlx::query_scalar("SELECT default_json_config FROM cli_settings WHERE cli_type = ?")
+            .bind(cli_type)
+            .fetch_optional(db.inner())
+            .await
+            .map_err(|e| e.to_string())?
+            .flatten();
+    sync_cli_config(cli_type, true, &default_config.unwrap_or_default(), port, db.inner()).await?;
+    cli_sync.mark_synced(cli_type, true);
+    Ok(())
+}
+
+/// Dedicate a listener port to each CLI type in `ports` (empty = shared port with User-Agent detection).
+///
+/// 新端口全部绑定成功后才保存；已接入网关的 CLI 配置改写到各自的端口。
+#[tauri::command]
+pub async fn update_cli_ports(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    listen_addr: State<'_, crate::ListenAddr>,
+    cli_listeners: State<'_, crate::CliListeners>,
+    cli_sync: State<'_, CliSyncState>,
+    ports: std::collections::HashMap<String, u16>,
+) -> Result<()> {
+    let shared_port = gateway_port.get();
+    let mut seen = std::collections::HashSet::new();
+    for (cli_type, port) in &ports {
+        cli_type.parse::<crate::services::proxy::CliType>()?;
+        if *port == 0 {
+            return Err(format!("Port of {} must be between 1 and 65535", cli_type));
+        }
+        if *port == shared_port {
+            return Err(format!("Port {} is already used by the shared listener", port));
+        }
+        if !seen.insert(*port) {
+            return Err(format!("Port {} is assigned to more than one CLI", port));
+        }
+    }
+    let host = listen_addr
+        .get()
+        .map(|addr| addr.ip().to_string())
+        .ok_or("Gateway is not listening")?;
+
+    let old_ports: Vec<(&str, u16)> = CLI_TYPES
+        .into_iter()
+        .map(|cli_type| (cli_type, gateway_port.for_cli(cli_type)))
+        .collect();
+    cli_listeners.apply(&host, &ports, &gateway_port).await?;
+
+    let json = serde_json::to_string(&ports).map_err(|e| e.to_string())?;
+    sqlx::query("UPDATE gateway_settings SET cli_ports = ?, updated_at = ? WHERE id = 1")
+        .bind(&json)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for (cli_type, old_port) in old_ports {
+        let new_port = gateway_port.for_cli(cli_type);
+        if new_port != old_port && check_cli_enabled(cli_type, old_port) {
+            if let Err(e) = retarget_cli_config(db.clone(), &cli_sync, cli_type, new_port).await {
+                tracing::warn!("Failed to update {} config to port {}: {}", cli_type, new_port, e);
+            }
+        }
+    }
+
+    let _ = crate::services::stats::record_system_log(
+        &log_db,
+        "info",
+        "cli_ports_changed",
+        &format!("Dedicated CLI listener ports: {}", if ports.is_empty() { "none".to_string() } else { json }),
+        None,
+        None,
+    ).await;
+    Ok(())
+}
+
+/// Dedicated listener ports saved by update_cli_ports
+pub async fn configured_cli_ports(db: &SqlitePool) -> std::collections::HashMap<String, u16> {
+    sqlx::query_scalar::<_, String>("SELECT cli_ports FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Listen host set from the app: an IP address or `localhost`
+fn check_server_host(host: &str) -> Result<String> {
+    let host = host.trim();
+    if host.eq_ignore_ascii_case("localhost") {
+        return Ok("localhost".to_string());
+    }
+    host.parse::<std::net::IpAddr>()
+        .map_err(|_| format!("Invalid listen host: '{}'", host))?;
+    Ok(host.to_string())
+}
+
+/// Host/port saved by update_server_binding; they take precedence over the config file at startup
+pub async fn configured_server_binding(db: &SqlitePool) -> (Option<String>, Option<u16>) {
+    let row = sqlx::query_as::<_, (Option<String>, Option<i64>)>(
+        "SELECT server_host, server_port FROM gateway_settings WHERE id = 1",
+    )
+    .fetch_optional(db)
+    .await
+    .ok()
+    .flatten();
+    match row {
+        Some((host, port)) => (
+            host.filter(|h| !h.trim().is_empty()),
+            port.and_then(|p| u16::try_from(p).ok()).filter(|p| *p != 0),
+        ),
+        None => (None, None),
+    }
+}
+
+/// Bind `host:port` and hand the gateway over to it.
+///
+/// 先绑定新地址：绑定失败时旧监听不受影响；之后保存配置、切换监听（旧监听优雅关闭，
+/// 进行中的请求继续完成），并把已接入网关的 CLI 配置改写到新端口。
+#[allow(clippy::too_many_arguments)]
+async fn rebind_gateway(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    app_config: State<'_, crate::AppConfig>,
+    gateway_port: State<'_, crate::GatewayPort>,
+    listen_addr: State<'_, crate::ListenAddr>,
+    gateway_server: State<'_, crate::GatewayServer>,
+    cli_listeners: State<'_, crate::CliListeners>,
+    cli_sync: State<'_, CliSyncState>,
+    host: String,
+    new_port: u16,
+) -> Result<()> {
+    use std::sync::atomic::Ordering;
+
+    let old_port = gateway_port.get();
+    let old_addr = listen_addr.get();
+    if new_port == 0 {
+        return Err("Port must be between 1 and 65535".to_string());
+    }
+    let same_host = old_addr.is_some_and(|addr| host.parse::<std::net::IpAddr>().ok() == Some(addr.ip()));
+    if new_port == old_port && same_host {
+        return Ok(());
+    }
+
+    let listener = match tokio::net::TcpListener::bind((host.as_str(), new_port)).await {
+        Ok(listener) => listener,
+        // 同端口只换地址时旧监听仍占着端口（如 0.0.0.0 与具体地址冲突），先停掉旧监听再绑定
+        Err(_) if new_port == old_port => {
+            gateway_server.stop();
+            match crate::bind_with_retry(&host, new_port).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    if let Some(addr) = old_addr {
+                        match crate::bind_with_retry(&addr.ip().to_string(), addr.port()).await {
+                            Ok(listener) => gateway_server.serve(listener),
+                            Err(e) => tracing::error!("Failed to restore gateway listener on {}: {}", addr, e),
+                        }
+                    }
+                    return Err(format!("{}:{} is not available: {}", host, new_port, e));
+                }
+            }
+        }
+        Err(e) => return Err(format!("{}:{} is not available: {}", host, new_port, e)),
+    };
+    let local_addr = listener.local_addr().map_err(|e| e.to_string())?;
+
+    // 先更新内存中的配置，配置文件监听重新加载时不会误报端口需要重启
+    let previous_port = {
+        let mut config = app_config.0.write().map_err(|e| e.to_string())?;
+        std::mem::replace(&mut config.server.port, new_port)
+    };
+    if let Err(e) = crate::config::save_server_port(&crate::config::get_config_path(), new_port) {
+        if let Ok(mut config) = app_config.0.write() {
+            config.server.port = previous_port;
+        }
+        return Err(format!("Failed to save config: {}", e));
+    }
+    sqlx::query("UPDATE gateway_settings SET server_host = ?, server_port = ?, updated_at = ? WHERE id = 1")
+        .bind(&host)
+        .bind(new_port as i64)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // CLI 配置是否接入网关按旧端口判断
+    let enabled_cli_types: Vec<(&str, u16)> = CLI_TYPES
+        .into_iter()
+        .map(|cli_type| (cli_type, gateway_port.for_cli(cli_type)))
+        .filter(|(cli_type, port)| check_cli_enabled(cli_type, *port))
+        .collect();
+
+    gateway_server.serve(listener);
+    gateway_port.0.store(new_port, Ordering::Relaxed);
+    listen_addr.set(local_addr);
+    tracing::info!("Gateway moved to {}", local_addr);
+
+    // 独立监听跟随主监听的地址
+    if !same_host {
+        let ports = configured_cli_ports(db.inner()).await;
+        cli_listeners.stop_all(&gateway_port);
+        if let Err(e) = cli_listeners.apply(&local_addr.ip().to_string(), &ports, &gateway_port).await {
+            tracing::warn!("Failed to move dedicated CLI listeners: {}", e);
+        }
+    }
+
+    for (cli_type, old_cli_port) in enabled_cli_types {
+        let new_port = gateway_port.for_cli(cli_type);
+        if new_port != old_cli_port {
+            if let Err(e) = retarget_cli_config(db.clone(), &cli_sync, cli_type, new_port).await {
+                tracing::warn!("Failed to update {} config to port {}: {}", cli_type, new_port, e);
+            }
+        }
+    }
+
+    let old = old_addr.map(|a| a.to_string()).unwrap_or_else(|| format!("port {}", old_port));
+    let _ = crate::services::stats::record_system_log(
+        &log_db,
+        "info",
+        "port_changed",
+        &format!("Gateway moved from {} to {}", old, local_addr),
+        None,
+        None,
+    ).await;
+
+    Ok(())
+}
+
+// MCP commands
+#[tauri::command]
+pub async fn get_mcps(db: State<'_, SqlitePool>) -> Result<Vec<McpResponse>> {
+    load_mcps(db.inner()).await
+}
+
+pub async fn load_mcps(db: &SqlitePool) -> Result<Vec<McpResponse>> {
+    let mcps = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs ORDER BY id")
+        .fetch_all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let cli_types = vec!["claude_code", "codex", "gemini"];
+
+    let mut results = Vec::new();
+    for mcp in mcps {
+        // Read real status from config files
+        let mut cli_flags = Vec::new();
+        for cli_type in &cli_types {
+            let enabled = mcp_enabled_in_file(cli_type, &mcp.name);
+            cli_flags.push(McpCliFlag {
+                cli_type: cli_type.to_string(),
+                enabled,
+            });
+        }
+
+        results.push(McpResponse {
+            id: mcp.id,
+            name: mcp.name,
+            config_json: mcp.config_json,
+            cli_flags,
+        });
+    }
+    Ok(results)
+}
+
+#[tauri::command]
+pub async fn get_mcp(db: State<'_, SqlitePool>, id: i64) -> Result<McpResponse> {
+    load_mcp(db.inner(), id).await
+}
+
+pub async fn load_mcp(db: &SqlitePool, id: i64) -> Result<McpResponse> {
+    let mcp = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "MCP not found".to_string())?;
+
+    // Read real status from config files
+    let cli_types = vec!["claude_code", "codex", "gemini"];
+    let mut cli_flags = Vec::new();
+    for cli_type in &cli_types {
+        let enabled = mcp_enabled_in_file(cli_type, &mcp.name);
+        cli_flags.push(McpCliFlag {
+            cli_type: cli_type.to_string(),
+            enabled,
+        });
+    }
+
+    Ok(McpResponse {
+        id: mcp.id,
+        name: mcp.name,
+        config_json: mcp.config_json,
+        cli_flags,
+    })
+}
+
+#[tauri::command]
+pub async fn create_mcp(db: State<'_, SqlitePool>, input: McpCreate) -> Result<McpResponse> {
+    insert_mcp(db.inner(), input).await
+}
+
+pub async fn insert_mcp(db: &SqlitePool, input: McpCreate) -> Result<McpResponse> {
+    ensure_valid_mcp_config(&input.config_json, input.cli_flags.as_deref())?;
+    let now = chrono::Utc::now().timestamp();
+
+    let result = sqlx::query(
+        "INSERT INTO mcp_configs (name, config_json, updated_at) VALUES (?, ?, ?)",
+    )
+    .bind(&input.name)
+    .bind(&input.config_json)
+    .bind(now)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let id = result.last_insert_rowid();
+
+    // Sync to CLI files if cli_flags provided
+    let cli_flags = input.cli_flags.unwrap_or_default();
+    if !cli_flags.is_empty() {
+        sync_single_mcp_to_cli(id, &input.name, &input.config_json, &cli_flags).await?;
+    }
+
+    load_mcp(db, id).await
+}
+
+#[tauri::command]
+pub async fn update_mcp(db: State<'_, SqlitePool>, id: i64, input: McpUpdate) -> Result<McpResponse> {
+    apply_mcp_update(db.inner(), id, input).await
+}
+
+pub async fn apply_mcp_update(db: &SqlitePool, id: i64, input: McpUpdate) -> Result<McpResponse> {
+    let now = chrono::Utc::now().timestamp();
+
+    let current = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "MCP not found".to_string())?;
+
+    // 配置未修改但启用了新的 CLI 时也需要校验（例如 Codex 的 TOML 限制）
+    if input.config_json.is_some() || input.cli_flags.is_some() {
+        let config_json = input.config_json.as_deref().unwrap_or(&current.config_json);
+        ensure_valid_mcp_config(config_json, input.cli_flags.as_deref())?;
+    }
+
+    let (name, config_json) = if input.name.is_some() || input.config_json.is_some() {
+        let new_name = input.name.unwrap_or(current.name.clone());
+        let new_config = input.config_json.unwrap_or(current.config_json.clone());
+
+        sqlx::query(
+            "UPDATE mcp_configs SET name = ?, config_json = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(&new_name)
+        .bind(&new_config)
+        .bind(now)
+        .bind(id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        (new_name, new_config)
+    } else {
+        (current.name, current.config_json)
+    };
+
+    // Sync to CLI files if cli_flags provided
+    if let Some(cli_flags) = input.cli_flags {
+        sync_single_mcp_to_cli(id, &name, &config_json, &cli_flags).await?;
+    }
+
+    load_mcp(db, id).await
+}
+
+#[tauri::command]
+pub async fn validate_mcp_config(config_json: String, cli_type: String) -> Result<McpValidationResult> {
+    Ok(crate::services::mcp::validate_mcp_config(&config_json, &cli_type))
+}
+
+/// Validate the MCP config for every CLI it will be synced to, returning the first error
+fn ensure_valid_mcp_config(config_json: &str, cli_flags: Option<&[McpCliFlag]>) -> Result<()> {
+    let mut targets: Vec<&str> = cli_flags
+        .unwrap_or_default()
+        .iter()
+        .filter(|f| f.enabled)
+        .map(|f| f.cli_type.as_str())
+        .collect();
+    if targets.is_empty() {
+        targets.push("");
+    }
+
+    for cli_type in targets {
+        let result = crate::services::mcp::validate_mcp_config(config_json, cli_type);
+        if let Some(error) = result.errors.into_iter().next() {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_mcp(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
+    remove_mcp(db.inner(), id).await
+}
+
+pub async fn remove_mcp(db: &SqlitePool, id: i64) -> Result<()> {
+    // Get MCP name before deletion
+    let mcp = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "MCP not found".to_string())?;
+
+    let mcp_name = mcp.name.clone();
+
+    // Delete from database
+    sqlx::query("DELETE FROM mcp_configs WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Remove from all CLI configs
+    delete_mcp_from_cli(&mcp_name)?;
+
+    Ok(())
+}
+
+// Sync a single MCP to CLI files based on enabled flags
+async fn sync_single_mcp_to_cli(
+    _mcp_id: i64,
+    mcp_name: &str,
+    mcp_config_json: &str,
+    cli_flags: &[McpCliFlag],
+) -> Result<()> {
+    let cli_types = vec!["claude_code", "codex", "gemini"];
+
+    for cli_type in cli_types {
+        // Check if this MCP is enabled for this CLI
+        let is_enabled = cli_flags.iter()
+            .any(|f| f.cli_type == cli_type && f.enabled);
+
+        let config_path = get_mcp_config_path(cli_type);
+        if let Some(path) = config_path {
+            // Handle Codex separately (TOML format)
+            if cli_type == "codex" {
+                sync_single_codex_mcp(path, mcp_name, mcp_config_json, is_enabled)?;
+                continue;
+            }
+
+            // For ClaudeCode and Gemini (JSON format)
+            // Read existing config or create new one
+            let mut config = if path.exists() {
+                let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+                serde_json::from_str::<serde_json::Value>(&content).unwrap_or_else(|_| serde_json::json!({}))
+            } else {
+                serde_json::json!({})
+            };
+
+            // Update MCP section
+            if is_enabled {
+                // Add or update this MCP
+                if let Ok(mcp_json) = serde_json::from_str::<serde_json::Value>(mcp_config_json) {
+                    if let Some(obj) = config.as_object_mut() {
+                        if !obj.contains_key("mcpServers") {
+                            obj.insert("mcpServers".to_string(), serde_json::json!({}));
+                        }
+                        if let Some(servers) = obj.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
+                            servers.insert(mcp_name.to_string(), mcp_json);
+                        }
+                    }
+                }
+            } else {
+                // Remove this MCP by name
+                if let Some(obj) = config.as_object_mut() {
+                    if let Some(servers) = obj.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
+                        servers.remove(mcp_name);
+                    }
+                }
+            }
+
+            // Write config file
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            let config_str = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+            std::fs::write(&path, config_str).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+// Helper function to sync a single MCP to Codex config.toml
+fn sync_single_codex_mcp(
+    config_path: std::path::PathBuf,
+    mcp_name: &str,
+    mcp_config_json: &str,
+    is_enabled: bool,
+) -> Result<()> {
+    // Read existing TOML or create new one
+    let mut doc = if config_path.exists() {
+        let content = std::fs::read_to_string(&config_path).map_err(|e| {
+            tracing::error!("Failed to read config.toml: {}", e);
+            e.to_string()
+        })?;
+        content.parse::<toml_edit::DocumentMut>().unwrap_or_else(|e| {
+            tracing::warn!("Failed to parse config.toml, creating new: {}", e);
+            toml_edit::DocumentMut::new()
+        })
+    } else {
+        toml_edit::DocumentMut::new()
+    };
+
+    // Ensure mcp_servers table exists
+    if !doc.contains_table("mcp_servers") {
+        doc["mcp_servers"] = toml_edit::table();
+    }
+
+    if is_enabled {
+        // Add or update this MCP
+        if let Ok(mcp_config) = serde_json::from_str::<serde_json::Value>(mcp_config_json) {
+            let mcp_type = mcp_config.get("type").and_then(|v| v.as_str()).unwrap_or("stdio");
+
+            // Create MCP server table
+            let mut server_table = toml_edit::Table::new();
+
+            // Handle STDIO type servers
+            if let Some(command) = mcp_config.get("command").and_then(|v| v.as_str()) {
+                server_table.insert("command", toml_edit::value(command));
+            }
+            if let Some(args) = mcp_config.get("args").and_then(|v| v.as_array()) {
+                let args_array: toml_edit::Array = args.iter()
+                    .filter_map(|v| v.as_str())
+                    .map(toml_edit::Value::from)
+                    .collect();
+                server_table.insert("args", toml_edit::Item::Value(args_array.into()));
+            }
+            if let Some(env) = mcp_config.get("env").and_then(|v| v.as_object()) {
+                let mut env_table = toml_edit::Table::new();
+                for (k, v) in env.iter() {
+                    if let Some(v_str) = v.as_str() {
+                        env_table.insert(k, toml_edit::value(v_str));
+                    }
+                }
+                server_table.insert("env", toml_edit::Item::Table(env_table));
+            }
+            if let Some(cwd) = mcp_config.get("cwd").and_then(|v| v.as_str()) {
+                server_table.insert("cwd", toml_edit::value(cwd));
+            }
+
+            // Handle HTTP/SSE type servers
+            if mcp_type == "sse" || mcp_type == "http" {
+                if let Some(url) = mcp_config.get("url").and_then(|v| v.as_str()) {
+                    server_table.insert("url", toml_edit::value(url));
+                }
+            }
+
+            // Optional fields
+            if let Some(timeout) = mcp_config.get("startup_timeout_sec").and_then(|v| v.as_i64()) {
+                server_table.insert("startup_timeout_sec", toml_edit::value(timeout));
+            }
+            if let Some(timeout) = mcp_config.get("tool_timeout_sec").and_then(|v| v.as_i64()) {
+                server_table.insert("tool_timeout_sec", toml_edit::value(timeout));
+            }
+
+            doc["mcp_servers"][mcp_name] = toml_edit::Item::Table(server_table);
+        }
+    } else {
+        // Remove this MCP by name
+        if let Some(table) = doc.get_mut("mcp_servers").and_then(|v| v.as_table_mut()) {
+            table.remove(mcp_name);
+        }
+    }
+
+    // Write config file
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            tracing::error!("Failed to create directory: {}", e);
+            e.to_string()
+        })?;
+    }
+    std::fs::write(&config_path, doc.to_string()).map_err(|e| {
+        tracing::error!("Failed to write config.toml: {}", e);
+        e.to_string()
+    })?;
+
+    Ok(())
+}
+
+// Delete a single MCP from all CLI configs
+fn delete_mcp_from_cli(mcp_name: &str) -> Result<()> {
+    let cli_types = vec!["claude_code", "codex", "gemini"];
+
+    for cli_type in cli_types {
+        let config_path = get_mcp_config_path(cli_type);
+        if let Some(path) = config_path {
+            if !path.exists() {
+                continue;
+            }
+
+            if cli_type == "codex" {
+                // Handle Codex TOML format
+                let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+                let mut doc = content.parse::<toml_edit::DocumentMut>().unwrap_or_else(|_| toml_edit::DocumentMut::new());
+
+                if let Some(table) = doc["mcp_servers"].as_table_mut() {
+                    table.remove(mcp_name);
+                }
+
+                std::fs::write(&path, doc.to_string()).map_err(|e| e.to_string())?;
+            } else {
+                // Handle Claude/Gemini JSON format
+                let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+                let mut config: serde_json::Value = serde_json::from_str(&content).unwrap_or_else(|_| serde_json::json!({}));
+
+                if let Some(mcp_servers) = config.get_mut("mcpServers").and_then(|v| v.as_object_mut()) {
+                    mcp_servers.remove(mcp_name);
+                }
+
+                let config_str = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+                std::fs::write(&path, config_str).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Read the MCP servers currently present in a CLI config file (Codex TOML converted to JSON)
+fn read_cli_mcp_servers(cli_type: &str) -> Result<std::collections::BTreeMap<String, serde_json::Value>> {
+    let path = get_mcp_config_path(cli_type).ok_or_else(|| "Invalid CLI type".to_string())?;
+    if !path.exists() {
+        return Ok(Default::default());
+    }
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+
+    if cli_type == "codex" {
+        let doc = content
+            .parse::<toml::Table>()
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        let Some(servers) = doc.get("mcp_servers").and_then(|v| v.as_table()) else {
+            return Ok(Default::default());
+        };
+        servers
+            .iter()
+            .map(|(name, server)| Ok((name.clone(), serde_json::to_value(server).map_err(|e| e.to_string())?)))
+            .collect()
+    } else {
+        let config: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        Ok(config
+            .get("mcpServers")
+            .and_then(|v| v.as_object())
+            .map(|servers| servers.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default())
+    }
+}
+
+// Project a stored MCP config onto the fields sync_single_codex_mcp writes to config.toml
+fn codex_mcp_projection(mcp_config: &serde_json::Value) -> serde_json::Value {
+    let mut server = serde_json::Map::new();
+    if let Some(command) = mcp_config.get("command").and_then(|v| v.as_str()) {
+        server.insert("command".to_string(), serde_json::json!(command));
+    }
+    if let Some(args) = mcp_config.get("args").and_then(|v| v.as_array()) {
+        let args: Vec<&str> = args.iter().filter_map(|v| v.as_str()).collect();
+        server.insert("args".to_string(), serde_json::json!(args));
+    }
+    if let Some(env) = mcp_config.get("env").and_then(|v| v.as_object()) {
+        let env: serde_json::Map<String, serde_json::Value> = env
+            .iter()
+            .filter(|(_, v)| v.is_string())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        server.insert("env".to_string(), serde_json::Value::Object(env));
+    }
+    if let Some(cwd) = mcp_config.get("cwd").and_then(|v| v.as_str()) {
+        server.insert("cwd".to_string(), serde_json::json!(cwd));
+    }
+    let mcp_type = mcp_config.get("type").and_then(|v| v.as_str()).unwrap_or("stdio");
+    if mcp_type == "sse" || mcp_type == "http" {
+        if let Some(url) = mcp_config.get("url").and_then(|v| v.as_str()) {
+            server.insert("url".to_string(), serde_json::json!(url));
+        }
+    }
+    for key in ["startup_timeout_sec", "tool_timeout_sec"] {
+        if let Some(timeout) = mcp_config.get(key).and_then(|v| v.as_i64()) {
+            server.insert(key.to_string(), serde_json::json!(timeout));
+        }
+    }
+    serde_json::Value::Object(server)
+}
+
+/// Compare stored MCP configs with the entries in one CLI config file
+async fn diff_mcp(db: &SqlitePool, cli_type: &str) -> Result<Vec<McpDiff>> {
+    let mut fs_servers = read_cli_mcp_servers(cli_type)?;
+    let mcps = sqlx::query_as::<_, McpConfig>("SELECT * FROM mcp_configs ORDER BY name")
+        .fetch_all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut diffs = Vec::new();
+    for mcp in mcps {
+        let fs_config = fs_servers.remove(&mcp.name);
+        let status = match &fs_config {
+            None => McpDiffStatus::DbOnly,
+            Some(fs_value) => {
+                // 语义比较：解析后的 JSON 相等即视为一致，忽略格式与键顺序
+                let db_value = serde_json::from_str::<serde_json::Value>(&mcp.config_json).ok();
+                let db_value = match (cli_type, db_value) {
+                    ("codex", Some(v)) => Some(codex_mcp_projection(&v)),
+                    (_, v) => v,
+                };
+                if db_value.as_ref() == Some(fs_value) {
+                    McpDiffStatus::InSync
+                } else {
+                    McpDiffStatus::Diverged
+                }
+            }
+        };
+        diffs.push(McpDiff {
+            mcp_name: mcp.name,
+            db_config: Some(mcp.config_json),
+            fs_config: fs_config.map(|v| v.to_string()),
+            status,
+        });
+    }
+
+    diffs.extend(fs_servers.into_iter().map(|(name, value)| McpDiff {
+        mcp_name: name,
+        db_config: None,
+        fs_config: Some(value.to_string()),
+        status: McpDiffStatus::FilesystemOnly,
+    }));
+    Ok(diffs)
+}
+
+#[tauri::command]
+pub async fn diff_mcp_with_filesystem(db: State<'_, SqlitePool>, cli_type: String) -> Result<Vec<McpDiff>> {
+    diff_mcp(db.inner(), &cli_type).await
+}
+
+#[tauri::command]
+pub async fn reconcile_mcp_from_filesystem(db: State<'_, SqlitePool>, cli_type: String) -> Result<i64> {
+    let diffs = diff_mcp(db.inner(), &cli_type).await?;
+    let now = chrono::Utc::now().timestamp();
+
+    let mut imported = 0i64;
+    for diff in diffs.into_iter().filter(|d| d.status == McpDiffStatus::FilesystemOnly) {
+        let Some(config_json) = diff.fs_config else {
+            continue;
+        };
+        let pretty = serde_json::from_str::<serde_json::Value>(&config_json)
+            .and_then(|v| serde_json::to_string_pretty(&v))
+            .unwrap_or(config_json);
+        sqlx::query("INSERT INTO mcp_configs (name, config_json, updated_at) VALUES (?, ?, ?)")
+            .bind(&diff.mcp_name)
+            .bind(&pretty)
+            .bind(now)
+            .execute(db.inner())
+            .await
+            .map_err(|e| e.to_string())?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+// Prompt commands
+#[tauri::command]
+pub async fn get_prompts(db: State<'_, SqlitePool>) -> Result<Vec<PromptResponse>> {
+    load_prompts(db.inner()).await
+}
+
+pub async fn load_prompts(db: &SqlitePool) -> Result<Vec<PromptResponse>> {
+    let prompts = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets ORDER BY id")
+        .fetch_all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let cli_types = vec!["claude_code", "codex", "gemini"];
+
+    let mut results = Vec::new();
+    for prompt in prompts {
+        // Read real status from prompt files
+        let mut cli_flags = Vec::new();
+        for cli_type in &cli_types {
+            let enabled = prompt_enabled_in_file(cli_type, &prompt.content);
+            cli_flags.push(PromptCliFlag {
+                cli_type: cli_type.to_string(),
+                enabled,
+            });
+        }
+
+        results.push(PromptResponse {
+            id: prompt.id,
+            name: prompt.name,
+            content: prompt.content,
+            cli_flags,
+            inject_system_prompt: prompt.inject_system_prompt != 0,
+        });
+    }
+    Ok(results)
+}
+
+/// Prompts whose content is currently in the CLI prompt file (several in append mode)
+#[tauri::command]
+pub async fn get_active_prompts(db: State<'_, SqlitePool>, cli_type: String) -> Result<Vec<PromptResponse>> {
+    let prompts = get_prompts(db).await?;
+    Ok(prompts
+        .into_iter()
+        .filter(|prompt| prompt.cli_flags.iter().any(|f| f.cli_type == cli_type && f.enabled))
+        .collect())
+}
+
+/// Contents of the prompts marked inject_system_prompt that are currently enabled for `cli_type`
+pub async fn load_injected_prompts(db: &SqlitePool, cli_type: &str) -> Result<Vec<String>> {
+    let contents: Vec<String> =
+        sqlx::query_scalar("SELECT content FROM prompt_presets WHERE inject_system_prompt = 1 ORDER BY id")
+            .fetch_all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+    Ok(contents
+        .into_iter()
+        .filter(|content| prompt_enabled_in_file(cli_type, content))
+        .collect())
+}
+
+#[tauri::command]
+pub async fn get_prompt(db: State<'_, SqlitePool>, id: i64) -> Result<PromptResponse> {
+    load_prompt(db.inner(), id).await
+}
+
+pub async fn load_prompt(db: &SqlitePool, id: i64) -> Result<PromptResponse> {
+    let prompt = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Prompt not found".to_string())?;
+
+    // Read real status from prompt files
+    let cli_types = vec!["claude_code", "codex", "gemini"];
+    let mut cli_flags = Vec::new();
+    for cli_type in &cli_types {
+        let enabled = prompt_enabled_in_file(cli_type, &prompt.content);
+        cli_flags.push(PromptCliFlag {
+            cli_type: cli_type.to_string(),
+            enabled,
+        });
+    }
+
+    Ok(PromptResponse {
+        id: prompt.id,
+        name: prompt.name,
+        content: prompt.content,
+        cli_flags,
+        inject_system_prompt: prompt.inject_system_prompt != 0,
+    })
+}
+
+#[tauri::command]
+pub async fn create_prompt(db: State<'_, SqlitePool>, input: PromptCreate) -> Result<PromptResponse> {
+    insert_prompt(db.inner(), input).await
+}
+
+pub async fn insert_prompt(db: &SqlitePool, input: PromptCreate) -> Result<PromptResponse> {
+    let now = chrono::Utc::now().timestamp();
+
+    let result = sqlx::query(
+        "INSERT INTO prompt_presets (name, content, inject_system_prompt, updated_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(&input.name)
+    .bind(&input.content)
+    .bind(input.inject_system_prompt.unwrap_or(false) as i64)
+    .bind(now)
+    .execute(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let id = result.last_insert_rowid();
+
+    // Sync to CLI files if cli_flags provided
+    let cli_flags = input.cli_flags.unwrap_or_default();
+    if !cli_flags.is_empty() {
+        sync_single_prompt_to_cli(db, &input.content, None, &cli_flags).await?;
+    }
+
+    load_prompt(db, id).await
+}
+
+#[tauri::command]
+pub async fn update_prompt(db: State<'_, SqlitePool>, id: i64, input: PromptUpdate) -> Result<PromptResponse> {
+    apply_prompt_update(db.inner(), id, input).await
+}
+
+pub async fn apply_prompt_update(db: &SqlitePool, id: i64, input: PromptUpdate) -> Result<PromptResponse> {
+    let now = chrono::Utc::now().timestamp();
+
+    let (content, previous_content) = if input.name.is_some() || input.content.is_some() {
+        let current = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Prompt not found".to_string())?;
+
+        let new_name = input.name.unwrap_or(current.name.clone());
+        let new_content = input.content.unwrap_or(current.content.clone());
+
+        sqlx::query(
+            "UPDATE prompt_presets SET name = ?, content = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(&new_name)
+        .bind(&new_content)
+        .bind(now)
+        .bind(id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        (new_content, Some(current.content))
+    } else {
+        // Get current values if not updating
+        let current = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "Prompt not found".to_string())?;
+        (current.content, None)
+    };
+
+    if let Some(inject_system_prompt) = input.inject_system_prompt {
+        sqlx::query("UPDATE prompt_presets SET inject_system_prompt = ?, updated_at = ? WHERE id = ?")
+            .bind(inject_system_prompt as i64)
+            .bind(now)
+            .bind(id)
+            .execute(db)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    // Sync to CLI files if cli_flags provided
+    if let Some(cli_flags) = input.cli_flags {
+        sync_single_prompt_to_cli(db, &content, previous_content.as_deref(), &cli_flags).await?;
+    }
+
+    load_prompt(db, id).await
+}
+
+#[tauri::command]
+pub async fn delete_prompt(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
+    remove_prompt(db.inner(), id).await
+}
+
+pub async fn remove_prompt(db: &SqlitePool, id: i64) -> Result<()> {
+    let prompt = sqlx::query_as::<_, PromptPreset>("SELECT * FROM prompt_presets WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    sqlx::query("DELETE FROM prompt_presets WHERE id = ?")
+        .bind(id)
+        .execute(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Append mode: take only this prompt's section out of the CLI prompt files
+    if let Some(prompt) = prompt {
+        for cli_type in ["claude_code", "codex", "gemini"] {
+            let Some(path) = get_prompt_file_path(cli_type) else {
+                continue;
+            };
+            if path.exists() && prompt_append_mode(db, cli_type).await? {
+                sync_prompt_section(&path, &prompt.content, None, false)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Sync a single prompt to CLI files based on enabled flags.
+// `previous_content` is the prompt text before an edit, replaced in append mode.
+async fn sync_single_prompt_to_cli(
+    db: &SqlitePool,
+    prompt_content: &str,
+    previous_content: Option<&str>,
+    cli_flags: &[PromptCliFlag],
+) -> Result<()> {
+    let cli_types = vec!["claude_code", "codex", "gemini"];
+
+    for cli_type in cli_types {
+        // Check if this prompt is enabled for this CLI
+        let is_enabled = cli_flags.iter()
+            .any(|f| f.cli_type == cli_type && f.enabled);
+
+        // Get the prompt file path for this CLI
+        let prompt_path = get_prompt_file_path(cli_type);
+        if let Some(path) = prompt_path {
+            // Check if CLI directory exists (skip if CLI not installed)
+            if let Some(parent) = path.parent() {
+                if !parent.exists() {
+                    continue;
+                }
+
+                if prompt_append_mode(db, cli_type).await? {
+                    sync_prompt_section(&path, prompt_content, previous_content, is_enabled)?;
+                } else if is_enabled {
+                    // Write prompt content to file
+                    std::fs::write(&path, prompt_content).map_err(|e| {
+                        tracing::error!("Failed to write prompt file: {}", e);
+                        e.to_string()
+                    })?;
+                } else {
+                    // Check if this prompt was previously in the file
+                    if path.exists() {
+                        let file_content = std::fs::read_to_string(&path).unwrap_or_default();
+                        if normalize_text(prompt_content) == normalize_text(&file_content) {
+                            // This prompt was in the file, clear it
+                            std::fs::write(&path, "").map_err(|e| {
+                                tracing::error!("Failed to clear prompt file: {}", e);
+                                e.to_string()
+                            })?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether enabled prompts are appended to the CLI prompt file (cli_settings.prompt_append_mode)
+async fn prompt_append_mode(db: &SqlitePool, cli_type: &str) -> Result<bool> {
+    let append: Option<i64> = sqlx::query_scalar("SELECT prompt_append_mode FROM cli_settings WHERE cli_type = ?")
+        .bind(cli_type)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(append.unwrap_or(0) != 0)
+}
+
+/// Add or remove one prompt's section of an append-mode prompt file, keeping the other prompts
+fn sync_prompt_section(
+    path: &std::path::Path,
+    prompt_content: &str,
+    previous_content: Option<&str>,
+    enabled: bool,
+) -> Result<()> {
+    let original = std::fs::read_to_string(path).unwrap_or_default();
+    let mut content = original.clone();
+    // 编辑过的提示词先移除旧内容，避免文件里残留两个版本
+    if let Some(previous) = previous_content.filter(|p| *p != prompt_content) {
+        content = remove_prompt_section(&content, previous).unwrap_or(content);
+    }
+    content = if enabled {
+        append_prompt_section(&content, prompt_content)
+    } else {
+        remove_prompt_section(&content, prompt_content).unwrap_or(content)
+    };
+
+    if content != original {
+        std::fs::write(path, content).map_err(|e| {
+            tracing::error!("Failed to write prompt file: {}", e);
+            e.to_string()
+        })?;
+    }
+    Ok(())
+}
+
+fn get_prompt_file_path(cli_type: &str) -> Option<std::path::PathBuf> {
+    let home = dirs::home_dir()?;
+    match cli_type {
+        "claude_code" => Some(home.join(".claude").join("CLAUDE.md")),
+        "codex" => Some(home.join(".codex").join("AGENTS.md")),
+        "gemini" => Some(home.join(".gemini").join("GEMINI.md")),
+        _ => None,
+    }
+}
+
+// Model pricing commands
+#[tauri::command]
+pub async fn get_model_pricing(db: State<'_, SqlitePool>) -> Result<Vec<ModelPricing>> {
+    sqlx::query_as::<_, ModelPricing>("SELECT * FROM model_pricing ORDER BY provider_name, model_id")
+        .fetch_all(db.inner())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_model_pricing(
+    db: State<'_, SqlitePool>,
+    provider_name: String,
+    model_id: String,
+    input_price_per_million_tokens: f64,
+    output_price_per_million_tokens: f64,
+    cache_read_price_per_million_tokens: Option<f64>,
+    cache_write_price_per_million_tokens: Option<f64>,
+) -> Result<ModelPricing> {
+    let provider_name = provider_name.trim().to_string();
+    let model_id = model_id.trim().to_string();
+    if provider_name.is_empty() || model_id.is_empty() {
+        return Err("Provider name and model id cannot be empty".to_string());
+    }
+    let prices = [input_price_per_million_tokens, output_price_per_million_tokens]
+        .into_iter()
+        .chain(cache_read_price_per_million_tokens)
+        .chain(cache_write_price_per_million_tokens);
+    for price in prices {
+        if !price.is_finite() || price < 0.0 {
+            return Err("Prices must be non-negative numbers".to_string());
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query(
+        r#"
+        INSERT INTO model_pricing (provider_name, model_id, input_price_per_million_tokens, output_price_per_million_tokens, cache_read_price_per_million_tokens, cache_write_price_per_million_tokens, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(provider_name, model_id) DO UPDATE SET
+            input_price_per_million_tokens = excluded.input_price_per_million_tokens,
+            output_price_per_million_tokens = excluded.output_price_per_million_tokens,
+            cache_read_price_per_million_tokens = excluded.cache_read_price_per_million_tokens,
+            cache_write_price_per_million_tokens = excluded.cache_write_price_per_million_tokens,
+            updated_at = excluded.updated_at
+        "#,
+    )
+    .bind(&provider_name)
+    .bind(&model_id)
+    .bind(input_price_per_million_tokens)
+    .bind(output_price_per_million_tokens)
+    .bind(cache_read_price_per_million_tokens)
+    .bind(cache_write_price_per_million_tokens)
+    .bind(now)
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    crate::services::pricing::find_pricing(db.inner(), &provider_name, &model_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Model pricing not found".to_string())
+}
+
+#[tauri::command]
+pub async fn delete_model_pricing(db: State<'_, SqlitePool>, id: i64) -> Result<()> {
+    sqlx::query("DELETE FROM model_pricing WHERE id = ?")
+        .bind(id)
+        .execute(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Stats commands
+#[tauri::command]
+pub async fn get_daily_stats(
+    log_db: State<'_, crate::LogDb>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    cli_type: Option<String>,
+) -> Result<Vec<DailyStats>> {
+    load_daily_stats(&log_db.0, start_date, end_date, cli_type).await
+}
+
+pub async fn load_daily_stats(
+    pool: &SqlitePool,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    cli_type: Option<String>,
+) -> Result<Vec<DailyStats>> {
+    let mut query = "SELECT * FROM usage_daily WHERE 1=1".to_string();
+    if start_date.is_some() {
+        query.push_str(" AND usage_date >= ?");
+    }
+    if end_date.is_some() {
+        query.push_str(" AND usage_date <= ?");
+    }
+    if cli_type.is_some() {
+        query.push_str(" AND cli_type = ?");
+    }
+    query.push_str(" ORDER BY usage_date DESC");
+
+    let mut q = sqlx::query_as::<_, DailyStats>(&query);
+    if let Some(ref sd) = start_date {
+        q = q.bind(sd);
+    }
+    if let Some(ref ed) = end_date {
+        q = q.bind(ed);
+    }
+    if let Some(ref ct) = cli_type {
+        q = q.bind(ct);
+    }
+
+    q.fetch_all(pool).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_provider_stats(
+    log_db: State<'_, crate::LogDb>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    cli_type: Option<String>,
+    provider_name: Option<String>,
+) -> Result<Vec<ProviderStatsResponse>> {
+    load_provider_stats(&log_db.0, start_date, end_date, cli_type, provider_name).await
+}
+
+pub async fn load_provider_stats(
+    pool: &SqlitePool,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    cli_type: Option<String>,
+    provider_name: Option<String>,
+) -> Result<Vec<ProviderStatsResponse>> {
+    let mut query = r#"
+        SELECT
+            cli_type,
+            provider_name,
+            model_id,
+            COUNT(*) as total_requests,
+            SUM(CASE WHEN status_code >= 200 AND status_code < 300 THEN 1 ELSE 0 END) as total_success,
+            SUM(input_tokens + output_tokens) as total_tokens,
+            SUM(elapsed_ms) as total_elapsed_ms,
+            AVG(ttfb_ms) as avg_ttfb_ms
+        FROM request_logs
+        WHERE 1=1
+    "#.to_string();
+
+    if start_date.is_some() {
+        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') >= ?");
+    }
+    if end_date.is_some() {
+        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') <= ?");
+    }
+    if cli_type.is_some() {
+        query.push_str(" AND cli_type = ?");
+    }
+    if provider_name.is_some() {
+        query.push_str(" AND provider_name = ?");
+    }
+    query.push_str(" GROUP BY cli_type, provider_name, model_id ORDER BY total_requests DESC");
+
+    let mut q = sqlx::query_as::<_, ProviderStatsRow>(&query);
+    if let Some(ref sd) = start_date {
+        q = q.bind(sd);
+    }
+    if let Some(ref ed) = end_date {
+        q = q.bind(ed);
+    }
+    if let Some(ref ct) = cli_type {
+        q = q.bind(ct);
+    }
+    if let Some(ref pn) = provider_name {
+        q = q.bind(pn);
+    }
+
+    let rows = q.fetch_all(pool).await.map_err(|e| e.to_string())?;
+
+    let results = rows.into_iter().map(|row| ProviderStatsResponse {
+        cli_type: row.cli_type,
+        provider_name: row.provider_name,
+        model_id: row.model_id,
+        total_requests: row.total_requests,
+        total_success: row.total_success,
+        total_tokens: row.total_tokens,
+        total_elapsed_ms: row.total_elapsed_ms,
+        avg_ttfb_ms: row.avg_ttfb_ms,
+        success_rate: if row.total_requests > 0 {
+            (row.total_success as f64 / row.total_requests as f64) * 100.0
+        } else {
+            0.0
+        },
+    }).collect();
+
+    Ok(results)
+}
+
+#[tauri::command]
+pub async fn get_model_usage_breakdown(
+    log_db: State<'_, crate::LogDb>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+) -> Result<Vec<ModelUsageStats>> {
+    let pool = &log_db.0;
+
+    let mut query = r#"
+        SELECT
+            model_id,
+            COUNT(*) as total_requests,
+            COALESCE(SUM(input_tokens), 0) as total_input_tokens,
+            COALESCE(SUM(output_tokens), 0) as total_output_tokens,
+            COUNT(DISTINCT provider_name) as provider_count,
+            COALESCE(AVG(elapsed_ms), 0.0) as avg_latency_ms
+        FROM request_logs
+        WHERE model_id IS NOT NULL AND model_id != ''
+    "#.to_string();
+
+    if start_date.is_some() {
+        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') >= ?");
+    }
+    if end_date.is_some() {
+        query.push_str(" AND datetime(created_at, 'unixepoch', 'localtime') <= ?");
+    }
+    query.push_str(" GROUP BY model_id ORDER BY total_requests DESC");
+
+    let mut q = sqlx::query_as::<_, ModelUsageStats>(&query);
+    if let Some(ref sd) = start_date {
+        q = q.bind(sd);
+    }
+    if let Some(ref ed) = end_date {
+        q = q.bind(ed);
+    }
+
+    q.fetch_all(pool).await.map_err(|e| e.to_string())
+}
+
+/// Models sent to a provider without hitting any of its enabled model maps.
+/// Requests rewritten by a global alias or a map carry a model_chain and count as covered.
+#[tauri::command]
+pub async fn get_uncovered_models(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, crate::LogDb>,
+    cli_type: Option<String>,
+) -> Result<Vec<UncoveredModel>> {
+    use crate::services::proxy::model_source_matches;
+
+    let mut query = r#"
+        SELECT
+            model_id,
+            provider_name,
+            cli_type,
+            COUNT(*) as request_count,
+            MIN(created_at) as first_seen,
+            MAX(created_at) as last_seen
+        FROM request_logs
+        WHERE model_id IS NOT NULL AND model_id != '' AND model_chain IS NULL
+    "#.to_string();
+    if cli_type.is_some() {
+        query.push_str(" AND cli_type = ?");
+    }
+    query.push_str(" GROUP BY model_id, provider_name, cli_type ORDER BY request_count DESC");
+
+    let mut q = sqlx::query_as::<_, UncoveredModel>(&query);
+    if let Some(ref ct) = cli_type {
+        q = q.bind(ct);
+    }
+    let used = q.fetch_all(&log_db.0).await.map_err(|e| e.to_string())?;
+
+    // 日志中只有服务商名称，按 (name, cli_type) 找到该服务商启用的映射
+    let maps: Vec<(String, String, String)> = sqlx::query_as(
+        r#"
+        SELECT p.name, p.cli_type, m.source_model
+        FROM provider_model_map m
+        JOIN providers p ON p.id = m.provider_id
+        WHERE m.enabled = 1
+        "#,
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(used
+        .into_iter()
+        .filter(|model| {
+            !maps.iter().any(|(provider_name, cli_type, source)| {
+                *provider_name == model.provider_name
+                    && *cli_type == model.cli_type
+                    && model_source_matches(source, &model.model_id)
+            })
+        })
+        .collect())
+}
+
+/// Known model IDs resembling `source_model`, best match first, as model map target candidates.
+///
+/// 候选来自服务商模型列表缓存、已配置的映射目标和请求日志中出现过的模型。
+#[tauri::command]
+pub async fn suggest_model_map(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, crate::LogDb>,
+    source_model: String,
+) -> Result<Vec<String>> {
+    const MAX_SUGGESTIONS: usize = 10;
+    const MIN_SIMILARITY: f64 = 0.4;
+
+    let source = source_model.trim().to_lowercase();
+    if source.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut known: std::collections::BTreeSet<String> = std::collections::BTreeSet::new();
+    let cached: Vec<String> = sqlx::query_scalar("SELECT models FROM provider_model_cache")
+        .fetch_all(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+    for models in cached {
+        known.extend(serde_json::from_str::<Vec<String>>(&models).unwrap_or_default());
+    }
+    let targets: Vec<String> = sqlx::query_scalar("SELECT DISTINCT target_model FROM provider_model_map")
+        .fetch_all(db.inner())
+        .await
+        .map_err(|e| e.to_string())?;
+    // 含通配符或捕获引用的目标不是具体模型
+    known.extend(targets.into_iter().filter(|t| !t.contains(['*', '?', '$'])));
+    let logged: Vec<String> = sqlx::query_scalar(
+        "SELECT DISTINCT model_id FROM request_logs WHERE model_id IS NOT NULL AND model_id != ''",
+    )
+    .fetch_all(&log_db.0)
+    .await
+    .map_err(|e| e.to_string())?;
+    known.extend(logged);
+
+    let mut scored: Vec<(f64, String)> = known
+        .into_iter()
+        .filter(|model| model.to_lowercase() != source)
+        .map(|model| (model_similarity(&source, &model.to_lowercase()), model))
+        .filter(|(score, _)| *score >= MIN_SIMILARITY)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    Ok(scored.into_iter().take(MAX_SUGGESTIONS).map(|(_, model)| model).collect())
+}
+
+/// 0..=1 similarity of two model IDs: 1 - edit distance / longer length,
+/// raised to 0.8 when one contains the other (e.g. a dated snapshot of the same model)
+fn model_similarity(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    // Levenshtein distance with a single row
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    let similarity = 1.0 - row[b.len()] as f64 / longest as f64;
+
+    let (a, b): (String, String) = (a.into_iter().collect(), b.into_iter().collect());
+    if a.contains(&b) || b.contains(&a) {
+        similarity.max(0.8)
+    } else {
+        similarity
+    }
+}
+
+#[tauri::command]
+pub async fn get_provider_error_breakdown(
+    log_db: State<'_, crate::LogDb>,
+    provider_name: Option<String>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+) -> Result<Vec<ProviderErrorBreakdown>> {
+    let pool = &log_db.0;
+
+    // 超时优先按错误信息判断（首字节超时没有状态码，流式空闲超时状态码为 200）
+    let mut query = r#"
+        SELECT
+            provider_name,
+            CASE
+                WHEN error_message LIKE '%timeout%' THEN 'timeout'
+                WHEN status_code = 429 THEN 'rate_limit'
+                WHEN status_code IN (401, 403) THEN 'auth_error'
+                WHEN status_code >= 500 THEN 'server_error'
+                WHEN status_code >= 400 THEN 'client_error'
+                ELSE 'unknown'
+            END as error_type,
+            COUNT(*) as count,
+            MAX(created_at) as last_occurrence
+        FROM request_logs
+        WHERE provider_name != ''
+          AND (status_code IS NULL OR status_code >= 400 OR error_message IS NOT NULL)
+    "#.to_string();
+
+    if provider_name.is_some() {
+        query.push_str(" AND provider_name = ?");
+    }
+    if start_date.is_some() {
+        query.push_str(" AND date(created_at, 'unixepoch', 'localtime') >= ?");
+    }
+    if end_date.is_some() {
+        query.push_str(" AND date(created_at, 'unixepoch', 'localtime') <= ?");
+    }
+    query.push_str(" GROUP BY provider_name, error_type ORDER BY count DESC");
+
+    let mut q = sqlx::query_as::<_, ProviderErrorBreakdown>(&query);
+    if let Some(ref pn) = provider_name {
+        q = q.bind(pn);
+    }
+    if let Some(ref sd) = start_date {
+        q = q.bind(sd);
+    }
+    if let Some(ref ed) = end_date {
+        q = q.bind(ed);
+    }
+
+    q.fetch_all(pool).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_hourly_stats(
+    log_db: State<'_, crate::LogDb>,
+    date: String,
+    cli_type: Option<String>,
+) -> Result<Vec<HourlyStats>> {
+    chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", date))?;
+
+    let pool = &log_db.0;
+
+    // created_at 有索引，先按时间戳范围粗筛，再按本地日期精确匹配
+    let mut query = r#"
+        SELECT
+            CAST(strftime('%H', created_at, 'unixepoch', 'localtime') AS INTEGER) as hour,
+            COUNT(*) as request_count,
+            COALESCE(SUM(CASE WHEN status_code >= 200 AND status_code < 300 THEN 1 ELSE 0 END), 0) as success_count,
+            COALESCE(SUM(input_tokens), 0) as input_tokens,
+            COALESCE(SUM(output_tokens), 0) as output_tokens
+        FROM request_logs
+        WHERE created_at >= CAST(strftime('%s', ?, 'utc') AS INTEGER) - 86400
+          AND created_at < CAST(strftime('%s', ?, 'utc') AS INTEGER) + 2 * 86400
+          AND date(created_at, 'unixepoch', 'localtime') = ?
+    "#.to_string();
+
+    if cli_type.is_some() {
+        query.push_str(" AND cli_type = ?");
+    }
+    query.push_str(" GROUP BY hour");
+
+    let mut q = sqlx::query_as::<_, HourlyStats>(&query)
+        .bind(&date)
+        .bind(&date)
+        .bind(&date);
+    if let Some(ref ct) = cli_type {
+        q = q.bind(ct);
+    }
+
+    let rows = q.fetch_all(pool).await.map_err(|e| e.to_string())?;
+
+    // 补齐 24 小时，缺失的小时填 0
+    let mut hours: Vec<HourlyStats> = (0..24)
+        .map(|hour| HourlyStats {
+            hour,
+            request_count: 0,
+            success_count: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+        })
+        .collect();
+    for row in rows {
+        if let Some(slot) = usize::try_from(row.hour).ok().and_then(|h| hours.get_mut(h)) {
+            *slot = row;
+        }
+    }
+
+    Ok(hours)
+}
+
+#[tauri::command]
+pub async fn get_available_models(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, crate::LogDb>,
+) -> Result<Vec<String>> {
+    let logged: Vec<(String,)> = sqlx::query_as(
+        "SELECT DISTINCT model_id FROM request_logs WHERE model_id IS NOT NULL AND model_id != ''",
+    )
+    .fetch_all(&log_db.0)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mapped: Vec<(String, String)> = sqlx::query_as(
+        "SELECT source_model, target_model FROM provider_model_map",
+    )
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    // BTreeSet 同时完成去重和排序
+    let models: std::collections::BTreeSet<String> = logged
+        .into_iter()
+        .map(|(m,)| m)
+        .chain(mapped.into_iter().flat_map(|(source, target)| [source, target]))
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty() && !m.starts_with(crate::services::proxy::MODEL_REGEX_PREFIX))
+        .collect();
+
+    Ok(models.into_iter().collect())
+}
+
+// Session helpers
+fn get_cli_base_dir(cli_type: &str) -> std::path::PathBuf {
+    let home = dirs::home_dir().unwrap_or_default();
+    match cli_type {
+        "codex" => home.join(".codex"),
+        "gemini" => home.join(".gemini"),
+        _ => home.join(".claude"),
+    }
+}
+
+// Compressed session files keep their original name plus ".gz"
+const SESSION_GZ_SUFFIX: &str = ".gz";
+
+fn is_gz_session(path: &std::path::Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.ends_with(SESSION_GZ_SUFFIX))
+}
+
+// File name without ".gz", used to match session file patterns
+fn session_file_name(path: &std::path::Path) -> &str {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    name.strip_suffix(SESSION_GZ_SUFFIX).unwrap_or(name)
+}
+
+// Session id: file name without ".gz" and the .jsonl/.json extension
+fn session_stem(path: &std::path::Path) -> String {
+    std::path::Path::new(session_file_name(path))
+        .file_stem()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+// Open a session file for line reading, decompressing .gz transparently
+fn open_session_reader(path: &std::path::Path) -> std::io::Result<Box<dyn std::io::BufRead>> {
+    use std::io::BufReader;
+    let file = std::fs::File::open(path)?;
+    if is_gz_session(path) {
+        Ok(Box::new(BufReader::new(flate2::read::GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+fn read_session_file(path: &std::path::Path) -> std::io::Result<String> {
+    use std::io::Read;
+    let mut content = String::new();
+    open_session_reader(path)?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+// Fall back to the compressed copy when the plain session file is gone
+fn resolve_session_file(path: std::path::PathBuf) -> std::path::PathBuf {
+    if path.exists() {
+        return path;
+    }
+    let mut gz = path.clone().into_os_string();
+    gz.push(SESSION_GZ_SUFFIX);
+    let gz = std::path::PathBuf::from(gz);
+    if gz.exists() { gz } else { path }
+}
+
+// Extract cwd from Codex session file
+fn extract_codex_cwd(file_path: &std::path::Path) -> Option<String> {
+    use std::io::BufRead;
+    let reader = open_session_reader(file_path).ok()?;
+    
+    for line in reader.lines().flatten() {
+        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
+            if data.get("type").and_then(|t| t.as_str()) == Some("session_meta") {
+                if let Some(cwd) = data.get("payload")
+                    .and_then(|p| p.get("cwd"))
+                    .and_then(|c| c.as_str()) {
+                    return Some(cwd.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+// Lines scanned per Claude Code session file when looking for the recorded cwd
+const CLAUDE_CWD_SCAN_LINES: usize = 50;
+
+// Extract cwd from the first Claude Code session file in a project folder that records one
+fn extract_claude_project_cwd(project_dir: &std::path::Path) -> Option<String> {
+    use std::io::BufRead;
+    let entries = std::fs::read_dir(project_dir).ok()?;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_file() || !session_file_name(&path).ends_with(".jsonl") {
+            continue;
+        }
+        let Ok(reader) = open_session_reader(&path) else {
+            continue;
+        };
+        for line in reader.lines().map_while(|l| l.ok()).take(CLAUDE_CWD_SCAN_LINES) {
+            let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) else {
+                continue;
+            };
+            if let Some(cwd) = data.get("cwd").and_then(|c| c.as_str()).filter(|c| !c.is_empty()) {
+                return Some(cwd.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Best-effort reverse of Claude Code's project folder naming.
+///
+/// Claude Code replaces every non-alphanumeric character of the project path with `-`
+/// (`/Users/alice/my-app` -> `-Users-alice-my-app`, `C:\work\app` -> `C--work-app`),
+/// so the name alone is ambiguous. Runs of `-` separated parts are matched against
+/// directories that exist on this machine (joined by `-`, ` `, `_` or `.`); whatever
+/// cannot be resolved is treated as one path component per part.
+pub fn decode_claude_project_path(encoded: &str) -> String {
+    // Windows 盘符：C:\ 编码为 "C--"
+    let bytes = encoded.as_bytes();
+    let (root, rest, sep) = if bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == b"--" {
+        (format!("{}:\\", &encoded[..1]), &encoded[3..], '\\')
+    } else if let Some(rest) = encoded.strip_prefix('-') {
+        ("/".to_string(), rest, '/')
+    } else {
+        (String::new(), encoded, '/')
+    };
+
+    let parts: Vec<&str> = rest.split('-').collect();
+    let mut components: Vec<String> = Vec::new();
+    let mut current = std::path::PathBuf::from(&root);
+    let mut resolved = !root.is_empty();
+    let mut i = 0;
+
+    while i < parts.len() {
+        // 从最长的片段开始尝试，优先匹配名称里本身带 - 的目录
+        let found = resolved
+            .then(|| {
+                (i..parts.len()).rev().find_map(|end| {
+                    ['-', ' ', '_', '.'].iter().find_map(|joiner| {
+                        let name = parts[i..=end].join(&joiner.to_string());
+                        (!name.is_empty() && current.join(&name).exists()).then_some((end, name))
+                    })
+                })
+            })
+            .flatten();
+
+        match found {
+            Some((end, name)) => {
+                current.push(&name);
+                components.push(name);
+                i = end + 1;
+            }
+            None => {
+                resolved = false;
+                // 空片段来自连续的 -，通常是以 . 开头的目录（如 .config）
+                if parts[i].is_empty() {
+                    if let Some(next) = parts.get(i + 1).filter(|p| !p.is_empty()) {
+                        components.push(format!(".{}", next));
+                        i += 2;
+                        continue;
+                    }
+                } else {
+                    components.push(parts[i].to_string());
+                }
+                i += 1;
+            }
+        }
+    }
+
+    format!("{}{}", root, components.join(&sep.to_string()))
+}
+
+// Handle Codex projects (group sessions by cwd)
+fn get_codex_projects(sessions_dir: std::path::PathBuf, page: i64, page_size: i64) -> Result<PaginatedProjects> {
+    use std::collections::HashMap;
+    use walkdir::WalkDir;
+    
     if !sessions_dir.exists() {
         return Ok(PaginatedProjects {
             items: vec![],
@@ -1933,9 +6530,7 @@ fn get_codex_projects(sessions_dir: std::path::PathBuf, page: i64, page_size: i6
     {
         let path = entry.path();
         if path.is_file() {
-            let filename = path.file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("");
+            let filename = session_file_name(path);
             
             if filename.starts_with("rollout-") && filename.ends_with(".jsonl") {
                 if let Some(cwd) = extract_codex_cwd(path) {
@@ -2055,9 +6650,7 @@ fn get_gemini_projects(tmp_dir: std::path::PathBuf, page: i64, page_size: i64) -
             for entry in entries.flatten() {
                 let session_path = entry.path();
                 if session_path.is_file() {
-                    let filename = session_path.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("");
+                    let filename = session_file_name(&session_path);
                     
                     if filename.starts_with("session-") && filename.ends_with(".json") {
                         session_count += 1;
@@ -2100,7 +6693,7 @@ fn get_gemini_projects(tmp_dir: std::path::PathBuf, page: i64, page_size: i64) -
 
 // Handle Codex sessions (find by cwd)
 fn get_codex_sessions(project_name: &str, page: i64, page_size: i64) -> Result<PaginatedSessions> {
-    use std::io::{BufRead, BufReader};
+    use std::io::BufRead;
     use walkdir::WalkDir;
     
     let home = dirs::home_dir().unwrap_or_default();
@@ -2125,9 +6718,7 @@ fn get_codex_sessions(project_name: &str, page: i64, page_size: i64) -> Result<P
     {
         let path = entry.path();
         if path.is_file() {
-            let filename = path.file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("");
+            let filename = session_file_name(path);
             
             if filename.starts_with("rollout-") && filename.ends_with(".jsonl") {
                 if let Some(cwd) = extract_codex_cwd(path) {
@@ -2160,10 +6751,7 @@ fn get_codex_sessions(project_name: &str, page: i64, page_size: i64) -> Result<P
     
     let mut sessions = Vec::new();
     for (path, meta) in page_files {
-        let session_id = path.file_stem()
-            .and_then(|n| n.to_str())
-            .unwrap_or("")
-            .to_string();
+        let session_id = session_stem(&path);
         
         let size = meta.len() as i64;
         let mtime = meta.modified().ok()
@@ -2173,8 +6761,7 @@ fn get_codex_sessions(project_name: &str, page: i64, page_size: i64) -> Result<P
         
         // Try to extract first message
         let mut first_message = String::new();
-        if let Ok(file) = std::fs::File::open(&path) {
-            let reader = BufReader::new(file);
+        if let Ok(reader) = open_session_reader(&path) {
             for line in reader.lines().flatten() {
                 if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
                     if data.get("type").and_then(|t| t.as_str()) == Some("event_msg") {
@@ -2190,484 +6777,1126 @@ fn get_codex_sessions(project_name: &str, page: i64, page_size: i64) -> Result<P
                 }
             }
         }
-        
-        sessions.push(SessionInfo {
-            session_id,
-            size,
-            mtime,
-            first_message,
-            git_branch: String::new(),
-            summary: String::new(),
-        });
+        
+        sessions.push(SessionInfo {
+            session_id,
+            size,
+            mtime,
+            first_message,
+            git_branch: String::new(),
+            summary: None,
+            estimated_cost_usd: None,
+        });
+    }
+    
+    Ok(PaginatedSessions {
+        items: sessions,
+        total,
+        page,
+        page_size,
+    })
+}
+
+// Handle Gemini sessions
+fn get_gemini_sessions(project_name: &str, page: i64, page_size: i64) -> Result<PaginatedSessions> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let chats_dir = home.join(".gemini").join("tmp").join(project_name).join("chats");
+    
+    if !chats_dir.exists() {
+        return Ok(PaginatedSessions {
+            items: vec![],
+            total: 0,
+            page,
+            page_size,
+        });
+    }
+    
+    let mut session_files: Vec<(std::path::PathBuf, std::fs::Metadata)> = Vec::new();
+    
+    if let Ok(entries) = std::fs::read_dir(&chats_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() {
+                let filename = session_file_name(&path);
+                
+                if filename.starts_with("session-") && filename.ends_with(".json") {
+                    if let Ok(meta) = path.metadata() {
+                        session_files.push((path, meta));
+                    }
+                }
+            }
+        }
+    }
+    
+    // Sort by mtime descending
+    session_files.sort_by(|a, b| {
+        let a_mtime = a.1.modified().ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let b_mtime = b.1.modified().ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        b_mtime.partial_cmp(&a_mtime).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    
+    let total = session_files.len() as i64;
+    let start = ((page - 1) * page_size) as usize;
+    let page_files: Vec<_> = session_files.into_iter().skip(start).take(page_size as usize).collect();
+    
+    let mut sessions = Vec::new();
+    for (path, meta) in page_files {
+        let session_id = session_stem(&path);
+        
+        let size = meta.len() as i64;
+        let mtime = meta.modified().ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        
+        // Try to extract first message
+        let mut first_message = String::new();
+        if let Ok(content) = read_session_file(&path) {
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
+                if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
+                    for msg in messages {
+                        if msg.get("type").and_then(|t| t.as_str()) == Some("user") {
+                            if let Some(text) = msg.get("content").and_then(|c| c.as_str()) {
+                                first_message = text.chars().take(200).collect();
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        
+        sessions.push(SessionInfo {
+            session_id,
+            size,
+            mtime,
+            first_message,
+            git_branch: String::new(),
+            summary: None,
+            estimated_cost_usd: None,
+        });
+    }
+    
+    Ok(PaginatedSessions {
+        items: sessions,
+        total,
+        page,
+        page_size,
+    })
+}
+
+// Find a Codex session file by searching the sessions directory recursively
+fn find_codex_session_file(session_id: &str) -> Result<std::path::PathBuf> {
+    use walkdir::WalkDir;
+    
+    let home = dirs::home_dir().unwrap_or_default();
+    let sessions_dir = home.join(".codex").join("sessions");
+    
+    for entry in WalkDir::new(&sessions_dir)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        // Match session_id which is the stem (filename without extension)
+        if path.is_file() && session_stem(path) == session_id {
+            return Ok(path.to_path_buf());
+        }
+    }
+    
+    Err(format!("Session file not found: {}", session_id))
+}
+
+// Parse Codex messages from JSONL file
+fn get_codex_messages(session_id: &str) -> Result<Vec<SessionMessage>> {
+    let session_file = find_codex_session_file(session_id)?;
+    
+    let content = read_session_file(&session_file)
+        .map_err(|e| format!("Failed to read session file: {}", e))?;
+
+    parse_codex_jsonl(&content)
+}
+
+// Parse Codex messages from JSONL content
+fn parse_codex_jsonl(content: &str) -> Result<Vec<SessionMessage>> {
+    use std::io::{BufRead, BufReader};
+
+    let reader = BufReader::new(content.as_bytes());
+    
+    let mut messages = Vec::new();
+    
+    for line in reader.lines().flatten() {
+        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
+            let msg_type = data.get("type").and_then(|t| t.as_str());
+            
+            // Only process response_item for structured messages
+            if msg_type == Some("response_item") {
+                if let Some(payload) = data.get("payload") {
+                    let item_type = payload.get("type").and_then(|t| t.as_str());
+                    let role = payload.get("role").and_then(|r| r.as_str());
+                    let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
+                    
+                    // User messages
+                    if role == Some("user") && item_type == Some("message") {
+                        if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
+                            let text_parts: Vec<String> = content_list.iter()
+                                .filter_map(|item| {
+                                    if item.get("type").and_then(|t| t.as_str()) == Some("input_text") {
+                                        item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
+                                    } else {
+                                        None
+                                    }
+                                })
+                                .collect();
+                            if !text_parts.is_empty() {
+                                messages.push(SessionMessage {
+                                    role: "user".to_string(),
+                                    content: text_parts.join("\n\n"),
+                                    timestamp,
+                                });
+                            }
+                        }
+                    }
+                    // Assistant messages
+                    else if role == Some("assistant") && item_type == Some("message") {
+                        if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
+                            let text_parts: Vec<String> = content_list.iter()
+                                .filter_map(|item| {
+                                    let item_type = item.get("type").and_then(|t| t.as_str());
+                                    if item_type == Some("output_text") || item_type == Some("text") {
+                                        item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
+                                    } else {
+                                        None
+                                    }
+                                })
+                                .collect();
+                            if !text_parts.is_empty() {
+                                messages.push(SessionMessage {
+                                    role: "assistant".to_string(),
+                                    content: text_parts.join("\n\n"),
+                                    timestamp,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
     }
     
-    Ok(PaginatedSessions {
-        items: sessions,
-        total,
-        page,
-        page_size,
-    })
+    Ok(messages)
 }
 
-// Handle Gemini sessions
-fn get_gemini_sessions(project_name: &str, page: i64, page_size: i64) -> Result<PaginatedSessions> {
-    let home = dirs::home_dir().unwrap_or_default();
-    let chats_dir = home.join(".gemini").join("tmp").join(project_name).join("chats");
-    
-    if !chats_dir.exists() {
-        return Ok(PaginatedSessions {
-            items: vec![],
-            total: 0,
-            page,
-            page_size,
-        });
-    }
+// Parse Claude Code messages from JSONL content
+fn parse_claude_jsonl(content: &str) -> Result<Vec<SessionMessage>> {
+    use std::io::{BufRead, BufReader};
     
-    let mut session_files: Vec<(std::path::PathBuf, std::fs::Metadata)> = Vec::new();
+    let mut messages = Vec::new();
+    let reader = BufReader::new(content.as_bytes());
     
-    if let Ok(entries) = std::fs::read_dir(&chats_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_file() {
-                let filename = path.file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("");
+    for line in reader.lines().flatten() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        
+        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
+            let msg_type = data.get("type").and_then(|t| t.as_str());
+            
+            if msg_type == Some("user") || msg_type == Some("assistant") {
+                let role = msg_type.unwrap();
+                let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
                 
-                if filename.starts_with("session-") && filename.ends_with(".json") {
-                    if let Ok(meta) = path.metadata() {
-                        session_files.push((path, meta));
+                if let Some(message) = data.get("message") {
+                    let content_val = message.get("content");
+                    
+                    let content = if let Some(arr) = content_val.and_then(|c| c.as_array()) {
+                        arr.iter()
+                            .filter_map(|item| {
+                                if item.get("type").and_then(|t| t.as_str()) == Some("text") {
+                                    item.get("text").and_then(|t| t.as_str())
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    } else if let Some(text) = content_val.and_then(|c| c.as_str()) {
+                        text.to_string()
+                    } else {
+                        continue;
+                    };
+                    
+                    if !content.is_empty() && content != "Warmup" {
+                        messages.push(SessionMessage {
+                            role: role.to_string(),
+                            content,
+                            timestamp,
+                        });
                     }
                 }
             }
         }
     }
     
-    // Sort by mtime descending
-    session_files.sort_by(|a, b| {
-        let a_mtime = a.1.modified().ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs_f64())
-            .unwrap_or(0.0);
-        let b_mtime = b.1.modified().ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs_f64())
-            .unwrap_or(0.0);
-        b_mtime.partial_cmp(&a_mtime).unwrap_or(std::cmp::Ordering::Equal)
-    });
-    
-    let total = session_files.len() as i64;
-    let start = ((page - 1) * page_size) as usize;
-    let page_files: Vec<_> = session_files.into_iter().skip(start).take(page_size as usize).collect();
-    
-    let mut sessions = Vec::new();
-    for (path, meta) in page_files {
-        let session_id = path.file_stem()
-            .and_then(|n| n.to_str())
-            .unwrap_or("")
-            .to_string();
-        
-        let size = meta.len() as i64;
-        let mtime = meta.modified().ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs_f64())
-            .unwrap_or(0.0);
-        
-        // Try to extract first message
-        let mut first_message = String::new();
-        if let Ok(content) = std::fs::read_to_string(&path) {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-                if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
-                    for msg in messages {
-                        if msg.get("type").and_then(|t| t.as_str()) == Some("user") {
-                            if let Some(text) = msg.get("content").and_then(|c| c.as_str()) {
-                                first_message = text.chars().take(200).collect();
-                                break;
+    Ok(messages)
+}
+
+// Parse Gemini messages from JSON content
+fn parse_gemini_json(content: &str) -> Result<Vec<SessionMessage>> {
+    let json: serde_json::Value = serde_json::from_str(content)
+        .map_err(|e| format!("Failed to parse session JSON: {}", e))?;
+
+    let mut messages = Vec::new();
+
+    // Try to parse messages in different formats
+    if let Some(msgs) = json.get("messages").and_then(|m| m.as_array()) {
+        // Standard format with messages array
+        for msg in msgs {
+            let msg_type = msg.get("type").and_then(|t| t.as_str()).unwrap_or("");
+            let role = match msg_type {
+                "human" | "user" => "user",
+                "assistant" | "ai" | "gemini" => "assistant",  // Add "gemini" type
+                _ => continue,
+            };
+
+            let content = if let Some(content_val) = msg.get("content") {
+                if let Some(arr) = content_val.as_array() {
+                    arr.iter()
+                        .filter_map(|item| item.get("text").and_then(|t| t.as_str()))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                } else if let Some(text) = content_val.as_str() {
+                    text.to_string()
+                } else {
+                    continue;
+                }
+            } else {
+                continue;
+            };
+
+            let timestamp = msg.get("timestamp").and_then(|t| t.as_str()).map(|s| {
+                chrono::DateTime::parse_from_rfc3339(s)
+                    .ok()
+                    .map(|dt| dt.timestamp())
+            }).flatten();
+
+            messages.push(SessionMessage {
+                role: role.to_string(),
+                content,
+                timestamp,
+            });
+        }
+    } else if let Some(conversation) = json.as_object() {
+        // Try to parse as flat object with role-based keys
+        for (key, value) in conversation {
+            if key == "id" || key == "title" || key == "created_at" || key == "updated_at" {
+                continue;
+            }
+            let role = if key.starts_with("user") || key.starts_with("human") {
+                "user"
+            } else if key.starts_with("assistant") || key.starts_with("ai") {
+                "assistant"
+            } else {
+                continue;
+            };
+
+            if let Some(text) = value.as_str() {
+                messages.push(SessionMessage {
+                    role: role.to_string(),
+                    content: text.to_string(),
+                    timestamp: None,
+                });
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+// Session commands
+#[tauri::command]
+pub async fn get_session_projects(
+    cli_type: String,
+    page: Option<i64>,
+    page_size: Option<i64>,
+) -> Result<PaginatedProjects> {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+
+    let base_dir = get_cli_base_dir(&cli_type);
+    let projects_dir = match cli_type.as_str() {
+        "codex" => base_dir.join("sessions"),
+        "gemini" => base_dir.join("tmp"),
+        _ => base_dir.join("projects"),
+    };
+
+    // For Codex, we need special handling since sessions are not in project folders
+    if cli_type == "codex" {
+        return get_codex_projects(projects_dir, page, page_size);
+    }
+
+    // For Gemini, check if sessions are in hash directories with chats subfolder
+    if cli_type == "gemini" {
+        return get_gemini_projects(projects_dir, page, page_size);
+    }
+
+    let mut projects = Vec::new();
+
+    if projects_dir.exists() {
+        if let Ok(entries) = std::fs::read_dir(&projects_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    let name = path.file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("")
+                        .to_string();
+
+                    if name.is_empty() || name.starts_with('.') {
+                        continue;
+                    }
+
+                    // Count sessions and calculate size
+                    let mut session_count = 0i64;
+                    let mut total_size = 0i64;
+                    let mut last_modified = 0f64;
+
+                    if let Ok(sessions) = std::fs::read_dir(&path) {
+                        for session in sessions.flatten() {
+                            let session_path = session.path();
+                            if session_path.is_file() {
+                                session_count += 1;
+                                if let Ok(meta) = session_path.metadata() {
+                                    total_size += meta.len() as i64;
+                                    if let Ok(mtime) = meta.modified() {
+                                        let secs = mtime.duration_since(std::time::UNIX_EPOCH)
+                                            .map(|d| d.as_secs_f64())
+                                            .unwrap_or(0.0);
+                                        if secs > last_modified {
+                                            last_modified = secs;
+                                        }
+                                    }
+                                }
                             }
                         }
                     }
+
+                    let display_name = if cli_type == "claude_code" {
+                        // Session files record the real cwd; the folder name is only a lossy encoding
+                        extract_claude_project_cwd(&path).unwrap_or_else(|| decode_claude_project_path(&name))
+                    } else {
+                        name.clone()
+                    };
+
+                    projects.push(ProjectInfo {
+                        name: name.clone(),
+                        display_name,
+                        full_path: path.to_string_lossy().to_string(),
+                        session_count,
+                        total_size,
+                        last_modified,
+                    });
                 }
             }
         }
-        
-        sessions.push(SessionInfo {
-            session_id,
-            size,
-            mtime,
-            first_message,
-            git_branch: String::new(),
-            summary: String::new(),
-        });
     }
-    
-    Ok(PaginatedSessions {
-        items: sessions,
+
+    // Sort by last_modified descending
+    projects.sort_by(|a, b| b.last_modified.partial_cmp(&a.last_modified).unwrap_or(std::cmp::Ordering::Equal));
+
+    let total = projects.len() as i64;
+    let start = ((page - 1) * page_size) as usize;
+    let items: Vec<_> = projects.into_iter().skip(start).take(page_size as usize).collect();
+
+    Ok(PaginatedProjects {
+        items,
         total,
         page,
         page_size,
     })
 }
 
-// Parse Codex messages from JSONL file
-fn get_codex_messages(session_id: &str) -> Result<Vec<SessionMessage>> {
-    use std::io::{BufRead, BufReader};
-    use walkdir::WalkDir;
-    
-    let home = dirs::home_dir().unwrap_or_default();
-    let sessions_dir = home.join(".codex").join("sessions");
-    
-    // Find the session file by searching recursively
-    let mut session_file_path: Option<std::path::PathBuf> = None;
-    for entry in WalkDir::new(&sessions_dir)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-        if path.is_file() {
-            // Match session_id which is the stem (filename without extension)
-            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
-                if stem == session_id {
-                    session_file_path = Some(path.to_path_buf());
-                    break;
-                }
+#[tauri::command]
+pub async fn get_project_sessions(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    cli_type: String,
+    project_name: String,
+    page: Option<i64>,
+    page_size: Option<i64>,
+) -> Result<PaginatedSessions> {
+    load_project_sessions(db.inner(), &log_db.0, cli_type, project_name, page, page_size).await
+}
+
+/// Sessions of a project with their estimated cost; shared by get_project_sessions and the HTTP API
+pub async fn load_project_sessions(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    cli_type: String,
+    project_name: String,
+    page: Option<i64>,
+    page_size: Option<i64>,
+) -> Result<PaginatedSessions> {
+    let mut result = list_project_sessions(cli_type.clone(), project_name, page, page_size)?;
+
+    // 按 session_id 汇总请求日志估算成本；估算失败不影响会话列表
+    let session_ids: Vec<String> = result.items.iter().map(|s| s.session_id.clone()).collect();
+    match crate::services::pricing::session_costs(db, log_db, &session_ids).await {
+        Ok(costs) => {
+            for session in &mut result.items {
+                session.estimated_cost_usd = costs.get(&session.session_id).copied();
             }
         }
+        Err(e) => tracing::warn!("Failed to estimate session costs: {}", e),
     }
-    
-    let session_file = session_file_path.ok_or_else(|| format!("Session file not found: {}", session_id))?;
-    
-    let file = std::fs::File::open(&session_file)
-        .map_err(|e| format!("Failed to open session file: {}", e))?;
-    let reader = BufReader::new(file);
-    
-    let mut messages = Vec::new();
-    
-    for line in reader.lines().flatten() {
-        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
-            let msg_type = data.get("type").and_then(|t| t.as_str());
-            
-            // Only process response_item for structured messages
-            if msg_type == Some("response_item") {
-                if let Some(payload) = data.get("payload") {
-                    let item_type = payload.get("type").and_then(|t| t.as_str());
-                    let role = payload.get("role").and_then(|r| r.as_str());
-                    let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
-                    
-                    // User messages
-                    if role == Some("user") && item_type == Some("message") {
-                        if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
-                            let text_parts: Vec<String> = content_list.iter()
-                                .filter_map(|item| {
-                                    if item.get("type").and_then(|t| t.as_str()) == Some("input_text") {
-                                        item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect();
-                            if !text_parts.is_empty() {
-                                messages.push(SessionMessage {
-                                    role: "user".to_string(),
-                                    content: text_parts.join("\n\n"),
-                                    timestamp,
-                                });
-                            }
-                        }
-                    }
-                    // Assistant messages
-                    else if role == Some("assistant") && item_type == Some("message") {
-                        if let Some(content_list) = payload.get("content").and_then(|c| c.as_array()) {
-                            let text_parts: Vec<String> = content_list.iter()
-                                .filter_map(|item| {
-                                    let item_type = item.get("type").and_then(|t| t.as_str());
-                                    if item_type == Some("output_text") || item_type == Some("text") {
-                                        item.get("text").and_then(|t| t.as_str()).map(|s| s.to_string())
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect();
-                            if !text_parts.is_empty() {
-                                messages.push(SessionMessage {
-                                    role: "assistant".to_string(),
-                                    content: text_parts.join("\n\n"),
-                                    timestamp,
-                                });
-                            }
-                        }
-                    }
+
+    if !session_ids.is_empty() {
+        let sql = format!(
+            "SELECT session_id, summary FROM session_summaries WHERE cli_type = ? AND session_id IN ({})",
+            vec!["?"; session_ids.len()].join(", ")
+        );
+        let mut q = sqlx::query_as::<_, (String, String)>(&sql).bind(&cli_type);
+        for id in &session_ids {
+            q = q.bind(id);
+        }
+        match q.fetch_all(db).await {
+            Ok(rows) => {
+                let summaries: std::collections::HashMap<String, String> = rows.into_iter().collect();
+                for session in &mut result.items {
+                    session.summary = summaries.get(&session.session_id).cloned();
                 }
             }
+            Err(e) => tracing::warn!("Failed to load session summaries: {}", e),
         }
     }
-    
-    Ok(messages)
+    Ok(result)
 }
 
-// Parse Claude Code messages from JSONL content
-fn parse_claude_jsonl(content: &str) -> Result<Vec<SessionMessage>> {
-    use std::io::{BufRead, BufReader};
-    
-    let mut messages = Vec::new();
-    let reader = BufReader::new(content.as_bytes());
-    
-    for line in reader.lines().flatten() {
-        if line.trim().is_empty() {
-            continue;
-        }
-        
-        if let Ok(data) = serde_json::from_str::<serde_json::Value>(&line) {
-            let msg_type = data.get("type").and_then(|t| t.as_str());
-            
-            if msg_type == Some("user") || msg_type == Some("assistant") {
-                let role = msg_type.unwrap();
-                let timestamp = data.get("timestamp").and_then(|t| t.as_i64());
-                
-                if let Some(message) = data.get("message") {
-                    let content_val = message.get("content");
-                    
-                    let content = if let Some(arr) = content_val.and_then(|c| c.as_array()) {
-                        arr.iter()
-                            .filter_map(|item| {
-                                if item.get("type").and_then(|t| t.as_str()) == Some("text") {
-                                    item.get("text").and_then(|t| t.as_str())
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect::<Vec<_>>()
-                            .join("\n")
-                    } else if let Some(text) = content_val.and_then(|c| c.as_str()) {
-                        text.to_string()
-                    } else {
+fn list_project_sessions(
+    cli_type: String,
+    project_name: String,
+    page: Option<i64>,
+    page_size: Option<i64>,
+) -> Result<PaginatedSessions> {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+
+    // Special handling for Codex
+    if cli_type == "codex" {
+        return get_codex_sessions(&project_name, page, page_size);
+    }
+
+    // Special handling for Gemini
+    if cli_type == "gemini" {
+        return get_gemini_sessions(&project_name, page, page_size);
+    }
+
+    // Claude Code default handling
+    let base_dir = get_cli_base_dir(&cli_type);
+    let project_dir = base_dir.join("projects").join(&project_name);
+
+    let mut sessions = Vec::new();
+
+    if project_dir.exists() {
+        if let Ok(entries) = std::fs::read_dir(&project_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_file() {
+                    let session_id = session_stem(&path);
+
+                    if session_id.is_empty() {
                         continue;
-                    };
-                    
-                    if !content.is_empty() && content != "Warmup" {
-                        messages.push(SessionMessage {
-                            role: role.to_string(),
-                            content,
-                            timestamp,
-                        });
                     }
+
+                    let mut size = 0i64;
+                    let mut mtime = 0f64;
+                    let mut first_message = String::new();
+
+                    if let Ok(meta) = path.metadata() {
+                        size = meta.len() as i64;
+                        if let Ok(mt) = meta.modified() {
+                            mtime = mt.duration_since(std::time::UNIX_EPOCH)
+                                .map(|d| d.as_secs_f64())
+                                .unwrap_or(0.0);
+                        }
+                    }
+
+                    // Try to read first message from JSON
+                    if let Ok(content) = read_session_file(&path) {
+                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
+                            // Claude Code format
+                            if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
+                                for msg in messages {
+                                    if msg.get("type").and_then(|t| t.as_str()) == Some("human") {
+                                        if let Some(content) = msg.get("content") {
+                                            if let Some(arr) = content.as_array() {
+                                                for item in arr {
+                                                    if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
+                                                        first_message = text.chars().take(200).collect();
+                                                        break;
+                                                    }
+                                                }
+                                            } else if let Some(text) = content.as_str() {
+                                                first_message = text.chars().take(200).collect();
+                                            }
+                                        }
+                                        break;
+                                    }
+                                    // Gemini format
+                                    if msg.get("type").and_then(|t| t.as_str()) == Some("user") {
+                                        if let Some(text) = msg.get("content").and_then(|c| c.as_str()) {
+                                            first_message = text.chars().take(200).collect();
+                                            break;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    sessions.push(SessionInfo {
+                        session_id,
+                        size,
+                        mtime,
+                        first_message,
+                        git_branch: String::new(),
+                        summary: None,
+                        estimated_cost_usd: None,
+                    });
                 }
             }
         }
     }
+
+    // Sort by mtime descending
+    sessions.sort_by(|a, b| b.mtime.partial_cmp(&a.mtime).unwrap_or(std::cmp::Ordering::Equal));
+
+    let total = sessions.len() as i64;
+    let start = ((page - 1) * page_size) as usize;
+    let items: Vec<_> = sessions.into_iter().skip(start).take(page_size as usize).collect();
+
+    Ok(PaginatedSessions {
+        items,
+        total,
+        page,
+        page_size,
+    })
+}
+
+#[tauri::command]
+pub async fn get_session_messages(
+    cli_type: String,
+    project_name: String,
+    session_id: String,
+) -> Result<Vec<SessionMessage>> {
+    // Special handling for Codex JSONL format
+    if cli_type == "codex" {
+        return get_codex_messages(&session_id);
+    }
     
-    Ok(messages)
+    let session_file = resolve_session_file(session_file_path(&cli_type, &project_name, &session_id));
+
+    let content = read_session_file(&session_file)
+        .map_err(|e| format!("Failed to read session file: {}", e))?;
+
+    // For Claude Code JSONL format
+    if cli_type == "claude_code" {
+        return parse_claude_jsonl(&content);
+    }
+    
+    // For Gemini JSON format
+    parse_gemini_json(&content)
 }
 
-// Session commands
+/// Ask a provider for a one-sentence summary of a session and store it in session_summaries.
+/// Uses the given provider, or the first enabled provider of the session's CLI type.
 #[tauri::command]
-pub async fn get_session_projects(
+#[allow(clippy::too_many_arguments)]
+pub async fn get_session_summary(
+    db: State<'_, SqlitePool>,
+    log_db: State<'_, LogDb>,
+    http_clients: State<'_, crate::services::http_client::HttpClientPool>,
+    mask_patterns: State<'_, crate::MaskPatterns>,
     cli_type: String,
-    page: Option<i64>,
-    page_size: Option<i64>,
-) -> Result<PaginatedProjects> {
-    let page = page.unwrap_or(1).max(1);
-    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+    project_name: String,
+    session_id: String,
+    provider_id: Option<i64>,
+) -> Result<String> {
+    use crate::services::proxy::{
+        apply_body_model_mapping, apply_url_model_mapping, parse_token_usage, CliType, ProviderProtocol, TokenUsage,
+    };
+    use crate::services::routing::ProviderWithMaps;
+    use crate::services::session_summary::{
+        conversation_excerpt, default_model, extract_summary, summary_request_body, SummaryApi, SUMMARY_PATH_PREFIX,
+    };
 
-    let base_dir = get_cli_base_dir(&cli_type);
-    let projects_dir = match cli_type.as_str() {
-        "codex" => base_dir.join("sessions"),
-        "gemini" => base_dir.join("tmp"),
-        _ => base_dir.join("projects"),
+    let messages = get_session_messages(cli_type.clone(), project_name, session_id.clone()).await?;
+    let excerpt = conversation_excerpt(&messages);
+    if excerpt.is_empty() {
+        return Err("Session has no messages to summarize".to_string());
+    }
+
+    let provider = match provider_id {
+        Some(id) => sqlx::query_as::<_, Provider>("SELECT * FROM providers WHERE id = ?")
+            .bind(id)
+            .fetch_optional(db.inner())
+            .await,
+        None => sqlx::query_as::<_, Provider>(
+            "SELECT * FROM providers WHERE cli_type = ? AND enabled = 1 ORDER BY sort_order, id LIMIT 1",
+        )
+        .bind(&cli_type)
+        .fetch_optional(db.inner())
+        .await,
+    }
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "No provider available for summarizing".to_string())?;
+    let model_maps = sqlx::query_as::<_, crate::db::models::ProviderModelMap>(
+        "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY sort_order, id",
+    )
+    .bind(provider.id)
+    .fetch_all(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let provider_cli: CliType = provider.cli_type.parse()?;
+    let openai_protocol = ProviderProtocol::from_provider(provider.protocol.as_deref()) == ProviderProtocol::OpenAi;
+    let api = SummaryApi::for_provider(provider_cli, openai_protocol);
+    let provider = ProviderWithMaps { provider, model_maps };
+
+    // 摘要请求同样经过服务商的模型映射
+    let model = default_model(provider_cli);
+    let body = summary_request_body(api, model, &excerpt);
+    let mapping = if api == SummaryApi::Gemini {
+        apply_url_model_mapping(&provider, &api.path(provider_cli, model), &body, &provider.model_maps)
+    } else {
+        apply_body_model_mapping(&provider, &body, &api.path(provider_cli, model))
     };
+    let model_id = mapping.target_model.or(mapping.source_model);
+    let body = mapping.body;
+    let path = mapping.path;
+    let provider = provider.provider;
+
+    let (request, logged_url) = provider_request(
+        db.inner(),
+        &http_clients,
+        &provider,
+        provider_cli,
+        reqwest::Method::POST,
+        &path,
+        model_id.as_deref(),
+    )
+    .await?;
 
-    // For Codex, we need special handling since sessions are not in project folders
-    if cli_type == "codex" {
-        return get_codex_projects(projects_dir, page, page_size);
+    let start = std::time::Instant::now();
+    let response = request
+        .body(body.clone())
+        .send()
+        .await
+        .map_err(|e| format!("Summary request failed: {}", e))?;
+    let status = response.status();
+    let response_bytes = response.bytes().await.map_err(|e| e.to_string())?;
+    let elapsed_ms = start.elapsed().as_millis() as i64;
+
+    let mut usage = TokenUsage::default();
+    parse_token_usage(&response_bytes, api.usage_format(), &mut usage);
+    let response_text = String::from_utf8_lossy(&response_bytes).to_string();
+
+    let info = crate::services::stats::RequestLogInfo {
+        forward_url: Some(logged_url),
+        forward_proxy: provider.proxy_url.as_deref().map(crate::services::masking::mask_url_credentials),
+        forward_body: Some(String::from_utf8_lossy(&body).to_string()),
+        provider_body: Some(response_text.clone()),
+        response_body: Some(response_text.clone()),
+        cache_creation_tokens: usage.cache_creation_tokens,
+        cache_read_tokens: usage.cache_read_tokens,
+        ..Default::default()
+    };
+    let _ = crate::services::stats::record_request_log(
+        &log_db.0,
+        &mask_patterns.0,
+        provider_cli.as_str(),
+        &provider.name,
+        model_id.as_deref(),
+        Some(status.as_u16()),
+        elapsed_ms,
+        usage.input_tokens,
+        usage.output_tokens,
+        "POST",
+        &format!("{} {}", SUMMARY_PATH_PREFIX, path),
+        Some(info),
+    )
+    .await;
+    let _ = crate::services::stats::record_request(
+        db.inner(),
+        &log_db.0,
+        &provider.name,
+        provider_cli.as_str(),
+        model_id.as_deref(),
+        status.is_success(),
+        &usage,
+    )
+    .await;
+
+    if !status.is_success() {
+        return Err(format!("Summary request failed with status {}", status.as_u16()));
     }
+    let summary = extract_summary(api, &response_bytes)
+        .ok_or_else(|| "Provider returned no summary text".to_string())?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO session_summaries (cli_type, session_id, summary, provider_name, created_at)
+        VALUES (?, ?, ?, ?, ?)
+        ON CONFLICT(cli_type, session_id) DO UPDATE SET
+            summary = excluded.summary,
+            provider_name = excluded.provider_name,
+            created_at = excluded.created_at
+        "#,
+    )
+    .bind(&cli_type)
+    .bind(&session_id)
+    .bind(&summary)
+    .bind(&provider.name)
+    .bind(chrono::Utc::now().timestamp())
+    .execute(db.inner())
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(summary)
+}
+
+// Plain (uncompressed) session file of Claude Code / Gemini sessions
+fn session_file_path(cli_type: &str, project_name: &str, session_id: &str) -> std::path::PathBuf {
+    let base_dir = get_cli_base_dir(cli_type);
+    match cli_type {
+        "gemini" => base_dir.join("tmp").join(project_name).join("chats").join(format!("{}.json", session_id)),
+        _ => base_dir.join("projects").join(project_name).join(format!("{}.jsonl", session_id)),
+    }
+}
+
+// Live session watching
+const SESSION_WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+const SESSION_MESSAGE_ADDED_EVENT: &str = "session_message_added";
+
+/// Read position of a watched session file.
+///
+/// JSONL 文件按字节偏移增量读取；Gemini 的 JSON 是整体重写的，只能重新解析后按消息数截取
+struct SessionWatchCursor {
+    offset: u64,
+    seen: usize,
+}
+
+// Offset just past the last complete line, so a half-written line is read again next time
+fn complete_lines_len(buf: &[u8]) -> usize {
+    buf.iter().rposition(|&b| b == b'\n').map(|i| i + 1).unwrap_or(0)
+}
+
+fn read_appended_session_messages(
+    cli_type: &str,
+    path: &std::path::Path,
+    cursor: &mut SessionWatchCursor,
+) -> Result<Vec<SessionMessage>> {
+    use std::io::{Read, Seek, SeekFrom};
 
-    // For Gemini, check if sessions are in hash directories with chats subfolder
     if cli_type == "gemini" {
-        return get_gemini_projects(projects_dir, page, page_size);
+        let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read session file: {}", e))?;
+        let messages = parse_gemini_json(&content)?;
+        // 文件被重写成更短的内容时从头开始
+        let start = if messages.len() < cursor.seen { 0 } else { cursor.seen };
+        cursor.seen = messages.len();
+        return Ok(messages.into_iter().skip(start).collect());
     }
 
-    let mut projects = Vec::new();
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open session file: {}", e))?;
+    let len = file.metadata().map_err(|e| e.to_string())?.len();
+    if len < cursor.offset {
+        // 文件被截断或替换
+        cursor.offset = 0;
+    }
+    file.seek(SeekFrom::Start(cursor.offset)).map_err(|e| e.to_string())?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).map_err(|e| format!("Failed to read session file: {}", e))?;
 
-    if projects_dir.exists() {
-        if let Ok(entries) = std::fs::read_dir(&projects_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_dir() {
-                    let name = path.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("")
-                        .to_string();
+    let complete = complete_lines_len(&buf);
+    cursor.offset += complete as u64;
+    let content = String::from_utf8_lossy(&buf[..complete]);
+    match cli_type {
+        "codex" => parse_codex_jsonl(&content),
+        _ => parse_claude_jsonl(&content),
+    }
+}
 
-                    if name.is_empty() || name.starts_with('.') {
-                        continue;
-                    }
+#[tauri::command]
+pub async fn watch_session_file(
+    app: tauri::AppHandle,
+    watchers: State<'_, crate::SessionWatchers>,
+    cli_type: String,
+    project_name: String,
+    session_id: String,
+) -> Result<()> {
+    use tauri::Emitter;
 
-                    // Count sessions and calculate size
-                    let mut session_count = 0i64;
-                    let mut total_size = 0i64;
-                    let mut last_modified = 0f64;
+    let mut watchers = watchers.0.lock().map_err(|e| e.to_string())?;
+    if watchers.get(&session_id).is_some_and(|handle| !handle.is_finished()) {
+        return Ok(());
+    }
 
-                    if let Ok(sessions) = std::fs::read_dir(&path) {
-                        for session in sessions.flatten() {
-                            let session_path = session.path();
-                            if session_path.is_file() {
-                                session_count += 1;
-                                if let Ok(meta) = session_path.metadata() {
-                                    total_size += meta.len() as i64;
-                                    if let Ok(mtime) = meta.modified() {
-                                        let secs = mtime.duration_since(std::time::UNIX_EPOCH)
-                                            .map(|d| d.as_secs_f64())
-                                            .unwrap_or(0.0);
-                                        if secs > last_modified {
-                                            last_modified = secs;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+    let path = match cli_type.as_str() {
+        "codex" => find_codex_session_file(&session_id)?,
+        _ => session_file_path(&cli_type, &project_name, &session_id),
+    };
+    if is_gz_session(&path) || !path.exists() {
+        return Err(format!("Session file not found: {}", session_id));
+    }
 
-                    let display_name = if cli_type == "claude_code" {
-                        // Decode path from project name
-                        name.replace("-", "/").replace("_", ":")
-                    } else {
-                        name.clone()
-                    };
+    // 从当前内容之后开始，已有消息由 get_session_messages 加载
+    let mut cursor = SessionWatchCursor { offset: 0, seen: 0 };
+    if cli_type == "gemini" {
+        read_appended_session_messages(&cli_type, &path, &mut cursor)?;
+    } else {
+        let content = std::fs::read(&path).map_err(|e| format!("Failed to read session file: {}", e))?;
+        cursor.offset = complete_lines_len(&content) as u64;
+    }
+    let mut last_modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+    let watched_id = session_id.clone();
+    let handle = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SESSION_WATCH_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            // 文件暂时不可读（被重命名/替换中）时下一轮再试
+            let Ok(modified) = tokio::fs::metadata(&path).await.and_then(|m| m.modified()) else {
+                continue;
+            };
+            if last_modified == Some(modified) {
+                continue;
+            }
+            last_modified = Some(modified);
 
-                    projects.push(ProjectInfo {
-                        name: name.clone(),
-                        display_name,
-                        full_path: path.to_string_lossy().to_string(),
-                        session_count,
-                        total_size,
-                        last_modified,
-                    });
+            match read_appended_session_messages(&cli_type, &path, &mut cursor) {
+                Ok(messages) if !messages.is_empty() => {
+                    let event = SessionMessagesAdded {
+                        session_id: watched_id.clone(),
+                        messages,
+                    };
+                    if let Err(e) = app.emit(SESSION_MESSAGE_ADDED_EVENT, event) {
+                        tracing::warn!(session_id = %watched_id, error = %e, "Failed to emit session messages");
+                    }
                 }
+                Ok(_) => {}
+                Err(e) => tracing::warn!(session_id = %watched_id, error = %e, "Failed to read watched session file"),
             }
         }
-    }
+    });
+    watchers.insert(session_id, handle);
 
-    // Sort by last_modified descending
-    projects.sort_by(|a, b| b.last_modified.partial_cmp(&a.last_modified).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(())
+}
 
-    let total = projects.len() as i64;
-    let start = ((page - 1) * page_size) as usize;
-    let items: Vec<_> = projects.into_iter().skip(start).take(page_size as usize).collect();
+#[tauri::command]
+pub async fn stop_watching_session(
+    watchers: State<'_, crate::SessionWatchers>,
+    session_id: String,
+) -> Result<()> {
+    let mut watchers = watchers.0.lock().map_err(|e| e.to_string())?;
+    if let Some(handle) = watchers.remove(&session_id) {
+        handle.abort();
+    }
+    Ok(())
+}
 
-    Ok(PaginatedProjects {
-        items,
-        total,
-        page,
-        page_size,
-    })
+// Session full-text search / compression
+const SEARCH_EXCERPT_CHARS: usize = 100;
+
+struct SessionFileEntry {
+    cli_type: &'static str,
+    // Codex 的项目名 (cwd) 需要读文件才能得到，命中后再解析
+    project_name: Option<String>,
+    session_id: String,
+    path: std::path::PathBuf,
 }
 
-#[tauri::command]
-pub async fn get_project_sessions(
-    cli_type: String,
-    project_name: String,
-    page: Option<i64>,
-    page_size: Option<i64>,
-) -> Result<PaginatedSessions> {
-    let page = page.unwrap_or(1).max(1);
-    let page_size = page_size.unwrap_or(20).clamp(1, 100);
+fn collect_session_files(cli_type: &'static str) -> Vec<SessionFileEntry> {
+    use walkdir::WalkDir;
+
+    let base_dir = get_cli_base_dir(cli_type);
+    let mut targets = Vec::new();
 
-    // Special handling for Codex
     if cli_type == "codex" {
-        return get_codex_sessions(&project_name, page, page_size);
+        for entry in WalkDir::new(base_dir.join("sessions"))
+            .follow_links(false)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let filename = session_file_name(path);
+            if path.is_file() && filename.starts_with("rollout-") && filename.ends_with(".jsonl") {
+                targets.push(SessionFileEntry {
+                    cli_type,
+                    project_name: None,
+                    session_id: session_stem(path),
+                    path: path.to_path_buf(),
+                });
+            }
+        }
+        return targets;
     }
 
-    // Special handling for Gemini
-    if cli_type == "gemini" {
-        return get_gemini_sessions(&project_name, page, page_size);
+    let projects_dir = match cli_type {
+        "gemini" => base_dir.join("tmp"),
+        _ => base_dir.join("projects"),
+    };
+    let Ok(projects) = std::fs::read_dir(&projects_dir) else {
+        return targets;
+    };
+
+    for project in projects.flatten() {
+        let project_path = project.path();
+        if !project_path.is_dir() {
+            continue;
+        }
+        let project_name = project.file_name().to_string_lossy().to_string();
+        let session_dir = match cli_type {
+            "gemini" => project_path.join("chats"),
+            _ => project_path,
+        };
+        let Ok(entries) = std::fs::read_dir(&session_dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let filename = session_file_name(&path);
+            let is_session = match cli_type {
+                "gemini" => filename.starts_with("session-") && filename.ends_with(".json"),
+                _ => filename.ends_with(".jsonl"),
+            };
+            if path.is_file() && is_session {
+                targets.push(SessionFileEntry {
+                    cli_type,
+                    project_name: Some(project_name.clone()),
+                    session_id: session_stem(&path),
+                    path,
+                });
+            }
+        }
     }
 
-    // Claude Code default handling
-    let base_dir = get_cli_base_dir(&cli_type);
-    let project_dir = base_dir.join("projects").join(&project_name);
+    targets
+}
+
+/// Case-insensitive search of `needle` (already lowercased) in `text`,
+/// returning ~100 chars around the first match
+fn search_excerpt(text: &str, needle: &str) -> Option<String> {
+    // 小写化可能改变字节长度，记录每个小写字节对应的原始字符下标
+    let mut lower = String::with_capacity(text.len());
+    let mut char_of_byte = Vec::with_capacity(text.len());
+    for (idx, ch) in text.chars().enumerate() {
+        for lc in ch.to_lowercase() {
+            lower.push(lc);
+            char_of_byte.extend(std::iter::repeat_n(idx, lc.len_utf8()));
+        }
+    }
+
+    let pos = lower.find(needle)?;
+    let match_char = char_of_byte[pos];
+    let needle_chars = needle.chars().count();
+    let start = match_char.saturating_sub(SEARCH_EXCERPT_CHARS.saturating_sub(needle_chars) / 2);
+
+    let excerpt: String = text
+        .chars()
+        .skip(start)
+        .take(SEARCH_EXCERPT_CHARS)
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect();
+    Some(excerpt)
+}
+
+fn search_session_file(target: &SessionFileEntry, needle: &str) -> Option<SessionSearchHit> {
+    let content = read_session_file(&target.path).ok()?;
+    let messages = match target.cli_type {
+        "codex" => parse_codex_jsonl(&content),
+        "gemini" => parse_gemini_json(&content),
+        _ => parse_claude_jsonl(&content),
+    }
+    .ok()?;
 
-    let mut sessions = Vec::new();
+    let matching_excerpt = messages
+        .iter()
+        .find_map(|msg| search_excerpt(&msg.content, needle))?;
 
-    if project_dir.exists() {
-        if let Ok(entries) = std::fs::read_dir(&project_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    let session_id = path.file_stem()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("")
-                        .to_string();
+    let project_name = match &target.project_name {
+        Some(name) => name.clone(),
+        None => extract_codex_cwd(&target.path).unwrap_or_default(),
+    };
+    let mtime = target.path.metadata().ok()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0);
+
+    Some(SessionSearchHit {
+        cli_type: target.cli_type.to_string(),
+        project_name,
+        session_id: target.session_id.clone(),
+        matching_excerpt,
+        mtime,
+    })
+}
 
-                    if session_id.is_empty() {
-                        continue;
-                    }
+#[tauri::command]
+pub async fn get_sessions_search(
+    query: String,
+    cli_type: Option<String>,
+    page: Option<i64>,
+    page_size: Option<i64>,
+) -> Result<PaginatedSearchResults> {
+    let page = page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(20).clamp(1, 100);
 
-                    let mut size = 0i64;
-                    let mut mtime = 0f64;
-                    let mut first_message = String::new();
+    let needle = query.trim().to_lowercase();
+    if needle.is_empty() {
+        return Err("Search query cannot be empty".to_string());
+    }
 
-                    if let Ok(meta) = path.metadata() {
-                        size = meta.len() as i64;
-                        if let Ok(mt) = meta.modified() {
-                            mtime = mt.duration_since(std::time::UNIX_EPOCH)
-                                .map(|d| d.as_secs_f64())
-                                .unwrap_or(0.0);
-                        }
-                    }
+    let cli_types: Vec<&'static str> = match cli_type.as_deref() {
+        None => vec!["claude_code", "codex", "gemini"],
+        Some("claude_code") => vec!["claude_code"],
+        Some("codex") => vec!["codex"],
+        Some("gemini") => vec!["gemini"],
+        Some(other) => return Err(format!("Invalid cli_type: {}", other)),
+    };
 
-                    // Try to read first message from JSON
-                    if let Ok(content) = std::fs::read_to_string(&path) {
-                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-                            // Claude Code format
-                            if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
-                                for msg in messages {
-                                    if msg.get("type").and_then(|t| t.as_str()) == Some("human") {
-                                        if let Some(content) = msg.get("content") {
-                                            if let Some(arr) = content.as_array() {
-                                                for item in arr {
-                                                    if let Some(text) = item.get("text").and_then(|t| t.as_str()) {
-                                                        first_message = text.chars().take(200).collect();
-                                                        break;
-                                                    }
-                                                }
-                                            } else if let Some(text) = content.as_str() {
-                                                first_message = text.chars().take(200).collect();
-                                            }
-                                        }
-                                        break;
-                                    }
-                                    // Gemini format
-                                    if msg.get("type").and_then(|t| t.as_str()) == Some("user") {
-                                        if let Some(text) = msg.get("content").and_then(|c| c.as_str()) {
-                                            first_message = text.chars().take(200).collect();
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+    let mut hits = tokio::task::spawn_blocking(move || {
+        use rayon::prelude::*;
 
-                    sessions.push(SessionInfo {
-                        session_id,
-                        size,
-                        mtime,
-                        first_message,
-                        git_branch: String::new(),
-                        summary: String::new(),
-                    });
-                }
-            }
-        }
-    }
+        let targets: Vec<SessionFileEntry> = cli_types
+            .into_par_iter()
+            .flat_map_iter(collect_session_files)
+            .collect();
+
+        targets
+            .par_iter()
+            .filter_map(|target| search_session_file(target, &needle))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
 
     // Sort by mtime descending
-    sessions.sort_by(|a, b| b.mtime.partial_cmp(&a.mtime).unwrap_or(std::cmp::Ordering::Equal));
+    hits.sort_by(|a, b| b.mtime.partial_cmp(&a.mtime).unwrap_or(std::cmp::Ordering::Equal));
 
-    let total = sessions.len() as i64;
+    let total = hits.len() as i64;
     let start = ((page - 1) * page_size) as usize;
-    let items: Vec<_> = sessions.into_iter().skip(start).take(page_size as usize).collect();
+    let items: Vec<_> = hits.into_iter().skip(start).take(page_size as usize).collect();
 
-    Ok(PaginatedSessions {
+    Ok(PaginatedSearchResults {
         items,
         total,
         page,
@@ -2675,100 +7904,108 @@ pub async fn get_project_sessions(
     })
 }
 
-#[tauri::command]
-pub async fn get_session_messages(
-    cli_type: String,
-    project_name: String,
-    session_id: String,
-) -> Result<Vec<SessionMessage>> {
-    // Special handling for Codex JSONL format
-    if cli_type == "codex" {
-        return get_codex_messages(&session_id);
+/// Gzip one session file in place (`x.jsonl` -> `x.jsonl.gz`), keeping its mtime so
+/// session lists stay ordered. Returns bytes saved.
+fn compress_session_file(path: &std::path::Path) -> std::io::Result<i64> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let meta = path.metadata()?;
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(SESSION_GZ_SUFFIX);
+    let gz_path = std::path::PathBuf::from(gz_path);
+
+    let result = (|| {
+        let mut input = std::fs::File::open(path)?;
+        let output = std::fs::File::create(&gz_path)?;
+        let mut encoder = GzEncoder::new(output, Compression::default());
+        std::io::copy(&mut input, &mut encoder)?;
+        let output = encoder.finish()?;
+        output.sync_all()?;
+        output.set_modified(meta.modified()?)?;
+        output.metadata()
+    })();
+
+    match result {
+        Ok(gz_meta) => {
+            std::fs::remove_file(path)?;
+            Ok(meta.len() as i64 - gz_meta.len() as i64)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&gz_path);
+            Err(e)
+        }
     }
-    
-    let base_dir = get_cli_base_dir(&cli_type);
-    let session_file = match cli_type.as_str() {
-        "gemini" => base_dir.join("tmp").join(&project_name).join("chats").join(format!("{}.json", session_id)),
-        _ => base_dir.join("projects").join(&project_name).join(format!("{}.jsonl", session_id)),
-    };
-
-    let content = std::fs::read_to_string(&session_file)
-        .map_err(|e| format!("Failed to read session file: {}", e))?;
+}
 
-    // For Claude Code JSONL format
-    if cli_type == "claude_code" {
-        return parse_claude_jsonl(&content);
+/// Sessions last modified before this time are compressed
+fn session_compress_cutoff(now: std::time::SystemTime, older_than_days: i64) -> Result<std::time::SystemTime> {
+    // CLI 可能仍在追加当前会话，至少保留最近一天不压缩
+    if older_than_days < 1 {
+        return Err("older_than_days must be at least 1".to_string());
     }
-    
-    // For Gemini JSON format
-    let json: serde_json::Value = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse session JSON: {}", e))?;
-
-    let mut messages = Vec::new();
-
-    // Try to parse messages in different formats
-    if let Some(msgs) = json.get("messages").and_then(|m| m.as_array()) {
-        // Standard format with messages array
-        for msg in msgs {
-            let msg_type = msg.get("type").and_then(|t| t.as_str()).unwrap_or("");
-            let role = match msg_type {
-                "human" | "user" => "user",
-                "assistant" | "ai" | "gemini" => "assistant",  // Add "gemini" type
-                _ => continue,
-            };
-
-            let content = if let Some(content_val) = msg.get("content") {
-                if let Some(arr) = content_val.as_array() {
-                    arr.iter()
-                        .filter_map(|item| item.get("text").and_then(|t| t.as_str()))
-                        .collect::<Vec<_>>()
-                        .join("\n")
-                } else if let Some(text) = content_val.as_str() {
-                    text.to_string()
-                } else {
-                    continue;
-                }
-            } else {
-                continue;
-            };
-
-            let timestamp = msg.get("timestamp").and_then(|t| t.as_str()).map(|s| {
-                chrono::DateTime::parse_from_rfc3339(s)
-                    .ok()
-                    .map(|dt| dt.timestamp())
-            }).flatten();
+    let out_of_range = || format!("older_than_days is out of range: {}", older_than_days);
+    let secs = (older_than_days as u64).checked_mul(86400).ok_or_else(out_of_range)?;
+    now.checked_sub(std::time::Duration::from_secs(secs)).ok_or_else(out_of_range)
+}
 
-            messages.push(SessionMessage {
-                role: role.to_string(),
-                content,
-                timestamp,
-            });
-        }
-    } else if let Some(conversation) = json.as_object() {
-        // Try to parse as flat object with role-based keys
-        for (key, value) in conversation {
-            if key == "id" || key == "title" || key == "created_at" || key == "updated_at" {
+#[tauri::command]
+pub async fn compress_sessions(
+    log_db: State<'_, crate::LogDb>,
+    cli_type: String,
+    older_than_days: i64,
+) -> Result<CompressionStats> {
+    let cli_type: &'static str = match cli_type.as_str() {
+        "claude_code" => "claude_code",
+        "codex" => "codex",
+        "gemini" => "gemini",
+        other => return Err(format!("Invalid cli_type: {}", other)),
+    };
+    let cutoff = session_compress_cutoff(std::time::SystemTime::now(), older_than_days)?;
+
+    let stats = tokio::task::spawn_blocking(move || {
+        let mut stats = CompressionStats {
+            files_compressed: 0,
+            bytes_saved: 0,
+        };
+        for entry in collect_session_files(cli_type) {
+            if is_gz_session(&entry.path) {
                 continue;
             }
-            let role = if key.starts_with("user") || key.starts_with("human") {
-                "user"
-            } else if key.starts_with("assistant") || key.starts_with("ai") {
-                "assistant"
-            } else {
+            let is_old = entry.path.metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|mtime| mtime < cutoff);
+            if !is_old {
                 continue;
-            };
-
-            if let Some(text) = value.as_str() {
-                messages.push(SessionMessage {
-                    role: role.to_string(),
-                    content: text.to_string(),
-                    timestamp: None,
-                });
+            }
+            match compress_session_file(&entry.path) {
+                Ok(saved) => {
+                    stats.files_compressed += 1;
+                    stats.bytes_saved += saved;
+                }
+                Err(e) => {
+                    tracing::warn!(path = %entry.path.display(), error = %e, "Failed to compress session file");
+                }
             }
         }
-    }
+        stats
+    })
+    .await
+    .map_err(|e| e.to_string())?;
 
-    Ok(messages)
+    let _ = crate::services::stats::record_system_log(
+        &log_db.0,
+        "info",
+        "sessions_compressed",
+        &format!(
+            "Compressed {} {} session files, saved {} bytes",
+            stats.files_compressed, cli_type, stats.bytes_saved
+        ),
+        None,
+        serde_json::to_string(&stats).ok().as_deref(),
+    ).await;
+
+    Ok(stats)
 }
 
 #[tauri::command]
@@ -2778,11 +8015,11 @@ pub async fn delete_session(
     session_id: String,
 ) -> Result<()> {
     let base_dir = get_cli_base_dir(&cli_type);
-    let session_file = match cli_type.as_str() {
+    let session_file = resolve_session_file(match cli_type.as_str() {
         "codex" => base_dir.join("sessions").join(format!("{}.jsonl", session_id)),
         "gemini" => base_dir.join("tmp").join(&project_name).join("chats").join(format!("{}.json", session_id)),
         _ => base_dir.join("projects").join(&project_name).join(format!("{}.jsonl", session_id)),
-    };
+    });
 
     std::fs::remove_file(&session_file)
         .map_err(|e| format!("Failed to delete session: {}", e))?;
@@ -2810,9 +8047,7 @@ pub async fn delete_project(
             {
                 let path = entry.path();
                 if path.is_file() {
-                    let filename = path.file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("");
+                    let filename = session_file_name(path);
                     if filename.starts_with("rollout-") && filename.ends_with(".jsonl") {
                         if let Some(cwd) = extract_codex_cwd(path) {
                             if cwd == project_name {
@@ -2838,12 +8073,29 @@ pub async fn delete_project(
     Ok(())
 }
 
-/// 退出应用程序（导入后需要手动重启）
-async fn exit_application() -> Result<()> {
-    tokio::spawn(async {
+/// 写入导入的数据库并退出应用程序（导入后需要手动重启）
+///
+/// 数据先写入临时文件，等网关停止、连接池关闭后再替换数据库文件，避免正在使用的数据库被覆盖。
+async fn exit_application(app: tauri::AppHandle, data: &[u8]) -> Result<()> {
+    let db_path = get_data_dir().join("ccg_gateway.db");
+    let import_path = db_path.with_extension("db.import");
+    std::fs::write(&import_path, data)
+        .map_err(|e| format!("Failed to write database: {}", e))?;
+
+    tokio::spawn(async move {
         // 延迟 3 秒，等待响应返回前端并给用户时间看提示
         tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
-        std::process::exit(0);
+        crate::stop_gateway(&app, "import").await;
+        if let Err(e) = std::fs::rename(&import_path, &db_path) {
+            tracing::error!("Failed to replace database with {}: {}", import_path.display(), e);
+        }
+        // 旧库的 WAL 不能应用到导入的数据库上
+        for suffix in ["-wal", "-shm"] {
+            let mut path = db_path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+        app.exit(0);
     });
 
     Ok(())
@@ -2852,11 +8104,15 @@ async fn exit_application() -> Result<()> {
 // Backup commands
 #[tauri::command]
 pub async fn get_webdav_settings(db: State<'_, SqlitePool>) -> Result<WebdavSettings> {
+    load_webdav_settings(db.inner()).await
+}
+
+pub async fn load_webdav_settings(db: &SqlitePool) -> Result<WebdavSettings> {
     // Try to get existing settings
     let settings = sqlx::query_as::<_, WebdavSettings>(
         "SELECT url, username, password FROM webdav_settings WHERE id = 1"
     )
-    .fetch_optional(db.inner())
+    .fetch_optional(db)
     .await
     .map_err(|e| e.to_string())?;
 
@@ -2869,7 +8125,7 @@ pub async fn get_webdav_settings(db: State<'_, SqlitePool>) -> Result<WebdavSett
                 "INSERT INTO webdav_settings (id, url, username, password, updated_at) VALUES (1, '', '', '', ?)"
             )
             .bind(now)
-            .execute(db.inner())
+            .execute(db)
             .await
             .map_err(|e| e.to_string())?;
 
@@ -2887,8 +8143,12 @@ pub async fn update_webdav_settings(
     db: State<'_, SqlitePool>,
     input: WebdavSettingsUpdate,
 ) -> Result<WebdavSettings> {
+    save_webdav_settings(db.inner(), input).await
+}
+
+pub async fn save_webdav_settings(db: &SqlitePool, input: WebdavSettingsUpdate) -> Result<WebdavSettings> {
     let now = chrono::Utc::now().timestamp();
-    let current = get_webdav_settings(db.clone()).await?;
+    let current = load_webdav_settings(db).await?;
 
     sqlx::query(
         "UPDATE webdav_settings SET url = ?, username = ?, password = ?, updated_at = ? WHERE id = 1"
@@ -2897,25 +8157,27 @@ pub async fn update_webdav_settings(
     .bind(input.username.unwrap_or(current.username))
     .bind(input.password.unwrap_or(current.password))
     .bind(now)
-    .execute(db.inner())
+    .execute(db)
     .await
     .map_err(|e| e.to_string())?;
 
-    get_webdav_settings(db).await
+    load_webdav_settings(db).await
 }
 
 #[tauri::command]
 pub async fn test_webdav_connection(
+    http_client: State<'_, crate::HttpClient>,
     url: String,
     username: String,
     password: String,
 ) -> Result<bool> {
-    use reqwest::Client;
+    check_webdav_connection(&http_client, &url, &username, &password).await
+}
 
-    let client = Client::new();
+pub async fn check_webdav_connection(client: &reqwest::Client, url: &str, username: &str, password: &str) -> Result<bool> {
     let response = client
-        .request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), &url)
-        .basic_auth(&username, Some(&password))
+        .request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), url)
+        .basic_auth(username, Some(password))
         .header("Depth", "0")
         .send()
         .await
@@ -2937,24 +8199,24 @@ pub async fn export_to_local() -> Result<Vec<u8>> {
 }
 
 #[tauri::command]
-pub async fn import_from_local(data: Vec<u8>) -> Result<()> {
-    let db_path = get_data_dir().join("ccg_gateway.db");
-
-    // Write the database file
-    std::fs::write(&db_path, &data)
-        .map_err(|e| format!("Failed to write database: {}", e))?;
-
+pub async fn import_from_local(app: tauri::AppHandle, data: Vec<u8>) -> Result<()> {
     // 退出应用，用户需手动重启
-    exit_application().await?;
+    exit_application(app, &data).await?;
 
     Ok(())
 }
 
 #[tauri::command]
-pub async fn export_to_webdav(db: State<'_, SqlitePool>) -> Result<String> {
-    use reqwest::Client;
+pub async fn export_to_webdav(
+    db: State<'_, SqlitePool>,
+    http_client: State<'_, crate::HttpClient>,
+) -> Result<String> {
+    upload_webdav_backup(db.inner(), &http_client).await
+}
 
-    let settings = get_webdav_settings(db.clone()).await?;
+/// Upload the database to `<url>/ccg-gateway-backup/`; returns the backup file name
+pub async fn upload_webdav_backup(db: &SqlitePool, client: &reqwest::Client) -> Result<String> {
+    let settings = load_webdav_settings(db).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
@@ -2971,7 +8233,6 @@ pub async fn export_to_webdav(db: State<'_, SqlitePool>) -> Result<String> {
     );
 
     // Ensure remote directory exists
-    let client = Client::new();
     let remote_dir = format!("{}/ccg-gateway-backup", settings.url.trim_end_matches('/'));
 
     // Try to create directory (ignore error if exists)
@@ -2999,15 +8260,19 @@ pub async fn export_to_webdav(db: State<'_, SqlitePool>) -> Result<String> {
 }
 
 #[tauri::command]
-pub async fn list_webdav_backups(db: State<'_, SqlitePool>) -> Result<Vec<WebdavBackup>> {
-    use reqwest::Client;
+pub async fn list_webdav_backups(
+    db: State<'_, SqlitePool>,
+    http_client: State<'_, crate::HttpClient>,
+) -> Result<Vec<WebdavBackup>> {
+    load_webdav_backups(db.inner(), &http_client).await
+}
 
-    let settings = get_webdav_settings(db).await?;
+pub async fn load_webdav_backups(db: &SqlitePool, client: &reqwest::Client) -> Result<Vec<WebdavBackup>> {
+    let settings = load_webdav_settings(db).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
 
-    let client = Client::new();
     let remote_dir = format!("{}/ccg-gateway-backup", settings.url.trim_end_matches('/'));
 
     let response = client
@@ -3107,17 +8372,18 @@ pub async fn list_webdav_backups(db: State<'_, SqlitePool>) -> Result<Vec<Webdav
 
 #[tauri::command]
 pub async fn import_from_webdav(
+    app: tauri::AppHandle,
     db: State<'_, SqlitePool>,
+    http_client: State<'_, crate::HttpClient>,
     filename: String,
 ) -> Result<()> {
-    use reqwest::Client;
-
-    let settings = get_webdav_settings(db).await?;
+    check_backup_filename(&filename)?;
+    let settings = load_webdav_settings(db.inner()).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
 
-    let client = Client::new();
+    let client = &**http_client;
     let remote_file = format!(
         "{}/ccg-gateway-backup/{}",
         settings.url.trim_end_matches('/'),
@@ -3137,14 +8403,8 @@ pub async fn import_from_webdav(
 
     let content = response.bytes().await.map_err(|e| e.to_string())?;
 
-    // Write to database file
-    let db_path = get_data_dir().join("ccg_gateway.db");
-
-    std::fs::write(&db_path, &content)
-        .map_err(|e| format!("Failed to write database: {}", e))?;
-
     // 退出应用，用户需手动重启
-    exit_application().await?;
+    exit_application(app, &content).await?;
 
     Ok(())
 }
@@ -3152,16 +8412,19 @@ pub async fn import_from_webdav(
 #[tauri::command]
 pub async fn delete_webdav_backup(
     db: State<'_, SqlitePool>,
+    http_client: State<'_, crate::HttpClient>,
     filename: String,
 ) -> Result<()> {
-    use reqwest::Client;
+    remove_webdav_backup(db.inner(), &http_client, &filename).await
+}
 
-    let settings = get_webdav_settings(db).await?;
+pub async fn remove_webdav_backup(db: &SqlitePool, client: &reqwest::Client, filename: &str) -> Result<()> {
+    check_backup_filename(filename)?;
+    let settings = load_webdav_settings(db).await?;
     if settings.url.is_empty() {
         return Err("WebDAV URL not configured".to_string());
     }
 
-    let client = Client::new();
     let remote_file = format!(
         "{}/ccg-gateway-backup/{}",
         settings.url.trim_end_matches('/'),
@@ -3181,3 +8444,215 @@ pub async fn delete_webdav_backup(
 
     Ok(())
 }
+
+/// Backup names come from list_webdav_backups; anything else could address another remote path
+fn check_backup_filename(filename: &str) -> Result<()> {
+    let valid = filename.starts_with("ccg_gateway_")
+        && filename.ends_with(".db")
+        && !filename.contains(['/', '\\', '?', '#', '%']);
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid backup file name: '{}'", filename))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_urls_written_by_the_gateway_are_retargeted() {
+        let written = std::collections::HashMap::from([("claude_code".to_string(), "http://127.0.0.1:7788/claude".to_string())]);
+        assert!(is_written_gateway_url(&written, "claude_code", "http://127.0.0.1:7788/claude/"));
+        // 用户自己在本机搭的代理
+        assert!(!is_written_gateway_url(&written, "claude_code", "http://127.0.0.1:8080"));
+        assert!(!is_written_gateway_url(&written, "gemini", "http://127.0.0.1:7788"));
+    }
+
+    #[test]
+    fn session_compress_cutoff_rejects_out_of_range_days() {
+        let now = std::time::SystemTime::now();
+        assert_eq!(session_compress_cutoff(now, 2).unwrap(), now - std::time::Duration::from_secs(2 * 86400));
+        assert!(session_compress_cutoff(now, 0).is_err());
+        assert!(session_compress_cutoff(now, i64::MAX).unwrap_err().contains("out of range"));
+    }
+
+    /// Claude Code's project directory name for a path: every non-alphanumeric character becomes `-`
+    fn encode_claude_project_path(path: &str) -> String {
+        path.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect()
+    }
+
+    #[test]
+    fn existing_directories_with_separators_in_their_names_are_restored() {
+        let root = std::env::temp_dir().join(format!("ccg-decode-{}", uuid::Uuid::new_v4().simple()));
+        let dirs = ["my-app/src", "my project/v1.2", "snake_case_dir", ".config/app"];
+        for dir in dirs {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for dir in dirs {
+            let path = root.join(dir).display().to_string();
+            assert_eq!(decode_claude_project_path(&encode_claude_project_path(&path)), path);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn unresolvable_paths_fall_back_to_one_component_per_part() {
+        assert_eq!(decode_claude_project_path("-nonexistent-ccg-my-app"), "/nonexistent/ccg/my/app");
+        assert_eq!(decode_claude_project_path("-nonexistent-ccg--config-app"), "/nonexistent/ccg/.config/app");
+        // 无法还原的特殊字符（+、@ 等）只能当作分隔符
+        assert_eq!(
+            decode_claude_project_path(&encode_claude_project_path("/nonexistent/user@host+1")),
+            "/nonexistent/user/host/1"
+        );
+    }
+
+    #[test]
+    fn windows_drive_paths_use_backslashes() {
+        assert_eq!(decode_claude_project_path("C--work-app"), "C:\\work\\app");
+        assert_eq!(decode_claude_project_path("D--Users-me--config"), "D:\\Users\\me\\.config");
+        assert_eq!(decode_claude_project_path("relative-dir"), "relative/dir");
+    }
+
+    #[tokio::test]
+    async fn log_context_is_bounded_by_the_ends_of_the_table() {
+        let dir = std::env::temp_dir().join(format!("ccg-log-context-{}", uuid::Uuid::new_v4().simple()));
+        let (log_db, _) = crate::db::init_db(&dir.join("ccg_logs.db")).await.unwrap();
+        let mask_patterns = crate::services::masking::MaskPatternCache::default();
+        for i in 0..6 {
+            crate::services::stats::record_request_log(
+                &log_db, &mask_patterns, "claude_code", "p", None, Some(200), 1, 0, 0, "POST", &format!("/r{}", i), None,
+            )
+            .await
+            .unwrap();
+        }
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM request_logs ORDER BY id").fetch_all(&log_db).await.unwrap();
+        let context = |index: usize, size: Option<i64>| load_request_log_context(&log_db, ids[index], size);
+        let paths = |items: &[RequestLogItem]| items.iter().map(|i| i.client_path.clone()).collect::<Vec<_>>();
+
+        // 第一条之前、最后一条之后没有记录
+        let first = context(0, Some(3)).await.unwrap();
+        assert!(first.before.is_empty());
+        assert_eq!(paths(&first.after), ["/r1", "/r2", "/r3"]);
+        let last = context(5, Some(3)).await.unwrap();
+        assert_eq!(paths(&last.before), ["/r2", "/r3", "/r4"]);
+        assert!(last.after.is_empty());
+
+        // 靠近两端时只返回实际存在的记录，顺序从旧到新
+        let near_start = context(1, Some(3)).await.unwrap();
+        assert_eq!(paths(&near_start.before), ["/r0"]);
+        assert_eq!(near_start.target.client_path, "/r1");
+        assert_eq!(paths(&context(4, None).await.unwrap().after), ["/r5"]);
+
+        assert!(context(2, Some(-1)).await.unwrap().before.is_empty());
+        assert!(load_request_log_context(&log_db, ids[5] + 1, None).await.is_err());
+        log_db.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleting_a_provider_keeps_the_logs_of_its_namesake_in_another_cli() {
+        let dir = std::env::temp_dir().join(format!("ccg-purge-{}", uuid::Uuid::new_v4().simple()));
+        let (db, _) = crate::db::init_db(&dir.join("ccg_gateway.db")).await.unwrap();
+        let (log_db, _) = crate::db::init_db(&dir.join("ccg_logs.db")).await.unwrap();
+        let ua_patterns = crate::services::proxy::UaPatternCache::default();
+        let mask_patterns = crate::services::masking::MaskPatternCache::default();
+        let mut ids = Vec::new();
+        for cli_type in ["claude_code", "codex"] {
+            let input: ProviderCreate = serde_json::from_value(serde_json::json!({
+                "cli_type": cli_type,
+                "name": "OpenAI",
+                "base_url": "http://127.0.0.1:1",
+                "api_key": "sk-test",
+            }))
+            .unwrap();
+            ids.push(insert_provider(&db, &log_db, &ua_patterns, input).await.unwrap().id);
+            let usage = crate::services::proxy::TokenUsage::default();
+            crate::services::stats::record_request(&db, &log_db, "OpenAI", cli_type, None, true, &usage).await.unwrap();
+            crate::services::stats::record_request_log(
+                &log_db, &mask_patterns, cli_type, "OpenAI", None, Some(200), 1, 0, 0, "POST", "/v1", None,
+            )
+            .await
+            .unwrap();
+        }
+        let names = |table: &'static str| {
+            let log_db = log_db.clone();
+            async move {
+                sqlx::query_as::<_, (String, String)>(&format!("SELECT cli_type, provider_name FROM {} ORDER BY cli_type", table))
+                    .fetch_all(&log_db)
+                    .await
+                    .unwrap()
+            }
+        };
+        let expected = vec![
+            ("claude_code".to_string(), "[deleted]".to_string()),
+            ("codex".to_string(), "OpenAI".to_string()),
+        ];
+
+        let schedules = crate::services::routing::ScheduleCache::default();
+        remove_provider(&db, &log_db, &ua_patterns, &schedules, ids[0], None).await.unwrap();
+        assert_eq!(names("request_logs").await, expected);
+        assert_eq!(names("usage_daily").await, expected);
+        // 系统日志没有 cli_type，同名服务商仍在时不改
+        let purged: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM system_logs WHERE provider_name = '[deleted]'")
+            .fetch_one(&log_db)
+            .await
+            .unwrap();
+        assert_eq!(purged, 0);
+
+        // 最后一个同名服务商删除后，系统日志一并匿名化
+        remove_provider(&db, &log_db, &ua_patterns, &schedules, ids[1], None).await.unwrap();
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM system_logs WHERE provider_name = 'OpenAI'")
+            .fetch_one(&log_db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+        db.close().await;
+        log_db.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deleting_the_first_appended_prompt_keeps_the_second() {
+        let dir = std::env::temp_dir().join(format!("ccg-prompts-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("CLAUDE.md");
+        let first = "# Style\nAnswer in English.";
+        let second = "# Tests\nRun cargo test before committing.";
+
+        sync_prompt_section(&path, first, None, true).unwrap();
+        sync_prompt_section(&path, second, None, true).unwrap();
+        // 重复启用不会追加第二份
+        sync_prompt_section(&path, second, None, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{}\n\n---\n\n{}\n", first, second));
+
+        sync_prompt_section(&path, first, None, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), format!("{}\n", second));
+        sync_prompt_section(&path, second, None, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prompt_sections_are_found_despite_whitespace_edits() {
+        let file = "intro\n\n---\n\n  # Style  \n\nAnswer in English.\n\n---\n\nlast\n";
+        assert_eq!(find_prompt_section(file, "# Style\nAnswer in English."), Some((4, 6)));
+        assert_eq!(remove_prompt_section(file, "# Style\nAnswer in English.").unwrap(), "intro\n\n---\n\nlast\n");
+        assert_eq!(remove_prompt_section(file, "intro").unwrap(), "# Style  \n\nAnswer in English.\n\n---\n\nlast\n");
+        assert!(remove_prompt_section(file, "absent").is_none());
+        assert!(find_prompt_section(file, "\n  \n").is_none());
+    }
+
+    #[test]
+    fn edited_prompts_replace_their_previous_section() {
+        let dir = std::env::temp_dir().join(format!("ccg-prompts-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("AGENTS.md");
+        std::fs::write(&path, "keep me\n").unwrap();
+        sync_prompt_section(&path, "old text", None, true).unwrap();
+        sync_prompt_section(&path, "new text", Some("old text"), true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me\n\n---\n\nnew text\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
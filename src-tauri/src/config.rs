@@ -1,21 +1,38 @@
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Config shared between the gateway and Tauri commands, swapped on reload
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub server: ServerConfig,
+    #[serde(default)]
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ServerConfig {
     #[serde(default = "default_port")]
     pub port: u16,
     #[serde(default = "default_host")]
     pub host: String,
+    /// 端口被占用时自动尝试其它端口
+    #[serde(default = "default_port_auto_select")]
+    pub port_auto_select: bool,
+    /// 依次尝试 port+1 ..= port+range，之后再使用随机端口
+    #[serde(default = "default_port_fallback_range")]
+    pub port_fallback_range: i64,
+    /// 退出时等待进行中的请求（包括流式响应）完成的最长秒数
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     #[serde(default = "default_db_path")]
     pub path: PathBuf,
@@ -23,6 +40,16 @@ pub struct DatabaseConfig {
     pub log_path: PathBuf,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// 日志文件路径，按天滚动（如 ccg.log -> ccg.log.2024-01-01）
+    #[serde(default = "default_log_file")]
+    pub log_file: Option<PathBuf>,
+    /// 配置了 log_file 时是否仍输出到 stdout
+    #[serde(default = "default_log_stdout")]
+    pub log_stdout: bool,
+}
+
 fn default_port() -> u16 {
     std::env::var("GATEWAY_PORT")
         .ok()
@@ -34,6 +61,41 @@ fn default_host() -> String {
     std::env::var("GATEWAY_HOST").unwrap_or_else(|_| "127.0.0.1".into())
 }
 
+fn default_port_auto_select() -> bool {
+    std::env::var("GATEWAY_PORT_AUTO_SELECT")
+        .ok()
+        .map(|v| !matches!(v.to_lowercase().as_str(), "0" | "false" | "no"))
+        .unwrap_or(true)
+}
+
+fn default_port_fallback_range() -> i64 {
+    std::env::var("GATEWAY_PORT_FALLBACK_RANGE")
+        .ok()
+        .and_then(|r| r.parse().ok())
+        .unwrap_or(9)
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    std::env::var("GATEWAY_SHUTDOWN_GRACE_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(10)
+}
+
+fn default_log_file() -> Option<PathBuf> {
+    std::env::var("LOG_FILE")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+fn default_log_stdout() -> bool {
+    std::env::var("LOG_STDOUT")
+        .ok()
+        .map(|v| !matches!(v.to_lowercase().as_str(), "0" | "false" | "no"))
+        .unwrap_or(true)
+}
+
 fn default_db_path() -> PathBuf {
     get_data_dir().join("ccg_gateway.db")
 }
@@ -57,23 +119,75 @@ pub fn get_data_dir() -> PathBuf {
     PathBuf::from(".").join(".ccg-gateway")
 }
 
-impl Default for Config {
+/// TOML config file path (~/.ccg-gateway/config.toml)
+pub fn get_config_path() -> PathBuf {
+    get_data_dir().join("config.toml")
+}
+
+impl Default for ServerConfig {
     fn default() -> Self {
         Self {
-            server: ServerConfig {
-                port: default_port(),
-                host: default_host(),
-            },
-            database: DatabaseConfig {
-                path: default_db_path(),
-                log_path: default_log_db_path(),
-            },
+            port: default_port(),
+            host: default_host(),
+            port_auto_select: default_port_auto_select(),
+            port_fallback_range: default_port_fallback_range(),
+            shutdown_grace_secs: default_shutdown_grace_secs(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            path: default_db_path(),
+            log_path: default_log_db_path(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            log_file: default_log_file(),
+            log_stdout: default_log_stdout(),
         }
     }
 }
 
 impl Config {
+    /// Load the config file, falling back to defaults when it is missing or invalid
     pub fn load() -> Self {
-        Config::default()
+        let path = get_config_path();
+        Self::load_from(&path).unwrap_or_else(|e| {
+            tracing::warn!("Failed to load config from {}: {}, using defaults", path.display(), e);
+            Config::default()
+        })
+    }
+
+    /// Parse the config file; a missing file yields the defaults
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        toml::from_str(&content).map_err(|e| e.to_string())
+    }
+}
+
+/// Write `server.port` into the config file, leaving the rest of the file untouched
+pub fn save_server_port(path: &Path, port: u16) -> Result<(), String> {
+    let content = if path.exists() {
+        std::fs::read_to_string(path).map_err(|e| e.to_string())?
+    } else {
+        String::new()
+    };
+    let mut doc = content
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(|e| e.to_string())?;
+    doc["server"]["port"] = toml_edit::value(port as i64);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
     }
+    std::fs::write(path, doc.to_string()).map_err(|e| e.to_string())
 }
@@ -8,21 +8,101 @@ use schema_definition::DatabaseSchema;
 use schema_diff::SchemaDiff;
 use schema_inspector::SchemaInspector;
 use schema_migrator::SchemaMigrator;
-use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
-use std::path::Path;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-pub async fn init_db(path: &Path) -> Result<SqlitePool, sqlx::Error> {
+/// A corrupt database file that init_db moved aside before creating a fresh one
+#[derive(Debug)]
+pub struct CorruptDatabase {
+    pub moved_to: PathBuf,
+    pub issues: Vec<String>,
+}
+
+pub async fn init_db(path: &Path) -> Result<(SqlitePool, Option<CorruptDatabase>), sqlx::Error> {
     // 1. 确保父目录存在
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).ok();
     }
 
-    // 2. 连接数据库
+    // 2. 连接数据库（每个连接都开启外键约束，子表数据随服务商级联删除）
     let db_url = format!("sqlite:{}?mode=rwc", path.display());
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
-        .await?;
+    let options = SqliteConnectOptions::from_str(&db_url)?.foreign_keys(true);
+    let connect = || SqlitePoolOptions::new().max_connections(5).connect_with(options.clone());
+
+    // 2.1 完整性检查：损坏的文件（断电、磁盘错误）移到一旁，重新创建空库
+    let issues = match connect().await {
+        Ok(pool) => match check_integrity(&pool).await {
+            Ok(issues) if issues.is_empty() => return migrate_db(path, pool).await.map(|pool| (pool, None)),
+            Ok(issues) => {
+                pool.close().await;
+                issues
+            }
+            Err(e) if is_corruption_error(&e) => {
+                pool.close().await;
+                vec![e.to_string()]
+            }
+            Err(e) => return Err(e),
+        },
+        Err(e) if is_corruption_error(&e) => vec![e.to_string()],
+        Err(e) => return Err(e),
+    };
+    let moved_to = quarantine_database(path)?;
+    tracing::error!("数据库已损坏，已移动到 {}: {:?}", moved_to.display(), issues);
+    let pool = connect().await?;
+    let pool = migrate_db(path, pool).await?;
+    Ok((pool, Some(CorruptDatabase { moved_to, issues })))
+}
+
+/// `PRAGMA integrity_check` and `PRAGMA foreign_key_check` findings (empty when healthy)
+pub async fn check_integrity(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let mut issues: Vec<String> = sqlx::query_scalar::<_, String>("PRAGMA integrity_check")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .filter(|line| line != "ok")
+        .collect();
+
+    // 每行: 子表, rowid, 父表, 外键序号
+    for row in sqlx::query("PRAGMA foreign_key_check").fetch_all(pool).await? {
+        let table: String = row.try_get(0)?;
+        let rowid: Option<i64> = row.try_get(1)?;
+        let parent: String = row.try_get(2)?;
+        issues.push(format!(
+            "{} row {} references a missing {} row",
+            table,
+            rowid.map(|id| id.to_string()).unwrap_or_else(|| "?".to_string()),
+            parent
+        ));
+    }
+    Ok(issues)
+}
+
+/// SQLITE_CORRUPT (11) / SQLITE_NOTADB (26), including extended codes
+fn is_corruption_error(e: &sqlx::Error) -> bool {
+    let Some(code) = e.as_database_error().and_then(|e| e.code()) else {
+        return false;
+    };
+    code.parse::<i64>().is_ok_and(|code| matches!(code & 0xff, 11 | 26))
+}
+
+/// Move the database (and its -wal / -shm files) to `<name>.corrupt.<timestamp>`
+fn quarantine_database(path: &Path) -> Result<PathBuf, sqlx::Error> {
+    let suffix = format!("corrupt.{}", chrono::Local::now().format("%Y%m%d%H%M%S"));
+    let moved_to = PathBuf::from(format!("{}.{}", path.display(), suffix));
+    std::fs::rename(path, &moved_to)?;
+    for ext in ["-wal", "-shm"] {
+        let sidecar = PathBuf::from(format!("{}{}", path.display(), ext));
+        if sidecar.exists() {
+            let _ = std::fs::rename(&sidecar, format!("{}{}.{}", path.display(), ext, suffix));
+        }
+    }
+    Ok(moved_to)
+}
+
+/// Create or migrate the schema of an opened database
+async fn migrate_db(path: &Path, pool: SqlitePool) -> Result<SqlitePool, sqlx::Error> {
 
     // 3. 判断数据库类型
     let is_log_db = path.ends_with("ccg_logs.db") || path.ends_with("ccg_logs");
@@ -81,10 +161,13 @@ pub async fn init_db(path: &Path) -> Result<SqlitePool, sqlx::Error> {
         tracing::info!("数据库迁移完成");
     }
 
-    // 13. 更新版本
+    // 13. 创建索引（重建表会连带删除旧索引）
+    create_indexes(&pool, &expected_schema).await?;
+
+    // 14. 更新版本
     update_version(&pool, expected_schema.version).await?;
 
-    // 14. 插入默认数据（仅主数据库）
+    // 15. 插入默认数据（仅主数据库）
     if !is_log_db {
         init_default_data(&pool).await?;
     }
@@ -103,6 +186,9 @@ async fn create_fresh_database(
         sqlx::query(&sql).execute(pool).await?;
     }
 
+    // 创建索引
+    create_indexes(pool, schema).await?;
+
     // 创建版本表
     create_version_table(pool).await?;
 
@@ -113,6 +199,14 @@ async fn create_fresh_database(
     Ok(())
 }
 
+/// 创建索引（IF NOT EXISTS，可重复执行）
+async fn create_indexes(pool: &SqlitePool, schema: &DatabaseSchema) -> Result<(), sqlx::Error> {
+    for sql in schema.to_create_index_sql() {
+        sqlx::query(&sql).execute(pool).await?;
+    }
+    Ok(())
+}
+
 /// 创建版本表
 async fn create_version_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     sqlx::query(
@@ -169,5 +263,13 @@ async fn init_default_data(pool: &SqlitePool) -> Result<(), sqlx::Error> {
         .execute(pool)
         .await?;
 
+    // budget_settings
+    for cli_type in ["claude_code", "codex", "gemini"] {
+        sqlx::query("INSERT OR IGNORE INTO budget_settings (cli_type, updated_at) VALUES (?, strftime('%s', 'now'))")
+            .bind(cli_type)
+            .execute(pool)
+            .await?;
+    }
+
     Ok(())
 }
@@ -1,9 +1,10 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::BTreeMap;
 
 // ==================== Provider 相关实体 ====================
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
 pub struct Provider {
     pub id: i64,
     pub cli_type: String,
@@ -16,6 +17,33 @@ pub struct Provider {
     pub consecutive_failures: i64,
     pub blacklisted_until: Option<i64>,
     pub sort_order: i64,
+    pub proxy_url: Option<String>,
+    pub user_agent_pattern: Option<String>,
+    pub cli_type_override: Option<String>,
+    pub insecure_skip_tls_verify: i64,
+    pub custom_headers: Option<String>,
+    pub extra_query_params: Option<String>,
+    pub path_rewrite_rules: Option<String>,
+    pub auth_scheme: Option<String>,
+    pub flavor: Option<String>,
+    pub wire_api: Option<String>,
+    pub protocol: Option<String>,
+    pub max_tokens_limit: Option<i64>,
+    pub strip_params: Option<String>,
+    pub inject_system_prompt: Option<String>,
+    pub inject_system_prompt_enabled: i64,
+    pub body_rewrite_rules: Option<String>,
+    pub drop_response_headers: Option<String>,
+    pub local_count_tokens: i64,
+    pub stream_include_usage: i64,
+    /// Daily token budget used up; skipped by routing until this time (next UTC midnight)
+    pub budget_exceeded_until: Option<i64>,
+    /// User-Agent sent upstream instead of the client's
+    pub proxy_user_agent: Option<String>,
+    /// Idle connections pooled per upstream host (NULL = pool default)
+    pub max_connections: Option<i64>,
+    /// TCP connect timeout for this provider (NULL = no limit)
+    pub connection_timeout_secs: Option<i64>,
     pub created_at: i64,
     pub updated_at: i64,
 }
@@ -27,6 +55,26 @@ pub struct ProviderModelMap {
     pub source_model: String,
     pub target_model: String,
     pub enabled: i64,
+    /// Match order within the provider (lower first, ties by id)
+    pub sort_order: i64,
+}
+
+// Path prefix rewrite rule (stored as JSON array on providers.path_rewrite_rules)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathRewriteRule {
+    pub from_prefix: String,
+    pub to_prefix: String,
+}
+
+// Body rewrite rule (stored as JSON array on providers.body_rewrite_rules), applied in order.
+// Paths are dotted keys with `[n]` / `[*]` for arrays, e.g. `tools[*].cache_control`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BodyRewriteRule {
+    Set { path: String, value: serde_json::Value },
+    Remove { path: String },
+    /// Rename the last key of `path` to `to` (same parent object)
+    Rename { path: String, to: String },
 }
 
 // Input DTOs
@@ -46,6 +94,28 @@ pub struct ProviderCreate {
     pub enabled: Option<bool>,
     pub failure_threshold: Option<i64>,
     pub blacklist_minutes: Option<i64>,
+    pub proxy_url: Option<String>,
+    pub user_agent_pattern: Option<String>,
+    pub cli_type_override: Option<String>,
+    pub insecure_skip_tls_verify: Option<bool>,
+    pub custom_headers: Option<BTreeMap<String, String>>,
+    pub extra_query_params: Option<BTreeMap<String, String>>,
+    pub path_rewrite_rules: Option<Vec<PathRewriteRule>>,
+    pub auth_scheme: Option<String>,
+    pub flavor: Option<String>,
+    pub wire_api: Option<String>,
+    pub protocol: Option<String>,
+    pub max_tokens_limit: Option<i64>,
+    pub strip_params: Option<Vec<String>>,
+    pub inject_system_prompt: Option<String>,
+    pub inject_system_prompt_enabled: Option<bool>,
+    pub body_rewrite_rules: Option<Vec<BodyRewriteRule>>,
+    pub drop_response_headers: Option<Vec<String>>,
+    pub local_count_tokens: Option<bool>,
+    pub stream_include_usage: Option<bool>,
+    pub proxy_user_agent: Option<String>,
+    pub max_connections: Option<i64>,
+    pub connection_timeout_secs: Option<i64>,
     pub model_maps: Option<Vec<ModelMapInput>>,
 }
 
@@ -57,9 +127,39 @@ pub struct ProviderUpdate {
     pub enabled: Option<bool>,
     pub failure_threshold: Option<i64>,
     pub blacklist_minutes: Option<i64>,
+    pub proxy_url: Option<String>,
+    pub user_agent_pattern: Option<String>,
+    pub cli_type_override: Option<String>,
+    pub insecure_skip_tls_verify: Option<bool>,
+    pub custom_headers: Option<BTreeMap<String, String>>,
+    pub extra_query_params: Option<BTreeMap<String, String>>,
+    pub path_rewrite_rules: Option<Vec<PathRewriteRule>>,
+    pub auth_scheme: Option<String>,
+    pub flavor: Option<String>,
+    pub wire_api: Option<String>,
+    pub protocol: Option<String>,
+    pub max_tokens_limit: Option<i64>,
+    pub strip_params: Option<Vec<String>>,
+    pub inject_system_prompt: Option<String>,
+    pub inject_system_prompt_enabled: Option<bool>,
+    pub body_rewrite_rules: Option<Vec<BodyRewriteRule>>,
+    pub drop_response_headers: Option<Vec<String>>,
+    pub local_count_tokens: Option<bool>,
+    pub stream_include_usage: Option<bool>,
+    pub proxy_user_agent: Option<String>,
+    pub max_connections: Option<i64>,
+    pub connection_timeout_secs: Option<i64>,
     pub model_maps: Option<Vec<ModelMapInput>>,
 }
 
+// Fields bulk_update_providers can change on several providers at once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderBulkPatch {
+    pub enabled: Option<bool>,
+    pub failure_threshold: Option<i64>,
+    pub blacklist_minutes: Option<i64>,
+}
+
 // Response DTOs
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelMapResponse {
@@ -67,6 +167,7 @@ pub struct ModelMapResponse {
     pub source_model: String,
     pub target_model: String,
     pub enabled: bool,
+    pub sort_order: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,7 +183,34 @@ pub struct ProviderResponse {
     pub consecutive_failures: i64,
     pub blacklisted_until: Option<i64>,
     pub sort_order: i64,
+    pub proxy_url: Option<String>,
+    pub user_agent_pattern: Option<String>,
+    pub cli_type_override: Option<String>,
+    pub insecure_skip_tls_verify: bool,
+    pub custom_headers: BTreeMap<String, String>,
+    pub extra_query_params: BTreeMap<String, String>,
+    pub path_rewrite_rules: Vec<PathRewriteRule>,
+    pub auth_scheme: Option<String>,
+    pub flavor: Option<String>,
+    pub wire_api: Option<String>,
+    pub protocol: Option<String>,
+    pub max_tokens_limit: Option<i64>,
+    pub strip_params: Vec<String>,
+    pub inject_system_prompt: Option<String>,
+    pub inject_system_prompt_enabled: bool,
+    pub body_rewrite_rules: Vec<BodyRewriteRule>,
+    pub drop_response_headers: Vec<String>,
+    pub local_count_tokens: bool,
+    /// Ask chat/completions streams for a final usage chunk (stream_options.include_usage)
+    pub stream_include_usage: bool,
+    /// User-Agent sent upstream instead of the client's
+    pub proxy_user_agent: Option<String>,
+    pub max_connections: Option<i64>,
+    pub connection_timeout_secs: Option<i64>,
+    pub budget_exceeded_until: Option<i64>,
     pub is_blacklisted: bool,
+    /// Country of the base_url host from provider_geo_cache (None until looked up)
+    pub country_code: Option<String>,
     pub model_maps: Vec<ModelMapResponse>,
 }
 
@@ -102,19 +230,273 @@ impl From<Provider> for ProviderResponse {
             consecutive_failures: p.consecutive_failures,
             blacklisted_until: p.blacklisted_until,
             sort_order: p.sort_order,
+            proxy_url: p.proxy_url,
+            user_agent_pattern: p.user_agent_pattern,
+            cli_type_override: p.cli_type_override,
+            insecure_skip_tls_verify: p.insecure_skip_tls_verify != 0,
+            custom_headers: p
+                .custom_headers
+                .as_deref()
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            extra_query_params: p
+                .extra_query_params
+                .as_deref()
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            path_rewrite_rules: p
+                .path_rewrite_rules
+                .as_deref()
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            auth_scheme: p.auth_scheme,
+            flavor: p.flavor,
+            wire_api: p.wire_api,
+            protocol: p.protocol,
+            max_tokens_limit: p.max_tokens_limit,
+            strip_params: p
+                .strip_params
+                .as_deref()
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            inject_system_prompt: p.inject_system_prompt,
+            inject_system_prompt_enabled: p.inject_system_prompt_enabled != 0,
+            body_rewrite_rules: p
+                .body_rewrite_rules
+                .as_deref()
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            drop_response_headers: p
+                .drop_response_headers
+                .as_deref()
+                .and_then(|s| serde_json::from_str(s).ok())
+                .unwrap_or_default(),
+            local_count_tokens: p.local_count_tokens != 0,
+            stream_include_usage: p.stream_include_usage != 0,
+            proxy_user_agent: p.proxy_user_agent,
+            max_connections: p.max_connections,
+            connection_timeout_secs: p.connection_timeout_secs,
+            budget_exceeded_until: p.budget_exceeded_until.filter(|t| *t > now),
             is_blacklisted,
+            country_code: None,
             model_maps: vec![], // Will be populated by the caller
         }
     }
 }
 
+// Portable provider list for export_providers / import_providers (no logs or runtime state)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderExport {
+    pub version: i64,
+    pub providers: Vec<ProviderCreate>,
+}
+
+impl From<ProviderResponse> for ProviderCreate {
+    fn from(p: ProviderResponse) -> Self {
+        Self {
+            cli_type: Some(p.cli_type),
+            name: p.name,
+            base_url: p.base_url,
+            api_key: p.api_key,
+            enabled: Some(p.enabled),
+            failure_threshold: Some(p.failure_threshold),
+            blacklist_minutes: Some(p.blacklist_minutes),
+            proxy_url: p.proxy_url,
+            user_agent_pattern: p.user_agent_pattern,
+            cli_type_override: p.cli_type_override,
+            insecure_skip_tls_verify: Some(p.insecure_skip_tls_verify),
+            custom_headers: Some(p.custom_headers),
+            extra_query_params: Some(p.extra_query_params),
+            path_rewrite_rules: Some(p.path_rewrite_rules),
+            auth_scheme: p.auth_scheme,
+            flavor: p.flavor,
+            wire_api: p.wire_api,
+            protocol: p.protocol,
+            max_tokens_limit: p.max_tokens_limit,
+            strip_params: Some(p.strip_params),
+            inject_system_prompt: p.inject_system_prompt,
+            inject_system_prompt_enabled: Some(p.inject_system_prompt_enabled),
+            body_rewrite_rules: Some(p.body_rewrite_rules),
+            drop_response_headers: Some(p.drop_response_headers),
+            local_count_tokens: Some(p.local_count_tokens),
+            stream_include_usage: Some(p.stream_include_usage),
+            proxy_user_agent: p.proxy_user_agent,
+            max_connections: p.max_connections,
+            connection_timeout_secs: p.connection_timeout_secs,
+            model_maps: Some(
+                p.model_maps
+                    .into_iter()
+                    .map(|m| ModelMapInput {
+                        source_model: m.source_model,
+                        target_model: m.target_model,
+                        enabled: m.enabled,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Provider API Key (多 key 轮询)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, FromRow)]
+pub struct ProviderApiKey {
+    pub id: i64,
+    pub provider_id: i64,
+    pub api_key: String,
+    pub enabled: i64,
+    pub last_used_at: Option<i64>,
+    pub failure_count: i64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderApiKeyResponse {
+    pub id: i64,
+    pub provider_id: i64,
+    pub api_key: String,
+    pub enabled: bool,
+    pub last_used_at: Option<i64>,
+    pub failure_count: i64,
+}
+
+impl From<ProviderApiKey> for ProviderApiKeyResponse {
+    fn from(k: ProviderApiKey) -> Self {
+        // 只展示最后 4 位
+        let chars: Vec<char> = k.api_key.chars().collect();
+        let tail: String = chars[chars.len().saturating_sub(4)..].iter().collect();
+        Self {
+            id: k.id,
+            provider_id: k.provider_id,
+            api_key: format!("****{}", tail),
+            enabled: k.enabled != 0,
+            last_used_at: k.last_used_at,
+            failure_count: k.failure_count,
+        }
+    }
+}
+
+// Provider Schedule (按时间段覆盖 sort_order)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ProviderSchedule {
+    pub id: i64,
+    pub provider_id: i64,
+    /// Bitmask of weekdays, bit 0 = Sunday ... bit 6 = Saturday
+    pub day_of_week: i64,
+    /// Local hour range [hour_start, hour_end), wraps past midnight when start > end
+    pub hour_start: i64,
+    pub hour_end: i64,
+    pub priority_override: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProviderScheduleCreate {
+    pub provider_id: i64,
+    pub day_of_week: Option<i64>,
+    pub hour_start: i64,
+    pub hour_end: i64,
+    pub priority_override: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProviderScheduleUpdate {
+    pub day_of_week: Option<i64>,
+    pub hour_start: Option<i64>,
+    pub hour_end: Option<i64>,
+    pub priority_override: Option<i64>,
+}
+
+// User-Agent Rule (全局 User-Agent -> CLI 类型规则，priority 小的先匹配)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UaRule {
+    pub id: i64,
+    /// Regex matched against the User-Agent header
+    pub pattern: String,
+    pub cli_type: String,
+    pub priority: i64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UaRuleCreate {
+    pub pattern: String,
+    pub cli_type: String,
+    pub priority: Option<i64>,
+}
+
+// Global Model Alias (按 CLI 类型的全局模型别名，先于服务商模型映射生效)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ModelAlias {
+    pub id: i64,
+    pub cli_type: String,
+    /// Wildcard pattern (* ?) or `re:` regex, same syntax as provider model maps
+    pub source_model: String,
+    pub target_model: String,
+    pub enabled: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ModelAliasCreate {
+    pub cli_type: String,
+    pub source_model: String,
+    pub target_model: String,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ModelAliasUpdate {
+    pub source_model: Option<String>,
+    pub target_model: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+// Model Pricing (按服务商 + 模型的 token 单价，美元 / 百万 token)
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ModelPricing {
+    pub id: i64,
+    pub provider_name: String,
+    pub model_id: String,
+    pub input_price_per_million_tokens: f64,
+    pub output_price_per_million_tokens: f64,
+    /// None = input price
+    pub cache_read_price_per_million_tokens: Option<f64>,
+    /// None = input price
+    pub cache_write_price_per_million_tokens: Option<f64>,
+    pub updated_at: i64,
+}
+
 // ==================== Settings 相关实体 ====================
 
+/// 默认请求体上限（MB）
+pub const DEFAULT_MAX_REQUEST_BODY_MB: i64 = 10;
+/// 请求体上限设置允许的最大值（MB）
+pub const MAX_REQUEST_BODY_MB_LIMIT: i64 = 1024;
+
 // Gateway Settings (完整版 - 对应数据库表)
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct GatewaySettingsRow {
     pub id: i64,
     pub debug_log: i64,
+    pub max_request_body_mb: i64,
+    pub log_body_max_kb: i64,
+    pub use_keychain: i64,
+    pub request_id_header: Option<String>,
+    pub ws_proxy_enabled: i64,
+    pub gateway_token: Option<String>,
+    pub gateway_token_enforced: i64,
+    pub listen_external: i64,
+    pub listen_address: Option<String>,
+    pub allow_simulation_commands: i64,
+    pub default_cli_type: String,
+    pub server_host: Option<String>,
+    pub server_port: Option<i64>,
+    pub cli_ports: String,
+    pub cli_path_prefix: i64,
+    pub default_proxy_user_agent: Option<String>,
+    pub sticky_session_enabled: i64,
     pub updated_at: i64,
 }
 
@@ -122,6 +504,31 @@ pub struct GatewaySettingsRow {
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct GatewaySettings {
     pub debug_log: i64,
+    pub max_request_body_mb: i64,
+    /// Size limit of each logged body in KB (0 = unlimited)
+    pub log_body_max_kb: i64,
+    /// Keep provider API keys in the OS keychain instead of the database
+    pub use_keychain: i64,
+    pub request_id_header: Option<String>,
+    pub ws_proxy_enabled: i64,
+    pub gateway_token: Option<String>,
+    pub gateway_token_enforced: i64,
+    pub listen_external: i64,
+    pub listen_address: Option<String>,
+    pub allow_simulation_commands: i64,
+    /// CLI type for requests no User-Agent rule recognizes
+    pub default_cli_type: String,
+    /// Listen host/port set from the app; override the config file when present
+    pub server_host: Option<String>,
+    pub server_port: Option<i64>,
+    /// JSON object of dedicated listener ports per CLI type (see update_cli_ports)
+    pub cli_ports: String,
+    /// Write /claude, /codex, /gemini prefixed gateway URLs into CLI configs
+    pub cli_path_prefix: i64,
+    /// User-Agent sent upstream for providers without proxy_user_agent (None keeps the client's)
+    pub default_proxy_user_agent: Option<String>,
+    /// Keep the requests of one client session on the same provider
+    pub sticky_session_enabled: i64,
 }
 
 // Timeout Settings (完整版 - 对应数据库表)
@@ -131,6 +538,8 @@ pub struct TimeoutSettingsRow {
     pub stream_first_byte_timeout: i64,
     pub stream_idle_timeout: i64,
     pub non_stream_timeout: i64,
+    pub stream_per_token_timeout_ms: i64,
+    pub stream_keepalive_interval: i64,
     pub updated_at: i64,
 }
 
@@ -140,6 +549,32 @@ pub struct TimeoutSettings {
     pub stream_first_byte_timeout: i64,
     pub stream_idle_timeout: i64,
     pub non_stream_timeout: i64,
+    /// 流式空闲超时按已输出 token 放宽（毫秒/token），0 表示关闭
+    pub stream_per_token_timeout_ms: i64,
+    /// 等待上游期间向客户端发送 SSE 注释的间隔（秒），0 表示关闭
+    pub stream_keepalive_interval: i64,
+}
+
+/// Fields to change in gateway_settings; None keeps the current value
+#[derive(Debug, Default, Deserialize)]
+pub struct GatewaySettingsUpdate {
+    pub debug_log: Option<bool>,
+    pub max_request_body_mb: Option<i64>,
+    pub log_body_max_kb: Option<i64>,
+    pub use_keychain: Option<bool>,
+    /// Empty string turns request IDs off
+    pub request_id_header: Option<String>,
+    pub ws_proxy_enabled: Option<bool>,
+    pub gateway_token_enforced: Option<bool>,
+    pub listen_external: Option<bool>,
+    /// Empty string listens on every interface
+    pub listen_address: Option<String>,
+    pub allow_simulation_commands: Option<bool>,
+    pub default_cli_type: Option<String>,
+    pub cli_path_prefix: Option<bool>,
+    /// Empty string keeps the client's User-Agent
+    pub default_proxy_user_agent: Option<String>,
+    pub sticky_session_enabled: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -147,6 +582,40 @@ pub struct TimeoutSettingsUpdate {
     pub stream_first_byte_timeout: Option<i64>,
     pub stream_idle_timeout: Option<i64>,
     pub non_stream_timeout: Option<i64>,
+    pub stream_per_token_timeout_ms: Option<i64>,
+    pub stream_keepalive_interval: Option<i64>,
+}
+
+// Daily token budget (budget_settings), applied to each provider of the CLI type
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct BudgetSettings {
+    pub cli_type: String,
+    pub daily_input_token_limit: Option<i64>,
+    pub daily_output_token_limit: Option<i64>,
+    pub warning_threshold_pct: i64,
+    /// Skip the provider for the rest of the day once a limit is reached
+    pub block_when_exceeded: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BudgetSettingsUpdate {
+    /// None or 0 removes the limit
+    pub daily_input_token_limit: Option<i64>,
+    pub daily_output_token_limit: Option<i64>,
+    pub warning_threshold_pct: Option<i64>,
+    pub block_when_exceeded: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetStatus {
+    pub cli_type: String,
+    pub provider_name: String,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub daily_input_token_limit: Option<i64>,
+    pub daily_output_token_limit: Option<i64>,
+    /// "ok", "warning" or "exceeded"
+    pub state: String,
 }
 
 // CLI Settings
@@ -154,6 +623,8 @@ pub struct TimeoutSettingsUpdate {
 pub struct CliSettingsRow {
     pub cli_type: String,
     pub default_json_config: Option<String>,
+    /// Enabled prompts are appended to the CLI prompt file instead of replacing it
+    pub prompt_append_mode: i64,
     pub updated_at: i64,
 }
 
@@ -162,12 +633,14 @@ pub struct CliSettingsResponse {
     pub cli_type: String,
     pub enabled: bool,
     pub default_json_config: String,
+    pub prompt_append_mode: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CliSettingsUpdate {
     pub enabled: Option<bool>,
     pub default_json_config: Option<String>,
+    pub prompt_append_mode: Option<bool>,
 }
 
 // WebDAV Settings
@@ -245,6 +718,30 @@ pub struct McpUpdate {
     pub cli_flags: Option<Vec<McpCliFlag>>,
 }
 
+#[derive(Debug, Serialize)]
+pub struct McpValidationResult {
+    pub valid: bool,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum McpDiffStatus {
+    InSync,
+    DbOnly,
+    FilesystemOnly,
+    Diverged,
+}
+
+/// Stored MCP config vs. the entry in a CLI config file
+#[derive(Debug, Serialize)]
+pub struct McpDiff {
+    pub mcp_name: String,
+    pub db_config: Option<String>,
+    pub fs_config: Option<String>,
+    pub status: McpDiffStatus,
+}
+
 // ==================== Prompt 相关实体 ====================
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -253,6 +750,8 @@ pub struct PromptPreset {
     pub name: String,
     pub content: String,
     pub updated_at: i64,
+    /// Also add the prompt to the systemInstruction of Gemini requests while it is enabled for Gemini
+    pub inject_system_prompt: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -267,6 +766,7 @@ pub struct PromptResponse {
     pub name: String,
     pub content: String,
     pub cli_flags: Vec<PromptCliFlag>,
+    pub inject_system_prompt: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -275,6 +775,7 @@ pub struct PromptCreate {
     pub content: String,
     pub enabled: Option<bool>,
     pub cli_flags: Option<Vec<PromptCliFlag>>,
+    pub inject_system_prompt: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -283,6 +784,7 @@ pub struct PromptUpdate {
     pub content: Option<String>,
     pub enabled: Option<bool>,
     pub cli_flags: Option<Vec<PromptCliFlag>>,
+    pub inject_system_prompt: Option<bool>,
 }
 
 // ==================== Request Logs 相关实体 ====================
@@ -299,8 +801,33 @@ pub struct RequestLogItem {
     pub elapsed_ms: i64,
     pub input_tokens: i64,
     pub output_tokens: i64,
+    pub cache_creation_tokens: i64,
+    pub cache_read_tokens: i64,
     pub client_method: String,
     pub client_path: String,
+    pub request_id: Option<String>,
+    pub client_ip: Option<String>,
+    /// Upstream time to first byte (response headers)
+    pub ttfb_ms: Option<i64>,
+}
+
+// Request Log Item with estimated cost (按 model_pricing 估算)
+#[derive(Debug, Serialize)]
+pub struct CostLogItem {
+    #[serde(flatten)]
+    pub log: RequestLogItem,
+    /// None when the provider/model pair has no configured price
+    pub cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaginatedCostLogs {
+    pub items: Vec<CostLogItem>,
+    pub total: i64,
+    pub page: i64,
+    pub page_size: i64,
+    /// Estimated cost of every matching log, not just this page
+    pub total_cost_usd: f64,
 }
 
 // Request Log Detail (详情视图)
@@ -315,11 +842,14 @@ pub struct RequestLogDetail {
     pub elapsed_ms: i64,
     pub input_tokens: i64,
     pub output_tokens: i64,
+    pub cache_creation_tokens: i64,
+    pub cache_read_tokens: i64,
     pub client_method: String,
     pub client_path: String,
     pub client_headers: Option<String>,
     pub client_body: Option<String>,
     pub forward_url: Option<String>,
+    pub forward_proxy: Option<String>,
     pub forward_headers: Option<String>,
     pub forward_body: Option<String>,
     pub provider_headers: Option<String>,
@@ -327,6 +857,47 @@ pub struct RequestLogDetail {
     pub response_headers: Option<String>,
     pub response_body: Option<String>,
     pub error_message: Option<String>,
+    pub body_transforms: Option<String>,
+    pub request_id: Option<String>,
+    pub client_ip: Option<String>,
+    /// Client model -> global alias -> provider map, when any rewrite happened
+    pub model_chain: Option<String>,
+    pub ttfb_ms: Option<i64>,
+    /// JSON array of upstream attempts: `{provider_name, status_code, error, elapsed_ms}`
+    pub attempts: Option<String>,
+    /// Message Batch id, when the request created, polled or fetched results of a batch
+    pub batch_id: Option<String>,
+}
+
+// Request log lookup key: numeric log id or the gateway request ID (X-CCG-Request-Id)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum RequestLogKey {
+    Id(i64),
+    RequestId(String),
+}
+
+impl From<String> for RequestLogKey {
+    // Path segments: all-digit values are log ids
+    fn from(value: String) -> Self {
+        match value.trim().parse::<i64>() {
+            Ok(id) => RequestLogKey::Id(id),
+            Err(_) => RequestLogKey::RequestId(value.trim().to_string()),
+        }
+    }
+}
+
+// One request of a Message Batch (batch_request_items)
+#[derive(Debug, FromRow, Serialize)]
+pub struct BatchRequestItem {
+    pub id: i64,
+    pub batch_id: String,
+    pub custom_id: String,
+    pub result_type: String,
+    pub status_code: Option<i64>,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub created_at: i64,
 }
 
 #[derive(Debug, Serialize)]
@@ -337,6 +908,14 @@ pub struct PaginatedLogs {
     pub page_size: i64,
 }
 
+/// A request log with its neighbours, oldest first
+#[derive(Debug, Serialize)]
+pub struct LogContext {
+    pub before: Vec<RequestLogItem>,
+    pub target: RequestLogDetail,
+    pub after: Vec<RequestLogItem>,
+}
+
 // ==================== System Logs 相关实体 ====================
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -383,6 +962,10 @@ pub struct UsageDaily {
     pub failure_count: i64,
     pub input_tokens: i64,
     pub output_tokens: i64,
+    pub cache_creation_tokens: i64,
+    pub cache_read_tokens: i64,
+    /// Estimated cost from model_pricing at the time of each request
+    pub cost_usd: f64,
 }
 
 // Daily Stats (别名，用于向后兼容)
@@ -398,6 +981,7 @@ pub struct ProviderStatsRow {
     pub total_success: i64,
     pub total_tokens: i64,
     pub total_elapsed_ms: i64,
+    pub avg_ttfb_ms: Option<f64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -410,6 +994,71 @@ pub struct ProviderStatsResponse {
     pub total_tokens: i64,
     pub total_elapsed_ms: i64,
     pub success_rate: f64,
+    /// Average upstream time to first byte (requests that got a response)
+    pub avg_ttfb_ms: Option<f64>,
+}
+
+// Model Usage Stats (从 request_logs 按模型聚合)
+#[derive(Debug, Serialize, FromRow)]
+pub struct ModelUsageStats {
+    pub model_id: String,
+    pub total_requests: i64,
+    pub total_input_tokens: i64,
+    pub total_output_tokens: i64,
+    pub provider_count: i64,
+    pub avg_latency_ms: f64,
+}
+
+// Uncovered Model (request_logs 中没有命中模型映射的模型)
+#[derive(Debug, Serialize, FromRow)]
+pub struct UncoveredModel {
+    pub model_id: String,
+    pub provider_name: String,
+    pub cli_type: String,
+    pub request_count: i64,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+// Provider Error Breakdown (从 request_logs 按错误类型聚合)
+#[derive(Debug, Serialize, FromRow)]
+pub struct ProviderErrorBreakdown {
+    pub provider_name: String,
+    /// timeout / rate_limit / server_error / auth_error / client_error / unknown
+    pub error_type: String,
+    pub count: i64,
+    pub last_occurrence: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PurgeResult {
+    pub request_log_count: i64,
+    pub system_log_count: i64,
+    pub usage_daily_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportProviderResult {
+    pub imported: i64,
+    pub skipped: i64,
+    pub errors: Vec<String>,
+}
+
+// Outcome of apply_env_file: setting keys applied / not understood, and `KEY: error` failures
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ApplyEnvResult {
+    pub applied: Vec<String>,
+    pub skipped: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct HourlyStats {
+    pub hour: i64,
+    pub request_count: i64,
+    pub success_count: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
 }
 
 // ==================== Session 相关实体 (非数据库) ====================
@@ -433,7 +1082,10 @@ pub struct SessionInfo {
     pub mtime: f64,
     pub first_message: String,
     pub git_branch: String,
-    pub summary: String,
+    /// Summary generated by get_session_summary
+    pub summary: Option<String>,
+    /// Sum of priced request_logs for this session; None when any model used has no pricing
+    pub estimated_cost_usd: Option<f64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -452,20 +1104,196 @@ pub struct PaginatedSessions {
     pub page_size: i64,
 }
 
-// Session Message (从会话文件解析)
+// Session Search (会话全文搜索)
+#[derive(Debug, Serialize)]
+pub struct SessionSearchHit {
+    pub cli_type: String,
+    pub project_name: String,
+    pub session_id: String,
+    /// 首个匹配位置附近约 100 个字符
+    pub matching_excerpt: String,
+    pub mtime: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaginatedSearchResults {
+    pub items: Vec<SessionSearchHit>,
+    pub total: i64,
+    pub page: i64,
+    pub page_size: i64,
+}
+
+// Session Compression (压缩旧会话文件)
 #[derive(Debug, Serialize)]
+pub struct CompressionStats {
+    pub files_compressed: i64,
+    pub bytes_saved: i64,
+}
+
+// Session Message (从会话文件解析)
+#[derive(Debug, Clone, Serialize)]
 pub struct SessionMessage {
     pub role: String,
     pub content: String,
     pub timestamp: Option<i64>,
 }
 
+/// Payload of the `session_message_added` event emitted by watch_session_file
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionMessagesAdded {
+    pub session_id: String,
+    pub messages: Vec<SessionMessage>,
+}
+
+// ==================== Replay 相关实体 (非数据库) ====================
+
+#[derive(Debug, Serialize)]
+pub struct ReplayResult {
+    pub log_id: i64,
+    pub provider_name: String,
+    pub status_code: u16,
+    pub elapsed_ms: i64,
+    pub response_body: String,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+}
+
+// ==================== Benchmark (非数据库) ====================
+
+/// Latency statistics of a provider benchmark (over the successful requests)
+#[derive(Debug, Serialize)]
+pub struct BenchmarkResult {
+    pub min_ms: i64,
+    pub max_ms: i64,
+    pub mean_ms: f64,
+    pub p95_ms: i64,
+    /// Share of requests answered with a 2xx status, 0.0 - 1.0
+    pub success_rate: f64,
+    pub requests_sent: i64,
+}
+
 // ==================== System Status (非数据库) ====================
 
+/// State of the gateway listener, set by the task that binds it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ServerStatus {
+    Starting,
+    Running {
+        address: std::net::SocketAddr,
+        /// Unix time the gateway started listening (kept across rebinds)
+        since: i64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+impl ServerStatus {
+    /// "starting", "running" or "failed"
+    pub fn state(&self) -> &'static str {
+        match self {
+            ServerStatus::Starting => "starting",
+            ServerStatus::Running { .. } => "running",
+            ServerStatus::Failed { .. } => "failed",
+        }
+    }
+
+    /// Seconds since the gateway started listening (0 unless running)
+    pub fn uptime(&self) -> i64 {
+        match self {
+            ServerStatus::Running { since, .. } => chrono::Utc::now().timestamp() - since,
+            _ => 0,
+        }
+    }
+
+    pub fn error(&self) -> Option<String> {
+        match self {
+            ServerStatus::Failed { error } => Some(error.clone()),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct SystemStatus {
+    /// "starting", "running" or "failed" (see ServerStatus)
     pub status: String,
+    /// Why the gateway could not listen, when status is "failed"
+    pub error: Option<String>,
     pub port: u16,
+    /// Seconds since the gateway started listening
     pub uptime: i64,
     pub version: String,
+    /// Address the gateway is actually bound to (host:port)
+    pub listen_address: Option<String>,
+    /// URL other machines should use when listening on a non-loopback address
+    pub lan_url: Option<String>,
+    /// Estimated cost of all logged requests with a configured price
+    pub total_cost_usd: f64,
+    /// Today's token usage against the daily budgets, per provider with a budget configured
+    pub budgets: Vec<BudgetStatus>,
+    /// Proxy requests holding a concurrency slot (open streams included)
+    pub requests_in_flight: u32,
+    /// gateway_settings.max_concurrent_requests; 0 = unlimited
+    pub max_concurrent_requests: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderHealthSummary {
+    pub id: i64,
+    pub cli_type: String,
+    pub name: String,
+    pub enabled: bool,
+    pub consecutive_failures: i64,
+    pub is_blacklisted: bool,
+    pub blacklisted_until: Option<i64>,
+}
+
+// PRAGMA integrity_check / foreign_key_check of both databases
+#[derive(Debug, Serialize)]
+pub struct DatabaseIntegrityResult {
+    pub ok: bool,
+    pub issues: Vec<String>,
+}
+
+// GeoIP location of a provider's base_url host (provider_geo_cache)
+#[derive(Debug, Serialize)]
+pub struct ProviderGeoInfo {
+    pub provider_id: i64,
+    pub host: String,
+    pub ip: String,
+    pub country_code: Option<String>,
+    pub city: Option<String>,
+    pub asn_name: Option<String>,
+    pub resolved_at: i64,
+}
+
+// Troubleshooting snapshot attached to bug reports
+#[derive(Debug, Serialize)]
+pub struct GatewayDiagnostics {
+    pub version: String,
+    pub port: u16,
+    pub db_size_bytes: u64,
+    pub log_db_size_bytes: u64,
+    pub active_connections: u32,
+    pub provider_statuses: Vec<ProviderHealthSummary>,
+    /// None when the platform does not expose it
+    pub memory_rss_kb: Option<u64>,
+    pub uptime_secs: i64,
+    pub total_requests_handled: u64,
+    pub requests_in_flight: u32,
+}
+
+// Proxy request still being handled (streaming responses until the stream ends)
+#[derive(Debug, Serialize)]
+pub struct ActiveRequest {
+    pub request_id: String,
+    pub provider_name: Option<String>,
+    pub cli_type: Option<String>,
+    pub model_id: Option<String>,
+    /// Unix timestamp (ms)
+    pub started_at: i64,
+    pub elapsed_ms: i64,
+    pub is_streaming: bool,
 }
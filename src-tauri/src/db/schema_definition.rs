@@ -16,6 +16,41 @@ pub struct TableDefinition {
     pub columns: Vec<ColumnDefinition>,
     pub primary_key: Vec<String>,
     pub unique_constraints: Vec<Vec<String>>,
+    pub foreign_keys: Vec<ForeignKeyDefinition>,
+}
+
+/// 外键定义
+#[derive(Debug, Clone)]
+pub struct ForeignKeyDefinition {
+    pub column: String,
+    pub ref_table: String,
+    pub ref_column: String,
+    /// ON DELETE 动作（如 CASCADE）
+    pub on_delete: Option<String>,
+}
+
+impl ForeignKeyDefinition {
+    /// 父记录删除时级联删除子记录
+    pub fn cascade(column: &str, ref_table: &str, ref_column: &str) -> Self {
+        Self {
+            column: column.to_string(),
+            ref_table: ref_table.to_string(),
+            ref_column: ref_column.to_string(),
+            on_delete: Some("CASCADE".to_string()),
+        }
+    }
+
+    /// 生成 FOREIGN KEY 子句
+    pub fn to_sql(&self) -> String {
+        let mut sql = format!(
+            "FOREIGN KEY ({}) REFERENCES {}({})",
+            self.column, self.ref_table, self.ref_column
+        );
+        if let Some(ref action) = self.on_delete {
+            sql.push_str(&format!(" ON DELETE {}", action));
+        }
+        sql
+    }
 }
 
 impl TableDefinition {
@@ -57,32 +92,61 @@ impl TableDefinition {
             sql.push(')');
         }
 
+        // 外键
+        for fk in &self.foreign_keys {
+            sql.push_str(",\n    ");
+            sql.push_str(&fk.to_sql());
+        }
+
         sql.push_str("\n)");
         sql
     }
 }
 
+/// 索引定义
+#[derive(Debug, Clone)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+impl IndexDefinition {
+    /// 生成 CREATE INDEX SQL
+    pub fn to_create_sql(&self) -> String {
+        format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+            self.name,
+            self.table,
+            self.columns.join(", ")
+        )
+    }
+}
+
 /// 数据库 Schema
 #[derive(Debug, Clone)]
 pub struct DatabaseSchema {
     pub version: i64,
     pub tables: HashMap<String, TableDefinition>,
+    pub indexes: Vec<IndexDefinition>,
 }
 
 impl DatabaseSchema {
     /// 获取当前主数据库 Schema
     pub fn current() -> Self {
         Self {
-            version: 2,
+            version: 55,
             tables: Self::define_main_tables(),
+            indexes: vec![],
         }
     }
 
     /// 获取日志数据库 Schema
     pub fn log_schema() -> Self {
         Self {
-            version: 1,
+            version: 13,
             tables: Self::define_log_tables(),
+            indexes: Self::define_log_indexes(),
         }
     }
 
@@ -91,6 +155,11 @@ impl DatabaseSchema {
         self.tables.values().map(|table| table.to_create_sql()).collect()
     }
 
+    /// 生成所有索引的 CREATE SQL
+    pub fn to_create_index_sql(&self) -> Vec<String> {
+        self.indexes.iter().map(|index| index.to_create_sql()).collect()
+    }
+
     /// 定义主数据库表
     fn define_main_tables() -> HashMap<String, TableDefinition> {
         let mut tables = HashMap::new();
@@ -162,92 +231,810 @@ impl DatabaseSchema {
                         default_value: None,
                     },
                     ColumnDefinition {
-                        name: "sort_order".to_string(),
+                        name: "sort_order".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "proxy_url".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "user_agent_pattern".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "cli_type_override".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "insecure_skip_tls_verify".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "custom_headers".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "extra_query_params".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "path_rewrite_rules".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "auth_scheme".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "flavor".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "wire_api".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "protocol".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "max_tokens_limit".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "strip_params".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "inject_system_prompt".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "inject_system_prompt_enabled".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "body_rewrite_rules".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "drop_response_headers".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "local_count_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "stream_include_usage".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    // 转发时替换客户端的 User-Agent（为空时使用 gateway_settings.default_proxy_user_agent）
+                    ColumnDefinition {
+                        name: "proxy_user_agent".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    // 每个上游主机保留的空闲连接数（为空时使用默认连接池大小）
+                    ColumnDefinition {
+                        name: "max_connections".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    // 建立 TCP 连接的超时（秒），为空时不限制
+                    ColumnDefinition {
+                        name: "connection_timeout_secs".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "budget_exceeded_until".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "created_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![vec!["cli_type".to_string(), "name".to_string()]],
+                foreign_keys: vec![],
+            },
+        );
+
+        // provider_model_map 表
+        tables.insert(
+            "provider_model_map".to_string(),
+            TableDefinition {
+                name: "provider_model_map".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "provider_id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "source_model".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "target_model".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "enabled".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "sort_order".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![vec![
+                    "provider_id".to_string(),
+                    "source_model".to_string(),
+                ]],
+                foreign_keys: vec![ForeignKeyDefinition::cascade("provider_id", "providers", "id")],
+            },
+        );
+
+        // provider_api_keys 表 (多 key 轮询)
+        tables.insert(
+            "provider_api_keys".to_string(),
+            TableDefinition {
+                name: "provider_api_keys".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "provider_id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "api_key".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "enabled".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "last_used_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "failure_count".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "created_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![vec![
+                    "provider_id".to_string(),
+                    "api_key".to_string(),
+                ]],
+                foreign_keys: vec![ForeignKeyDefinition::cascade("provider_id", "providers", "id")],
+            },
+        );
+
+        // provider_schedules 表 (按时间段覆盖服务商优先级)
+        tables.insert(
+            "provider_schedules".to_string(),
+            TableDefinition {
+                name: "provider_schedules".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "provider_id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "day_of_week".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("127".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "hour_start".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "hour_end".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "priority_override".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "created_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![],
+                foreign_keys: vec![ForeignKeyDefinition::cascade("provider_id", "providers", "id")],
+            },
+        );
+
+        // user_agent_rules 表 (按 User-Agent 识别 CLI 类型，priority 小的先匹配)
+        tables.insert(
+            "user_agent_rules".to_string(),
+            TableDefinition {
+                name: "user_agent_rules".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "pattern".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "priority".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "created_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![],
+                foreign_keys: vec![],
+            },
+        );
+
+        // session_summaries 表 (服务商生成的会话一句话摘要)
+        tables.insert(
+            "session_summaries".to_string(),
+            TableDefinition {
+                name: "session_summaries".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "session_id".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "summary".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "provider_name".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "created_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["cli_type".to_string(), "session_id".to_string()],
+                unique_constraints: vec![],
+                foreign_keys: vec![],
+            },
+        );
+
+        // provider_model_cache 表 (服务商 models 接口返回的模型列表，缓存 24 小时)
+        tables.insert(
+            "provider_model_cache".to_string(),
+            TableDefinition {
+                name: "provider_model_cache".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "provider_id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "base_url".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "models".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'[]'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "fetched_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["provider_id".to_string()],
+                unique_constraints: vec![],
+                foreign_keys: vec![ForeignKeyDefinition::cascade("provider_id", "providers", "id")],
+            },
+        );
+
+        // provider_geo_cache 表 (base_url 主机的 GeoIP 位置，7 天后重新查询)
+        tables.insert(
+            "provider_geo_cache".to_string(),
+            TableDefinition {
+                name: "provider_geo_cache".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "provider_id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "host".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "ip".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "country_code".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "city".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "asn_name".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "resolved_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["provider_id".to_string()],
+                unique_constraints: vec![],
+                foreign_keys: vec![ForeignKeyDefinition::cascade("provider_id", "providers", "id")],
+            },
+        );
+
+        // global_model_aliases 表 (按 CLI 类型的全局模型别名，先于服务商模型映射生效)
+        tables.insert(
+            "global_model_aliases".to_string(),
+            TableDefinition {
+                name: "global_model_aliases".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "source_model".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "target_model".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "enabled".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "created_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![vec!["cli_type".to_string(), "source_model".to_string()]],
+                foreign_keys: vec![],
+            },
+        );
+
+        // model_pricing 表 (按服务商 + 模型配置 token 单价，用于成本估算)
+        tables.insert(
+            "model_pricing".to_string(),
+            TableDefinition {
+                name: "model_pricing".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "provider_name".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "model_id".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "input_price_per_million_tokens".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "output_price_per_million_tokens".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    // 缓存读取 / 写入单价，NULL 时按输入单价计算
+                    ColumnDefinition {
+                        name: "cache_read_price_per_million_tokens".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "cache_write_price_per_million_tokens".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![vec![
+                    "provider_name".to_string(),
+                    "model_id".to_string(),
+                ]],
+                foreign_keys: vec![],
+            },
+        );
+
+        // gateway_settings 表
+        tables.insert(
+            "gateway_settings".to_string(),
+            TableDefinition {
+                name: "gateway_settings".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "debug_log".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "max_request_body_mb".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("10".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_body_max_kb".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("100".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "use_keychain".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_exclude_paths".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'[]'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_exclude_count_usage".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "log_mask_patterns".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'[]'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "request_id_header".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "ws_proxy_enabled".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "gateway_token".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "gateway_token_enforced".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("1".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "listen_external".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "listen_address".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "allow_simulation_commands".to_string(),
                         data_type: "INTEGER".to_string(),
                         nullable: false,
                         default_value: Some("0".to_string()),
                     },
                     ColumnDefinition {
-                        name: "created_at".to_string(),
-                        data_type: "INTEGER".to_string(),
+                        name: "default_cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
                         nullable: false,
+                        default_value: Some("'claude_code'".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "server_host".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
                         default_value: None,
                     },
                     ColumnDefinition {
-                        name: "updated_at".to_string(),
+                        name: "server_port".to_string(),
                         data_type: "INTEGER".to_string(),
-                        nullable: false,
+                        nullable: true,
                         default_value: None,
                     },
-                ],
-                primary_key: vec!["id".to_string()],
-                unique_constraints: vec![vec!["cli_type".to_string(), "name".to_string()]],
-            },
-        );
-
-        // provider_model_map 表
-        tables.insert(
-            "provider_model_map".to_string(),
-            TableDefinition {
-                name: "provider_model_map".to_string(),
-                columns: vec![
+                    // 按 CLI 类型的独立监听端口 {"claude_code": 7789, ...}，为空时单端口按 UA 识别
                     ColumnDefinition {
-                        name: "id".to_string(),
-                        data_type: "INTEGER".to_string(),
+                        name: "cli_ports".to_string(),
+                        data_type: "TEXT".to_string(),
                         nullable: false,
-                        default_value: None,
+                        default_value: Some("'{}'".to_string()),
                     },
+                    // 同步 CLI 配置时在网关地址后加 /claude、/codex、/gemini 前缀，由路径决定 CLI 类型
                     ColumnDefinition {
-                        name: "provider_id".to_string(),
+                        name: "cli_path_prefix".to_string(),
                         data_type: "INTEGER".to_string(),
                         nullable: false,
-                        default_value: None,
+                        default_value: Some("0".to_string()),
                     },
+                    // 未设置 proxy_user_agent 的服务商转发时使用的 User-Agent（为空时保留客户端的）
                     ColumnDefinition {
-                        name: "source_model".to_string(),
+                        name: "default_proxy_user_agent".to_string(),
                         data_type: "TEXT".to_string(),
-                        nullable: false,
+                        nullable: true,
                         default_value: None,
                     },
+                    // 同一会话的请求固定转发到同一服务商
                     ColumnDefinition {
-                        name: "target_model".to_string(),
-                        data_type: "TEXT".to_string(),
+                        name: "sticky_session_enabled".to_string(),
+                        data_type: "INTEGER".to_string(),
                         nullable: false,
-                        default_value: None,
+                        default_value: Some("0".to_string()),
                     },
+                    // /api 的 CORS 允许来源（JSON 数组），为空时不返回跨域头
                     ColumnDefinition {
-                        name: "enabled".to_string(),
-                        data_type: "INTEGER".to_string(),
+                        name: "cors_allowed_origins".to_string(),
+                        data_type: "TEXT".to_string(),
                         nullable: false,
-                        default_value: Some("1".to_string()),
+                        default_value: Some("'[]'".to_string()),
                     },
-                ],
-                primary_key: vec!["id".to_string()],
-                unique_constraints: vec![vec![
-                    "provider_id".to_string(),
-                    "source_model".to_string(),
-                ]],
-            },
-        );
-
-        // gateway_settings 表
-        tables.insert(
-            "gateway_settings".to_string(),
-            TableDefinition {
-                name: "gateway_settings".to_string(),
-                columns: vec![
+                    // 允许使用代理的客户端 IP / CIDR（JSON 数组），为空时不限制
                     ColumnDefinition {
-                        name: "id".to_string(),
-                        data_type: "INTEGER".to_string(),
+                        name: "allowed_client_ips".to_string(),
+                        data_type: "TEXT".to_string(),
                         nullable: false,
-                        default_value: Some("1".to_string()),
+                        default_value: Some("'[]'".to_string()),
                     },
+                    // 同时处理的代理请求上限（含未结束的流式响应），0 表示不限制
                     ColumnDefinition {
-                        name: "debug_log".to_string(),
+                        name: "max_concurrent_requests".to_string(),
                         data_type: "INTEGER".to_string(),
                         nullable: false,
                         default_value: Some("0".to_string()),
                     },
+                    // 网关写入各 CLI 配置的地址（JSON 对象 cli_type -> url），端口变化时只改写这些地址
+                    ColumnDefinition {
+                        name: "cli_config_urls".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: Some("'{}'".to_string()),
+                    },
                     ColumnDefinition {
                         name: "updated_at".to_string(),
                         data_type: "INTEGER".to_string(),
@@ -257,6 +1044,7 @@ impl DatabaseSchema {
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![],
+                foreign_keys: vec![],
             },
         );
 
@@ -290,6 +1078,18 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: Some("120".to_string()),
                     },
+                    ColumnDefinition {
+                        name: "stream_per_token_timeout_ms".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("500".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "stream_keepalive_interval".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
                     ColumnDefinition {
                         name: "updated_at".to_string(),
                         data_type: "INTEGER".to_string(),
@@ -299,6 +1099,7 @@ impl DatabaseSchema {
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![],
+                foreign_keys: vec![],
             },
         );
 
@@ -320,6 +1121,61 @@ impl DatabaseSchema {
                         nullable: true,
                         default_value: None,
                     },
+                    ColumnDefinition {
+                        name: "prompt_append_mode".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "updated_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["cli_type".to_string()],
+                unique_constraints: vec![],
+                foreign_keys: vec![],
+            },
+        );
+
+        // budget_settings 表 (每个 CLI 类型下单个服务商的每日 token 预算)
+        tables.insert(
+            "budget_settings".to_string(),
+            TableDefinition {
+                name: "budget_settings".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "cli_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "daily_input_token_limit".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "daily_output_token_limit".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "warning_threshold_pct".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("80".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "block_when_exceeded".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
                     ColumnDefinition {
                         name: "updated_at".to_string(),
                         data_type: "INTEGER".to_string(),
@@ -329,6 +1185,7 @@ impl DatabaseSchema {
                 ],
                 primary_key: vec!["cli_type".to_string()],
                 unique_constraints: vec![],
+                foreign_keys: vec![],
             },
         );
 
@@ -365,6 +1222,7 @@ impl DatabaseSchema {
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![vec!["name".to_string()]],
+                foreign_keys: vec![],
             },
         );
 
@@ -398,9 +1256,17 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: None,
                     },
+                    // 启用于 Gemini 时，同时注入每个请求的 systemInstruction
+                    ColumnDefinition {
+                        name: "inject_system_prompt".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![vec!["name".to_string()]],
+                foreign_keys: vec![],
             },
         );
 
@@ -455,12 +1321,44 @@ impl DatabaseSchema {
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![],
+                foreign_keys: vec![],
             },
         );
 
         tables
     }
 
+    /// 定义日志数据库索引
+    fn define_log_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition {
+                name: "idx_request_logs_created_at".to_string(),
+                table: "request_logs".to_string(),
+                columns: vec!["created_at".to_string()],
+            },
+            IndexDefinition {
+                name: "idx_request_logs_session_id".to_string(),
+                table: "request_logs".to_string(),
+                columns: vec!["session_id".to_string()],
+            },
+            IndexDefinition {
+                name: "idx_request_logs_request_id".to_string(),
+                table: "request_logs".to_string(),
+                columns: vec!["request_id".to_string()],
+            },
+            IndexDefinition {
+                name: "idx_request_logs_provider_model".to_string(),
+                table: "request_logs".to_string(),
+                columns: vec!["provider_name".to_string(), "model_id".to_string()],
+            },
+            IndexDefinition {
+                name: "idx_request_logs_batch_id".to_string(),
+                table: "request_logs".to_string(),
+                columns: vec!["batch_id".to_string()],
+            },
+        ]
+    }
+
     /// 定义日志数据库表
     fn define_log_tables() -> HashMap<String, TableDefinition> {
         let mut tables = HashMap::new();
@@ -525,6 +1423,18 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: Some("0".to_string()),
                     },
+                    ColumnDefinition {
+                        name: "cache_creation_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "cache_read_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
                     ColumnDefinition {
                         name: "client_method".to_string(),
                         data_type: "TEXT".to_string(),
@@ -555,6 +1465,12 @@ impl DatabaseSchema {
                         nullable: true,
                         default_value: None,
                     },
+                    ColumnDefinition {
+                        name: "forward_proxy".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
                     ColumnDefinition {
                         name: "forward_headers".to_string(),
                         data_type: "TEXT".to_string(),
@@ -597,9 +1513,119 @@ impl DatabaseSchema {
                         nullable: true,
                         default_value: None,
                     },
+                    ColumnDefinition {
+                        name: "session_id".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "body_transforms".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "request_id".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "client_ip".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "model_chain".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "ttfb_ms".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "attempts".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "batch_id".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![],
+                foreign_keys: vec![],
+            },
+        );
+
+        // batch_request_items 表 (Message Batches 结果中的每个请求，按 batch_id 关联 request_logs)
+        tables.insert(
+            "batch_request_items".to_string(),
+            TableDefinition {
+                name: "batch_request_items".to_string(),
+                columns: vec![
+                    ColumnDefinition {
+                        name: "id".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "batch_id".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "custom_id".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "result_type".to_string(),
+                        data_type: "TEXT".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "status_code".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: true,
+                        default_value: None,
+                    },
+                    ColumnDefinition {
+                        name: "input_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "output_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "created_at".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: None,
+                    },
+                ],
+                primary_key: vec!["id".to_string()],
+                unique_constraints: vec![vec!["batch_id".to_string(), "custom_id".to_string()]],
+                foreign_keys: vec![],
             },
         );
 
@@ -654,6 +1680,7 @@ impl DatabaseSchema {
                 ],
                 primary_key: vec!["id".to_string()],
                 unique_constraints: vec![],
+                foreign_keys: vec![],
             },
         );
 
@@ -711,6 +1738,24 @@ impl DatabaseSchema {
                         nullable: false,
                         default_value: Some("0".to_string()),
                     },
+                    ColumnDefinition {
+                        name: "cache_creation_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "cache_read_tokens".to_string(),
+                        data_type: "INTEGER".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
+                    ColumnDefinition {
+                        name: "cost_usd".to_string(),
+                        data_type: "REAL".to_string(),
+                        nullable: false,
+                        default_value: Some("0".to_string()),
+                    },
                 ],
                 primary_key: vec![
                     "usage_date".to_string(),
@@ -718,6 +1763,7 @@ impl DatabaseSchema {
                     "cli_type".to_string(),
                 ],
                 unique_constraints: vec![],
+                foreign_keys: vec![],
             },
         );
 
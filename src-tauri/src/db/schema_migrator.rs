@@ -1,7 +1,8 @@
 use super::schema_definition::{DatabaseSchema, TableDefinition};
 use super::schema_diff::{SchemaChange, SchemaDiff};
 use super::schema_inspector::SchemaInspector;
-use sqlx::SqlitePool;
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, Row, Sqlite, SqlitePool};
 
 /// 迁移执行器
 pub struct SchemaMigrator<'a> {
@@ -20,8 +21,18 @@ impl<'a> SchemaMigrator<'a> {
 
     /// 应用所有变更（使用事务确保原子性）
     pub async fn apply(&self, diff: SchemaDiff) -> Result<(), sqlx::Error> {
+        // 迁移期间关闭外键：删除重建前的旧表不能级联删除子表数据
+        // （该 PRAGMA 在事务内无效，必须在同一连接上、事务开始前设置）
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await?;
+        let result = self.apply_changes(&mut conn, diff).await;
+        sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await?;
+        result
+    }
+
+    async fn apply_changes(&self, conn: &mut PoolConnection<Sqlite>, diff: SchemaDiff) -> Result<(), sqlx::Error> {
         // 开启事务
-        let mut tx = self.pool.begin().await?;
+        let mut tx = conn.begin().await?;
         
         // 处理所有变更
         for change in diff.changes {
@@ -38,11 +49,38 @@ impl<'a> SchemaMigrator<'a> {
             }
         }
         
+        // 外键校验不通过则整体回滚
+        Self::check_foreign_keys(&mut tx).await?;
+
         // 提交事务
         tx.commit().await?;
         Ok(())
     }
 
+    /// PRAGMA foreign_key_check，存在违反外键约束的记录时返回错误
+    async fn check_foreign_keys(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<(), sqlx::Error> {
+        // 返回格式: table, rowid, parent, fkid
+        let rows = sqlx::query("PRAGMA foreign_key_check").fetch_all(&mut **tx).await?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let violations: Vec<String> = rows
+            .iter()
+            .take(10)
+            .map(|row| {
+                let table: String = row.get(0);
+                let rowid: Option<i64> = row.try_get(1).ok();
+                let parent: String = row.get(2);
+                format!("{}#{} -> {}", table, rowid.unwrap_or_default(), parent)
+            })
+            .collect();
+        Err(sqlx::Error::Protocol(format!(
+            "迁移后发现 {} 条违反外键约束的记录: {}",
+            rows.len(),
+            violations.join(", ")
+        )))
+    }
+
     /// 删除表（事务版本）
     async fn drop_table_tx(
         &self,
@@ -108,24 +146,51 @@ impl<'a> SchemaMigrator<'a> {
         }
 
         // 4. 重建表
-        // 4.1 重命名旧表
-        let rename_sql = format!("ALTER TABLE {} RENAME TO {}_old", table, table);
-        sqlx::query(&rename_sql).execute(&mut **tx).await?;
-
-        // 4.2 创建新表（使用期望的结构）
-        self.create_table_tx(tx, expected_table).await?;
+        // 先建新表再改名：重命名旧表会让子表的外键引用跟着指向旧表
+        // 4.1 创建新表（使用期望的结构）
+        let mut new_table = expected_table.clone();
+        new_table.name = format!("{}_new", table);
+        self.create_table_tx(tx, &new_table).await?;
 
-        // 4.3 复制数据（只复制共同列）
+        // 4.2 复制数据（只复制共同列；新增外键时丢弃父记录已不存在的孤儿数据）
         let column_list = keep_columns.join(", ");
+        let orphan_filters: Vec<String> = expected_table
+            .foreign_keys
+            .iter()
+            .filter(|fk| keep_columns.contains(&fk.column))
+            .map(|fk| {
+                format!(
+                    "({col} IS NULL OR {col} IN (SELECT {ref_col} FROM {ref_table}))",
+                    col = fk.column,
+                    ref_col = fk.ref_column,
+                    ref_table = fk.ref_table
+                )
+            })
+            .collect();
+        let where_clause = if orphan_filters.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", orphan_filters.join(" AND "))
+        };
         let copy_sql = format!(
-            "INSERT INTO {} ({}) SELECT {} FROM {}_old",
-            table, column_list, column_list, table
+            "INSERT INTO {}_new ({}) SELECT {} FROM {}{}",
+            table, column_list, column_list, table, where_clause
         );
-        sqlx::query(&copy_sql).execute(&mut **tx).await?;
+        let copied = sqlx::query(&copy_sql).execute(&mut **tx).await?.rows_affected();
+        if !where_clause.is_empty() {
+            let count_sql = format!("SELECT COUNT(*) FROM {}", table);
+            let total: i64 = sqlx::query_scalar(&count_sql).fetch_one(&mut **tx).await?;
+            let dropped = total as u64 - copied;
+            if dropped > 0 {
+                tracing::warn!("表 {} 丢弃 {} 条父记录已不存在的数据", table, dropped);
+            }
+        }
 
-        // 4.4 删除旧表
-        let drop_sql = format!("DROP TABLE {}_old", table);
+        // 4.3 删除旧表，新表改回原名
+        let drop_sql = format!("DROP TABLE {}", table);
         sqlx::query(&drop_sql).execute(&mut **tx).await?;
+        let rename_sql = format!("ALTER TABLE {}_new RENAME TO {}", table, table);
+        sqlx::query(&rename_sql).execute(&mut **tx).await?;
 
         tracing::info!("表 {} 重建完成", table);
         Ok(())
@@ -4,9 +4,14 @@ pub mod config;
 pub mod db;
 pub mod services;
 
-use config::Config;
+use config::{Config, SharedConfig};
 use db::init_db;
 use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 use tauri::Manager;
 use tauri::menu::{MenuBuilder, MenuItemBuilder};
 use tauri::tray::{TrayIconBuilder, TrayIconEvent};
@@ -14,6 +19,222 @@ use tauri::tray::{TrayIconBuilder, TrayIconEvent};
 // Type wrappers for Tauri state
 pub struct LogDb(pub SqlitePool);
 pub struct StartTime(pub i64);
+pub struct HttpClient(pub reqwest::Client);
+pub struct UaPatterns(pub services::proxy::UaPatternCache);
+pub struct UaRules(pub services::proxy::UaRuleCache);
+pub struct MaskPatterns(pub services::masking::MaskPatternCache);
+pub struct LogExcludePaths(pub services::log_exclude::LogExcludeCache);
+pub struct CorsOrigins(pub services::cors::CorsOriginCache);
+pub struct AllowedClientIps(pub services::ip_allowlist::IpAllowlistCache);
+pub struct ProviderSchedules(pub services::routing::ScheduleCache);
+/// Port of the shared listener, plus the ports of the dedicated per-CLI listeners that are running
+#[derive(Clone, Default)]
+pub struct GatewayPort(pub Arc<AtomicU16>, pub Arc<dashmap::DashMap<String, u16>>);
+/// Gateway listener state (starting / running on an address / failed to bind), shared by
+/// get_system_status, /health and the tray tooltip
+#[derive(Clone)]
+pub struct ListenAddr(pub Arc<tokio::sync::watch::Sender<db::models::ServerStatus>>);
+pub struct AppConfig(pub SharedConfig);
+/// Running gateway listener; update_gateway_port swaps it for a listener on another port
+#[derive(Clone)]
+pub struct GatewayServer {
+    router: axum::Router,
+    shutdown: Arc<Mutex<Option<tokio::sync::oneshot::Sender<()>>>>,
+}
+/// Dedicated listeners per CLI type (gateway_settings.cli_ports), each forcing its CLI type
+#[derive(Clone)]
+pub struct CliListeners {
+    state: Arc<api::AppState>,
+    servers: Arc<Mutex<HashMap<String, (SocketAddr, GatewayServer)>>>,
+}
+/// Session file watchers started by watch_session_file, keyed by session id
+#[derive(Default)]
+pub struct SessionWatchers(pub Mutex<HashMap<String, tokio::task::JoinHandle<()>>>);
+
+/// 配置文件变更的防抖间隔（编辑器保存时往往连续触发多次事件）
+const CONFIG_RELOAD_DEBOUNCE_MS: u64 = 500;
+/// CLI 配置文件变更的防抖间隔（CLI 自身写配置时也会连续触发多次事件）
+const CLI_CONFIG_WATCH_DEBOUNCE_MS: u64 = 1000;
+
+impl GatewayPort {
+    /// Actual port the gateway is listening on
+    pub fn get(&self) -> u16 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Port written into the config file of `cli_type`: its dedicated listener, else the shared one
+    pub fn for_cli(&self, cli_type: &str) -> u16 {
+        self.1.get(cli_type).map(|port| *port).unwrap_or_else(|| self.get())
+    }
+}
+
+impl CliListeners {
+    pub fn new(state: Arc<api::AppState>) -> Self {
+        Self {
+            state,
+            servers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Stop every dedicated listener (before moving them to another host)
+    pub fn stop_all(&self, gateway_port: &GatewayPort) {
+        for (cli_type, (_, server)) in self.servers.lock().unwrap_or_else(|e| e.into_inner()).drain() {
+            server.stop();
+            gateway_port.1.remove(&cli_type);
+        }
+    }
+
+    /// Run a dedicated listener on `host` for each entry of `ports` and stop the others.
+    ///
+    /// 先绑定全部新端口，任一失败时现有监听保持不变；地址未变的监听继续使用。
+    pub async fn apply(&self, host: &str, ports: &HashMap<String, u16>, gateway_port: &GatewayPort) -> Result<(), String> {
+        let running: HashMap<String, SocketAddr> = self
+            .servers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(cli_type, (addr, _))| (cli_type.clone(), *addr))
+            .collect();
+        let host_ip = host.parse::<std::net::IpAddr>().ok();
+
+        let mut bound = Vec::new();
+        for (cli_type, port) in ports {
+            let cli: services::proxy::CliType = cli_type.parse()?;
+            let unchanged = running
+                .get(cli_type)
+                .is_some_and(|addr| addr.port() == *port && Some(addr.ip()) == host_ip);
+            if unchanged {
+                continue;
+            }
+            // 刚停掉的监听可能还没释放端口
+            let listener = bind_with_retry(host, *port)
+                .await
+                .map_err(|e| format!("{}:{} is not available for {}: {}", host, port, cli_type, e))?;
+            let addr = listener.local_addr().map_err(|e| e.to_string())?;
+            bound.push((cli_type.clone(), cli, addr, listener));
+        }
+
+        let mut servers = self.servers.lock().unwrap_or_else(|e| e.into_inner());
+        servers.retain(|cli_type, (_, server)| {
+            let keep = ports.contains_key(cli_type);
+            if !keep {
+                server.stop();
+                gateway_port.1.remove(cli_type);
+            }
+            keep
+        });
+        for (cli_type, cli, addr, listener) in bound {
+            let (current, server) = servers
+                .entry(cli_type.clone())
+                .or_insert_with(|| (addr, GatewayServer::new(api::create_router(self.state.clone(), Some(cli)))));
+            *current = addr;
+            server.serve(listener);
+            gateway_port.1.insert(cli_type.clone(), addr.port());
+            tracing::info!("Dedicated {} listener on {}", cli_type, addr);
+        }
+        Ok(())
+    }
+}
+
+impl GatewayServer {
+    pub fn new(router: axum::Router) -> Self {
+        Self {
+            router,
+            shutdown: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Stop accepting connections on the current listener; in-flight requests finish
+    pub fn stop(&self) {
+        let previous = self.shutdown.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(previous) = previous {
+            let _ = previous.send(());
+        }
+    }
+
+    /// Serve the gateway on `listener`, shutting down the listener served before.
+    ///
+    /// 旧监听立即停止接受新连接，进行中的请求（包括流式响应）继续完成。
+    pub fn serve(&self, listener: tokio::net::TcpListener) {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let previous = self.shutdown.lock().unwrap_or_else(|e| e.into_inner()).replace(tx);
+        if let Some(previous) = previous {
+            let _ = previous.send(());
+        }
+
+        // Connect info lets the proxy log the client address
+        let service = self.router.clone().into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move {
+            let shutdown = async {
+                let _ = rx.await;
+            };
+            if let Err(e) = axum::serve(listener, service).with_graceful_shutdown(shutdown).await {
+                tracing::error!("Gateway server error: {}", e);
+            }
+        });
+    }
+}
+
+impl Default for ListenAddr {
+    fn default() -> Self {
+        Self(Arc::new(tokio::sync::watch::Sender::new(db::models::ServerStatus::Starting)))
+    }
+}
+
+impl ListenAddr {
+    /// Address the gateway listener is bound to (None until bound)
+    pub fn get(&self) -> Option<SocketAddr> {
+        match *self.0.borrow() {
+            db::models::ServerStatus::Running { address, .. } => Some(address),
+            _ => None,
+        }
+    }
+
+    /// Mark the gateway as listening on `addr`; a rebind keeps the original start time
+    pub fn set(&self, addr: SocketAddr) {
+        self.0.send_modify(|status| {
+            let since = match status {
+                db::models::ServerStatus::Running { since, .. } => *since,
+                _ => chrono::Utc::now().timestamp(),
+            };
+            *status = db::models::ServerStatus::Running { address: addr, since };
+        });
+    }
+
+    /// Mark the gateway as unable to listen
+    pub fn set_failed(&self, error: String) {
+        self.0.send_replace(db::models::ServerStatus::Failed { error });
+    }
+
+    pub fn status(&self) -> db::models::ServerStatus {
+        self.0.borrow().clone()
+    }
+
+    /// Receiver notified whenever the listener state changes
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<db::models::ServerStatus> {
+        self.0.subscribe()
+    }
+
+    /// True when other machines can reach the gateway (not bound to loopback)
+    pub fn is_external(&self) -> bool {
+        self.get().is_some_and(|addr| !addr.ip().is_loopback())
+    }
+
+    /// URL other machines on the network should use, when listening beyond loopback.
+    ///
+    /// 绑定 0.0.0.0 / :: 时取默认路由所在网卡的地址（UDP connect 不会真正发包）
+    pub fn lan_url(&self) -> Option<String> {
+        let addr = self.get().filter(|addr| !addr.ip().is_loopback())?;
+        let ip = if addr.ip().is_unspecified() {
+            let socket = std::net::UdpSocket::bind(("0.0.0.0", 0)).ok()?;
+            socket.connect(("8.8.8.8", 80)).ok()?;
+            socket.local_addr().ok()?.ip()
+        } else {
+            addr.ip()
+        };
+        Some(format!("http://{}", SocketAddr::new(ip, addr.port())))
+    }
+}
 
 impl std::ops::Deref for LogDb {
     type Target = SqlitePool;
@@ -22,6 +243,231 @@ impl std::ops::Deref for LogDb {
     }
 }
 
+impl std::ops::Deref for HttpClient {
+    type Target = reqwest::Client;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Bind right after a listener was stopped; its task releases the port asynchronously
+pub(crate) async fn bind_with_retry(host: &str, port: u16) -> std::io::Result<tokio::net::TcpListener> {
+    let mut attempts = 0;
+    loop {
+        match tokio::net::TcpListener::bind((host, port)).await {
+            Ok(listener) => return Ok(listener),
+            Err(_) if attempts < 20 => {
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Bind the gateway listener, falling back to port+1..=port+range and then a random port
+async fn bind_listener(server: &config::ServerConfig) -> std::io::Result<tokio::net::TcpListener> {
+    let err = match tokio::net::TcpListener::bind((server.host.as_str(), server.port)).await {
+        Ok(listener) => return Ok(listener),
+        Err(e) => e,
+    };
+    if !server.port_auto_select {
+        return Err(err);
+    }
+    tracing::warn!("Port {} unavailable ({}), trying fallback ports", server.port, err);
+
+    for offset in 1..=server.port_fallback_range.max(0) {
+        let Ok(port) = u16::try_from(server.port as i64 + offset) else {
+            break;
+        };
+        if let Ok(listener) = tokio::net::TcpListener::bind((server.host.as_str(), port)).await {
+            return Ok(listener);
+        }
+    }
+
+    // 最后交给系统分配随机端口
+    tokio::net::TcpListener::bind((server.host.as_str(), 0)).await
+}
+
+/// Stop the listeners, wait for in-flight requests and log writes, then close the databases.
+///
+/// 最多等待 server.shutdown_grace_secs 秒；只执行一次，之后调用方负责退出进程。
+pub async fn stop_gateway(app: &tauri::AppHandle, reason: &str) {
+    static STOPPING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    if STOPPING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    app.state::<GatewayServer>().stop();
+    app.state::<CliListeners>().stop_all(&app.state::<GatewayPort>());
+
+    let grace = app
+        .state::<AppConfig>()
+        .0
+        .read()
+        .map(|c| c.server.shutdown_grace_secs)
+        .unwrap_or(10);
+    let counters = app.state::<services::diagnostics::ProxyCounters>();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(grace);
+    while counters.busy() > 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let remaining = counters.busy();
+    let message = if remaining == 0 {
+        format!("CCG Gateway stopped ({})", reason)
+    } else {
+        format!("CCG Gateway stopped ({}) with {} request(s) still running after {}s", reason, remaining, grace)
+    };
+    tracing::info!("{}", message);
+    let log_db = app.state::<LogDb>();
+    let _ = services::stats::record_system_log(&log_db, "info", "gateway_stopped", &message, None, None).await;
+
+    app.state::<SqlitePool>().close().await;
+    log_db.close().await;
+}
+
+/// Re-read the config file and swap it into the shared config.
+///
+/// host/port/database 只在启动时生效，变更后记录警告提示需要重启。
+pub async fn reload_config_file(config: &SharedConfig, log_db: &SqlitePool) -> Result<Config, String> {
+    let path = config::get_config_path();
+    let new_config = match Config::load_from(&path) {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = services::stats::record_system_log(
+                log_db,
+                "error",
+                "config_reload_failed",
+                &format!("Failed to reload {}: {}", path.display(), e),
+                None,
+                None,
+            ).await;
+            return Err(e);
+        }
+    };
+
+    let old_config = {
+        let mut guard = config.write().map_err(|e| e.to_string())?;
+        std::mem::replace(&mut *guard, new_config.clone())
+    };
+
+    let mut restart_required = Vec::new();
+    if old_config.server.host != new_config.server.host {
+        restart_required.push(format!("server.host: {} -> {}", old_config.server.host, new_config.server.host));
+    }
+    if old_config.server.port != new_config.server.port {
+        restart_required.push(format!("server.port: {} -> {}", old_config.server.port, new_config.server.port));
+    }
+    if old_config.database != new_config.database {
+        restart_required.push("database paths".to_string());
+    }
+
+    if !restart_required.is_empty() {
+        let message = format!("Config changes require restart to take effect: {}", restart_required.join(", "));
+        tracing::warn!("{}", message);
+        let _ = services::stats::record_system_log(log_db, "warn", "config_restart_required", &message, None, None).await;
+    }
+
+    tracing::info!("Config reloaded from {}", path.display());
+    let _ = services::stats::record_system_log(
+        log_db,
+        "info",
+        "config_reloaded",
+        &format!("Config reloaded from {}", path.display()),
+        None,
+        None,
+    ).await;
+
+    Ok(new_config)
+}
+
+/// Watch the config file and reload it on change (debounced)
+fn spawn_config_watcher(config: SharedConfig, log_db: SqlitePool) {
+    use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
+
+    let path = config::get_config_path();
+    let Some(dir) = path.parent().map(|p| p.to_path_buf()) else {
+        return;
+    };
+
+    std::thread::spawn(move || {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut debouncer = match new_debouncer(Duration::from_millis(CONFIG_RELOAD_DEBOUNCE_MS), tx) {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::warn!("Failed to create config watcher: {}", e);
+                return;
+            }
+        };
+        // 监听所在目录而不是文件本身：文件可能尚不存在，且编辑器常以替换方式保存
+        if let Err(e) = debouncer.watcher().watch(&dir, RecursiveMode::NonRecursive) {
+            tracing::warn!("Failed to watch {}: {}", dir.display(), e);
+            return;
+        }
+
+        for result in rx {
+            let Ok(events) = result else {
+                continue;
+            };
+            if events.iter().any(|event| event.path == path) {
+                let _ = tauri::async_runtime::block_on(reload_config_file(&config, &log_db));
+            }
+        }
+    });
+}
+
+/// Watch the CLI config files and flag the ones changed away from what the gateway wrote
+fn spawn_cli_config_watcher(app: tauri::AppHandle, cli_sync: services::cli_sync::CliSyncState, port: GatewayPort) {
+    use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
+    use services::cli_sync::{cli_config_paths, cli_type_for_path, CLI_CONFIG_MODIFIED_EVENT, CLI_TYPES};
+    use tauri::Emitter;
+
+    let mut dirs: Vec<std::path::PathBuf> = CLI_TYPES
+        .into_iter()
+        .flat_map(cli_config_paths)
+        .filter_map(|path| path.parent().map(|p| p.to_path_buf()))
+        .collect();
+    dirs.dedup();
+
+    std::thread::spawn(move || {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut debouncer = match new_debouncer(Duration::from_millis(CLI_CONFIG_WATCH_DEBOUNCE_MS), tx) {
+            Ok(d) => d,
+            Err(e) => {
+                tracing::warn!("Failed to create CLI config watcher: {}", e);
+                return;
+            }
+        };
+        // CLI 未安装时目录不存在，跳过即可
+        for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+            if let Err(e) = debouncer.watcher().watch(dir, RecursiveMode::NonRecursive) {
+                tracing::warn!("Failed to watch {}: {}", dir.display(), e);
+            }
+        }
+
+        for result in rx {
+            let Ok(events) = result else {
+                continue;
+            };
+            let mut changed: Vec<&str> = events.iter().filter_map(|event| cli_type_for_path(&event.path)).collect();
+            changed.sort_unstable();
+            changed.dedup();
+
+            for cli_type in changed {
+                let enabled = commands::check_cli_enabled(cli_type, port.for_cli(cli_type));
+                let status = cli_sync.observe(cli_type, enabled);
+                if status.out_of_sync {
+                    tracing::warn!("{} config was modified outside the gateway (gateway enabled: {})", cli_type, enabled);
+                }
+                if let Err(e) = app.emit(CLI_CONFIG_MODIFIED_EVENT, status) {
+                    tracing::warn!("Failed to emit CLI config change: {}", e);
+                }
+            }
+        }
+    });
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let config = Config::load();
@@ -42,37 +488,247 @@ pub fn run() {
                     std::fs::create_dir_all(parent).ok();
                 }
 
-                let db = init_db(&db_path).await.expect("Failed to init database");
-                let log_db = init_db(&log_db_path)
+                let (db, corrupt_db) = init_db(&db_path).await.expect("Failed to init database");
+                let (log_db, corrupt_log_db) = init_db(&log_db_path)
                     .await
                     .expect("Failed to init log database");
+                for corrupt in [corrupt_db, corrupt_log_db].into_iter().flatten() {
+                    let _ = services::stats::record_system_log(
+                        &log_db,
+                        "error",
+                        "database_corruption_detected",
+                        &format!(
+                            "Corrupt database moved to {} and recreated: {}",
+                            corrupt.moved_to.display(),
+                            corrupt.issues.join("; ")
+                        ),
+                        None,
+                        None,
+                    ).await;
+                }
 
                 app.manage(db.clone());
                 app.manage(LogDb(log_db.clone()));
+
+                // Token local clients present to the proxy (generated on first run)
+                if let Err(e) = commands::ensure_gateway_token(&db).await {
+                    tracing::warn!("Failed to initialize gateway token: {}", e);
+                }
                 app.manage(StartTime(start_time));
 
+                // Shared HTTP client (connection pool) for proxy and WebDAV
+                let http_client = services::http_client::build_shared_client();
+                app.manage(HttpClient(http_client.clone()));
+                let http_clients = services::http_client::HttpClientPool::new(http_client);
+                app.manage(http_clients.clone());
+
+                // Compiled provider User-Agent patterns for CLI type detection
+                let ua_patterns = services::proxy::UaPatternCache::default();
+                if let Err(e) = services::proxy::reload_ua_patterns(&db, &ua_patterns).await {
+                    tracing::warn!("Failed to load User-Agent patterns: {}", e);
+                }
+                app.manage(UaPatterns(ua_patterns.clone()));
+
+                // Global User-Agent rules and the default CLI type
+                let ua_rules = services::proxy::UaRuleCache::default();
+                if let Err(e) = services::proxy::reload_ua_rules(&db, &ua_rules).await {
+                    tracing::warn!("Failed to load User-Agent rules: {}", e);
+                }
+                app.manage(UaRules(ua_rules.clone()));
+
+                // Compiled regexes for masking secrets in request logs
+                let mask_patterns = services::masking::MaskPatternCache::default();
+                if let Err(e) = services::masking::reload_mask_patterns(&db, &mask_patterns).await {
+                    tracing::warn!("Failed to load log mask patterns: {}", e);
+                }
+                app.manage(MaskPatterns(mask_patterns.clone()));
+
+                // Client paths kept out of the request log
+                let log_exclude = services::log_exclude::LogExcludeCache::default();
+                if let Err(e) = services::log_exclude::reload_log_exclude_rules(&db, &log_exclude).await {
+                    tracing::warn!("Failed to load log exclude paths: {}", e);
+                }
+                app.manage(LogExcludePaths(log_exclude.clone()));
+
+                // Origins allowed to call /api from a browser
+                let cors_origins = services::cors::CorsOriginCache::default();
+                if let Err(e) = services::cors::reload_cors_origins(&db, &cors_origins).await {
+                    tracing::warn!("Failed to load CORS origins: {}", e);
+                }
+                app.manage(CorsOrigins(cors_origins.clone()));
+
+                // Client addresses allowed to use the proxy
+                let allowed_client_ips = services::ip_allowlist::IpAllowlistCache::default();
+                if let Err(e) = services::ip_allowlist::reload_allowed_client_ips(&db, &allowed_client_ips).await {
+                    tracing::warn!("Failed to load allowed client IPs: {}", e);
+                }
+                app.manage(AllowedClientIps(allowed_client_ips.clone()));
+
+                // Time-of-day provider priority schedules
+                let schedules = services::routing::ScheduleCache::default();
+                if let Err(e) = services::routing::reload_provider_schedules(&db, &schedules).await {
+                    tracing::warn!("Failed to load provider schedules: {}", e);
+                }
+                app.manage(ProviderSchedules(schedules.clone()));
+
+                // Actual listening port, updated once the listener is bound
+                let gateway_port = GatewayPort(Arc::new(AtomicU16::new(config.server.port)), Default::default());
+                app.manage(gateway_port.clone());
+                let listen_addr = ListenAddr::default();
+                app.manage(listen_addr.clone());
+
+                // Live config, kept in sync with the config file
+                let shared_config: SharedConfig = Arc::new(RwLock::new(config.clone()));
+                app.manage(AppConfig(shared_config.clone()));
+                spawn_config_watcher(shared_config.clone(), log_db.clone());
+
+                // Request counters read by get_gateway_diagnostics
+                let proxy_counters = services::diagnostics::ProxyCounters::default();
+                app.manage(proxy_counters.clone());
+                app.manage(SessionWatchers::default());
+
+                // Proxy requests in flight; the frontend is told whenever their number changes
+                let active_requests = services::active_requests::ActiveRequests::default();
+                app.manage(active_requests.clone());
+
+                // Ceiling on concurrent proxy requests
+                let concurrency = services::concurrency::ConcurrencyLimiter::default();
+                if let Err(e) = services::concurrency::reload_max_concurrent_requests(&db, &concurrency).await {
+                    tracing::warn!("Failed to load concurrent request limit: {}", e);
+                }
+                app.manage(concurrency.clone());
+                let mut active_count = active_requests.subscribe();
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    use tauri::Emitter;
+                    while active_count.changed().await.is_ok() {
+                        let count = *active_count.borrow_and_update();
+                        if let Err(e) = app_handle.emit(services::active_requests::ACTIVE_REQUESTS_CHANGED_EVENT, count) {
+                            tracing::warn!("Failed to emit active request count: {}", e);
+                        }
+                    }
+                });
+
+                // CLI config files changed outside the gateway; initial state is taken once the port is known
+                let cli_sync = services::cli_sync::CliSyncState::default();
+                app.manage(cli_sync.clone());
+                spawn_cli_config_watcher(app.handle().clone(), cli_sync.clone(), gateway_port.clone());
+
                 // Start HTTP server for proxy
                 let state = api::AppState {
                     db: db.clone(),
                     log_db: log_db.clone(),
+                    http_clients: http_clients.clone(),
+                    ua_patterns,
+                    ua_rules,
+                    mask_patterns,
+                    log_exclude,
+                    cors_origins,
+                    allowed_client_ips,
+                    key_cursors: services::provider::KeyCursors::default(),
+                    schedules,
+                    session_provider_map: services::routing::SessionProviderMap::default(),
+                    port: gateway_port.clone(),
+                    listen_addr: listen_addr.clone(),
+                    config: shared_config,
+                    counters: proxy_counters,
+                    active_requests,
+                    concurrency,
+                    cli_sync: cli_sync.clone(),
                 };
 
-                let router = api::create_router(state);
-                let addr = format!("{}:{}", config.server.host, config.server.port);
+                let state = Arc::new(state);
+                let gateway_server = GatewayServer::new(api::create_router(state.clone(), None));
+                app.manage(gateway_server.clone());
+                let cli_listeners = CliListeners::new(state);
+                app.manage(cli_listeners.clone());
+                let cli_ports = commands::configured_cli_ports(&db).await;
+                let mut server_config = config.server.clone();
+                // Host/port chosen in the app (update_server_binding) win over the config file
+                let (server_host, server_port) = commands::configured_server_binding(&db).await;
+                if let Some(host) = server_host {
+                    server_config.host = host;
+                }
+                if let Some(port) = server_port {
+                    server_config.port = port;
+                }
+                // LAN mode replaces the configured host with 0.0.0.0 or the chosen interface
+                if let Some(host) = commands::external_listen_host(&db).await {
+                    server_config.host = host;
+                }
 
             let log_db_clone = log_db.clone();
             tokio::spawn(async move {
-                // Bind listener with better error handling
-                let listener = match tokio::net::TcpListener::bind(&addr).await {
-                    Ok(listener) => {
-                        tracing::info!("Gateway HTTP server listening on {}", addr);
-                        listener
-                    }
+                // Bind listener, falling back to other ports when the configured one is taken
+                let listener = match bind_listener(&server_config).await {
+                    Ok(listener) => listener,
                     Err(e) => {
-                        tracing::error!("Failed to bind to {}: {}", addr, e);
-                        panic!("Cannot bind to address {}: {}", addr, e);
+                        tracing::error!("Failed to bind gateway on {}:{}: {}", server_config.host, server_config.port, e);
+                        listen_addr.set_failed(format!("Cannot bind to {}:{}: {}", server_config.host, server_config.port, e));
+                        let _ = crate::services::stats::record_system_log(
+                            &log_db_clone,
+                            "error",
+                            "gateway_bind_failed",
+                            &format!("Cannot bind to {}:{}: {}", server_config.host, server_config.port, e),
+                            None,
+                            None,
+                        ).await;
+                        return;
                     }
                 };
+                let port = match listener.local_addr() {
+                    Ok(local_addr) => {
+                        listen_addr.set(local_addr);
+                        local_addr.port()
+                    }
+                    Err(_) => server_config.port,
+                };
+                gateway_port.0.store(port, Ordering::Relaxed);
+                let addr = listen_addr
+                    .get()
+                    .map(|a| a.to_string())
+                    .unwrap_or_else(|| format!("{}:{}", server_config.host, port));
+                tracing::info!("Gateway HTTP server listening on {}", addr);
+                if listen_addr.is_external() {
+                    tracing::warn!("Gateway is reachable from other machines; the gateway token is required");
+                }
+
+                if port != server_config.port {
+                    let _ = crate::services::stats::record_system_log(
+                        &log_db_clone,
+                        "warn",
+                        "port_changed",
+                        &format!("Port {} is in use, gateway moved to port {}", server_config.port, port),
+                        None,
+                        None,
+                    ).await;
+                }
+
+                // Dedicated per-CLI listeners share the host of the main listener
+                if !cli_ports.is_empty() {
+                    let host = listen_addr.get().map(|a| a.ip().to_string()).unwrap_or_else(|| server_config.host.clone());
+                    if let Err(e) = cli_listeners.apply(&host, &cli_ports, &gateway_port).await {
+                        tracing::error!("Failed to start dedicated CLI listeners: {}", e);
+                        let _ = crate::services::stats::record_system_log(
+                            &log_db_clone,
+                            "error",
+                            "gateway_bind_failed",
+                            &format!("Dedicated CLI listeners not started: {}", e),
+                            None,
+                            None,
+                        ).await;
+                    }
+                }
+
+                // Keep CLI configs pointing at wherever we actually ended up listening
+                let updated = commands::retarget_cli_configs(&db, &gateway_port, commands::cli_path_prefix_enabled(&db).await).await;
+                if !updated.is_empty() {
+                    let files: Vec<String> = updated.iter().map(|p| p.display().to_string()).collect();
+                    tracing::info!("Updated CLI configs to the gateway ports: {:?}", files);
+                }
+                for cli_type in services::cli_sync::CLI_TYPES {
+                    cli_sync.mark_synced(cli_type, commands::check_cli_enabled(cli_type, gateway_port.for_cli(cli_type)));
+                }
 
                 // Log gateway startup
                 let _ = crate::services::stats::record_system_log(
@@ -84,9 +740,7 @@ pub fn run() {
                     None,
                 ).await;
 
-                if let Err(e) = axum::serve(listener, router).await {
-                    tracing::error!("Gateway server error: {}", e);
-                }
+                gateway_server.serve(listener);
             });
             });
 
@@ -100,7 +754,7 @@ pub fn run() {
             // Get default app icon for tray
             let icon = app.default_window_icon().cloned().unwrap();
             
-            let _tray = TrayIconBuilder::new()
+            let tray = TrayIconBuilder::new()
                 .icon(icon)
                 .tooltip("CCG Gateway")
                 .menu(&menu)
@@ -114,7 +768,11 @@ pub fn run() {
                         }
                     }
                     "quit" => {
-                        std::process::exit(0);
+                        let app = app.clone();
+                        tauri::async_runtime::spawn(async move {
+                            stop_gateway(&app, "quit").await;
+                            app.exit(0);
+                        });
                     }
                     _ => {}
                 })
@@ -143,6 +801,22 @@ pub fn run() {
                 })
                 .build(app)?;
 
+            // Tray tooltip follows the listener state
+            let mut server_status = app.state::<ListenAddr>().subscribe();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    let tooltip = match server_status.borrow_and_update().clone() {
+                        db::models::ServerStatus::Starting => "CCG Gateway - 启动中".to_string(),
+                        db::models::ServerStatus::Running { address, .. } => format!("CCG Gateway - {}", address),
+                        db::models::ServerStatus::Failed { error } => format!("CCG Gateway - 启动失败: {}", error),
+                    };
+                    let _ = tray.set_tooltip(Some(tooltip));
+                    if server_status.changed().await.is_err() {
+                        break;
+                    }
+                }
+            });
+
             // Handle window close event - always minimize to tray
             if let Some(window) = app.get_webview_window("main") {
                 let window_clone = window.clone();
@@ -163,15 +837,68 @@ pub fn run() {
             commands::update_provider,
             commands::delete_provider,
             commands::reorder_providers,
+            commands::reorder_model_maps,
+            commands::get_provider_model_suggestions,
+            commands::get_provider_geographic_info,
+            commands::refresh_provider_geo_info,
+            commands::export_providers,
+            commands::import_providers,
+            commands::import_providers_from_openai_config,
+            commands::bulk_update_providers,
             commands::reset_provider_failures,
+            commands::simulate_provider_failure,
+            commands::simulate_provider_recovery,
+            commands::list_provider_api_keys,
+            commands::add_provider_api_key,
+            commands::remove_provider_api_key,
+            commands::get_provider_schedules,
+            commands::create_provider_schedule,
+            commands::update_provider_schedule,
+            commands::delete_provider_schedule,
+            commands::list_ua_rules,
+            commands::create_ua_rule,
+            commands::delete_ua_rule,
+            commands::get_model_aliases,
+            commands::create_model_alias,
+            commands::update_model_alias,
+            commands::delete_model_alias,
             commands::get_gateway_settings,
             commands::update_gateway_settings,
+            commands::migrate_keys_to_keychain,
+            commands::update_gateway_port,
+            commands::update_server_binding,
+            commands::get_gateway_config_as_env_file,
+            commands::apply_env_file,
+            commands::update_cli_ports,
+            commands::regenerate_gateway_token,
+            commands::get_mask_patterns,
+            commands::update_mask_patterns,
+            commands::get_log_exclude_paths,
+            commands::update_log_exclude_paths,
+            commands::get_cors_origins,
+            commands::update_cors_origins,
+            commands::get_allowed_client_ips,
+            commands::update_allowed_client_ips,
+            commands::get_max_concurrent_requests,
+            commands::update_max_concurrent_requests,
+            commands::get_active_requests,
+            commands::cancel_active_request,
             commands::get_timeout_settings,
             commands::update_timeout_settings,
             commands::get_cli_settings,
             commands::update_cli_settings,
+            commands::get_cli_sync_status,
+            commands::get_budget_settings,
+            commands::update_budget_settings,
             commands::get_request_logs,
+            commands::get_request_cost_breakdown,
             commands::get_request_log_detail,
+            commands::get_batch_items,
+            commands::get_request_log_context,
+            commands::get_request_log_by_id,
+            commands::replay_request,
+            commands::benchmark_provider,
+            commands::benchmark_concurrent,
             commands::clear_request_logs,
             commands::get_system_logs,
             commands::clear_system_logs,
@@ -183,14 +910,36 @@ pub fn run() {
             commands::delete_mcp,
             commands::get_prompts,
             commands::get_prompt,
+            commands::get_active_prompts,
             commands::create_prompt,
             commands::update_prompt,
             commands::delete_prompt,
+            commands::get_model_pricing,
+            commands::set_model_pricing,
+            commands::delete_model_pricing,
             commands::get_daily_stats,
             commands::get_provider_stats,
+            commands::get_model_usage_breakdown,
+            commands::get_uncovered_models,
+            commands::suggest_model_map,
+            commands::get_provider_error_breakdown,
+            commands::get_hourly_stats,
+            commands::reload_config,
+            commands::get_gateway_diagnostics,
+            commands::check_database_integrity,
+            commands::purge_provider_from_logs,
+            commands::validate_mcp_config,
+            commands::diff_mcp_with_filesystem,
+            commands::reconcile_mcp_from_filesystem,
+            commands::get_available_models,
             commands::get_session_projects,
             commands::get_project_sessions,
             commands::get_session_messages,
+            commands::get_session_summary,
+            commands::watch_session_file,
+            commands::stop_watching_session,
+            commands::get_sessions_search,
+            commands::compress_sessions,
             commands::delete_session,
             commands::delete_project,
             commands::get_webdav_settings,
@@ -1,14 +1,64 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tracing_subscriber::EnvFilter;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Human-readable or JSON (LOG_FORMAT=json) formatting layer for the given writer
+fn fmt_layer<S, W>(json: bool, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    if json {
+        layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed()
+    } else {
+        layer.boxed()
+    }
+}
 
 fn main() {
     // Default to info level, can be overridden by RUST_LOG env var
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("info,ccg_gateway=debug,ccg_gateway_lib=debug"));
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
+    let json = std::env::var("LOG_FORMAT")
+        .map(|v| v.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    let logging = ccg_gateway_lib::config::Config::load().logging;
+
+    // 写文件使用非阻塞 writer，guard 需存活到进程结束以刷新缓冲
+    let mut _file_guard = None;
+    let file_layer = logging.log_file.as_ref().and_then(|path| {
+        let dir = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(std::path::Path::new("."));
+        let file_name = path.file_name()?;
+        std::fs::create_dir_all(dir).ok();
+        let (writer, guard) =
+            tracing_appender::non_blocking(tracing_appender::rolling::daily(dir, file_name));
+        _file_guard = Some(guard);
+        Some(fmt_layer(json, writer, false))
+    });
+    let stdout_layer = (logging.log_stdout || file_layer.is_none())
+        .then(|| fmt_layer(json, std::io::stdout, !json));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stdout_layer)
+        .with(file_layer)
         .init();
 
     ccg_gateway_lib::run();
@@ -0,0 +1,149 @@
+//! Proxy requests in flight, listed by get_active_requests and cancellable from the app.
+//!
+//! 请求进入代理时登记，处理结束（流式响应为流结束）时由 ActiveRequestGuard 移除；
+//! 取消只是发出信号，由代理在等待上游或转发流时响应。
+
+use dashmap::DashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::db::models::ActiveRequest;
+
+/// Tauri event carrying the number of active requests whenever it changes
+pub const ACTIVE_REQUESTS_CHANGED_EVENT: &str = "active_requests_changed";
+
+/// Registry entry; provider, model and streaming are filled in once known
+pub struct ActiveRequestInfo {
+    pub cli_type: Option<String>,
+    pub provider_name: Option<String>,
+    pub model_id: Option<String>,
+    pub is_streaming: bool,
+    /// Unix timestamp (ms)
+    started_at: i64,
+    start: Instant,
+    cancel: watch::Sender<bool>,
+}
+
+#[derive(Clone)]
+pub struct ActiveRequests {
+    requests: Arc<DashMap<Uuid, ActiveRequestInfo>>,
+    count: Arc<watch::Sender<usize>>,
+}
+
+impl Default for ActiveRequests {
+    fn default() -> Self {
+        Self {
+            requests: Arc::new(DashMap::new()),
+            count: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+impl ActiveRequests {
+    /// Register a request; it stays listed until the returned guard is dropped
+    pub fn register(&self, id: Uuid) -> ActiveRequestGuard {
+        let (cancel, _) = watch::channel(false);
+        self.requests.insert(
+            id,
+            ActiveRequestInfo {
+                cli_type: None,
+                provider_name: None,
+                model_id: None,
+                is_streaming: false,
+                started_at: chrono::Utc::now().timestamp_millis(),
+                start: Instant::now(),
+                cancel,
+            },
+        );
+        self.publish_count();
+        ActiveRequestGuard {
+            requests: self.clone(),
+            id,
+        }
+    }
+
+    pub fn update(&self, id: &Uuid, f: impl FnOnce(&mut ActiveRequestInfo)) {
+        if let Some(mut info) = self.requests.get_mut(id) {
+            f(&mut info);
+        }
+    }
+
+    /// Current requests, oldest first
+    pub fn list(&self) -> Vec<ActiveRequest> {
+        let mut requests: Vec<ActiveRequest> = self
+            .requests
+            .iter()
+            .map(|entry| snapshot(entry.key(), entry.value()))
+            .collect();
+        requests.sort_by_key(|r| r.started_at);
+        requests
+    }
+
+    /// Signal the request to stop; None when it is not (or no longer) active
+    pub fn cancel(&self, id: &Uuid) -> Option<ActiveRequest> {
+        let info = self.requests.get(id)?;
+        info.cancel.send_replace(true);
+        Some(snapshot(id, &info))
+    }
+
+    /// Receiver notified whenever the number of active requests changes
+    pub fn subscribe(&self) -> watch::Receiver<usize> {
+        self.count.subscribe()
+    }
+
+    fn publish_count(&self) {
+        let count = self.requests.len();
+        self.count.send_if_modified(|current| {
+            let changed = *current != count;
+            *current = count;
+            changed
+        });
+    }
+}
+
+fn snapshot(id: &Uuid, info: &ActiveRequestInfo) -> ActiveRequest {
+    ActiveRequest {
+        request_id: id.to_string(),
+        provider_name: info.provider_name.clone(),
+        cli_type: info.cli_type.clone(),
+        model_id: info.model_id.clone(),
+        started_at: info.started_at,
+        elapsed_ms: info.start.elapsed().as_millis() as i64,
+        is_streaming: info.is_streaming,
+    }
+}
+
+/// Removes the request from the registry when dropped (handler returned, stream ended or was cancelled)
+pub struct ActiveRequestGuard {
+    requests: ActiveRequests,
+    id: Uuid,
+}
+
+impl ActiveRequestGuard {
+    pub fn is_streaming(&self) -> bool {
+        self.requests.requests.get(&self.id).is_some_and(|info| info.is_streaming)
+    }
+
+    /// Resolves once cancel_active_request is called for this request
+    pub fn cancelled(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let receiver = self.requests.requests.get(&self.id).map(|info| info.cancel.subscribe());
+        async move {
+            if let Some(mut receiver) = receiver {
+                if receiver.wait_for(|cancelled| *cancelled).await.is_ok() {
+                    return;
+                }
+            }
+            // 已结束的请求不会再被取消
+            std::future::pending().await
+        }
+    }
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        self.requests.requests.remove(&self.id);
+        self.requests.publish_count();
+    }
+}
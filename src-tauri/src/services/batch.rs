@@ -0,0 +1,121 @@
+//! Anthropic Message Batches: per-request results kept in batch_request_items.
+//!
+//! 创建/查询批次返回 `"type": "message_batch"` 对象，只记录 batch_id；
+//! `/v1/messages/batches/{id}/results` 返回 JSONL（每行一个请求结果），逐条写入 batch_request_items。
+
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+/// One request of a batch result file
+#[derive(Debug, Clone)]
+pub struct BatchResultItem {
+    pub custom_id: String,
+    /// succeeded / errored / canceled / expired
+    pub result_type: String,
+    pub status_code: Option<i64>,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+}
+
+/// Batch id in `/v1/messages/batches/{id}` or `/v1/messages/batches/{id}/results`
+pub fn batch_id_from_path(client_path: &str) -> Option<String> {
+    let path = client_path.split('?').next().unwrap_or(client_path);
+    let (_, rest) = path.split_once("/messages/batches/")?;
+    let id = rest.split('/').next().unwrap_or_default();
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+/// HTTP status matching the error type of an errored result
+fn error_status(error_type: &str) -> i64 {
+    match error_type {
+        "invalid_request_error" => 400,
+        "authentication_error" => 401,
+        "permission_error" => 403,
+        "not_found_error" => 404,
+        "request_too_large" => 413,
+        "rate_limit_error" => 429,
+        "overloaded_error" => 529,
+        _ => 500,
+    }
+}
+
+fn parse_result_item(value: &Value) -> Option<BatchResultItem> {
+    let custom_id = value.get("custom_id")?.as_str()?.to_string();
+    let result = value.get("result")?;
+    let result_type = result.get("type").and_then(Value::as_str).unwrap_or("unknown").to_string();
+    let usage = result.pointer("/message/usage");
+    let tokens = |key: &str| usage.and_then(|u| u.get(key)).and_then(Value::as_i64).unwrap_or(0);
+    let status_code = match result_type.as_str() {
+        "succeeded" => Some(200),
+        // error 可能是 {"type":"error","error":{"type":...}} 或直接 {"type":...}
+        "errored" => result
+            .pointer("/error/error/type")
+            .or_else(|| result.pointer("/error/type"))
+            .and_then(Value::as_str)
+            .map(error_status),
+        _ => None,
+    };
+    Some(BatchResultItem {
+        custom_id,
+        status_code,
+        input_tokens: tokens("input_tokens"),
+        output_tokens: tokens("output_tokens"),
+        result_type,
+    })
+}
+
+/// Batch id and per-request results of a batch response, None for other responses.
+///
+/// 结果可以是 JSONL 或 JSON 数组；结果文件本身不含 batch_id，从请求路径中取。
+pub fn parse_batch_response(body: &[u8], client_path: &str) -> Option<(String, Vec<BatchResultItem>)> {
+    let text = std::str::from_utf8(body).ok()?.trim();
+    if let Ok(value) = serde_json::from_str::<Value>(text) {
+        if value.get("type").and_then(Value::as_str) == Some("message_batch") {
+            let id = value.get("id")?.as_str()?.to_string();
+            return Some((id, Vec::new()));
+        }
+        if let Some(array) = value.as_array() {
+            let items: Vec<BatchResultItem> = array.iter().filter_map(parse_result_item).collect();
+            return (!items.is_empty()).then_some((batch_id_from_path(client_path)?, items));
+        }
+    }
+
+    let batch_id = batch_id_from_path(client_path)?;
+    let items: Vec<BatchResultItem> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|value| parse_result_item(&value))
+        .collect();
+    (!items.is_empty()).then_some((batch_id, items))
+}
+
+/// Store the results; downloading the same results again replaces the earlier rows
+pub async fn record_batch_items(log_db: &SqlitePool, batch_id: &str, items: &[BatchResultItem]) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let mut tx = log_db.begin().await?;
+    for item in items {
+        sqlx::query(
+            r#"
+            INSERT INTO batch_request_items (batch_id, custom_id, result_type, status_code, input_tokens, output_tokens, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(batch_id, custom_id) DO UPDATE SET
+                result_type = excluded.result_type,
+                status_code = excluded.status_code,
+                input_tokens = excluded.input_tokens,
+                output_tokens = excluded.output_tokens,
+                created_at = excluded.created_at
+            "#,
+        )
+        .bind(batch_id)
+        .bind(&item.custom_id)
+        .bind(&item.result_type)
+        .bind(item.status_code)
+        .bind(item.input_tokens)
+        .bind(item.output_tokens)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
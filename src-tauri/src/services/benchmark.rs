@@ -0,0 +1,58 @@
+//! Provider latency benchmarks: minimal non-streaming requests, timed one by one.
+//!
+//! 请求格式与会话摘要相同（按服务商选择 Anthropic / chat/completions / Gemini），只要求 1 个输出 token。
+
+use serde_json::json;
+
+use crate::db::models::BenchmarkResult;
+use crate::services::session_summary::SummaryApi;
+
+/// client_path prefix marking benchmark requests in the request log
+pub const BENCHMARK_PATH_PREFIX: &str = "[BENCHMARK]";
+/// Pause between sequential requests, to stay clear of provider rate limits
+pub const BENCHMARK_INTERVAL_MS: u64 = 50;
+/// Upper bound of requests in one benchmark
+pub const MAX_BENCHMARK_REQUESTS: i64 = 1000;
+/// Upper bound of parallel requests in benchmark_concurrent
+pub const MAX_BENCHMARK_CONCURRENCY: i64 = 50;
+
+/// "ping" request asking for a single output token
+pub fn ping_request_body(api: SummaryApi, model: &str) -> Vec<u8> {
+    let body = match api {
+        SummaryApi::Anthropic | SummaryApi::ChatCompletions => json!({
+            "model": model,
+            "messages": [{ "role": "user", "content": "ping" }],
+            "max_tokens": 1,
+        }),
+        SummaryApi::Gemini => json!({
+            "contents": [{ "role": "user", "parts": [{ "text": "ping" }] }],
+            "generationConfig": { "maxOutputTokens": 1 },
+        }),
+    };
+    body.to_string().into_bytes()
+}
+
+/// Nearest-rank percentile of sorted durations
+fn percentile(sorted: &[i64], p: f64) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Latency distribution of the successful requests; `samples` holds (success, elapsed_ms)
+pub fn summarize(samples: &[(bool, i64)]) -> BenchmarkResult {
+    let mut durations: Vec<i64> = samples.iter().filter(|(ok, _)| *ok).map(|(_, ms)| *ms).collect();
+    durations.sort_unstable();
+    let succeeded = durations.len();
+    let requests_sent = samples.len() as i64;
+    BenchmarkResult {
+        min_ms: durations.first().copied().unwrap_or(0),
+        max_ms: durations.last().copied().unwrap_or(0),
+        mean_ms: if succeeded == 0 { 0.0 } else { durations.iter().sum::<i64>() as f64 / succeeded as f64 },
+        p95_ms: percentile(&durations, 95.0),
+        success_rate: if requests_sent == 0 { 0.0 } else { succeeded as f64 / requests_sent as f64 },
+        requests_sent,
+    }
+}
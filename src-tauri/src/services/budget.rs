@@ -0,0 +1,288 @@
+//! Daily token budgets (budget_settings).
+//!
+//! 限额按 CLI 类型配置，分别作用于该类型下的每个服务商：统计 usage_daily 中当天
+//! (provider_name, cli_type) 的累计 token。日期与 usage_daily 一致使用 UTC。
+
+use sqlx::SqlitePool;
+
+use crate::db::models::{BudgetSettings, BudgetStatus};
+use crate::services::proxy::TokenUsage;
+use crate::services::stats::record_system_log;
+
+/// Budget settings of a CLI type (seeded for every CLI type at startup)
+pub async fn load_budget_settings(db: &SqlitePool, cli_type: &str) -> Result<Option<BudgetSettings>, sqlx::Error> {
+    sqlx::query_as::<_, BudgetSettings>(
+        "SELECT cli_type, daily_input_token_limit, daily_output_token_limit, warning_threshold_pct, block_when_exceeded FROM budget_settings WHERE cli_type = ?",
+    )
+    .bind(cli_type)
+    .fetch_optional(db)
+    .await
+}
+
+/// Positive limit, or None when unlimited
+fn limit(value: Option<i64>) -> Option<i64> {
+    value.filter(|v| *v > 0)
+}
+
+fn warning_level(limit: i64, threshold_pct: i64) -> i64 {
+    limit * threshold_pct.clamp(1, 100) / 100
+}
+
+/// "ok", "warning" or "exceeded" for `used` tokens against `limit`
+fn budget_state(used: i64, limit: Option<i64>, threshold_pct: i64) -> &'static str {
+    match limit {
+        Some(limit) if used >= limit => "exceeded",
+        Some(limit) if used >= warning_level(limit, threshold_pct) => "warning",
+        _ => "ok",
+    }
+}
+
+/// Start of the next UTC day, when today's usage_daily row stops counting
+fn next_utc_midnight() -> i64 {
+    let tomorrow = chrono::Utc::now().date_naive() + chrono::Days::new(1);
+    tomorrow.and_hms_opt(0, 0, 0).map(|t| t.and_utc().timestamp()).unwrap_or_default()
+}
+
+/// Today's (input, output) tokens of a provider from usage_daily
+async fn today_usage(log_db: &SqlitePool, provider_name: &str, cli_type: &str) -> Result<(i64, i64), sqlx::Error> {
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    let row = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT input_tokens, output_tokens FROM usage_daily WHERE usage_date = ? AND provider_name = ? AND cli_type = ?",
+    )
+    .bind(&today)
+    .bind(provider_name)
+    .bind(cli_type)
+    .fetch_optional(log_db)
+    .await?;
+    Ok(row.unwrap_or((0, 0)))
+}
+
+/// Compare today's usage with the budget after a request was added to usage_daily.
+///
+/// 只在本次请求跨过阈值时记录日志，同一天内每个阈值只提醒一次；拉黑则只看当前
+/// 是否超额，之后的请求（或中途开启拉黑）同样会拉黑。
+pub async fn check_token_budget(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    provider_name: &str,
+    cli_type: &str,
+    usage: &TokenUsage,
+) -> Result<(), sqlx::Error> {
+    let Some(settings) = load_budget_settings(db, cli_type).await? else {
+        return Ok(());
+    };
+    let input_limit = limit(settings.daily_input_token_limit);
+    let output_limit = limit(settings.daily_output_token_limit);
+    if input_limit.is_none() && output_limit.is_none() {
+        return Ok(());
+    }
+
+    let (input_used, output_used) = today_usage(log_db, provider_name, cli_type).await?;
+    let checks = [
+        ("input", input_used, usage.input_tokens, input_limit),
+        ("output", output_used, usage.output_tokens, output_limit),
+    ];
+
+    let mut exceeded = false;
+    for (kind, used, added, limit) in checks {
+        let Some(limit) = limit else {
+            continue;
+        };
+        let before = budget_state(used - added, Some(limit), settings.warning_threshold_pct);
+        let after = budget_state(used, Some(limit), settings.warning_threshold_pct);
+        exceeded |= after == "exceeded";
+        if before == after {
+            continue;
+        }
+        match after {
+            "exceeded" => {
+                let _ = record_system_log(
+                    log_db,
+                    "error",
+                    "token_budget_exceeded",
+                    &format!("{} used {} of {} daily {} tokens ({})", provider_name, used, limit, kind, cli_type),
+                    Some(provider_name),
+                    None,
+                )
+                .await;
+            }
+            "warning" => {
+                let _ = record_system_log(
+                    log_db,
+                    "warn",
+                    "token_budget_warning",
+                    &format!(
+                        "{} used {} of {} daily {} tokens ({}, {}% threshold)",
+                        provider_name, used, limit, kind, cli_type, settings.warning_threshold_pct
+                    ),
+                    Some(provider_name),
+                    None,
+                )
+                .await;
+            }
+            _ => {}
+        }
+    }
+
+    if exceeded && settings.block_when_exceeded != 0 {
+        set_budget_block(db, provider_name, cli_type, Some(next_utc_midnight())).await?;
+    }
+    Ok(())
+}
+
+async fn set_budget_block(db: &SqlitePool, provider_name: &str, cli_type: &str, until: Option<i64>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE providers SET budget_exceeded_until = ? WHERE name = ? AND cli_type = ?")
+        .bind(until)
+        .bind(provider_name)
+        .bind(cli_type)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Re-evaluate the budget blocks of a CLI type after its settings were saved: providers
+/// still over the new limits stay (or become) blocked, the others are released.
+pub async fn reconcile_budget_blocks(db: &SqlitePool, log_db: &SqlitePool, cli_type: &str) -> Result<(), sqlx::Error> {
+    let settings = load_budget_settings(db, cli_type).await?;
+    let providers: Vec<String> = sqlx::query_scalar("SELECT name FROM providers WHERE cli_type = ?")
+        .bind(cli_type)
+        .fetch_all(db)
+        .await?;
+    for provider_name in providers {
+        let blocked = match &settings {
+            Some(settings) if settings.block_when_exceeded != 0 => {
+                let (input_used, output_used) = today_usage(log_db, &provider_name, cli_type).await?;
+                [
+                    (input_used, limit(settings.daily_input_token_limit)),
+                    (output_used, limit(settings.daily_output_token_limit)),
+                ]
+                .into_iter()
+                .any(|(used, limit)| budget_state(used, limit, settings.warning_threshold_pct) == "exceeded")
+            }
+            _ => false,
+        };
+        set_budget_block(db, &provider_name, cli_type, blocked.then(next_utc_midnight)).await?;
+    }
+    Ok(())
+}
+
+/// Today's usage of every provider whose CLI type has a budget
+pub async fn budget_statuses(db: &SqlitePool, log_db: &SqlitePool) -> Result<Vec<BudgetStatus>, sqlx::Error> {
+    let settings = sqlx::query_as::<_, BudgetSettings>(
+        "SELECT cli_type, daily_input_token_limit, daily_output_token_limit, warning_threshold_pct, block_when_exceeded FROM budget_settings ORDER BY cli_type",
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut statuses = Vec::new();
+    for budget in settings {
+        let input_limit = limit(budget.daily_input_token_limit);
+        let output_limit = limit(budget.daily_output_token_limit);
+        if input_limit.is_none() && output_limit.is_none() {
+            continue;
+        }
+        let providers: Vec<String> = sqlx::query_scalar("SELECT name FROM providers WHERE cli_type = ? ORDER BY sort_order, id")
+            .bind(&budget.cli_type)
+            .fetch_all(db)
+            .await?;
+        for provider_name in providers {
+            let (input_tokens, output_tokens) = today_usage(log_db, &provider_name, &budget.cli_type).await?;
+            let states = [
+                budget_state(input_tokens, input_limit, budget.warning_threshold_pct),
+                budget_state(output_tokens, output_limit, budget.warning_threshold_pct),
+            ];
+            let state = ["exceeded", "warning"]
+                .into_iter()
+                .find(|s| states.contains(s))
+                .unwrap_or("ok");
+            statuses.push(BudgetStatus {
+                cli_type: budget.cli_type.clone(),
+                provider_name,
+                input_tokens,
+                output_tokens,
+                daily_input_token_limit: input_limit,
+                daily_output_token_limit: output_limit,
+                state: state.to_string(),
+            });
+        }
+    }
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn set_budget(db: &SqlitePool, input_limit: i64, block: bool) {
+        sqlx::query(
+            "INSERT INTO budget_settings (cli_type, daily_input_token_limit, warning_threshold_pct, block_when_exceeded, updated_at) VALUES ('claude_code', ?, 80, ?, 0)
+             ON CONFLICT(cli_type) DO UPDATE SET daily_input_token_limit = excluded.daily_input_token_limit, block_when_exceeded = excluded.block_when_exceeded",
+        )
+        .bind(input_limit)
+        .bind(block as i64)
+        .execute(db)
+        .await
+        .unwrap();
+    }
+
+    async fn blocked(db: &SqlitePool) -> bool {
+        sqlx::query_scalar::<_, Option<i64>>("SELECT budget_exceeded_until FROM providers WHERE name = 'p'")
+            .fetch_one(db)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn providers_over_budget_stay_blocked() {
+        let dir = std::env::temp_dir().join(format!("ccg-budget-{}", uuid::Uuid::new_v4().simple()));
+        let (db, _) = crate::db::init_db(&dir.join("ccg_gateway.db")).await.unwrap();
+        let (log_db, _) = crate::db::init_db(&dir.join("ccg_logs.db")).await.unwrap();
+        let input: crate::db::models::ProviderCreate = serde_json::from_value(serde_json::json!({
+            "cli_type": "claude_code",
+            "name": "p",
+            "base_url": "http://127.0.0.1:1",
+            "api_key": "sk-test",
+        }))
+        .unwrap();
+        let ua_patterns = crate::services::proxy::UaPatternCache::default();
+        crate::commands::insert_provider(&db, &log_db, &ua_patterns, input).await.unwrap();
+        let request = |tokens: i64| {
+            let (db, log_db) = (db.clone(), log_db.clone());
+            async move {
+                let usage = TokenUsage { input_tokens: tokens, ..Default::default() };
+                crate::services::stats::record_request(&db, &log_db, "p", "claude_code", None, true, &usage).await.unwrap();
+                check_token_budget(&db, &log_db, "p", "claude_code", &usage).await.unwrap();
+            }
+        };
+
+        // 跨过限额时尚未开启拉黑，之后开启：下一次请求即拉黑
+        set_budget(&db, 100, false).await;
+        request(150).await;
+        assert!(!blocked(&db).await);
+        set_budget(&db, 100, true).await;
+        request(10).await;
+        assert!(blocked(&db).await);
+
+        // 保存设置只释放已低于新限额的服务商
+        set_budget(&db, 120, true).await;
+        reconcile_budget_blocks(&db, &log_db, "claude_code").await.unwrap();
+        assert!(blocked(&db).await);
+        set_budget(&db, 1000, true).await;
+        reconcile_budget_blocks(&db, &log_db, "claude_code").await.unwrap();
+        assert!(!blocked(&db).await);
+        set_budget(&db, 100, true).await;
+        reconcile_budget_blocks(&db, &log_db, "claude_code").await.unwrap();
+        assert!(blocked(&db).await);
+
+        let exceeded_logs: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM system_logs WHERE event_type = 'token_budget_exceeded'")
+                .fetch_one(&log_db)
+                .await
+                .unwrap();
+        assert_eq!(exceeded_logs, 1);
+        db.close().await;
+        log_db.close().await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
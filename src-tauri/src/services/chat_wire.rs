@@ -0,0 +1,42 @@
+//! Helpers shared by the chat/completions translators (translate.rs, responses_chat.rs).
+
+use serde_json::Value;
+
+/// Replace the trailing `endpoint` of `path` with `/chat/completions`, keeping the query:
+/// `/v1/messages?beta=true` -> `/v1/chat/completions?beta=true`
+pub fn chat_completions_path(path: &str, endpoint: &str) -> String {
+    let (path, query) = match path.split_once('?') {
+        Some((p, q)) => (p, Some(q)),
+        None => (path, None),
+    };
+    let base = path.strip_suffix(endpoint).unwrap_or(path);
+    match query {
+        Some(q) => format!("{}/chat/completions?{}", base, q),
+        None => format!("{}/chat/completions", base),
+    }
+}
+
+/// String value of `key`, None when missing or not a string
+pub fn str_field<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(|v| v.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_is_replaced_and_query_kept() {
+        assert_eq!(chat_completions_path("/v1/messages?beta=true", "/messages"), "/v1/chat/completions?beta=true");
+        assert_eq!(chat_completions_path("/v1/responses", "/responses"), "/v1/chat/completions");
+        assert_eq!(chat_completions_path("/openai", "/responses"), "/openai/chat/completions");
+    }
+
+    #[test]
+    fn str_field_ignores_non_strings() {
+        let value = serde_json::json!({"a": "x", "b": 1});
+        assert_eq!(str_field(&value, "a"), Some("x"));
+        assert_eq!(str_field(&value, "b"), None);
+        assert_eq!(str_field(&value, "c"), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! Whether the CLI config files still match what the gateway last wrote.
+//!
+//! Claude Code、手动编辑等外部程序可能覆盖 CLI 配置文件；文件被修改后重新判断是否仍接入网关，
+//! 与网关最后一次写入的状态不一致时标记为 out_of_sync。
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use serde::Serialize;
+
+/// CLI types whose config files the gateway manages
+pub const CLI_TYPES: [&str; 3] = ["claude_code", "codex", "gemini"];
+
+/// Tauri event emitted when a CLI config file changes on disk
+pub const CLI_CONFIG_MODIFIED_EVENT: &str = "cli_config_externally_modified";
+
+/// Payload of `CLI_CONFIG_MODIFIED_EVENT` and item of get_cli_sync_status
+#[derive(Debug, Clone, Serialize)]
+pub struct CliSyncStatus {
+    pub cli_type: String,
+    /// The config file currently points at the gateway
+    pub is_gateway_enabled: bool,
+    /// The file no longer matches the state the gateway last wrote
+    pub out_of_sync: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CliSyncEntry {
+    /// State written by the gateway (or found at startup)
+    expected: bool,
+    /// State found in the file on the last check
+    actual: bool,
+}
+
+/// Per-CLI sync state shared by the watcher, the Tauri commands and the proxy
+#[derive(Clone, Default)]
+pub struct CliSyncState(Arc<DashMap<String, CliSyncEntry>>);
+
+impl CliSyncState {
+    /// Record the state the gateway just wrote (or found at startup), clearing out_of_sync
+    pub fn mark_synced(&self, cli_type: &str, enabled: bool) {
+        self.0.insert(
+            cli_type.to_string(),
+            CliSyncEntry {
+                expected: enabled,
+                actual: enabled,
+            },
+        );
+    }
+
+    /// Record the state found in the file after a change on disk
+    pub fn observe(&self, cli_type: &str, enabled: bool) -> CliSyncStatus {
+        let mut entry = self.0.entry(cli_type.to_string()).or_insert(CliSyncEntry {
+            expected: enabled,
+            actual: enabled,
+        });
+        entry.actual = enabled;
+        CliSyncStatus {
+            cli_type: cli_type.to_string(),
+            is_gateway_enabled: enabled,
+            out_of_sync: entry.expected != enabled,
+        }
+    }
+
+    pub fn status(&self, cli_type: &str) -> CliSyncStatus {
+        let entry = self.0.get(cli_type).map(|e| *e);
+        CliSyncStatus {
+            cli_type: cli_type.to_string(),
+            is_gateway_enabled: entry.is_some_and(|e| e.actual),
+            out_of_sync: entry.is_some_and(|e| e.expected != e.actual),
+        }
+    }
+}
+
+/// Files read by check_cli_enabled for `cli_type`
+pub fn cli_config_paths(cli_type: &str) -> Vec<PathBuf> {
+    let Some(home) = dirs::home_dir() else {
+        return Vec::new();
+    };
+    match cli_type {
+        "claude_code" => vec![home.join(".claude").join("settings.json")],
+        "codex" => vec![home.join(".codex").join("config.toml")],
+        // 网关地址写在 .env 中，settings.json 保存其余配置
+        "gemini" => vec![
+            home.join(".gemini").join("settings.json"),
+            home.join(".gemini").join(".env"),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// CLI type owning the config file at `path`
+pub fn cli_type_for_path(path: &std::path::Path) -> Option<&'static str> {
+    CLI_TYPES
+        .into_iter()
+        .find(|cli_type| cli_config_paths(cli_type).iter().any(|p| p == path))
+}
@@ -0,0 +1,124 @@
+//! Global ceiling on concurrent proxy requests (gateway_settings.max_concurrent_requests).
+//!
+//! 超过上限的请求立即返回 503 + Retry-After，而不是排队等待：CLI 会自行重试，
+//! 排队只会让上游请求与 SQLite 写入继续堆积。流式响应占用名额直到流结束。
+
+use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Seconds a rejected client is asked to wait before retrying
+pub const RETRY_AFTER_SECS: u32 = 1;
+
+/// Upper bound accepted by update_max_concurrent_requests
+pub const MAX_CONCURRENT_REQUESTS_LIMIT: u32 = 10_000;
+
+#[derive(Clone, Default)]
+pub struct ConcurrencyLimiter {
+    /// 0 = unlimited
+    limit: Arc<AtomicU32>,
+    in_flight: Arc<AtomicU32>,
+}
+
+impl ConcurrencyLimiter {
+    /// Take a slot; None when the limit is reached. The slot is freed when the permit is dropped
+    pub fn try_acquire(&self) -> Option<ConcurrencyPermit> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (limit == 0 || n < limit).then_some(n + 1))
+            .ok()?;
+        Some(ConcurrencyPermit(self.in_flight.clone()))
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Requests holding a slot, streaming responses included
+    pub fn in_flight(&self) -> u32 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Lowering the limit does not interrupt requests already running
+    pub fn set_limit(&self, limit: u32) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+}
+
+pub struct ConcurrencyPermit(Arc<AtomicU32>);
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Read the limit from gateway_settings (0 = unlimited)
+pub async fn load_max_concurrent_requests(db: &SqlitePool) -> Result<u32, sqlx::Error> {
+    let limit = sqlx::query_scalar::<_, i64>("SELECT max_concurrent_requests FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await?
+        .unwrap_or(0);
+    Ok(limit.clamp(0, MAX_CONCURRENT_REQUESTS_LIMIT as i64) as u32)
+}
+
+/// Apply the limit stored in the database
+pub async fn reload_max_concurrent_requests(db: &SqlitePool, limiter: &ConcurrencyLimiter) -> Result<(), sqlx::Error> {
+    limiter.set_limit(load_max_concurrent_requests(db).await?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_capped_and_released_on_drop() {
+        let limiter = ConcurrencyLimiter::default();
+        limiter.set_limit(2);
+        let first = limiter.try_acquire().unwrap();
+        let second = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.in_flight(), 2);
+
+        drop(first);
+        let third = limiter.try_acquire().unwrap();
+        drop((second, third));
+        assert_eq!(limiter.in_flight(), 0);
+    }
+
+    #[test]
+    fn zero_is_unlimited_and_lowering_keeps_running_requests() {
+        let limiter = ConcurrencyLimiter::default();
+        let permits: Vec<_> = (0..100).map(|_| limiter.try_acquire().unwrap()).collect();
+        limiter.set_limit(10);
+        assert_eq!(limiter.in_flight(), 100);
+        assert!(limiter.try_acquire().is_none());
+        drop(permits);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn concurrent_acquires_never_exceed_the_limit() {
+        let limiter = ConcurrencyLimiter::default();
+        limiter.set_limit(3);
+        let peak = Arc::new(AtomicU32::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (limiter, peak) = (limiter.clone(), peak.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if let Some(_permit) = limiter.try_acquire() {
+                            peak.fetch_max(limiter.in_flight(), Ordering::Relaxed);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(peak.load(Ordering::Relaxed) <= 3);
+        assert_eq!(limiter.in_flight(), 0);
+    }
+}
@@ -0,0 +1,76 @@
+//! CORS for the management API (`/api`): only origins in gateway_settings.cors_allowed_origins.
+//!
+//! 代理路由不启用 CORS：CLI 不是浏览器，不需要跨域头；OPTIONS 请求由代理自行应答。
+//! 允许列表保存后立即生效（CorsLayer 每次请求读取缓存），无需重启监听。
+
+use axum::http::HeaderValue;
+use sqlx::SqlitePool;
+use std::sync::{Arc, RwLock};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+/// Allowed origins, replaced when the settings are saved
+pub type CorsOriginCache = Arc<RwLock<Vec<HeaderValue>>>;
+
+/// Normalize an origin to `scheme://host[:port]`; paths, wildcards and non-HTTP schemes are rejected
+fn normalize_origin(origin: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid CORS origin '{}': expected scheme://host[:port]", origin);
+    let trimmed = origin.trim().trim_end_matches('/');
+    let url = reqwest::Url::parse(trimmed).map_err(|_| invalid())?;
+    if !matches!(url.scheme(), "http" | "https")
+        || url.host_str().is_none()
+        || url.path() != "/"
+        || url.query().is_some()
+        || url.fragment().is_some()
+        || !url.username().is_empty()
+    {
+        return Err(invalid());
+    }
+    Ok(url.origin().ascii_serialization())
+}
+
+/// Normalize every origin; empty or malformed entries are errors, duplicates are dropped
+pub fn normalize_cors_origins(origins: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for origin in origins {
+        if origin.trim().is_empty() {
+            return Err("CORS origin cannot be empty".to_string());
+        }
+        let origin = normalize_origin(origin)?;
+        if !normalized.contains(&origin) {
+            normalized.push(origin);
+        }
+    }
+    Ok(normalized)
+}
+
+/// Read the allowlist (JSON array) from gateway_settings
+pub async fn load_cors_origins(db: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let raw = sqlx::query_scalar::<_, String>("SELECT cors_allowed_origins FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await?
+        .unwrap_or_default();
+    Ok(serde_json::from_str(&raw).unwrap_or_default())
+}
+
+/// Refresh the cached allowlist from the database
+pub async fn reload_cors_origins(db: &SqlitePool, cache: &CorsOriginCache) -> Result<(), sqlx::Error> {
+    let origins = load_cors_origins(db)
+        .await?
+        .iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect();
+    *cache.write().unwrap_or_else(|e| e.into_inner()) = origins;
+    Ok(())
+}
+
+/// CORS layer for `/api`; with an empty allowlist no CORS headers are sent.
+///
+/// 请求头按预检请求回显：`*` 不包含 Authorization，网关令牌需要显式放行。
+pub fn api_cors_layer(cache: CorsOriginCache) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            cache.read().unwrap_or_else(|e| e.into_inner()).contains(origin)
+        }))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+}
@@ -0,0 +1,88 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Live proxy counters shared by the HTTP server and the diagnostics command
+#[derive(Clone, Default)]
+pub struct ProxyCounters {
+    /// Requests currently inside the proxy handler
+    pub requests_in_flight: Arc<AtomicU32>,
+    /// Streaming responses and WebSocket sessions still open after the handler returned
+    pub active_connections: Arc<AtomicU32>,
+    /// Proxy requests received since startup
+    pub total_requests: Arc<AtomicU64>,
+    /// Stream log rows still being written after the response ended
+    pub pending_log_writes: Arc<AtomicU32>,
+}
+
+impl ProxyCounters {
+    /// Requests, open streams and log writes that shutdown waits for
+    pub fn busy(&self) -> u32 {
+        self.requests_in_flight.load(Ordering::Relaxed)
+            + self.active_connections.load(Ordering::Relaxed)
+            + self.pending_log_writes.load(Ordering::Relaxed)
+    }
+}
+
+/// Increments a gauge when created and decrements it when dropped,
+/// so early returns and cancelled streams are counted correctly.
+pub struct GaugeGuard(Arc<AtomicU32>);
+
+impl GaugeGuard {
+    pub fn new(gauge: &Arc<AtomicU32>) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Self(gauge.clone())
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Size of a SQLite database including its WAL file (0 when missing)
+pub fn db_size_bytes(path: &Path) -> u64 {
+    let size = |p: &Path| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    size(path) + size(Path::new(&wal))
+}
+
+/// Resident set size of this process in KB
+#[cfg(target_os = "linux")]
+pub fn memory_rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+/// Resident set size of this process in KB
+#[cfg(target_os = "macos")]
+pub fn memory_rss_kb() -> Option<u64> {
+    let mut info = std::mem::MaybeUninit::<libc::mach_task_basic_info>::uninit();
+    let mut count = libc::MACH_TASK_BASIC_INFO_COUNT;
+    // SAFETY: task_info writes at most `count` words into `info` and reports success via the return code
+    let kr = unsafe {
+        libc::task_info(
+            libc::mach_task_self_,
+            libc::MACH_TASK_BASIC_INFO,
+            info.as_mut_ptr() as libc::task_info_t,
+            &mut count,
+        )
+    };
+    if kr != libc::KERN_SUCCESS {
+        return None;
+    }
+    // SAFETY: initialized by the successful task_info call above
+    let info = unsafe { info.assume_init() };
+    Some(info.resident_size / 1024)
+}
+
+/// Resident set size of this process in KB (not available on this platform)
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn memory_rss_kb() -> Option<u64> {
+    None
+}
@@ -0,0 +1,84 @@
+//! Gateway settings as a bash-compatible env file (`KEY=value` per line).
+//!
+//! 导出包含端口、已启用服务商（密钥脱敏）与 CLI 接入状态；导入只应用端口与 CLI 开关，
+//! 服务商相关的键仅供查看，不会写回。
+
+pub const GATEWAY_PORT_KEY: &str = "GATEWAY_PORT";
+pub const ENABLED_PROVIDERS_KEY: &str = "GATEWAY_ENABLED_PROVIDERS";
+
+/// `CLI_CLAUDE_CODE_ENABLED` / `CLI_CODEX_ENABLED` / `CLI_GEMINI_ENABLED`
+pub fn cli_enabled_key(cli_type: &str) -> String {
+    format!("CLI_{}_ENABLED", cli_type.to_uppercase())
+}
+
+/// Per-provider key, e.g. `GATEWAY_PROVIDER_1_URL`
+pub fn provider_key(index: usize, field: &str) -> String {
+    format!("GATEWAY_PROVIDER_{}_{}", index, field)
+}
+
+/// Quote a value for bash; plain values are written as is
+pub fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:,@%+=".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+fn unquote(value: &str) -> Result<String, String> {
+    if let Some(inner) = value.strip_prefix('\'') {
+        // shell_quote 写出的 'it'\''s' 形式
+        let inner = inner.strip_suffix('\'').ok_or("unterminated single quote")?;
+        return Ok(inner.replace(r"'\''", "'"));
+    }
+    if let Some(inner) = value.strip_prefix('"') {
+        let inner = inner.strip_suffix('"').ok_or("unterminated double quote")?;
+        return Ok(inner.replace("\\\"", "\"").replace("\\\\", "\\"));
+    }
+    // Unquoted values end at an inline comment
+    let value = match value.find(" #") {
+        Some(pos) => &value[..pos],
+        None => value,
+    };
+    Ok(value.trim_end().to_string())
+}
+
+/// `KEY=value` entries in file order; blank lines and comments are ignored,
+/// an `export ` prefix is allowed. Malformed lines are returned as errors.
+pub fn parse_env_file(content: &str) -> Vec<Result<(String, String), String>> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+            let entry = match line.split_once('=') {
+                Some((key, value))
+                    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+                {
+                    unquote(value.trim())
+                        .map(|value| (key.to_string(), value))
+                        .map_err(|e| format!("line {}: {}", index + 1, e))
+                }
+                _ => Err(format!("line {}: expected KEY=value", index + 1)),
+            };
+            Some(entry)
+        })
+        .collect()
+}
+
+/// true/false, 1/0, yes/no, on/off
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,103 @@
+//! Provider location from GeoLite2 databases (country/city and ASN).
+//!
+//! 数据库随应用打包在 resources/geoip 下（可为 .mmdb.gz 压缩文件）；数据目录 geoip/ 下的同名文件优先，
+//! 便于替换为更新的版本。数据库首次查询时加载，之后常驻内存。
+
+use std::io::Read;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use maxminddb::{geoip2, Reader};
+
+const CITY_DB: &str = "GeoLite2-City.mmdb";
+const ASN_DB: &str = "GeoLite2-ASN.mmdb";
+
+pub struct GeoDatabases {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+/// Location of one address; fields are None when the databases have no record
+#[derive(Debug, Clone, Default)]
+pub struct GeoLocation {
+    pub country_code: Option<String>,
+    pub city: Option<String>,
+    pub asn_name: Option<String>,
+}
+
+static DATABASES: Mutex<Option<Arc<GeoDatabases>>> = Mutex::new(None);
+
+/// Read `<dir>/<name>` or its gzip copy `<dir>/<name>.gz`
+fn open_database(dir: &Path, name: &str) -> Option<Reader<Vec<u8>>> {
+    let plain = dir.join(name);
+    let data = if plain.exists() {
+        std::fs::read(&plain).ok()?
+    } else {
+        let file = std::fs::File::open(dir.join(format!("{}.gz", name))).ok()?;
+        let mut data = Vec::new();
+        flate2::read::GzDecoder::new(file).read_to_end(&mut data).ok()?;
+        data
+    };
+    match Reader::from_source(data) {
+        Ok(reader) => Some(reader),
+        Err(e) => {
+            tracing::warn!("Invalid GeoIP database {} in {}: {}", name, dir.display(), e);
+            None
+        }
+    }
+}
+
+fn open_first(dirs: &[PathBuf], name: &str) -> Option<Reader<Vec<u8>>> {
+    dirs.iter().find_map(|dir| open_database(dir, name))
+}
+
+/// Databases found in `dirs` (searched in order); loaded once, retried while none is found
+pub fn databases(dirs: &[PathBuf]) -> Result<Arc<GeoDatabases>, String> {
+    let mut cached = DATABASES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(databases) = cached.as_ref() {
+        return Ok(databases.clone());
+    }
+    let databases = GeoDatabases {
+        city: open_first(dirs, CITY_DB),
+        asn: open_first(dirs, ASN_DB),
+    };
+    if databases.city.is_none() && databases.asn.is_none() {
+        return Err(format!("No GeoLite2 database found ({} / {})", CITY_DB, ASN_DB));
+    }
+    let databases = Arc::new(databases);
+    *cached = Some(databases.clone());
+    Ok(databases)
+}
+
+impl GeoDatabases {
+    pub fn lookup(&self, ip: IpAddr) -> GeoLocation {
+        let mut location = GeoLocation::default();
+        if let Some(record) = self.city.as_ref().and_then(|r| r.lookup::<geoip2::City>(ip).ok()) {
+            location.country_code = record.country.and_then(|c| c.iso_code).map(str::to_string);
+            location.city = record
+                .city
+                .and_then(|c| c.names)
+                .and_then(|names| names.get("en").copied())
+                .map(str::to_string);
+        }
+        if let Some(record) = self.asn.as_ref().and_then(|r| r.lookup::<geoip2::Asn>(ip).ok()) {
+            location.asn_name = record.autonomous_system_organization.map(str::to_string);
+        }
+        location
+    }
+}
+
+/// First address `host` resolves to (IP literals are returned as is)
+pub async fn resolve_host(host: &str) -> Result<IpAddr, String> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+    tokio::net::lookup_host((host, 443))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or_else(|| format!("{} did not resolve to any address", host))
+}
@@ -0,0 +1,213 @@
+use dashmap::{DashMap, DashSet};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::services::masking::mask_url_credentials;
+
+/// 连接池中空闲连接的保留时间
+const POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+/// 每个上游主机保留的最大空闲连接数
+const POOL_MAX_IDLE_PER_HOST: usize = 32;
+/// TCP keepalive 间隔
+const TCP_KEEPALIVE_SECS: u64 = 60;
+
+/// Supported outbound proxy schemes
+const PROXY_SCHEMES: &[&str] = &["http", "https", "socks5", "socks5h"];
+
+/// Per-provider client options. Providers with identical options share one client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ClientOptions {
+    pub proxy_url: Option<String>,
+    pub insecure_skip_tls_verify: bool,
+    /// Idle connections kept per upstream host (None = POOL_MAX_IDLE_PER_HOST)
+    pub max_connections: Option<usize>,
+    pub connect_timeout_secs: Option<u64>,
+}
+
+impl ClientOptions {
+    pub fn from_provider(provider: &crate::db::models::Provider) -> Self {
+        Self {
+            proxy_url: normalize_proxy_url(provider.proxy_url.as_deref()),
+            insecure_skip_tls_verify: provider.insecure_skip_tls_verify != 0,
+            max_connections: provider
+                .max_connections
+                .filter(|n| *n > 0)
+                .and_then(|n| usize::try_from(n).ok()),
+            connect_timeout_secs: provider
+                .connection_timeout_secs
+                .filter(|secs| *secs > 0)
+                .and_then(|secs| u64::try_from(secs).ok()),
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Build the shared HTTP client used by the proxy and WebDAV commands.
+///
+/// reqwest::Client 内部是 Arc，clone 成本很低；复用同一个实例才能命中连接池，
+/// 避免每个请求都重新握手 TLS。
+pub fn build_shared_client() -> reqwest::Client {
+    build_client(&ClientOptions::default()).unwrap_or_else(|e| {
+        tracing::warn!("Failed to build pooled HTTP client, falling back to default: {}", e);
+        reqwest::Client::new()
+    })
+}
+
+fn build_client(options: &ClientOptions) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(POOL_IDLE_TIMEOUT_SECS))
+        .pool_max_idle_per_host(options.max_connections.unwrap_or(POOL_MAX_IDLE_PER_HOST))
+        .tcp_keepalive(Duration::from_secs(TCP_KEEPALIVE_SECS))
+        .http2_adaptive_window(true)
+        .http2_keep_alive_interval(Duration::from_secs(TCP_KEEPALIVE_SECS))
+        .http2_keep_alive_while_idle(true);
+
+    apply_options(builder, options)?.build().map_err(|e| e.to_string())
+}
+
+/// Client for WebSocket upgrades: the handshake only works over HTTP/1.1,
+/// and upgraded connections never go back to the pool.
+fn build_upgrade_client(options: &ClientOptions) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .http1_only()
+        .pool_max_idle_per_host(0)
+        .tcp_keepalive(Duration::from_secs(TCP_KEEPALIVE_SECS));
+
+    apply_options(builder, options)?.build().map_err(|e| e.to_string())
+}
+
+fn apply_options(
+    mut builder: reqwest::ClientBuilder,
+    options: &ClientOptions,
+) -> Result<reqwest::ClientBuilder, String> {
+    if let Some(ref proxy_url) = options.proxy_url {
+        let proxy = reqwest::Proxy::all(proxy_url)
+            .map_err(|e| format!("Invalid proxy URL '{}': {}", mask_url_credentials(proxy_url), e))?;
+        builder = builder.proxy(proxy);
+    }
+
+    if options.insecure_skip_tls_verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if let Some(secs) = options.connect_timeout_secs {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+
+    Ok(builder)
+}
+
+/// Treat empty / whitespace-only proxy URLs as "no proxy"
+pub fn normalize_proxy_url(proxy_url: Option<&str>) -> Option<String> {
+    proxy_url
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+/// Validate a proxy URL before saving it to the database
+pub fn validate_proxy_url(proxy_url: &str) -> Result<(), String> {
+    let scheme = proxy_url
+        .split_once("://")
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+        .ok_or_else(|| format!("Invalid proxy URL '{}': missing scheme (e.g. socks5://127.0.0.1:1080)", mask_url_credentials(proxy_url)))?;
+
+    if !PROXY_SCHEMES.contains(&scheme.as_str()) {
+        return Err(format!(
+            "Invalid proxy URL '{}': unsupported scheme '{}', expected one of {}",
+            mask_url_credentials(proxy_url),
+            scheme,
+            PROXY_SCHEMES.join(", ")
+        ));
+    }
+
+    reqwest::Proxy::all(proxy_url)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid proxy URL '{}': {}", mask_url_credentials(proxy_url), e))
+}
+
+/// Shared default client plus lazily-built clients for providers with custom options
+#[derive(Clone)]
+pub struct HttpClientPool {
+    default_client: reqwest::Client,
+    clients: Arc<Mutex<HashMap<ClientOptions, reqwest::Client>>>,
+    /// HTTP/1.1-only clients used for WebSocket upgrades
+    upgrade_clients: Arc<Mutex<HashMap<ClientOptions, reqwest::Client>>>,
+    /// Providers that already got the "TLS verification disabled" warning
+    insecure_warned: Arc<DashSet<i64>>,
+    /// Options each provider was last served with
+    provider_options: Arc<DashMap<i64, ClientOptions>>,
+}
+
+impl HttpClientPool {
+    pub fn new(default_client: reqwest::Client) -> Self {
+        Self {
+            default_client,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            upgrade_clients: Arc::new(Mutex::new(HashMap::new())),
+            insecure_warned: Arc::new(DashSet::new()),
+            provider_options: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn default_client(&self) -> &reqwest::Client {
+        &self.default_client
+    }
+
+    /// Get (or build and cache) the client for a provider with the given options
+    pub fn client_for(&self, provider_id: i64, options: &ClientOptions) -> Result<reqwest::Client, String> {
+        self.track_provider(provider_id, options);
+        if options.is_default() {
+            return Ok(self.default_client.clone());
+        }
+
+        let mut clients = self.clients.lock().map_err(|e| e.to_string())?;
+        if let Some(client) = clients.get(options) {
+            return Ok(client.clone());
+        }
+
+        let client = build_client(options)?;
+        clients.insert(options.clone(), client.clone());
+        Ok(client)
+    }
+
+    /// Get (or build and cache) the HTTP/1.1 client used for WebSocket upgrades
+    pub fn upgrade_client_for(&self, provider_id: i64, options: &ClientOptions) -> Result<reqwest::Client, String> {
+        self.track_provider(provider_id, options);
+        let mut clients = self.upgrade_clients.lock().map_err(|e| e.to_string())?;
+        if let Some(client) = clients.get(options) {
+            return Ok(client.clone());
+        }
+
+        let client = build_upgrade_client(options)?;
+        clients.insert(options.clone(), client.clone());
+        Ok(client)
+    }
+
+    /// Remember the provider's options; when they changed (provider edited) and no other
+    /// provider uses the old ones, the old clients and their pooled connections are dropped
+    fn track_provider(&self, provider_id: i64, options: &ClientOptions) {
+        let previous = self.provider_options.insert(provider_id, options.clone());
+        let Some(previous) = previous.filter(|previous| previous != options) else {
+            return;
+        };
+        if self.provider_options.iter().any(|entry| *entry.value() == previous) {
+            return;
+        }
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.remove(&previous);
+        }
+        if let Ok(mut clients) = self.upgrade_clients.lock() {
+            clients.remove(&previous);
+        }
+    }
+
+    /// Returns true only the first time an insecure provider is used (per process)
+    pub fn first_insecure_use(&self, provider_id: i64) -> bool {
+        self.insecure_warned.insert(provider_id)
+    }
+}
@@ -0,0 +1,125 @@
+//! Client addresses allowed to use the proxy (gateway_settings.allowed_client_ips).
+//!
+//! 列表为空时不限制；本机回环地址始终放行，避免本机 CLI 被误拦截。
+//! 只作用于代理路由，/api 与 /health 不受影响。
+
+use sqlx::SqlitePool;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+/// An IP address or CIDR block; host bits are cleared when parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    if addr.is_ipv4() {
+        32
+    } else {
+        128
+    }
+}
+
+fn masked(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V4((u32::from(v4) & mask).into())
+        }
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            IpAddr::V6((u128::from(v6) & mask).into())
+        }
+    }
+}
+
+impl IpNetwork {
+    /// `192.168.1.10`, `192.168.1.0/24`, `fd00::/8`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let invalid = || format!("Invalid IP address or CIDR: '{}'", value);
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max_prefix(&addr))
+                .ok_or_else(invalid)?,
+            None => max_prefix(&addr),
+        };
+        Ok(Self {
+            addr: masked(addr, prefix),
+            prefix,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 客户端在双栈监听下表现为 ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.addr.is_ipv4() && masked(ip, self.prefix) == self.addr
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == max_prefix(&self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+/// Allowed networks, replaced when the settings are saved
+pub type IpAllowlistCache = Arc<RwLock<Vec<IpNetwork>>>;
+
+/// Whether `ip` may use the proxy; an empty allowlist allows everyone
+pub fn is_allowed(allowlist: &[IpNetwork], ip: IpAddr) -> bool {
+    allowlist.is_empty() || ip.to_canonical().is_loopback() || allowlist.iter().any(|net| net.contains(ip))
+}
+
+/// Normalize every entry (`192.168.1.5/24` becomes `192.168.1.0/24`); invalid entries are errors
+pub fn normalize_allowed_client_ips(entries: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for entry in entries {
+        let entry = IpNetwork::parse(entry)?.to_string();
+        if !normalized.contains(&entry) {
+            normalized.push(entry);
+        }
+    }
+    Ok(normalized)
+}
+
+/// Read the allowlist (JSON array) from gateway_settings
+pub async fn load_allowed_client_ips(db: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let raw = sqlx::query_scalar::<_, String>("SELECT allowed_client_ips FROM gateway_settings WHERE id = 1")
+        .fetch_optional(db)
+        .await?
+        .unwrap_or_default();
+    Ok(serde_json::from_str(&raw).unwrap_or_default())
+}
+
+/// Refresh the cached allowlist from the database; invalid entries are skipped
+pub async fn reload_allowed_client_ips(db: &SqlitePool, cache: &IpAllowlistCache) -> Result<(), sqlx::Error> {
+    let networks = load_allowed_client_ips(db)
+        .await?
+        .iter()
+        .filter_map(|entry| match IpNetwork::parse(entry) {
+            Ok(network) => Some(network),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                None
+            }
+        })
+        .collect();
+    *cache.write().unwrap_or_else(|e| e.into_inner()) = networks;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! Client paths kept out of the request log (health probes, model list polling, count_tokens...).
+//!
+//! 匹配的请求照常转发，只是不写 request_logs；是否仍计入 usage_daily 由 count_usage 决定。
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::sync::{Arc, RwLock};
+
+use crate::services::proxy::wildcard_match;
+
+/// gateway_settings.log_exclude_paths / log_exclude_count_usage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogExcludeRules {
+    /// Wildcard patterns (`*`, `?`) matched against the client path without its query string
+    pub patterns: Vec<String>,
+    /// Still count excluded requests in usage_daily
+    pub count_usage: bool,
+}
+
+impl Default for LogExcludeRules {
+    fn default() -> Self {
+        Self {
+            patterns: Vec::new(),
+            count_usage: true,
+        }
+    }
+}
+
+impl LogExcludeRules {
+    /// Whether requests to `client_path` skip the request log
+    pub fn excludes(&self, client_path: &str) -> bool {
+        let path = client_path.split('?').next().unwrap_or(client_path);
+        self.patterns.iter().any(|pattern| wildcard_match(pattern, path))
+    }
+}
+
+/// Current rules, replaced when the settings are saved
+pub type LogExcludeCache = Arc<RwLock<LogExcludeRules>>;
+
+/// Trim the patterns and reject empty ones
+pub fn normalize_log_exclude_paths(patterns: &[String]) -> Result<Vec<String>, String> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let pattern = pattern.trim();
+            if pattern.is_empty() {
+                Err("Log exclude path cannot be empty".to_string())
+            } else {
+                Ok(pattern.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Read the rules from gateway_settings
+pub async fn load_log_exclude_rules(db: &SqlitePool) -> Result<LogExcludeRules, sqlx::Error> {
+    let row = sqlx::query_as::<_, (String, i64)>(
+        "SELECT log_exclude_paths, log_exclude_count_usage FROM gateway_settings WHERE id = 1",
+    )
+    .fetch_optional(db)
+    .await?;
+    Ok(row
+        .map(|(patterns, count_usage)| LogExcludeRules {
+            patterns: serde_json::from_str(&patterns).unwrap_or_default(),
+            count_usage: count_usage != 0,
+        })
+        .unwrap_or_default())
+}
+
+/// Refresh the cached rules from the database
+pub async fn reload_log_exclude_rules(db: &SqlitePool, cache: &LogExcludeCache) -> Result<(), sqlx::Error> {
+    let rules = load_log_exclude_rules(db).await?;
+    *cache.write().unwrap_or_else(|e| e.into_inner()) = rules;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use serde_json::Value;
+
+use crate::db::models::McpValidationResult;
+
+/// Validate a single MCP server config before it is saved / synced to CLI files.
+///
+/// `cli_type` 为空时只做通用校验；为 "codex" 时额外检查能否写入 TOML。
+pub fn validate_mcp_config(config_json: &str, cli_type: &str) -> McpValidationResult {
+    let mut errors = Vec::new();
+
+    let json = match serde_json::from_str::<Value>(config_json) {
+        Ok(json) => json,
+        Err(e) => {
+            errors.push(format!("line {}: invalid JSON: {}", e.line(), e));
+            return McpValidationResult { valid: false, errors };
+        }
+    };
+
+    let Some(obj) = json.as_object() else {
+        errors.push("line 1: MCP config must be a JSON object".to_string());
+        return McpValidationResult { valid: false, errors };
+    };
+
+    let line = |key: &str| line_of_key(config_json, key);
+
+    // type 缺省时按字段推断：有 command 视为 stdio，否则视为远程服务
+    let transport = match obj.get("type") {
+        Some(Value::String(t)) => t.to_lowercase(),
+        Some(_) => {
+            errors.push(format!("line {}: \"type\" must be a string", line("type")));
+            String::new()
+        }
+        None if obj.contains_key("command") => "stdio".to_string(),
+        None if obj.contains_key("url") || obj.contains_key("httpUrl") => "http".to_string(),
+        None => "stdio".to_string(),
+    };
+
+    match transport.as_str() {
+        "stdio" => match obj.get("command") {
+            Some(Value::String(cmd)) if !cmd.trim().is_empty() => {}
+            Some(_) => errors.push(format!("line {}: \"command\" must be a non-empty string", line("command"))),
+            None => errors.push("line 1: stdio MCP requires a \"command\"".to_string()),
+        },
+        "sse" | "http" | "streamable-http" => {
+            let key = if obj.contains_key("url") { "url" } else { "httpUrl" };
+            match obj.get(key) {
+                Some(Value::String(url)) => {
+                    if let Err(e) = check_url(url) {
+                        errors.push(format!("line {}: invalid \"{}\": {}", line(key), key, e));
+                    }
+                }
+                Some(_) => errors.push(format!("line {}: \"{}\" must be a string", line(key), key)),
+                None => errors.push(format!("line 1: {} MCP requires a \"url\"", transport)),
+            }
+        }
+        "" => {}
+        other => errors.push(format!(
+            "line {}: unsupported type \"{}\", expected stdio, sse or http",
+            line("type"),
+            other
+        )),
+    }
+
+    if let Some(args) = obj.get("args") {
+        let all_strings = args
+            .as_array()
+            .is_some_and(|items| items.iter().all(|v| v.is_string()));
+        if !all_strings {
+            errors.push(format!("line {}: \"args\" must be an array of strings", line("args")));
+        }
+    }
+
+    if cli_type == "codex" {
+        collect_toml_errors(&json, "", config_json, &mut errors);
+    }
+
+    McpValidationResult {
+        valid: errors.is_empty(),
+        errors,
+    }
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("unsupported scheme '{}'", parsed.scheme()));
+    }
+    Ok(())
+}
+
+/// TOML 没有 null，Codex 的 config.toml 无法表示这些字段
+fn collect_toml_errors(value: &Value, path: &str, source: &str, errors: &mut Vec<String>) {
+    match value {
+        Value::Null => {
+            let key = path.rsplit('.').next().and_then(|k| k.split('[').next()).unwrap_or(path);
+            errors.push(format!(
+                "line {}: \"{}\" is null, which cannot be written to Codex TOML config",
+                line_of_key(source, key),
+                path
+            ));
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                collect_toml_errors(item, &format!("{}[{}]", path, i), source, errors);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                collect_toml_errors(item, &child, source, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 1-based line of the first `"key"` occurrence, used to point errors at the source
+fn line_of_key(source: &str, key: &str) -> usize {
+    let needle = format!("\"{}\"", key);
+    source
+        .lines()
+        .position(|l| l.contains(&needle))
+        .map(|i| i + 1)
+        .unwrap_or(1)
+}
@@ -1,4 +1,27 @@
+pub mod active_requests;
+pub mod batch;
+pub mod benchmark;
+pub mod budget;
+pub mod chat_wire;
+pub mod cli_sync;
+pub mod concurrency;
+pub mod cors;
+pub mod diagnostics;
+pub mod env_file;
+pub mod geoip;
+pub mod http_client;
+pub mod ip_allowlist;
+pub mod log_exclude;
+pub mod masking;
+pub mod mcp;
+pub mod pricing;
 pub mod provider;
 pub mod proxy;
+pub mod responses_chat;
 pub mod routing;
+pub mod secrets;
+pub mod session_summary;
 pub mod stats;
+pub mod token_count;
+pub mod translate;
+pub mod websocket;
@@ -0,0 +1,195 @@
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+
+use crate::db::models::ModelPricing;
+use crate::services::proxy::TokenUsage;
+
+/// Estimated USD cost of a request. Cache tokens are counted apart from input_tokens
+/// (as Claude reports them); without a cache price they cost the input price.
+pub fn cost_usd(pricing: &ModelPricing, usage: &TokenUsage) -> f64 {
+    let input_price = pricing.input_price_per_million_tokens;
+    (usage.input_tokens as f64 * input_price
+        + usage.output_tokens as f64 * pricing.output_price_per_million_tokens
+        + usage.cache_read_tokens as f64 * pricing.cache_read_price_per_million_tokens.unwrap_or(input_price)
+        + usage.cache_creation_tokens as f64 * pricing.cache_write_price_per_million_tokens.unwrap_or(input_price))
+        / 1_000_000.0
+}
+
+/// Look up the configured price for a provider/model pair
+pub async fn find_pricing(
+    db: &SqlitePool,
+    provider_name: &str,
+    model_id: &str,
+) -> Result<Option<ModelPricing>, sqlx::Error> {
+    sqlx::query_as::<_, ModelPricing>(
+        "SELECT * FROM model_pricing WHERE provider_name = ? AND model_id = ?",
+    )
+    .bind(provider_name)
+    .bind(model_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// All configured prices keyed by (provider_name, model_id)
+pub async fn load_pricing(db: &SqlitePool) -> Result<HashMap<(String, String), ModelPricing>, sqlx::Error> {
+    Ok(sqlx::query_as::<_, ModelPricing>("SELECT * FROM model_pricing")
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|p| ((p.provider_name.clone(), p.model_id.clone()), p))
+        .collect())
+}
+
+/// Estimated cost of all logged requests, optionally of one CLI type.
+/// Models without a configured price add nothing.
+pub async fn logged_cost_total(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    cli_type: Option<&str>,
+) -> Result<f64, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (String, String, i64, i64, i64, i64)>(
+        r#"
+        SELECT provider_name, COALESCE(model_id, '') AS model_id,
+               SUM(input_tokens) AS input_tokens, SUM(output_tokens) AS output_tokens,
+               SUM(cache_read_tokens) AS cache_read_tokens, SUM(cache_creation_tokens) AS cache_creation_tokens
+        FROM request_logs
+        WHERE ? IS NULL OR cli_type = ?
+        GROUP BY provider_name, model_id
+        "#,
+    )
+    .bind(cli_type)
+    .bind(cli_type)
+    .fetch_all(log_db)
+    .await?;
+    if rows.is_empty() {
+        return Ok(0.0);
+    }
+
+    let pricing = load_pricing(db).await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(provider_name, model_id, input_tokens, output_tokens, cache_read_tokens, cache_creation_tokens)| {
+            let usage = TokenUsage { input_tokens, output_tokens, cache_read_tokens, cache_creation_tokens };
+            pricing.get(&(provider_name, model_id)).map(|p| cost_usd(p, &usage))
+        })
+        .sum())
+}
+
+/// Key used in request_logs.session_id for a session listed from the CLI files.
+/// Codex rollout files are named `rollout-<timestamp>-<uuid>` while requests carry just the uuid.
+pub fn session_log_key(session_id: &str) -> &str {
+    const UUID_LEN: usize = 36;
+    match session_id.len().checked_sub(UUID_LEN) {
+        Some(start) if start > 0 && session_id.is_char_boundary(start) => {
+            let tail = &session_id[start..];
+            if uuid::Uuid::parse_str(tail).is_ok() {
+                tail
+            } else {
+                session_id
+            }
+        }
+        _ => session_id,
+    }
+}
+
+/// Estimated cost per session id (keys as given in `session_ids`).
+/// Sessions without logged usage, or using a model that has no pricing, are left out.
+pub async fn session_costs(
+    db: &SqlitePool,
+    log_db: &SqlitePool,
+    session_ids: &[String],
+) -> Result<HashMap<String, f64>, sqlx::Error> {
+    let mut costs = HashMap::new();
+    if session_ids.is_empty() {
+        return Ok(costs);
+    }
+
+    let keys: HashMap<&str, &String> = session_ids.iter().map(|id| (session_log_key(id), id)).collect();
+    let placeholders = vec!["?"; keys.len()].join(", ");
+    let sql = format!(
+        r#"
+        SELECT session_id, provider_name, COALESCE(model_id, '') AS model_id,
+               SUM(input_tokens) AS input_tokens, SUM(output_tokens) AS output_tokens,
+               SUM(cache_read_tokens) AS cache_read_tokens, SUM(cache_creation_tokens) AS cache_creation_tokens
+        FROM request_logs
+        WHERE session_id IN ({})
+        GROUP BY session_id, provider_name, model_id
+        HAVING SUM(input_tokens) + SUM(output_tokens) + SUM(cache_read_tokens) + SUM(cache_creation_tokens) > 0
+        "#,
+        placeholders
+    );
+    let mut q = sqlx::query_as::<_, (String, String, String, i64, i64, i64, i64)>(&sql);
+    for key in keys.keys() {
+        q = q.bind(*key);
+    }
+    let rows = q.fetch_all(log_db).await?;
+    if rows.is_empty() {
+        return Ok(costs);
+    }
+
+    let pricing = load_pricing(db).await?;
+
+    // 任一模型未配置价格时整段会话不给出估算，避免低估
+    let mut unpriced = Vec::new();
+    for (session_id, provider_name, model_id, input_tokens, output_tokens, cache_read_tokens, cache_creation_tokens) in rows {
+        let Some(&id) = keys.get(session_id.as_str()) else {
+            continue;
+        };
+        let usage = TokenUsage { input_tokens, output_tokens, cache_read_tokens, cache_creation_tokens };
+        match pricing.get(&(provider_name, model_id)) {
+            Some(p) => *costs.entry(id.clone()).or_insert(0.0) += cost_usd(p, &usage),
+            None => unpriced.push(id.clone()),
+        }
+    }
+    for id in unpriced {
+        costs.remove(&id);
+    }
+    Ok(costs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pricing(cache_read: Option<f64>, cache_write: Option<f64>) -> ModelPricing {
+        ModelPricing {
+            id: 1,
+            provider_name: "p".to_string(),
+            model_id: "m".to_string(),
+            input_price_per_million_tokens: 3.0,
+            output_price_per_million_tokens: 15.0,
+            cache_read_price_per_million_tokens: cache_read,
+            cache_write_price_per_million_tokens: cache_write,
+            updated_at: 0,
+        }
+    }
+
+    fn usage() -> TokenUsage {
+        TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 1_000_000,
+            cache_read_tokens: 2_000_000,
+            cache_creation_tokens: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn cache_tokens_use_their_own_prices() {
+        let cost = cost_usd(&pricing(Some(0.3), Some(3.75)), &usage());
+        assert!((cost - (3.0 + 15.0 + 0.6 + 3.75)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cache_tokens_fall_back_to_the_input_price() {
+        let cost = cost_usd(&pricing(None, None), &usage());
+        assert!((cost - (3.0 + 15.0 + 6.0 + 3.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn codex_rollout_ids_map_to_the_uuid() {
+        let uuid = "0199a1b2-c3d4-7e5f-8a9b-0c1d2e3f4a5b";
+        assert_eq!(session_log_key(&format!("rollout-2025-01-01T00-00-00-{}", uuid)), uuid);
+        assert_eq!(session_log_key(uuid), uuid);
+        assert_eq!(session_log_key("short"), "short");
+    }
+}
@@ -1,4 +1,9 @@
+use dashmap::DashMap;
 use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::db::models::{Provider, ProviderApiKey};
 
 /// Record a successful request for a provider
 /// Resets consecutive_failures to 0
@@ -118,3 +123,171 @@ pub async fn reset_failures(db: &SqlitePool, provider_id: i64) -> Result<(), sql
 
     Ok(())
 }
+
+/// Per-provider round-robin cursor for API key rotation
+pub type KeyCursors = Arc<DashMap<i64, Arc<AtomicUsize>>>;
+
+/// Pick the next API key for a provider from `provider_api_keys` (round-robin).
+/// Keys whose failure_count reached the provider's failure_threshold are skipped
+/// until blacklist_minutes have passed since they were last used.
+/// Returns None when the provider has no rotation keys configured.
+pub async fn select_api_key(
+    db: &SqlitePool,
+    provider: &Provider,
+    cursors: &KeyCursors,
+) -> Result<Option<ProviderApiKey>, sqlx::Error> {
+    let keys = sqlx::query_as::<_, ProviderApiKey>(
+        "SELECT * FROM provider_api_keys WHERE provider_id = ? AND enabled = 1 ORDER BY id",
+    )
+    .bind(provider.id)
+    .fetch_all(db)
+    .await?;
+
+    let now = chrono::Utc::now().timestamp();
+    let cursor = cursors
+        .entry(provider.id)
+        .or_insert_with(|| Arc::new(AtomicUsize::new(0)))
+        .clone();
+    let Some(selection) = pick_api_key(&keys, provider, now, &cursor) else {
+        return Ok(None);
+    };
+
+    // last_used_at 同时是冷却起点：冷却中的 key 被借用时不更新，否则冷却会被不断延长
+    if !selection.cooling_down {
+        sqlx::query("UPDATE provider_api_keys SET last_used_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(selection.key.id)
+            .execute(db)
+            .await?;
+    }
+
+    Ok(Some(selection.key.clone()))
+}
+
+pub struct KeySelection<'a> {
+    pub key: &'a ProviderApiKey,
+    /// Every key is cooling down and this one was borrowed anyway
+    pub cooling_down: bool,
+}
+
+/// Round-robin over the usable keys; when all are cooling down, the key whose
+/// cooldown ends soonest (the least recently used one)
+pub fn pick_api_key<'a>(
+    keys: &'a [ProviderApiKey],
+    provider: &Provider,
+    now: i64,
+    cursor: &AtomicUsize,
+) -> Option<KeySelection<'a>> {
+    let cooldown_until = now - provider.blacklist_minutes * 60;
+    let usable: Vec<&ProviderApiKey> = keys
+        .iter()
+        .filter(|k| {
+            k.failure_count < provider.failure_threshold
+                || k.last_used_at.map(|t| t <= cooldown_until).unwrap_or(true)
+        })
+        .collect();
+
+    if usable.is_empty() {
+        // 全部 key 都在冷却中：退回最早解除冷却的 key，而不是直接失败
+        return keys
+            .iter()
+            .min_by_key(|k| k.last_used_at.unwrap_or(0))
+            .map(|key| KeySelection { key, cooling_down: true });
+    }
+    let idx = cursor.fetch_add(1, Ordering::Relaxed) % usable.len();
+    Some(KeySelection { key: usable[idx], cooling_down: false })
+}
+
+/// Reset the failure counter of a rotation key after a successful request
+pub async fn record_key_success(db: &SqlitePool, key_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE provider_api_keys SET failure_count = 0 WHERE id = ?")
+        .bind(key_id)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Increment the failure counter of a rotation key
+/// Returns true when the key just reached the threshold and will be skipped
+pub async fn record_key_failure(db: &SqlitePool, key_id: i64) -> Result<bool, sqlx::Error> {
+    let row: Option<(i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT k.failure_count, p.failure_threshold
+        FROM provider_api_keys k JOIN providers p ON p.id = k.provider_id
+        WHERE k.id = ?
+        "#,
+    )
+    .bind(key_id)
+    .fetch_optional(db)
+    .await?;
+
+    let Some((failure_count, failure_threshold)) = row else {
+        return Ok(false);
+    };
+
+    sqlx::query("UPDATE provider_api_keys SET failure_count = failure_count + 1 WHERE id = ?")
+        .bind(key_id)
+        .execute(db)
+        .await?;
+
+    Ok(failure_count + 1 == failure_threshold)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn provider() -> Provider {
+        Provider {
+            id: 1,
+            failure_threshold: 3,
+            blacklist_minutes: 10,
+            ..Default::default()
+        }
+    }
+
+    fn key(id: i64, failure_count: i64, last_used_at: Option<i64>) -> ProviderApiKey {
+        ProviderApiKey {
+            id,
+            provider_id: 1,
+            api_key: format!("sk-{}", id),
+            enabled: 1,
+            failure_count,
+            last_used_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn usable_keys_rotate() {
+        let keys = [key(1, 0, None), key(2, 0, Some(NOW - 5)), key(3, 0, None)];
+        let cursor = AtomicUsize::new(0);
+        let picked: Vec<i64> = (0..4).map(|_| pick_api_key(&keys, &provider(), NOW, &cursor).unwrap().key.id).collect();
+        assert_eq!(picked, vec![1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn cooling_keys_are_skipped_until_the_cooldown_ends() {
+        let keys = [key(1, 3, Some(NOW - 60)), key(2, 0, Some(NOW)), key(3, 3, Some(NOW - 601))];
+        let cursor = AtomicUsize::new(0);
+        let picked: Vec<i64> = (0..4).map(|_| pick_api_key(&keys, &provider(), NOW, &cursor).unwrap().key.id).collect();
+        assert_eq!(picked, vec![2, 3, 2, 3]);
+    }
+
+    #[test]
+    fn all_cooling_picks_the_soonest_to_recover() {
+        let keys = [key(1, 3, Some(NOW - 30)), key(2, 5, Some(NOW - 300)), key(3, 3, Some(NOW - 120))];
+        let cursor = AtomicUsize::new(0);
+        let selection = pick_api_key(&keys, &provider(), NOW, &cursor).unwrap();
+        assert_eq!(selection.key.id, 2);
+        assert!(selection.cooling_down);
+        assert_eq!(cursor.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn no_keys_means_no_rotation() {
+        assert!(pick_api_key(&[], &provider(), NOW, &AtomicUsize::new(0)).is_none());
+    }
+}
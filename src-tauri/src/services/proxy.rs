@@ -1,13 +1,17 @@
 use axum::http::HeaderMap;
+use dashmap::DashMap;
 use regex::Regex;
 use serde_json::Value;
+use sqlx::SqlitePool;
+use std::sync::{Arc, OnceLock, RwLock};
 use std::time::Duration;
 
 use crate::db::models::ProviderModelMap;
+use crate::services::masking::mask_secret_query_params;
 use crate::services::routing::ProviderWithMaps;
 
 /// Wildcard pattern matching: * matches any characters, ? matches single character
-fn wildcard_match(pattern: &str, value: &str) -> bool {
+pub fn wildcard_match(pattern: &str, value: &str) -> bool {
     let pattern_chars: Vec<char> = pattern.chars().collect();
     let value_chars: Vec<char> = value.chars().collect();
 
@@ -42,9 +46,76 @@ fn wildcard_match(pattern: &str, value: &str) -> bool {
     p_idx == pattern_chars.len()
 }
 
+/// Model map sources starting with this prefix are regexes instead of wildcard patterns
+pub const MODEL_REGEX_PREFIX: &str = "re:";
+
+/// Compiled model map regexes keyed by source pattern (compiled once, validated on save)
+fn model_regex(pattern: &str) -> Option<Regex> {
+    static CACHE: OnceLock<DashMap<String, Regex>> = OnceLock::new();
+    let cache = CACHE.get_or_init(DashMap::new);
+    if let Some(re) = cache.get(pattern) {
+        return Some(re.clone());
+    }
+    let re = compile_model_regex(pattern).ok()?;
+    cache.insert(pattern.to_string(), re.clone());
+    Some(re)
+}
+
+/// Compile a `re:` source pattern; it must match the whole model name
+pub fn compile_model_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// Regex equivalent of a wildcard pattern with one capture group per `*`
+fn wildcard_regex(pattern: &str) -> String {
+    let mut re = String::new();
+    for c in pattern.chars() {
+        match c {
+            '*' => re.push_str("(.*?)"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re
+}
+
+/// Resolve the target model for one map entry, or None if the source does not match.
+/// `re:` sources may reference capture groups in the target (`$1`, `${name}`);
+/// each `*` in a wildcard target takes what the source `*` at the same position matched.
+fn map_model(source_pattern: &str, target: &str, model: &str) -> Option<String> {
+    match source_pattern.strip_prefix(MODEL_REGEX_PREFIX) {
+        Some(pattern) => {
+            let caps = model_regex(pattern)?.captures(model)?;
+            let mut mapped = String::new();
+            caps.expand(target, &mut mapped);
+            Some(mapped)
+        }
+        None if target.contains('*') => {
+            let caps = model_regex(&wildcard_regex(source_pattern))?.captures(model)?;
+            let mut groups = caps.iter().skip(1).map(|m| m.map_or("", |m| m.as_str()));
+            let mut mapped = String::new();
+            for (i, part) in target.split('*').enumerate() {
+                if i > 0 {
+                    // 目标中多出来的 * 没有对应的捕获，替换为空
+                    mapped.push_str(groups.next().unwrap_or_default());
+                }
+                mapped.push_str(part);
+            }
+            Some(mapped)
+        }
+        None => wildcard_match(source_pattern, model).then(|| target.to_string()),
+    }
+}
+
+/// Whether a model map source (wildcard or `re:` pattern) matches `model`
+pub fn model_source_matches(source_pattern: &str, model: &str) -> bool {
+    map_model(source_pattern, "", model).is_some()
+}
+
 /// CLI type enum
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum CliType {
+    #[default]
     ClaudeCode,
     Codex,
     Gemini,
@@ -58,6 +129,44 @@ impl CliType {
             CliType::Gemini => "gemini",
         }
     }
+
+    /// Proxy path prefix that selects this CLI type regardless of the User-Agent
+    pub fn path_prefix(&self) -> &'static str {
+        match self {
+            CliType::ClaudeCode => "/claude",
+            CliType::Codex => "/codex",
+            CliType::Gemini => "/gemini",
+        }
+    }
+}
+
+/// Split a `/claude`, `/codex` or `/gemini` prefix off a client path (query string included).
+/// The prefix must be a whole segment; the remaining path is what gets forwarded.
+pub fn strip_cli_path_prefix(full_path: &str) -> Option<(CliType, String)> {
+    [CliType::ClaudeCode, CliType::Codex, CliType::Gemini]
+        .into_iter()
+        .find_map(|cli_type| {
+            let rest = full_path.strip_prefix(cli_type.path_prefix())?;
+            match rest.chars().next() {
+                None => Some((cli_type, "/".to_string())),
+                Some('/') => Some((cli_type, rest.to_string())),
+                Some('?') => Some((cli_type, format!("/{}", rest))),
+                Some(_) => None,
+            }
+        })
+}
+
+impl std::str::FromStr for CliType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "claude_code" => Ok(CliType::ClaudeCode),
+            "codex" => Ok(CliType::Codex),
+            "gemini" => Ok(CliType::Gemini),
+            _ => Err(format!("Unknown CLI type: {}", s)),
+        }
+    }
 }
 
 impl std::fmt::Display for CliType {
@@ -71,25 +180,173 @@ impl std::fmt::Display for CliType {
 pub struct TokenUsage {
     pub input_tokens: i64,
     pub output_tokens: i64,
+    /// Claude prompt caching: tokens written to the cache
+    pub cache_creation_tokens: i64,
+    /// Claude prompt caching: tokens read from the cache (billed at a fraction of input)
+    pub cache_read_tokens: i64,
 }
 
-/// Detect CLI type from User-Agent header
-pub fn detect_cli_type(headers: &HeaderMap) -> CliType {
-    let ua = headers
-        .get("user-agent")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("")
-        .to_lowercase();
+/// Built-in User-Agent detection; None for tools it does not recognize
+pub fn builtin_cli_type(ua: &str) -> Option<CliType> {
+    let ua = ua.to_lowercase();
 
     if ua.contains("codex") || ua.contains("openai") {
-        CliType::Codex
+        Some(CliType::Codex)
     } else if ua.contains("gemini") || ua.contains("google") {
-        CliType::Gemini
+        Some(CliType::Gemini)
+    } else if ua.contains("claude") || ua.contains("anthropic") {
+        Some(CliType::ClaudeCode)
     } else {
-        CliType::ClaudeCode
+        None
+    }
+}
+
+/// Compiled per-provider User-Agent pattern
+#[derive(Debug, Clone)]
+pub struct UaPattern {
+    pub regex: Regex,
+    pub cli_type: CliType,
+}
+
+/// Provider id -> compiled User-Agent pattern, shared between the proxy and Tauri commands
+pub type UaPatternCache = Arc<DashMap<i64, UaPattern>>;
+
+/// Rebuild the User-Agent pattern cache from enabled providers
+pub async fn reload_ua_patterns(db: &SqlitePool, cache: &UaPatternCache) -> Result<(), sqlx::Error> {
+    let rows: Vec<(i64, String, String, Option<String>)> = sqlx::query_as(
+        r#"
+        SELECT id, cli_type, user_agent_pattern, cli_type_override FROM providers
+        WHERE enabled = 1 AND user_agent_pattern IS NOT NULL AND user_agent_pattern != ''
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    cache.clear();
+    for (id, cli_type, pattern, cli_type_override) in rows {
+        let target = cli_type_override.as_deref().unwrap_or(&cli_type);
+        let (Ok(regex), Ok(cli_type)) = (Regex::new(&pattern), target.parse::<CliType>()) else {
+            tracing::warn!(provider_id = id, pattern = %pattern, "Skipping invalid User-Agent pattern");
+            continue;
+        };
+        cache.insert(id, UaPattern { regex, cli_type });
+    }
+
+    Ok(())
+}
+
+/// Global User-Agent rules (ordered by priority) and the fallback CLI type
+#[derive(Debug, Default)]
+pub struct UaRuleSet {
+    pub rules: Vec<UaPattern>,
+    pub default_cli_type: CliType,
+}
+
+/// User-Agent rules shared between the proxy and Tauri commands
+pub type UaRuleCache = Arc<RwLock<UaRuleSet>>;
+
+/// Rebuild the rule cache from user_agent_rules and gateway_settings.default_cli_type
+pub async fn reload_ua_rules(db: &SqlitePool, cache: &UaRuleCache) -> Result<(), sqlx::Error> {
+    let rows: Vec<(i64, String, String)> = sqlx::query_as(
+        "SELECT id, pattern, cli_type FROM user_agent_rules ORDER BY priority, id",
+    )
+    .fetch_all(db)
+    .await?;
+    let default_cli_type: Option<String> =
+        sqlx::query_scalar("SELECT default_cli_type FROM gateway_settings WHERE id = 1")
+            .fetch_optional(db)
+            .await?;
+
+    let mut rules = Vec::with_capacity(rows.len());
+    for (id, pattern, cli_type) in rows {
+        let (Ok(regex), Ok(cli_type)) = (Regex::new(&pattern), cli_type.parse::<CliType>()) else {
+            tracing::warn!(rule_id = id, pattern = %pattern, "Skipping invalid User-Agent rule");
+            continue;
+        };
+        rules.push(UaPattern { regex, cli_type });
+    }
+    let default_cli_type = default_cli_type
+        .and_then(|t| t.parse::<CliType>().ok())
+        .unwrap_or_default();
+
+    *cache.write().unwrap_or_else(|e| e.into_inner()) = UaRuleSet { rules, default_cli_type };
+    Ok(())
+}
+
+/// Detect CLI type: provider User-Agent patterns first, then the global rules,
+/// then the built-in detection, and finally the configured default type
+pub fn detect_cli_type_with_patterns(headers: &HeaderMap, patterns: &UaPatternCache, rules: &UaRuleCache) -> CliType {
+    let ua = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    // 多个 provider 同时命中时取 id 最小的，保证结果稳定
+    let matched = patterns
+        .iter()
+        .filter(|entry| entry.value().regex.is_match(ua))
+        .min_by_key(|entry| *entry.key())
+        .map(|entry| entry.value().cli_type);
+    if let Some(cli_type) = matched {
+        return cli_type;
+    }
+
+    let rules = rules.read().unwrap_or_else(|e| e.into_inner());
+    rules
+        .rules
+        .iter()
+        .find(|rule| rule.regex.is_match(ua))
+        .map(|rule| rule.cli_type)
+        .or_else(|| builtin_cli_type(ua))
+        .unwrap_or(rules.default_cli_type)
+}
+
+/// Extract the client session id used to attribute request logs to a CLI session.
+/// Claude Code: `metadata.user_id` (`user_..._session_<uuid>` or a JSON string with `session_id`);
+/// Codex: the `session_id` header. Gemini CLI sends no stable session id.
+pub fn extract_session_id(headers: &HeaderMap, body: &[u8], cli_type: CliType) -> Option<String> {
+    match cli_type {
+        CliType::ClaudeCode => {
+            let json = serde_json::from_slice::<Value>(body).ok()?;
+            let user_id = json.get("metadata")?.get("user_id")?.as_str()?;
+            if let Ok(Value::Object(obj)) = serde_json::from_str::<Value>(user_id) {
+                return obj.get("session_id").and_then(|v| v.as_str()).map(|s| s.to_string());
+            }
+            user_id
+                .rsplit_once("_session_")
+                .map(|(_, id)| id.to_string())
+                .filter(|id| !id.is_empty())
+        }
+        CliType::Codex => headers
+            .get("session_id")
+            .or_else(|| headers.get("conversation_id"))
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty()),
+        CliType::Gemini => None,
     }
 }
 
+/// Session id used for sticky routing: the `X-Session-ID` header, a top-level `session_id`
+/// body field, or the session id the CLI itself sends (see extract_session_id)
+pub fn sticky_session_id(headers: &HeaderMap, body: &[u8], cli_type: CliType) -> Option<String> {
+    let from_header = headers
+        .get("x-session-id")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    from_header
+        .or_else(|| {
+            serde_json::from_slice::<Value>(body)
+                .ok()?
+                .get("session_id")?
+                .as_str()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        })
+        .or_else(|| extract_session_id(headers, body, cli_type))
+}
+
 /// Check if request is streaming based on body content
 pub fn is_streaming(body: &[u8], path: &str, cli_type: CliType) -> bool {
     match cli_type {
@@ -152,14 +409,14 @@ pub fn apply_body_model_mapping(
         return result;
     }
 
-    // Find matching model map (supports wildcard: * matches any, ? matches single char)
+    // First matching model map wins (wildcard: * matches any, ? matches single char; "re:" prefix: regex)
     for map in &provider.model_maps {
-        if wildcard_match(&map.source_model, &model) {
-            result.target_model = Some(map.target_model.clone());
+        if let Some(target) = map_model(&map.source_model, &map.target_model, &model) {
+            result.target_model = Some(target.clone());
 
             // Replace model in body
             if let Some(obj) = json.as_object_mut() {
-                obj.insert("model".to_string(), Value::String(map.target_model.clone()));
+                obj.insert("model".to_string(), Value::String(target));
             }
 
             if let Ok(new_body) = serde_json::to_vec(&json) {
@@ -173,14 +430,40 @@ pub fn apply_body_model_mapping(
     result
 }
 
-/// Apply model mapping for URL-based APIs (Gemini)
+/// Rewrite the `model` fields of a Gemini request body (top level and countTokens'
+/// `generateContentRequest`), keeping the `models/` prefix when the client used it.
+/// Returns None when the body names no model.
+fn rewrite_gemini_body_model(body: &[u8], model: &str) -> Option<Vec<u8>> {
+    let mut json = serde_json::from_slice::<Value>(body).ok()?;
+    let mut replaced = false;
+    for pointer in ["/model", "/generateContentRequest/model"] {
+        if let Some(field) = json.pointer_mut(pointer) {
+            let Some(current) = field.as_str() else {
+                continue;
+            };
+            *field = Value::String(match current.strip_prefix("models/") {
+                Some(_) => format!("models/{}", model),
+                None => model.to_string(),
+            });
+            replaced = true;
+        }
+    }
+    if !replaced {
+        return None;
+    }
+    serde_json::to_vec(&json).ok()
+}
+
+/// Apply model mapping for URL-based APIs (Gemini).
+/// The path segment decides the mapping; `model` fields in the body follow it.
 pub fn apply_url_model_mapping(
     _provider: &ProviderWithMaps,
     path: &str,
+    body: &[u8],
     model_maps: &[ProviderModelMap],
 ) -> ModelMappingResult {
     let mut result = ModelMappingResult {
-        body: vec![],
+        body: body.to_vec(),
         path: path.to_string(),
         source_model: None,
         target_model: None,
@@ -204,16 +487,18 @@ pub fn apply_url_model_mapping(
         return result;
     }
 
-    // Find matching model map (supports wildcard: * matches any, ? matches single char)
+    // First matching model map wins (wildcard: * matches any, ? matches single char; "re:" prefix: regex)
     for map in model_maps {
-        if wildcard_match(&map.source_model, source_model) {
-            result.target_model = Some(map.target_model.clone());
-
+        if let Some(target) = map_model(&map.source_model, &map.target_model, source_model) {
             // Replace model in path
             result.path = path.replace(
                 &format!("/models/{}", source_model),
-                &format!("/models/{}", map.target_model),
+                &format!("/models/{}", target),
             );
+            if let Some(new_body) = rewrite_gemini_body_model(body, &target) {
+                result.body = new_body;
+            }
+            result.target_model = Some(target);
 
             break;
         }
@@ -222,6 +507,468 @@ pub fn apply_url_model_mapping(
     result
 }
 
+/// Per-request model override header (stripped before forwarding)
+pub const MODEL_OVERRIDE_HEADER: &str = "x-ccg-model";
+
+/// Response header carrying the gateway request ID (request_logs.request_id)
+pub const REQUEST_ID_RESPONSE_HEADER: &str = "x-ccg-request-id";
+
+/// Remove the model override header, returning its value when set
+pub fn take_model_override(headers: &mut HeaderMap) -> Option<String> {
+    let value = headers.remove(MODEL_OVERRIDE_HEADER)?;
+    let model = value.to_str().ok()?.trim();
+    (!model.is_empty()).then(|| model.to_string())
+}
+
+/// Force the request model after model mapping: the body `model` field for
+/// Claude / Codex, the `/models/{model}` path segment for Gemini.
+/// Returns None when the request carries no model to replace.
+pub fn apply_model_override(cli_type: CliType, body: &[u8], path: &str, model: &str) -> Option<(Vec<u8>, String)> {
+    if cli_type == CliType::Gemini {
+        let re = Regex::new(r"/models/[^/:]+").unwrap();
+        re.find(path)?;
+        let path = re.replace(path, format!("/models/{}", model).as_str()).into_owned();
+        let body = rewrite_gemini_body_model(body, model).unwrap_or_else(|| body.to_vec());
+        return Some((body, path));
+    }
+
+    let mut json = serde_json::from_slice::<Value>(body).ok()?;
+    let obj = json.as_object_mut()?;
+    obj.get("model")?;
+    obj.insert("model".to_string(), Value::String(model.to_string()));
+    Some((serde_json::to_vec(&json).ok()?, path.to_string()))
+}
+
+/// Model named by the request: the body `model` field for Claude / Codex,
+/// the `/models/{model}` path segment for Gemini
+fn request_model(cli_type: CliType, body: &[u8], path: &str) -> Option<String> {
+    if cli_type == CliType::Gemini {
+        let re = Regex::new(r"/models/([^/:]+)").unwrap();
+        return re.captures(path)?.get(1).map(|m| m.as_str().to_string());
+    }
+    let json = serde_json::from_slice::<Value>(body).ok()?;
+    json.get("model")?.as_str().map(|s| s.to_string())
+}
+
+/// Enabled global model aliases of one CLI type as (source pattern, target), in match order
+pub async fn load_model_aliases(db: &SqlitePool, cli_type: CliType) -> Result<Vec<(String, String)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT source_model, target_model FROM global_model_aliases WHERE cli_type = ? AND enabled = 1 ORDER BY id",
+    )
+    .bind(cli_type.as_str())
+    .fetch_all(db)
+    .await
+}
+
+/// Rewrite the client model with the first matching global alias, before the provider's
+/// own model maps run. Aliases use the model map syntax (wildcards, `re:` regexes).
+/// Returns None when no alias matched.
+pub fn apply_model_aliases(
+    cli_type: CliType,
+    body: &[u8],
+    path: &str,
+    aliases: &[(String, String)],
+) -> Option<ModelMappingResult> {
+    let model = request_model(cli_type, body, path)?;
+    let target = aliases
+        .iter()
+        .find_map(|(source, target)| map_model(source, target, &model))
+        .filter(|target| *target != model)?;
+    let (body, path) = apply_model_override(cli_type, body, path, &target)?;
+    Some(ModelMappingResult {
+        body,
+        path,
+        source_model: Some(model),
+        target_model: Some(target),
+    })
+}
+
+/// Clamp the requested output token count to the provider's `max_tokens_limit`.
+/// Claude: `max_tokens`; Codex: `max_output_tokens` (and `max_tokens` for chat bodies);
+/// Gemini: `generationConfig.maxOutputTokens`.
+/// Returns the rewritten body and a note for the request log, or None if nothing was clamped.
+pub fn clamp_max_tokens(body: &[u8], cli_type: CliType, limit: Option<i64>) -> Option<(Vec<u8>, String)> {
+    let limit = limit.filter(|l| *l > 0)?;
+    let mut json = serde_json::from_slice::<Value>(body).ok()?;
+
+    let (target, fields): (Option<&mut Value>, &[&str]) = match cli_type {
+        CliType::ClaudeCode => (Some(&mut json), &["max_tokens"]),
+        CliType::Codex => (Some(&mut json), &["max_output_tokens", "max_tokens"]),
+        CliType::Gemini => (json.get_mut("generationConfig"), &["maxOutputTokens"]),
+    };
+    let obj = target?.as_object_mut()?;
+
+    let mut notes = Vec::new();
+    for field in fields {
+        let Some(requested) = obj.get(*field).and_then(|v| v.as_i64()) else {
+            continue;
+        };
+        if requested > limit {
+            obj.insert(field.to_string(), Value::from(limit));
+            notes.push(format!("{} clamped {} -> {}", field, requested, limit));
+        }
+    }
+    if notes.is_empty() {
+        return None;
+    }
+
+    let new_body = serde_json::to_vec(&json).ok()?;
+    Some((new_body, notes.join("; ")))
+}
+
+/// Prepend the provider's system prompt to the request.
+/// Claude: `system` (string or content blocks); Codex: `instructions`; Gemini: `systemInstruction.parts`.
+/// Bodies that already carry the prompt (e.g. replayed requests) and endpoints other than
+/// generation are left untouched.
+pub fn inject_system_prompt(body: &[u8], path: &str, cli_type: CliType, prompt: &str) -> Option<Vec<u8>> {
+    let prompt = prompt.trim();
+    if prompt.is_empty() || !accepts_system_prompt(path, cli_type) {
+        return None;
+    }
+    let mut json = serde_json::from_slice::<Value>(body).ok()?;
+    let obj = json.as_object_mut()?;
+
+    let injected = match cli_type {
+        CliType::ClaudeCode | CliType::Codex => {
+            let field = if cli_type == CliType::ClaudeCode { "system" } else { "instructions" };
+            match obj.get_mut(field) {
+                None | Some(Value::Null) => {
+                    obj.insert(field.to_string(), Value::String(prompt.to_string()));
+                    true
+                }
+                Some(Value::String(existing)) if existing.contains(prompt) => false,
+                Some(Value::String(existing)) => {
+                    *existing = format!("{}\n\n{}", prompt, existing);
+                    true
+                }
+                Some(Value::Array(blocks)) if cli_type == CliType::ClaudeCode => {
+                    if blocks.iter().any(|b| b.get("text").and_then(|t| t.as_str()) == Some(prompt)) {
+                        false
+                    } else {
+                        blocks.insert(0, serde_json::json!({ "type": "text", "text": prompt }));
+                        true
+                    }
+                }
+                Some(_) => false,
+            }
+        }
+        CliType::Gemini => match gemini_instruction_parts(obj) {
+            Some(parts) if parts.iter().any(|p| p.get("text").and_then(|t| t.as_str()) == Some(prompt)) => false,
+            Some(parts) => {
+                parts.insert(0, serde_json::json!({ "text": prompt }));
+                true
+            }
+            None => false,
+        },
+    };
+    if !injected {
+        return None;
+    }
+    serde_json::to_vec(&json).ok()
+}
+
+/// Generation endpoints, the only ones whose body takes a top-level system prompt:
+/// message batches and Gemini countTokens reject the field. Claude count_tokens takes
+/// `system`, so the count includes the prompt the real request will carry.
+fn accepts_system_prompt(path: &str, cli_type: CliType) -> bool {
+    let path = path.split('?').next().unwrap_or(path).trim_end_matches('/');
+    match cli_type {
+        CliType::ClaudeCode => path.ends_with("/messages") || path.ends_with("/messages/count_tokens"),
+        CliType::Codex => crate::services::responses_chat::is_responses_path(path),
+        CliType::Gemini => crate::services::translate::gemini_generate_target(path).is_some(),
+    }
+}
+
+/// `systemInstruction.parts` of a Gemini request (snake_case `system_instruction` is kept
+/// when the client used it), created when missing; None when the field is malformed
+fn gemini_instruction_parts(obj: &mut serde_json::Map<String, Value>) -> Option<&mut Vec<Value>> {
+    let key = if obj.contains_key("system_instruction") { "system_instruction" } else { "systemInstruction" };
+    let instruction = obj
+        .entry(key)
+        .or_insert_with(|| serde_json::json!({ "parts": [] }));
+    if instruction.is_null() {
+        *instruction = serde_json::json!({ "parts": [] });
+    }
+    instruction
+        .as_object_mut()?
+        .entry("parts")
+        .or_insert_with(|| Value::Array(vec![]))
+        .as_array_mut()
+}
+
+/// Append prompt presets to the `systemInstruction` of a Gemini request, after the parts
+/// already there. Prompts whose text is already in a part (Gemini CLI embeds GEMINI.md
+/// in its own system instruction) are skipped; None when nothing was added.
+pub fn append_gemini_system_instruction(body: &[u8], prompts: &[String]) -> Option<Vec<u8>> {
+    let mut json = serde_json::from_slice::<Value>(body).ok()?;
+    let parts = gemini_instruction_parts(json.as_object_mut()?)?;
+
+    let mut appended = false;
+    for prompt in prompts.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let present = parts
+            .iter()
+            .any(|p| p.get("text").and_then(|t| t.as_str()).is_some_and(|text| text.contains(prompt)));
+        if !present {
+            parts.push(serde_json::json!({ "text": prompt }));
+            appended = true;
+        }
+    }
+    if !appended {
+        return None;
+    }
+    serde_json::to_vec(&json).ok()
+}
+
+/// Ask a streaming chat/completions request for the final usage chunk by setting
+/// `stream_options.include_usage`; relays omit usage from streams unless asked.
+/// Returns None when the body is not a streaming chat request or already sets the option.
+pub fn inject_stream_include_usage(body: &[u8], path: &str) -> Option<Vec<u8>> {
+    let path = path.split('?').next().unwrap_or(path);
+    if !path.trim_end_matches('/').ends_with("/chat/completions") {
+        return None;
+    }
+    let mut json: Value = serde_json::from_slice(body).ok()?;
+    if json.get("stream").and_then(|v| v.as_bool()) != Some(true) {
+        return None;
+    }
+
+    let obj = json.as_object_mut()?;
+    let options = obj
+        .entry("stream_options")
+        .or_insert_with(|| Value::Object(serde_json::Map::new()));
+    // 客户端显式设置过（包括 false）则保持原样
+    let options = options.as_object_mut()?;
+    if options.contains_key("include_usage") {
+        return None;
+    }
+    options.insert("include_usage".to_string(), Value::Bool(true));
+
+    serde_json::to_vec(&json).ok()
+}
+
+/// Strip the provider's `strip_params` (JSON array of rewrite paths, e.g. `metadata` or
+/// `messages.content.cache_control`) from the request body. Returns the rewritten body and the paths actually removed,
+/// or None if nothing matched.
+pub fn strip_body_params(body: &[u8], strip_params: Option<&str>) -> Option<(Vec<u8>, Vec<String>)> {
+    let params = strip_params.and_then(|s| serde_json::from_str::<Vec<String>>(s).ok())?;
+    if params.is_empty() {
+        return None;
+    }
+    let mut json = serde_json::from_slice::<Value>(body).ok()?;
+
+    // 与 body_rewrite_rules 的 remove 走同一套路径解析
+    let removed: Vec<String> = params
+        .into_iter()
+        .filter(|param| {
+            parse_rewrite_path(param.trim()).is_ok_and(|segments| rewrite_at(&mut json, &segments, false, &mut remove_at))
+        })
+        .collect();
+    if removed.is_empty() {
+        return None;
+    }
+
+    let new_body = serde_json::to_vec(&json).ok()?;
+    Some((new_body, removed))
+}
+
+/// One step of a body rewrite path
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+    /// `[*]`: every element of an array
+    Each,
+}
+
+/// Parse `a.b[0].c[*].d` into segments (shared by body_rewrite_rules and strip_params)
+fn parse_rewrite_path(path: &str) -> Result<Vec<PathSegment>, String> {
+    let invalid = || format!("Invalid rewrite path '{}'", path);
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut rest) = match part.find('[') {
+            Some(i) => (&part[..i], &part[i..]),
+            None => (part, ""),
+        };
+        if key.is_empty() {
+            return Err(invalid());
+        }
+        segments.push(PathSegment::Key(key.to_string()));
+        while !rest.is_empty() {
+            let end = rest.find(']').ok_or_else(invalid)?;
+            let index = &rest[1..end];
+            segments.push(if index == "*" {
+                PathSegment::Each
+            } else {
+                PathSegment::Index(index.parse().map_err(|_| invalid())?)
+            });
+            rest = &rest[end + 1..];
+            if !rest.is_empty() && !rest.starts_with('[') {
+                return Err(invalid());
+            }
+        }
+    }
+    Ok(segments)
+}
+
+/// Check a strip_params entry when the provider is saved
+pub fn validate_strip_param(path: &str) -> Result<(), String> {
+    parse_rewrite_path(path.trim()).map(|_| ())
+}
+
+/// Check every rule's path (and rename target) so bad rules are rejected when the provider is saved
+pub fn validate_body_rewrite_rules(rules: &[crate::db::models::BodyRewriteRule]) -> Result<(), String> {
+    use crate::db::models::BodyRewriteRule;
+    for rule in rules {
+        let (path, rename_to) = match rule {
+            BodyRewriteRule::Set { path, .. } | BodyRewriteRule::Remove { path } => (path, None),
+            BodyRewriteRule::Rename { path, to } => (path, Some(to)),
+        };
+        let segments = parse_rewrite_path(path.trim())?;
+        let last_is_key = matches!(segments.last(), Some(PathSegment::Key(_)));
+        match rule {
+            BodyRewriteRule::Set { .. } if matches!(segments.last(), Some(PathSegment::Each)) => {
+                return Err(format!("Rewrite path '{}' cannot end with [*] for set", path));
+            }
+            BodyRewriteRule::Rename { .. } if !last_is_key => {
+                return Err(format!("Rename path '{}' must end with a key", path));
+            }
+            _ => {}
+        }
+        if let Some(to) = rename_to {
+            let to = to.trim();
+            if to.is_empty() || to.contains(['.', '[', ']']) {
+                return Err(format!("Invalid rename target '{}': must be a plain key", to));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply `op` to every value addressed by `path`; returns whether anything changed.
+/// A key met on an array applies to every element (`messages.content.cache_control`).
+/// With `create` (for `set`) missing objects along the path are built, and only kept
+/// once the rest of the path applied, so a path that fails halfway leaves no `{}` behind.
+fn rewrite_at<F>(value: &mut Value, path: &[PathSegment], create: bool, op: &mut F) -> bool
+where
+    F: FnMut(&mut Value, &PathSegment) -> bool,
+{
+    let Some((first, rest)) = path.split_first() else {
+        return false;
+    };
+    if let (PathSegment::Key(_), Value::Array(items)) = (first, &mut *value) {
+        let mut changed = false;
+        for item in items {
+            changed |= rewrite_at(item, path, create, op);
+        }
+        return changed;
+    }
+    if rest.is_empty() {
+        return op(value, first);
+    }
+    match first {
+        PathSegment::Key(key) => {
+            let Some(obj) = value.as_object_mut() else {
+                return false;
+            };
+            match obj.get_mut(key) {
+                Some(v) => rewrite_at(v, rest, create, op),
+                None if create => {
+                    let mut created = Value::Object(serde_json::Map::new());
+                    let changed = rewrite_at(&mut created, rest, create, op);
+                    if changed {
+                        obj.insert(key.clone(), created);
+                    }
+                    changed
+                }
+                None => false,
+            }
+        }
+        PathSegment::Index(i) => value
+            .as_array_mut()
+            .and_then(|items| items.get_mut(*i))
+            .is_some_and(|v| rewrite_at(v, rest, create, op)),
+        PathSegment::Each => {
+            let mut changed = false;
+            for item in value.as_array_mut().into_iter().flatten() {
+                changed |= rewrite_at(item, rest, create, op);
+            }
+            changed
+        }
+    }
+}
+
+/// `remove` operation on the last path segment
+fn remove_at(parent: &mut Value, last: &PathSegment) -> bool {
+    match (last, parent) {
+        (PathSegment::Key(key), Value::Object(obj)) => obj.remove(key).is_some(),
+        (PathSegment::Index(i), Value::Array(items)) if *i < items.len() => {
+            items.remove(*i);
+            true
+        }
+        (PathSegment::Each, Value::Array(items)) => {
+            let changed = !items.is_empty();
+            items.clear();
+            changed
+        }
+        _ => false,
+    }
+}
+
+/// Apply the provider's `body_rewrite_rules` (JSON array of set / remove / rename operations) in order.
+/// Returns the rewritten body and the number of rules that changed something, or None if none did.
+pub fn apply_body_rewrite_rules(body: &[u8], body_rewrite_rules: Option<&str>) -> Option<(Vec<u8>, usize)> {
+    use crate::db::models::BodyRewriteRule;
+    let rules = body_rewrite_rules
+        .and_then(|s| serde_json::from_str::<Vec<BodyRewriteRule>>(s).ok())
+        .filter(|r| !r.is_empty())?;
+    let mut json = serde_json::from_slice::<Value>(body).ok()?;
+
+    let mut applied = 0;
+    for rule in &rules {
+        let changed = match rule {
+            BodyRewriteRule::Set { path, value } => {
+                let Ok(segments) = parse_rewrite_path(path.trim()) else { continue };
+                rewrite_at(&mut json, &segments, true, &mut |parent, last| match (last, parent) {
+                    (PathSegment::Key(key), Value::Object(obj)) => obj.insert(key.clone(), value.clone()).as_ref() != Some(value),
+                    (PathSegment::Index(i), Value::Array(items)) if *i < items.len() => {
+                        let changed = items[*i] != *value;
+                        items[*i] = value.clone();
+                        changed
+                    }
+                    _ => false,
+                })
+            }
+            BodyRewriteRule::Remove { path } => {
+                let Ok(segments) = parse_rewrite_path(path.trim()) else { continue };
+                rewrite_at(&mut json, &segments, false, &mut remove_at)
+            }
+            BodyRewriteRule::Rename { path, to } => {
+                let Ok(segments) = parse_rewrite_path(path.trim()) else { continue };
+                let to = to.trim();
+                rewrite_at(&mut json, &segments, false, &mut |parent, last| match (last, parent) {
+                    (PathSegment::Key(key), Value::Object(obj)) if key != to => match obj.remove(key) {
+                        Some(v) => {
+                            obj.insert(to.to_string(), v);
+                            true
+                        }
+                        None => false,
+                    },
+                    _ => false,
+                })
+            }
+        };
+        if changed {
+            applied += 1;
+        }
+    }
+    if applied == 0 {
+        return None;
+    }
+
+    let new_body = serde_json::to_vec(&json).ok()?;
+    Some((new_body, applied))
+}
+
 /// Parse token usage from response data
 pub fn parse_token_usage(data: &[u8], cli_type: CliType, usage: &mut TokenUsage) {
     let Ok(json) = serde_json::from_slice::<Value>(data) else {
@@ -231,19 +978,22 @@ pub fn parse_token_usage(data: &[u8], cli_type: CliType, usage: &mut TokenUsage)
     match cli_type {
         CliType::ClaudeCode => {
             // Claude format: message.usage or usage at root
-            if let Some(msg_usage) = json.get("message").and_then(|m| m.get("usage")) {
-                if let Some(input) = msg_usage.get("input_tokens").and_then(|v| v.as_i64()) {
+            let claude_usage = json
+                .get("message")
+                .and_then(|m| m.get("usage"))
+                .or_else(|| json.get("usage"));
+            if let Some(claude_usage) = claude_usage {
+                if let Some(input) = claude_usage.get("input_tokens").and_then(|v| v.as_i64()) {
                     usage.input_tokens = input;
                 }
-                if let Some(output) = msg_usage.get("output_tokens").and_then(|v| v.as_i64()) {
+                if let Some(output) = claude_usage.get("output_tokens").and_then(|v| v.as_i64()) {
                     usage.output_tokens = output;
                 }
-            } else if let Some(root_usage) = json.get("usage") {
-                if let Some(input) = root_usage.get("input_tokens").and_then(|v| v.as_i64()) {
-                    usage.input_tokens = input;
+                if let Some(created) = claude_usage.get("cache_creation_input_tokens").and_then(|v| v.as_i64()) {
+                    usage.cache_creation_tokens = created;
                 }
-                if let Some(output) = root_usage.get("output_tokens").and_then(|v| v.as_i64()) {
-                    usage.output_tokens = output;
+                if let Some(read) = claude_usage.get("cache_read_input_tokens").and_then(|v| v.as_i64()) {
+                    usage.cache_read_tokens = read;
                 }
             }
         }
@@ -259,7 +1009,9 @@ pub fn parse_token_usage(data: &[u8], cli_type: CliType, usage: &mut TokenUsage)
                         usage.output_tokens = output;
                     }
                 }
-            } else if let Some(root_usage) = json.get("usage") {
+            } else if let Some(root_usage) = json.get("usage").filter(|u| u.is_object()) {
+                // chat/completions streams: earlier chunks carry `"usage": null`, the usage
+                // arrives in a final chunk whose `choices` is empty (stream_options.include_usage)
                 if let Some(input) = root_usage
                     .get("prompt_tokens")
                     .or_else(|| root_usage.get("input_tokens"))
@@ -314,6 +1066,80 @@ pub fn parse_streaming_token_usage(line: &str, cli_type: CliType, usage: &mut To
     parse_token_usage(data.as_bytes(), cli_type, usage);
 }
 
+/// SSE comment sent to the client while the upstream is silent.
+/// Claude / OpenAI / Gemini SSE clients all skip lines starting with ':'.
+pub const SSE_KEEPALIVE: &[u8] = b": ping\n\n";
+
+/// True for `text/event-stream` responses (Gemini without `alt=sse` streams a JSON array instead)
+pub fn is_event_stream(headers: &reqwest::header::HeaderMap) -> bool {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim_start().to_ascii_lowercase().starts_with("text/event-stream"))
+}
+
+/// True when `chunk` ends on an event boundary, so a comment can be sent without splitting an event
+pub fn ends_sse_event(chunk: &[u8]) -> bool {
+    chunk.ends_with(b"\n\n") || chunk.ends_with(b"\r\n\r\n")
+}
+
+/// Incremental SSE usage parser.
+///
+/// SSE 事件经常在 TCP chunk 中间被切断，这里按字节缓冲，只把完整的行交给
+/// `parse_streaming_token_usage`，剩余的半行留到下一个 chunk 再拼接。
+#[derive(Debug)]
+pub struct SseUsageParser {
+    cli_type: CliType,
+    buffer: Vec<u8>,
+    usage: TokenUsage,
+}
+
+impl SseUsageParser {
+    pub fn new(cli_type: CliType) -> Self {
+        Self {
+            cli_type,
+            buffer: Vec::new(),
+            usage: TokenUsage::default(),
+        }
+    }
+
+    /// Feed raw stream bytes; complete lines are parsed immediately
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+        let Some(last_newline) = self.buffer.iter().rposition(|b| *b == b'\n') else {
+            return;
+        };
+        let rest = self.buffer.split_off(last_newline + 1);
+        let complete = std::mem::replace(&mut self.buffer, rest);
+        for line in complete.split(|b| *b == b'\n') {
+            self.parse_line(line);
+        }
+    }
+
+    /// Output tokens reported by the stream so far
+    pub fn output_tokens(&self) -> i64 {
+        self.usage.output_tokens
+    }
+
+    /// Flush the trailing partial line and return the accumulated usage
+    pub fn finish(&mut self) -> TokenUsage {
+        let line = std::mem::take(&mut self.buffer);
+        self.parse_line(&line);
+        self.usage.clone()
+    }
+
+    fn parse_line(&mut self, line: &[u8]) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            return;
+        }
+        if let Ok(line) = std::str::from_utf8(line) {
+            // 流式响应可能多次更新 usage，后出现的值覆盖先前的值
+            parse_streaming_token_usage(line, self.cli_type, &mut self.usage);
+        }
+    }
+}
+
 /// Headers to filter out when forwarding requests
 const FILTERED_HEADERS: &[&str] = &[
     "host",
@@ -329,19 +1155,27 @@ const FILTERED_HEADERS: &[&str] = &[
     "proxy-authorization",
 ];
 
+/// Anthropic headers forwarded as-is for Claude Code and stripped for the other CLI types
+pub const PASSTHROUGH_HEADERS: &[&str] = &["anthropic-beta", "anthropic-version", "x-api-key"];
+
 /// Filter headers for forwarding
-pub fn filter_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
+pub fn filter_headers(headers: &HeaderMap, cli_type: CliType) -> reqwest::header::HeaderMap {
     let mut filtered = reqwest::header::HeaderMap::new();
 
     for (name, value) in headers.iter() {
         let name_str = name.as_str().to_lowercase();
-        if !FILTERED_HEADERS.contains(&name_str.as_str()) {
-            if let Ok(header_name) = reqwest::header::HeaderName::from_bytes(name.as_str().as_bytes())
+        if FILTERED_HEADERS.contains(&name_str.as_str()) {
+            continue;
+        }
+        if cli_type != CliType::ClaudeCode && PASSTHROUGH_HEADERS.contains(&name_str.as_str()) {
+            continue;
+        }
+        if let Ok(header_name) = reqwest::header::HeaderName::from_bytes(name.as_str().as_bytes())
+        {
+            if let Ok(header_value) = reqwest::header::HeaderValue::from_bytes(value.as_bytes())
             {
-                if let Ok(header_value) = reqwest::header::HeaderValue::from_bytes(value.as_bytes())
-                {
-                    filtered.insert(header_name, header_value);
-                }
+                // append 保留重复的头（如多行 anthropic-beta）
+                filtered.append(header_name, header_value);
             }
         }
     }
@@ -349,12 +1183,324 @@ pub fn filter_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
     filtered
 }
 
-/// Set authentication header based on CLI type
+/// Response headers never passed back to the client: hop-by-hop headers and the relay's cookies
+const DROPPED_RESPONSE_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "upgrade",
+    "proxy-connection",
+    "proxy-authenticate",
+    "set-cookie",
+];
+
+/// Headers the client needs to read the body; provider drop lists cannot remove them
+const PROTECTED_RESPONSE_HEADERS: &[&str] = &["content-type", "content-length", "content-encoding"];
+
+/// Which upstream response headers are copied to the client response
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHeaderPolicy {
+    /// Provider drop list: exact names, or prefixes ending in `*` (e.g. `anthropic-ratelimit-*`)
+    drop: Vec<String>,
+}
+
+impl ResponseHeaderPolicy {
+    /// Built-in defaults plus the provider's drop_response_headers (JSON array)
+    pub fn from_provider(drop_response_headers: Option<&str>) -> Self {
+        let drop = drop_response_headers
+            .and_then(|s| serde_json::from_str::<Vec<String>>(s).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|h| h.to_ascii_lowercase())
+            .collect();
+        Self { drop }
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        if PROTECTED_RESPONSE_HEADERS.contains(&name.as_str()) {
+            return true;
+        }
+        if DROPPED_RESPONSE_HEADERS.contains(&name.as_str()) {
+            return false;
+        }
+        !self.drop.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => *pattern == name,
+        })
+    }
+}
+
+/// Per-provider authentication scheme (NULL in the database = CLI default)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// Authorization: Bearer <key>
+    Bearer,
+    /// x-api-key: <key> + anthropic-version (official Anthropic API)
+    XApiKey,
+    /// ?key=<key> appended to the upstream URL
+    QueryParam,
+    /// Forward the client's own credentials untouched
+    Passthrough,
+}
+
+impl AuthScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthScheme::Bearer => "bearer",
+            AuthScheme::XApiKey => "x-api-key",
+            AuthScheme::QueryParam => "query_param",
+            AuthScheme::Passthrough => "passthrough",
+        }
+    }
+
+    /// Parse the provider column; unknown values fall back to the CLI default
+    pub fn from_provider(auth_scheme: Option<&str>) -> Option<Self> {
+        auth_scheme.and_then(|s| s.parse().ok())
+    }
+}
+
+impl std::str::FromStr for AuthScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bearer" => Ok(AuthScheme::Bearer),
+            "x-api-key" => Ok(AuthScheme::XApiKey),
+            "query_param" => Ok(AuthScheme::QueryParam),
+            "passthrough" => Ok(AuthScheme::Passthrough),
+            _ => Err(format!(
+                "Unknown auth scheme: {} (expected bearer, x-api-key, query_param or passthrough)",
+                s
+            )),
+        }
+    }
+}
+
+/// Upstream API flavor for Codex providers (NULL in the database = OpenAI-compatible)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderFlavor {
+    OpenAi,
+    /// Azure OpenAI: `/openai/deployments/{deployment}/...`, `api-key` header, `api-version` query
+    AzureOpenAi,
+}
+
+impl ProviderFlavor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderFlavor::OpenAi => "openai",
+            ProviderFlavor::AzureOpenAi => "azure_openai",
+        }
+    }
+
+    pub fn from_provider(flavor: Option<&str>) -> Self {
+        flavor.and_then(|s| s.parse().ok()).unwrap_or(ProviderFlavor::OpenAi)
+    }
+}
+
+impl std::str::FromStr for ProviderFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openai" => Ok(ProviderFlavor::OpenAi),
+            "azure_openai" => Ok(ProviderFlavor::AzureOpenAi),
+            _ => Err(format!("Unknown provider flavor: {} (expected openai or azure_openai)", s)),
+        }
+    }
+}
+
+/// Upstream wire API for Codex providers (NULL in the database = Responses API)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireApi {
+    Responses,
+    /// Translate `/v1/responses` to `/v1/chat/completions` (see services::responses_chat)
+    Chat,
+}
+
+impl WireApi {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WireApi::Responses => "responses",
+            WireApi::Chat => "chat",
+        }
+    }
+
+    pub fn from_provider(wire_api: Option<&str>) -> Self {
+        wire_api.and_then(|s| s.parse().ok()).unwrap_or(WireApi::Responses)
+    }
+}
+
+impl std::str::FromStr for WireApi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "responses" => Ok(WireApi::Responses),
+            "chat" => Ok(WireApi::Chat),
+            _ => Err(format!("Unknown wire API: {} (expected responses or chat)", s)),
+        }
+    }
+}
+
+/// Upstream protocol for Claude Code / Gemini providers (NULL in the database = the CLI's native API)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderProtocol {
+    Native,
+    /// Translate Messages / generateContent requests to `/v1/chat/completions` (see services::translate)
+    OpenAi,
+}
+
+impl ProviderProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderProtocol::Native => "native",
+            ProviderProtocol::OpenAi => "openai",
+        }
+    }
+
+    pub fn from_provider(protocol: Option<&str>) -> Self {
+        protocol.and_then(|s| s.parse().ok()).unwrap_or(ProviderProtocol::Native)
+    }
+}
+
+impl std::str::FromStr for ProviderProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(ProviderProtocol::Native),
+            "openai" => Ok(ProviderProtocol::OpenAi),
+            _ => Err(format!("Unknown protocol: {} (expected native or openai)", s)),
+        }
+    }
+}
+
+/// api-version injected for Azure OpenAI when neither the client nor extra_query_params set one
+pub const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
+
+/// Map an OpenAI-style path onto an Azure deployment:
+/// `/v1/chat/completions` -> `/openai/deployments/{deployment}/chat/completions`
+pub fn azure_openai_path(path: &str, deployment: &str) -> String {
+    if path.starts_with("/openai/") {
+        return path.to_string();
+    }
+    let rest = path.strip_prefix("/v1").unwrap_or(path);
+    format!("/openai/deployments/{}{}", urlencoding::encode(deployment), rest)
+}
+
+/// Append `api-version` unless the URL already carries one
+pub fn append_azure_api_version(url: &str) -> String {
+    let has_version = url
+        .split_once('?')
+        .is_some_and(|(_, q)| q.split('&').any(|p| p.split('=').next() == Some("api-version")));
+    if has_version {
+        return url.to_string();
+    }
+    let separator = if !url.contains('?') {
+        "?"
+    } else if url.ends_with('?') || url.ends_with('&') {
+        ""
+    } else {
+        "&"
+    };
+    format!("{}{}api-version={}", url, separator, DEFAULT_AZURE_API_VERSION)
+}
+
+/// Azure OpenAI authenticates with `api-key: <key>` instead of a bearer token
+pub fn set_azure_api_key(headers: &mut reqwest::header::HeaderMap, api_key: &str) {
+    for name in CLIENT_AUTH_HEADERS {
+        headers.remove(*name);
+    }
+    if let Ok(value) = reqwest::header::HeaderValue::from_str(api_key) {
+        headers.insert("api-key", value);
+    }
+}
+
+/// Default anthropic-version sent with x-api-key auth when the client did not set one
+const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Client credential headers replaced when the gateway injects its own key
+const CLIENT_AUTH_HEADERS: &[&str] = &["authorization", "x-api-key", "x-goog-api-key", "api-key"];
+
+fn credential_matches(value: &str, token: &str) -> bool {
+    let value = value.trim();
+    let value = value
+        .strip_prefix("Bearer ")
+        .or_else(|| value.strip_prefix("bearer "))
+        .unwrap_or(value);
+    token_matches(value, token)
+}
+
+/// Compare a presented gateway token in constant time (only the length leaks),
+/// so response timing does not reveal how much of the token was right
+pub fn token_matches(presented: &str, token: &str) -> bool {
+    let (presented, token) = (presented.trim().as_bytes(), token.as_bytes());
+    presented.len() == token.len() && presented.iter().zip(token).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Whether the client sent the gateway token in Authorization, x-api-key or x-goog-api-key
+pub fn client_presents_token(headers: &HeaderMap, token: &str) -> bool {
+    CLIENT_AUTH_HEADERS
+        .iter()
+        .filter_map(|name| headers.get(*name))
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| credential_matches(v, token))
+}
+
+/// Drop the gateway token so it is neither forwarded upstream nor stored in the request log
+pub fn strip_gateway_token(headers: &mut HeaderMap, token: &str) {
+    for name in CLIENT_AUTH_HEADERS {
+        let matches = headers
+            .get(*name)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| credential_matches(v, token));
+        if matches {
+            headers.remove(*name);
+        }
+    }
+}
+
+/// Set authentication header based on the provider auth scheme, or the CLI type by default
 pub fn set_auth_header(
     headers: &mut reqwest::header::HeaderMap,
     api_key: &str,
     cli_type: CliType,
+    auth_scheme: Option<AuthScheme>,
 ) {
+    if let Some(scheme) = auth_scheme {
+        if scheme == AuthScheme::Passthrough {
+            return;
+        }
+        // 显式指定了认证方式时，先清掉客户端自带的凭证，避免上游收到两套 key
+        for name in CLIENT_AUTH_HEADERS {
+            headers.remove(*name);
+        }
+        match scheme {
+            AuthScheme::Bearer => {
+                if let Ok(value) = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", api_key)) {
+                    headers.insert(reqwest::header::AUTHORIZATION, value);
+                }
+            }
+            AuthScheme::XApiKey => {
+                if let Ok(value) = reqwest::header::HeaderValue::from_str(api_key) {
+                    headers.insert("x-api-key", value);
+                }
+                if !headers.contains_key("anthropic-version") {
+                    headers.insert(
+                        "anthropic-version",
+                        reqwest::header::HeaderValue::from_static(DEFAULT_ANTHROPIC_VERSION),
+                    );
+                }
+            }
+            // 由 append_auth_query_param 写入 URL
+            AuthScheme::QueryParam | AuthScheme::Passthrough => {}
+        }
+        return;
+    }
+
     match cli_type {
         CliType::ClaudeCode => {
             // Claude uses Authorization: Bearer
@@ -379,6 +1525,154 @@ pub fn set_auth_header(
     }
 }
 
+/// Merge provider-level static headers (JSON object) into the outgoing headers.
+/// Returns the names that were applied so the caller can mask them in logs.
+pub fn apply_custom_headers(
+    headers: &mut reqwest::header::HeaderMap,
+    custom_headers: Option<&str>,
+) -> Vec<reqwest::header::HeaderName> {
+    let Some(map) = custom_headers
+        .and_then(|s| serde_json::from_str::<std::collections::BTreeMap<String, String>>(s).ok())
+    else {
+        return Vec::new();
+    };
+
+    let mut applied = Vec::new();
+    for (name, value) in map {
+        let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(name.trim().as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) else {
+            continue;
+        };
+        headers.insert(name.clone(), value);
+        applied.push(name);
+    }
+    applied
+}
+
+/// Replace the client's User-Agent with the provider's proxy_user_agent, else the gateway default.
+/// Neither set keeps the client's User-Agent.
+pub fn apply_proxy_user_agent(
+    headers: &mut reqwest::header::HeaderMap,
+    provider_user_agent: Option<&str>,
+    default_user_agent: Option<&str>,
+) {
+    let user_agent = [provider_user_agent, default_user_agent]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .find(|ua| !ua.is_empty());
+    if let Some(value) = user_agent.and_then(|ua| reqwest::header::HeaderValue::from_str(ua).ok()) {
+        headers.insert(reqwest::header::USER_AGENT, value);
+    }
+}
+
+/// Rewrite the request path with provider prefix rules (JSON array of
+/// `{from_prefix, to_prefix}`). Rules are evaluated in order; first match wins.
+/// A prefix only matches whole segments: `/v1` matches `/v1`, `/v1/messages` and `/v1?x`, not `/v1beta`.
+pub fn apply_path_rewrites(path: &str, path_rewrite_rules: Option<&str>) -> String {
+    let Some(rules) = path_rewrite_rules
+        .and_then(|s| serde_json::from_str::<Vec<crate::db::models::PathRewriteRule>>(s).ok())
+    else {
+        return path.to_string();
+    };
+
+    for rule in &rules {
+        let Some(rest) = path.strip_prefix(rule.from_prefix.as_str()) else {
+            continue;
+        };
+        if rest.is_empty() || rest.starts_with(['/', '?']) || rule.from_prefix.ends_with('/') {
+            return format!("{}{}", rule.to_prefix, rest);
+        }
+    }
+    path.to_string()
+}
+
+/// Append provider-level query parameters (JSON object) to the upstream URL,
+/// keeping any query string the client already sent (e.g. Gemini `?alt=sse`)
+pub fn append_query_params(url: &str, extra_query_params: Option<&str>) -> String {
+    let Some(params) = extra_query_params
+        .and_then(|s| serde_json::from_str::<std::collections::BTreeMap<String, String>>(s).ok())
+        .filter(|p| !p.is_empty())
+    else {
+        return url.to_string();
+    };
+
+    let encoded: Vec<String> = params
+        .iter()
+        .map(|(k, v)| format!("{}={}", urlencoding::encode(k.trim()), urlencoding::encode(v)))
+        .collect();
+
+    let separator = if !url.contains('?') {
+        "?"
+    } else if url.ends_with('?') || url.ends_with('&') {
+        ""
+    } else {
+        "&"
+    };
+    format!("{}{}{}", url, separator, encoded.join("&"))
+}
+
+/// Rewrite the `key` query parameter: `None` drops it, `Some(v)` replaces its value.
+/// Other parameters (e.g. Gemini `alt=sse`) keep their order.
+fn rewrite_key_query_param(url: &str, replacement: Option<&str>) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+
+    let params: Vec<String> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| {
+            let name = p.split('=').next().unwrap_or("");
+            if name != "key" {
+                return Some(p.to_string());
+            }
+            replacement.map(|v| format!("key={}", v))
+        })
+        .collect();
+
+    if params.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, params.join("&"))
+    }
+}
+
+/// Append the API key as `key=<api_key>` for the query_param auth scheme,
+/// replacing any `key` the client already sent
+pub fn append_auth_query_param(url: &str, api_key: &str) -> String {
+    let url = rewrite_key_query_param(url, None);
+    let separator = if !url.contains('?') {
+        "?"
+    } else if url.ends_with('?') || url.ends_with('&') {
+        ""
+    } else {
+        "&"
+    };
+    format!("{}{}key={}", url, separator, urlencoding::encode(api_key))
+}
+
+/// Apply the auth scheme to the upstream URL, returning (upstream URL, URL for logs).
+/// When the gateway injects its own credentials the client's `key` is dropped;
+/// the logged URL never contains a key in clear text.
+pub fn apply_auth_query(url: &str, api_key: &str, auth_scheme: Option<AuthScheme>) -> (String, String) {
+    match auth_scheme {
+        Some(AuthScheme::QueryParam) => {
+            let url = append_auth_query_param(url, api_key);
+            let logged = mask_secret_query_params(&url);
+            (url, logged)
+        }
+        None | Some(AuthScheme::Passthrough) => (url.to_string(), mask_secret_query_params(url)),
+        Some(_) => {
+            let url = rewrite_key_query_param(url, None);
+            let logged = mask_secret_query_params(&url);
+            (url, logged)
+        }
+    }
+}
+
 /// Build upstream URL from provider base URL and request path
 pub fn build_upstream_url(base_url: &str, path: &str, cli_type: CliType) -> String {
     let base = base_url.trim_end_matches('/');
@@ -405,6 +1699,10 @@ pub struct TimeoutConfig {
     pub first_byte_timeout: Duration,
     pub idle_timeout: Duration,
     pub non_stream_timeout: Duration,
+    /// Extra idle allowance per streamed output token (None = fixed idle timeout)
+    pub per_token_ms: Option<Duration>,
+    /// Interval of SSE keep-alive comments sent while the upstream is silent (None = off)
+    pub keepalive_interval: Option<Duration>,
 }
 
 impl Default for TimeoutConfig {
@@ -413,6 +1711,8 @@ impl Default for TimeoutConfig {
             first_byte_timeout: Duration::from_secs(60),
             idle_timeout: Duration::from_secs(30),
             non_stream_timeout: Duration::from_secs(120),
+            per_token_ms: Some(Duration::from_millis(500)),
+            keepalive_interval: None,
         }
     }
 }
@@ -422,11 +1722,513 @@ impl TimeoutConfig {
         stream_first_byte_timeout: i64,
         stream_idle_timeout: i64,
         non_stream_timeout: i64,
+        stream_per_token_timeout_ms: i64,
+        stream_keepalive_interval: i64,
     ) -> Self {
         Self {
             first_byte_timeout: Duration::from_secs(stream_first_byte_timeout as u64),
             idle_timeout: Duration::from_secs(stream_idle_timeout as u64),
             non_stream_timeout: Duration::from_secs(non_stream_timeout as u64),
+            per_token_ms: (stream_per_token_timeout_ms > 0)
+                .then(|| Duration::from_millis(stream_per_token_timeout_ms as u64)),
+            keepalive_interval: (stream_keepalive_interval > 0)
+                .then(|| Duration::from_secs(stream_keepalive_interval as u64)),
+        }
+    }
+
+    /// Stream idle timeout after `output_tokens` have been received:
+    /// max(idle_timeout, output_tokens * per_token_ms), so long generations get more slack.
+    pub fn stream_idle_timeout(&self, output_tokens: i64) -> Duration {
+        match self.per_token_ms {
+            Some(per_token) if output_tokens > 0 => {
+                self.idle_timeout.max(per_token.saturating_mul(output_tokens.min(u32::MAX as i64) as u32))
+            }
+            _ => self.idle_timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rewrite(body: Value, rules: Value) -> Option<(Value, usize)> {
+        let (out, applied) = apply_body_rewrite_rules(&serde_json::to_vec(&body).unwrap(), Some(&rules.to_string()))?;
+        Some((serde_json::from_slice(&out).unwrap(), applied))
+    }
+
+    fn strip(body: Value, params: &[&str]) -> Option<(Value, Vec<String>)> {
+        let params = serde_json::to_string(params).unwrap();
+        let (out, removed) = strip_body_params(&serde_json::to_vec(&body).unwrap(), Some(&params))?;
+        Some((serde_json::from_slice(&out).unwrap(), removed))
+    }
+
+    #[test]
+    fn strip_params_remove_keys_through_arrays() {
+        let body = json!({
+            "metadata": {"user_id": "u"},
+            "messages": [
+                {"role": "user", "content": [{"type": "text", "text": "hi", "cache_control": {"type": "ephemeral"}}]},
+                {"role": "assistant", "content": "plain"}
+            ]
+        });
+        let (out, removed) = strip(body, &["metadata", "messages.content.cache_control", "absent"]).unwrap();
+        assert_eq!(removed, vec!["metadata", "messages.content.cache_control"]);
+        assert_eq!(out, json!({
+            "messages": [
+                {"role": "user", "content": [{"type": "text", "text": "hi"}]},
+                {"role": "assistant", "content": "plain"}
+            ]
+        }));
+    }
+
+    #[test]
+    fn strip_params_accept_rewrite_path_syntax() {
+        let body = json!({"tools": [{"name": "a", "cache_control": {}}, {"name": "b", "cache_control": {}}]});
+        let (out, _) = strip(body, &["tools[0].cache_control"]).unwrap();
+        assert_eq!(out, json!({"tools": [{"name": "a"}, {"name": "b", "cache_control": {}}]}));
+        assert!(strip(json!({"a": 1}), &["b"]).is_none());
+        assert!(validate_strip_param("a..b").is_err());
+        assert!(validate_strip_param("tools[x]").is_err());
+    }
+
+    #[test]
+    fn strip_params_ignore_empty_or_malformed_lists() {
+        let body = serde_json::to_vec(&json!({"metadata": {}})).unwrap();
+        assert!(strip_body_params(&body, None).is_none());
+        assert!(strip_body_params(&body, Some("[]")).is_none());
+        assert!(strip_body_params(&body, Some("metadata")).is_none());
+        assert!(strip_body_params(b"not json", Some(r#"["metadata"]"#)).is_none());
+        // 非法路径只跳过自身，不影响其它条目
+        let (out, removed) = strip(json!({"metadata": {}, "thinking": {}}), &["a..b", " thinking "]).unwrap();
+        assert_eq!(removed, vec![" thinking "]);
+        assert_eq!(out, json!({"metadata": {}}));
+    }
+
+    #[test]
+    fn strip_params_remove_nested_keys_only() {
+        let body = json!({"generationConfig": {"thinkingConfig": {"budget": 1}, "temperature": 0.2}});
+        let (out, _) = strip(body, &["generationConfig.thinkingConfig"]).unwrap();
+        assert_eq!(out, json!({"generationConfig": {"temperature": 0.2}}));
+    }
+
+    #[test]
+    fn rewrite_rules_apply_in_order() {
+        let body = json!({"model": "m", "max_tokens": 10, "tools": [{"name": "a", "cache_control": {}}, {"name": "b"}]});
+        let rules = json!([
+            {"op": "set", "path": "max_tokens", "value": 20},
+            {"op": "remove", "path": "tools[*].cache_control"},
+            {"op": "rename", "path": "max_tokens", "to": "max_output_tokens"},
+            {"op": "set", "path": "model", "value": "m"}
+        ]);
+        let (out, applied) = rewrite(body, rules).unwrap();
+        assert_eq!(applied, 3);
+        assert_eq!(out, json!({"model": "m", "max_output_tokens": 20, "tools": [{"name": "a"}, {"name": "b"}]}));
+    }
+
+    #[test]
+    fn set_creates_missing_objects() {
+        let (out, applied) = rewrite(json!({}), json!([{"op": "set", "path": "generationConfig.thinkingConfig.budget", "value": 0}])).unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(out, json!({"generationConfig": {"thinkingConfig": {"budget": 0}}}));
+    }
+
+    #[test]
+    fn set_leaves_no_intermediates_when_the_path_fails() {
+        // `a` 不存在：新建的对象上没有下标 0，整条规则不生效，也不留下 `a: {}`
+        let rules = json!([{"op": "set", "path": "a.b[0].c", "value": 1}]);
+        assert!(rewrite(json!({"x": 1}), rules).is_none());
+        let rules = json!([{"op": "set", "path": "x.y", "value": 1}]);
+        assert!(rewrite(json!({"x": 1}), rules).is_none());
+    }
+
+    #[test]
+    fn index_paths_only_touch_existing_elements() {
+        let body = json!({"messages": [{"role": "system"}, {"role": "user"}]});
+        let rules = json!([
+            {"op": "remove", "path": "messages[0]"},
+            {"op": "set", "path": "messages[5].role", "value": "x"}
+        ]);
+        let (out, applied) = rewrite(body, rules).unwrap();
+        assert_eq!(applied, 1);
+        assert_eq!(out, json!({"messages": [{"role": "user"}]}));
+    }
+
+    #[test]
+    fn path_rewrites_match_whole_segments() {
+        let rules = r#"[{"from_prefix":"/v1","to_prefix":"/api/v2"}]"#;
+        assert_eq!(apply_path_rewrites("/v1", Some(rules)), "/api/v2");
+        assert_eq!(apply_path_rewrites("/v1/messages?beta=true", Some(rules)), "/api/v2/messages?beta=true");
+        assert_eq!(apply_path_rewrites("/v1?key=x", Some(rules)), "/api/v2?key=x");
+        assert_eq!(apply_path_rewrites("/v1beta/models", Some(rules)), "/v1beta/models");
+    }
+
+    #[test]
+    fn path_rewrites_fall_through_to_later_rules() {
+        let rules = r#"[{"from_prefix":"/v1","to_prefix":"/a"},{"from_prefix":"/v1beta/","to_prefix":"/b/"}]"#;
+        assert_eq!(apply_path_rewrites("/v1beta/models", Some(rules)), "/b/models");
+        assert_eq!(apply_path_rewrites("/other", Some(rules)), "/other");
+        assert_eq!(apply_path_rewrites("/v1", None), "/v1");
+    }
+
+    #[test]
+    fn invalid_bodies_and_rules_are_left_alone() {
+        assert!(apply_body_rewrite_rules(b"not json", Some(r#"[{"op":"remove","path":"a"}]"#)).is_none());
+        assert!(rewrite(json!({"a": 1}), json!([{"op": "remove", "path": "a[x"}])).is_none());
+        assert!(rewrite(json!({"a": 1}), json!([])).is_none());
+    }
+
+    #[test]
+    fn wildcard_set_and_rename_touch_every_element() {
+        let body = json!({"tools": [{"name": "a", "input_schema": {}}, {"name": "b", "input_schema": {}}], "messages": []});
+        let rules = json!([
+            {"op": "set", "path": "tools[*].strict", "value": true},
+            {"op": "rename", "path": "tools[*].input_schema", "to": " parameters "},
+            {"op": "remove", "path": "messages[*]"}
+        ]);
+        let (out, applied) = rewrite(body, rules).unwrap();
+        // 空数组上的 [*] 什么也不改，不计入
+        assert_eq!(applied, 2);
+        assert_eq!(out, json!({
+            "tools": [{"name": "a", "parameters": {}, "strict": true}, {"name": "b", "parameters": {}, "strict": true}],
+            "messages": []
+        }));
+    }
+
+    #[test]
+    fn rules_that_change_nothing_are_not_counted() {
+        let body = json!({"model": "m", "stream": true});
+        let rules = json!([
+            {"op": "set", "path": "stream", "value": true},
+            {"op": "rename", "path": "model", "to": "model"},
+            {"op": "rename", "path": "absent", "to": "x"},
+            {"op": "remove", "path": "model.inner"}
+        ]);
+        assert!(rewrite(body, rules).is_none());
+    }
+
+    fn validate(rules: Value) -> Result<(), String> {
+        validate_body_rewrite_rules(&serde_json::from_value::<Vec<crate::db::models::BodyRewriteRule>>(rules).unwrap())
+    }
+
+    #[test]
+    fn rewrite_rules_are_validated_on_save() {
+        assert!(validate(json!([
+            {"op": "set", "path": "tools[*].strict", "value": true},
+            {"op": "remove", "path": "messages[*]"},
+            {"op": "rename", "path": " max_tokens ", "to": "max_output_tokens"}
+        ]))
+        .is_ok());
+        for rules in [
+            json!([{"op": "remove", "path": "a..b"}]),
+            json!([{"op": "remove", "path": "a[1"}]),
+            json!([{"op": "remove", "path": "a[0]b"}]),
+            json!([{"op": "set", "path": "tools[*]", "value": 1}]),
+            json!([{"op": "rename", "path": "tools[0]", "to": "x"}]),
+            json!([{"op": "rename", "path": "a", "to": "b.c"}]),
+            json!([{"op": "rename", "path": "a", "to": "  "}]),
+        ] {
+            assert!(validate(rules.clone()).is_err(), "{} should be rejected", rules);
         }
     }
+
+    const CLAUDE_SSE: &str = "event: message_start\r\n\
+data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":1200,\"output_tokens\":1,\"cache_read_input_tokens\":800}}}\r\n\r\n\
+event: content_block_delta\r\n\
+data: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"你好，世界\"}}\r\n\r\n\
+event: message_delta\r\n\
+data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":345}}\r\n\r\n\
+event: message_stop\r\n\
+data: {\"type\":\"message_stop\"}";
+
+    fn sse_usage(chunks: &[&[u8]], cli_type: CliType) -> TokenUsage {
+        let mut parser = SseUsageParser::new(cli_type);
+        for chunk in chunks {
+            parser.feed(chunk);
+        }
+        parser.finish()
+    }
+
+    #[test]
+    fn sse_usage_survives_every_split_offset() {
+        let bytes = CLAUDE_SSE.as_bytes();
+        let whole = sse_usage(&[bytes], CliType::ClaudeCode);
+        assert_eq!((whole.input_tokens, whole.output_tokens, whole.cache_read_tokens), (1200, 345, 800));
+        // 包括切在 \r\n 之间和多字节 UTF-8 字符中间
+        for at in 0..=bytes.len() {
+            let (head, tail) = bytes.split_at(at);
+            let split = sse_usage(&[head, tail], CliType::ClaudeCode);
+            assert_eq!((split.input_tokens, split.output_tokens, split.cache_read_tokens), (1200, 345, 800), "split at {}", at);
+        }
+    }
+
+    #[test]
+    fn sse_usage_survives_tiny_chunks() {
+        let chunks: Vec<&[u8]> = CLAUDE_SSE.as_bytes().chunks(3).collect();
+        let usage = sse_usage(&chunks, CliType::ClaudeCode);
+        assert_eq!((usage.input_tokens, usage.output_tokens), (1200, 345));
+    }
+
+    #[test]
+    fn sse_usage_reads_the_final_chat_completions_chunk() {
+        let stream = "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}],\"usage\":null}\n\n\
+data: {\"choices\":[],\"usage\":{\"prompt_tokens\":21,\"completion_tokens\":9}}\n\n\
+data: [DONE]\n\n";
+        let chunks: Vec<&[u8]> = stream.as_bytes().chunks(7).collect();
+        let usage = sse_usage(&chunks, CliType::Codex);
+        assert_eq!((usage.input_tokens, usage.output_tokens), (21, 9));
+    }
+
+    fn client_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "127.0.0.1:7788".parse().unwrap());
+        headers.insert("content-length", "12".parse().unwrap());
+        headers.insert("content-type", "application/json".parse().unwrap());
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+        headers.append("anthropic-beta", "interleaved-thinking-2025-05-14".parse().unwrap());
+        headers.append("anthropic-beta", "context-1m-2025-08-07".parse().unwrap());
+        headers.insert("x-api-key", "sk-client".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn anthropic_headers_are_forwarded_only_for_claude_code() {
+        let forwarded = filter_headers(&client_headers(), CliType::ClaudeCode);
+        let betas: Vec<_> = forwarded.get_all("anthropic-beta").iter().map(|v| v.to_str().unwrap()).collect();
+        assert_eq!(betas, ["interleaved-thinking-2025-05-14", "context-1m-2025-08-07"]);
+        assert_eq!(forwarded["anthropic-version"], "2023-06-01");
+        assert!(forwarded.contains_key("x-api-key"));
+        assert!(!forwarded.contains_key("host"));
+        assert!(!forwarded.contains_key("content-length"));
+
+        for cli_type in [CliType::Gemini, CliType::Codex] {
+            let forwarded = filter_headers(&client_headers(), cli_type);
+            for name in PASSTHROUGH_HEADERS {
+                assert!(!forwarded.contains_key(*name), "{} forwarded for {:?}", name, cli_type);
+            }
+            assert_eq!(forwarded["content-type"], "application/json");
+        }
+    }
+
+    fn model_map(source_model: &str, target_model: &str) -> ProviderModelMap {
+        ProviderModelMap {
+            id: 1,
+            provider_id: 1,
+            source_model: source_model.to_string(),
+            target_model: target_model.to_string(),
+            enabled: 1,
+            sort_order: 0,
+        }
+    }
+
+    fn gemini_mapping(path: &str, body: Value) -> (String, Value, Option<String>) {
+        let provider = ProviderWithMaps {
+            provider: Default::default(),
+            model_maps: vec![model_map("gemini-2.5-pro", "gemini-2.5-flash")],
+        };
+        let body = serde_json::to_vec(&body).unwrap();
+        let result = apply_url_model_mapping(&provider, path, &body, &provider.model_maps);
+        (result.path, serde_json::from_slice(&result.body).unwrap(), result.target_model)
+    }
+
+    #[test]
+    fn gemini_mapping_rewrites_generate_content_paths() {
+        let body = json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]});
+        let (path, out, target) = gemini_mapping("/v1beta/models/gemini-2.5-pro:generateContent", body.clone());
+        assert_eq!(path, "/v1beta/models/gemini-2.5-flash:generateContent");
+        assert_eq!(out, body);
+        assert_eq!(target.as_deref(), Some("gemini-2.5-flash"));
+
+        let (path, _, _) = gemini_mapping("/v1beta/models/gemini-2.5-pro:streamGenerateContent?alt=sse", body);
+        assert_eq!(path, "/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse");
+    }
+
+    #[test]
+    fn gemini_mapping_rewrites_body_model_fields() {
+        let (path, out, _) = gemini_mapping(
+            "/v1beta/models/gemini-2.5-pro:countTokens",
+            json!({"generateContentRequest": {"model": "models/gemini-2.5-pro", "contents": []}}),
+        );
+        assert_eq!(path, "/v1beta/models/gemini-2.5-flash:countTokens");
+        assert_eq!(out["generateContentRequest"]["model"], "models/gemini-2.5-flash");
+
+        let (_, out, _) = gemini_mapping(
+            "/v1beta/models/gemini-2.5-pro:generateContent",
+            json!({"model": "gemini-2.5-pro", "contents": []}),
+        );
+        assert_eq!(out["model"], "gemini-2.5-flash");
+
+        // 未命中映射时请求体原样保留
+        let (path, out, target) = gemini_mapping(
+            "/v1beta/models/gemini-2.0:countTokens",
+            json!({"generateContentRequest": {"model": "models/gemini-2.0"}}),
+        );
+        assert_eq!(path, "/v1beta/models/gemini-2.0:countTokens");
+        assert_eq!(out["generateContentRequest"]["model"], "models/gemini-2.0");
+        assert!(target.is_none());
+    }
+
+    #[test]
+    fn gemini_model_override_follows_into_the_body() {
+        let body = serde_json::to_vec(&json!({"generateContentRequest": {"model": "models/a"}})).unwrap();
+        let (body, path) = apply_model_override(CliType::Gemini, &body, "/v1beta/models/a:countTokens", "b").unwrap();
+        assert_eq!(path, "/v1beta/models/b:countTokens");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["generateContentRequest"]["model"], "models/b");
+    }
+
+    #[test]
+    fn wildcard_targets_take_captures_in_order() {
+        assert_eq!(map_model("claude-*", "glm-*", "claude-sonnet-4").as_deref(), Some("glm-sonnet-4"));
+        assert_eq!(
+            map_model("claude-*-*", "vendor/*-v*", "claude-opus-4-1").as_deref(),
+            Some("vendor/opus-v4-1")
+        );
+        // `?` 只匹配一个字符，不产生捕获
+        assert_eq!(map_model("gpt-?-*", "openai/*", "gpt-5-mini").as_deref(), Some("openai/mini"));
+        assert_eq!(map_model("gpt-?-*", "openai/*", "gpt-55-mini"), None);
+        // 目标里多出的 * 没有对应捕获时替换为空
+        assert_eq!(map_model("a-*", "b-*-*", "a-x").as_deref(), Some("b-x-"));
+        // 源模式里的正则元字符按字面匹配
+        assert_eq!(map_model("o1.*", "o3*", "o1.mini").as_deref(), Some("o3mini"));
+        assert_eq!(map_model("o1.*", "o3*", "o1xmini"), None);
+    }
+
+    #[test]
+    fn wildcard_sources_without_target_stars_map_to_the_literal_target() {
+        assert_eq!(map_model("*haiku*", "fast-model", "claude-3-5-haiku-latest").as_deref(), Some("fast-model"));
+        assert_eq!(map_model("claude-?-opus", "big", "claude-3-opus").as_deref(), Some("big"));
+        assert_eq!(map_model("claude-?-opus", "big", "claude-35-opus"), None);
+        assert!(model_source_matches("**", ""));
+        assert!(!model_source_matches("claude-*", "gpt-4o"));
+    }
+
+    #[test]
+    fn regex_sources_expand_groups() {
+        assert_eq!(
+            map_model("re:claude-(\\w+)-(\\d+)", "anthropic/$1@$2", "claude-opus-4").as_deref(),
+            Some("anthropic/opus@4")
+        );
+        // 必须整体匹配
+        assert_eq!(map_model("re:opus", "x", "claude-opus-4"), None);
+    }
+
+    fn user_agent_after(provider: Option<&str>, default: Option<&str>) -> Option<String> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::USER_AGENT, "claude-cli/2.0.1".parse().unwrap());
+        apply_proxy_user_agent(&mut headers, provider, default);
+        headers.get(reqwest::header::USER_AGENT).map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn proxy_user_agent_prefers_the_provider_then_the_default() {
+        assert_eq!(user_agent_after(Some("curl/7.88"), Some("gateway/1")).as_deref(), Some("curl/7.88"));
+        assert_eq!(user_agent_after(Some("  "), Some(" gateway/1 ")).as_deref(), Some("gateway/1"));
+        assert_eq!(user_agent_after(None, None).as_deref(), Some("claude-cli/2.0.1"));
+        // 含控制字符的值不是合法头部，保留客户端的 User-Agent
+        assert_eq!(user_agent_after(Some("bad\nua"), None).as_deref(), Some("claude-cli/2.0.1"));
+    }
+
+    fn gemini_instruction_after(body: Value, prompts: &[&str]) -> Option<Value> {
+        let prompts: Vec<String> = prompts.iter().map(|p| p.to_string()).collect();
+        append_gemini_system_instruction(&serde_json::to_vec(&body).unwrap(), &prompts)
+            .map(|body| serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn gemini_system_instruction_is_created_when_missing() {
+        let body = serde_json::json!({ "contents": [{ "role": "user", "parts": [{ "text": "hi" }] }] });
+        let json = gemini_instruction_after(body, &["Be brief.", "  "]).unwrap();
+        assert_eq!(json["systemInstruction"], serde_json::json!({ "parts": [{ "text": "Be brief." }] }));
+        assert_eq!(json["contents"][0]["parts"][0]["text"], "hi");
+
+        let json = gemini_instruction_after(serde_json::json!({ "systemInstruction": null }), &["Be brief."]).unwrap();
+        assert_eq!(json["systemInstruction"]["parts"][0]["text"], "Be brief.");
+    }
+
+    #[test]
+    fn gemini_system_instruction_keeps_existing_parts_first() {
+        let body = serde_json::json!({
+            "systemInstruction": { "role": "system", "parts": [{ "text": "You are Gemini CLI." }] },
+        });
+        let json = gemini_instruction_after(body, &["Be brief.", "Answer in English."]).unwrap();
+        assert_eq!(json["systemInstruction"]["role"], "system");
+        let texts: Vec<&str> = json["systemInstruction"]["parts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(texts, ["You are Gemini CLI.", "Be brief.", "Answer in English."]);
+
+        // snake_case 写法原样保留，不另建 systemInstruction
+        let body = serde_json::json!({ "system_instruction": { "parts": [{ "text": "base" }] } });
+        let json = gemini_instruction_after(body, &["Be brief."]).unwrap();
+        assert!(json.get("systemInstruction").is_none());
+        assert_eq!(json["system_instruction"]["parts"][1]["text"], "Be brief.");
+    }
+
+    #[test]
+    fn gemini_prompts_already_embedded_are_not_repeated() {
+        // Gemini CLI 会把 GEMINI.md 拼进自己的 system instruction
+        let body = serde_json::json!({
+            "systemInstruction": { "parts": [{ "text": "You are Gemini CLI.\n\n# GEMINI.md\nBe brief." }] },
+        });
+        assert_eq!(gemini_instruction_after(body.clone(), &["Be brief."]), None);
+        let json = gemini_instruction_after(body, &[" Be brief. ", "Use tabs."]).unwrap();
+        assert_eq!(json["systemInstruction"]["parts"].as_array().unwrap().len(), 2);
+        assert_eq!(json["systemInstruction"]["parts"][1]["text"], "Use tabs.");
+    }
+
+    #[test]
+    fn gemini_system_instruction_ignores_unusable_bodies() {
+        assert_eq!(append_gemini_system_instruction(b"not json", &["Be brief.".to_string()]), None);
+        assert_eq!(append_gemini_system_instruction(b"[]", &["Be brief.".to_string()]), None);
+        let body = serde_json::json!({ "systemInstruction": "plain text" });
+        assert_eq!(gemini_instruction_after(body, &["Be brief."]), None);
+        assert_eq!(gemini_instruction_after(serde_json::json!({}), &[]), None);
+    }
+
+    #[test]
+    fn gateway_token_matches_only_the_exact_token() {
+        assert!(token_matches(" gw-secret-token ", "gw-secret-token"));
+        assert!(!token_matches("gw-secret-tokeX", "gw-secret-token"));
+        assert!(!token_matches("gw-secret", "gw-secret-token"));
+        assert!(!token_matches("", "gw-secret-token"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer gw-secret-token".parse().unwrap());
+        assert!(client_presents_token(&headers, "gw-secret-token"));
+        headers.insert("authorization", "Bearer gw-secret-tokens".parse().unwrap());
+        assert!(!client_presents_token(&headers, "gw-secret-token"));
+    }
+
+    fn injected(body: Value, path: &str, cli_type: CliType) -> Option<Value> {
+        inject_system_prompt(&serde_json::to_vec(&body).unwrap(), path, cli_type, "Be brief.")
+            .map(|body| serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn system_prompt_goes_into_generation_requests() {
+        let json = injected(json!({"messages": []}), "/v1/messages?beta=true", CliType::ClaudeCode).unwrap();
+        assert_eq!(json["system"], "Be brief.");
+        let json = injected(json!({"messages": []}), "/v1/messages/count_tokens", CliType::ClaudeCode).unwrap();
+        assert_eq!(json["system"], "Be brief.");
+        let json = injected(json!({"input": []}), "/v1/responses", CliType::Codex).unwrap();
+        assert_eq!(json["instructions"], "Be brief.");
+        let json = injected(json!({"contents": []}), "/v1beta/models/gemini-2.5-pro:streamGenerateContent?alt=sse", CliType::Gemini).unwrap();
+        assert_eq!(json["systemInstruction"]["parts"][0]["text"], "Be brief.");
+    }
+
+    #[test]
+    fn system_prompt_skips_count_tokens_and_batches() {
+        // countTokens 只接受 contents / generateContentRequest
+        let body = json!({"contents": [{"role": "user", "parts": [{"text": "hi"}]}]});
+        assert_eq!(injected(body, "/v1beta/models/gemini-2.5-pro:countTokens", CliType::Gemini), None);
+        let body = json!({"requests": [{"custom_id": "a", "params": {"messages": []}}]});
+        assert_eq!(injected(body, "/v1/messages/batches", CliType::ClaudeCode), None);
+        assert_eq!(injected(json!({"input": "hi"}), "/v1/responses/input_tokens", CliType::Codex), None);
+        assert_eq!(injected(json!({"model": "m"}), "/v1/chat/completions", CliType::Codex), None);
+    }
 }
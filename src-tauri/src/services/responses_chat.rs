@@ -0,0 +1,1000 @@
+//! Translate Codex Responses API traffic to OpenAI chat/completions (provider `wire_api = "chat"`).
+//!
+//! Request: Responses `instructions` / `input` items / tools -> chat `messages` / tools.
+//! Response: chat completion JSON or SSE chunks -> Responses object / Responses SSE events.
+
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::services::chat_wire::{self, str_field};
+
+/// Request-side context needed to translate the chat response back
+#[derive(Debug, Clone, Default)]
+pub struct ChatTranslation {
+    pub model: String,
+    /// Freeform (`type: custom`) tools, exposed to chat as functions taking `{"input": string}`
+    pub custom_tools: HashSet<String>,
+}
+
+/// Whether the request path targets the Responses API (`.../responses`)
+pub fn is_responses_path(path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or(path);
+    path.ends_with("/responses")
+}
+
+/// `/v1/responses?x=y` -> `/v1/chat/completions?x=y`
+pub fn chat_completions_path(path: &str) -> String {
+    chat_wire::chat_completions_path(path, "/responses")
+}
+
+// ==================== Request: Responses -> Chat ====================
+
+/// Convert a Responses API request body into a chat/completions request body
+pub fn responses_to_chat_request(body: &[u8]) -> Result<(Vec<u8>, ChatTranslation), String> {
+    let req: Value = serde_json::from_slice(body).map_err(|e| format!("Invalid Responses request: {}", e))?;
+    let obj = req
+        .as_object()
+        .ok_or_else(|| "Responses request must be a JSON object".to_string())?;
+
+    let mut ctx = ChatTranslation {
+        model: str_field(&req, "model").unwrap_or_default().to_string(),
+        ..Default::default()
+    };
+
+    let mut tools = Vec::new();
+    for tool in obj.get("tools").and_then(|t| t.as_array()).into_iter().flatten() {
+        let Some(name) = str_field(tool, "name") else {
+            continue;
+        };
+        match str_field(tool, "type") {
+            Some("function") => {
+                let mut function = Map::new();
+                function.insert("name".to_string(), json!(name));
+                for key in ["description", "parameters", "strict"] {
+                    if let Some(v) = tool.get(key).filter(|v| !v.is_null()) {
+                        function.insert(key.to_string(), v.clone());
+                    }
+                }
+                tools.push(json!({ "type": "function", "function": function }));
+            }
+            Some("custom") => {
+                ctx.custom_tools.insert(name.to_string());
+                tools.push(json!({
+                    "type": "function",
+                    "function": {
+                        "name": name,
+                        "description": str_field(tool, "description").unwrap_or_default(),
+                        "parameters": {
+                            "type": "object",
+                            "properties": { "input": { "type": "string", "description": "Raw tool input" } },
+                            "required": ["input"]
+                        }
+                    }
+                }));
+            }
+            // local_shell / web_search 等内置工具在 chat 接口中没有对应物
+            _ => {}
+        }
+    }
+
+    let mut messages: Vec<Value> = Vec::new();
+    if let Some(instructions) = str_field(&req, "instructions").filter(|s| !s.is_empty()) {
+        messages.push(json!({ "role": "system", "content": instructions }));
+    }
+    match obj.get("input") {
+        Some(Value::String(text)) => messages.push(json!({ "role": "user", "content": text })),
+        Some(Value::Array(items)) => {
+            for item in items {
+                push_input_item(&mut messages, item);
+            }
+        }
+        _ => {}
+    }
+
+    let mut chat = Map::new();
+    chat.insert("model".to_string(), json!(ctx.model));
+    chat.insert("messages".to_string(), Value::Array(messages));
+    for key in ["temperature", "top_p", "parallel_tool_calls", "user", "stream"] {
+        if let Some(v) = obj.get(key).filter(|v| !v.is_null()) {
+            chat.insert(key.to_string(), v.clone());
+        }
+    }
+    if obj.get("stream").and_then(|v| v.as_bool()) == Some(true) {
+        // chat 流默认不返回 usage，需要显式开启
+        chat.insert("stream_options".to_string(), json!({ "include_usage": true }));
+    }
+    if let Some(max) = obj.get("max_output_tokens").filter(|v| !v.is_null()) {
+        chat.insert("max_tokens".to_string(), max.clone());
+    }
+    if let Some(effort) = obj.get("reasoning").and_then(|r| r.get("effort")).filter(|v| !v.is_null()) {
+        chat.insert("reasoning_effort".to_string(), effort.clone());
+    }
+    if let Some(format) = obj.get("text").and_then(|t| t.get("format")) {
+        match str_field(format, "type") {
+            Some("json_schema") => {
+                let mut schema = Map::new();
+                for key in ["name", "schema", "strict", "description"] {
+                    if let Some(v) = format.get(key).filter(|v| !v.is_null()) {
+                        schema.insert(key.to_string(), v.clone());
+                    }
+                }
+                chat.insert("response_format".to_string(), json!({ "type": "json_schema", "json_schema": schema }));
+            }
+            Some("json_object") => {
+                chat.insert("response_format".to_string(), json!({ "type": "json_object" }));
+            }
+            _ => {}
+        }
+    }
+    if !tools.is_empty() {
+        match obj.get("tool_choice") {
+            Some(Value::String(choice)) => {
+                chat.insert("tool_choice".to_string(), json!(choice));
+            }
+            Some(choice @ Value::Object(_)) => {
+                if let Some(name) = str_field(choice, "name") {
+                    chat.insert(
+                        "tool_choice".to_string(),
+                        json!({ "type": "function", "function": { "name": name } }),
+                    );
+                }
+            }
+            _ => {}
+        }
+        chat.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    let body = serde_json::to_vec(&Value::Object(chat)).map_err(|e| e.to_string())?;
+    Ok((body, ctx))
+}
+
+fn push_input_item(messages: &mut Vec<Value>, item: &Value) {
+    let item_type = str_field(item, "type").unwrap_or("message");
+    match item_type {
+        "message" => {
+            let role = match str_field(item, "role").unwrap_or("user") {
+                "developer" | "system" => "system",
+                "assistant" => "assistant",
+                _ => "user",
+            };
+            messages.push(json!({ "role": role, "content": convert_content(item.get("content")) }));
+        }
+        "function_call" | "custom_tool_call" => {
+            let arguments = if item_type == "custom_tool_call" {
+                json!({ "input": str_field(item, "input").unwrap_or_default() }).to_string()
+            } else {
+                str_field(item, "arguments").unwrap_or("{}").to_string()
+            };
+            let call = json!({
+                "id": str_field(item, "call_id").unwrap_or_default(),
+                "type": "function",
+                "function": { "name": str_field(item, "name").unwrap_or_default(), "arguments": arguments }
+            });
+            // 连续的工具调用合并到同一条 assistant 消息
+            if let Some(last) = messages
+                .last_mut()
+                .filter(|m| str_field(m, "role") == Some("assistant"))
+            {
+                if let Some(calls) = last.get_mut("tool_calls").and_then(|c| c.as_array_mut()) {
+                    calls.push(call);
+                } else {
+                    last["tool_calls"] = json!([call]);
+                }
+                return;
+            }
+            messages.push(json!({ "role": "assistant", "content": Value::Null, "tool_calls": [call] }));
+        }
+        "function_call_output" | "custom_tool_call_output" => {
+            let output = match item.get("output") {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Array(parts)) => parts
+                    .iter()
+                    .filter_map(|p| str_field(p, "text"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                Some(other) => other.to_string(),
+                None => String::new(),
+            };
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": str_field(item, "call_id").unwrap_or_default(),
+                "content": output
+            }));
+        }
+        // reasoning 等条目在 chat 接口中无法表达
+        _ => {}
+    }
+}
+
+/// Responses content parts -> chat content (plain string unless images are present)
+fn convert_content(content: Option<&Value>) -> Value {
+    let parts = match content {
+        Some(Value::String(s)) => return json!(s),
+        Some(Value::Array(parts)) => parts,
+        _ => return json!(""),
+    };
+
+    let mut texts = Vec::new();
+    let mut chat_parts = Vec::new();
+    let mut has_image = false;
+    for part in parts {
+        match str_field(part, "type") {
+            Some("input_text") | Some("output_text") | Some("text") => {
+                let text = str_field(part, "text").unwrap_or_default();
+                texts.push(text);
+                chat_parts.push(json!({ "type": "text", "text": text }));
+            }
+            Some("input_image") => {
+                if let Some(url) = str_field(part, "image_url") {
+                    has_image = true;
+                    chat_parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if has_image {
+        Value::Array(chat_parts)
+    } else {
+        json!(texts.join("\n"))
+    }
+}
+
+// ==================== Response: Chat -> Responses ====================
+
+fn response_id(chat_id: Option<&str>) -> String {
+    match chat_id.filter(|id| !id.is_empty()) {
+        Some(id) => format!("resp_{}", id.trim_start_matches("chatcmpl-")),
+        None => format!("resp_{}", chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()),
+    }
+}
+
+fn message_item(id: &str, text: &str, status: &str) -> Value {
+    json!({
+        "type": "message",
+        "id": id,
+        "status": status,
+        "role": "assistant",
+        "content": [{ "type": "output_text", "text": text, "annotations": [] }]
+    })
+}
+
+fn reasoning_item(id: &str, text: &str) -> Value {
+    json!({
+        "type": "reasoning",
+        "id": id,
+        "summary": [],
+        "content": [{ "type": "reasoning_text", "text": text }]
+    })
+}
+
+fn tool_call_item(ctx: &ChatTranslation, call_id: &str, name: &str, arguments: &str, status: &str) -> Value {
+    if ctx.custom_tools.contains(name) {
+        let input = serde_json::from_str::<Value>(arguments)
+            .ok()
+            .and_then(|v| str_field(&v, "input").map(|s| s.to_string()))
+            .unwrap_or_else(|| arguments.to_string());
+        json!({
+            "type": "custom_tool_call",
+            "id": format!("ctc_{}", call_id),
+            "status": status,
+            "call_id": call_id,
+            "name": name,
+            "input": input
+        })
+    } else {
+        json!({
+            "type": "function_call",
+            "id": format!("fc_{}", call_id),
+            "status": status,
+            "call_id": call_id,
+            "name": name,
+            "arguments": arguments
+        })
+    }
+}
+
+/// chat `usage` -> Responses `usage`
+fn responses_usage(usage: Option<&Value>) -> Value {
+    let Some(usage) = usage.filter(|u| u.is_object()) else {
+        return Value::Null;
+    };
+    let get = |v: &Value, key: &str| v.get(key).and_then(|n| n.as_i64()).unwrap_or(0);
+    let input = get(usage, "prompt_tokens");
+    let output = get(usage, "completion_tokens");
+    let cached = usage.get("prompt_tokens_details").map(|d| get(d, "cached_tokens")).unwrap_or(0);
+    let reasoning = usage
+        .get("completion_tokens_details")
+        .map(|d| get(d, "reasoning_tokens"))
+        .unwrap_or(0);
+    json!({
+        "input_tokens": input,
+        "input_tokens_details": { "cached_tokens": cached },
+        "output_tokens": output,
+        "output_tokens_details": { "reasoning_tokens": reasoning },
+        "total_tokens": usage.get("total_tokens").and_then(|n| n.as_i64()).unwrap_or(input + output)
+    })
+}
+
+fn response_object(
+    id: &str,
+    created_at: i64,
+    model: &str,
+    status: &str,
+    output: Vec<Value>,
+    usage: Value,
+    finish_reason: Option<&str>,
+) -> Value {
+    let incomplete_details = if finish_reason == Some("length") {
+        json!({ "reason": "max_output_tokens" })
+    } else {
+        Value::Null
+    };
+    json!({
+        "id": id,
+        "object": "response",
+        "created_at": created_at,
+        "status": status,
+        "model": model,
+        "output": output,
+        "usage": usage,
+        "incomplete_details": incomplete_details
+    })
+}
+
+/// Convert a non-streaming chat completion into a Responses API object
+pub fn chat_to_responses_body(body: &[u8], ctx: &ChatTranslation) -> Option<Vec<u8>> {
+    let chat: Value = serde_json::from_slice(body).ok()?;
+    let choice = chat.get("choices")?.get(0)?;
+    let message = choice.get("message")?;
+
+    let id = response_id(str_field(&chat, "id"));
+    let suffix = id.trim_start_matches("resp_");
+    let mut output = Vec::new();
+
+    if let Some(reasoning) = str_field(message, "reasoning_content").filter(|s| !s.is_empty()) {
+        output.push(reasoning_item(&format!("rs_{}", suffix), reasoning));
+    }
+    if let Some(text) = str_field(message, "content").filter(|s| !s.is_empty()) {
+        output.push(message_item(&format!("msg_{}", suffix), text, "completed"));
+    }
+    for call in message.get("tool_calls").and_then(|c| c.as_array()).into_iter().flatten() {
+        let function = call.get("function").cloned().unwrap_or(Value::Null);
+        output.push(tool_call_item(
+            ctx,
+            str_field(call, "id").unwrap_or_default(),
+            str_field(&function, "name").unwrap_or_default(),
+            str_field(&function, "arguments").unwrap_or("{}"),
+            "completed",
+        ));
+    }
+
+    let finish_reason = str_field(choice, "finish_reason");
+    let status = if finish_reason == Some("length") { "incomplete" } else { "completed" };
+    let response = response_object(
+        &id,
+        chat.get("created").and_then(|c| c.as_i64()).unwrap_or_else(|| chrono::Utc::now().timestamp()),
+        str_field(&chat, "model").unwrap_or(&ctx.model),
+        status,
+        output,
+        responses_usage(chat.get("usage")),
+        finish_reason,
+    );
+    serde_json::to_vec(&response).ok()
+}
+
+struct OpenText {
+    output_index: usize,
+    id: String,
+    text: String,
+}
+
+struct OpenToolCall {
+    output_index: usize,
+    call_id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Incrementally converts chat/completions SSE chunks into Responses API SSE events
+pub struct ChatToResponsesStream {
+    ctx: ChatTranslation,
+    buffer: Vec<u8>,
+    sequence: u64,
+    response_id: Option<String>,
+    created_at: i64,
+    model: String,
+    next_output_index: usize,
+    /// Finished output items with their output_index
+    output: Vec<(usize, Value)>,
+    reasoning: Option<OpenText>,
+    message: Option<OpenText>,
+    /// Open tool calls keyed by the chat `tool_calls[].index`
+    tool_calls: BTreeMap<u64, OpenToolCall>,
+    usage: Option<Value>,
+    finish_reason: Option<String>,
+    completed: bool,
+}
+
+impl ChatToResponsesStream {
+    pub fn new(ctx: ChatTranslation) -> Self {
+        let model = ctx.model.clone();
+        Self {
+            ctx,
+            buffer: Vec::new(),
+            sequence: 0,
+            response_id: None,
+            created_at: 0,
+            model,
+            next_output_index: 0,
+            output: Vec::new(),
+            reasoning: None,
+            message: None,
+            tool_calls: BTreeMap::new(),
+            usage: None,
+            finish_reason: None,
+            completed: false,
+        }
+    }
+
+    /// Feed raw upstream bytes; returns the Responses SSE bytes for all complete lines
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.buffer.extend_from_slice(chunk);
+        let Some(last_newline) = self.buffer.iter().rposition(|b| *b == b'\n') else {
+            return out;
+        };
+        let rest = self.buffer.split_off(last_newline + 1);
+        let complete = std::mem::replace(&mut self.buffer, rest);
+        for line in complete.split(|b| *b == b'\n') {
+            self.handle_line(line, &mut out);
+        }
+        out
+    }
+
+    /// Flush the trailing partial line and make sure `response.completed` was sent
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let line = std::mem::take(&mut self.buffer);
+        self.handle_line(&line, &mut out);
+        self.complete(&mut out);
+        out
+    }
+
+    fn handle_line(&mut self, line: &[u8], out: &mut Vec<u8>) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Ok(line) = std::str::from_utf8(line) else {
+            return;
+        };
+        let Some(data) = line.strip_prefix("data:").map(|d| d.trim()) else {
+            return;
+        };
+        if data == "[DONE]" {
+            self.complete(out);
+            return;
+        }
+        if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+            self.handle_chunk(&chunk, out);
+        }
+    }
+
+    fn emit(&mut self, out: &mut Vec<u8>, event_type: &str, mut data: Value) {
+        data["type"] = json!(event_type);
+        data["sequence_number"] = json!(self.sequence);
+        self.sequence += 1;
+        out.extend_from_slice(format!("event: {}\ndata: {}\n\n", event_type, data).as_bytes());
+    }
+
+    fn id(&self) -> &str {
+        self.response_id.as_deref().unwrap_or_default()
+    }
+
+    fn item_id(&self, prefix: &str) -> String {
+        format!("{}_{}_{}", prefix, self.id().trim_start_matches("resp_"), self.next_output_index)
+    }
+
+    fn snapshot(&self, status: &str) -> Value {
+        let mut output = self.output.clone();
+        output.sort_by_key(|(index, _)| *index);
+        response_object(
+            self.id(),
+            self.created_at,
+            &self.model,
+            status,
+            output.into_iter().map(|(_, item)| item).collect(),
+            responses_usage(self.usage.as_ref()),
+            self.finish_reason.as_deref(),
+        )
+    }
+
+    fn ensure_started(&mut self, chunk: Option<&Value>, out: &mut Vec<u8>) {
+        if self.response_id.is_some() {
+            return;
+        }
+        self.response_id = Some(response_id(chunk.and_then(|c| str_field(c, "id"))));
+        self.created_at = chunk
+            .and_then(|c| c.get("created"))
+            .and_then(|c| c.as_i64())
+            .unwrap_or_else(|| chrono::Utc::now().timestamp());
+        if let Some(model) = chunk.and_then(|c| str_field(c, "model")).filter(|m| !m.is_empty()) {
+            self.model = model.to_string();
+        }
+        let response = self.snapshot("in_progress");
+        self.emit(out, "response.created", json!({ "response": response }));
+        let response = self.snapshot("in_progress");
+        self.emit(out, "response.in_progress", json!({ "response": response }));
+    }
+
+    fn handle_chunk(&mut self, chunk: &Value, out: &mut Vec<u8>) {
+        if self.completed {
+            return;
+        }
+        if let Some(error) = chunk.get("error") {
+            self.ensure_started(None, out);
+            let mut response = self.snapshot("failed");
+            response["error"] = json!({
+                "code": error.get("code").cloned().unwrap_or(Value::Null),
+                "message": str_field(error, "message").unwrap_or("upstream error")
+            });
+            self.emit(out, "response.failed", json!({ "response": response }));
+            self.completed = true;
+            return;
+        }
+
+        self.ensure_started(Some(chunk), out);
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            self.usage = Some(usage.clone());
+        }
+
+        let Some(choice) = chunk.get("choices").and_then(|c| c.get(0)) else {
+            return;
+        };
+        if let Some(delta) = choice.get("delta") {
+            let reasoning = str_field(delta, "reasoning_content").or_else(|| str_field(delta, "reasoning"));
+            if let Some(text) = reasoning.filter(|s| !s.is_empty()) {
+                self.reasoning_delta(text, out);
+            }
+            if let Some(text) = str_field(delta, "content").filter(|s| !s.is_empty()) {
+                self.close_reasoning(out);
+                self.text_delta(text, out);
+            }
+            if let Some(calls) = delta.get("tool_calls").and_then(|c| c.as_array()) {
+                self.close_reasoning(out);
+                self.close_message(out);
+                for call in calls {
+                    self.tool_call_delta(call, out);
+                }
+            }
+        }
+        if let Some(reason) = str_field(choice, "finish_reason") {
+            self.finish_reason = Some(reason.to_string());
+            self.close_all(out);
+        }
+    }
+
+    fn reasoning_delta(&mut self, delta: &str, out: &mut Vec<u8>) {
+        if self.reasoning.is_none() {
+            let open = OpenText {
+                output_index: self.next_output_index,
+                id: self.item_id("rs"),
+                text: String::new(),
+            };
+            self.next_output_index += 1;
+            let item = json!({ "type": "reasoning", "id": open.id, "summary": [], "content": [] });
+            self.emit(out, "response.output_item.added", json!({ "output_index": open.output_index, "item": item }));
+            self.reasoning = Some(open);
+        }
+        let Some(open) = self.reasoning.as_mut() else {
+            return;
+        };
+        open.text.push_str(delta);
+        let data = json!({
+            "item_id": open.id,
+            "output_index": open.output_index,
+            "content_index": 0,
+            "delta": delta
+        });
+        self.emit(out, "response.reasoning_text.delta", data);
+    }
+
+    fn text_delta(&mut self, delta: &str, out: &mut Vec<u8>) {
+        if self.message.is_none() {
+            let open = OpenText {
+                output_index: self.next_output_index,
+                id: self.item_id("msg"),
+                text: String::new(),
+            };
+            self.next_output_index += 1;
+            let item = json!({
+                "type": "message",
+                "id": open.id,
+                "status": "in_progress",
+                "role": "assistant",
+                "content": []
+            });
+            self.emit(out, "response.output_item.added", json!({ "output_index": open.output_index, "item": item }));
+            let part = json!({
+                "item_id": open.id,
+                "output_index": open.output_index,
+                "content_index": 0,
+                "part": { "type": "output_text", "text": "", "annotations": [] }
+            });
+            self.emit(out, "response.content_part.added", part);
+            self.message = Some(open);
+        }
+        let Some(open) = self.message.as_mut() else {
+            return;
+        };
+        open.text.push_str(delta);
+        let data = json!({
+            "item_id": open.id,
+            "output_index": open.output_index,
+            "content_index": 0,
+            "delta": delta
+        });
+        self.emit(out, "response.output_text.delta", data);
+    }
+
+    fn tool_call_delta(&mut self, call: &Value, out: &mut Vec<u8>) {
+        let index = call.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+        let function = call.get("function").cloned().unwrap_or(Value::Null);
+
+        if !self.tool_calls.contains_key(&index) {
+            let call_id = str_field(call, "id")
+                .filter(|id| !id.is_empty())
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| self.item_id("call"));
+            let open = OpenToolCall {
+                output_index: self.next_output_index,
+                call_id,
+                name: str_field(&function, "name").unwrap_or_default().to_string(),
+                arguments: String::new(),
+            };
+            self.next_output_index += 1;
+            let item = tool_call_item(&self.ctx, &open.call_id, &open.name, "", "in_progress");
+            self.emit(out, "response.output_item.added", json!({ "output_index": open.output_index, "item": item }));
+            self.tool_calls.insert(index, open);
+        }
+
+        let Some(open) = self.tool_calls.get_mut(&index) else {
+            return;
+        };
+        if open.name.is_empty() {
+            if let Some(name) = str_field(&function, "name") {
+                open.name = name.to_string();
+            }
+        }
+        let Some(delta) = str_field(&function, "arguments").filter(|s| !s.is_empty()) else {
+            return;
+        };
+        open.arguments.push_str(delta);
+        // custom 工具的参数是包了一层的 JSON，只在结束时一次性给出 input
+        if self.ctx.custom_tools.contains(&open.name) {
+            return;
+        }
+        let data = json!({
+            "item_id": format!("fc_{}", open.call_id),
+            "output_index": open.output_index,
+            "delta": delta
+        });
+        self.emit(out, "response.function_call_arguments.delta", data);
+    }
+
+    fn close_reasoning(&mut self, out: &mut Vec<u8>) {
+        let Some(open) = self.reasoning.take() else {
+            return;
+        };
+        let done = json!({
+            "item_id": open.id,
+            "output_index": open.output_index,
+            "content_index": 0,
+            "text": open.text
+        });
+        self.emit(out, "response.reasoning_text.done", done);
+        let item = reasoning_item(&open.id, &open.text);
+        self.emit(out, "response.output_item.done", json!({ "output_index": open.output_index, "item": item }));
+        self.output.push((open.output_index, item));
+    }
+
+    fn close_message(&mut self, out: &mut Vec<u8>) {
+        let Some(open) = self.message.take() else {
+            return;
+        };
+        let done = json!({
+            "item_id": open.id,
+            "output_index": open.output_index,
+            "content_index": 0,
+            "text": open.text
+        });
+        self.emit(out, "response.output_text.done", done);
+        let part = json!({
+            "item_id": open.id,
+            "output_index": open.output_index,
+            "content_index": 0,
+            "part": { "type": "output_text", "text": open.text, "annotations": [] }
+        });
+        self.emit(out, "response.content_part.done", part);
+        let item = message_item(&open.id, &open.text, "completed");
+        self.emit(out, "response.output_item.done", json!({ "output_index": open.output_index, "item": item }));
+        self.output.push((open.output_index, item));
+    }
+
+    fn close_tool_calls(&mut self, out: &mut Vec<u8>) {
+        let calls = std::mem::take(&mut self.tool_calls);
+        for open in calls.into_values() {
+            let arguments = if open.arguments.is_empty() { "{}".to_string() } else { open.arguments };
+            if !self.ctx.custom_tools.contains(&open.name) {
+                let done = json!({
+                    "item_id": format!("fc_{}", open.call_id),
+                    "output_index": open.output_index,
+                    "arguments": arguments
+                });
+                self.emit(out, "response.function_call_arguments.done", done);
+            }
+            let item = tool_call_item(&self.ctx, &open.call_id, &open.name, &arguments, "completed");
+            self.emit(out, "response.output_item.done", json!({ "output_index": open.output_index, "item": item }));
+            self.output.push((open.output_index, item));
+        }
+    }
+
+    fn close_all(&mut self, out: &mut Vec<u8>) {
+        self.close_reasoning(out);
+        self.close_message(out);
+        self.close_tool_calls(out);
+    }
+
+    fn complete(&mut self, out: &mut Vec<u8>) {
+        if self.completed {
+            return;
+        }
+        self.completed = true;
+
+        if self.response_id.is_none() {
+            // 上游没有返回任何数据块，按失败处理，避免 Codex 一直等待 response.completed
+            self.ensure_started(None, out);
+            let mut response = self.snapshot("failed");
+            response["error"] = json!({ "code": "empty_stream", "message": "upstream stream ended without data" });
+            self.emit(out, "response.failed", json!({ "response": response }));
+            return;
+        }
+
+        self.close_all(out);
+        let status = if self.finish_reason.as_deref() == Some("length") { "incomplete" } else { "completed" };
+        let response = self.snapshot(status);
+        self.emit(out, "response.completed", json!({ "response": response }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_chat(req: Value) -> (Value, ChatTranslation) {
+        let (body, ctx) = responses_to_chat_request(req.to_string().as_bytes()).unwrap();
+        (serde_json::from_slice(&body).unwrap(), ctx)
+    }
+
+    /// Parse Responses SSE output into (event type, data) pairs
+    fn events(sse: &[u8]) -> Vec<(String, Value)> {
+        String::from_utf8(sse.to_vec())
+            .unwrap()
+            .split("\n\n")
+            .filter(|block| !block.is_empty())
+            .map(|block| {
+                let mut lines = block.lines();
+                let event = lines.next().unwrap().strip_prefix("event: ").unwrap().to_string();
+                let data = serde_json::from_str(lines.next().unwrap().strip_prefix("data: ").unwrap()).unwrap();
+                (event, data)
+            })
+            .collect()
+    }
+
+    fn stream(ctx: ChatTranslation, chunks: &[Value], split: usize) -> Vec<(String, Value)> {
+        let mut upstream: String = chunks.iter().map(|c| format!("data: {}\n\n", c)).collect();
+        upstream.push_str("data: [DONE]\n\n");
+        let mut translator = ChatToResponsesStream::new(ctx);
+        let mut out = Vec::new();
+        for piece in upstream.as_bytes().chunks(split) {
+            out.extend(translator.feed(piece));
+        }
+        out.extend(translator.finish());
+        events(&out)
+    }
+
+    #[test]
+    fn request_maps_instructions_input_and_tools() {
+        let (chat, ctx) = to_chat(json!({
+            "model": "gpt-5",
+            "instructions": "Be brief",
+            "stream": true,
+            "max_output_tokens": 256,
+            "reasoning": {"effort": "high"},
+            "input": [
+                {"type": "message", "role": "developer", "content": [{"type": "input_text", "text": "env"}]},
+                {"type": "message", "role": "user", "content": [{"type": "input_text", "text": "list files"}]},
+                {"type": "reasoning", "summary": []},
+                {"type": "function_call", "call_id": "c1", "name": "shell", "arguments": "{\"cmd\":\"ls\"}"},
+                {"type": "custom_tool_call", "call_id": "c2", "name": "apply_patch", "input": "*** Begin Patch"},
+                {"type": "function_call_output", "call_id": "c1", "output": "a.txt"},
+                {"type": "custom_tool_call_output", "call_id": "c2", "output": [{"type": "input_text", "text": "ok"}]}
+            ],
+            "tools": [
+                {"type": "function", "name": "shell", "parameters": {"type": "object"}},
+                {"type": "custom", "name": "apply_patch", "description": "patch"},
+                {"type": "web_search"}
+            ],
+            "tool_choice": "auto"
+        }));
+        assert!(ctx.custom_tools.contains("apply_patch"));
+        assert_eq!(chat["max_tokens"], 256);
+        assert_eq!(chat["reasoning_effort"], "high");
+        assert_eq!(chat["stream_options"], json!({"include_usage": true}));
+        assert_eq!(chat["tool_choice"], "auto");
+        assert_eq!(chat["tools"].as_array().unwrap().len(), 2);
+        assert_eq!(chat["tools"][1]["function"]["parameters"]["required"], json!(["input"]));
+        assert_eq!(
+            chat["messages"],
+            json!([
+                {"role": "system", "content": "Be brief"},
+                {"role": "system", "content": "env"},
+                {"role": "user", "content": "list files"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "c1", "type": "function", "function": {"name": "shell", "arguments": "{\"cmd\":\"ls\"}"}},
+                    {"id": "c2", "type": "function", "function": {"name": "apply_patch", "arguments": "{\"input\":\"*** Begin Patch\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "c1", "content": "a.txt"},
+                {"role": "tool", "tool_call_id": "c2", "content": "ok"}
+            ])
+        );
+    }
+
+    #[test]
+    fn request_keeps_images_as_content_parts() {
+        let (chat, _) = to_chat(json!({
+            "model": "gpt-5",
+            "input": [{"role": "user", "content": [
+                {"type": "input_text", "text": "what is this"},
+                {"type": "input_image", "image_url": "data:image/png;base64,AAAA"}
+            ]}]
+        }));
+        assert_eq!(
+            chat["messages"][0]["content"],
+            json!([
+                {"type": "text", "text": "what is this"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}}
+            ])
+        );
+        assert!(chat.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn non_streaming_response_becomes_output_items() {
+        let ctx = ChatTranslation {
+            model: "gpt-5".to_string(),
+            custom_tools: HashSet::from(["apply_patch".to_string()]),
+        };
+        let chat = json!({
+            "id": "chatcmpl-abc",
+            "created": 1700000000,
+            "model": "gpt-5-2025",
+            "choices": [{
+                "finish_reason": "tool_calls",
+                "message": {
+                    "role": "assistant",
+                    "reasoning_content": "thinking",
+                    "content": "Patching",
+                    "tool_calls": [{"id": "c9", "type": "function", "function": {"name": "apply_patch", "arguments": "{\"input\":\"diff\"}"}}]
+                }
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "prompt_tokens_details": {"cached_tokens": 4}}
+        });
+        let body = chat_to_responses_body(chat.to_string().as_bytes(), &ctx).unwrap();
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["id"], "resp_abc");
+        assert_eq!(response["status"], "completed");
+        assert_eq!(response["model"], "gpt-5-2025");
+        let output = response["output"].as_array().unwrap();
+        assert_eq!(output.len(), 3);
+        assert_eq!(output[0]["type"], "reasoning");
+        assert_eq!(output[1]["content"][0]["text"], "Patching");
+        assert_eq!(output[2]["type"], "custom_tool_call");
+        assert_eq!(output[2]["input"], "diff");
+        assert_eq!(
+            response["usage"],
+            json!({
+                "input_tokens": 10,
+                "input_tokens_details": {"cached_tokens": 4},
+                "output_tokens": 5,
+                "output_tokens_details": {"reasoning_tokens": 0},
+                "total_tokens": 15
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_response_is_incomplete() {
+        let chat = json!({"id": "x", "choices": [{"finish_reason": "length", "message": {"content": "partial"}}]});
+        let body = chat_to_responses_body(chat.to_string().as_bytes(), &ChatTranslation::default()).unwrap();
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["status"], "incomplete");
+        assert_eq!(response["incomplete_details"]["reason"], "max_output_tokens");
+    }
+
+    #[test]
+    fn streaming_text_and_tool_calls() {
+        let chunks = [
+            json!({"id": "chatcmpl-s1", "created": 1, "model": "gpt-5", "choices": [{"delta": {"role": "assistant", "content": "Hel"}}]}),
+            json!({"id": "chatcmpl-s1", "choices": [{"delta": {"content": "lo"}}]}),
+            json!({"id": "chatcmpl-s1", "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "c1", "function": {"name": "shell", "arguments": "{\"cmd\""}}]}}]}),
+            json!({"id": "chatcmpl-s1", "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": ":\"ls\"}"}}]}}]}),
+            json!({"id": "chatcmpl-s1", "choices": [{"delta": {}, "finish_reason": "tool_calls"}]}),
+            json!({"id": "chatcmpl-s1", "choices": [], "usage": {"prompt_tokens": 7, "completion_tokens": 3}}),
+        ];
+        // 逐字节喂入与整块喂入的结果必须一致
+        let whole = stream(ChatTranslation::default(), &chunks, usize::MAX);
+        assert_eq!(stream(ChatTranslation::default(), &chunks, 1), whole);
+
+        let types: Vec<&str> = whole.iter().map(|(event, _)| event.as_str()).collect();
+        assert_eq!(
+            types,
+            [
+                "response.created",
+                "response.in_progress",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.output_item.added",
+                "response.function_call_arguments.delta",
+                "response.function_call_arguments.delta",
+                "response.function_call_arguments.done",
+                "response.output_item.done",
+                "response.completed",
+            ]
+        );
+        for (i, (_, data)) in whole.iter().enumerate() {
+            assert_eq!(data["sequence_number"], i as u64);
+        }
+        let completed = &whole.last().unwrap().1["response"];
+        assert_eq!(completed["id"], "resp_s1");
+        assert_eq!(completed["output"][0]["content"][0]["text"], "Hello");
+        assert_eq!(completed["output"][1]["arguments"], "{\"cmd\":\"ls\"}");
+        assert_eq!(completed["usage"]["input_tokens"], 7);
+        assert_eq!(completed["usage"]["output_tokens"], 3);
+    }
+
+    #[test]
+    fn streaming_custom_tool_input_is_unwrapped_once() {
+        let ctx = ChatTranslation {
+            model: "gpt-5".to_string(),
+            custom_tools: HashSet::from(["apply_patch".to_string()]),
+        };
+        let chunks = [
+            json!({"id": "c", "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "p1", "function": {"name": "apply_patch", "arguments": "{\"input\":"}}]}}]}),
+            json!({"id": "c", "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": "\"diff\"}"}}]}, "finish_reason": "tool_calls"}]}),
+        ];
+        let events = stream(ctx, &chunks, 5);
+        assert!(events.iter().all(|(event, _)| !event.starts_with("response.function_call_arguments")));
+        let done = events.iter().find(|(event, _)| event == "response.output_item.done").unwrap();
+        assert_eq!(done.1["item"]["type"], "custom_tool_call");
+        assert_eq!(done.1["item"]["input"], "diff");
+    }
+
+    #[test]
+    fn streaming_errors_and_empty_streams_fail_the_response() {
+        let failed = stream(ChatTranslation::default(), &[json!({"error": {"message": "overloaded"}})], 64);
+        let (event, data) = failed.last().unwrap();
+        assert_eq!(event, "response.failed");
+        assert_eq!(data["response"]["error"]["message"], "overloaded");
+        assert!(failed.iter().all(|(event, _)| event != "response.completed"));
+
+        let mut translator = ChatToResponsesStream::new(ChatTranslation::default());
+        let (event, data) = events(&translator.finish()).pop().unwrap();
+        assert_eq!(event, "response.failed");
+        assert_eq!(data["response"]["error"]["code"], "empty_stream");
+    }
+}
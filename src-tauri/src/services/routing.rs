@@ -1,6 +1,28 @@
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use dashmap::DashMap;
 use sqlx::SqlitePool;
+use std::sync::{Arc, RwLock};
 
-use crate::db::models::{Provider, ProviderModelMap};
+use crate::db::models::{Provider, ProviderModelMap, ProviderSchedule};
+
+/// day_of_week mask matching every day
+pub const ALL_DAYS_MASK: i64 = 0x7f;
+
+/// All provider schedules, shared between the proxy and Tauri commands
+pub type ScheduleCache = Arc<RwLock<Vec<ProviderSchedule>>>;
+
+/// How long a session keeps its provider after its last request
+pub const STICKY_SESSION_TTL_SECS: i64 = 4 * 3600;
+
+/// Provider a session is pinned to (sticky_session_enabled)
+#[derive(Debug, Clone, Copy)]
+pub struct StickyProvider {
+    pub provider_id: i64,
+    pub expires_at: i64,
+}
+
+/// Session id (prefixed with the CLI type) -> pinned provider
+pub type SessionProviderMap = Arc<DashMap<String, StickyProvider>>;
 
 /// Provider with its model mappings
 #[derive(Debug, Clone)]
@@ -9,33 +31,90 @@ pub struct ProviderWithMaps {
     pub model_maps: Vec<ProviderModelMap>,
 }
 
+/// Rebuild the schedule cache from the provider_schedules table
+pub async fn reload_provider_schedules(db: &SqlitePool, cache: &ScheduleCache) -> Result<(), sqlx::Error> {
+    let schedules = sqlx::query_as::<_, ProviderSchedule>("SELECT * FROM provider_schedules ORDER BY id")
+        .fetch_all(db)
+        .await?;
+    *cache.write().unwrap_or_else(|e| e.into_inner()) = schedules;
+    Ok(())
+}
+
+/// Whether a schedule is active at the given local time
+pub fn schedule_is_active(schedule: &ProviderSchedule, at: NaiveDateTime) -> bool {
+    let day_bit = 1i64 << at.weekday().num_days_from_sunday();
+    if schedule.day_of_week & day_bit == 0 {
+        return false;
+    }
+    let hour = at.hour() as i64;
+    if schedule.hour_start <= schedule.hour_end {
+        hour >= schedule.hour_start && hour < schedule.hour_end
+    } else {
+        // 跨零点，例如 22 -> 6
+        hour >= schedule.hour_start || hour < schedule.hour_end
+    }
+}
+
+/// Reorder providers by their effective sort_order at the given local time.
+/// An active schedule replaces the provider's sort_order; when several are active the lowest wins.
+pub fn apply_provider_schedules(providers: &mut [Provider], schedules: &[ProviderSchedule], at: NaiveDateTime) {
+    let active: Vec<&ProviderSchedule> = schedules
+        .iter()
+        .filter(|s| schedule_is_active(s, at))
+        .collect();
+    if active.is_empty() {
+        return;
+    }
+
+    providers.sort_by_cached_key(|p| {
+        let effective = active
+            .iter()
+            .filter(|s| s.provider_id == p.id)
+            .map(|s| s.priority_override)
+            .min()
+            .unwrap_or(p.sort_order);
+        (effective, p.id)
+    });
+}
+
+/// Apply the cached schedules using the current local time
+fn apply_scheduled_order(providers: &mut [Provider], schedules: &ScheduleCache) {
+    let schedules = schedules.read().unwrap_or_else(|e| e.into_inner());
+    apply_provider_schedules(providers, &schedules, chrono::Local::now().naive_local());
+}
+
 /// Select an available provider for the given CLI type
 /// Returns None if all providers are blacklisted or none are configured
 pub async fn select_provider(
     db: &SqlitePool,
+    schedules: &ScheduleCache,
     cli_type: &str,
 ) -> Result<Option<ProviderWithMaps>, sqlx::Error> {
     let now = chrono::Utc::now().timestamp();
 
     // Query enabled providers ordered by sort_order, excluding blacklisted ones
-    let providers = sqlx::query_as::<_, Provider>(
+    let mut providers = sqlx::query_as::<_, Provider>(
         r#"
         SELECT * FROM providers
         WHERE cli_type = ?
           AND enabled = 1
           AND (blacklisted_until IS NULL OR blacklisted_until <= ?)
+          AND (budget_exceeded_until IS NULL OR budget_exceeded_until <= ?)
         ORDER BY sort_order, id
         "#,
     )
     .bind(cli_type)
     .bind(now)
+    .bind(now)
     .fetch_all(db)
     .await?;
 
+    apply_scheduled_order(&mut providers, schedules);
+
     // Return the first available provider with its model maps
     if let Some(provider) = providers.into_iter().next() {
         let model_maps = sqlx::query_as::<_, ProviderModelMap>(
-            "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY id",
+            "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY sort_order, id",
         )
         .bind(provider.id)
         .fetch_all(db)
@@ -50,28 +129,33 @@ pub async fn select_provider(
 /// Get all available providers for a CLI type (for fallback scenarios)
 pub async fn get_available_providers(
     db: &SqlitePool,
+    schedules: &ScheduleCache,
     cli_type: &str,
 ) -> Result<Vec<ProviderWithMaps>, sqlx::Error> {
     let now = chrono::Utc::now().timestamp();
 
-    let providers = sqlx::query_as::<_, Provider>(
+    let mut providers = sqlx::query_as::<_, Provider>(
         r#"
         SELECT * FROM providers
         WHERE cli_type = ?
           AND enabled = 1
           AND (blacklisted_until IS NULL OR blacklisted_until <= ?)
+          AND (budget_exceeded_until IS NULL OR budget_exceeded_until <= ?)
         ORDER BY sort_order, id
         "#,
     )
     .bind(cli_type)
     .bind(now)
+    .bind(now)
     .fetch_all(db)
     .await?;
 
+    apply_scheduled_order(&mut providers, schedules);
+
     let mut result = Vec::new();
     for provider in providers {
         let model_maps = sqlx::query_as::<_, ProviderModelMap>(
-            "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY id",
+            "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY sort_order, id",
         )
         .bind(provider.id)
         .fetch_all(db)
@@ -82,3 +166,161 @@ pub async fn get_available_providers(
 
     Ok(result)
 }
+
+/// The provider `id` if it can take requests for `cli_type` right now (enabled, not blacklisted, within budget)
+async fn available_provider(db: &SqlitePool, cli_type: &str, id: i64) -> Result<Option<ProviderWithMaps>, sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let provider = sqlx::query_as::<_, Provider>(
+        r#"
+        SELECT * FROM providers
+        WHERE id = ?
+          AND cli_type = ?
+          AND enabled = 1
+          AND (blacklisted_until IS NULL OR blacklisted_until <= ?)
+          AND (budget_exceeded_until IS NULL OR budget_exceeded_until <= ?)
+        "#,
+    )
+    .bind(id)
+    .bind(cli_type)
+    .bind(now)
+    .bind(now)
+    .fetch_optional(db)
+    .await?;
+    let Some(provider) = provider else {
+        return Ok(None);
+    };
+    let model_maps = sqlx::query_as::<_, ProviderModelMap>(
+        "SELECT * FROM provider_model_map WHERE provider_id = ? AND enabled = 1 ORDER BY sort_order, id",
+    )
+    .bind(provider.id)
+    .fetch_all(db)
+    .await?;
+    Ok(Some(ProviderWithMaps { provider, model_maps }))
+}
+
+/// select_provider, keeping requests of one session on the same provider.
+///
+/// 会话首次请求正常选择服务商并记录；之后优先使用记录的服务商，不可用（拉黑、禁用、超预算）时重新选择。
+/// 每次命中都会顺延 STICKY_SESSION_TTL_SECS。
+pub async fn select_sticky_provider(
+    db: &SqlitePool,
+    schedules: &ScheduleCache,
+    sessions: &SessionProviderMap,
+    cli_type: &str,
+    session_id: &str,
+) -> Result<Option<ProviderWithMaps>, sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let key = format!("{}:{}", cli_type, session_id);
+
+    let pinned = sessions
+        .get(&key)
+        .filter(|s| s.expires_at > now)
+        .map(|s| s.provider_id);
+    let selected = match pinned {
+        Some(id) => match available_provider(db, cli_type, id).await? {
+            Some(provider) => Some(provider),
+            None => select_provider(db, schedules, cli_type).await?,
+        },
+        None => {
+            // 新会话时顺便清理过期的记录
+            sessions.retain(|_, s| s.expires_at > now);
+            select_provider(db, schedules, cli_type).await?
+        }
+    };
+
+    if let Some(ref selected) = selected {
+        sessions.insert(key, StickyProvider {
+            provider_id: selected.provider.id,
+            expires_at: now + STICKY_SESSION_TTL_SECS,
+        });
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, Weekday};
+
+    fn schedule(provider_id: i64, day_of_week: i64, hour_start: i64, hour_end: i64, priority_override: i64) -> ProviderSchedule {
+        ProviderSchedule {
+            id: provider_id,
+            provider_id,
+            day_of_week,
+            hour_start,
+            hour_end,
+            priority_override,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    fn provider(id: i64, sort_order: i64) -> Provider {
+        Provider {
+            id,
+            sort_order,
+            ..Default::default()
+        }
+    }
+
+    /// 2026-10-13 is a Tuesday
+    fn tuesday(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 13).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn daytime_range_excludes_its_end_hour() {
+        let day = schedule(1, ALL_DAYS_MASK, 9, 18, 0);
+        assert!(!schedule_is_active(&day, tuesday(8)));
+        assert!(schedule_is_active(&day, tuesday(9)));
+        assert!(schedule_is_active(&day, tuesday(17)));
+        assert!(!schedule_is_active(&day, tuesday(18)));
+    }
+
+    #[test]
+    fn overnight_range_wraps_past_midnight() {
+        let night = schedule(1, ALL_DAYS_MASK, 22, 6, 0);
+        assert!(schedule_is_active(&night, tuesday(22)));
+        assert!(schedule_is_active(&night, tuesday(23)));
+        assert!(schedule_is_active(&night, tuesday(0)));
+        assert!(schedule_is_active(&night, tuesday(5)));
+        assert!(!schedule_is_active(&night, tuesday(6)));
+        assert!(!schedule_is_active(&night, tuesday(12)));
+    }
+
+    #[test]
+    fn weekday_mask_is_checked_against_the_current_day() {
+        assert_eq!(tuesday(0).weekday(), Weekday::Tue);
+        let tuesday_bit = 1 << Weekday::Tue.num_days_from_sunday();
+        assert!(schedule_is_active(&schedule(1, tuesday_bit, 0, 24, 0), tuesday(2)));
+        assert!(!schedule_is_active(&schedule(1, ALL_DAYS_MASK & !tuesday_bit, 0, 24, 0), tuesday(2)));
+        // 跨零点的区间按当天星期判断：周二 02:00 属于周二的规则
+        let monday_bit = 1 << Weekday::Mon.num_days_from_sunday();
+        assert!(!schedule_is_active(&schedule(1, monday_bit, 22, 6, 0), tuesday(2)));
+    }
+
+    #[test]
+    fn night_schedule_provider_is_preferred_at_two_on_tuesday() {
+        let schedules = [schedule(2, ALL_DAYS_MASK, 22, 6, -1)];
+
+        let mut providers = vec![provider(1, 0), provider(2, 1)];
+        apply_provider_schedules(&mut providers, &schedules, tuesday(2));
+        assert_eq!(providers.iter().map(|p| p.id).collect::<Vec<_>>(), [2, 1]);
+
+        let mut providers = vec![provider(1, 0), provider(2, 1)];
+        apply_provider_schedules(&mut providers, &schedules, tuesday(14));
+        assert_eq!(providers.iter().map(|p| p.id).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn lowest_active_override_wins_and_ties_keep_id_order() {
+        let schedules = [
+            schedule(3, ALL_DAYS_MASK, 0, 24, 5),
+            schedule(3, ALL_DAYS_MASK, 0, 6, 0),
+            schedule(1, ALL_DAYS_MASK, 0, 24, 0),
+        ];
+        let mut providers = vec![provider(1, 9), provider(2, 1), provider(3, 9)];
+        apply_provider_schedules(&mut providers, &schedules, tuesday(2));
+        assert_eq!(providers.iter().map(|p| p.id).collect::<Vec<_>>(), [1, 3, 2]);
+    }
+}
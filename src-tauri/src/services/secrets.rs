@@ -0,0 +1,136 @@
+//! Provider API keys kept in the OS keychain instead of the database.
+//!
+//! 开启 use_keychain 后，providers.api_key 只保存 `keychain:{id}` 引用，
+//! 实际的 Key 存在系统钥匙串的 `ccg-gateway/provider-{id}` 条目中；
+//! 轮换 Key（provider_api_keys）保存 `keychain:key-{id}`，对应 `ccg-gateway/provider-key-{id}`。
+
+use std::sync::OnceLock;
+
+use dashmap::DashMap;
+
+/// Keychain service name of every gateway secret
+const KEYCHAIN_SERVICE: &str = "ccg-gateway";
+/// providers.api_key values with this prefix reference a keychain entry
+pub const KEYCHAIN_PREFIX: &str = "keychain:";
+
+/// Secrets already read from the keychain (avoids a keychain round trip per request)
+fn cache() -> &'static DashMap<String, String> {
+    static CACHE: OnceLock<DashMap<String, String>> = OnceLock::new();
+    CACHE.get_or_init(DashMap::new)
+}
+
+fn entry(key_id: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, key_id).map_err(|e| e.to_string())
+}
+
+/// Store `value` in the keychain under `ccg-gateway/{key_id}`
+pub fn store_secret(key_id: &str, value: &str) -> Result<(), String> {
+    entry(key_id)?.set_password(value).map_err(|e| e.to_string())?;
+    cache().insert(key_id.to_string(), value.to_string());
+    Ok(())
+}
+
+/// Read the secret stored under `ccg-gateway/{key_id}`
+pub fn retrieve_secret(key_id: &str) -> Result<String, String> {
+    if let Some(value) = cache().get(key_id) {
+        return Ok(value.clone());
+    }
+    let value = entry(key_id)?.get_password().map_err(|e| e.to_string())?;
+    cache().insert(key_id.to_string(), value.clone());
+    Ok(value)
+}
+
+/// Remove the secret stored under `ccg-gateway/{key_id}` (missing entries are fine)
+pub fn delete_secret(key_id: &str) -> Result<(), String> {
+    cache().remove(key_id);
+    match entry(key_id)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Keychain entry name of a provider's API key
+pub fn provider_secret_id(provider_id: i64) -> String {
+    format!("provider-{}", provider_id)
+}
+
+/// providers.api_key value referencing the provider's keychain entry
+pub fn provider_key_ref(provider_id: i64) -> String {
+    format!("{}{}", KEYCHAIN_PREFIX, provider_id)
+}
+
+/// Keychain entry name of a rotation key (provider_api_keys row)
+pub fn rotation_secret_id(key_id: i64) -> String {
+    format!("provider-key-{}", key_id)
+}
+
+/// provider_api_keys.api_key value referencing the rotation key's keychain entry
+pub fn rotation_key_ref(key_id: i64) -> String {
+    format!("{}key-{}", KEYCHAIN_PREFIX, key_id)
+}
+
+/// Plaintext API key of a stored providers.api_key / provider_api_keys.api_key value
+pub fn resolve_api_key(api_key: &str) -> Result<String, String> {
+    let Some(reference) = api_key.strip_prefix(KEYCHAIN_PREFIX) else {
+        return Ok(api_key.to_string());
+    };
+    let invalid = || format!("Invalid keychain reference: {}", api_key);
+    match reference.strip_prefix("key-") {
+        Some(id) => {
+            let id: i64 = id.parse().map_err(|_| invalid())?;
+            retrieve_secret(&rotation_secret_id(id))
+                .map_err(|e| format!("Failed to read rotation API key #{} from keychain: {}", id, e))
+        }
+        None => {
+            let id: i64 = reference.parse().map_err(|_| invalid())?;
+            retrieve_secret(&provider_secret_id(id))
+                .map_err(|e| format!("Failed to read API key of provider #{} from keychain: {}", id, e))
+        }
+    }
+}
+
+/// Value to save in providers.api_key for `api_key`: with `use_keychain` the key moves
+/// to the keychain and a reference is returned. References copied from another provider
+/// are resolved so each provider owns its entry.
+pub fn protect_provider_key(provider_id: i64, api_key: &str, use_keychain: bool) -> Result<String, String> {
+    protect_key(&provider_key_ref(provider_id), &provider_secret_id(provider_id), api_key, use_keychain)
+}
+
+/// Same as protect_provider_key for a provider_api_keys row
+pub fn protect_rotation_key(key_id: i64, api_key: &str, use_keychain: bool) -> Result<String, String> {
+    protect_key(&rotation_key_ref(key_id), &rotation_secret_id(key_id), api_key, use_keychain)
+}
+
+fn protect_key(own_ref: &str, secret_id: &str, api_key: &str, use_keychain: bool) -> Result<String, String> {
+    if api_key.is_empty() || api_key == own_ref {
+        return Ok(api_key.to_string());
+    }
+    let plaintext = resolve_api_key(api_key)?;
+    if !use_keychain {
+        return Ok(plaintext);
+    }
+    store_secret(secret_id, &plaintext)
+        .map_err(|e| format!("Failed to store API key in keychain: {}", e))?;
+    Ok(own_ref.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plaintext_keys_resolve_to_themselves() {
+        assert_eq!(resolve_api_key("sk-plain").unwrap(), "sk-plain");
+        assert_eq!(protect_rotation_key(7, "sk-plain", false).unwrap(), "sk-plain");
+    }
+
+    #[test]
+    fn rotation_references_use_their_own_entries() {
+        assert_eq!(rotation_key_ref(7), "keychain:key-7");
+        assert_eq!(rotation_secret_id(7), "provider-key-7");
+        assert_ne!(rotation_secret_id(7), provider_secret_id(7));
+        // 已是自己的引用时不再读写钥匙串
+        assert_eq!(protect_rotation_key(7, "keychain:key-7", true).unwrap(), "keychain:key-7");
+        assert!(resolve_api_key("keychain:key-x").unwrap_err().contains("Invalid keychain reference"));
+    }
+}
@@ -0,0 +1,159 @@
+//! One-sentence session summaries generated by a provider.
+//!
+//! 只取会话开头约 2000 token 的内容，足够概括主题，也避免长会话产生大量费用。
+
+use serde_json::{json, Value};
+
+use crate::db::models::SessionMessage;
+use crate::services::proxy::CliType;
+use crate::services::token_count::estimate_text_tokens;
+
+/// Approximate token budget of the conversation excerpt sent for summarizing
+pub const SUMMARY_INPUT_TOKENS: i64 = 2000;
+const SUMMARY_MAX_OUTPUT_TOKENS: i64 = 200;
+/// client_path prefix marking summary requests in the request log
+pub const SUMMARY_PATH_PREFIX: &str = "[SUMMARY]";
+
+const SUMMARY_PROMPT: &str = "Summarize the following conversation between a user and an AI coding assistant in one sentence. \
+Reply with that sentence only, in the language the user writes in.";
+
+/// Wire format of the summary request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryApi {
+    /// `/v1/messages`
+    Anthropic,
+    /// `/chat/completions`
+    ChatCompletions,
+    /// `/v1beta/models/{model}:generateContent`
+    Gemini,
+}
+
+impl SummaryApi {
+    /// Codex providers and providers speaking the OpenAI protocol get chat/completions
+    pub fn for_provider(cli_type: CliType, openai_protocol: bool) -> Self {
+        match cli_type {
+            CliType::Codex => SummaryApi::ChatCompletions,
+            _ if openai_protocol => SummaryApi::ChatCompletions,
+            CliType::ClaudeCode => SummaryApi::Anthropic,
+            CliType::Gemini => SummaryApi::Gemini,
+        }
+    }
+
+    /// Request path appended to the provider base URL
+    pub fn path(self, cli_type: CliType, model: &str) -> String {
+        match self {
+            SummaryApi::Anthropic => "/v1/messages".to_string(),
+            // Codex 的 base_url 自带 /v1
+            SummaryApi::ChatCompletions if cli_type == CliType::Codex => "/chat/completions".to_string(),
+            SummaryApi::ChatCompletions => "/v1/chat/completions".to_string(),
+            SummaryApi::Gemini => format!("/v1beta/models/{}:generateContent", model),
+        }
+    }
+
+    /// CLI type whose usage format the response carries
+    pub fn usage_format(self) -> CliType {
+        match self {
+            SummaryApi::Anthropic => CliType::ClaudeCode,
+            SummaryApi::ChatCompletions => CliType::Codex,
+            SummaryApi::Gemini => CliType::Gemini,
+        }
+    }
+}
+
+/// Model requested before the provider's model maps are applied
+pub fn default_model(cli_type: CliType) -> &'static str {
+    match cli_type {
+        CliType::ClaudeCode => "claude-haiku-4-5",
+        CliType::Codex => "gpt-4o-mini",
+        CliType::Gemini => "gemini-2.5-flash",
+    }
+}
+
+/// Leading part of the conversation, cut at about `SUMMARY_INPUT_TOKENS`
+pub fn conversation_excerpt(messages: &[SessionMessage]) -> String {
+    let mut excerpt = String::new();
+    let mut budget = SUMMARY_INPUT_TOKENS;
+
+    for message in messages {
+        let line = format!("{}: {}\n\n", message.role, message.content.trim());
+        let tokens = estimate_text_tokens(&line);
+        if tokens <= budget {
+            excerpt.push_str(&line);
+            budget -= tokens;
+            continue;
+        }
+
+        // 按 estimate_text_tokens 的口径截断最后一条：ASCII 4 字符 1 token，其余 1 字符 1 token
+        let mut quarters = budget * 4;
+        for c in line.chars() {
+            let cost = if c.is_ascii() { 1 } else { 4 };
+            if cost > quarters {
+                break;
+            }
+            quarters -= cost;
+            excerpt.push(c);
+        }
+        break;
+    }
+
+    excerpt.trim_end().to_string()
+}
+
+/// Request body asking for a one-sentence summary of `excerpt`
+pub fn summary_request_body(api: SummaryApi, model: &str, excerpt: &str) -> Vec<u8> {
+    let body = match api {
+        SummaryApi::Anthropic => json!({
+            "model": model,
+            "max_tokens": SUMMARY_MAX_OUTPUT_TOKENS,
+            "system": SUMMARY_PROMPT,
+            "messages": [{ "role": "user", "content": excerpt }],
+        }),
+        SummaryApi::ChatCompletions => json!({
+            "model": model,
+            "max_tokens": SUMMARY_MAX_OUTPUT_TOKENS,
+            "messages": [
+                { "role": "system", "content": SUMMARY_PROMPT },
+                { "role": "user", "content": excerpt },
+            ],
+        }),
+        SummaryApi::Gemini => json!({
+            "systemInstruction": { "parts": [{ "text": SUMMARY_PROMPT }] },
+            "contents": [{ "role": "user", "parts": [{ "text": excerpt }] }],
+            "generationConfig": { "maxOutputTokens": SUMMARY_MAX_OUTPUT_TOKENS },
+        }),
+    };
+    body.to_string().into_bytes()
+}
+
+/// Summary text of a non-streaming response, whitespace collapsed to one line
+pub fn extract_summary(api: SummaryApi, body: &[u8]) -> Option<String> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    let text = match api {
+        SummaryApi::Anthropic => json
+            .get("content")?
+            .as_array()?
+            .iter()
+            .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+            .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+            .collect::<String>(),
+        SummaryApi::ChatCompletions => json
+            .get("choices")?
+            .get(0)?
+            .get("message")?
+            .get("content")?
+            .as_str()?
+            .to_string(),
+        SummaryApi::Gemini => json
+            .get("candidates")?
+            .get(0)?
+            .get("content")?
+            .get("parts")?
+            .as_array()?
+            .iter()
+            .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+            .collect::<String>(),
+    };
+
+    let summary = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!summary.is_empty()).then_some(summary)
+}
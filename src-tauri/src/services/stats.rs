@@ -1,27 +1,44 @@
+use serde::Serialize;
 use sqlx::SqlitePool;
 
+use crate::services::masking::{mask_sensitive_data, MaskPatternCache};
+use crate::services::proxy::TokenUsage;
+
 /// Record a request in the daily usage statistics
 pub async fn record_request(
+    db: &SqlitePool,
     log_db: &SqlitePool,
     provider_name: &str,
     cli_type: &str,
+    model_id: Option<&str>,
     success: bool,
-    input_tokens: i64,
-    output_tokens: i64,
+    usage: &TokenUsage,
 ) -> Result<(), sqlx::Error> {
     let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
 
+    // 按请求当时的价格累计，之后修改价格不影响历史数据
+    let cost_usd = match model_id {
+        Some(model_id) => crate::services::pricing::find_pricing(db, provider_name, model_id)
+            .await?
+            .map(|p| crate::services::pricing::cost_usd(&p, usage))
+            .unwrap_or(0.0),
+        None => 0.0,
+    };
+
     // Upsert into usage_daily table
     sqlx::query(
         r#"
-        INSERT INTO usage_daily (usage_date, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens)
-        VALUES (?, ?, ?, 1, ?, ?, ?, ?)
+        INSERT INTO usage_daily (usage_date, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, cost_usd)
+        VALUES (?, ?, ?, 1, ?, ?, ?, ?, ?, ?, ?)
         ON CONFLICT(usage_date, provider_name, cli_type) DO UPDATE SET
             request_count = request_count + 1,
             success_count = success_count + excluded.success_count,
             failure_count = failure_count + excluded.failure_count,
             input_tokens = input_tokens + excluded.input_tokens,
-            output_tokens = output_tokens + excluded.output_tokens
+            output_tokens = output_tokens + excluded.output_tokens,
+            cache_creation_tokens = cache_creation_tokens + excluded.cache_creation_tokens,
+            cache_read_tokens = cache_read_tokens + excluded.cache_read_tokens,
+            cost_usd = cost_usd + excluded.cost_usd
         "#,
     )
     .bind(&today)
@@ -29,20 +46,50 @@ pub async fn record_request(
     .bind(cli_type)
     .bind(if success { 1 } else { 0 })
     .bind(if success { 0 } else { 1 })
-    .bind(input_tokens)
-    .bind(output_tokens)
+    .bind(usage.input_tokens)
+    .bind(usage.output_tokens)
+    .bind(usage.cache_creation_tokens)
+    .bind(usage.cache_read_tokens)
+    .bind(cost_usd)
     .execute(log_db)
     .await?;
 
     Ok(())
 }
 
+/// One upstream attempt made while serving a request
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestAttempt {
+    pub provider_name: String,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub elapsed_ms: i64,
+}
+
+/// Put in place of the bytes dropped from a logged body
+pub fn truncation_marker(skipped: usize) -> String {
+    format!("...[truncated {} bytes]...", skipped)
+}
+
+/// Whether a logged body had bytes dropped (truncation_marker, or the `...[truncated]` suffix of older rows)
+pub fn is_truncated_body(body: &str) -> bool {
+    if body.ends_with("...[truncated]") {
+        return true;
+    }
+    body.match_indices("...[truncated ").any(|(pos, prefix)| {
+        let rest = &body[pos + prefix.len()..];
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        digits > 0 && rest[digits..].starts_with(" bytes]...")
+    })
+}
+
 /// Request log detail info
 #[derive(Default)]
 pub struct RequestLogInfo {
     pub client_headers: Option<String>,
     pub client_body: Option<String>,
     pub forward_url: Option<String>,
+    pub forward_proxy: Option<String>,
     pub forward_headers: Option<String>,
     pub forward_body: Option<String>,
     pub provider_headers: Option<String>,
@@ -50,11 +97,32 @@ pub struct RequestLogInfo {
     pub response_headers: Option<String>,
     pub response_body: Option<String>,
     pub error_message: Option<String>,
+    pub cache_creation_tokens: i64,
+    pub cache_read_tokens: i64,
+    /// Client session the request belongs to (Claude Code metadata.user_id / Codex session_id header)
+    pub session_id: Option<String>,
+    /// Body rewrites applied by the gateway (e.g. max_tokens clamping)
+    pub body_transforms: Option<String>,
+    /// Gateway-generated id sent upstream in gateway_settings.request_id_header
+    pub request_id: Option<String>,
+    /// Address of the client that sent the request
+    pub client_ip: Option<String>,
+    /// Model rewrites in order, e.g. `sonnet -> claude-sonnet-4-5 -> relay-sonnet`
+    pub model_chain: Option<String>,
+    /// Time from sending the upstream request to receiving its response headers
+    pub ttfb_ms: Option<i64>,
+    /// Upstream attempts in order; the last one produced the logged response
+    pub attempts: Vec<RequestAttempt>,
+    /// Rotation key used for this request (not persisted, used for key health tracking)
+    pub api_key_id: Option<i64>,
+    /// Message Batch the request created, polled or downloaded results of
+    pub batch_id: Option<String>,
 }
 
 /// Record a request log entry
 pub async fn record_request_log(
     log_db: &SqlitePool,
+    mask_patterns: &MaskPatternCache,
     cli_type: &str,
     provider_name: &str,
     model_id: Option<&str>,
@@ -67,12 +135,31 @@ pub async fn record_request_log(
     info: Option<RequestLogInfo>,
 ) -> Result<(), sqlx::Error> {
     let now = chrono::Utc::now().timestamp();
-    let info = info.unwrap_or_default();
+    let mut info = info.unwrap_or_default();
+
+    // Mask secrets in bodies before they hit the log database
+    {
+        let patterns = mask_patterns.read().unwrap_or_else(|e| e.into_inner());
+        for body in [
+            &mut info.client_body,
+            &mut info.forward_body,
+            &mut info.provider_body,
+            &mut info.response_body,
+        ] {
+            if let Some(text) = body.as_mut() {
+                *text = mask_sensitive_data(text, &patterns);
+            }
+        }
+    }
+
+    let attempts = (!info.attempts.is_empty())
+        .then(|| serde_json::to_string(&info.attempts).ok())
+        .flatten();
 
     sqlx::query(
         r#"
-        INSERT INTO request_logs (created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_headers, forward_body, provider_headers, provider_body, response_headers, response_body, error_message)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        INSERT INTO request_logs (created_at, cli_type, provider_name, model_id, status_code, elapsed_ms, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, client_method, client_path, client_headers, client_body, forward_url, forward_proxy, forward_headers, forward_body, provider_headers, provider_body, response_headers, response_body, error_message, session_id, body_transforms, request_id, client_ip, model_chain, ttfb_ms, attempts, batch_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         "#,
     )
     .bind(now)
@@ -83,11 +170,14 @@ pub async fn record_request_log(
     .bind(elapsed_ms)
     .bind(input_tokens)
     .bind(output_tokens)
+    .bind(info.cache_creation_tokens)
+    .bind(info.cache_read_tokens)
     .bind(client_method)
     .bind(client_path)
     .bind(&info.client_headers)
     .bind(&info.client_body)
     .bind(&info.forward_url)
+    .bind(&info.forward_proxy)
     .bind(&info.forward_headers)
     .bind(&info.forward_body)
     .bind(&info.provider_headers)
@@ -95,6 +185,14 @@ pub async fn record_request_log(
     .bind(&info.response_headers)
     .bind(&info.response_body)
     .bind(&info.error_message)
+    .bind(&info.session_id)
+    .bind(&info.body_transforms)
+    .bind(&info.request_id)
+    .bind(&info.client_ip)
+    .bind(&info.model_chain)
+    .bind(info.ttfb_ms)
+    .bind(&attempts)
+    .bind(&info.batch_id)
     .execute(log_db)
     .await?;
 
@@ -130,8 +228,113 @@ pub async fn record_system_log(
     Ok(())
 }
 
+/// Placeholder written over the provider name when its logs are purged
+pub const DELETED_PROVIDER_NAME: &str = "[deleted]";
+
+/// Anonymize a provider's name in request_logs, system_logs and usage_daily.
+///
+/// 三张表都在日志库中，使用同一个事务。usage_daily 以 (date, provider, cli_type)
+/// 为主键，需要先合并到 "[deleted]" 行再删除原行。
+///
+/// `cli_type` limits request_logs and usage_daily to one CLI, since another CLI may have a
+/// provider with the same name. system_logs has no cli_type column, so it is only
+/// touched with `include_system_logs`.
+pub async fn purge_provider_logs(
+    log_db: &SqlitePool,
+    provider_name: &str,
+    cli_type: Option<&str>,
+    include_system_logs: bool,
+) -> Result<crate::db::models::PurgeResult, sqlx::Error> {
+    let mut tx = log_db.begin().await?;
+
+    let request_log_count = sqlx::query(
+        "UPDATE request_logs SET provider_name = ? WHERE provider_name = ? AND (? IS NULL OR cli_type = ?)",
+    )
+    .bind(DELETED_PROVIDER_NAME)
+    .bind(provider_name)
+    .bind(cli_type)
+    .bind(cli_type)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let system_log_count = if include_system_logs {
+        sqlx::query(
+            "UPDATE system_logs SET provider_name = ?, message = REPLACE(message, ?, ?) WHERE provider_name = ?",
+        )
+        .bind(DELETED_PROVIDER_NAME)
+        .bind(provider_name)
+        .bind(DELETED_PROVIDER_NAME)
+        .bind(provider_name)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+    } else {
+        0
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO usage_daily (usage_date, provider_name, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, cost_usd)
+        SELECT usage_date, ?, cli_type, request_count, success_count, failure_count, input_tokens, output_tokens, cache_creation_tokens, cache_read_tokens, cost_usd
+        FROM usage_daily WHERE provider_name = ? AND (? IS NULL OR cli_type = ?)
+        ON CONFLICT(usage_date, provider_name, cli_type) DO UPDATE SET
+            request_count = request_count + excluded.request_count,
+            success_count = success_count + excluded.success_count,
+            failure_count = failure_count + excluded.failure_count,
+            input_tokens = input_tokens + excluded.input_tokens,
+            output_tokens = output_tokens + excluded.output_tokens,
+            cache_creation_tokens = cache_creation_tokens + excluded.cache_creation_tokens,
+            cache_read_tokens = cache_read_tokens + excluded.cache_read_tokens,
+            cost_usd = cost_usd + excluded.cost_usd
+        "#,
+    )
+    .bind(DELETED_PROVIDER_NAME)
+    .bind(provider_name)
+    .bind(cli_type)
+    .bind(cli_type)
+    .execute(&mut *tx)
+    .await?;
+
+    let usage_daily_count =
+        sqlx::query("DELETE FROM usage_daily WHERE provider_name = ? AND (? IS NULL OR cli_type = ?)")
+            .bind(provider_name)
+            .bind(cli_type)
+            .bind(cli_type)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(crate::db::models::PurgeResult {
+        request_log_count: request_log_count as i64,
+        system_log_count: system_log_count as i64,
+        usage_daily_count: usage_daily_count as i64,
+    })
+}
+
 /// Helper to create system log details JSON
 pub fn create_log_details(data: &serde_json::Value) -> String {
     data.to_string()
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_bodies_are_detected() {
+        let body = format!("{{\"model\":\"x\",{}\"end\":1}}", truncation_marker(2048));
+        assert!(is_truncated_body(&body));
+        assert!(is_truncated_body("{\"model\":\"x\"...[truncated]"));
+    }
+
+    #[test]
+    fn complete_bodies_are_not_truncated() {
+        assert!(!is_truncated_body("{\"model\":\"x\"}"));
+        assert!(!is_truncated_body("text mentioning ...[truncated later"));
+        assert!(!is_truncated_body("...[truncated  bytes]..."));
+    }
+}
@@ -0,0 +1,71 @@
+//! Local approximation of Anthropic `count_tokens` for relays that do not implement it.
+//!
+//! 不追求精确：ASCII 约 4 字符 1 token，CJK 等非 ASCII 字符按 1 字符 1 token 估算。
+
+use serde_json::Value;
+
+/// Rough per-image cost (Anthropic caps images at about 1600 tokens)
+const IMAGE_TOKENS: i64 = 1600;
+/// Role / separator overhead per message
+const MESSAGE_OVERHEAD_TOKENS: i64 = 3;
+
+/// Whether the request path is the Messages token counting endpoint
+pub fn is_count_tokens_path(path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or(path);
+    path.ends_with("/messages/count_tokens")
+}
+
+/// Approximate token count of a piece of text
+pub fn estimate_text_tokens(text: &str) -> i64 {
+    let (ascii, other) = text.chars().fold((0i64, 0i64), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    (ascii + 3) / 4 + other
+}
+
+fn content_tokens(content: &Value) -> i64 {
+    match content {
+        Value::String(text) => estimate_text_tokens(text),
+        Value::Array(blocks) => blocks.iter().map(block_tokens).sum(),
+        _ => 0,
+    }
+}
+
+fn block_tokens(block: &Value) -> i64 {
+    match block.get("type").and_then(|t| t.as_str()) {
+        Some("text") => block.get("text").and_then(|t| t.as_str()).map(estimate_text_tokens).unwrap_or(0),
+        Some("thinking") => block.get("thinking").and_then(|t| t.as_str()).map(estimate_text_tokens).unwrap_or(0),
+        Some("image") | Some("document") => IMAGE_TOKENS,
+        Some("tool_use") => {
+            let name = block.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+            let input = block.get("input").map(|i| i.to_string()).unwrap_or_default();
+            estimate_text_tokens(name) + estimate_text_tokens(&input)
+        }
+        Some("tool_result") => block.get("content").map(content_tokens).unwrap_or(0),
+        _ => estimate_text_tokens(&block.to_string()),
+    }
+}
+
+/// Estimate `input_tokens` for a Messages / count_tokens request body
+pub fn estimate_input_tokens(body: &[u8]) -> Result<i64, String> {
+    let req: Value = serde_json::from_slice(body).map_err(|e| format!("Invalid count_tokens request: {}", e))?;
+    if !req.is_object() {
+        return Err("count_tokens request must be a JSON object".to_string());
+    }
+
+    let mut tokens = req.get("system").map(content_tokens).unwrap_or(0);
+    if let Some(messages) = req.get("messages").and_then(|m| m.as_array()) {
+        for message in messages {
+            tokens += MESSAGE_OVERHEAD_TOKENS + message.get("content").map(content_tokens).unwrap_or(0);
+        }
+    }
+    if let Some(tools) = req.get("tools").and_then(|t| t.as_array()) {
+        // 工具定义按 JSON 文本整体估算
+        tokens += tools.iter().map(|tool| estimate_text_tokens(&tool.to_string())).sum::<i64>();
+    }
+    Ok(tokens.max(1))
+}
@@ -0,0 +1,1370 @@
+//! Translate Claude Code / Gemini CLI traffic to OpenAI chat/completions (provider `protocol = "openai"`).
+//!
+//! Claude Code: Anthropic `system` / `messages` / `tools` -> chat, chat -> Anthropic message / SSE events.
+//! Gemini CLI: `contents` / `systemInstruction` / `generationConfig` -> chat, chat -> `candidates` / SSE chunks.
+
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::services::chat_wire::{self, str_field};
+use crate::services::responses_chat::{self, ChatToResponsesStream, ChatTranslation};
+
+/// Request-side context needed to translate the chat response back
+#[derive(Debug, Clone, Default)]
+pub struct AnthropicTranslation {
+    pub model: String,
+}
+
+/// Whether the request path targets the Messages API (`.../messages`, not `count_tokens`)
+pub fn is_messages_path(path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or(path);
+    path.ends_with("/messages")
+}
+
+/// `/v1/messages?beta=true` -> `/v1/chat/completions?beta=true`
+pub fn chat_completions_path(path: &str) -> String {
+    chat_wire::chat_completions_path(path, "/messages")
+}
+
+// ==================== Request: Anthropic -> Chat ====================
+
+/// Convert an Anthropic Messages request body into a chat/completions request body
+pub fn anthropic_to_chat_request(body: &[u8]) -> Result<(Vec<u8>, AnthropicTranslation), String> {
+    let req: Value = serde_json::from_slice(body).map_err(|e| format!("Invalid Messages request: {}", e))?;
+    let obj = req
+        .as_object()
+        .ok_or_else(|| "Messages request must be a JSON object".to_string())?;
+
+    let ctx = AnthropicTranslation {
+        model: str_field(&req, "model").unwrap_or_default().to_string(),
+    };
+
+    let mut messages = Vec::new();
+    let system = match obj.get("system") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(blocks)) => join_text_blocks(blocks),
+        _ => String::new(),
+    };
+    if !system.is_empty() {
+        messages.push(json!({"role": "system", "content": system}));
+    }
+    for message in obj.get("messages").and_then(|m| m.as_array()).into_iter().flatten() {
+        push_message(&mut messages, message);
+    }
+
+    let mut chat = Map::new();
+    chat.insert("model".to_string(), json!(ctx.model));
+    chat.insert("messages".to_string(), Value::Array(messages));
+
+    // 只转换带 input_schema 的自定义工具，服务端工具（web_search 等）OpenAI 无对应
+    let tools: Vec<Value> = obj
+        .get("tools")
+        .and_then(|t| t.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|tool| {
+            let name = str_field(tool, "name")?;
+            let schema = tool.get("input_schema")?;
+            let mut function = json!({"name": name, "parameters": schema});
+            if let Some(description) = tool.get("description") {
+                function["description"] = description.clone();
+            }
+            Some(json!({"type": "function", "function": function}))
+        })
+        .collect();
+    if !tools.is_empty() {
+        chat.insert("tools".to_string(), Value::Array(tools));
+        if let Some(choice) = obj.get("tool_choice") {
+            match str_field(choice, "type") {
+                Some("auto") => {
+                    chat.insert("tool_choice".to_string(), json!("auto"));
+                }
+                Some("any") => {
+                    chat.insert("tool_choice".to_string(), json!("required"));
+                }
+                Some("none") => {
+                    chat.insert("tool_choice".to_string(), json!("none"));
+                }
+                Some("tool") => {
+                    if let Some(name) = str_field(choice, "name") {
+                        chat.insert(
+                            "tool_choice".to_string(),
+                            json!({"type": "function", "function": {"name": name}}),
+                        );
+                    }
+                }
+                _ => {}
+            }
+            if choice.get("disable_parallel_tool_use").and_then(|v| v.as_bool()) == Some(true) {
+                chat.insert("parallel_tool_calls".to_string(), json!(false));
+            }
+        }
+    }
+
+    if let Some(max_tokens) = obj.get("max_tokens") {
+        chat.insert("max_tokens".to_string(), max_tokens.clone());
+    }
+    for key in ["temperature", "top_p"] {
+        if let Some(value) = obj.get(key) {
+            chat.insert(key.to_string(), value.clone());
+        }
+    }
+    if let Some(stop) = obj.get("stop_sequences").filter(|s| s.as_array().is_some_and(|a| !a.is_empty())) {
+        chat.insert("stop".to_string(), stop.clone());
+    }
+    if let Some(user) = obj.get("metadata").and_then(|m| m.get("user_id")) {
+        chat.insert("user".to_string(), user.clone());
+    }
+    if obj.get("stream").and_then(|v| v.as_bool()) == Some(true) {
+        chat.insert("stream".to_string(), json!(true));
+        // 没有 usage 就无法回填 message_delta.usage
+        chat.insert("stream_options".to_string(), json!({"include_usage": true}));
+    }
+
+    let body = serde_json::to_vec(&Value::Object(chat)).map_err(|e| e.to_string())?;
+    Ok((body, ctx))
+}
+
+fn join_text_blocks(blocks: &[Value]) -> String {
+    blocks
+        .iter()
+        .filter(|b| str_field(b, "type") == Some("text"))
+        .filter_map(|b| str_field(b, "text"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Append one Anthropic message; tool_result blocks become separate `tool` messages
+fn push_message(messages: &mut Vec<Value>, message: &Value) {
+    let role = str_field(message, "role").unwrap_or("user");
+    let blocks = match message.get("content") {
+        Some(Value::String(s)) => {
+            messages.push(json!({"role": role, "content": s}));
+            return;
+        }
+        Some(Value::Array(blocks)) => blocks,
+        _ => return,
+    };
+
+    let mut parts = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in blocks {
+        match str_field(block, "type") {
+            Some("text") => {
+                if let Some(text) = str_field(block, "text") {
+                    parts.push(json!({"type": "text", "text": text}));
+                }
+            }
+            Some("image") => {
+                if let Some(url) = image_url(block.get("source")) {
+                    parts.push(json!({"type": "image_url", "image_url": {"url": url}}));
+                }
+            }
+            Some("tool_use") => {
+                let arguments = block
+                    .get("input")
+                    .map(|i| i.to_string())
+                    .unwrap_or_else(|| "{}".to_string());
+                tool_calls.push(json!({
+                    "id": str_field(block, "id").unwrap_or_default(),
+                    "type": "function",
+                    "function": {
+                        "name": str_field(block, "name").unwrap_or_default(),
+                        "arguments": arguments,
+                    },
+                }));
+            }
+            Some("tool_result") => {
+                let content = match block.get("content") {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(Value::Array(items)) => join_text_blocks(items),
+                    _ => String::new(),
+                };
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": str_field(block, "tool_use_id").unwrap_or_default(),
+                    "content": content,
+                }));
+            }
+            // thinking / redacted_thinking 块无法回传给 OpenAI 兼容服务
+            _ => {}
+        }
+    }
+
+    if parts.is_empty() && tool_calls.is_empty() {
+        return;
+    }
+    let content = match parts.as_slice() {
+        [] => Value::Null,
+        // 纯文本用字符串，兼容只接受 string content 的服务
+        _ if parts.iter().all(|p| str_field(p, "type") == Some("text")) => Value::String(
+            parts
+                .iter()
+                .filter_map(|p| str_field(p, "text"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        _ => Value::Array(parts),
+    };
+    let mut chat_message = json!({"role": role, "content": content});
+    if !tool_calls.is_empty() {
+        chat_message["tool_calls"] = Value::Array(tool_calls);
+    }
+    messages.push(chat_message);
+}
+
+fn image_url(source: Option<&Value>) -> Option<String> {
+    let source = source?;
+    match str_field(source, "type")? {
+        "base64" => Some(format!(
+            "data:{};base64,{}",
+            str_field(source, "media_type").unwrap_or("image/png"),
+            str_field(source, "data")?
+        )),
+        "url" => str_field(source, "url").map(|s| s.to_string()),
+        _ => None,
+    }
+}
+
+// ==================== Response: Chat -> Anthropic ====================
+
+/// chat finish_reason -> Anthropic stop_reason
+pub fn map_stop_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "max_tokens",
+        Some("tool_calls") | Some("function_call") => "tool_use",
+        Some("content_filter") => "refusal",
+        _ => "end_turn",
+    }
+}
+
+fn message_id(chat_id: Option<&str>) -> String {
+    match chat_id {
+        Some(id) if !id.is_empty() => format!("msg_{}", id.trim_start_matches("chatcmpl-")),
+        _ => format!("msg_{}", uuid::Uuid::new_v4().simple()),
+    }
+}
+
+/// chat usage -> Anthropic usage; cached prompt tokens are reported separately
+fn anthropic_usage(usage: Option<&Value>) -> Value {
+    let get = |key: &str| usage.and_then(|u| u.get(key)).and_then(|v| v.as_i64()).unwrap_or(0);
+    let cached = usage
+        .and_then(|u| u.get("prompt_tokens_details"))
+        .and_then(|d| d.get("cached_tokens"))
+        .and_then(|v| v.as_i64())
+        .unwrap_or(0);
+    json!({
+        "input_tokens": (get("prompt_tokens") - cached).max(0),
+        "output_tokens": get("completion_tokens"),
+        "cache_read_input_tokens": cached,
+    })
+}
+
+fn parse_arguments(arguments: &str) -> Value {
+    serde_json::from_str(arguments).unwrap_or_else(|_| json!({}))
+}
+
+/// Convert a non-streaming chat completion into an Anthropic message; None if the body is not one
+pub fn chat_to_anthropic_body(body: &[u8], ctx: &AnthropicTranslation) -> Option<Vec<u8>> {
+    let chat: Value = serde_json::from_slice(body).ok()?;
+    let choice = chat.get("choices")?.as_array()?.first()?;
+    let message = choice.get("message")?;
+
+    let mut content = Vec::new();
+    if let Some(text) = str_field(message, "content").filter(|t| !t.is_empty()) {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    for call in message.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+        let function = call.get("function");
+        content.push(json!({
+            "type": "tool_use",
+            "id": str_field(call, "id").unwrap_or_default(),
+            "name": function.and_then(|f| str_field(f, "name")).unwrap_or_default(),
+            "input": parse_arguments(function.and_then(|f| str_field(f, "arguments")).unwrap_or("{}")),
+        }));
+    }
+
+    let message = json!({
+        "id": message_id(str_field(&chat, "id")),
+        "type": "message",
+        "role": "assistant",
+        "model": str_field(&chat, "model").unwrap_or(&ctx.model),
+        "content": content,
+        "stop_reason": map_stop_reason(str_field(choice, "finish_reason")),
+        "stop_sequence": null,
+        "usage": anthropic_usage(chat.get("usage")),
+    });
+    serde_json::to_vec(&message).ok()
+}
+
+enum OpenBlock {
+    Text,
+    /// Tool call keyed by the chat `tool_calls[].index`
+    Tool(u64),
+}
+
+/// Incrementally converts chat/completions SSE chunks into Anthropic Messages SSE events
+pub struct ChatToAnthropicStream {
+    ctx: AnthropicTranslation,
+    buffer: Vec<u8>,
+    started: bool,
+    next_index: usize,
+    open: Option<OpenBlock>,
+    /// chat tool call index -> Anthropic content block index
+    tool_blocks: HashMap<u64, usize>,
+    usage: Option<Value>,
+    finish_reason: Option<String>,
+    completed: bool,
+}
+
+impl ChatToAnthropicStream {
+    pub fn new(ctx: AnthropicTranslation) -> Self {
+        Self {
+            ctx,
+            buffer: Vec::new(),
+            started: false,
+            next_index: 0,
+            open: None,
+            tool_blocks: HashMap::new(),
+            usage: None,
+            finish_reason: None,
+            completed: false,
+        }
+    }
+
+    /// Feed raw upstream bytes; returns the Anthropic SSE bytes for all complete lines
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.buffer.extend_from_slice(chunk);
+        let Some(last_newline) = self.buffer.iter().rposition(|b| *b == b'\n') else {
+            return out;
+        };
+        let rest = self.buffer.split_off(last_newline + 1);
+        let complete = std::mem::replace(&mut self.buffer, rest);
+        for line in complete.split(|b| *b == b'\n') {
+            self.handle_line(line, &mut out);
+        }
+        out
+    }
+
+    /// Flush the trailing partial line and make sure `message_stop` (or an error) was sent
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let line = std::mem::take(&mut self.buffer);
+        self.handle_line(&line, &mut out);
+        self.complete(&mut out);
+        out
+    }
+
+    fn handle_line(&mut self, line: &[u8], out: &mut Vec<u8>) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Ok(line) = std::str::from_utf8(line) else {
+            return;
+        };
+        let Some(data) = line.strip_prefix("data:").map(|d| d.trim()) else {
+            return;
+        };
+        if data == "[DONE]" {
+            self.complete(out);
+            return;
+        }
+        if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+            self.handle_chunk(&chunk, out);
+        }
+    }
+
+    fn emit(&mut self, out: &mut Vec<u8>, event_type: &str, mut data: Value) {
+        data["type"] = json!(event_type);
+        out.extend_from_slice(format!("event: {}\ndata: {}\n\n", event_type, data).as_bytes());
+    }
+
+    fn ensure_started(&mut self, chunk: &Value, out: &mut Vec<u8>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let model = str_field(chunk, "model").unwrap_or(&self.ctx.model).to_string();
+        let message = json!({
+            "id": message_id(str_field(chunk, "id")),
+            "type": "message",
+            "role": "assistant",
+            "model": model,
+            "content": [],
+            "stop_reason": null,
+            "stop_sequence": null,
+            "usage": {"input_tokens": 0, "output_tokens": 0},
+        });
+        self.emit(out, "message_start", json!({"message": message}));
+    }
+
+    fn handle_chunk(&mut self, chunk: &Value, out: &mut Vec<u8>) {
+        if self.completed {
+            return;
+        }
+        if let Some(error) = chunk.get("error") {
+            let message = str_field(error, "message").unwrap_or("Upstream error").to_string();
+            self.fail(out, &message);
+            return;
+        }
+        self.ensure_started(chunk, out);
+
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            self.usage = Some(usage.clone());
+        }
+        let Some(choice) = chunk.get("choices").and_then(|c| c.as_array()).and_then(|c| c.first()) else {
+            return;
+        };
+        if let Some(delta) = choice.get("delta") {
+            if let Some(text) = str_field(delta, "content").filter(|t| !t.is_empty()) {
+                self.text_delta(text, out);
+            }
+            for call in delta.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+                self.tool_call_delta(call, out);
+            }
+        }
+        if let Some(reason) = str_field(choice, "finish_reason") {
+            self.finish_reason = Some(reason.to_string());
+        }
+    }
+
+    fn start_block(&mut self, out: &mut Vec<u8>, block: OpenBlock, content_block: Value) -> usize {
+        self.close_block(out);
+        let index = self.next_index;
+        self.next_index += 1;
+        self.emit(out, "content_block_start", json!({"index": index, "content_block": content_block}));
+        self.open = Some(block);
+        index
+    }
+
+    fn close_block(&mut self, out: &mut Vec<u8>) {
+        if self.open.take().is_some() {
+            let index = self.next_index - 1;
+            self.emit(out, "content_block_stop", json!({"index": index}));
+        }
+    }
+
+    fn text_delta(&mut self, text: &str, out: &mut Vec<u8>) {
+        if !matches!(self.open, Some(OpenBlock::Text)) {
+            self.start_block(out, OpenBlock::Text, json!({"type": "text", "text": ""}));
+        }
+        let index = self.next_index - 1;
+        self.emit(
+            out,
+            "content_block_delta",
+            json!({"index": index, "delta": {"type": "text_delta", "text": text}}),
+        );
+    }
+
+    fn tool_call_delta(&mut self, call: &Value, out: &mut Vec<u8>) {
+        let chat_index = call.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+        let function = call.get("function");
+
+        let index = match self.open {
+            Some(OpenBlock::Tool(open)) if open == chat_index => self.next_index - 1,
+            // 已经关闭的工具块无法再追加参数（上游交错输出时丢弃）
+            _ if self.tool_blocks.contains_key(&chat_index) => return,
+            _ => {
+                let id = str_field(call, "id")
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| format!("toolu_{}", uuid::Uuid::new_v4().simple()));
+                let name = function.and_then(|f| str_field(f, "name")).unwrap_or_default();
+                let index = self.start_block(
+                    out,
+                    OpenBlock::Tool(chat_index),
+                    json!({"type": "tool_use", "id": id, "name": name, "input": {}}),
+                );
+                self.tool_blocks.insert(chat_index, index);
+                index
+            }
+        };
+
+        if let Some(arguments) = function.and_then(|f| str_field(f, "arguments")).filter(|a| !a.is_empty()) {
+            self.emit(
+                out,
+                "content_block_delta",
+                json!({"index": index, "delta": {"type": "input_json_delta", "partial_json": arguments}}),
+            );
+        }
+    }
+
+    fn fail(&mut self, out: &mut Vec<u8>, message: &str) {
+        self.completed = true;
+        self.emit(out, "error", json!({"error": {"type": "api_error", "message": message}}));
+    }
+
+    fn complete(&mut self, out: &mut Vec<u8>) {
+        if self.completed {
+            return;
+        }
+        if !self.started || self.finish_reason.is_none() {
+            // 上游中途断开：不伪造正常结束，交给客户端重试
+            self.close_block(out);
+            self.fail(out, "Upstream stream ended before completion");
+            return;
+        }
+        self.completed = true;
+        self.close_block(out);
+        let stop_reason = map_stop_reason(self.finish_reason.as_deref());
+        let usage = anthropic_usage(self.usage.as_ref());
+        self.emit(
+            out,
+            "message_delta",
+            json!({"delta": {"stop_reason": stop_reason, "stop_sequence": null}, "usage": usage}),
+        );
+        self.emit(out, "message_stop", json!({}));
+    }
+}
+
+// ==================== Gemini <-> Chat ====================
+
+/// Request-side context needed to translate the chat response back to Gemini
+#[derive(Debug, Clone, Default)]
+pub struct GeminiTranslation {
+    pub model: String,
+}
+
+/// Upstream path for translated Gemini requests (`/v1beta/models/...` has no OpenAI equivalent prefix)
+pub const GEMINI_CHAT_PATH: &str = "/v1/chat/completions";
+
+/// Gemini generate endpoint in a path: `/v1beta/models/{model}:generateContent` -> (model, streaming)
+pub fn gemini_generate_target(path: &str) -> Option<(String, bool)> {
+    let path = path.split('?').next().unwrap_or(path);
+    let (_, rest) = path.split_once("/models/")?;
+    let (model, method) = rest.split_once(':')?;
+    match method {
+        "generateContent" => Some((model.to_string(), false)),
+        "streamGenerateContent" => Some((model.to_string(), true)),
+        _ => None,
+    }
+}
+
+/// Gemini (OpenAPI subset) schemas use upper-case type names; JSON Schema wants lower-case
+fn normalize_schema(schema: &Value) -> Value {
+    match schema {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| {
+                    let v = match (k.as_str(), v) {
+                        ("type", Value::String(t)) => Value::String(t.to_lowercase()),
+                        _ => normalize_schema(v),
+                    };
+                    (k.clone(), v)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(normalize_schema).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Convert a Gemini generateContent request into a chat/completions request body.
+/// `path` supplies the model and whether the client asked for streamGenerateContent.
+pub fn gemini_to_chat_request(body: &[u8], path: &str) -> Result<(Vec<u8>, GeminiTranslation), String> {
+    let (model, stream) =
+        gemini_generate_target(path).ok_or_else(|| format!("Not a Gemini generate path: {}", path))?;
+    let req: Value = serde_json::from_slice(body).map_err(|e| format!("Invalid Gemini request: {}", e))?;
+    let obj = req
+        .as_object()
+        .ok_or_else(|| "Gemini request must be a JSON object".to_string())?;
+
+    let mut messages = Vec::new();
+    let system = obj
+        .get("systemInstruction")
+        .or_else(|| obj.get("system_instruction"))
+        .map(gemini_parts_text)
+        .unwrap_or_default();
+    if !system.is_empty() {
+        messages.push(json!({"role": "system", "content": system}));
+    }
+
+    // Gemini 的 functionCall 没有 id，按出现顺序生成并按函数名配对 functionResponse
+    let mut pending_calls: Vec<(String, String)> = Vec::new();
+    let mut call_seq = 0usize;
+    for content in obj.get("contents").and_then(|c| c.as_array()).into_iter().flatten() {
+        let role = match str_field(content, "role") {
+            Some("model") => "assistant",
+            _ => "user",
+        };
+        let mut parts = Vec::new();
+        let mut tool_calls = Vec::new();
+        for part in content.get("parts").and_then(|p| p.as_array()).into_iter().flatten() {
+            if part.get("thought").and_then(|t| t.as_bool()) == Some(true) {
+                continue;
+            }
+            if let Some(text) = str_field(part, "text") {
+                parts.push(json!({"type": "text", "text": text}));
+            } else if let Some(data) = part.get("inlineData").or_else(|| part.get("inline_data")) {
+                let mime = str_field(data, "mimeType")
+                    .or_else(|| str_field(data, "mime_type"))
+                    .unwrap_or("image/png");
+                if let Some(b64) = str_field(data, "data") {
+                    let url = format!("data:{};base64,{}", mime, b64);
+                    parts.push(json!({"type": "image_url", "image_url": {"url": url}}));
+                }
+            } else if let Some(call) = part.get("functionCall").or_else(|| part.get("function_call")) {
+                let name = str_field(call, "name").unwrap_or_default().to_string();
+                let id = str_field(call, "id").map(|s| s.to_string()).unwrap_or_else(|| {
+                    call_seq += 1;
+                    format!("call_{}", call_seq)
+                });
+                let arguments = call.get("args").map(|a| a.to_string()).unwrap_or_else(|| "{}".to_string());
+                tool_calls.push(json!({
+                    "id": id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": arguments},
+                }));
+                pending_calls.push((name, id));
+            } else if let Some(response) = part.get("functionResponse").or_else(|| part.get("function_response")) {
+                let name = str_field(response, "name").unwrap_or_default();
+                let id = match str_field(response, "id") {
+                    Some(id) => id.to_string(),
+                    None => match pending_calls.iter().position(|(n, _)| n == name) {
+                        Some(i) => pending_calls.remove(i).1,
+                        None => {
+                            call_seq += 1;
+                            format!("call_{}", call_seq)
+                        }
+                    },
+                };
+                let output = response.get("response").cloned().unwrap_or(Value::Null);
+                let output = match output.get("output").or_else(|| output.get("content")) {
+                    Some(Value::String(s)) => s.clone(),
+                    _ => output.to_string(),
+                };
+                messages.push(json!({"role": "tool", "tool_call_id": id, "content": output}));
+            }
+        }
+
+        if parts.is_empty() && tool_calls.is_empty() {
+            continue;
+        }
+        let content = if parts.is_empty() {
+            Value::Null
+        } else if parts.iter().all(|p| str_field(p, "type") == Some("text")) {
+            Value::String(parts.iter().filter_map(|p| str_field(p, "text")).collect::<Vec<_>>().join(""))
+        } else {
+            Value::Array(parts)
+        };
+        let mut message = json!({"role": role, "content": content});
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = Value::Array(tool_calls);
+        }
+        messages.push(message);
+    }
+
+    let mut chat = Map::new();
+    chat.insert("model".to_string(), json!(model));
+    chat.insert("messages".to_string(), Value::Array(messages));
+
+    let tools: Vec<Value> = obj
+        .get("tools")
+        .and_then(|t| t.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|t| t.get("functionDeclarations").or_else(|| t.get("function_declarations")))
+        .filter_map(|d| d.as_array())
+        .flatten()
+        .filter_map(|decl| {
+            let name = str_field(decl, "name")?;
+            let parameters = decl
+                .get("parametersJsonSchema")
+                .cloned()
+                .or_else(|| decl.get("parameters").map(normalize_schema))
+                .unwrap_or_else(|| json!({"type": "object", "properties": {}}));
+            let mut function = json!({"name": name, "parameters": parameters});
+            if let Some(description) = decl.get("description") {
+                function["description"] = description.clone();
+            }
+            Some(json!({"type": "function", "function": function}))
+        })
+        .collect();
+    if !tools.is_empty() {
+        chat.insert("tools".to_string(), Value::Array(tools));
+        let calling = obj
+            .get("toolConfig")
+            .and_then(|c| c.get("functionCallingConfig"));
+        if let Some(calling) = calling {
+            let allowed = calling
+                .get("allowedFunctionNames")
+                .and_then(|a| a.as_array())
+                .filter(|a| a.len() == 1)
+                .and_then(|a| a[0].as_str());
+            let choice = match (str_field(calling, "mode"), allowed) {
+                (Some("ANY"), Some(name)) => Some(json!({"type": "function", "function": {"name": name}})),
+                (Some("ANY"), None) => Some(json!("required")),
+                (Some("NONE"), _) => Some(json!("none")),
+                (Some("AUTO"), _) => Some(json!("auto")),
+                _ => None,
+            };
+            if let Some(choice) = choice {
+                chat.insert("tool_choice".to_string(), choice);
+            }
+        }
+    }
+
+    if let Some(config) = obj.get("generationConfig").or_else(|| obj.get("generation_config")) {
+        for (from, to) in [
+            ("temperature", "temperature"),
+            ("topP", "top_p"),
+            ("maxOutputTokens", "max_tokens"),
+            ("stopSequences", "stop"),
+            ("presencePenalty", "presence_penalty"),
+            ("frequencyPenalty", "frequency_penalty"),
+            ("seed", "seed"),
+        ] {
+            if let Some(value) = config.get(from) {
+                chat.insert(to.to_string(), value.clone());
+            }
+        }
+        if str_field(config, "responseMimeType") == Some("application/json") {
+            chat.insert("response_format".to_string(), json!({"type": "json_object"}));
+        }
+    }
+
+    if stream {
+        chat.insert("stream".to_string(), json!(true));
+        chat.insert("stream_options".to_string(), json!({"include_usage": true}));
+    }
+
+    let body = serde_json::to_vec(&Value::Object(chat)).map_err(|e| e.to_string())?;
+    Ok((body, GeminiTranslation { model }))
+}
+
+fn gemini_parts_text(content: &Value) -> String {
+    content
+        .get("parts")
+        .and_then(|p| p.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|p| str_field(p, "text"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// chat finish_reason -> Gemini finishReason
+pub fn map_finish_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "MAX_TOKENS",
+        Some("content_filter") => "SAFETY",
+        _ => "STOP",
+    }
+}
+
+fn gemini_usage(usage: &Value) -> Value {
+    let get = |key: &str| usage.get(key).and_then(|v| v.as_i64()).unwrap_or(0);
+    let prompt = get("prompt_tokens");
+    let completion = get("completion_tokens");
+    let mut metadata = json!({
+        "promptTokenCount": prompt,
+        "candidatesTokenCount": completion,
+        "totalTokenCount": usage.get("total_tokens").and_then(|v| v.as_i64()).unwrap_or(prompt + completion),
+    });
+    if let Some(cached) = usage
+        .get("prompt_tokens_details")
+        .and_then(|d| d.get("cached_tokens"))
+        .and_then(|v| v.as_i64())
+        .filter(|c| *c > 0)
+    {
+        metadata["cachedContentTokenCount"] = json!(cached);
+    }
+    metadata
+}
+
+fn function_call_part(name: &str, arguments: &str) -> Value {
+    json!({"functionCall": {"name": name, "args": parse_arguments(arguments)}})
+}
+
+fn gemini_chunk(parts: Vec<Value>, finish_reason: Option<&str>, usage: Option<&Value>, model: &str) -> Value {
+    let mut candidate = json!({"content": {"role": "model", "parts": parts}, "index": 0});
+    if let Some(reason) = finish_reason {
+        candidate["finishReason"] = json!(reason);
+    }
+    let mut chunk = json!({"candidates": [candidate], "modelVersion": model});
+    if let Some(usage) = usage {
+        chunk["usageMetadata"] = gemini_usage(usage);
+    }
+    chunk
+}
+
+/// Convert a non-streaming chat completion into a Gemini GenerateContentResponse
+pub fn chat_to_gemini_body(body: &[u8], ctx: &GeminiTranslation) -> Option<Vec<u8>> {
+    let chat: Value = serde_json::from_slice(body).ok()?;
+    let choice = chat.get("choices")?.as_array()?.first()?;
+    let message = choice.get("message")?;
+
+    let mut parts = Vec::new();
+    if let Some(text) = str_field(message, "content").filter(|t| !t.is_empty()) {
+        parts.push(json!({"text": text}));
+    }
+    for call in message.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+        let function = call.get("function");
+        parts.push(function_call_part(
+            function.and_then(|f| str_field(f, "name")).unwrap_or_default(),
+            function.and_then(|f| str_field(f, "arguments")).unwrap_or("{}"),
+        ));
+    }
+
+    let chunk = gemini_chunk(
+        parts,
+        Some(map_finish_reason(str_field(choice, "finish_reason"))),
+        chat.get("usage"),
+        str_field(&chat, "model").unwrap_or(&ctx.model),
+    );
+    serde_json::to_vec(&chunk).ok()
+}
+
+/// Incrementally converts chat/completions SSE chunks into Gemini `alt=sse` chunks.
+/// Text is forwarded as it arrives; function calls are buffered because Gemini sends them whole.
+pub struct ChatToGeminiStream {
+    ctx: GeminiTranslation,
+    buffer: Vec<u8>,
+    started: bool,
+    model: Option<String>,
+    /// chat tool call index -> (name, accumulated arguments)
+    tool_calls: BTreeMap<u64, (String, String)>,
+    usage: Option<Value>,
+    finish_reason: Option<String>,
+    completed: bool,
+}
+
+impl ChatToGeminiStream {
+    pub fn new(ctx: GeminiTranslation) -> Self {
+        Self {
+            ctx,
+            buffer: Vec::new(),
+            started: false,
+            model: None,
+            tool_calls: BTreeMap::new(),
+            usage: None,
+            finish_reason: None,
+            completed: false,
+        }
+    }
+
+    /// Feed raw upstream bytes; returns the Gemini SSE bytes for all complete lines
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.buffer.extend_from_slice(chunk);
+        let Some(last_newline) = self.buffer.iter().rposition(|b| *b == b'\n') else {
+            return out;
+        };
+        let rest = self.buffer.split_off(last_newline + 1);
+        let complete = std::mem::replace(&mut self.buffer, rest);
+        for line in complete.split(|b| *b == b'\n') {
+            self.handle_line(line, &mut out);
+        }
+        out
+    }
+
+    /// Flush the trailing partial line and send the final chunk (finishReason + usageMetadata)
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let line = std::mem::take(&mut self.buffer);
+        self.handle_line(&line, &mut out);
+        self.complete(&mut out);
+        out
+    }
+
+    fn handle_line(&mut self, line: &[u8], out: &mut Vec<u8>) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Ok(line) = std::str::from_utf8(line) else {
+            return;
+        };
+        let Some(data) = line.strip_prefix("data:").map(|d| d.trim()) else {
+            return;
+        };
+        if data == "[DONE]" {
+            self.complete(out);
+            return;
+        }
+        if let Ok(chunk) = serde_json::from_str::<Value>(data) {
+            self.handle_chunk(&chunk, out);
+        }
+    }
+
+    fn emit(&self, out: &mut Vec<u8>, data: Value) {
+        out.extend_from_slice(format!("data: {}\r\n\r\n", data).as_bytes());
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(&self.ctx.model)
+    }
+
+    fn handle_chunk(&mut self, chunk: &Value, out: &mut Vec<u8>) {
+        if self.completed {
+            return;
+        }
+        if let Some(error) = chunk.get("error") {
+            let message = str_field(error, "message").unwrap_or("Upstream error").to_string();
+            self.fail(out, &message);
+            return;
+        }
+        self.started = true;
+        if self.model.is_none() {
+            self.model = str_field(chunk, "model").map(|s| s.to_string());
+        }
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            self.usage = Some(usage.clone());
+        }
+        let Some(choice) = chunk.get("choices").and_then(|c| c.as_array()).and_then(|c| c.first()) else {
+            return;
+        };
+        if let Some(delta) = choice.get("delta") {
+            if let Some(text) = str_field(delta, "content").filter(|t| !t.is_empty()) {
+                let data = gemini_chunk(vec![json!({"text": text})], None, None, self.model());
+                self.emit(out, data);
+            }
+            for call in delta.get("tool_calls").and_then(|t| t.as_array()).into_iter().flatten() {
+                let index = call.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+                let entry = self.tool_calls.entry(index).or_default();
+                if let Some(function) = call.get("function") {
+                    if let Some(name) = str_field(function, "name") {
+                        entry.0.push_str(name);
+                    }
+                    if let Some(arguments) = str_field(function, "arguments") {
+                        entry.1.push_str(arguments);
+                    }
+                }
+            }
+        }
+        if let Some(reason) = str_field(choice, "finish_reason") {
+            self.finish_reason = Some(reason.to_string());
+        }
+    }
+
+    fn fail(&mut self, out: &mut Vec<u8>, message: &str) {
+        self.completed = true;
+        self.emit(out, json!({"error": {"code": 500, "message": message, "status": "INTERNAL"}}));
+    }
+
+    fn complete(&mut self, out: &mut Vec<u8>) {
+        if self.completed {
+            return;
+        }
+        if !self.started || self.finish_reason.is_none() {
+            self.fail(out, "Upstream stream ended before completion");
+            return;
+        }
+        self.completed = true;
+        let parts = std::mem::take(&mut self.tool_calls)
+            .into_values()
+            .map(|(name, arguments)| function_call_part(&name, &arguments))
+            .collect();
+        let data = gemini_chunk(
+            parts,
+            Some(map_finish_reason(self.finish_reason.as_deref())),
+            self.usage.as_ref(),
+            self.model(),
+        );
+        self.emit(out, data);
+    }
+}
+
+// ==================== Dispatch ====================
+
+/// Response translation selected for a request (None = pass through)
+#[derive(Debug, Clone)]
+pub enum ResponseTranslation {
+    /// Codex Responses API <- chat/completions
+    Responses(ChatTranslation),
+    /// Claude Code Messages API <- chat/completions
+    Anthropic(AnthropicTranslation),
+    /// Gemini generateContent <- chat/completions
+    Gemini(GeminiTranslation),
+}
+
+impl ResponseTranslation {
+    /// Translate a complete non-streaming response body
+    pub fn translate_body(&self, body: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            ResponseTranslation::Responses(ctx) => responses_chat::chat_to_responses_body(body, ctx),
+            ResponseTranslation::Anthropic(ctx) => chat_to_anthropic_body(body, ctx),
+            ResponseTranslation::Gemini(ctx) => chat_to_gemini_body(body, ctx),
+        }
+    }
+
+    pub fn stream(self) -> StreamTranslator {
+        match self {
+            ResponseTranslation::Responses(ctx) => StreamTranslator::Responses(Box::new(ChatToResponsesStream::new(ctx))),
+            ResponseTranslation::Anthropic(ctx) => StreamTranslator::Anthropic(ChatToAnthropicStream::new(ctx)),
+            ResponseTranslation::Gemini(ctx) => StreamTranslator::Gemini(ChatToGeminiStream::new(ctx)),
+        }
+    }
+
+    /// Translate a complete SSE body in one go (used for logging)
+    pub fn translate_stream_body(self, body: &[u8]) -> Vec<u8> {
+        let mut translator = self.stream();
+        let mut translated = translator.feed(body);
+        translated.extend(translator.finish());
+        translated
+    }
+}
+
+/// Incremental SSE translator for a [`ResponseTranslation`]
+pub enum StreamTranslator {
+    Responses(Box<ChatToResponsesStream>),
+    Anthropic(ChatToAnthropicStream),
+    Gemini(ChatToGeminiStream),
+}
+
+impl StreamTranslator {
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        match self {
+            StreamTranslator::Responses(t) => t.feed(chunk),
+            StreamTranslator::Anthropic(t) => t.feed(chunk),
+            StreamTranslator::Gemini(t) => t.feed(chunk),
+        }
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        match self {
+            StreamTranslator::Responses(t) => t.finish(),
+            StreamTranslator::Anthropic(t) => t.finish(),
+            StreamTranslator::Gemini(t) => t.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `event:` + `data:` SSE blocks -> (event type, data) pairs
+    fn sse_events(sse: &[u8]) -> Vec<(String, Value)> {
+        String::from_utf8(sse.to_vec())
+            .unwrap()
+            .split("\n\n")
+            .filter(|block| !block.is_empty())
+            .map(|block| {
+                let mut lines = block.lines();
+                let event = lines.next().unwrap().strip_prefix("event: ").unwrap().to_string();
+                let data = serde_json::from_str(lines.next().unwrap().strip_prefix("data: ").unwrap()).unwrap();
+                (event, data)
+            })
+            .collect()
+    }
+
+    fn chat_sse(chunks: &[Value]) -> String {
+        let mut sse: String = chunks.iter().map(|c| format!("data: {}\n\n", c)).collect();
+        sse.push_str("data: [DONE]\n\n");
+        sse
+    }
+
+    fn anthropic_stream(chunks: &[Value], split: usize) -> Vec<(String, Value)> {
+        let mut translator = ChatToAnthropicStream::new(AnthropicTranslation { model: "claude".to_string() });
+        let mut out = Vec::new();
+        for piece in chat_sse(chunks).as_bytes().chunks(split) {
+            out.extend(translator.feed(piece));
+        }
+        out.extend(translator.finish());
+        sse_events(&out)
+    }
+
+    #[test]
+    fn anthropic_request_maps_system_tools_and_tool_results() {
+        let req = json!({
+            "model": "claude-sonnet",
+            "max_tokens": 1024,
+            "stream": true,
+            "system": [{"type": "text", "text": "You are terse"}, {"type": "text", "text": "Use tools"}],
+            "messages": [
+                {"role": "user", "content": "read a.txt"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "hmm"},
+                    {"type": "text", "text": "Reading"},
+                    {"type": "tool_use", "id": "tu1", "name": "Read", "input": {"path": "a.txt"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "tu1", "content": [{"type": "text", "text": "hello"}]},
+                    {"type": "text", "text": "summarize"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/jpeg", "data": "AAAA"}}
+                ]}
+            ],
+            "tools": [
+                {"name": "Read", "description": "read a file", "input_schema": {"type": "object"}},
+                {"type": "web_search_20250305", "name": "web_search"}
+            ],
+            "tool_choice": {"type": "any", "disable_parallel_tool_use": true},
+            "stop_sequences": []
+        });
+        let (body, ctx) = anthropic_to_chat_request(req.to_string().as_bytes()).unwrap();
+        let chat: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(ctx.model, "claude-sonnet");
+        assert_eq!(chat["max_tokens"], 1024);
+        assert_eq!(chat["stream_options"], json!({"include_usage": true}));
+        assert_eq!(chat["tool_choice"], "required");
+        assert_eq!(chat["parallel_tool_calls"], false);
+        assert!(chat.get("stop").is_none());
+        assert_eq!(
+            chat["tools"],
+            json!([{"type": "function", "function": {"name": "Read", "description": "read a file", "parameters": {"type": "object"}}}])
+        );
+        assert_eq!(
+            chat["messages"],
+            json!([
+                {"role": "system", "content": "You are terse\nUse tools"},
+                {"role": "user", "content": "read a.txt"},
+                {"role": "assistant", "content": "Reading", "tool_calls": [
+                    {"id": "tu1", "type": "function", "function": {"name": "Read", "arguments": "{\"path\":\"a.txt\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "tu1", "content": "hello"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "summarize"},
+                    {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,AAAA"}}
+                ]}
+            ])
+        );
+    }
+
+    #[test]
+    fn anthropic_response_maps_content_and_usage() {
+        let chat = json!({
+            "id": "chatcmpl-42",
+            "model": "gpt-4o",
+            "choices": [{
+                "finish_reason": "tool_calls",
+                "message": {"content": "Let me check", "tool_calls": [
+                    {"id": "call_1", "function": {"name": "Read", "arguments": "{\"path\":\"a.txt\"}"}}
+                ]}
+            }],
+            "usage": {"prompt_tokens": 100, "completion_tokens": 20, "prompt_tokens_details": {"cached_tokens": 60}}
+        });
+        let body = chat_to_anthropic_body(chat.to_string().as_bytes(), &AnthropicTranslation::default()).unwrap();
+        let message: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(message["id"], "msg_42");
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(
+            message["content"],
+            json!([
+                {"type": "text", "text": "Let me check"},
+                {"type": "tool_use", "id": "call_1", "name": "Read", "input": {"path": "a.txt"}}
+            ])
+        );
+        assert_eq!(
+            message["usage"],
+            json!({"input_tokens": 40, "output_tokens": 20, "cache_read_input_tokens": 60})
+        );
+        assert!(chat_to_anthropic_body(b"{\"error\":{}}", &AnthropicTranslation::default()).is_none());
+    }
+
+    #[test]
+    fn anthropic_stream_emits_blocks_in_order() {
+        let chunks = [
+            json!({"id": "chatcmpl-7", "model": "gpt-4o", "choices": [{"delta": {"role": "assistant", "content": "On "}}]}),
+            json!({"id": "chatcmpl-7", "choices": [{"delta": {"content": "it"}}]}),
+            json!({"id": "chatcmpl-7", "choices": [{"delta": {"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "Read", "arguments": "{\"pa"}}]}}]}),
+            json!({"id": "chatcmpl-7", "choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": "th\":1}"}}]}}]}),
+            json!({"id": "chatcmpl-7", "choices": [{"delta": {}, "finish_reason": "tool_calls"}]}),
+            json!({"id": "chatcmpl-7", "choices": [], "usage": {"prompt_tokens": 9, "completion_tokens": 4}}),
+        ];
+        let whole = anthropic_stream(&chunks, usize::MAX);
+        assert_eq!(anthropic_stream(&chunks, 1), whole);
+
+        let types: Vec<&str> = whole.iter().map(|(event, _)| event.as_str()).collect();
+        assert_eq!(
+            types,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_eq!(whole[0].1["message"]["id"], "msg_7");
+        assert_eq!(whole[5].1["index"], 1);
+        assert_eq!(whole[5].1["content_block"]["id"], "call_1");
+        assert_eq!(whole[7].1["delta"]["partial_json"], "th\":1}");
+        assert_eq!(whole[9].1["delta"]["stop_reason"], "tool_use");
+        assert_eq!(whole[9].1["usage"]["input_tokens"], 9);
+        assert_eq!(whole[9].1["usage"]["output_tokens"], 4);
+    }
+
+    #[test]
+    fn anthropic_stream_cut_short_ends_with_an_error() {
+        let mut translator = ChatToAnthropicStream::new(AnthropicTranslation::default());
+        let mut out = translator.feed(b"data: {\"choices\":[{\"delta\":{\"content\":\"par");
+        out.extend(translator.feed(b"tial\"}}]}\n\n"));
+        out.extend(translator.finish());
+        let events = sse_events(&out);
+        let types: Vec<&str> = events.iter().map(|(event, _)| event.as_str()).collect();
+        assert_eq!(
+            types,
+            ["message_start", "content_block_start", "content_block_delta", "content_block_stop", "error"]
+        );
+        assert_eq!(events[2].1["delta"]["text"], "partial");
+    }
+
+    fn gemini_request(req: Value, path: &str) -> Value {
+        let (body, _) = gemini_to_chat_request(req.to_string().as_bytes(), path).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Gemini `alt=sse` chunks (`data:` lines only)
+    fn gemini_chunks(sse: &[u8]) -> Vec<Value> {
+        String::from_utf8(sse.to_vec())
+            .unwrap()
+            .split("\r\n\r\n")
+            .filter(|block| !block.is_empty())
+            .map(|block| serde_json::from_str(block.strip_prefix("data: ").unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn gemini_generate_target_reads_model_and_method() {
+        assert_eq!(
+            gemini_generate_target("/v1beta/models/gemini-2.5-pro:streamGenerateContent?alt=sse"),
+            Some(("gemini-2.5-pro".to_string(), true))
+        );
+        assert_eq!(
+            gemini_generate_target("/v1beta/models/gemini-2.5-pro:generateContent"),
+            Some(("gemini-2.5-pro".to_string(), false))
+        );
+        assert_eq!(gemini_generate_target("/v1beta/models/gemini-2.5-pro:countTokens"), None);
+    }
+
+    #[test]
+    fn gemini_multi_turn_contents_pair_function_responses_by_name() {
+        let chat = gemini_request(
+            json!({
+                "systemInstruction": {"parts": [{"text": "Be helpful"}, {"text": "Be brief"}]},
+                "contents": [
+                    {"role": "user", "parts": [{"text": "Weather in "}, {"text": "Paris and Rome?"}]},
+                    {"role": "model", "parts": [
+                        {"text": "thinking...", "thought": true},
+                        {"functionCall": {"name": "weather", "args": {"city": "Paris"}}},
+                        {"functionCall": {"name": "weather", "args": {"city": "Rome"}}},
+                        {"functionCall": {"name": "clock", "args": {}}}
+                    ]},
+                    {"role": "user", "parts": [
+                        {"functionResponse": {"name": "clock", "response": {"output": "12:00"}}},
+                        {"functionResponse": {"name": "weather", "response": {"output": "sunny"}}},
+                        {"functionResponse": {"name": "weather", "response": {"temp": 20}}}
+                    ]},
+                    {"role": "model", "parts": [{"text": "Sunny in Paris"}]},
+                    {"role": "user", "parts": [
+                        {"text": "And this?"},
+                        {"inlineData": {"mimeType": "image/jpeg", "data": "AAAA"}}
+                    ]}
+                ],
+                "generationConfig": {"maxOutputTokens": 100, "topP": 0.9, "responseMimeType": "application/json"}
+            }),
+            "/v1beta/models/gemini-2.5-pro:streamGenerateContent?alt=sse",
+        );
+        assert_eq!(chat["model"], "gemini-2.5-pro");
+        assert_eq!(chat["stream"], true);
+        assert_eq!(chat["max_tokens"], 100);
+        assert_eq!(chat["top_p"], 0.9);
+        assert_eq!(chat["response_format"], json!({"type": "json_object"}));
+        assert_eq!(
+            chat["messages"],
+            json!([
+                {"role": "system", "content": "Be helpful\nBe brief"},
+                {"role": "user", "content": "Weather in Paris and Rome?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}},
+                    {"id": "call_2", "type": "function", "function": {"name": "weather", "arguments": "{\"city\":\"Rome\"}"}},
+                    {"id": "call_3", "type": "function", "function": {"name": "clock", "arguments": "{}"}}
+                ]},
+                // 同名调用按出现顺序依次配对
+                {"role": "tool", "tool_call_id": "call_3", "content": "12:00"},
+                {"role": "tool", "tool_call_id": "call_1", "content": "sunny"},
+                {"role": "tool", "tool_call_id": "call_2", "content": "{\"temp\":20}"},
+                {"role": "assistant", "content": "Sunny in Paris"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "And this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/jpeg;base64,AAAA"}}
+                ]}
+            ])
+        );
+    }
+
+    #[test]
+    fn gemini_explicit_ids_and_tool_config_are_kept() {
+        let chat = gemini_request(
+            json!({
+                "contents": [
+                    {"role": "model", "parts": [{"functionCall": {"id": "abc", "name": "ls", "args": {}}}]},
+                    {"role": "user", "parts": [{"functionResponse": {"id": "abc", "name": "ls", "response": {"content": "a.txt"}}}]}
+                ],
+                "tools": [{"functionDeclarations": [
+                    {"name": "ls", "description": "list", "parameters": {"type": "OBJECT", "properties": {"dir": {"type": "STRING"}}}}
+                ]}],
+                "toolConfig": {"functionCallingConfig": {"mode": "ANY", "allowedFunctionNames": ["ls"]}}
+            }),
+            "/v1beta/models/gemini-2.5-flash:generateContent",
+        );
+        assert!(chat.get("stream").is_none());
+        assert_eq!(chat["messages"][0]["tool_calls"][0]["id"], "abc");
+        assert_eq!(chat["messages"][1], json!({"role": "tool", "tool_call_id": "abc", "content": "a.txt"}));
+        assert_eq!(
+            chat["tools"][0]["function"]["parameters"],
+            json!({"type": "object", "properties": {"dir": {"type": "string"}}})
+        );
+        assert_eq!(chat["tool_choice"], json!({"type": "function", "function": {"name": "ls"}}));
+    }
+
+    #[test]
+    fn gemini_response_maps_parts_and_usage() {
+        let chat = json!({
+            "model": "gpt-4o",
+            "choices": [{"finish_reason": "length", "message": {"content": "Hi", "tool_calls": [
+                {"id": "c1", "function": {"name": "ls", "arguments": "{\"dir\":\".\"}"}}
+            ]}}],
+            "usage": {"prompt_tokens": 8, "completion_tokens": 2, "prompt_tokens_details": {"cached_tokens": 3}}
+        });
+        let ctx = GeminiTranslation { model: "gemini-2.5-pro".to_string() };
+        let body: Value = serde_json::from_slice(&chat_to_gemini_body(chat.to_string().as_bytes(), &ctx).unwrap()).unwrap();
+        let candidate = &body["candidates"][0];
+        assert_eq!(candidate["finishReason"], "MAX_TOKENS");
+        assert_eq!(
+            candidate["content"]["parts"],
+            json!([{"text": "Hi"}, {"functionCall": {"name": "ls", "args": {"dir": "."}}}])
+        );
+        assert_eq!(
+            body["usageMetadata"],
+            json!({"promptTokenCount": 8, "candidatesTokenCount": 2, "totalTokenCount": 10, "cachedContentTokenCount": 3})
+        );
+    }
+
+    #[test]
+    fn gemini_stream_forwards_text_and_buffers_function_calls() {
+        let upstream = chat_sse(&[
+            json!({"model": "gpt-4o", "choices": [{"delta": {"content": "Look"}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"name": "ls", "arguments": "{\"dir\""}}]}}]}),
+            json!({"choices": [{"delta": {"tool_calls": [{"index": 0, "function": {"arguments": ":\"src\"}"}}]}, "finish_reason": "tool_calls"}]}),
+            json!({"choices": [], "usage": {"prompt_tokens": 5, "completion_tokens": 6}}),
+        ]);
+        let mut translator = ChatToGeminiStream::new(GeminiTranslation { model: "gemini-2.5-pro".to_string() });
+        let mut out = Vec::new();
+        for piece in upstream.as_bytes().chunks(4) {
+            out.extend(translator.feed(piece));
+        }
+        out.extend(translator.finish());
+        let chunks = gemini_chunks(&out);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0]["candidates"][0]["content"]["parts"], json!([{"text": "Look"}]));
+        assert_eq!(chunks[0]["modelVersion"], "gpt-4o");
+        assert_eq!(
+            chunks[1]["candidates"][0]["content"]["parts"],
+            json!([{"functionCall": {"name": "ls", "args": {"dir": "src"}}}])
+        );
+        assert_eq!(chunks[1]["candidates"][0]["finishReason"], "STOP");
+        assert_eq!(chunks[1]["usageMetadata"]["totalTokenCount"], 11);
+    }
+
+    #[test]
+    fn gemini_stream_cut_short_ends_with_an_error() {
+        let mut translator = ChatToGeminiStream::new(GeminiTranslation::default());
+        let mut out = translator.feed(b"data: {\"choices\":[{\"delta\":{\"content\":\"x\"}}]}\n\n");
+        out.extend(translator.finish());
+        let chunks = gemini_chunks(&out);
+        assert_eq!(chunks.last().unwrap()["error"]["status"], "INTERNAL");
+    }
+}
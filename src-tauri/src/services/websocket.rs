@@ -0,0 +1,444 @@
+use axum::http::{header, HeaderMap};
+use flate2::{Decompress, FlushDecompress};
+use std::io::Cursor;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tungstenite::protocol::frame::coding::{Data, OpCode};
+use tungstenite::protocol::frame::FrameHeader;
+
+/// 单条文本消息的解析上限，超出部分只转发不解析
+const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+/// 每个方向的读缓冲大小
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// True for `Connection: upgrade` + `Upgrade: websocket` requests
+pub fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    let upgrade_websocket = headers
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("websocket"));
+    connection_upgrade && upgrade_websocket
+}
+
+/// ws:// / wss:// base URLs are requested with http:// / https:// for the handshake
+pub fn handshake_url(url: &str) -> String {
+    if let Some(rest) = url.strip_prefix("wss://") {
+        format!("https://{}", rest)
+    } else if let Some(rest) = url.strip_prefix("ws://") {
+        format!("http://{}", rest)
+    } else {
+        url.to_string()
+    }
+}
+
+/// Restore the handshake headers dropped by `filter_headers`.
+///
+/// Sec-WebSocket-Extensions 原样转发：连接只做字节转发，压缩（permessage-deflate）的消息由 TextFrameScanner 解压后解析
+pub fn prepare_handshake_headers(headers: &mut reqwest::header::HeaderMap) {
+    use reqwest::header::{HeaderValue, CONNECTION, UPGRADE};
+    headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+    headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+}
+
+/// Passive WebSocket frame reader: collects complete text messages from the
+/// relayed byte stream without altering it. Frame headers are parsed by tungstenite.
+#[derive(Default)]
+pub struct TextFrameScanner {
+    buf: Vec<u8>,
+    /// Payload bytes of an oversized frame still to be discarded
+    skip: u64,
+    message: Vec<u8>,
+    /// Kind of the (possibly fragmented) data message being collected
+    current: Option<Data>,
+    /// The message being collected is permessage-deflate compressed (RSV1 on its first frame)
+    compressed: bool,
+    /// Shared across messages: the upstream may reuse its compression window (context takeover)
+    inflater: Option<Decompress>,
+    /// A compressed message was skipped, so later compressed messages can no longer be inflated
+    inflate_lost: bool,
+    /// The stream is not valid WebSocket framing; nothing more is parsed
+    desync: bool,
+}
+
+impl TextFrameScanner {
+    /// Feed relayed bytes; returns the text messages completed by them
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        if self.desync {
+            return messages;
+        }
+        self.buf.extend_from_slice(data);
+
+        loop {
+            if self.skip > 0 {
+                let n = self.skip.min(self.buf.len() as u64) as usize;
+                self.buf.drain(..n);
+                self.skip -= n as u64;
+                if self.skip > 0 {
+                    break;
+                }
+            }
+
+            let mut cursor = Cursor::new(&self.buf[..]);
+            let (header, payload_len) = match FrameHeader::parse(&mut cursor) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    tracing::debug!(error = %e, "Stopped parsing WebSocket frames");
+                    self.desync = true;
+                    self.buf = Vec::new();
+                    break;
+                }
+            };
+            let header_len = cursor.position() as usize;
+
+            if payload_len > MAX_MESSAGE_BYTES as u64 {
+                // 超大帧直接跳过，所在消息也不再解析
+                self.buf.drain(..header_len);
+                self.skip = payload_len;
+                match header.opcode {
+                    OpCode::Data(Data::Continue) => self.drop_message(self.compressed),
+                    OpCode::Data(_) => self.drop_message(header.rsv1),
+                    OpCode::Control(_) => {}
+                }
+                continue;
+            }
+            let total = header_len + payload_len as usize;
+            if self.buf.len() < total {
+                break;
+            }
+            let mut payload: Vec<u8> = self.buf.drain(..total).skip(header_len).collect();
+            if let Some(key) = header.mask {
+                for (i, b) in payload.iter_mut().enumerate() {
+                    *b ^= key[i % 4];
+                }
+            }
+
+            match header.opcode {
+                OpCode::Data(kind @ (Data::Text | Data::Binary)) => {
+                    if self.current.is_some() {
+                        // 上一条消息没有结束帧：按已丢弃处理
+                        self.drop_message(self.compressed);
+                    }
+                    self.current = Some(kind);
+                    self.compressed = header.rsv1;
+                    self.message = payload;
+                }
+                OpCode::Data(Data::Continue) if self.current.is_some() => {
+                    if self.message.len() + payload.len() > MAX_MESSAGE_BYTES {
+                        self.drop_message(self.compressed);
+                        continue;
+                    }
+                    self.message.extend_from_slice(&payload);
+                }
+                // 控制帧可以穿插在分片之间；被丢弃消息的后续分片也忽略
+                _ => continue,
+            }
+
+            if header.is_final {
+                if let Some(text) = self.finish_message() {
+                    messages.push(text);
+                }
+            }
+        }
+        messages
+    }
+
+    fn drop_message(&mut self, compressed: bool) {
+        if compressed {
+            self.inflate_lost = true;
+        }
+        self.current = None;
+        self.message = Vec::new();
+    }
+
+    /// The completed message if it is text (inflated when compressed)
+    fn finish_message(&mut self) -> Option<Vec<u8>> {
+        let kind = self.current.take()?;
+        let mut message = std::mem::take(&mut self.message);
+        if self.compressed {
+            // 二进制消息同样要解压，压缩窗口才能与上游保持一致
+            if self.inflate_lost {
+                return None;
+            }
+            message = match self.inflate(&message) {
+                Some(inflated) => inflated,
+                None => {
+                    self.inflate_lost = true;
+                    return None;
+                }
+            };
+        }
+        (kind == Data::Text).then_some(message)
+    }
+
+    /// RFC 7692: the sender strips the trailing 00 00 ff ff of each flushed message
+    fn inflate(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let inflater = self.inflater.get_or_insert_with(|| Decompress::new(false));
+        let mut input = Vec::with_capacity(data.len() + 4);
+        input.extend_from_slice(data);
+        input.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+
+        let mut output = Vec::with_capacity(data.len() * 4);
+        let mut offset = 0;
+        loop {
+            if output.capacity() - output.len() < RELAY_BUFFER_SIZE {
+                output.reserve(RELAY_BUFFER_SIZE);
+            }
+            let (in_before, out_before) = (inflater.total_in(), inflater.total_out());
+            inflater.decompress_vec(&input[offset..], &mut output, FlushDecompress::Sync).ok()?;
+            offset += (inflater.total_in() - in_before) as usize;
+            if output.len() > MAX_MESSAGE_BYTES {
+                return None;
+            }
+            let progressed = inflater.total_in() != in_before || inflater.total_out() != out_before;
+            if !progressed || (offset >= input.len() && output.len() < output.capacity()) {
+                break;
+            }
+        }
+        Some(output)
+    }
+}
+
+/// How a relayed WebSocket connection ended
+#[derive(Debug)]
+pub enum RelayEnd {
+    ClientClosed,
+    UpstreamClosed,
+    IdleTimeout,
+    Error(String),
+}
+
+impl RelayEnd {
+    /// Error message for the request log (normal closes have none)
+    pub fn error_message(&self) -> Option<String> {
+        match self {
+            RelayEnd::ClientClosed | RelayEnd::UpstreamClosed => None,
+            RelayEnd::IdleTimeout => Some("WebSocket idle timeout".to_string()),
+            RelayEnd::Error(e) => Some(format!("WebSocket relay error: {}", e)),
+        }
+    }
+}
+
+enum Side {
+    Client(std::io::Result<usize>),
+    Upstream(std::io::Result<usize>),
+}
+
+/// Relay bytes in both directions until either side closes or no traffic
+/// passes for `idle_timeout`. Text messages sent by the upstream are handed to `on_text`.
+pub async fn relay<C, U, F>(client: C, upstream: U, idle_timeout: Duration, mut on_text: F) -> RelayEnd
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+    F: FnMut(&[u8]),
+{
+    let (mut client_rd, mut client_wr) = tokio::io::split(client);
+    let (mut upstream_rd, mut upstream_wr) = tokio::io::split(upstream);
+    let mut client_buf = vec![0u8; RELAY_BUFFER_SIZE];
+    let mut upstream_buf = vec![0u8; RELAY_BUFFER_SIZE];
+    let mut scanner = TextFrameScanner::default();
+
+    loop {
+        let side = tokio::time::timeout(idle_timeout, async {
+            tokio::select! {
+                r = client_rd.read(&mut client_buf) => Side::Client(r),
+                r = upstream_rd.read(&mut upstream_buf) => Side::Upstream(r),
+            }
+        })
+        .await;
+
+        match side {
+            Err(_) => return RelayEnd::IdleTimeout,
+            Ok(Side::Client(Ok(0))) => {
+                let _ = upstream_wr.shutdown().await;
+                return RelayEnd::ClientClosed;
+            }
+            Ok(Side::Client(Ok(n))) => {
+                if let Err(e) = upstream_wr.write_all(&client_buf[..n]).await {
+                    return RelayEnd::Error(e.to_string());
+                }
+            }
+            Ok(Side::Upstream(Ok(0))) => {
+                let _ = client_wr.shutdown().await;
+                return RelayEnd::UpstreamClosed;
+            }
+            Ok(Side::Upstream(Ok(n))) => {
+                for message in scanner.feed(&upstream_buf[..n]) {
+                    on_text(&message);
+                }
+                if let Err(e) = client_wr.write_all(&upstream_buf[..n]).await {
+                    return RelayEnd::Error(e.to_string());
+                }
+            }
+            Ok(Side::Client(Err(e))) | Ok(Side::Upstream(Err(e))) => return RelayEnd::Error(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compress, Compression, FlushCompress};
+    use tungstenite::protocol::frame::coding::Control;
+
+    fn frame(opcode: OpCode, is_final: bool, mask: Option<[u8; 4]>, payload: &[u8]) -> Vec<u8> {
+        frame_with_rsv1(opcode, is_final, false, mask, payload)
+    }
+
+    fn frame_with_rsv1(opcode: OpCode, is_final: bool, rsv1: bool, mask: Option<[u8; 4]>, payload: &[u8]) -> Vec<u8> {
+        let header = FrameHeader { is_final, rsv1, opcode, mask, ..Default::default() };
+        let mut out = Vec::new();
+        header.format(payload.len() as u64, &mut out).unwrap();
+        let mut payload = payload.to_vec();
+        if let Some(key) = mask {
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= key[i % 4];
+            }
+        }
+        out.extend_from_slice(&payload);
+        out
+    }
+
+    fn text(payload: &str) -> Vec<u8> {
+        frame(OpCode::Data(Data::Text), true, None, payload.as_bytes())
+    }
+
+    /// permessage-deflate payload with the trailing 00 00 ff ff removed
+    fn deflate(compressor: &mut Compress, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + 64);
+        compressor.compress_vec(data, &mut out, FlushCompress::Sync).unwrap();
+        assert!(out.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+        out.truncate(out.len() - 4);
+        out
+    }
+
+    #[test]
+    fn complete_text_frames_are_reported() {
+        let mut scanner = TextFrameScanner::default();
+        let mut data = text("{\"a\":1}");
+        data.extend(text("{\"b\":2}"));
+        assert_eq!(scanner.feed(&data), vec![b"{\"a\":1}".to_vec(), b"{\"b\":2}".to_vec()]);
+    }
+
+    #[test]
+    fn frames_split_across_reads_are_reassembled() {
+        let mut scanner = TextFrameScanner::default();
+        let payload = "x".repeat(300); // 16 位长度字段
+        let data = text(&payload);
+        let mut messages = Vec::new();
+        for chunk in data.chunks(3) {
+            messages.extend(scanner.feed(chunk));
+        }
+        assert_eq!(messages, vec![payload.into_bytes()]);
+    }
+
+    #[test]
+    fn fragmented_messages_are_joined() {
+        let mut scanner = TextFrameScanner::default();
+        let mut data = frame(OpCode::Data(Data::Text), false, None, b"{\"usage\":");
+        data.extend(frame(OpCode::Data(Data::Continue), false, None, b"{\"input\""));
+        assert!(scanner.feed(&data).is_empty());
+        let messages = scanner.feed(&frame(OpCode::Data(Data::Continue), true, None, b":3}}"));
+        assert_eq!(messages, vec![b"{\"usage\":{\"input\":3}}".to_vec()]);
+    }
+
+    #[test]
+    fn control_frames_between_fragments_are_ignored() {
+        let mut scanner = TextFrameScanner::default();
+        let mut data = frame(OpCode::Data(Data::Text), false, None, b"hel");
+        data.extend(frame(OpCode::Control(Control::Ping), true, None, b"ping"));
+        data.extend(frame(OpCode::Control(Control::Pong), true, None, b""));
+        data.extend(frame(OpCode::Data(Data::Continue), true, None, b"lo"));
+        assert_eq!(scanner.feed(&data), vec![b"hello".to_vec()]);
+    }
+
+    #[test]
+    fn masked_frames_are_unmasked() {
+        let mut scanner = TextFrameScanner::default();
+        let mut data = frame(OpCode::Data(Data::Text), false, Some([1, 2, 3, 4]), b"masked ");
+        data.extend(frame(OpCode::Data(Data::Continue), true, Some([9, 8, 7, 6]), b"text"));
+        assert_eq!(scanner.feed(&data), vec![b"masked text".to_vec()]);
+    }
+
+    #[test]
+    fn binary_messages_are_not_reported() {
+        let mut scanner = TextFrameScanner::default();
+        let mut data = frame(OpCode::Data(Data::Binary), false, None, b"\x00\x01");
+        data.extend(frame(OpCode::Data(Data::Continue), true, None, b"\x02"));
+        data.extend(text("after"));
+        assert_eq!(scanner.feed(&data), vec![b"after".to_vec()]);
+    }
+
+    #[test]
+    fn oversized_frames_are_skipped() {
+        let mut scanner = TextFrameScanner::default();
+        let big = vec![b'x'; MAX_MESSAGE_BYTES + 1];
+        let data = frame(OpCode::Data(Data::Text), true, None, &big);
+        let mut messages = Vec::new();
+        for chunk in data.chunks(RELAY_BUFFER_SIZE) {
+            messages.extend(scanner.feed(chunk));
+        }
+        assert!(messages.is_empty());
+        assert_eq!(scanner.feed(&text("next")), vec![b"next".to_vec()]);
+    }
+
+    #[test]
+    fn oversized_fragmented_messages_are_dropped() {
+        let mut scanner = TextFrameScanner::default();
+        let half = vec![b'x'; MAX_MESSAGE_BYTES / 2 + 1];
+        let mut data = frame(OpCode::Data(Data::Text), false, None, &half);
+        data.extend(frame(OpCode::Data(Data::Continue), false, None, &half));
+        data.extend(frame(OpCode::Data(Data::Continue), true, None, b"tail"));
+        data.extend(text("next"));
+        assert_eq!(scanner.feed(&data), vec![b"next".to_vec()]);
+    }
+
+    #[test]
+    fn invalid_framing_stops_parsing() {
+        let mut scanner = TextFrameScanner::default();
+        // 0x3 是保留的数据帧操作码
+        assert!(scanner.feed(&[0x83, 0x00]).is_empty());
+        assert!(scanner.feed(&text("ignored")).is_empty());
+    }
+
+    #[test]
+    fn compressed_messages_are_inflated_with_context_takeover() {
+        let mut scanner = TextFrameScanner::default();
+        let mut compressor = Compress::new(Compression::default(), false);
+        let first = "{\"usage\":{\"input_tokens\":12}}";
+        let second = "{\"usage\":{\"input_tokens\":34}}";
+        let mut data = frame_with_rsv1(OpCode::Data(Data::Text), true, true, None, &deflate(&mut compressor, first.as_bytes()));
+        // 第二条复用第一条的压缩窗口，且被分成两片
+        let compressed = deflate(&mut compressor, second.as_bytes());
+        let (head, tail) = compressed.split_at(compressed.len() / 2);
+        data.extend(frame_with_rsv1(OpCode::Data(Data::Text), false, true, None, head));
+        data.extend(frame(OpCode::Data(Data::Continue), true, None, tail));
+        assert_eq!(scanner.feed(&data), vec![first.as_bytes().to_vec(), second.as_bytes().to_vec()]);
+    }
+
+    #[test]
+    fn compressed_binary_messages_keep_the_window_in_sync() {
+        let mut scanner = TextFrameScanner::default();
+        let mut compressor = Compress::new(Compression::default(), false);
+        let mut data = frame_with_rsv1(OpCode::Data(Data::Binary), true, true, None, &deflate(&mut compressor, b"binary binary binary"));
+        data.extend(frame_with_rsv1(OpCode::Data(Data::Text), true, true, None, &deflate(&mut compressor, b"text binary binary")));
+        assert_eq!(scanner.feed(&data), vec![b"text binary binary".to_vec()]);
+    }
+
+    #[test]
+    fn upgrade_handshake_keeps_extensions() {
+        use reqwest::header::{HeaderValue, SEC_WEBSOCKET_EXTENSIONS};
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(SEC_WEBSOCKET_EXTENSIONS, HeaderValue::from_static("permessage-deflate; client_max_window_bits"));
+        prepare_handshake_headers(&mut headers);
+        assert_eq!(headers[SEC_WEBSOCKET_EXTENSIONS], "permessage-deflate; client_max_window_bits");
+        assert_eq!(headers[reqwest::header::UPGRADE], "websocket");
+    }
+}